@@ -4,6 +4,7 @@ use chumsky::prelude::*;
 use chumsky::text::*;
 pub use chumsky::Parser;
 
+use hlbc::analysis::profile::ProfileKey;
 use hlbc::Str;
 
 pub type IndexRange = Range<usize>;
@@ -21,6 +22,40 @@ pub enum ElementRef {
     Fn(usize),
 }
 
+/// Output format of a call graph
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum GraphFormat {
+    /// Graphviz dot
+    #[default]
+    Dot,
+    /// Json list of nodes and edges
+    Json,
+}
+
+/// Output format of `export`
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum ExportFormat {
+    /// Haxe source files
+    #[default]
+    Hx,
+    /// Json of the decompiled classes
+    Json,
+    /// Disassembly of the methods
+    Asm,
+}
+
+/// Arguments of `export`
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    /// Output directory
+    pub out: Str,
+    /// Globs on the fully qualified class names, every class is included if empty
+    pub include: Vec<Str>,
+    /// Globs on the fully qualified class names
+    pub exclude: Vec<Str>,
+    pub format: ExportFormat,
+}
+
 #[derive(Debug, Clone)]
 pub enum Command {
     /// Exit the application
@@ -31,6 +66,8 @@ pub enum Command {
     Explain(Str),
     /// Open the wiki in the browser
     Wiki,
+    /// Run a script with the given arguments
+    RunScript(Str, Vec<Str>),
 
     /// Show generale information about the bytecode
     Info,
@@ -42,21 +79,63 @@ pub enum Command {
     SearchStr(Str),
     Debugfile(IndexRange),
     SearchDebugfile(Str),
+    /// List the source files with the number of functions defined in each
+    Files,
     Type(IndexRange),
     Global(IndexRange),
     Native(IndexRange),
     Constant(IndexRange),
     FunctionHeader(IndexRange),
     Function(IndexRange),
+    /// Disassemble functions with the referenced elements resolved in comments
+    FunctionAnnotated(IndexRange),
     FunctionNamed(Str),
     SearchFunction(Str),
+    /// Find functions matching a signature pattern
+    SearchSignature(Str),
+    /// Find opcode sequences matching a pattern
+    SearchSequence(Str),
     InFile(FileOrIndex),
     FileOf(usize),
     SaveTo(Str),
-    Callgraph(usize, usize),
+    /// Write the Markdown reference of the classes to a directory
+    Docs(Str),
+    /// Decompile every type to Haxe source files in a directory
+    Sources(Str),
+    /// Write extern declarations of the given packages (all if empty) in a directory
+    Externs(Str, Vec<Str>),
+    /// Write the strings used by the code to a CSV or PO file for translation
+    ExportStrings(Str),
+    /// Apply the translations of a CSV or PO file and serialize the patched bytecode to another file
+    ImportStrings(Str, Str),
+    /// Decompile or disassemble the classes matching some globs, one file per class
+    Export(ExportOptions),
+    /// Compile the decompiled classes matching some globs (all if empty) with the Haxe compiler
+    Verify(Vec<Str>),
+    Callgraph(usize, usize, GraphFormat),
+    /// Create a dot graph of the basic blocks of a function
+    Cfg(usize),
     RefTo(ElementRef),
     DecompType(usize),
     Decomp(usize),
+    /// Decompile a method or a static function by its qualified name
+    DecompNamed(Str),
+    /// Decompile a class by its full name
+    DecompClass(Str),
+    /// Decompile a function with the opcodes interleaved as comments
+    DecompAsm(usize),
+    /// List the functions likely generated by macros or build tools
+    Generated,
+    /// Report the update loops and the state machines, where to start reading the program
+    MainLoop,
+    /// List the code that can never be executed
+    DeadCode,
+    /// Instantiations, field accesses and unused methods of each class
+    TypeStats,
+    /// Rank the functions by size or complexity, as a table or as json
+    Profile(ProfileKey, bool),
+    /// Compare with another version of the program
+    Diff(Str),
 }
 
 // Used a default max values for index ranges
@@ -113,33 +192,85 @@ pub fn command_parser(ctx: &ParseContext) -> impl Parser<char, Command, Error =
 
     let string = string();
 
-    // We split the parsers in 2 to not overflow the tuple maximum size
+    // We split the parsers in groups to not overflow the tuple maximum size
 
     let core_cmds = choice((
         cmd!("exit" => Exit),
         cmd!("help" => Help),
         cmd!("explain"; string.clone() => Explain),
         cmd!("wiki" => Wiki),
+        cmd!("run-script")
+            .ignore_then(path())
+            .then(path().padded().repeated())
+            .map(|(file, args)| RunScript(file, args)),
     ));
 
-    choice((
-        core_cmds,
+    let search_cmds = choice((
+        cmd!("sstr"; string.clone() => SearchStr),
+        cmd!("sfile"; string.clone() => SearchDebugfile),
+        cmd!("sfn"; string.clone() => SearchFunction),
+        cmd!("ssig"; string.clone() => SearchSignature),
+        cmd!("sseq"; string.clone() => SearchSequence),
+    ));
+
+    let export_cmds = choice((
+        cmd!("saveto"; string.clone() => SaveTo),
+        cmd!("docs"; string.clone() => Docs),
+        cmd!("sources"; string.clone() => Sources),
+        cmd!("externs")
+            .ignore_then(path())
+            .then(path().padded().repeated())
+            .map(|(dir, packages)| Externs(dir, packages)),
+        cmd!("export-strings"; string.clone() => ExportStrings),
+        cmd!("import-strings")
+            .ignore_then(path())
+            .then(path().padded())
+            .map(|(input, output)| ImportStrings(input, output)),
+        cmd!("export").ignore_then(export_options()).map(Export),
+        cmd!("verify")
+            .ignore_then(path().padded().repeated())
+            .map(Verify),
+    ));
+
+    let report_cmds = choice((
         cmd!("info" => Info),
         cmd!("entrypoint" => Entrypoint),
+        cmd!("generated" => Generated),
+        cmd!("mainloop" => MainLoop),
+        cmd!("deadcode" => DeadCode),
+        cmd!("typestats" => TypeStats),
+        cmd!("profile")
+            .ignore_then(
+                choice((
+                    just("ops").to(ProfileKey::Ops),
+                    just("regs").to(ProfileKey::Regs),
+                    just("complexity").to(ProfileKey::Complexity),
+                ))
+                .or_not(),
+            )
+            .then(just("json").padded().or_not())
+            .map(|(key, json)| Profile(key.unwrap_or_default(), json.is_some())),
+        cmd!("files" => Files),
+        cmd!("diff"; string.clone() => Diff),
+    ));
+
+    choice((
+        core_cmds,
+        search_cmds,
+        export_cmds,
+        report_cmds,
         cmd!("int", "i"; index_range(ctx.int_max) => Int),
         cmd!("float", "f"; index_range(ctx.float_max) => Float),
         cmd!("string", "s"; index_range(ctx.string_max) => String),
-        cmd!("sstr"; string.clone() => SearchStr),
         cmd!("debugfile", "file"; index_range(ctx.debug_file_max) => Debugfile),
-        cmd!("sfile"; string.clone() => SearchDebugfile),
         cmd!("type", "t"; index_range(ctx.type_max) => Type),
         cmd!("global", "g"; index_range(ctx.global_max) => Global),
         cmd!("constant", "c"; index_range(ctx.constant_max) => Constant),
         cmd!("native", "n"; index_range(ctx.native_max) => Native),
+        cmd!("fnasm"; index_range(ctx.findex_max) => FunctionAnnotated),
         cmd!("fnh"; index_range(ctx.findex_max) => FunctionHeader),
         cmd!("fn"; index_range(ctx.findex_max) => Function),
         cmd!("fnamed", "fnn"; string.clone() => FunctionNamed),
-        cmd!("sfn"; string.clone() => SearchFunction),
         cmd!("infile").ignore_then(choice((
             num().map(|n| InFile(FileOrIndex::Index(n))),
             filter(|c: &char| !c.is_whitespace())
@@ -147,11 +278,18 @@ pub fn command_parser(ctx: &ParseContext) -> impl Parser<char, Command, Error =
                 .map(|v| InFile(FileOrIndex::File(v.into_iter().collect()))),
         ))),
         cmd!("fileof"; num() => FileOf),
-        cmd!("saveto"; string => SaveTo),
         cmd!("callgraph")
             .ignore_then(num())
             .then(num().padded())
-            .map(|(f, d)| Callgraph(f, d)),
+            .then(
+                choice((
+                    just("dot").to(GraphFormat::Dot),
+                    just("json").to(GraphFormat::Json),
+                ))
+                .or_not(),
+            )
+            .map(|((f, d), format)| Callgraph(f, d, format.unwrap_or_default())),
+        cmd!("cfg"; num() => Cfg),
         cmd!("refto")
             .ignore_then(choice((
                 just("string@").ignore_then(num()).map(ElementRef::String),
@@ -159,8 +297,16 @@ pub fn command_parser(ctx: &ParseContext) -> impl Parser<char, Command, Error =
                 just("fn@").ignore_then(num()).map(ElementRef::Fn),
             )))
             .map(RefTo),
-        cmd!("decomp"; num() => Decomp),
+        cmd!("decompasm"; num() => DecompAsm),
         cmd!("decompt"; num() => DecompType),
+        cmd!("decomp").ignore_then(choice((
+            just("--class")
+                .padded()
+                .ignore_then(path())
+                .map(DecompClass),
+            just("fn@").or_not().ignore_then(num()).map(Decomp),
+            path().map(DecompNamed),
+        ))),
     ))
 }
 
@@ -168,6 +314,72 @@ fn string() -> impl Parser<char, Str, Error = Simple<char>> + Clone {
     filter(|c: &char| c != &';').repeated().map(Str::from_iter)
 }
 
+/// A file path without whitespaces, for commands taking more than one path
+fn path() -> impl Parser<char, Str, Error = Simple<char>> {
+    filter(|c: &char| !c.is_whitespace() && c != &';')
+        .repeated()
+        .at_least(1)
+        .map(Str::from_iter)
+}
+
+/// An argument of `export`, in any order
+enum ExportArg {
+    Out(Str),
+    Include(Str),
+    Exclude(Str),
+    Format(ExportFormat),
+}
+
+/// `--out <dir>` is required, `--include` and `--exclude` can be repeated
+fn export_options() -> impl Parser<char, ExportOptions, Error = Simple<char>> {
+    // Quotes are kept when typed in the interactive prompt
+    let glob = || path().map(|g| Str::from(g.trim_matches(['\'', '"'])));
+    choice((
+        just("--out")
+            .padded()
+            .ignore_then(path())
+            .map(ExportArg::Out),
+        just("--include")
+            .padded()
+            .ignore_then(glob())
+            .map(ExportArg::Include),
+        just("--exclude")
+            .padded()
+            .ignore_then(glob())
+            .map(ExportArg::Exclude),
+        just("--format")
+            .padded()
+            .ignore_then(choice((
+                just("hx").to(ExportFormat::Hx),
+                just("json").to(ExportFormat::Json),
+                just("asm").to(ExportFormat::Asm),
+            )))
+            .map(ExportArg::Format),
+    ))
+    .padded()
+    .repeated()
+    .try_map(|args, span| {
+        let mut out = None;
+        let mut include = Vec::new();
+        let mut exclude = Vec::new();
+        let mut format = ExportFormat::default();
+        for arg in args {
+            match arg {
+                ExportArg::Out(dir) => out = Some(dir),
+                ExportArg::Include(glob) => include.push(glob),
+                ExportArg::Exclude(glob) => exclude.push(glob),
+                ExportArg::Format(f) => format = f,
+            }
+        }
+        Ok(ExportOptions {
+            out: out.ok_or_else(|| Simple::custom(span, "Missing --out <dir>"))?,
+            include,
+            exclude,
+            format,
+        })
+    })
+}
+
 fn num() -> impl Parser<char, usize, Error = Simple<char>> {
     int::<_, Simple<char>>(10)
         .map(|s: String| s.parse::<usize>().unwrap())
@@ -202,9 +414,11 @@ fn index_range(max: usize) -> impl Parser<char, IndexRange, Error = Simple<char>
 #[cfg(test)]
 mod tests {
     use chumsky::Parser;
+    use hlbc::analysis::profile::ProfileKey;
 
     use crate::command::{
-        index_range, parse_command, parse_commands, Command, FileOrIndex, ParseContext,
+        index_range, parse_command, parse_commands, Command, ExportFormat, FileOrIndex,
+        GraphFormat, ParseContext,
     };
 
     #[test]
@@ -298,6 +512,198 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_command_signature() {
+        let parsed = parse_command(&ParseContext::default(), "ssig (String, Int) -> Bool");
+        assert!(match parsed {
+            Ok(Command::SearchSignature(s)) => {
+                s == "(String, Int) -> Bool"
+            }
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn test_command_sequence() {
+        let parsed = parse_command(&ParseContext::default(), "sseq GetThis * Ret");
+        assert!(matches!(parsed, Ok(Command::SearchSequence(s)) if s == "GetThis * Ret"));
+    }
+
+    #[test]
+    fn test_command_fnasm() {
+        let ctx = ParseContext {
+            findex_max: 10,
+            ..ParseContext::default()
+        };
+        let parsed = parse_command(&ctx, "fnasm 3");
+        assert!(matches!(parsed, Ok(Command::FunctionAnnotated(r)) if r == (3..4)));
+    }
+
+    #[test]
+    fn test_command_generated() {
+        let parsed = parse_command(&ParseContext::default(), "generated");
+        assert!(matches!(parsed, Ok(Command::Generated)));
+    }
+
+    #[test]
+    fn test_command_mainloop() {
+        let parsed = parse_command(&ParseContext::default(), "mainloop");
+        assert!(matches!(parsed, Ok(Command::MainLoop)));
+    }
+
+    #[test]
+    fn test_command_deadcode() {
+        let parsed = parse_command(&ParseContext::default(), "deadcode");
+        assert!(matches!(parsed, Ok(Command::DeadCode)));
+    }
+
+    #[test]
+    fn test_command_typestats() {
+        let parsed = parse_command(&ParseContext::default(), "typestats");
+        assert!(matches!(parsed, Ok(Command::TypeStats)));
+    }
+
+    #[test]
+    fn test_command_verify() {
+        let parsed = parse_command(&ParseContext::default(), "verify game.* ui.Menu");
+        assert!(matches!(parsed, Ok(Command::Verify(globs)) if globs == ["game.*", "ui.Menu"]));
+        let parsed = parse_command(&ParseContext::default(), "verify");
+        assert!(matches!(parsed, Ok(Command::Verify(globs)) if globs.is_empty()));
+    }
+
+    #[test]
+    fn test_command_profile() {
+        let parsed = parse_command(&ParseContext::default(), "profile");
+        assert!(matches!(
+            parsed,
+            Ok(Command::Profile(ProfileKey::Ops, false))
+        ));
+        let parsed = parse_command(&ParseContext::default(), "profile complexity json");
+        assert!(matches!(
+            parsed,
+            Ok(Command::Profile(ProfileKey::Complexity, true))
+        ));
+    }
+
+    #[test]
+    fn test_command_diff() {
+        let parsed = parse_command(&ParseContext::default(), "diff game-1.1.hl");
+        assert!(matches!(parsed, Ok(Command::Diff(file)) if file == "game-1.1.hl"));
+    }
+
+    #[test]
+    fn test_command_callgraph() {
+        let parsed = parse_command(&ParseContext::default(), "callgraph 12 3");
+        assert!(matches!(
+            parsed,
+            Ok(Command::Callgraph(12, 3, GraphFormat::Dot))
+        ));
+        let parsed = parse_command(&ParseContext::default(), "callgraph 12 3 json");
+        assert!(matches!(
+            parsed,
+            Ok(Command::Callgraph(12, 3, GraphFormat::Json))
+        ));
+    }
+
+    #[test]
+    fn test_command_cfg() {
+        let parsed = parse_command(&ParseContext::default(), "cfg 12");
+        assert!(matches!(parsed, Ok(Command::Cfg(12))));
+    }
+
+    #[test]
+    fn test_command_docs() {
+        let parsed = parse_command(&ParseContext::default(), "docs out/docs");
+        assert!(matches!(parsed, Ok(Command::Docs(dir)) if dir == "out/docs"));
+    }
+
+    #[test]
+    fn test_command_files() {
+        let parsed = parse_command(&ParseContext::default(), "files");
+        assert!(matches!(parsed, Ok(Command::Files)));
+        let parsed = parse_command(&ParseContext::default(), "file 2");
+        assert!(matches!(parsed, Ok(Command::Debugfile(_))));
+    }
+
+    #[test]
+    fn test_command_sources() {
+        let parsed = parse_command(&ParseContext::default(), "sources out/src");
+        assert!(matches!(parsed, Ok(Command::Sources(dir)) if dir == "out/src"));
+    }
+
+    #[test]
+    fn test_command_externs() {
+        let parsed = parse_command(&ParseContext::default(), "externs out game.api game.ui");
+        assert!(matches!(
+            parsed,
+            Ok(Command::Externs(dir, packages)) if dir == "out" && packages == ["game.api", "game.ui"]
+        ));
+        let parsed = parse_command(&ParseContext::default(), "externs out");
+        assert!(matches!(parsed, Ok(Command::Externs(_, packages)) if packages.is_empty()));
+    }
+
+    #[test]
+    fn test_command_strings() {
+        let parsed = parse_command(&ParseContext::default(), "export-strings strings.po");
+        assert!(matches!(parsed, Ok(Command::ExportStrings(file)) if file == "strings.po"));
+        let parsed = parse_command(
+            &ParseContext::default(),
+            "import-strings strings.csv  patched.hl",
+        );
+        assert!(matches!(
+            parsed,
+            Ok(Command::ImportStrings(input, output)) if input == "strings.csv" && output == "patched.hl"
+        ));
+    }
+
+    #[test]
+    fn test_command_export() {
+        let parsed = parse_command(
+            &ParseContext::default(),
+            "export --out out/ --include 'my.game.*' --exclude *.Macro* --format asm",
+        );
+        assert!(matches!(
+            parsed,
+            Ok(Command::Export(opts)) if opts.out == "out/" && opts.include == ["my.game.*"] && opts.exclude == ["*.Macro*"] && opts.format == ExportFormat::Asm
+        ));
+        let parsed = parse_command(&ParseContext::default(), "export --out out");
+        assert!(matches!(
+            parsed,
+            Ok(Command::Export(opts)) if opts.include.is_empty() && opts.format == ExportFormat::Hx
+        ));
+        assert!(parse_command(&ParseContext::default(), "export --include a.*").is_err());
+        let parsed = parse_command(&ParseContext::default(), "export-strings strings.po");
+        assert!(matches!(parsed, Ok(Command::ExportStrings(file)) if file == "strings.po"));
+    }
+
+    #[test]
+    fn test_command_run_script() {
+        let parsed = parse_command(
+            &ParseContext::default(),
+            "run-script scripts/rename.rhai (String)->Int  out.hl",
+        );
+        assert!(matches!(
+            parsed,
+            Ok(Command::RunScript(file, args)) if file == "scripts/rename.rhai" && args == ["(String)->Int", "out.hl"]
+        ));
+    }
+
+    #[test]
+    fn test_command_prefix() {
+        let parsed = parse_command(&ParseContext::default(), "decomp 12");
+        assert!(matches!(parsed, Ok(Command::Decomp(12))));
+        let parsed = parse_command(&ParseContext::default(), "decompasm 12");
+        assert!(matches!(parsed, Ok(Command::DecompAsm(12))));
+        let parsed = parse_command(&ParseContext::default(), "decompt 12");
+        assert!(matches!(parsed, Ok(Command::DecompType(12))));
+        let parsed = parse_command(&ParseContext::default(), "decomp fn@12");
+        assert!(matches!(parsed, Ok(Command::Decomp(12))));
+        let parsed = parse_command(&ParseContext::default(), "decomp MyClass.method");
+        assert!(matches!(parsed, Ok(Command::DecompNamed(name)) if name == "MyClass.method"));
+        let parsed = parse_command(&ParseContext::default(), "decomp --class my.pack.MyClass");
+        assert!(matches!(parsed, Ok(Command::DecompClass(name)) if name == "my.pack.MyClass"));
+    }
+
     #[test]
     fn test_command_list() {
         let parsed = parse_commands(
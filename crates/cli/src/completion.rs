@@ -0,0 +1,273 @@
+use std::collections::BTreeSet;
+
+use clap::ValueEnum;
+
+use hlbc::Bytecode;
+
+/// Every command name and alias, keep in sync with the command parser
+const COMMANDS: &[&str] = &[
+    "exit",
+    "help",
+    "explain",
+    "wiki",
+    "run-script",
+    "info",
+    "entrypoint",
+    "generated",
+    "mainloop",
+    "deadcode",
+    "typestats",
+    "profile",
+    "diff",
+    "i",
+    "int",
+    "f",
+    "float",
+    "s",
+    "string",
+    "sstr",
+    "file",
+    "debugfile",
+    "files",
+    "sfile",
+    "t",
+    "type",
+    "g",
+    "global",
+    "c",
+    "constant",
+    "n",
+    "native",
+    "fnh",
+    "fn",
+    "fnasm",
+    "fnn",
+    "fnamed",
+    "sfn",
+    "ssig",
+    "sseq",
+    "infile",
+    "fileof",
+    "refto",
+    "saveto",
+    "docs",
+    "sources",
+    "externs",
+    "export-strings",
+    "import-strings",
+    "export",
+    "verify",
+    "callgraph",
+    "cfg",
+    "decomp",
+    "decompasm",
+    "decompt",
+];
+
+#[derive(ValueEnum, Copy, Clone, Debug)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// The completion script for a shell.
+/// Completing a command calls `hlbc <file> --complete <partial command>` to get the candidates.
+pub fn script(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => BASH,
+        Shell::Zsh => ZSH,
+        Shell::Fish => FISH,
+    }
+}
+
+/// Complete a partial command line. Candidates are the whole line with the last word completed.
+///
+/// Command names are always completed, arguments are completed with the names found in the bytecode
+/// for the commands working with names.
+pub fn complete(code: &Bytecode, line: &str) -> Vec<String> {
+    let line = line.trim_start_matches(['\'', '"']);
+    // Only complete the last command of a list
+    let (previous, current) = match line.rfind(';') {
+        Some(i) => line.split_at(i + 1),
+        None => ("", line),
+    };
+    let indent = &current[..current.len() - current.trim_start().len()];
+    let current = current.trim_start();
+
+    match current.split_once(' ') {
+        None => COMMANDS
+            .iter()
+            .filter(|c| c.starts_with(current))
+            .map(|c| format!("{previous}{indent}{c}"))
+            .collect(),
+        Some((cmd, arg)) => {
+            let arg = arg.trim_start();
+            let names: BTreeSet<String> = match cmd {
+                "fnn" | "fnamed" | "sfn" => code
+                    .functions
+                    .iter()
+                    .map(|f| f.name(code).to_string())
+                    .collect(),
+                "decomp" if arg.starts_with("--") => code
+                    .types
+                    .iter()
+                    .filter_map(|t| t.get_type_obj())
+                    .map(|obj| obj.name(code))
+                    .filter(|name| !name.starts_with('$'))
+                    .map(|name| format!("--class {name}"))
+                    .collect(),
+                "decomp" => code
+                    .functions
+                    .iter()
+                    .filter_map(|f| {
+                        let class = f.parent?.as_obj(code)?.name(code);
+                        Some(format!(
+                            "{}.{}",
+                            class.trim_start_matches('$'),
+                            f.name(code)
+                        ))
+                    })
+                    .collect(),
+                "infile" | "sfile" => code
+                    .debug_files
+                    .iter()
+                    .flatten()
+                    .map(|f| f.to_string())
+                    .collect(),
+                _ => BTreeSet::new(),
+            };
+            names
+                .into_iter()
+                .filter(|n| n.starts_with(arg))
+                .map(|n| format!("{previous}{indent}{cmd} {n}"))
+                .collect()
+        }
+    }
+}
+
+const BASH: &str = r#"_hlbc() {
+    local cur prev file i
+    cur="${COMP_WORDS[COMP_CWORD]}"
+    prev="${COMP_WORDS[COMP_CWORD-1]}"
+    case "$prev" in
+        -c|--command|-w|--watch)
+            for ((i = 1; i < COMP_CWORD; i++)); do
+                case "${COMP_WORDS[i]}" in
+                    -c|--command|-w|--watch) ((i++)) ;;
+                    -*) ;;
+                    *) file="${COMP_WORDS[i]}"; break ;;
+                esac
+            done
+            [[ -n "$file" ]] || return
+            local IFS=$'\n'
+            COMPREPLY=($(hlbc "$file" --complete "$cur" 2>/dev/null))
+            ;;
+        --completions)
+            COMPREPLY=($(compgen -W "bash zsh fish" -- "$cur"))
+            ;;
+        *)
+            if [[ "$cur" == -* ]]; then
+                COMPREPLY=($(compgen -W "-c --command -w --watch --completions -h --help -V --version" -- "$cur"))
+            else
+                COMPREPLY=($(compgen -f -- "$cur"))
+            fi
+            ;;
+    esac
+}
+
+complete -o filenames -F _hlbc hlbc
+"#;
+
+const ZSH: &str = r#"#compdef hlbc
+
+_hlbc_commands() {
+    local file w skip
+    for w in ${words[2,CURRENT-1]}; do
+        if [[ -n $skip ]]; then
+            skip=
+            continue
+        fi
+        case $w in
+            -c|--command|-w|--watch) skip=1 ;;
+            -*) ;;
+            *) file=$w; break ;;
+        esac
+    done
+    [[ -n $file ]] || return 1
+    local -a candidates
+    candidates=("${(@f)$(hlbc "$file" --complete "$PREFIX" 2>/dev/null)}")
+    compadd -U -- $candidates
+}
+
+_hlbc() {
+    _arguments \
+        '(-c --command)'{-c,--command}'[Execute the command at startup]:command:_hlbc_commands' \
+        '(-w --watch)'{-w,--watch}'[Execute the command each time the file changes]:command:_hlbc_commands' \
+        '--completions[Print the completion script for a shell]:shell:(bash zsh fish)' \
+        '1:file:_files'
+}
+
+if [ "$funcstack[1]" = "_hlbc" ]; then
+    _hlbc "$@"
+else
+    compdef _hlbc hlbc
+fi
+"#;
+
+const FISH: &str = r#"function __hlbc_file
+    set -l skip 0
+    for t in (commandline -opc)[2..-1]
+        if test $skip -eq 1
+            set skip 0
+            continue
+        end
+        switch $t
+            case -c --command -w --watch
+                set skip 1
+            case '-*'
+            case '*'
+                echo $t
+                return
+        end
+    end
+end
+
+function __hlbc_complete_command
+    set -l file (__hlbc_file)
+    test -n "$file"; and hlbc $file --complete (commandline -ct) 2>/dev/null
+end
+
+complete -c hlbc -s c -l command -x -a '(__hlbc_complete_command)' -d 'Execute the command at startup'
+complete -c hlbc -s w -l watch -x -a '(__hlbc_complete_command)' -d 'Execute the command each time the file changes'
+complete -c hlbc -l completions -x -a 'bash zsh fish' -d 'Print the completion script for a shell'
+"#;
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::Builder;
+    use hlbc::Bytecode;
+
+    use crate::completion::complete;
+
+    #[test]
+    fn complete_commands() {
+        let code = Bytecode::default();
+        assert_eq!(complete(&code, "decompa"), vec!["decompasm"]);
+        assert_eq!(complete(&code, "'info; ent"), vec!["info; entrypoint"]);
+        assert!(complete(&code, "fn 12").is_empty());
+    }
+
+    #[test]
+    fn complete_names() {
+        let mut b = Builder::new();
+        b.class("my.Player", None, &[]);
+        b.class("$my.Player", None, &[]);
+        let code = b.build().unwrap();
+        assert_eq!(
+            complete(&code, "decomp --class my."),
+            vec!["decomp --class my.Player"]
+        );
+        assert!(complete(&code, "decomp --class $").is_empty());
+    }
+}
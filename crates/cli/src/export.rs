@@ -0,0 +1,166 @@
+use std::fs;
+use std::path::Path;
+
+use hlbc::fmt::EnhancedFmt;
+use hlbc::types::{Type, TypeObj};
+use hlbc::Bytecode;
+use hlbc_decompiler::fmt::FormatOptions;
+use hlbc_decompiler::{
+    decompile_class_outline, source_file_path, write_source_tree_matching, DecompilerOptions,
+};
+
+use crate::command::{ExportFormat, ExportOptions};
+
+/// Match a name against a glob, `*` matches any sequence of characters (dots included) and `?` any single character
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position after the last star and the position in the name it currently matches up to
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                star = Some((p, n));
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the star match one more character
+                Some((after, matched)) => {
+                    p = after;
+                    n = matched + 1;
+                    star = Some((after, n));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Write one file per selected class in the output directory, like `pack/age/Name.hx`.
+/// Returns the number of files written.
+pub fn export(code: &Bytecode, opts: &ExportOptions) -> anyhow::Result<usize> {
+    let selected = |name: &str| {
+        (opts.include.is_empty() || opts.include.iter().any(|g| glob_match(g, name)))
+            && !opts.exclude.iter().any(|g| glob_match(g, name))
+    };
+    let dir = Path::new(&*opts.out);
+    let decompiler_opts = DecompilerOptions::default();
+    let fmt = FormatOptions::new(2);
+
+    if opts.format == ExportFormat::Hx {
+        return Ok(write_source_tree_matching(
+            code,
+            dir,
+            &decompiler_opts,
+            &fmt,
+            selected,
+        )?);
+    }
+
+    let decompiler_opts = decompiler_opts.with_static_values(code);
+    let mut count = 0;
+    for ty in &code.types {
+        let Type::Obj(obj) = ty else {
+            continue;
+        };
+        let name = obj.name(code);
+        // Static members are exported with their class
+        if name.starts_with('$') || !selected(&name) {
+            continue;
+        }
+        let (ext, content) = match opts.format {
+            ExportFormat::Hx => unreachable!(),
+            ExportFormat::Json => ("json", class_json(code, obj, &decompiler_opts)?),
+            ExportFormat::Asm => ("hlasm", disassemble_class(code, obj)),
+        };
+        let (package, short) = name.rsplit_once('.').unwrap_or(("", &name));
+        let path = source_file_path(dir, package, short, ext);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, content)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(feature = "json")]
+fn class_json(code: &Bytecode, obj: &TypeObj, opts: &DecompilerOptions) -> anyhow::Result<String> {
+    Ok(hlbc_decompiler::decompile_class_with(code, obj, opts).to_json()?)
+}
+
+#[cfg(not(feature = "json"))]
+fn class_json(_: &Bytecode, _: &TypeObj, _: &DecompilerOptions) -> anyhow::Result<String> {
+    anyhow::bail!(
+        "hlbc-cli has been built without json support. Build with feature 'json' to export json"
+    )
+}
+
+/// Disassembly of every method of the class, static ones included
+fn disassemble_class(code: &Bytecode, obj: &TypeObj) -> String {
+    decompile_class_outline(code, obj)
+        .methods
+        .iter()
+        .filter_map(|m| m.fun.as_fn(code))
+        .map(|f| format!("{}\n", f.display::<EnhancedFmt>(code)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::Builder;
+    use hlbc::types::Type;
+    use hlbc::Str;
+    use temp_dir::TempDir;
+
+    use crate::command::{ExportFormat, ExportOptions};
+    use crate::export::{export, glob_match};
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("my.game.*", "my.game.Player"));
+        assert!(glob_match("my.game.*", "my.game.ui.Menu"));
+        assert!(!glob_match("my.game.*", "my.Game"));
+        assert!(glob_match("*.Macro*", "my.game.MacroTools"));
+        assert!(!glob_match("*.Macro*", "Macro"));
+        assert!(glob_match("my.?ame.Player", "my.game.Player"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b", "aXbY"));
+    }
+
+    #[test]
+    fn stays_in_out_dir() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        b.class("pkg.Point", None, &[("x", int)]);
+        b.class("../../evil/Escape", None, &[]);
+        let code = b.build().unwrap();
+
+        for format in [ExportFormat::Hx, ExportFormat::Asm] {
+            let tmp = TempDir::new().unwrap();
+            let out = tmp.path().join("out");
+            let opts = ExportOptions {
+                out: Str::from(out.to_str().unwrap()),
+                include: Vec::new(),
+                exclude: Vec::new(),
+                format,
+            };
+            assert_eq!(export(&code, &opts).unwrap(), 2);
+            let ext = if format == ExportFormat::Hx {
+                "hx"
+            } else {
+                "hlasm"
+            };
+            assert!(out.join("pkg").join(format!("Point.{ext}")).exists());
+            assert!(out.join("_").join(format!("_evil_Escape.{ext}")).exists());
+            // Nothing written next to the output directory
+            assert_eq!(tmp.path().read_dir().unwrap().count(), 1);
+        }
+    }
+}
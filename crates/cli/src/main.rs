@@ -1,40 +1,111 @@
 use std::fs;
-use std::io::{stdin, BufReader, BufWriter, Write};
+use std::io::{BufWriter, Write};
 use std::iter::repeat;
 use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
 use anyhow::Context;
-use clap::Parser as ClapParser;
+use clap::{Parser as ClapParser, Subcommand};
+use rustyline::error::ReadlineError;
 use temp_dir::TempDir;
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
+use hlbc::analysis::cfg::cfg;
+use hlbc::analysis::files::{functions_in_files, source_file};
+use hlbc::analysis::search::SequencePattern;
+use hlbc::analysis::signature::SignaturePattern;
 use hlbc::fmt::EnhancedFmt;
 use hlbc::opcodes::Opcode;
-use hlbc::types::{FunPtr, RefFun, RefGlobal, Type};
+use hlbc::symbols::SymbolMap;
+use hlbc::types::{FunPtr, RefFun, RefGlobal, RefType, Type};
 use hlbc::*;
+use hlbc_decompiler::facade;
+use hlbc_decompiler::tokens::{Origin, Token, TokenKind};
 
-use crate::command::{commands_parser, Command, ElementRef, FileOrIndex, ParseContext, Parser};
+use crate::command::{
+    commands_parser, Command, ElementRef, FileOrIndex, GraphFormat, ParseContext, Parser,
+};
+use crate::completion::Shell;
 
 /// Command parser
 mod command;
+/// Shell completion scripts
+mod completion;
+/// Batch export of classes with `export`
+mod export;
+/// Interactive prompt with history and completion
+mod repl;
+/// Rhai scripts with `run-script`
+#[cfg(feature = "script")]
+mod script;
+/// Compiling the decompiled classes again with `verify`
+mod verify;
 
 #[derive(ClapParser, Debug)]
-#[clap(author, version, about)]
+#[clap(
+    author,
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
+    #[clap(subcommand)]
+    mode: Option<Mode>,
     /// The file to open, can be Hashlink bytecode or Haxe source file
-    file: PathBuf,
+    #[clap(required_unless_present = "completions")]
+    file: Option<PathBuf>,
     /// Execute the command each time the file changes
     #[clap(short, long)]
     watch: Option<String>,
     /// Execute the command at startup
     #[clap(short, long)]
     command: Option<String>,
+    /// Apply a symbol map (.hlbcmap) naming functions, types, fields and variables
+    #[clap(long)]
+    symbols: Option<PathBuf>,
+    /// Print the completion script for a shell, which also completes element names from the opened file
+    #[clap(long, value_enum)]
+    completions: Option<Shell>,
+    /// Print the completions for a partial command, used by the completion scripts
+    #[clap(long, hide = true)]
+    complete: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Mode {
+    /// Explore a file in the interactive prompt, the same as `hlbc <file>`
+    Repl {
+        /// The file to open, can be Hashlink bytecode or Haxe source file
+        file: PathBuf,
+    },
+    /// Print the string literals used by the code, with their index in the string pool
+    Strings {
+        /// The file to open, can be Hashlink bytecode or Haxe source file
+        file: PathBuf,
+        /// Also print the functions using each string
+        #[clap(long)]
+        refs: bool,
+    },
 }
 
 fn main() -> anyhow::Result<()> {
     let args: Args = Args::parse();
 
+    if let Some(shell) = args.completions {
+        print!("{}", completion::script(shell));
+        return Ok(());
+    }
+    let source = match &args.mode {
+        Some(Mode::Repl { file } | Mode::Strings { file, .. }) => file.clone(),
+        None => {
+            let Some(file) = args.file.clone() else {
+                unreachable!("clap requires the file")
+            };
+            file
+        }
+    };
+
     #[cfg(not(feature = "watch"))]
     if args.watch.is_some() {
         println!("The program was not compiled with the 'watch' feature enabled.");
@@ -49,11 +120,7 @@ fn main() -> anyhow::Result<()> {
         ColorChoice::Never
     });
 
-    let is_source = args
-        .file
-        .extension()
-        .map(|ext| ext == "hx")
-        .unwrap_or(false);
+    let is_source = source.extension().map(|ext| ext == "hx").unwrap_or(false);
 
     let dir = TempDir::new()?;
     let file = if is_source {
@@ -62,21 +129,23 @@ fn main() -> anyhow::Result<()> {
             stdout.flush()?;
         }
         let path = dir.child("bytecode.hl");
-        compile(&args.file, &path)?;
+        compile(&source, &path)?;
         if tty {
             println!(" OK");
         }
         path
     } else {
-        args.file.clone()
+        source.clone()
     };
 
     let start = Instant::now();
 
-    let code = {
-        let mut r = BufReader::new(fs::File::open(&file)?);
-        Bytecode::deserialize(&mut r)?
-    };
+    let code = open(&file, args.symbols.as_deref())?;
+
+    if let Some(Mode::Strings { refs, .. }) = args.mode {
+        print_strings(&code, refs);
+        return Ok(());
+    }
 
     if tty {
         println!("Loaded ! ({} ms)", start.elapsed().as_millis());
@@ -94,6 +163,13 @@ fn main() -> anyhow::Result<()> {
         findex_max: code.findex_max(),
     };
 
+    if let Some(line) = args.complete {
+        for candidate in completion::complete(&code, &line) {
+            println!("{candidate}");
+        }
+        return Ok(());
+    }
+
     let parser = commands_parser(&parse_ctx);
 
     macro_rules! execute_commands {
@@ -130,10 +206,10 @@ fn main() -> anyhow::Result<()> {
 
         debouncer
             .watcher()
-            .watch(&args.file, RecursiveMode::NonRecursive)
+            .watch(&source, RecursiveMode::NonRecursive)
             .expect("Can't watch file");
 
-        println!("Watching file '{}', command : {watch}", args.file.display());
+        println!("Watching file '{}', command : {watch}", source.display());
 
         let commands = parser.parse(watch.as_str()).expect("Can't parse command");
 
@@ -144,13 +220,10 @@ fn main() -> anyhow::Result<()> {
                 Ok(Ok(events)) => {
                     for e in events {
                         if is_source {
-                            compile(&args.file, &file)?;
+                            compile(&source, &file)?;
                         }
 
-                        let code = {
-                            let mut r = BufReader::new(fs::File::open(&file)?);
-                            Bytecode::deserialize(&mut r)?
-                        };
+                        let code = open(&file, args.symbols.as_deref())?;
 
                         execute_commands!(&code, commands.clone(); break 'watch);
                     }
@@ -169,20 +242,31 @@ fn main() -> anyhow::Result<()> {
         return Ok(());
     }
 
+    let mut editor = repl::editor(&code, tty)?;
     'main: loop {
-        let mut line = String::new();
-        stdout.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)))?;
-        print!("> ");
-        stdout.set_color(ColorSpec::new().set_fg(Some(Color::Cyan)))?;
-        stdout.flush()?;
-        stdin().read_line(&mut line)?;
-        stdout.reset()?;
-
-        let commands = parser
-            .parse(line.trim())
-            .expect("Error while parsing command.");
-        execute_commands!(&code, commands; break 'main);
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            // Ctrl-C or Ctrl-D
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str())?;
+
+        match parser.parse(line.trim()) {
+            Ok(commands) => {
+                execute_commands!(&code, commands; break 'main);
+            }
+            Err(errors) => {
+                for e in errors {
+                    println!("Invalid command : {e}");
+                }
+            }
+        }
     }
+    repl::save_history(&mut editor)?;
     Ok(())
 }
 
@@ -218,28 +302,50 @@ exit                         | Exit hlbc-cli
 help                         | This message
 explain     <opcode>         | Get information about an opcode
 wiki                         | Open the bytecode wiki in a browser
+run-script  <file> <args>..  | Run a Rhai script on a copy of the bytecode, see the README for the api
 info                         | General information about the bytecode
 entrypoint                   | Get the bytecode entrypoint
+generated                    | List functions likely generated by macros or tools
+mainloop                     | Find the update loops and state machines, where to start reading
+deadcode                     | List the code that can never be executed
+typestats                    | Instantiations, field accesses and unused methods of each class
+profile     [key] [json]     | Rank the functions by ops, regs or complexity, with totals by class and package
+diff        <file>           | Compare with another version of the program
 i,int       <idx>            | Get the int at index
 f,float     <idx>            | Get the float at index
 s,string    <idx>            | Get the string at index
 sstr        <str>            | Find a string
 file,debugfile <idx>         | Get the debug file name at index
 sfile       <str>            | Find the debug file named
+files                        | List the source files with their number of functions
 t,type      <idx>            | Get the type at index
 g,global    <idx>            | Get global at index
 c,constant  <idx>            | Get constant at index
 n,native    <idx>            | Get native at index
 fnh         <findex>         | Get header of function at index
 fn          <findex>         | Get a function by findex
+fnasm       <findex>         | Disassemble a function, resolving the referenced elements in comments
 fnn,fnamed  <str>            | Get a function by name
 sfn         <str>            | Find a function by name
+ssig        <signature>      | Find functions by signature : (String, Int, ..) -> Bool
+sseq        <pattern>        | Find opcode sequences, _ is any opcode and * any number : GetThis * Ret
 infile      <idx|str>        | Find functions in file
 fileof      <findex>         | Get the file where findex is defined
 refto       <any@idx>        | Find references to a given bytecode element
 saveto      <filename>       | Serialize the bytecode to a file
-callgraph   <findex> <depth> | Create a dot call graph from a function and a max depth
-decomp      <findex>         | Decompile a function
+docs        <dir>            | Write the Markdown reference of the classes to a directory
+sources     <dir>            | Decompile every type to Haxe source files in a directory
+externs     <dir> <pkg>..    | Write extern declarations of some packages (all by default) to compile against
+verify      <glob>..         | Compile the decompiled classes with haxe and group the errors by decompiler limitation
+export-strings <file>        | Export the strings used by the code to translate, as CSV or PO (.po)
+import-strings <file> <out>  | Apply translations from a CSV or PO file and save the patched bytecode
+export --out <dir> <opts>..  | Decompile classes to one file each : --include/--exclude <glob>, --format hx|json|asm
+callgraph   <findex> <depth> | Create a dot call graph from a function and a max depth, add 'json' for a json output
+cfg         <findex>         | Create a dot graph of the basic blocks of a function
+decomp      <findex>         | Decompile a function, also fn@<findex>
+decomp      <Class.method>   | Decompile a method or a static function by name
+decomp --class <name>        | Decompile a class by its full name, e.g. my.pack.MyClass
+decompasm   <findex>         | Decompile a function, showing the opcodes for each statement
 decompt     <idx>            | Decompile a type
 
 Remember you can use the range notation in place of an index to navigate through data : a..b
@@ -255,11 +361,21 @@ This is the same range notation as Rust and is supported with most commands."#
             }
         }
         Command::Wiki => webbrowser::open("https://github.com/Gui-Yom/hlbc/wiki")?,
+        Command::RunScript(file, args) => {
+            #[cfg(feature = "script")]
+            {
+                script::run_script(code, Path::new(&*file), &args)?;
+            }
+
+            #[cfg(not(feature = "script"))]
+            {
+                println!("hlbc-cli has been built without scripting support. Build with feature 'script' to run scripts");
+            }
+        }
         Command::Info => {
             println!(
-                "version: {}\ndebug: {}\nnints: {}\nnfloats: {}\nnstrings: {}\nntypes: {}\nnnatives: {}\nnfunctions: {}\nnconstants: {}",
-                code.version,
-                code.debug_files.is_some(),
+                "{}\nnints: {}\nnfloats: {}\nnstrings: {}\nntypes: {}\nnnatives: {}\nnfunctions: {}\nnconstants: {}",
+                code.capabilities(),
                 code.ints.len(),
                 code.floats.len(),
                 code.strings.len(),
@@ -305,6 +421,15 @@ This is the same range notation as Rust and is supported with most commands."#
                 println!("{}", debug_files[i]);
             }
         }
+        Command::Files => {
+            let debug_files = require_debug_info(code)?;
+            for (file, funs) in functions_in_files(code) {
+                if let Some(i) = debug_files.iter().position(|f| *f == file) {
+                    print_i!(i);
+                }
+                println!("{file} ({} functions)", funs.len());
+            }
+        }
         Command::SearchDebugfile(str) => {
             let debug_files = require_debug_info(code)?;
             for (i, s) in debug_files.iter().enumerate() {
@@ -412,6 +537,15 @@ This is the same range notation as Rust and is supported with most commands."#
                 }
             }
         }
+        Command::FunctionAnnotated(range) => {
+            for findex in range {
+                print_i!(findex);
+                match code.get(RefFun(findex)) {
+                    FunPtr::Fun(f) => print!("{}", f.display_annotated(code)),
+                    FunPtr::Native(n) => println!("{}", n.display::<EnhancedFmt>(code)),
+                }
+            }
+        }
         Command::FunctionNamed(str) => {
             if let Some(f) = code.function_by_name(&str) {
                 println!("{}", f.display::<EnhancedFmt>(code));
@@ -427,12 +561,189 @@ This is the same range notation as Rust and is supported with most commands."#
                 println!("unknown");
             }
         }
+        Command::SearchSignature(str) => match str.parse::<SignaturePattern>() {
+            Ok(pattern) => {
+                for f in code.find_by_signature(&pattern) {
+                    println!("{}", f.display_header::<EnhancedFmt>(code));
+                }
+            }
+            Err(e) => println!("Invalid signature pattern : {e}"),
+        },
+        Command::SearchSequence(str) => match str.parse::<SequencePattern>() {
+            Ok(pattern) => {
+                for hit in code.find_sequence(&pattern) {
+                    println!(
+                        "{} at {}..{}",
+                        hit.fun.display_header::<EnhancedFmt>(code),
+                        hit.pos,
+                        hit.pos + hit.len
+                    );
+                }
+            }
+            Err(e) => println!("Invalid opcode pattern : {e}"),
+        },
+        Command::Generated => {
+            for (f, hint) in code.generated_functions() {
+                println!("{} ({hint})", f.display_header::<EnhancedFmt>(code));
+            }
+        }
+        Command::MainLoop => {
+            let report = code.main_loops();
+            println!(
+                "{} functions reachable from the entrypoint",
+                report.reachable
+            );
+            println!("Update loops :");
+            for (f, hint) in &report.update_loops {
+                println!("  {} ({hint})", f.display_header::<EnhancedFmt>(code));
+            }
+            println!("State machines :");
+            for s in &report.state_machines {
+                println!(
+                    "  {} at {} : switch with {} cases",
+                    s.fun.display_header::<EnhancedFmt>(code),
+                    s.pos,
+                    s.cases
+                );
+            }
+        }
+        Command::DeadCode => {
+            let dead = code.dead_code();
+            for (f, ranges) in &dead {
+                let ranges: Vec<_> = ranges
+                    .iter()
+                    .map(|r| format!("{}..{}", r.start, r.end))
+                    .collect();
+                println!(
+                    "{} : {}",
+                    f.display_header::<EnhancedFmt>(code),
+                    ranges.join(", ")
+                );
+            }
+            println!("{} functions with unreachable code", dead.len());
+        }
+        Command::TypeStats => {
+            let report = code.type_stats();
+            println!("Packages :");
+            for (package, total) in &report.packages {
+                let package: &str = if package.is_empty() {
+                    "<top level>"
+                } else {
+                    package
+                };
+                println!(
+                    "  {package} : {} types, {} instantiations, {} field accesses, {} unused methods",
+                    total.types,
+                    total.instantiations,
+                    total.field_accesses,
+                    total.unused_methods
+                );
+            }
+            println!("Types :");
+            for s in &report.types {
+                let Some(obj) = code[s.ty].get_type_obj() else {
+                    continue;
+                };
+                println!(
+                    "  {} : {} instantiations, {} field accesses",
+                    obj.name(code),
+                    s.instantiations,
+                    s.accesses()
+                );
+                for (field, count) in obj.own_fields.iter().zip(&s.field_accesses) {
+                    if *count > 0 {
+                        println!("    {} : {count}", field.name(code));
+                    }
+                }
+                for fun in &s.unused_methods {
+                    println!("    unused {}", fun.display_header::<EnhancedFmt>(code));
+                }
+            }
+        }
+        Command::Profile(key, json) => {
+            // Only the biggest ones are interesting in a large program
+            const TOP: usize = 50;
+            let mut report = code.profile();
+            report.sort(key);
+            if json {
+                println!("{}", report.to_json()?);
+                return Ok(());
+            }
+            println!("Functions :");
+            for p in report.functions.iter().take(TOP) {
+                println!(
+                    "  {:>6} ops {:>4} regs {:>4} complexity  {}@{}",
+                    p.ops,
+                    p.regs,
+                    p.complexity(),
+                    p.name,
+                    p.findex.0
+                );
+            }
+            let mut classes: Vec<_> = report.classes.iter().collect();
+            classes.sort_by_key(|(_, t)| std::cmp::Reverse(t.ops));
+            println!("Classes :");
+            for (class, t) in classes.into_iter().take(TOP) {
+                println!("  {:>6} ops {:>4} functions  {class}", t.ops, t.functions);
+            }
+            println!("Packages :");
+            for (package, t) in &report.packages {
+                let package: &str = if package.is_empty() {
+                    "<top level>"
+                } else {
+                    package
+                };
+                println!(
+                    "  {package} : {} functions, {} ops, {} branches",
+                    t.functions, t.ops, t.branches
+                );
+            }
+        }
+        Command::Diff(file) => {
+            let new = facade::open(&*file)?;
+            let diff = code.diff(&new);
+            if diff.is_empty() {
+                println!("No differences");
+            }
+            for f in &diff.removed_functions {
+                println!("- {}", f.display_header::<EnhancedFmt>(code));
+            }
+            for f in &diff.added_functions {
+                println!("+ {}", f.display_header::<EnhancedFmt>(&new));
+            }
+            for f in &diff.changed_functions {
+                println!(
+                    "~ {} ({} opcodes changed)",
+                    f.new.display_header::<EnhancedFmt>(&new),
+                    f.changes()
+                );
+            }
+            for t in &diff.removed_types {
+                println!("- type {}", code[*t].display::<EnhancedFmt>(code));
+            }
+            for t in &diff.added_types {
+                println!("+ type {}", new[*t].display::<EnhancedFmt>(&new));
+            }
+            for (_, t) in &diff.changed_types {
+                println!("~ type {}", new[*t].display::<EnhancedFmt>(&new));
+            }
+            println!(
+                "Strings : {} added, {} removed",
+                diff.added_strings.len(),
+                diff.removed_strings.len()
+            );
+            println!(
+                "Globals : {} added, {} removed",
+                diff.added_globals.len(),
+                diff.removed_globals.len()
+            );
+        }
         Command::InFile(foi) => {
             let debug_files = require_debug_info(code)?;
             match foi {
                 FileOrIndex::File(str) => {
                     if let Some(idx) = debug_files.iter().enumerate().find_map(|(i, d)| {
-                        if d == str {
+                        if d.as_str() == str.as_str() {
                             Some(i)
                         } else {
                             None
@@ -440,7 +751,7 @@ This is the same range notation as Rust and is supported with most commands."#
                     }) {
                         println!("Functions in file@{idx} : {}", debug_files[idx]);
                         for (i, f) in code.functions.iter().enumerate() {
-                            if f.debug_info.as_ref().unwrap()[f.ops.len() - 1].0 == idx {
+                            if source_file(f) == Some(idx) {
                                 print_i!(i);
                                 println!("{}", f.display_header::<EnhancedFmt>(code));
                             }
@@ -452,7 +763,7 @@ This is the same range notation as Rust and is supported with most commands."#
                 FileOrIndex::Index(idx) => {
                     println!("Functions in file@{idx} : {}", debug_files[idx]);
                     for (i, f) in code.functions.iter().enumerate() {
-                        if f.debug_info.as_ref().unwrap()[f.ops.len() - 1].0 == idx {
+                        if source_file(f) == Some(idx) {
                             print_i!(i);
                             println!("{}", f.display_header::<EnhancedFmt>(code));
                         }
@@ -462,9 +773,9 @@ This is the same range notation as Rust and is supported with most commands."#
         }
         Command::FileOf(idx) => {
             let debug_files = require_debug_info(code)?;
-            match code.get(RefFun(idx)) {
+            match code.try_get(RefFun(idx))? {
                 FunPtr::Fun(f) => {
-                    let idx = f.debug_info.as_ref().unwrap()[f.ops.len() - 1].0;
+                    let idx = source_file(f).context("No debug info")?;
                     println!(
                         "{} is in file@{idx} : {}",
                         f.display_header::<EnhancedFmt>(code),
@@ -481,16 +792,97 @@ This is the same range notation as Rust and is supported with most commands."#
             }
         }
         Command::SaveTo(file) => {
+            hlbc_decompiler::facade::save(code, &*file)?;
+        }
+        Command::Docs(dir) => {
+            let count = hlbc_decompiler::doc::write_markdown_docs(code, Path::new(&*dir), false)?;
+            println!("Documented {count} classes in {dir}");
+        }
+        Command::Sources(dir) => {
+            let count = hlbc_decompiler::write_source_tree(
+                code,
+                Path::new(&*dir),
+                &hlbc_decompiler::DecompilerOptions::default(),
+                &hlbc_decompiler::fmt::FormatOptions::new(2),
+            )?;
+            println!("Wrote {count} source files in {dir}");
+        }
+        Command::Externs(dir, packages) => {
+            let packages: Vec<&str> = packages.iter().map(|p| &**p).collect();
+            let count = hlbc_decompiler::write_extern_stubs(
+                code,
+                Path::new(&*dir),
+                &packages,
+                &hlbc_decompiler::fmt::FormatOptions::new(2),
+            )?;
+            println!("Wrote {count} extern declarations in {dir}");
+        }
+        Command::Verify(globs) => {
+            println!("Compiling the decompiled classes, this can take a while ...");
+            let report = verify::verify(code, &globs)?;
+            for f in &report.failures {
+                println!("{} : {} errors", f.class, f.errors.len());
+                for (_, error) in &f.errors {
+                    println!("  {error}");
+                }
+            }
+            if !report.other_errors.is_empty() {
+                println!("Other errors :");
+                for error in &report.other_errors {
+                    println!("  {error}");
+                }
+            }
+            println!(
+                "{}/{} classes compile ({:.1}%)",
+                report.checked - report.failures.len(),
+                report.checked,
+                report.score() * 100.0
+            );
+            let mut groups: Vec<_> = report.by_limitation().into_iter().collect();
+            groups.sort_by_key(|(_, (count, _))| std::cmp::Reverse(*count));
+            for (limitation, (count, classes)) in groups {
+                println!(
+                    "{} : {count} in {} classes",
+                    limitation.description(),
+                    classes.len()
+                );
+            }
+        }
+        Command::Export(opts) => {
+            let count = export::export(code, &opts)?;
+            println!("Exported {count} files to {}", opts.out);
+        }
+        Command::ExportStrings(file) => {
             let mut w = BufWriter::new(fs::File::create(&*file)?);
-            code.serialize(&mut w)?;
+            if is_po(&file) {
+                translation::export_po(code, &mut w)?;
+            } else {
+                translation::export_csv(code, &mut w)?;
+            }
+            println!("Exported strings to {file}");
         }
-        Command::Callgraph(idx, depth) => {
+        Command::ImportStrings(input, output) => {
+            let translations = fs::read_to_string(&*input)?;
+            let mut patched = code.clone();
+            let count = if is_po(&input) {
+                translation::import_po(&mut patched, &translations)?
+            } else {
+                translation::import_csv(&mut patched, &translations)?
+            };
+            let mut w = BufWriter::new(fs::File::create(&*output)?);
+            patched.serialize(&mut w)?;
+            println!("Translated {count} strings, saved to {output}");
+        }
+        Command::Callgraph(idx, depth, format) => {
             #[cfg(feature = "graph")]
             {
-                use hlbc::analysis::graph::{call_graph, display_graph};
+                use hlbc::analysis::graph::{call_graph, display_graph, display_graph_json};
 
                 let graph = call_graph(code, RefFun(idx), depth);
-                println!("{}", display_graph(&graph, code));
+                match format {
+                    GraphFormat::Dot => println!("{}", display_graph(&graph, code)),
+                    GraphFormat::Json => println!("{}", display_graph_json(&graph, code)),
+                }
             }
 
             #[cfg(not(feature = "graph"))]
@@ -498,6 +890,11 @@ This is the same range notation as Rust and is supported with most commands."#
                 println!("hlbc-cli has been built without graph support. Build with feature 'graph' to enable callgraph generation");
             }
         }
+        Command::Cfg(idx) => {
+            if let Some(fun) = RefFun(idx).as_fn(code) {
+                println!("{}", cfg(fun).display(code, fun));
+            }
+        }
         Command::RefTo(elem) => match elem {
             ElementRef::String(idx) => {
                 println!(
@@ -582,30 +979,118 @@ This is the same range notation as Rust and is supported with most commands."#
             }
         },
         Command::Decomp(idx) => {
-            if let Some(fun) = RefFun(idx).as_fn(code) {
+            if let Ok(tokens) = facade::decompile_function_tokens(
+                code,
+                RefFun(idx),
+                &hlbc_decompiler::DecompilerOptions::default(),
+            ) {
+                print_tokens(stdout, &tokens)?;
+            }
+        }
+        Command::DecompNamed(name) => {
+            if let Some(fun) = facade::find_method(code, &name) {
+                let tokens = facade::decompile_function_tokens(
+                    code,
+                    fun,
+                    &hlbc_decompiler::DecompilerOptions::default(),
+                )?;
+                print_tokens(stdout, &tokens)?;
+            } else {
+                println!("No method named '{name}', expected Class.method");
+            }
+        }
+        Command::DecompClass(name) => {
+            if let Some(ty) = facade::find_class(code, &name) {
+                let tokens = facade::decompile_class_tokens(
+                    code,
+                    ty,
+                    &hlbc_decompiler::DecompilerOptions::default(),
+                )?;
+                print_tokens(stdout, &tokens)?;
+            } else {
+                println!("No class named '{name}'");
+            }
+        }
+        Command::DecompAsm(idx) => {
+            if let Ok(source) = facade::decompile_function(
+                code,
+                RefFun(idx),
+                &hlbc_decompiler::DecompilerOptions {
+                    interleave_opcodes: true,
+                    ..Default::default()
+                },
+            ) {
+                println!("{source}");
+            }
+        }
+        Command::DecompType(idx) => match facade::decompile_class(
+            code,
+            RefType(idx),
+            &hlbc_decompiler::DecompilerOptions::default(),
+        ) {
+            Ok(source) => {
                 println!(
-                    "{}",
-                    hlbc_decompiler::decompile_function(code, fun)
-                        .display(code, &hlbc_decompiler::fmt::FormatOptions::new(2))
+                    "Dumping type@{idx} : {}",
+                    facade::type_name(code, RefType(idx))?
                 );
+                println!("{source}");
             }
+            Err(_) => println!("Type {idx} is not an obj"),
+        },
+    }
+    Ok(())
+}
+
+/// Print decompiled code with syntax highlighting, colors are disabled when the output isn't a terminal
+fn print_tokens(stdout: &mut StandardStream, tokens: &[Token]) -> anyhow::Result<()> {
+    for token in tokens {
+        let color = match token.kind {
+            TokenKind::Keyword => Some(Color::Magenta),
+            TokenKind::Type => Some(Color::Yellow),
+            TokenKind::Literal => Some(Color::Green),
+            TokenKind::Comment => Some(Color::Ansi256(242)),
+            TokenKind::Ident if matches!(token.origin, Origin::Fun(_)) => Some(Color::Blue),
+            _ => None,
+        };
+        if let Some(color) = color {
+            stdout.set_color(ColorSpec::new().set_fg(Some(color)))?;
+            write!(stdout, "{token}")?;
+            stdout.reset()?;
+        } else {
+            write!(stdout, "{token}")?;
         }
-        Command::DecompType(idx) => {
-            let ty = &code.types[idx];
-            match ty {
-                Type::Obj(obj) => {
-                    println!("Dumping type@{idx} : {}", ty.display::<EnhancedFmt>(code));
-                    println!(
-                        "{}",
-                        hlbc_decompiler::decompile_class(code, obj)
-                            .display(code, &hlbc_decompiler::fmt::FormatOptions::new(2))
-                    );
-                }
-                _ => println!("Type {idx} is not an obj"),
+    }
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// The string literals for `hlbc strings`, one per line, followed by the functions using them with `--refs`
+fn print_strings(code: &Bytecode, refs: bool) {
+    for entry in translation::translatable_strings(code) {
+        println!("{} {:?}", entry.index.0, &*entry.source);
+        if refs {
+            for fun in &entry.context {
+                println!("    {fun}");
             }
         }
     }
-    Ok(())
+}
+
+/// Load the bytecode, name the anonymous functions and apply the symbol map
+fn open(file: &Path, symbols: Option<&Path>) -> anyhow::Result<Bytecode> {
+    let mut code = facade::open(file)?;
+    code.name_closures();
+    if let Some(symbols) = symbols {
+        let map = SymbolMap::from_json(&fs::read_to_string(symbols)?)?;
+        map.apply(&mut code)
+            .with_context(|| format!("Can't apply the symbol map {}", symbols.display()))?;
+    }
+    Ok(code)
+}
+
+/// Translation files are CSV unless they have the PO extension
+fn is_po(file: &str) -> bool {
+    Path::new(file).extension().is_some_and(|ext| ext == "po")
 }
 
 /// Compile a Haxe source file to Hashlink bytecode by directly calling the Haxe compiler.
@@ -0,0 +1,84 @@
+use std::borrow::Cow;
+use std::path::PathBuf;
+
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use hlbc::Bytecode;
+
+use crate::completion;
+
+/// Line editor of the prompt
+pub type ReplEditor<'c> = Editor<ReplHelper<'c>, DefaultHistory>;
+
+/// Completes the commands and the names found in the bytecode, like the shell completion
+pub struct ReplHelper<'c> {
+    code: &'c Bytecode,
+    color: bool,
+}
+
+impl Completer for ReplHelper<'_> {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        // Candidates are the whole line with the last word completed
+        Ok((0, completion::complete(self.code, &line[..pos])))
+    }
+}
+
+impl Hinter for ReplHelper<'_> {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper<'_> {
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
+        &'s self,
+        prompt: &'p str,
+        _default: bool,
+    ) -> Cow<'b, str> {
+        if self.color {
+            Cow::Owned(format!("\x1b[33m{prompt}\x1b[0m"))
+        } else {
+            Cow::Borrowed(prompt)
+        }
+    }
+}
+
+impl Validator for ReplHelper<'_> {}
+
+impl Helper for ReplHelper<'_> {}
+
+/// The prompt editor with the history of the previous sessions
+pub fn editor(code: &Bytecode, color: bool) -> rustyline::Result<ReplEditor<'_>> {
+    let mut editor = ReplEditor::new()?;
+    editor.set_helper(Some(ReplHelper { code, color }));
+    if let Some(file) = history_file() {
+        // There is no history on the first run
+        let _ = editor.load_history(&file);
+    }
+    Ok(editor)
+}
+
+/// Save the history for the next sessions
+pub fn save_history(editor: &mut ReplEditor) -> rustyline::Result<()> {
+    match history_file() {
+        Some(file) => editor.save_history(&file),
+        None => Ok(()),
+    }
+}
+
+/// `.hlbc_history` in the home directory
+fn history_file() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".hlbc_history"))
+}
@@ -0,0 +1,322 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use anyhow::anyhow;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope, INT};
+
+use hlbc::analysis::signature::SignaturePattern;
+use hlbc::types::{RefFun, RefType, Type, TypeObj};
+use hlbc::{Bytecode, Resolve, Str};
+use hlbc_decompiler::{facade, DecompilerOptions};
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Run a [Rhai](https://rhai.rs) script on a copy of the bytecode, changes are only written with `code.save(file)`.
+/// The arguments are given to the script in the `ARGS` array.
+pub fn run_script(code: &Bytecode, path: &Path, args: &[Str]) -> anyhow::Result<()> {
+    let mut scope = Scope::new();
+    scope.push("code", Code(Rc::new(RefCell::new(code.clone()))));
+    scope.push_constant(
+        "ARGS",
+        args.iter()
+            .map(|a| Dynamic::from(a.to_string()))
+            .collect::<Array>(),
+    );
+    engine()
+        .run_file_with_scope(&mut scope, path.to_path_buf())
+        .map_err(|e| anyhow!("{} : {e}", path.display()))
+}
+
+/// The api available to scripts, documented in the README
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .register_type_with_name::<Code>("Bytecode")
+        .register_fn("functions", Code::functions)
+        .register_fn("function", Code::function)
+        .register_fn("find_functions", Code::find_functions)
+        .register_fn("classes", Code::classes)
+        .register_fn("strings", Code::strings)
+        .register_fn("save", Code::save);
+    engine
+        .register_type_with_name::<Fun>("Function")
+        .register_get("findex", Fun::findex)
+        .register_get("name", Fun::name)
+        .register_get("is_native", Fun::is_native)
+        .register_fn("ops", Fun::ops)
+        .register_fn("decompile", Fun::decompile)
+        .register_fn("rename", Fun::rename)
+        .register_fn("patch", Fun::patch)
+        .register_fn("to_string", Fun::header);
+    engine
+        .register_type_with_name::<Class>("Class")
+        .register_get("index", Class::index)
+        .register_get("name", Class::name)
+        .register_get("parent", Class::parent)
+        .register_get("fields", Class::fields)
+        .register_get("methods", Class::methods)
+        .register_fn("decompile", Class::decompile)
+        .register_fn("to_string", Class::name);
+    engine
+}
+
+/// The bytecode, shared with every element given to the script
+#[derive(Clone)]
+struct Code(Rc<RefCell<Bytecode>>);
+
+impl Code {
+    fn fun(&self, findex: RefFun) -> Dynamic {
+        Dynamic::from(Fun {
+            code: self.clone(),
+            findex,
+        })
+    }
+
+    /// Every function with code, natives excluded
+    fn functions(&mut self) -> Array {
+        let code = self.0.borrow();
+        code.functions.iter().map(|f| self.fun(f.findex)).collect()
+    }
+
+    /// Function or native by findex
+    fn function(&mut self, findex: INT) -> ScriptResult<Dynamic> {
+        let findex = RefFun(findex as usize);
+        self.0.borrow().try_get(findex).map_err(|e| e.to_string())?;
+        Ok(self.fun(findex))
+    }
+
+    /// Functions and natives matching a signature pattern, e.g. `(String, ..) -> Bool`
+    fn find_functions(&mut self, signature: &str) -> ScriptResult<Array> {
+        let pattern: SignaturePattern = signature.parse()?;
+        let code = self.0.borrow();
+        Ok(code
+            .find_by_signature(&pattern)
+            .map(|findex| self.fun(findex))
+            .collect())
+    }
+
+    fn classes(&mut self) -> Array {
+        let code = self.0.borrow();
+        code.types
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| matches!(t, Type::Obj(_)))
+            .map(|(i, _)| {
+                Dynamic::from(Class {
+                    code: self.clone(),
+                    ty: RefType(i),
+                })
+            })
+            .collect()
+    }
+
+    fn strings(&mut self) -> Array {
+        let code = self.0.borrow();
+        code.strings
+            .iter()
+            .map(|s| Dynamic::from(s.to_string()))
+            .collect()
+    }
+
+    /// Serialize the bytecode with the changes made by the script
+    fn save(&mut self, file: &str) -> ScriptResult<()> {
+        facade::save(&self.0.borrow(), file).map_err(|e| e.to_string().into())
+    }
+}
+
+/// A function or a native
+#[derive(Clone)]
+struct Fun {
+    code: Code,
+    findex: RefFun,
+}
+
+impl Fun {
+    fn findex(&mut self) -> INT {
+        self.findex.0 as INT
+    }
+
+    fn name(&mut self) -> String {
+        self.findex.name(&self.code.0.borrow()).to_string()
+    }
+
+    fn is_native(&mut self) -> bool {
+        self.code.0.borrow().get(self.findex).is_native()
+    }
+
+    fn header(&mut self) -> String {
+        facade::function_header(&self.code.0.borrow(), self.findex).unwrap_or_default()
+    }
+
+    /// Opcodes in the assembly view, empty for a native
+    fn ops(&mut self) -> Array {
+        facade::disassemble(&self.code.0.borrow(), self.findex)
+            .unwrap_or_default()
+            .into_iter()
+            .map(Dynamic::from)
+            .collect()
+    }
+
+    fn decompile(&mut self) -> ScriptResult<String> {
+        let code = self.code.0.borrow();
+        facade::decompile_function(&code, self.findex, &DecompilerOptions::default())
+            .map_err(|e| e.to_string().into())
+    }
+
+    /// Replace an opcode with one written as in `f.ops()`, returns the previous one
+    fn patch(&mut self, pos: INT, op: &str) -> ScriptResult<String> {
+        let mut code = self.code.0.borrow_mut();
+        let pos = pos as usize;
+        let f = self.findex.try_as_fn(&code).map_err(|e| e.to_string())?;
+        let old = facade::opcode(&code, f, pos).map_err(|e| e.to_string())?;
+        facade::patch_opcode(&mut code, self.findex, pos, op).map_err(|e| e.to_string())?;
+        Ok(old)
+    }
+
+    fn rename(&mut self, name: &str) -> ScriptResult<()> {
+        self.code
+            .0
+            .borrow_mut()
+            .rename_function(self.findex, name)
+            .map_err(|e| e.to_string().into())
+    }
+}
+
+/// A class (obj type)
+#[derive(Clone)]
+struct Class {
+    code: Code,
+    ty: RefType,
+}
+
+impl Class {
+    fn obj<'a>(&self, code: &'a Bytecode) -> &'a TypeObj {
+        self.ty.as_obj(code).expect("Class is an obj type")
+    }
+
+    fn index(&mut self) -> INT {
+        self.ty.0 as INT
+    }
+
+    fn name(&mut self) -> String {
+        let code = self.code.0.borrow();
+        self.obj(&code).name(&code).to_string()
+    }
+
+    /// Name of the parent class, `()` if there is none
+    fn parent(&mut self) -> Dynamic {
+        let code = self.code.0.borrow();
+        match self.obj(&code).super_ {
+            Some(parent) => Dynamic::from(facade::type_name(&code, parent).unwrap_or_default()),
+            None => Dynamic::UNIT,
+        }
+    }
+
+    /// Fields declared by this class as `#{ name, ty }` maps
+    fn fields(&mut self) -> Array {
+        let code = self.code.0.borrow();
+        self.obj(&code)
+            .own_fields
+            .iter()
+            .map(|f| {
+                let mut field = Map::new();
+                field.insert("name".into(), Dynamic::from(f.name(&code).to_string()));
+                field.insert(
+                    "ty".into(),
+                    Dynamic::from(facade::type_name(&code, f.t).unwrap_or_default()),
+                );
+                Dynamic::from_map(field)
+            })
+            .collect()
+    }
+
+    /// Methods and functions bound to fields
+    fn methods(&mut self) -> Array {
+        let code = self.code.0.borrow();
+        let obj = self.obj(&code);
+        obj.protos
+            .iter()
+            .map(|p| p.findex)
+            .chain(obj.bindings.values().copied())
+            .map(|findex| self.code.fun(findex))
+            .collect()
+    }
+
+    fn decompile(&mut self) -> ScriptResult<String> {
+        let code = self.code.0.borrow();
+        facade::decompile_class(&code, self.ty, &DecompilerOptions::default())
+            .map_err(|e| e.to_string().into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use rhai::{Dynamic, Scope};
+
+    use hlbc::builder::Builder;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{Reg, Type};
+
+    use crate::script::{engine, Code};
+
+    #[test]
+    fn query_and_patch() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        let int = b.ty(Type::I32);
+        let one = b.int(1);
+        b.int(5);
+        b.function(
+            "main",
+            &[],
+            int,
+            &[int],
+            vec![
+                Opcode::Int {
+                    dst: Reg(0),
+                    ptr: one,
+                },
+                Opcode::Ret { ret: Reg(0) },
+            ],
+        );
+        b.native("std", "log", &[], void);
+        let code = Code(Rc::new(RefCell::new(b.build().unwrap())));
+
+        let mut scope = Scope::new();
+        scope.push("code", code.clone());
+        let mut eval = |script| {
+            engine()
+                .eval_with_scope::<Dynamic>(&mut scope, script)
+                .map(|v| v.to_string())
+        };
+        assert_eq!(
+            eval("code.function(0).to_string()").unwrap(),
+            "fn main@0 () -> i32"
+        );
+        assert_eq!(
+            eval("code.function(0).ops()").unwrap(),
+            r#"["Int         reg0 = 1", "Ret         reg0"]"#
+        );
+        let source = eval("code.function(0).decompile()").unwrap();
+        assert!(source.contains("return 1;"), "{source}");
+        assert!(eval("code.function(1).decompile()").is_err());
+
+        assert_eq!(
+            eval(r#"code.function(0).patch(0, "Int reg0 = 5")"#).unwrap(),
+            "Int         reg0 = 1"
+        );
+        assert!(eval(r#"code.function(0).patch(2, "Ret reg0")"#).is_err());
+        assert!(eval(r#"code.function(1).patch(0, "Ret reg0")"#).is_err());
+        let source = eval("code.function(0).decompile()").unwrap();
+        assert!(source.contains("return 5;"), "{source}");
+        // The changes are made to the bytecode shared with the script
+        let code = code.0.borrow();
+        assert!(
+            matches!(code.functions[0].ops[0], Opcode::Int { dst: Reg(0), ptr } if code.ints[ptr.0] == 5)
+        );
+    }
+}
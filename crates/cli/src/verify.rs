@@ -0,0 +1,322 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use temp_dir::TempDir;
+
+use hlbc::types::Type;
+use hlbc::{Bytecode, Resolve, Str};
+use hlbc_decompiler::fmt::FormatOptions;
+use hlbc_decompiler::{write_source_tree_matching, DecompilerOptions};
+
+use crate::export::glob_match;
+
+/// Start of the messages of the Haxe lexer and parser
+const SYNTAX_ERRORS: &[&str] = &[
+    "Unexpected ",
+    "Unterminated ",
+    "Unclosed ",
+    "Invalid character",
+    "Invalid escape sequence",
+    "Missing ;",
+    "Missing type declaration",
+    "Expected ",
+    "Duplicate default",
+];
+
+/// What the decompiler got wrong, guessed from a Haxe compiler error
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Limitation {
+    /// Invalid syntax, often an expression the decompiler couldn't recover
+    Syntax,
+    /// A variable that is never declared, like a captured variable
+    UnknownIdentifier,
+    /// A type that doesn't exist in the decompiled code or the standard library
+    MissingType,
+    /// A field or a method that doesn't exist on the type inferred by the compiler
+    MissingField,
+    /// A value of the wrong type, like a missing or extra cast
+    TypeMismatch,
+    /// A function ending without returning a value, from control flow that wasn't fully structured
+    MissingReturn,
+    /// A variable or a field declared twice
+    Redefinition,
+    Other,
+}
+
+impl Limitation {
+    /// Guess the limitation from an error message of the Haxe compiler
+    pub fn classify(message: &str) -> Self {
+        let has = |s: &str| message.contains(s);
+        if has("Missing return") {
+            Limitation::MissingReturn
+        } else if has("Unknown identifier") {
+            Limitation::UnknownIdentifier
+        } else if has("Type not found") || has("Class not found") || has("does not define type") {
+            Limitation::MissingType
+        } else if has("has no field") || has("has no static field") {
+            Limitation::MissingField
+        } else if has(" should be ") || has("Cannot unify") {
+            Limitation::TypeMismatch
+        } else if SYNTAX_ERRORS.iter().any(|p| message.starts_with(p)) {
+            Limitation::Syntax
+        } else if has("Duplicate") || has("already defined") || has("Redefinition") {
+            Limitation::Redefinition
+        } else {
+            Limitation::Other
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Limitation::Syntax => "syntax errors",
+            Limitation::UnknownIdentifier => "unknown identifiers",
+            Limitation::MissingType => "missing types",
+            Limitation::MissingField => "missing fields",
+            Limitation::TypeMismatch => "type mismatches",
+            Limitation::MissingReturn => "missing returns",
+            Limitation::Redefinition => "redefinitions",
+            Limitation::Other => "other errors",
+        }
+    }
+}
+
+/// A class that doesn't compile once decompiled
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub class: Str,
+    /// Error messages of the compiler in the file of the class, without their position
+    pub errors: Vec<(Limitation, String)>,
+}
+
+/// Result of compiling the decompiled classes again
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// Number of classes checked
+    pub checked: usize,
+    /// Classes with errors, sorted by name
+    pub failures: Vec<Failure>,
+    /// Errors of the compiler outside of the checked classes, like in a typedef, or without a position
+    pub other_errors: Vec<String>,
+}
+
+impl VerifyReport {
+    /// Part of the classes compiling, between 0 and 1
+    pub fn score(&self) -> f64 {
+        if self.checked == 0 {
+            1.0
+        } else {
+            (self.checked - self.failures.len()) as f64 / self.checked as f64
+        }
+    }
+
+    /// Number of errors and the classes having them, by limitation
+    pub fn by_limitation(&self) -> BTreeMap<Limitation, (usize, Vec<&Str>)> {
+        let mut groups: BTreeMap<Limitation, (usize, Vec<&Str>)> = BTreeMap::new();
+        for f in &self.failures {
+            for (limitation, _) in &f.errors {
+                let (count, classes) = groups.entry(*limitation).or_default();
+                *count += 1;
+                if classes.last() != Some(&&f.class) {
+                    classes.push(&f.class);
+                }
+            }
+        }
+        groups
+    }
+}
+
+/// The file and the message of an error line of the Haxe compiler, like
+/// `pack/Name.hx:12: characters 5-10 : Unknown identifier : x`
+fn error_location(line: &str) -> Option<(&str, &str)> {
+    let (position, message) = line.split_once(" : ")?;
+    let end = position.find(".hx:")? + ".hx".len();
+    Some((&position[..end], message.trim()))
+}
+
+/// Fully qualified name of the type declared in a file of the source tree in `dir`
+fn type_name(dir: &Path, file: &str) -> Option<String> {
+    let file = Path::new(file);
+    let path = file.strip_prefix(dir).unwrap_or(file).with_extension("");
+    let parts: Option<Vec<&str>> = path.iter().map(|p| p.to_str()).collect();
+    Some(parts?.join("."))
+}
+
+/// Type check classes of the decompiled source tree in `dir` in one run of the compiler, returns its error lines
+fn check(dir: &Path, classes: &[&Str]) -> anyhow::Result<Vec<String>> {
+    let output = Command::new("haxe")
+        .arg("-cp")
+        .arg(dir)
+        .args([
+            "--no-output",
+            "-hl",
+            "verify.hl",
+            "-D",
+            "message.reporting=classic",
+        ])
+        .args(classes.iter().map(|c| c.as_str()))
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .output()?;
+    if output.status.success() {
+        return Ok(Vec::new());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let mut errors: Vec<String> = stderr
+        .lines()
+        .filter(|l| error_location(l).is_some())
+        .map(str::to_owned)
+        .collect();
+    if errors.is_empty() {
+        errors.push(stderr.trim().to_owned());
+    }
+    Ok(errors)
+}
+
+/// Group the error lines of the compiler by the checked class declared in their file
+fn report(dir: &Path, classes: &[&Str], errors: Vec<String>) -> VerifyReport {
+    let mut failures: BTreeMap<&Str, Vec<(Limitation, String)>> = BTreeMap::new();
+    let mut other_errors = Vec::new();
+    for line in errors {
+        let located = error_location(&line).and_then(|(file, message)| {
+            let name = type_name(dir, file)?;
+            let class = classes.iter().find(|&&c| *c == *name)?;
+            Some((*class, message))
+        });
+        match located {
+            Some((class, message)) => failures
+                .entry(class)
+                .or_default()
+                .push((Limitation::classify(message), message.to_owned())),
+            None => other_errors.push(line),
+        }
+    }
+    VerifyReport {
+        checked: classes.len(),
+        failures: failures
+            .into_iter()
+            .map(|(class, errors)| Failure {
+                class: class.clone(),
+                errors,
+            })
+            .collect(),
+        other_errors,
+    }
+}
+
+/// Decompile the classes of the program (the standard library excluded) and compile the ones matching a glob (all if
+/// `globs` is empty) with the Haxe compiler. Requires having `haxe` in the `PATH`.
+///
+/// The classes are compiled together, an error stopping the compiler early hides the errors of the classes it
+/// didn't get to.
+pub fn verify(code: &Bytecode, globs: &[Str]) -> anyhow::Result<VerifyReport> {
+    // The decompiled standard library would replace the real one
+    let program: HashSet<Str> = code
+        .types
+        .iter()
+        .filter_map(|t| match t {
+            Type::Obj(obj) if !obj.is_from_std(code) => Some(obj.name(code)),
+            Type::Enum { name, .. } if !name.is_null() => Some(code.get(*name)),
+            _ => None,
+        })
+        .filter(|name| {
+            !name.starts_with('$') && !name.starts_with("haxe.") && !name.starts_with("hl.")
+        })
+        .collect();
+    let dir = TempDir::new()?;
+    write_source_tree_matching(
+        code,
+        dir.path(),
+        &DecompilerOptions::default(),
+        &FormatOptions::new(2),
+        |name| program.contains(name),
+    )?;
+
+    let mut classes: Vec<&Str> = program
+        .iter()
+        .filter(|name| globs.is_empty() || globs.iter().any(|g| glob_match(g, name)))
+        .collect();
+    classes.sort();
+    if classes.is_empty() {
+        return Ok(VerifyReport::default());
+    }
+    let errors = check(dir.path(), &classes)?;
+    Ok(report(dir.path(), &classes, errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use hlbc::Str;
+
+    use crate::verify::{error_location, report, Failure, Limitation, VerifyReport};
+
+    #[test]
+    fn classify() {
+        let line = "game/Player.hx:12: characters 5-10 : Unknown identifier : speed";
+        let (file, message) = error_location(line).unwrap();
+        assert_eq!(file, "game/Player.hx");
+        assert_eq!(message, "Unknown identifier : speed");
+        assert_eq!(Limitation::classify(message), Limitation::UnknownIdentifier);
+        assert_eq!(Limitation::classify("Unexpected ["), Limitation::Syntax);
+        assert_eq!(Limitation::classify("Missing ;"), Limitation::Syntax);
+        assert_eq!(
+            Limitation::classify("Int should be String"),
+            Limitation::TypeMismatch
+        );
+        assert_eq!(
+            Limitation::classify("Missing return: Int"),
+            Limitation::MissingReturn
+        );
+        // Not a syntax error, even if it starts like one
+        assert_eq!(
+            Limitation::classify("Missing arguments for the call"),
+            Limitation::Other
+        );
+        assert_eq!(error_location("Compilation failed"), None);
+
+        let report = VerifyReport {
+            checked: 4,
+            failures: vec![Failure {
+                class: Str::from("game.Player"),
+                errors: vec![
+                    (Limitation::Syntax, "Unexpected [".to_owned()),
+                    (Limitation::Syntax, "Unexpected ]".to_owned()),
+                ],
+            }],
+            other_errors: Vec::new(),
+        };
+        assert_eq!(report.score(), 0.75);
+        assert_eq!(report.by_limitation()[&Limitation::Syntax].0, 2);
+        assert_eq!(report.by_limitation()[&Limitation::Syntax].1.len(), 1);
+    }
+
+    #[test]
+    fn errors_by_file() {
+        let dir = Path::new("/tmp/tree");
+        let player = Str::from("game.Player");
+        let main = Str::from("Main");
+        let errors = [
+            "/tmp/tree/game/Player.hx:3: characters 1-5 : Unexpected [",
+            "Main.hx:8: characters 2-9 : Unknown identifier : x",
+            "game/Player.hx:9: lines 9-12 : Missing return: Int",
+            "game/Point.hx:1: characters 1-5 : Unexpected }",
+            "Compilation failed",
+        ]
+        .map(str::to_owned);
+        let report = report(dir, &[&main, &player], errors.to_vec());
+        assert_eq!(report.checked, 2);
+        let failures: Vec<_> = report
+            .failures
+            .iter()
+            .map(|f| (f.class.as_str(), f.errors.len()))
+            .collect();
+        assert_eq!(failures, [("Main", 1), ("game.Player", 2)]);
+        assert_eq!(
+            report.failures[1].errors[1],
+            (Limitation::MissingReturn, "Missing return: Int".to_owned())
+        );
+        assert_eq!(report.other_errors, &errors[3..]);
+    }
+}
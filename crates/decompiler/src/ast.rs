@@ -1,15 +1,19 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use hlbc::fmt::EnhancedFmt;
 use hlbc::types::{RefEnumConstruct, RefField, RefFloat, RefFun, RefInt, RefString, RefType, Reg};
 use hlbc::{Bytecode, Str};
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct SourceFile {
     pub class: Class,
 }
 
+/// A class declaration. Interfaces, `final` and visibility modifiers aren't kept in the bytecode, they can't be
+/// recovered.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Class {
     pub name: Str,
     pub parent: Option<Str>,
@@ -17,27 +21,110 @@ pub struct Class {
     pub methods: Vec<Method>,
 }
 
+#[cfg(feature = "serde")]
+impl Class {
+    /// Serialize the decompiled class to json. Bytecode elements are serialized as their index.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
+
+/// The types declared in a package
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Module {
+    /// Dotted package path, empty for the top level package
+    pub package: Str,
+    /// Types from other packages used in this one, sorted
+    pub imports: Vec<Str>,
+    pub classes: Vec<Class>,
+    pub enums: Vec<Enum>,
+    pub typedefs: Vec<Typedef>,
+}
+
+#[cfg(feature = "serde")]
+impl Module {
+    /// Serialize the decompiled package to json. Bytecode elements are serialized as their index.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Enum {
+    pub name: Str,
+    pub variants: Vec<EnumVariant>,
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct EnumVariant {
+    pub name: Str,
+    pub params: Vec<RefType>,
+}
+
+/// An anonymous structure type
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Typedef {
+    pub name: Str,
+    pub fields: Vec<(Str, RefType)>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct ClassField {
     pub name: Str,
     pub ty: RefType,
     pub static_: bool,
+    /// Initial value of a static variable, when it is set to a constant on startup
+    pub value: Option<Constant>,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Method {
     pub fun: RefFun,
     pub static_: bool,
     pub dynamic: bool,
+    /// Redefines a method of a superclass
+    pub override_: bool,
     pub statements: Vec<Statement>,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl Method {
+    /// The parameters, without `this` for instance methods
+    pub fn params(&self, code: &Bytecode) -> Vec<Param> {
+        crate::params(
+            code,
+            self.fun.as_fn(code).unwrap(),
+            usize::from(!self.static_),
+        )
+    }
+
+    pub fn return_type(&self, code: &Bytecode) -> RefType {
+        self.fun.as_fn(code).unwrap().ty(code).ret
+    }
+}
+
+/// A parameter of a function
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Param {
+    pub name: Str,
+    pub ty: RefType,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Constant {
     InlineInt(usize),
     Int(RefInt),
     Float(RefFloat),
     String(RefString),
+    /// A string that isn't in the constant pool, like a string decoded by a [StringDecoders](crate::StringDecoders)
+    Str(Str),
     Bool(bool),
     Null,
     /// 'this' instance
@@ -45,6 +132,7 @@ pub enum Constant {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Operation {
     /// `+`
     Add(Box<Expr>, Box<Expr>),
@@ -86,10 +174,49 @@ pub enum Operation {
     Lt(Box<Expr>, Box<Expr>),
     /// \<=
     Lte(Box<Expr>, Box<Expr>),
+    /// `...`, the range of a for loop
+    Interval(Box<Expr>, Box<Expr>),
+    /// `??`
+    NullCoalesce(Box<Expr>, Box<Expr>),
+}
+
+impl Operation {
+    /// How tightly the operator binds its operands, higher binds tighter
+    pub fn precedence(&self) -> u8 {
+        use Operation::*;
+        match self {
+            Neg(_) | Not(_) | Incr(_) | Decr(_) => 10,
+            Mod(_, _) => 9,
+            Mul(_, _) | Div(_, _) => 8,
+            Add(_, _) | Sub(_, _) => 7,
+            Shl(_, _) | Shr(_, _) => 6,
+            Xor(_, _) => 5,
+            Eq(_, _) | NotEq(_, _) | Gt(_, _) | Gte(_, _) | Lt(_, _) | Lte(_, _) => 4,
+            Interval(_, _) => 3,
+            And(_, _) => 2,
+            Or(_, _) => 1,
+            NullCoalesce(_, _) => 0,
+        }
+    }
+
+    /// An operand of this operation must be put in parentheses, `right` for the right operand and the operand of
+    /// unary operators.
+    pub fn needs_parens(&self, operand: &Expr, right: bool) -> bool {
+        let Expr::Op(inner) = operand else {
+            return false;
+        };
+        let (outer, inner) = (self.precedence(), inner.precedence());
+        // && and || are associative, a && (b && c) is a && b && c
+        inner < outer
+            || (right
+                && inner == outer
+                && !matches!(self, Operation::And(_, _) | Operation::Or(_, _)))
+    }
 }
 
 /// Constructor call
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct ConstructorCall {
     pub ty: RefType,
     pub args: Vec<Expr>,
@@ -101,8 +228,35 @@ impl ConstructorCall {
     }
 }
 
+/// Anonymous function
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Closure {
+    pub fun: RefFun,
+    /// Name of the anonymous function inferred from the use of the closure, like the parameter receiving it
+    pub name: Option<Str>,
+    /// The first argument of the function is the environment holding the captured variables
+    pub has_env: bool,
+    /// Values captured from the enclosing function, in the order of the environment fields.
+    /// Accesses to the environment in the body are replaced by those.
+    pub captures: Vec<Expr>,
+    pub stmts: Vec<Statement>,
+}
+
+impl Closure {
+    /// The expression the closure body is made of, if it's only that, to use the short arrow function syntax
+    pub fn body_expr(&self) -> Option<&Expr> {
+        let mut stmts = self.stmts.iter().filter(|s| s.is_code());
+        match (stmts.next(), stmts.next()) {
+            (Some(Statement::Return(Some(e)) | Statement::ExprStatement(e)), None) => Some(e),
+            _ => None,
+        }
+    }
+}
+
 /// Function or method call
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Call {
     pub fun: Expr,
     pub args: Vec<Expr>,
@@ -123,19 +277,24 @@ impl Call {
 
 /// An expression with a value
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Expr {
     /// An anonymous structure : { field: value }
-    Anonymous(RefType, HashMap<RefField, Expr>),
+    Anonymous(RefType, BTreeMap<RefField, Expr>),
     /// Array access : array\[index]
     Array(Box<Expr>, Box<Expr>),
+    /// Array literal : \[a, b, c]
+    ArrayLiteral(Vec<Expr>),
     /// Function call
     Call(Box<Call>),
+    /// Type conversion to the given type
+    Cast(Box<Expr>, RefType, CastKind),
     /// Constant value
     Constant(Constant),
     /// Constructor call
     Constructor(ConstructorCall),
     /// Arrow function (...) -> {...}
-    Closure(RefFun, Vec<Statement>),
+    Closure(Closure),
     EnumConstr(RefType, RefEnumConstruct, Vec<Expr>),
     /// Field access : obj.field
     Field(Box<Expr>, Str),
@@ -149,14 +308,82 @@ pub enum Expr {
         /// Not empty
         else_: Vec<Statement>,
     },
+    /// Map literal : \[key => value]
+    MapLiteral(Vec<(Expr, Expr)>),
     /// Operator
     Op(Operation),
+    /// Safe navigation, null if the object is null : obj?.field
+    SafeField(Box<Expr>, Str),
+    /// Path of a class, to access its static fields : haxe.Log
+    TypePath(Str),
     // For when there should be something, but we don't known what
     Unknown(String),
     /// Variable identifier
     Variable(Reg, Option<Str>),
 }
 
+impl Expr {
+    /// Evaluating the expression does more than computing a value : it calls code, increments a variable or allocates
+    /// a new object. Such an expression can't be duplicated, or moved past another one, without changing the program.
+    pub fn has_side_effects(&self) -> bool {
+        use Operation::*;
+        match self {
+            Expr::Anonymous(..)
+            | Expr::ArrayLiteral(_)
+            | Expr::Call(_)
+            | Expr::Constructor(_)
+            | Expr::Closure(_)
+            | Expr::IfElse { .. }
+            | Expr::MapLiteral(_) => true,
+            // Variants without parameters are shared
+            Expr::EnumConstr(_, _, args) => !args.is_empty(),
+            Expr::Array(array, index) => array.has_side_effects() || index.has_side_effects(),
+            Expr::Cast(e, _, _) | Expr::Field(e, _) | Expr::SafeField(e, _) => e.has_side_effects(),
+            Expr::Op(op) => match op {
+                Incr(_) | Decr(_) => true,
+                Neg(e) | Not(e) => e.has_side_effects(),
+                Add(a, b)
+                | Sub(a, b)
+                | Mul(a, b)
+                | Div(a, b)
+                | Mod(a, b)
+                | Shl(a, b)
+                | Shr(a, b)
+                | And(a, b)
+                | Or(a, b)
+                | Xor(a, b)
+                | Eq(a, b)
+                | NotEq(a, b)
+                | Gt(a, b)
+                | Gte(a, b)
+                | Lt(a, b)
+                | Lte(a, b)
+                | Interval(a, b)
+                | NullCoalesce(a, b) => a.has_side_effects() || b.has_side_effects(),
+            },
+            Expr::Constant(_)
+            | Expr::FunRef(_)
+            | Expr::TypePath(_)
+            | Expr::Unknown(_)
+            | Expr::Variable(..) => false,
+        }
+    }
+}
+
+/// How a value is converted, which decides how the cast is written
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum CastKind {
+    /// Throws if the value has another type : cast(expr, Type)
+    Checked,
+    /// Unchecked cast : cast expr
+    Unchecked,
+    /// Float to int truncation : Std.int(expr)
+    ToInt,
+    /// A conversion Haxe does implicitly (to Dynamic, to Float, to a structure type) : (expr : Type)
+    Implicit,
+}
+
 pub const fn cst_int(cst: RefInt) -> Expr {
     Expr::Constant(Constant::Int(cst))
 }
@@ -209,6 +436,8 @@ make_op_shorthand!(gt, Gt, e1, e2);
 make_op_shorthand!(gte, Gte, e1, e2);
 make_op_shorthand!(lt, Lt, e1, e2);
 make_op_shorthand!(lte, Lte, e1, e2);
+make_op_shorthand!(interval, Interval, e1, e2);
+make_op_shorthand!(null_coalesce, NullCoalesce, e1, e2);
 
 /// Invert an expression, will also optimize the expression.
 pub fn not(e: Expr) -> Expr {
@@ -254,6 +483,11 @@ pub fn call_fun(fun: RefFun, args: Vec<Expr>) -> Expr {
     Expr::Call(Box::new(Call::new_fun(fun, args)))
 }
 
+/// Call to a static function through its class : Std.string(x)
+pub fn static_call(class: Str, name: Str, args: Vec<Expr>) -> Expr {
+    call(Expr::Field(Box::new(Expr::TypePath(class)), name), args)
+}
+
 pub fn field(expr: Expr, obj: RefType, field: RefField, code: &Bytecode) -> Expr {
     // FIXME meh
     Expr::Field(
@@ -263,6 +497,7 @@ pub fn field(expr: Expr, obj: RefType, field: RefField, code: &Bytecode) -> Expr
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Statement {
     /// Variable assignment
     Assign {
@@ -271,6 +506,13 @@ pub enum Statement {
         variable: Expr,
         assign: Expr,
     },
+    /// Assignment combined with an operator : `variable += value`
+    CompoundAssign {
+        variable: Expr,
+        /// The assignment operator, like `+=`
+        op: &'static str,
+        value: Expr,
+    },
     /// Expression statement
     ExprStatement(Expr),
     /// Return an expression or nothing (void)
@@ -292,6 +534,12 @@ pub enum Statement {
         cond: Expr,
         stmts: Vec<Statement>,
     },
+    /// For loop over an interval or an iterator
+    For {
+        variable: Expr,
+        iterable: Expr,
+        stmts: Vec<Statement>,
+    },
     Break,
     Continue,
     Throw(Expr),
@@ -301,9 +549,28 @@ pub enum Statement {
     Catch {
         stmts: Vec<Statement>,
     },
+    /// Target of a [Statement::Goto], identified by the position of the opcode it marks
+    Label(usize),
+    /// Jump to a [Statement::Label], when the control flow couldn't be structured
+    Goto(usize),
+    /// An opcode of the function, emitted before the statements it produced when interleaving opcodes
+    Opcode(usize),
+    /// Opcodes the next statement has been decompiled from, the ones of the values inlined in it included, sorted.
+    /// Recorded when interleaving opcodes, it is not rendered.
+    Source(Vec<usize>),
     Comment(String),
 }
 
+impl Statement {
+    /// False for comments, interleaved opcodes and sources, which can be looked past
+    pub fn is_code(&self) -> bool {
+        !matches!(
+            self,
+            Statement::Comment(_) | Statement::Opcode(_) | Statement::Source(_)
+        )
+    }
+}
+
 /// Create an expression statement
 pub fn stmt(e: Expr) -> Statement {
     Statement::ExprStatement(e)
@@ -0,0 +1,136 @@
+use std::vec;
+
+use hlbc::cancel::CancellationToken;
+use hlbc::types::Function;
+use hlbc::Bytecode;
+
+use crate::ast::Method;
+use crate::{decompile_function_with, DecompilerOptions};
+
+/// Decompile many functions a few at a time.
+///
+/// Decompiling a whole game takes seconds, which is too long to block a thread that also has to stay responsive,
+/// like the UI thread of a browser where there are no other threads to offload work to.
+/// A batch is its own continuation : call [Batch::step] once per frame (or per tick of any event loop)
+/// until it [is done](Batch::is_done).
+///
+/// ```
+/// # use hlbc::Bytecode;
+/// # use hlbc_decompiler::{Batch, DecompilerOptions};
+/// # let code = Bytecode::default();
+/// let mut batch = Batch::all(&code, DecompilerOptions::default());
+/// while !batch.is_done() {
+///     for method in batch.step(16) {
+///         // ...
+///     }
+///     // Yield to the event loop
+/// }
+/// ```
+///
+/// A batch is also an [Iterator] over the decompiled methods.
+///
+/// Batches only borrow the bytecode and are [Send], threads can share one loaded bytecode and each decompile
+/// their own part of it :
+///
+/// ```
+/// # use std::thread;
+/// # use hlbc::Bytecode;
+/// # use hlbc_decompiler::{Batch, DecompilerOptions};
+/// # let code = &Bytecode::default();
+/// thread::scope(|s| {
+///     for chunk in code.functions.chunks(256) {
+///         s.spawn(move || Batch::new(code, chunk, DecompilerOptions::default()).count());
+///     }
+/// });
+/// ```
+pub struct Batch<'c> {
+    code: &'c Bytecode,
+    opts: DecompilerOptions,
+    pending: vec::IntoIter<&'c Function>,
+    total: usize,
+    cancel: CancellationToken,
+}
+
+impl<'c> Batch<'c> {
+    pub fn new(
+        code: &'c Bytecode,
+        functions: impl IntoIterator<Item = &'c Function>,
+        opts: DecompilerOptions,
+    ) -> Self {
+        let pending: Vec<_> = functions.into_iter().collect();
+        Self {
+            code,
+            opts,
+            total: pending.len(),
+            pending: pending.into_iter(),
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Stop the batch when `cancel` is cancelled, it is then done even if some functions haven't been decompiled.
+    /// The token is checked before each function.
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Every function in the bytecode, in order
+    pub fn all(code: &'c Bytecode, opts: DecompilerOptions) -> Self {
+        Self::new(code, &code.functions, opts)
+    }
+
+    /// Decompile up to `n` functions
+    pub fn step(&mut self, n: usize) -> Vec<Method> {
+        self.by_ref().take(n).collect()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.pending.as_slice().is_empty() || self.is_cancelled()
+    }
+
+    /// The batch has been stopped before decompiling every function
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Number of functions decompiled so far
+    pub fn done(&self) -> usize {
+        self.total - self.pending.len()
+    }
+
+    /// Number of functions in the batch
+    pub fn total(&self) -> usize {
+        self.total
+    }
+}
+
+impl Iterator for Batch<'_> {
+    type Item = Method;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_cancelled() {
+            return None;
+        }
+        self.pending
+            .next()
+            .map(|f| decompile_function_with(self.code, f, &self.opts))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.is_cancelled() {
+            return (0, Some(0));
+        }
+        self.pending.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Batch<'_> {}
+
+// Decompilation results are sent back from worker threads
+const _: () = {
+    const fn assert_send<T: Send>() {}
+    assert_send::<Batch<'static>>();
+    assert_send::<Method>();
+    assert_send::<crate::ast::Class>();
+    assert_send::<crate::ast::Module>();
+};
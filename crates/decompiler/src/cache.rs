@@ -0,0 +1,259 @@
+use std::collections::{HashMap, HashSet};
+
+use hlbc::opcodes::Opcode;
+use hlbc::types::RefFun;
+use hlbc::Bytecode;
+
+use crate::ast::{Method, Statement};
+use crate::fmt::FormatOptions;
+use crate::{decompile_function_with, DecompilerOptions};
+
+/// Decompiled functions, kept until they are invalidated.
+///
+/// Interactive tools show the same functions over and over, decompiling them again on every click is wasteful.
+/// The cache decompiles a function the first time it is asked for, and renders it the first time its source is asked
+/// for.
+///
+/// The cache doesn't watch the bytecode : call [DecompileCache::invalidate] after patching a function and
+/// [DecompileCache::clear] after bigger changes, like loading another file.
+///
+/// ```
+/// # use hlbc::Bytecode;
+/// # use hlbc::types::RefFun;
+/// # use hlbc_decompiler::fmt::FormatOptions;
+/// # use hlbc_decompiler::{DecompileCache, DecompilerOptions};
+/// # let code = Bytecode::default();
+/// let mut cache = DecompileCache::new(DecompilerOptions::default(), FormatOptions::new(2));
+/// // Decompiled and rendered on the first call only
+/// if let Some(source) = cache.source(&code, RefFun(0)) {
+///     println!("{source}");
+/// }
+/// ```
+pub struct DecompileCache {
+    opts: DecompilerOptions,
+    fmt: FormatOptions,
+    entries: HashMap<RefFun, Entry>,
+}
+
+struct Entry {
+    method: Method,
+    /// Rendered on demand
+    source: Option<String>,
+}
+
+impl DecompileCache {
+    pub fn new(opts: DecompilerOptions, fmt: FormatOptions) -> Self {
+        Self {
+            opts,
+            fmt,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn options(&self) -> &DecompilerOptions {
+        &self.opts
+    }
+
+    /// Change the decompiler options, every function will be decompiled again
+    pub fn set_options(&mut self, opts: DecompilerOptions) {
+        self.opts = opts;
+        self.clear();
+    }
+
+    /// Change how functions are rendered, the decompiled functions are kept
+    pub fn set_format(&mut self, fmt: FormatOptions) {
+        self.fmt = fmt;
+        for entry in self.entries.values_mut() {
+            entry.source = None;
+        }
+    }
+
+    /// The decompiled function, `None` for natives and invalid indices
+    pub fn method(&mut self, code: &Bytecode, fun: RefFun) -> Option<&Method> {
+        self.entry(code, fun).map(|e| &e.method)
+    }
+
+    /// The statements of the decompiled function, see [DecompileCache::method]
+    pub fn statements(&mut self, code: &Bytecode, fun: RefFun) -> Option<&[Statement]> {
+        self.method(code, fun).map(|m| m.statements.as_slice())
+    }
+
+    /// The rendered source of the function, see [DecompileCache::method]
+    pub fn source(&mut self, code: &Bytecode, fun: RefFun) -> Option<&str> {
+        let fmt = self.fmt.clone();
+        let Entry { method, source } = self.entry(code, fun)?;
+        Some(
+            source
+                .get_or_insert_with(|| method.display(code, &fmt).to_string())
+                .as_str(),
+        )
+    }
+
+    fn entry(&mut self, code: &Bytecode, fun: RefFun) -> Option<&mut Entry> {
+        let f = fun.try_as_fn(code).ok()?;
+        let opts = &self.opts;
+        Some(self.entries.entry(fun).or_insert_with(|| Entry {
+            method: decompile_function_with(code, f, opts),
+            source: None,
+        }))
+    }
+
+    /// The function has been decompiled and not invalidated since
+    pub fn is_cached(&self, fun: RefFun) -> bool {
+        self.entries.contains_key(&fun)
+    }
+
+    /// Forget a function after patching it, `code` is the patched bytecode.
+    ///
+    /// The functions whose output depends on it are forgotten too : its callers and their own callers, as a call can
+    /// be simplified depending on the called code (property accessors, constant returns following chains of getters),
+    /// and the functions creating any of those as a closure, since closures are decompiled inline, however deeply they
+    /// are nested.
+    pub fn invalidate(&mut self, code: &Bytecode, fun: RefFun) {
+        let mut seen = HashSet::new();
+        // The code of the functions creating a closure doesn't change, only their output : their callers are not
+        // affected
+        let mut changed = vec![(fun, true)];
+        while let Some((fun, called)) = changed.pop() {
+            if !seen.insert((fun, called)) {
+                continue;
+            }
+            self.entries.remove(&fun);
+            for (parent, closure) in references(code, fun) {
+                if closure {
+                    changed.push((parent, false));
+                } else if called {
+                    changed.push((parent, true));
+                }
+            }
+        }
+    }
+
+    /// Forget everything
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// Functions referencing `fun`, and whether they create a closure of it
+fn references(code: &Bytecode, fun: RefFun) -> impl Iterator<Item = (RefFun, bool)> + '_ {
+    code.functions.iter().flat_map(move |f| {
+        f.find_fun_refs()
+            .filter(move |&(_, _, target)| target == fun)
+            .map(move |(_, op, _)| {
+                let closure = matches!(
+                    op,
+                    Opcode::StaticClosure { .. } | Opcode::InstanceClosure { .. }
+                );
+                (f.findex, closure)
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::Builder;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefFun, Reg, Type};
+
+    use crate::fmt::FormatOptions;
+    use crate::{DecompileCache, DecompilerOptions};
+
+    #[test]
+    fn invalidate() {
+        let ret = Opcode::Ret { ret: Reg(0) };
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        let caller = b.function("", &[], void, &[void], Vec::new());
+        let callee = b.function("", &[], void, &[void], vec![ret.clone()]);
+        let other = b.function("", &[], void, &[void], vec![ret.clone()]);
+        b.function_mut(caller).ops = vec![
+            Opcode::Call0 {
+                dst: Reg(0),
+                fun: callee,
+            },
+            ret.clone(),
+        ];
+        // A getter calling a getter, its constant return follows the chain down to callee
+        let top = b.function(
+            "",
+            &[],
+            void,
+            &[void],
+            vec![
+                Opcode::Call0 {
+                    dst: Reg(0),
+                    fun: caller,
+                },
+                ret,
+            ],
+        );
+        let code = b.build().unwrap();
+        let mut cache = DecompileCache::new(DecompilerOptions::default(), FormatOptions::new(2));
+        for f in [caller, callee, other, top] {
+            assert!(cache.source(&code, f).is_some());
+        }
+        assert!(cache.source(&code, RefFun(4)).is_none());
+
+        cache.invalidate(&code, callee);
+        assert!(!cache.is_cached(caller));
+        assert!(!cache.is_cached(callee));
+        assert!(!cache.is_cached(top));
+        assert!(cache.is_cached(other));
+    }
+
+    #[test]
+    fn invalidate_nested_closures() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        let closure_t = b.fun_type(&[], void);
+        let callee = b.function("", &[], void, &[void], vec![Opcode::Ret { ret: Reg(0) }]);
+        // outer creates middle, which creates inner, which calls callee
+        let closure = |fun| {
+            vec![
+                Opcode::StaticClosure { dst: Reg(0), fun },
+                Opcode::Ret { ret: Reg(0) },
+            ]
+        };
+        let inner = b.function(
+            "",
+            &[],
+            void,
+            &[void],
+            vec![
+                Opcode::Call0 {
+                    dst: Reg(0),
+                    fun: callee,
+                },
+                Opcode::Ret { ret: Reg(0) },
+            ],
+        );
+        let middle = b.function("", &[], closure_t, &[closure_t], closure(inner));
+        let outer = b.function("", &[], closure_t, &[closure_t], closure(middle));
+        // Calls outer, doesn't depend on the closures
+        let user = b.function(
+            "",
+            &[],
+            void,
+            &[closure_t],
+            vec![
+                Opcode::Call0 {
+                    dst: Reg(0),
+                    fun: outer,
+                },
+                Opcode::Ret { ret: Reg(0) },
+            ],
+        );
+        let code = b.build().unwrap();
+        let mut cache = DecompileCache::new(DecompilerOptions::default(), FormatOptions::new(2));
+        for f in [callee, inner, middle, outer, user] {
+            assert!(cache.source(&code, f).is_some());
+        }
+
+        cache.invalidate(&code, callee);
+        for f in [callee, inner, middle, outer] {
+            assert!(!cache.is_cached(f));
+        }
+        assert!(cache.is_cached(user));
+    }
+}
@@ -0,0 +1,89 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use hlbc::types::{Function, RefFun};
+use hlbc::{Bytecode, Resolve};
+
+/// An opcode the decompiler couldn't make sense of.
+/// It is kept as a comment in the output so the rest of the function can still be decompiled.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostic {
+    /// Function containing the opcode, closures are decompiled with their parent function
+    pub findex: RefFun,
+    /// Index of the opcode in the function
+    pub pos: usize,
+    pub reason: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fn@{} opcode {}: {}",
+            self.findex.0, self.pos, self.reason
+        )
+    }
+}
+
+/// Error returned by [try_decompile_function](crate::try_decompile_function)
+#[derive(Debug, Clone)]
+pub struct DecompileError {
+    /// Every problem found in the function, in order
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Display for DecompileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to decompile {} opcodes", self.diagnostics.len())?;
+        for d in &self.diagnostics {
+            write!(f, "\n  {d}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for DecompileError {}
+
+/// Find the indices the decompiler follows without checking them : the type of the function and of its registers, and
+/// the registers of each opcode. A function with such problems can't be decompiled at all.
+///
+/// Problems with the function itself are reported at its first opcode.
+pub(crate) fn validate(code: &Bytecode, f: &Function) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut report = |pos, reason| {
+        diagnostics.push(Diagnostic {
+            findex: f.findex,
+            pos,
+            reason,
+        })
+    };
+    match f.t.as_fun(code) {
+        Some(ty) if ty.args.len() > f.regs.len() => report(
+            0,
+            format!("{} arguments for {} registers", ty.args.len(), f.regs.len()),
+        ),
+        Some(_) => {}
+        None => report(0, format!("type@{} is not a function type", f.t.0)),
+    }
+    for (i, &ty) in f.regs.iter().enumerate() {
+        if let Err(e) = code.try_get(ty) {
+            report(0, format!("type of reg{i}: {e}"));
+        }
+    }
+    for (pos, op) in f.ops.iter().enumerate() {
+        for (name, reg) in op.registers() {
+            if reg.0 as usize >= f.regs.len() {
+                report(
+                    pos,
+                    format!(
+                        "{name} is reg{} but there are {} registers",
+                        reg.0,
+                        f.regs.len()
+                    ),
+                );
+            }
+        }
+    }
+    diagnostics
+}
@@ -0,0 +1,131 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::path::Path;
+use std::{fs, io};
+
+use hlbc::types::{RefType, Type, TypeObj};
+use hlbc::{Bytecode, Str};
+
+use crate::decompile_class_outline;
+use crate::fmt::{to_haxe_type, FormatOptions};
+
+/// Classes documented together, so they can link to each other
+struct Docs<'c> {
+    code: &'c Bytecode,
+    /// Documented classes, sorted by name
+    classes: Vec<(Str, RefType, &'c TypeObj)>,
+    documented: HashSet<RefType>,
+    subclasses: HashMap<RefType, Vec<RefType>>,
+}
+
+impl<'c> Docs<'c> {
+    fn new(code: &'c Bytecode, include_std: bool) -> Self {
+        let mut classes = Vec::new();
+        let mut subclasses: HashMap<RefType, Vec<RefType>> = HashMap::new();
+        for (i, ty) in code.types.iter().enumerate() {
+            let Type::Obj(obj) = ty else {
+                continue;
+            };
+            let name = obj.name(code);
+            // Static members are documented with their class
+            if name.starts_with('$') || (!include_std && obj.is_from_std(code)) {
+                continue;
+            }
+            if let Some(parent) = obj.super_ {
+                subclasses.entry(parent).or_default().push(RefType(i));
+            }
+            classes.push((name, RefType(i), obj));
+        }
+        classes.sort_by(|a, b| a.0.cmp(&b.0));
+        Self {
+            code,
+            documented: classes.iter().map(|&(_, ty, _)| ty).collect(),
+            classes,
+            subclasses,
+        }
+    }
+
+    /// Link to the page of a class, or only its name if it isn't documented
+    fn link(&self, ty: RefType) -> String {
+        let name = ty.as_obj(self.code).map(|obj| obj.name(self.code));
+        let name = name.as_deref().unwrap_or("?");
+        if self.documented.contains(&ty) {
+            format!("[`{name}`]({})", page_name(name))
+        } else {
+            format!("`{name}`")
+        }
+    }
+
+    fn class_page(&self, ty: RefType, obj: &TypeObj) -> String {
+        let code = self.code;
+        let class = decompile_class_outline(code, obj);
+        let fmt = FormatOptions::new(0);
+        let mut out = String::new();
+        writeln!(out, "# `{}`\n", class.name).unwrap();
+
+        let mut parents = Vec::new();
+        let mut parent = obj.super_;
+        while let Some(p) = parent {
+            parents.push(self.link(p));
+            parent = p.as_obj(code).and_then(|obj| obj.super_);
+        }
+        if !parents.is_empty() {
+            writeln!(out, "Extends : {}\n", parents.join(" → ")).unwrap();
+        }
+        if let Some(children) = self.subclasses.get(&ty) {
+            let children: Vec<_> = children.iter().map(|&c| self.link(c)).collect();
+            writeln!(out, "Subclasses : {}\n", children.join(", ")).unwrap();
+        }
+
+        if !class.fields.is_empty() {
+            writeln!(out, "## Fields\n\n| Name | Type |\n|------|------|").unwrap();
+            for f in &class.fields {
+                writeln!(
+                    out,
+                    "| `{}{}` | `{}` |",
+                    if f.static_ { "static " } else { "" },
+                    f.name,
+                    to_haxe_type(&code[f.ty], code, &fmt)
+                )
+                .unwrap();
+            }
+            out.push('\n');
+        }
+
+        if !class.methods.is_empty() {
+            writeln!(out, "## Methods\n\n```haxe").unwrap();
+            for m in &class.methods {
+                writeln!(out, "{}", m.display_signature(code, &fmt)).unwrap();
+            }
+            writeln!(out, "```").unwrap();
+        }
+        out
+    }
+}
+
+/// File name of the page documenting a class
+fn page_name(class: &str) -> String {
+    format!("{class}.md")
+}
+
+/// Markdown reference page of a class : inheritance, fields with their types and method signatures.
+pub fn class_markdown(code: &Bytecode, ty: RefType) -> Option<String> {
+    let obj = ty.as_obj(code)?;
+    Some(Docs::new(code, true).class_page(ty, obj))
+}
+
+/// Write the Markdown reference of all the classes in `dir`, one page per class.
+///
+/// Also writes a `SUMMARY.md` listing every page so the directory can be used as the source of an
+/// [mdBook](https://rust-lang.github.io/mdBook/). Returns the number of documented classes.
+pub fn write_markdown_docs(code: &Bytecode, dir: &Path, include_std: bool) -> io::Result<usize> {
+    let docs = Docs::new(code, include_std);
+    fs::create_dir_all(dir)?;
+    let mut summary = String::from("# Summary\n\n");
+    for &(ref name, ty, obj) in &docs.classes {
+        fs::write(dir.join(page_name(name)), docs.class_page(ty, obj))?;
+        writeln!(summary, "- [{name}]({})", page_name(name)).unwrap();
+    }
+    fs::write(dir.join("SUMMARY.md"), summary)?;
+    Ok(docs.classes.len())
+}
@@ -0,0 +1,403 @@
+use std::fs;
+use std::io::BufWriter;
+use std::path::Path;
+
+use hlbc::asm::parse_opcode;
+use hlbc::fmt::EnhancedFmt;
+use hlbc::opcodes::Opcode;
+use hlbc::types::{FunPtr, Function, RefFun, RefType, Type, TypeObj};
+use hlbc::{Bytecode, Error, Resolve, Result};
+
+use crate::ast::Method;
+use crate::fmt::{to_haxe_type, FormatOptions};
+use crate::module::with_typedefs;
+use crate::tokens::{source_map, text, LineMapping, Token, TokenWriter};
+use crate::{decompile_class_with, decompile_function_with, DecompilerOptions};
+
+/// Indentation of the decompiled code
+pub const INDENT: usize = 2;
+
+/// Load a bytecode file, like `hlboot.dat`, a `.hl` file or an executable with the bytecode embedded.
+/// With the `serde` feature, a `.json` file is read back as exported by [Bytecode::to_json].
+pub fn open(path: impl AsRef<Path>) -> Result<Bytecode> {
+    let path = path.as_ref();
+    #[cfg(feature = "serde")]
+    if path.extension().is_some_and(|ext| ext == "json") {
+        return Bytecode::from_json(&fs::read_to_string(path)?);
+    }
+    // The first magic header found might not be the bytecode in an executable
+    Bytecode::from_file(path).or_else(|_| Bytecode::from_embedded(fs::File::open(path)?))
+}
+
+/// Serialize the bytecode to a file, with the changes made to it
+pub fn save(code: &Bytecode, path: impl AsRef<Path>) -> Result<()> {
+    let mut w = BufWriter::new(fs::File::create(path)?);
+    code.serialize(&mut w)
+}
+
+/// The signature of a function or a native, e.g. `fn update@12 (Game, f64) -> void`
+pub fn function_header(code: &Bytecode, findex: RefFun) -> Result<String> {
+    code.try_get(findex)?;
+    Ok(findex.display_header::<EnhancedFmt>(code).to_string())
+}
+
+/// The Haxe signature of a function for list views, e.g. `function update(dt: Float): Bool`.
+/// Parameters without a name in the debug info are named after their position. Natives are `extern`.
+pub fn signature(code: &Bytecode, findex: RefFun) -> Result<String> {
+    // Inferring the typedef names for every row of a list would be too slow
+    let opts = FormatOptions::new(INDENT);
+    Ok(match code.try_get(findex)? {
+        FunPtr::Fun(f) => Method {
+            fun: findex,
+            static_: !f.is_method(),
+            dynamic: false,
+            override_: false,
+            statements: Vec::new(),
+        }
+        .display_signature(code, &opts)
+        .to_string(),
+        FunPtr::Native(n) => {
+            let ty = n.ty(code);
+            let params = ty
+                .args
+                .iter()
+                .enumerate()
+                .map(|(i, arg)| format!("arg{i}: {}", to_haxe_type(&code[*arg], code, &opts)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let mut sig = format!("extern function {}({params})", n.name(code));
+            if !ty.ret.is_void() {
+                sig += &format!(": {}", to_haxe_type(&code[ty.ret], code, &opts));
+            }
+            sig
+        }
+    })
+}
+
+/// The opcode at `pos` in a function, as in the assembly view
+pub fn opcode(code: &Bytecode, f: &Function, pos: usize) -> Result<String> {
+    let op = f.ops.get(pos).ok_or(Error::InvalidIndex {
+        kind: "opcode",
+        index: pos,
+        len: f.ops.len(),
+    })?;
+    Ok(op.display(code, f, pos as i32, 11).to_string())
+}
+
+/// Every opcode of a function as in the assembly view. Empty for a native.
+pub fn disassemble(code: &Bytecode, findex: RefFun) -> Result<Vec<String>> {
+    Ok(match code.try_get(findex)? {
+        FunPtr::Fun(f) => (0..f.ops.len())
+            .map(|i| opcode(code, f, i))
+            .collect::<Result<_>>()?,
+        FunPtr::Native(_) => Vec::new(),
+    })
+}
+
+/// Replace the opcode at `pos` in a function with one written as in the assembly view, e.g. `Int reg0 = 5`.
+/// Returns the opcode it replaces. See [hlbc::asm] for the syntax, constants are looked up in the pools so new ones
+/// must be added first, e.g. with [Bytecode::add_int].
+pub fn patch_opcode(code: &mut Bytecode, findex: RefFun, pos: usize, text: &str) -> Result<Opcode> {
+    let op = parse_opcode(code, findex.try_as_fn(code)?, pos, text)?;
+    code.functions
+        .iter_mut()
+        .find(|f| f.findex == findex)
+        // Checked by try_as_fn
+        .unwrap()
+        .replace_op(pos, op)
+}
+
+/// The type name as in the disassembly, e.g. `i32` or `game.Player`
+pub fn type_name(code: &Bytecode, ty: RefType) -> Result<String> {
+    code.try_get(ty)?;
+    Ok(ty.display::<EnhancedFmt>(code).to_string())
+}
+
+/// Decompile a function to Haxe source code. Fails for a native.
+pub fn decompile_function(
+    code: &Bytecode,
+    findex: RefFun,
+    opts: &DecompilerOptions,
+) -> Result<String> {
+    decompile_function_indented(code, findex, opts, INDENT)
+}
+
+/// Like [decompile_function], indented with `indent` spaces instead of [INDENT]
+pub fn decompile_function_indented(
+    code: &Bytecode,
+    findex: RefFun,
+    opts: &DecompilerOptions,
+    indent: usize,
+) -> Result<String> {
+    let f = findex.try_as_fn(code)?;
+    Ok(decompile_function_with(code, f, opts)
+        .display(code, &format_options_indented(code, indent))
+        .to_string())
+}
+
+/// Decompile a class with its fields and methods to Haxe source code. Fails if the type isn't a class.
+pub fn decompile_class(code: &Bytecode, ty: RefType, opts: &DecompilerOptions) -> Result<String> {
+    decompile_class_indented(code, ty, opts, INDENT)
+}
+
+/// Like [decompile_class], indented with `indent` spaces instead of [INDENT]
+pub fn decompile_class_indented(
+    code: &Bytecode,
+    ty: RefType,
+    opts: &DecompilerOptions,
+    indent: usize,
+) -> Result<String> {
+    Ok(decompile_class_with(code, class(code, ty)?, opts)
+        .display(code, &format_options_indented(code, indent))
+        .to_string())
+}
+
+/// Decompile an enum declaration with its constructors to Haxe source code. Fails if the type isn't an enum.
+pub fn decompile_enum(code: &Bytecode, ty: RefType) -> Result<String> {
+    crate::decompile_enum(code, code.try_get(ty)?)
+        .map(|decl| decl.display(code, &format_options(code)).to_string())
+        .ok_or_else(|| Error::MalformedBytecode(format!("type@{} is not an enum", ty.0)))
+}
+
+/// Find a class by its full name, e.g. `my.pack.MyClass`
+pub fn find_class(code: &Bytecode, name: &str) -> Option<RefType> {
+    code.types
+        .iter()
+        .position(|t| matches!(t, Type::Obj(obj) if obj.name(code) == name))
+        .map(RefType)
+}
+
+/// Find a method or a static function by its qualified name, e.g. `my.pack.MyClass.update`
+pub fn find_method(code: &Bytecode, name: &str) -> Option<RefFun> {
+    let (class, method) = name.rsplit_once('.')?;
+    code.functions
+        .iter()
+        .find(|f| {
+            f.name(code) == method
+                && f.parent
+                    .and_then(|p| p.as_obj(code))
+                    // Static functions belong to the `$Class` type
+                    .is_some_and(|obj| obj.name(code).trim_start_matches('$') == class)
+        })
+        .map(|f| f.findex)
+}
+
+/// Like [decompile_function], but as tokens for syntax highlighting
+pub fn decompile_function_tokens(
+    code: &Bytecode,
+    findex: RefFun,
+    opts: &DecompilerOptions,
+) -> Result<Vec<Token>> {
+    let f = findex.try_as_fn(code)?;
+    let mut writer = TokenWriter::new(code);
+    writer.method(
+        &decompile_function_with(code, f, opts),
+        &format_options(code),
+    );
+    Ok(writer.finish())
+}
+
+/// Like [decompile_function], with the opcodes each line of the source has been decompiled from
+pub fn decompile_function_mapped(
+    code: &Bytecode,
+    findex: RefFun,
+    opts: &DecompilerOptions,
+) -> Result<(String, Vec<LineMapping>)> {
+    let f = findex.try_as_fn(code)?;
+    // The opcodes are needed to know where the statements come from, but not in the output
+    let opts = DecompilerOptions {
+        interleave_opcodes: true,
+        ..opts.clone()
+    };
+    let mut writer = TokenWriter::new(code).with_opcodes(false);
+    writer.method(
+        &decompile_function_with(code, f, &opts),
+        &format_options(code),
+    );
+    let tokens = writer.finish();
+    Ok((text(&tokens), source_map(&tokens)))
+}
+
+/// Like [decompile_class], but as tokens for syntax highlighting
+pub fn decompile_class_tokens(
+    code: &Bytecode,
+    ty: RefType,
+    opts: &DecompilerOptions,
+) -> Result<Vec<Token>> {
+    let obj = class(code, ty)?;
+    let mut writer = TokenWriter::new(code);
+    writer.class(
+        &decompile_class_with(code, obj, opts),
+        &format_options(code),
+    );
+    Ok(writer.finish())
+}
+
+/// Anonymous structures are named as in the exported source trees, instead of being displayed as `Dynamic`
+fn format_options(code: &Bytecode) -> FormatOptions {
+    format_options_indented(code, INDENT)
+}
+
+fn format_options_indented(code: &Bytecode, indent: usize) -> FormatOptions {
+    with_typedefs(code, &FormatOptions::new(indent))
+}
+
+fn class(code: &Bytecode, ty: RefType) -> Result<&TypeObj> {
+    match code.try_get(ty)? {
+        Type::Obj(obj) | Type::Struct(obj) => Ok(obj),
+        _ => Err(Error::MalformedBytecode(format!(
+            "type@{} is not a class",
+            ty.0
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::Builder;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{ObjField, RefFun, RefString, RefType, Reg, Type, TypeFun};
+    use hlbc::{Bytecode, Error, Str};
+
+    use crate::DecompilerOptions;
+    use crate::{facade, tokens};
+
+    /// The indexes are used by the tests : Int is type 1, Main is type 3 and its field name is string 3
+    fn code() -> Bytecode {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        let int = b.ty(Type::I32);
+        let main = b.function("main", &[], void, &[void], Vec::new());
+        b.class("Main", None, &[("x", int)]);
+        let log = b.native("std", "log", &[], void);
+        b.function_mut(main).ops = vec![
+            Opcode::Call0 {
+                dst: Reg(0),
+                fun: log,
+            },
+            Opcode::Ret { ret: Reg(0) },
+        ];
+        b.build().unwrap()
+    }
+
+    #[test]
+    fn query_and_decompile() {
+        let code = code();
+        let opts = DecompilerOptions::default();
+        assert_eq!(facade::disassemble(&code, RefFun(0)).unwrap().len(), 2);
+        assert!(facade::disassemble(&code, RefFun(1)).unwrap().is_empty());
+        assert!(matches!(
+            facade::function_header(&code, RefFun(2)),
+            Err(Error::InvalidIndex { .. })
+        ));
+        assert_eq!(facade::type_name(&code, RefType(3)).unwrap(), "Main");
+
+        let source = facade::decompile_function(&code, RefFun(0), &opts).unwrap();
+        assert!(source.contains("log();"), "{source}");
+        let (source, map) = facade::decompile_function_mapped(&code, RefFun(0), &opts).unwrap();
+        let lines: Vec<_> = source.lines().collect();
+        assert!(map.iter().any(|m| lines[m.line].contains("log();")));
+        assert!(facade::decompile_function(&code, RefFun(1), &opts).is_err());
+
+        let class = facade::decompile_class(&code, RefType(3), &opts).unwrap();
+        assert!(
+            class.starts_with("class Main {\n  var x: Int;\n"),
+            "{class}"
+        );
+        let indented = facade::decompile_class_indented(&code, RefType(3), &opts, 4).unwrap();
+        assert!(
+            indented.starts_with("class Main {\n    var x: Int;\n"),
+            "{indented}"
+        );
+        assert!(facade::decompile_class(&code, RefType(1), &opts).is_err());
+
+        let tokens = facade::decompile_class_tokens(&code, RefType(3), &opts).unwrap();
+        assert_eq!(tokens::text(&tokens), class);
+    }
+
+    #[test]
+    fn typedef_names() {
+        let mut code = code();
+        code.strings.push(Str::from("y"));
+        let field = |name, t| ObjField {
+            name: RefString(name),
+            t: RefType(t),
+        };
+        code.types.push(Type::Virtual {
+            fields: vec![field(3, 1), field(6, 1)],
+        });
+        let Type::Obj(obj) = &mut code.types[3] else {
+            unreachable!()
+        };
+        obj.own_fields = vec![field(3, 4)];
+        obj.fields = obj.own_fields.clone();
+        let class =
+            facade::decompile_class(&code, RefType(3), &DecompilerOptions::default()).unwrap();
+        assert!(
+            class.starts_with("class Main {\n  var x: Point;\n"),
+            "{class}"
+        );
+    }
+
+    #[test]
+    fn signatures() {
+        let mut code = code();
+        assert_eq!(
+            facade::signature(&code, RefFun(0)).unwrap(),
+            "static function main()"
+        );
+        code.types[2] = Type::Fun(TypeFun {
+            args: vec![RefType(3), RefType(1)],
+            ret: RefType(1),
+        });
+        code.functions[0].parent = Some(RefType(3));
+        code.functions[0].regs = vec![RefType(3), RefType(1)];
+        assert_eq!(
+            facade::signature(&code, RefFun(0)).unwrap(),
+            "function main(arg0: Int): Int"
+        );
+        assert_eq!(
+            facade::signature(&code, RefFun(1)).unwrap(),
+            "extern function log(arg0: Main, arg1: Int): Int"
+        );
+        assert!(facade::signature(&code, RefFun(2)).is_err());
+    }
+
+    #[test]
+    fn find_by_name() {
+        let mut code = code();
+        assert_eq!(facade::find_class(&code, "Main"), Some(RefType(3)));
+        assert_eq!(facade::find_class(&code, "Nope"), None);
+        assert_eq!(facade::find_method(&code, "Main.main"), None);
+        code.functions[0].parent = Some(RefType(3));
+        assert_eq!(facade::find_method(&code, "Main.main"), Some(RefFun(0)));
+        assert_eq!(facade::find_method(&code, "Main.other"), None);
+        assert_eq!(facade::find_method(&code, "main"), None);
+    }
+
+    #[test]
+    fn patch() {
+        let mut code = code();
+        assert_eq!(
+            facade::opcode(&code, &code.functions[0], 1).unwrap(),
+            "Ret         reg0"
+        );
+        assert!(matches!(
+            facade::opcode(&code, &code.functions[0], 2),
+            Err(Error::InvalidIndex { .. })
+        ));
+        let old = facade::patch_opcode(&mut code, RefFun(0), 0, "Null reg0 = null").unwrap();
+        assert!(matches!(old, Opcode::Call0 { .. }));
+        assert_eq!(
+            facade::disassemble(&code, RefFun(0)).unwrap(),
+            ["Null        reg0 = null", "Ret         reg0"]
+        );
+        assert!(facade::patch_opcode(&mut code, RefFun(0), 0, "Nope reg0").is_err());
+        assert!(facade::patch_opcode(&mut code, RefFun(0), 2, "Ret reg0").is_err());
+        assert!(facade::patch_opcode(&mut code, RefFun(1), 0, "Ret reg0").is_err());
+        assert!(matches!(
+            code.functions[0].ops[0],
+            Opcode::Null { dst: Reg(0) }
+        ));
+    }
+}
@@ -1,12 +1,17 @@
 use std::fmt;
 use std::fmt::{Display, Formatter};
+use std::sync::Arc;
 
 use hlbc::fmt::{BytecodeFmt, EnhancedFmt};
-use hlbc::types::{Function, RefField, Type};
+use hlbc::types::{FunPtr, Function, RefEnumConstruct, RefField, RefFun, RefType, Type};
 use hlbc::Str;
 use hlbc::{Bytecode, Resolve};
 
-use crate::ast::{Class, Constant, ConstructorCall, Expr, Method, Operation, Statement};
+use crate::ast::{
+    CastKind, Class, Constant, ConstructorCall, Enum, Expr, Method, Module, Operation, Param,
+    Statement, Typedef,
+};
+use crate::{params, TypedefNames};
 
 const INDENT: &str = "                                                                ";
 
@@ -14,6 +19,12 @@ const INDENT: &str = "
 pub struct FormatOptions {
     indent: &'static str,
     inc_indent: usize,
+    /// Label enum constructor arguments with their parameter name
+    pub(crate) enum_param_names: bool,
+    /// Annotate variable declarations with their type
+    var_types: bool,
+    /// Names of the anonymous structures
+    typedefs: Option<Arc<TypedefNames>>,
 }
 
 impl FormatOptions {
@@ -21,13 +32,43 @@ impl FormatOptions {
         Self {
             indent: "",
             inc_indent,
+            enum_param_names: false,
+            var_types: true,
+            typedefs: None,
         }
     }
 
+    /// Render enum constructions as `Point(x: 1, y: 2)` instead of `Point(1, 2)`.
+    pub fn with_enum_param_names(self, enum_param_names: bool) -> Self {
+        Self {
+            enum_param_names,
+            ..self
+        }
+    }
+
+    /// Render variable declarations as `var x: Int = 1` instead of `var x = 1`.
+    /// The type is the one of the register holding the variable. Enabled by default.
+    pub fn with_var_types(self, var_types: bool) -> Self {
+        Self { var_types, ..self }
+    }
+
+    /// Render anonymous structure types with their typedef name instead of `Dynamic`.
+    pub fn with_typedefs(self, typedefs: Arc<TypedefNames>) -> Self {
+        Self {
+            typedefs: Some(typedefs),
+            ..self
+        }
+    }
+
+    /// Names of the anonymous structures, if set with [Self::with_typedefs]
+    pub fn typedefs(&self) -> Option<&Arc<TypedefNames>> {
+        self.typedefs.as_ref()
+    }
+
     pub fn inc_nesting(&self) -> Self {
         FormatOptions {
             indent: &INDENT[..self.indent.len() + self.inc_indent],
-            ..*self
+            ..self.clone()
         }
     }
 }
@@ -38,32 +79,225 @@ impl Display for FormatOptions {
     }
 }
 
-fn to_haxe_type<'a>(ty: &Type, ctx: &'a Bytecode) -> impl Display + 'a {
-    use crate::Type::*;
+pub(crate) fn to_haxe_type<'a>(
+    ty: &Type,
+    ctx: &'a Bytecode,
+    opts: &FormatOptions,
+) -> impl Display + 'a {
     match ty {
-        Void => Str::from_static("Void"),
-        I32 => Str::from_static("Int"),
-        F64 => Str::from_static("Float"),
-        Bool => Str::from_static("Bool"),
-        Bytes => Str::from_static("hl.Bytes"),
-        Dyn => Str::from_static("Dynamic"),
-        Fun(_) => Str::from_static("Function"),
-        Obj(obj) => ctx.get(obj.name),
-        _ => Str::from_static("other"),
+        Type::Virtual { .. } => opts
+            .typedefs
+            .as_ref()
+            .and_then(|names| names.name(ctx, ty))
+            .unwrap_or(Str::from_static("Dynamic")),
+        Type::Void => Str::from_static("Void"),
+        Type::UI8 => Str::from_static("hl.UI8"),
+        Type::UI16 => Str::from_static("hl.UI16"),
+        Type::I32 => Str::from_static("Int"),
+        Type::I64 => Str::from_static("hl.I64"),
+        Type::F32 => Str::from_static("Single"),
+        Type::F64 => Str::from_static("Float"),
+        Type::Bool => Str::from_static("Bool"),
+        Type::Bytes => Str::from_static("hl.Bytes"),
+        Type::Dyn | Type::DynObj => Str::from_static("Dynamic"),
+        Type::Fun(_) | Type::Method(_) => Str::from_static("Function"),
+        Type::Obj(obj) | Type::Struct(obj) => ctx.get(obj.name),
+        Type::Array => Str::from_static("hl.NativeArray<Dynamic>"),
+        Type::Type => Str::from_static("hl.Type"),
+        Type::Ref(inner) => Str::from(format!("hl.Ref<{}>", to_haxe_type(&ctx[*inner], ctx, opts))),
+        Type::Null(inner) => Str::from(format!("Null<{}>", to_haxe_type(&ctx[*inner], ctx, opts))),
+        Type::Packed(inner) => Str::from(to_haxe_type(&ctx[*inner], ctx, opts).to_string()),
+        Type::Abstract { name } => Str::from(format!("hl.Abstract<\"{}\">", ctx.get(*name))),
+        Type::Enum { name, .. } if !name.is_null() => ctx.get(*name),
+        Type::Enum { .. } => Str::from_static("Dynamic"),
+    }
+}
+
+/// `name: Type` of each parameter
+fn display_params<'a>(
+    params: Vec<Param>,
+    ctx: &'a Bytecode,
+    opts: &'a FormatOptions,
+) -> impl Display + 'a {
+    fmtools::join(
+        ", ",
+        params.into_iter().map(move |p| {
+            fmtools::fmt! { move
+                {p.name}": "{to_haxe_type(&ctx[p.ty], ctx, opts)}
+            }
+        }),
+    )
+}
+
+/// Statements on their own line, indented with `opts`. [Statement::Source] isn't rendered.
+fn display_block<'a>(
+    stmts: &'a [Statement],
+    opts: &'a FormatOptions,
+    code: &'a Bytecode,
+    f: &'a Function,
+) -> impl Display + 'a {
+    fmtools::fmt! { move
+        for stmt in stmts.iter().filter(|s| !matches!(s, Statement::Source(_))) {
+            {opts}{stmt.display(opts, code, f)}"\n"
+        }
+    }
+}
+
+/// Type annotation of a variable declaration, from the type of its register
+pub(crate) fn declared_type<'a>(
+    opts: &FormatOptions,
+    code: &'a Bytecode,
+    f: &Function,
+    declaration: bool,
+    variable: &Expr,
+) -> Option<impl Display + 'a> {
+    match variable {
+        Expr::Variable(reg, _) if declaration && opts.var_types => {
+            let ty = &code[*f.regs.get(reg.0 as usize)?];
+            (!matches!(ty, Type::Void)).then(|| to_haxe_type(ty, code, opts))
+        }
+        _ => None,
+    }
+}
+
+/// Name of an enum variant, falling back to `Enum.Construct<index>` for unnamed variants.
+pub(crate) fn enum_construct_name(code: &Bytecode, ty: RefType, constr: RefEnumConstruct) -> Str {
+    match (&code[ty], ty.enum_construct(constr, code)) {
+        (_, Some(c)) if !c.name.is_null() => c.name(code),
+        (Type::Enum { name, .. }, _) => Str::from(format!("{}.Construct{}", code[*name], constr.0)),
+        _ => Str::from(constr.to_string()),
+    }
+}
+
+/// Name of a function, natives of the standard library are shown as the Haxe function they implement
+pub(crate) fn fun_name(code: &Bytecode, fun: RefFun) -> Str {
+    match code.get(fun) {
+        FunPtr::Native(n) => n
+            .known(code)
+            .map_or_else(|| n.name(code), |k| Str::from_static(k.haxe)),
+        FunPtr::Fun(_) => fun.name(code),
+    }
+}
+
+/// Name of the `i`-th parameter of an enum variant.
+///
+/// Hashlink bytecode only stores the parameter types, so we can only give a positional name.
+pub(crate) fn enum_param_name(i: usize) -> Str {
+    Str::from(format!("_{i}"))
+}
+
+impl Module {
+    /// The source of the whole package : declaration, imports, then typedefs, enums and classes
+    pub fn display<'a>(&'a self, ctx: &'a Bytecode, opts: &'a FormatOptions) -> impl Display + 'a {
+        fmtools::fmt! { move
+            {self.display_header()}
+            for t in &self.typedefs {
+                {t.display(ctx, opts)}"\n\n"
+            }
+            for e in &self.enums {
+                {e.display(ctx, opts)}"\n\n"
+            }
+            for c in &self.classes {
+                {c.display(ctx, opts)}"\n\n"
+            }
+        }
+    }
+
+    /// The package declaration and the imports, followed by an empty line
+    pub fn display_header(&self) -> impl Display + '_ {
+        fmtools::fmt! { move
+            "package" if !self.package.is_empty() { " "{self.package} } ";\n\n"
+            for import in &self.imports {
+                "import "{import}";\n"
+            }
+            if !self.imports.is_empty() { "\n" }
+        }
+    }
+}
+
+impl Enum {
+    pub fn display<'a>(&'a self, ctx: &'a Bytecode, opts: &'a FormatOptions) -> impl Display + 'a {
+        let new_opts = opts.inc_nesting();
+        fmtools::fmt! { move
+            {opts}"enum "{self.name}" {\n"
+            for v in &self.variants {
+                {new_opts}{v.name}
+                if !v.params.is_empty() {
+                    "("{fmtools::join(", ", v.params.iter().enumerate().map(move |(i, p)| fmtools::fmt! { move
+                        {enum_param_name(i)}": "{to_haxe_type(&ctx[*p], ctx, opts)}
+                    }))}")"
+                }
+                ";\n"
+            }
+            {opts}"}"
+        }
+    }
+}
+
+impl Typedef {
+    pub fn display<'a>(&'a self, ctx: &'a Bytecode, opts: &'a FormatOptions) -> impl Display + 'a {
+        fmtools::fmt! { move
+            {opts}"typedef "{self.name}" = { "
+            {fmtools::join(", ", self.fields.iter().map(move |(name, ty)| fmtools::fmt! { move
+                {name}": "{to_haxe_type(&ctx[*ty], ctx, opts)}
+            }))}
+            " };"
+        }
     }
 }
 
 impl Class {
     pub fn display<'a>(&'a self, ctx: &'a Bytecode, opts: &'a FormatOptions) -> impl Display + 'a {
+        let new_opts = opts.inc_nesting();
+        fmtools::fmt! { move
+            {self.display_header(ctx, opts)}
+            for m in &self.methods {
+                "\n"
+                {m.display(ctx, &new_opts)}
+            }
+            {opts}"}"
+        }
+    }
+
+    /// The class declaration and its fields, without the methods and the closing brace.
+    pub fn display_header<'a>(
+        &'a self,
+        ctx: &'a Bytecode,
+        opts: &'a FormatOptions,
+    ) -> impl Display + 'a {
+        self.header(ctx, opts, true)
+    }
+
+    /// Extern variables can't have an initial value
+    fn header<'a>(
+        &'a self,
+        ctx: &'a Bytecode,
+        opts: &'a FormatOptions,
+        values: bool,
+    ) -> impl Display + 'a {
         let new_opts = opts.inc_nesting();
         fmtools::fmt! { move
             {opts}"class "{self.name} if let Some(parent) = self.parent.as_ref() { " extends "{parent} } " {\n"
             for f in &self.fields {
-                {new_opts} if f.static_ { "static " } "var "{f.name}": "{to_haxe_type(&ctx[f.ty], ctx)}";\n"
+                {new_opts} if f.static_ { "static " } "var "{f.name}": "{to_haxe_type(&ctx[f.ty], ctx, opts)}
+                if let Some(value) = f.value.as_ref().filter(|_| values) { " = "{value.display(ctx)} }
+                ";\n"
             }
+        }
+    }
+
+    /// The class as an `extern` declaration, with the method signatures only.
+    /// Meant for top level declarations, the indentation is ignored.
+    pub fn display_extern<'a>(
+        &'a self,
+        ctx: &'a Bytecode,
+        opts: &'a FormatOptions,
+    ) -> impl Display + 'a {
+        let new_opts = opts.inc_nesting();
+        fmtools::fmt! { move
+            "extern "{self.header(ctx, opts, false)}
             for m in &self.methods {
-                "\n"
-                {m.display(ctx, &new_opts)}
+                {new_opts}{m.display_signature(ctx, opts)}";\n"
             }
             {opts}"}"
         }
@@ -75,29 +309,39 @@ impl Method {
         let new_opts = opts.inc_nesting();
         let fun = self.fun.as_fn(ctx).unwrap();
         fmtools::fmt! { move
-            {opts} if self.static_ { "static " } if self.dynamic { "dynamic " }
-            "function "{fun.name(ctx)}"("
-            {fmtools::join(", ", fun.args(ctx).iter().enumerate().skip(if self.static_ { 0 } else { 1 })
-                .map(move |(i, arg)| fmtools::fmt! {move
-                    {fun.arg_name(ctx, i).unwrap_or(Str::from("_"))}": "{to_haxe_type(&ctx[*arg], ctx)}
-                }))}
-            ")" if !fun.ty(ctx).ret.is_void() { ": "{to_haxe_type(fun.ret(ctx), ctx)} } " {"
+            {opts}{self.display_signature(ctx, opts)}" {"
 
             if self.statements.is_empty() {
                 "}"
             } else {
                 "\n"
-                for stmt in &self.statements {
-                    {new_opts}{stmt.display(&new_opts, ctx, fun)}"\n"
-                }
+                {display_block(&self.statements, &new_opts, ctx, fun)}
                 {opts}"}"
             }
             "\n"
         }
     }
+
+    /// The method declaration without the body : `static function name(a: Int): Bool`
+    pub fn display_signature<'a>(
+        &'a self,
+        ctx: &'a Bytecode,
+        opts: &'a FormatOptions,
+    ) -> impl Display + 'a {
+        let fun = self.fun.as_fn(ctx).unwrap();
+        fmtools::fmt! { move
+            if self.override_ { "override " } if self.static_ { "static " } if self.dynamic { "dynamic " }
+            "function "{fun.name(ctx)}"("{display_params(self.params(ctx), ctx, opts)}")"
+            if !fun.ty(ctx).ret.is_void() { ": "{to_haxe_type(fun.ret(ctx), ctx, opts)} }
+        }
+    }
 }
 
 impl Constant {
+    pub fn display<'a>(&'a self, code: &'a Bytecode) -> impl Display + 'a {
+        fmtools::fmt!(move {|f| self.fmt(f, code)?;})
+    }
+
     fn fmt(&self, f: &mut Formatter, code: &Bytecode) -> fmt::Result {
         use Constant::*;
         match *self {
@@ -107,6 +351,7 @@ impl Constant {
             String(c) => {
                 write!(f, "\"{}\"", code[c])
             }
+            Str(ref s) => write!(f, "\"{}\"", s.escape_debug()),
             Bool(c) => Display::fmt(&c, f),
             Null => f.write_str("null"),
             This => f.write_str("this"),
@@ -122,38 +367,63 @@ impl Operation {
         f: &'a Function,
     ) -> impl Display + 'a {
         use Operation::*;
-        macro_rules! disp {
+        // Operands, in parentheses when needed
+        macro_rules! lhs {
             ($e:ident) => {
-                $e.display(indent, code, f)
+                operand(self, $e, false, indent, code, f)
+            };
+        }
+        macro_rules! rhs {
+            ($e:ident) => {
+                operand(self, $e, true, indent, code, f)
             };
         }
         fmtools::fmt! { move
             match self {
-                Add(e1, e2) => {{disp!(e1)}" + "{disp!(e2)}}
-                Sub(e1, e2) => {{disp!(e1)}" - "{disp!(e2)}}
-                Mul(e1, e2) => {{disp!(e1)}" * "{disp!(e2)}}
-                Div(e1, e2) => {{disp!(e1)}" / "{disp!(e2)}}
-                Mod(e1, e2) => {{disp!(e1)}" % "{disp!(e2)}}
-                Shl(e1, e2) => {{disp!(e1)}" << "{disp!(e2)}}
-                Shr(e1, e2) => {{disp!(e1)}" >> "{disp!(e2)}}
-                And(e1, e2) => {{disp!(e1)}" && "{disp!(e2)}}
-                Or(e1, e2) => {{disp!(e1)}" || "{disp!(e2)}}
-                Xor(e1, e2) => {{disp!(e1)}" ^ "{disp!(e2)}}
-                Neg(expr) => {"-"{disp!(expr)}}
-                Not(expr) => {"!"{disp!(expr)}}
-                Incr(expr) => {{disp!(expr)}"++"}
-                Decr(expr) => {{disp!(expr)}"--"}
-                Eq(e1, e2) => {{disp!(e1)}" == "{disp!(e2)}}
-                NotEq(e1, e2) => {{disp!(e1)}" != "{disp!(e2)}}
-                Gt(e1, e2) => {{disp!(e1)}" > "{disp!(e2)}}
-                Gte(e1, e2) => {{disp!(e1)}" >= "{disp!(e2)}}
-                Lt(e1, e2) => {{disp!(e1)}" < "{disp!(e2)}}
-                Lte(e1, e2) => {{disp!(e1)}" <= "{disp!(e2)}}
+                Add(e1, e2) => {{lhs!(e1)}" + "{rhs!(e2)}}
+                Sub(e1, e2) => {{lhs!(e1)}" - "{rhs!(e2)}}
+                Mul(e1, e2) => {{lhs!(e1)}" * "{rhs!(e2)}}
+                Div(e1, e2) => {{lhs!(e1)}" / "{rhs!(e2)}}
+                Mod(e1, e2) => {{lhs!(e1)}" % "{rhs!(e2)}}
+                Shl(e1, e2) => {{lhs!(e1)}" << "{rhs!(e2)}}
+                Shr(e1, e2) => {{lhs!(e1)}" >> "{rhs!(e2)}}
+                And(e1, e2) => {{lhs!(e1)}" && "{rhs!(e2)}}
+                Or(e1, e2) => {{lhs!(e1)}" || "{rhs!(e2)}}
+                Xor(e1, e2) => {{lhs!(e1)}" ^ "{rhs!(e2)}}
+                Neg(expr) => {"-"{rhs!(expr)}}
+                Not(expr) => {"!"{rhs!(expr)}}
+                Incr(expr) => {{rhs!(expr)}"++"}
+                Decr(expr) => {{rhs!(expr)}"--"}
+                Eq(e1, e2) => {{lhs!(e1)}" == "{rhs!(e2)}}
+                NotEq(e1, e2) => {{lhs!(e1)}" != "{rhs!(e2)}}
+                Gt(e1, e2) => {{lhs!(e1)}" > "{rhs!(e2)}}
+                Gte(e1, e2) => {{lhs!(e1)}" >= "{rhs!(e2)}}
+                Lt(e1, e2) => {{lhs!(e1)}" < "{rhs!(e2)}}
+                Lte(e1, e2) => {{lhs!(e1)}" <= "{rhs!(e2)}}
+                Interval(e1, e2) => {{lhs!(e1)}"..."{rhs!(e2)}}
+                NullCoalesce(e1, e2) => {{lhs!(e1)}" ?? "{rhs!(e2)}}
             }
         }
     }
 }
 
+/// An operand of `op`, in parentheses if it binds less tightly
+fn operand<'a>(
+    op: &'a Operation,
+    e: &'a Expr,
+    right: bool,
+    indent: &'a FormatOptions,
+    code: &'a Bytecode,
+    f: &'a Function,
+) -> impl Display + 'a {
+    let parens = op.needs_parens(e, right);
+    fmtools::fmt! { move
+        if parens { "(" }
+        {e.display(indent, code, f)}
+        if parens { ")" }
+    }
+}
+
 impl Expr {
     pub fn display<'a>(
         &'a self,
@@ -184,46 +454,78 @@ impl Expr {
                 Expr::Array(array, index) => {
                     {disp!(array)}"["{disp!(index)}"]"
                 }
+                Expr::ArrayLiteral(values) => {
+                    "["{fmtools::join(", ", values.iter().map(|e| disp!(e)))}"]"
+                }
                 Expr::Call(call) => {
                     {disp!(call.fun)}"("{fmtools::join(", ", call.args.iter().map(|e| disp!(e)))}")"
                 }
+                Expr::Cast(e, ty, kind) => {
+                    match kind {
+                        CastKind::Checked => {
+                            "cast("{disp!(e)}", "{to_haxe_type(&code[*ty], code, indent)}")"
+                        }
+                        CastKind::Unchecked => {
+                            "cast("{disp!(e)}")"
+                        }
+                        CastKind::ToInt => {
+                            "Std.int("{disp!(e)}")"
+                        }
+                        CastKind::Implicit => {
+                            "("{disp!(e)}" : "{to_haxe_type(&code[*ty], code, indent)}")"
+                        }
+                    }
+                }
                 Expr::Constant(c) => {|f| c.fmt(f, code)?;},
                 Expr::Constructor(ConstructorCall { ty, args }) => {
                     "new "{ty.display::<EnhancedFmt>(code)}"("{fmtools::join(", ", args.iter().map(|e| disp!(e)))}")"
                 }
-                Expr::Closure(f, stmts) => {
-                    let fun = f.as_fn(code).unwrap();
-                    "("{fmtools::join(", ", fun.ty(code).args.iter().enumerate().map(move |(i, arg)|
-                        fmtools::fmt! { move
-                            {fun.arg_name(code, i).unwrap_or(Str::from("_"))}": "{to_haxe_type(&code[*arg], code)}
-                        }
-                    ))}") -> {\n"
-                    let indent2 = indent.inc_nesting();
-                    for stmt in stmts {
-                        {indent2}{stmt.display(&indent2, code, fun)}"\n"
+                Expr::Closure(closure) => {
+                    let fun = closure.fun.as_fn(code).unwrap();
+                    // The environment isn't an argument in the source
+                    "("{display_params(params(code, fun, usize::from(closure.has_env)), code, indent)}") -> "
+                    if let Some(body) = closure.body_expr() {
+                        {body.display(indent, code, fun)}
+                    } else {
+                        "{\n"
+                        let indent2 = indent.inc_nesting();
+                        {display_block(&closure.stmts, &indent2, code, fun)}
+                        {indent}"}"
                     }
-                    {indent}"}"
                 }
                 Expr::EnumConstr(ty, constr, args) => {
-                    {constr.display::<EnhancedFmt>(code, &code[*ty])}"("{fmtools::join(", ", args.iter().map(|e| disp!(e)))}")"
+                    {enum_construct_name(code, *ty, *constr)}
+                    if !args.is_empty() {
+                        "("{fmtools::join(", ", args.iter().enumerate().map(|(i, e)| fmtools::fmt! { move
+                            if indent.enum_param_names { {enum_param_name(i)}": " }
+                            {disp!(e)}
+                        }))}")"
+                    }
                 }
                 Expr::Field(receiver, name) => {
                     {disp!(receiver)}"."{name}
                 }
-                Expr::FunRef(fun) => {{fun.name(code)}},
+                Expr::FunRef(fun) => {{fun_name(code, *fun)}},
                 Expr::IfElse { cond, if_, else_ } => {
                     "if ("{disp!(cond)}") {\n"
                     let indent2 = indent.inc_nesting();
-                    for stmt in if_ {
-                        {indent2}{stmt.display(&indent2, code, f)}"\n"
-                    }
+                    {display_block(if_, &indent2, code, f)}
                     {indent}"} else {\n"
-                    for stmt in else_ {
-                        {indent2}{stmt.display(&indent2, code, f)}"\n"
-                    }
+                    {display_block(else_, &indent2, code, f)}
                     {indent}"}"
                 }
+                Expr::MapLiteral(entries) => {
+                    "["{fmtools::join(", ", entries.iter().map(|(k, v)| {
+                        fmtools::fmt! { move
+                            {disp!(k)}" => "{disp!(v)}
+                        }
+                    }))}"]"
+                }
                 Expr::Op(op) => {{disp!(op)}},
+                Expr::SafeField(receiver, name) => {
+                    {disp!(receiver)}"?."{name}
+                }
+                Expr::TypePath(path) => {{path}},
                 Expr::Unknown(msg) => {
                      "["{msg}"]"
                 }
@@ -258,7 +560,12 @@ impl Statement {
                     variable,
                     assign,
                 } => {
-                    if *declaration { "var " } else { "" }{disp!(variable)}" = "{disp!(assign)}";"
+                    if *declaration { "var " } else { "" }{disp!(variable)}
+                    if let Some(ty) = declared_type(indent, code, f, *declaration, variable) { ": "{ty} }
+                    " = "{disp!(assign)}";"
+                }
+                Statement::CompoundAssign { variable, op, value } => {
+                    {disp!(variable)}" "{op}" "{disp!(value)}";"
                 }
                 Statement::ExprStatement(expr) => {
                     {disp!(expr)}";"
@@ -269,15 +576,11 @@ impl Statement {
                 Statement::IfElse { cond, if_, else_ } => {
                     "if ("{disp!(cond)}") {\n"
                     let indent2 = indent.inc_nesting();
-                    for stmt in if_ {
-                        {indent2}{stmt.display(&indent2, code, f)}"\n"
-                    }
+                    {display_block(if_, &indent2, code, f)}
                     {indent}"}"
                     if !else_.is_empty() {
                         " else {\n"
-                        for stmt in else_ {
-                            {indent2}{stmt.display(&indent2, code, f)}"\n"
-                        }
+                        {display_block(else_, &indent2, code, f)}
                         {indent}"}"
                     }
                 }
@@ -287,24 +590,24 @@ impl Statement {
                     let indent3 = indent2.inc_nesting();
                     if !default.is_empty() {
                         {indent2}"default:\n"
-                        for stmt in default {
-                            {indent3}{stmt.display(&indent3, code, f)}"\n"
-                        }
+                        {display_block(default, &indent3, code, f)}
                     }
                     for (pattern, stmts) in cases {
                         {indent2}"case "{disp!(pattern)}":\n"
-                        for stmt in stmts {
-                            {indent3}{stmt.display(&indent3, code, f)}"\n"
-                        }
+                        {display_block(stmts, &indent3, code, f)}
                     }
                     {indent}"}"
                 }
                 Statement::While { cond, stmts } => {
                     "while ("{disp!(cond)}") {\n"
                     let indent2 = indent.inc_nesting();
-                    for stmt in stmts {
-                        {indent2}{stmt.display(&indent2, code, f)}"\n"
-                    }
+                    {display_block(stmts, &indent2, code, f)}
+                    {indent}"}"
+                }
+                Statement::For { variable, iterable, stmts } => {
+                    "for ("{disp!(variable)}" in "{disp!(iterable)}") {\n"
+                    let indent2 = indent.inc_nesting();
+                    {display_block(stmts, &indent2, code, f)}
                     {indent}"}"
                 }
                 Statement::Break => {
@@ -319,19 +622,25 @@ impl Statement {
                 Statement::Try { stmts } => {
                     "try {\n"
                     let indent2 = indent.inc_nesting();
-                    for stmt in stmts {
-                        {indent2}{stmt.display(&indent2, code, f)}"\n"
-                    }
+                    {display_block(stmts, &indent2, code, f)}
                     {indent}"}"
                 }
                 Statement::Catch { stmts } => {
                     "catch () {\n"
                     let indent2 = indent.inc_nesting();
-                    for stmt in stmts {
-                        {indent2}{stmt.display(&indent2, code, f)}"\n"
-                    }
+                    {display_block(stmts, &indent2, code, f)}
                     {indent}"}"
                 }
+                Statement::Label(pos) => {
+                    "L"{pos}":"
+                }
+                Statement::Goto(pos) => {
+                    "goto L"{pos}";"
+                }
+                Statement::Opcode(pos) => {
+                    "// "{pos:>3}": "{f.ops[*pos].display(code, f, *pos as i32, 11)}
+                }
+                Statement::Source(_) => {}
                 Statement::Comment(comment) => {
                     "// "{comment}
                 }
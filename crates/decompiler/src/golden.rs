@@ -0,0 +1,122 @@
+//! The sources in `data` are compiled with the Haxe compiler and the decompiled main class of each one is compared with
+//! the expected output checked in `data/golden`.
+//!
+//! When `haxe` isn't installed, the `.hl` files already built in `data` are used. After a change to the decompiler, run
+//! with `HLBC_BLESS=1` to write the current output as the expected one and review the diff like any other change.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs};
+
+use crate::{facade, DecompilerOptions};
+
+const DATA: &str = "../../data";
+
+/// The test programs, sorted by name
+fn sources() -> Vec<PathBuf> {
+    let mut sources: Vec<_> = fs::read_dir(DATA)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "hx"))
+        .collect();
+    sources.sort();
+    sources
+}
+
+/// Compile a program to `out`, `None` if the Haxe compiler isn't available. The error is the output of the compiler.
+fn compile(source: &Path, out: &Path) -> Option<Result<(), String>> {
+    let output = Command::new("haxe")
+        .current_dir(DATA)
+        .arg("-hl")
+        .arg(out)
+        .arg("-main")
+        .arg(source.file_name()?)
+        .output()
+        .ok()?;
+    Some(if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_owned())
+    })
+}
+
+/// Source of the class named after the program
+fn decompile(hl: &Path, name: &str) -> String {
+    let code = facade::open(hl).unwrap();
+    let class = facade::find_class(&code, name)
+        .unwrap_or_else(|| panic!("no class {name} in {}", hl.display()));
+    facade::decompile_class(&code, class, &DecompilerOptions::default()).unwrap()
+}
+
+/// First line differing between the expected and the actual output
+fn first_difference(expected: &str, actual: &str) -> String {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    for line in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(e), Some(a)) if e == a => {}
+            (None, None) => break,
+            (e, a) => {
+                return format!(
+                    "line {line}\n  expected : {}\n  actual   : {}",
+                    e.unwrap_or("<end>"),
+                    a.unwrap_or("<end>")
+                )
+            }
+        }
+    }
+    "trailing whitespace".to_owned()
+}
+
+#[test]
+fn golden() {
+    let bless = env::var_os("HLBC_BLESS").is_some();
+    let golden = Path::new(DATA).join("golden");
+    // Built outside of data, other tests are reading the .hl files there
+    let out = env::temp_dir().join(format!("hlbc-golden-{}", std::process::id()));
+    fs::create_dir_all(&out).unwrap();
+
+    let mut failures = Vec::new();
+    for source in sources() {
+        let name = source.file_stem().unwrap().to_str().unwrap();
+        let mut hl = out.join(name).with_extension("hl");
+        match compile(&source, &hl) {
+            Some(Ok(())) => {}
+            Some(Err(e)) => {
+                failures.push(format!("{name} : haxe failed\n{e}"));
+                continue;
+            }
+            None => {
+                hl = source.with_extension("hl");
+                if !hl.exists() {
+                    eprintln!("skipping {name} : haxe isn't installed and {name}.hl isn't built");
+                    continue;
+                }
+            }
+        }
+        let actual = decompile(&hl, name);
+        let expected_file = golden.join(name).with_extension("hx");
+        if bless {
+            fs::create_dir_all(&golden).unwrap();
+            fs::write(&expected_file, &actual).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&expected_file) {
+            // Checked out with CRLF on Windows
+            Ok(expected) if expected.replace("\r\n", "\n") == actual => {}
+            Ok(expected) => failures.push(format!(
+                "{name} : {}",
+                first_difference(&expected.replace("\r\n", "\n"), &actual)
+            )),
+            Err(_) => {
+                eprintln!("no expected output for {name}, run with HLBC_BLESS=1 to create it")
+            }
+        }
+    }
+    let _ = fs::remove_dir_all(&out);
+    assert!(
+        failures.is_empty(),
+        "decompiled output changed, run with HLBC_BLESS=1 if it is expected :\n{}",
+        failures.join("\n")
+    );
+}
@@ -3,25 +3,236 @@
 //!
 //! The decompiler takes bytecode elements as input and outputs [ast] structures that can be displayed.
 
-use std::collections::{HashMap, HashSet};
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
+use std::sync::Arc;
 
 use ast::*;
+use fmt::enum_param_name;
+use hlbc::analysis::cfg::{cfg, Cfg};
+use hlbc::analysis::closures::{context_name, is_anonymous};
+use hlbc::analysis::deadcode::{is_leftover, unreachable_ops};
+use hlbc::analysis::init::InitValue;
+use hlbc::analysis::liveness::{is_read_after, reads_of};
+use hlbc::analysis::returns::ConstantReturn;
+use hlbc::analysis::ssa::{ssa, Def, Ssa};
 use hlbc::fmt::EnhancedFmt;
 use hlbc::opcodes::Opcode;
-use hlbc::types::{Function, RefField, RefFun, RefString, Reg, Type, TypeObj};
+use hlbc::types::{
+    ConstantValue, FunPtr, Function, RefField, RefFun, RefGlobal, RefType, Reg, Type, TypeObj,
+};
 use hlbc::{Bytecode, Resolve, Str};
 use scopes::*;
 
+pub use batch::Batch;
+pub use cache::DecompileCache;
+pub use diagnostic::{DecompileError, Diagnostic};
+pub use module::{
+    decompile_modules, decompile_modules_with, extern_modules, source_file_path,
+    write_extern_stubs, write_source_tree, write_source_tree_matching,
+};
+#[cfg(feature = "rayon")]
+pub use parallel::{decompile_all, decompile_all_classes};
+pub use strings::{DecodeArg, Decoder, StringDecoders};
+pub use typedefs::TypedefNames;
+
 #[cfg(feature = "alt")]
 mod alt;
 /// A simple representation for the Haxe source code generated by the decompiler
 pub mod ast;
+/// Decompiling a lot of functions without blocking
+mod batch;
+/// Keeping decompiled functions around for interactive tools
+mod cache;
+/// Problems found while decompiling
+mod diagnostic;
+/// Markdown API reference generation
+pub mod doc;
+/// High level operations shared by the cli, the gui, scripts and bindings : open, query, decompile and save
+pub mod facade;
 /// Functions to render the [ast] to a string
 pub mod fmt;
+/// Comparing the decompiled test programs with their expected output
+#[cfg(test)]
+mod golden;
+/// Grouping declarations by package
+mod module;
+/// Decompiling on every core
+#[cfg(feature = "rayon")]
+mod parallel;
 /// AST post-processing
 mod post;
 /// Scope handling structures
 mod scopes;
+/// Decoding the strings of obfuscated programs
+mod strings;
+/// Control flow graph structuring
+mod structured;
+/// Rendering the [ast] as tokens tagged with their origin in the bytecode
+pub mod tokens;
+/// Naming the anonymous structures
+mod typedefs;
+
+/// Options controlling the decompiler output
+#[derive(Debug, Clone)]
+pub struct DecompilerOptions {
+    /// Emit the opcodes that produced a statement as comments above it.
+    /// Useful to check the decompiler output against the bytecode.
+    pub interleave_opcodes: bool,
+    /// Transformations applied to the raw AST, in order.
+    /// With no passes, the output maps almost directly to the opcodes.
+    pub passes: Vec<Pass>,
+    /// How the control flow is recovered
+    pub backend: Backend,
+    /// Make [try_decompile_function] succeed even if some opcodes couldn't be decompiled,
+    /// they are left as comments like with the other functions.
+    pub raw_fallback: bool,
+    /// Keep the conversions Haxe does implicitly (to Dynamic, to Float, to a structure type) and the unchecked casts
+    /// inserted by the compiler. They are elided by default, truncations to Int and checked casts are always kept.
+    pub implicit_casts: bool,
+    /// Replace calls to functions always returning the same constant or global with that value, with a comment
+    /// naming the function. Collapses the getters of configuration values. Calls with arguments that could have side
+    /// effects are kept.
+    pub constant_returns: bool,
+    /// Replace calls to trivial property accessors (`get_x()` returning a field, `set_x(v)` assigning one) with
+    /// the property access they were compiled from : `obj.x` and `obj.x = v`.
+    pub property_access: bool,
+    /// When the [Backend::Scopes] backend can't follow the control flow of a function, decompile it again with
+    /// [Backend::Structured], which renders the parts it can't structure with labels and gotos instead of giving up.
+    pub goto_fallback: bool,
+    /// Emit the source file and line a statement was compiled from as a comment above it, when it changes.
+    /// Needs the debug info.
+    pub line_comments: bool,
+    /// Replace calls to the string decoding functions of obfuscated programs with the decoded strings
+    pub string_decoders: StringDecoders,
+    /// Initial values of the static variables of every class, see [Self::with_static_values].
+    /// When unset, [decompile_class_with] analyzes the initializers again for each class.
+    pub static_values: Option<Arc<HashMap<RefGlobal, HashMap<RefField, InitValue>>>>,
+}
+
+impl Default for DecompilerOptions {
+    fn default() -> Self {
+        Self {
+            interleave_opcodes: false,
+            passes: Pass::ALL.to_vec(),
+            backend: Backend::default(),
+            raw_fallback: false,
+            implicit_casts: false,
+            constant_returns: false,
+            property_access: false,
+            goto_fallback: false,
+            line_comments: false,
+            string_decoders: StringDecoders::default(),
+            static_values: None,
+        }
+    }
+}
+
+/// Algorithm used to recover the control flow (conditions, loops) of a function
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum Backend {
+    /// Read the opcodes in order while tracking the open scopes.
+    /// Fast and close to the bytecode, but can fail on unusual control flow.
+    #[default]
+    Scopes,
+    /// Find loops and branches in the control flow graph of the function using dominators.
+    /// Control flow that can't be structured is emitted as labels and gotos.
+    Structured,
+}
+
+impl DecompilerOptions {
+    /// Enable or disable a pass. An enabled pass is put back at its default position in the pipeline.
+    pub fn with_pass(mut self, pass: Pass, enabled: bool) -> Self {
+        self.passes.retain(|&p| p != pass);
+        if enabled {
+            self.passes.push(pass);
+            self.passes
+                .sort_by_key(|p| Pass::ALL.iter().position(|a| a == p));
+        }
+        self
+    }
+
+    pub fn is_enabled(&self, pass: Pass) -> bool {
+        self.passes.contains(&pass)
+    }
+
+    /// Find the initial values of the static variables of the program once, to decompile many classes.
+    /// Kept if already set.
+    pub fn with_static_values(mut self, code: &Bytecode) -> Self {
+        self.static_values
+            .get_or_insert_with(|| Arc::new(code.all_static_initial_values()));
+        self
+    }
+}
+
+/// A transformation pass over the decompiled AST
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Pass {
+    /// Restore standard library idioms expanded by the compiler : `trace`, int to string
+    StdIdioms,
+    /// Restore string concatenations from `__add__` calls and `StringBuf` manipulation
+    StringConcat,
+    /// Remove assignments to variables that are never read
+    DeadStores,
+    /// Inline compiler generated temporaries (`_g`, `tmp`) into their only use
+    InlineExpressions,
+    /// Turn if/else branches assigning the same variable into an if expression (ternary)
+    IfExpressions,
+    /// Recover loop conditions and for loops
+    LoopIdioms,
+    /// Turn chains of comparisons against constants (like switches over strings) into switch statements
+    Switches,
+    /// Turn assignments of an operation on the assigned variable into compound assignments (`x += y`)
+    CompoundAssignments,
+    /// Fold the construction of a map followed by calls to `set` into a map literal
+    MapLiterals,
+    /// Recover the null coalescing (`a ?? b`) and safe navigation (`a?.b`) operators from if expressions
+    NullSafety,
+    /// Turn if expressions with a boolean constant branch into `&&` and `||`
+    BooleanOperators,
+    /// Call the static functions of the standard library with their class, like `Std.string(x)`
+    StdCalls,
+}
+
+impl Pass {
+    /// All the passes, in their default order
+    pub const ALL: [Pass; 12] = [
+        Pass::StdIdioms,
+        Pass::StringConcat,
+        Pass::MapLiterals,
+        // Before dead stores, which would remove the unused variable of a for loop
+        Pass::LoopIdioms,
+        // Before if expressions, which would take over branches assigning the same variable
+        Pass::Switches,
+        Pass::DeadStores,
+        Pass::InlineExpressions,
+        Pass::IfExpressions,
+        // Work on the if expressions
+        Pass::BooleanOperators,
+        Pass::NullSafety,
+        Pass::CompoundAssignments,
+        // Last, the other passes recognize calls to the standard library by their function
+        Pass::StdCalls,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pass::StdIdioms => "std idioms",
+            Pass::StringConcat => "string concatenation",
+            Pass::DeadStores => "dead stores",
+            Pass::InlineExpressions => "inline expressions",
+            Pass::IfExpressions => "if expressions",
+            Pass::LoopIdioms => "loop idioms",
+            Pass::Switches => "switches",
+            Pass::CompoundAssignments => "compound assignments",
+            Pass::MapLiterals => "map literals",
+            Pass::NullSafety => "null safety",
+            Pass::BooleanOperators => "boolean operators",
+            Pass::StdCalls => "std calls",
+        }
+    }
+}
 
 enum ExprCtx {
     Constructor {
@@ -30,9 +241,36 @@ enum ExprCtx {
     },
     Anonymous {
         pos: usize,
-        fields: HashMap<RefField, Expr>,
+        fields: BTreeMap<RefField, Expr>,
         remaining: usize,
     },
+    /// Elements of an array literal, see [DecompilerState::array_literal]
+    Array {
+        reg: Reg,
+        values: Vec<Expr>,
+    },
+}
+
+/// Maximum number of opcodes of a function considered as a trivial property accessor
+const MAX_ACCESSOR_LEN: usize = 4;
+
+/// A trivial property accessor, with the name of its property
+enum Accessor {
+    Get(Str),
+    Set(Str),
+}
+
+/// What a call opcode calls
+#[derive(Copy, Clone)]
+enum Callee {
+    /// A function or a native, `Call0` to `CallN`
+    Fun(RefFun),
+    /// A method of the first argument, `CallMethod`
+    Method(RefField),
+    /// A method of `this`, `CallThis`
+    This(RefField),
+    /// The closure in a register, `CallClosure`
+    Closure(Reg),
 }
 
 struct DecompilerState<'c> {
@@ -40,6 +278,8 @@ struct DecompilerState<'c> {
     scopes: Scopes,
     // Expression values for each registers
     reg_state: HashMap<Reg, Expr>,
+    // Opcodes inlined in the expression of each register, empty for variables
+    sources: HashMap<Reg, Vec<usize>>,
     // For parsing statements made of multiple instructions like constructor calls and anonymous structures
     // TODO move this to another pass on the generated ast
     expr_ctx: Vec<ExprCtx>,
@@ -47,52 +287,202 @@ struct DecompilerState<'c> {
     seen: HashSet<Str>,
     f: &'c Function,
     code: &'c Bytecode,
+    opts: &'c DecompilerOptions,
+    // Index of the opcode being processed
+    pos: usize,
+    // First opcode not yet attached to a statement
+    pending_ops: usize,
+    diagnostics: Vec<Diagnostic>,
+    // A jump couldn't be matched to a scope, the control flow in the output is wrong
+    unstructured: bool,
+    // Control flow graph for liveness queries, built on first use
+    cfg: OnceCell<Cfg>,
+    // Values held by the registers, to tell apart the values sharing a register, built on first use
+    ssa: OnceCell<Ssa>,
+    // Opcodes that can't be reached from the start of the function, except the jumps and returns left by the
+    // compiler, built on first use
+    unreachable: OnceCell<Vec<Range<usize>>>,
+    // Start of the last unreachable range annotated with a comment
+    noted_unreachable: Option<usize>,
+    // Source line of the last statement annotated with a comment
+    noted_line: Option<(Str, usize)>,
 }
 
 impl<'c> DecompilerState<'c> {
-    fn new(code: &'c Bytecode, f: &'c Function) -> DecompilerState<'c> {
+    fn new(
+        code: &'c Bytecode,
+        f: &'c Function,
+        opts: &'c DecompilerOptions,
+    ) -> DecompilerState<'c> {
         let scopes = Scopes::new();
         let mut reg_state = HashMap::with_capacity(f.regs.len());
         let expr_ctx = Vec::new();
         let mut seen = HashSet::new();
+        let unreachable = OnceCell::new();
+        // Only the code after a jump or a return can be unreachable, no need for the cfg otherwise
+        if !f.ops.iter().rev().skip(1).any(|op| {
+            matches!(
+                op,
+                Opcode::JAlways { .. }
+                    | Opcode::Ret { .. }
+                    | Opcode::Throw { .. }
+                    | Opcode::Rethrow { .. }
+            )
+        }) {
+            let _ = unreachable.set(Vec::new());
+        }
 
         let mut start = 0;
         // First argument / First register is 'this'
-        if f.is_method() || code.get(f.name) == "__constructor__" {
+        if f.has_this(code) {
             reg_state.insert(Reg(0), cst_this());
             start = 1;
         }
 
         // Initialize register state with the function arguments
         for i in start..f.ty(code).args.len() {
-            let name = f.arg_name(code, i - start);
-            reg_state.insert(Reg(i as u32), Expr::Variable(Reg(i as u32), name.clone()));
-            if let Some(name) = name {
-                seen.insert(name);
-            }
+            let name = arg_name(code, f, i);
+            reg_state.insert(
+                Reg(i as u32),
+                Expr::Variable(Reg(i as u32), Some(name.clone())),
+            );
+            seen.insert(name);
         }
 
         Self {
             scopes,
             reg_state,
+            sources: HashMap::new(),
             expr_ctx,
             seen,
             f,
             code,
+            opts,
+            pos: 0,
+            pending_ops: 0,
+            diagnostics: Vec::new(),
+            unstructured: false,
+            unreachable,
+            noted_unreachable: None,
+            noted_line: None,
+            cfg: OnceCell::new(),
+            ssa: OnceCell::new(),
+        }
+    }
+
+    fn cfg(&self) -> &Cfg {
+        // Reuse the one of the SSA form if it was needed first
+        match self.ssa.get() {
+            Some(ssa) => &ssa.cfg,
+            None => self.cfg.get_or_init(|| cfg(self.f)),
         }
     }
 
+    fn ssa(&self) -> &Ssa {
+        self.ssa.get_or_init(|| ssa(self.f))
+    }
+
+    /// The unreachable range the current opcode is in, if any
+    fn unreachable_range(&self) -> Option<Range<usize>> {
+        self.unreachable
+            .get_or_init(|| {
+                unreachable_ops(self.cfg())
+                    .into_iter()
+                    .filter(|r| !is_leftover(&self.f.ops[r.clone()]))
+                    .collect()
+            })
+            .iter()
+            .find(|r| r.contains(&self.pos))
+            .cloned()
+    }
+
     fn push_stmt(&mut self, stmt: Statement) {
+        if self.opts.interleave_opcodes {
+            self.flush_ops(self.pos + 1);
+        }
+        let source = self.source().filter(|_| stmt.is_code());
+        if let Some(r) = self.unreachable_range() {
+            if self.noted_unreachable != Some(r.start) {
+                self.noted_unreachable = Some(r.start);
+                self.scopes.push_stmt(comment("unreachable"));
+            }
+        }
+        if self.opts.line_comments {
+            if let Some(line) = self.f.line(self.code, self.pos) {
+                if self.noted_line.as_ref() != Some(&line) {
+                    self.scopes
+                        .push_stmt(comment(format!("{}:{}", line.0, line.1)));
+                    self.noted_line = Some(line);
+                }
+            }
+        }
+        if let Some(source) = source {
+            self.scopes.push_stmt(source);
+        }
         self.scopes.push_stmt(stmt);
     }
 
+    /// Opcodes the current opcode and the expressions of the registers it reads have been decompiled from, sorted
+    fn op_sources(&self) -> Vec<usize> {
+        let mut ops = vec![self.pos];
+        for reg in self.f.ops[self.pos].read_regs() {
+            ops.extend(self.sources.get(&reg).into_iter().flatten());
+        }
+        ops.sort_unstable();
+        ops.dedup();
+        ops
+    }
+
+    /// [Statement::Source] of a statement built from the current opcode, when interleaving opcodes
+    fn source(&self) -> Option<Statement> {
+        self.opts
+            .interleave_opcodes
+            .then(|| Statement::Source(self.op_sources()))
+    }
+
+    /// Record that the current opcode couldn't be decompiled, it is emitted as a comment instead
+    fn fail(&mut self, reason: impl Into<String>) {
+        let reason = reason.into();
+        self.scopes.push_stmt(comment(format!(
+            "{:>3}: {} ({reason})",
+            self.pos,
+            self.f.ops[self.pos].display(self.code, self.f, self.pos as i32, 11)
+        )));
+        self.diagnostics.push(Diagnostic {
+            findex: self.f.findex,
+            pos: self.pos,
+            reason,
+        });
+    }
+
+    /// Like [Self::fail], for a jump that doesn't fit the open scopes
+    fn fail_control_flow(&mut self, reason: impl Into<String>) {
+        self.unstructured = true;
+        self.fail(reason);
+    }
+
+    /// Emit the opcodes up to `end` (exclusive) that have not been attached to a statement yet.
+    fn flush_ops(&mut self, end: usize) {
+        for i in self.pending_ops..end {
+            self.scopes.push_stmt(Statement::Opcode(i));
+        }
+        self.pending_ops = self.pending_ops.max(end);
+    }
+
     // Update the register state and create a statement depending on inline rules
     fn push_expr(&mut self, i: usize, dst: Reg, expr: Expr) {
         let name = self.f.var_name(self.code, i);
         // Inline check
         if name.is_none() {
-            self.reg_state.insert(dst, expr);
+            // Inlining would repeat the side effects or move them past other ones
+            if expr.has_side_effects() && !self.is_effect_inlinable(dst) {
+                self.push_temporary(dst, expr);
+            } else {
+                self.sources.insert(dst, self.op_sources());
+                self.reg_state.insert(dst, expr);
+            }
         } else {
+            self.sources.remove(&dst);
             self.reg_state
                 .insert(dst, Expr::Variable(dst, name.clone()));
             let declaration = self.seen.insert(name.clone().unwrap());
@@ -112,16 +502,28 @@ impl<'c> DecompilerState<'c> {
             .unwrap_or_else(|| Expr::Unknown("missing expr".to_owned()))
     }
 
+    /// Conversion of `src` to the type of `dst`, implicit and unchecked casts are only kept if asked to
+    fn cast(&self, src: Reg, dst: Reg, kind: CastKind) -> Expr {
+        let expr = self.expr(src);
+        match kind {
+            CastKind::Implicit | CastKind::Unchecked if !self.opts.implicit_casts => expr,
+            _ => Expr::Cast(Box::new(expr), self.f[dst], kind),
+        }
+    }
+
     /// Expands the expression of many registers
     fn args_expr(&self, args: &[Reg]) -> Vec<Expr> {
         args.iter().map(|&r| self.expr(r)).collect()
     }
 
-    /// Push a call to a function, which might be a constructor call.
-    fn push_call(&mut self, i: usize, dst: Reg, fun: RefFun, args: &[Reg]) {
+    /// Lower any call opcode, which might be a constructor call.
+    ///
+    /// The call is a statement if it returns nothing and the value of `dst` otherwise. The callee signature is
+    /// unknown for dynamic values and unresolved methods, a `dst` register of type `Void` makes it a statement too.
+    fn push_call(&mut self, i: usize, dst: Reg, callee: Callee, args: &[Reg]) {
         if let Some(&ExprCtx::Constructor { reg, pos }) = self.expr_ctx.last() {
-            if reg == args[0] {
-                self.push_expr(
+            if matches!(callee, Callee::Fun(_)) && args.first() == Some(&reg) {
+                self.push_effect(
                     pos,
                     reg,
                     Expr::Constructor(ConstructorCall::new(
@@ -130,675 +532,1362 @@ impl<'c> DecompilerState<'c> {
                     )),
                 );
                 self.expr_ctx.pop();
+                return;
+            }
+        }
+        let code = self.code;
+        if let Callee::Fun(fun) = callee {
+            if self.array_literal(i, dst, fun, args) {
+                return;
+            }
+            if let Some(value) = self.decoded_string(fun, args) {
+                self.push_expr(i, dst, Expr::Constant(Constant::Str(Str::from(value))));
+                return;
+            }
+            if let Some(value) = self.constant_return(dst, fun, args) {
+                self.push_stmt(comment(format!(
+                    "{} always returns this value",
+                    fun.display::<EnhancedFmt>(code)
+                )));
+                self.push_expr(i, dst, value);
+                return;
+            }
+        }
+        if self.property_access(i, dst, callee, args).is_some() {
+            return;
+        }
+        let (call, ret) = match callee {
+            Callee::Fun(fun) => {
+                self.push_stmt(comment(fun.display::<EnhancedFmt>(code).to_string()));
+                let call = match fun.as_fn(code) {
+                    Some(func) if func.is_method() && !args.is_empty() => call(
+                        Expr::Field(Box::new(self.expr(args[0])), func.name(code)),
+                        self.args_expr(&args[1..]),
+                    ),
+                    _ => call_fun(fun, self.args_expr(args)),
+                };
+                (call, Some(fun.ty(code).ret))
             }
+            Callee::Method(field) => {
+                let obj = self.f.regtype(args[0]);
+                let call = call(
+                    ast::field(self.expr(args[0]), obj, field, code),
+                    self.args_expr(&args[1..]),
+                );
+                let ret = obj
+                    .method(field.0, code)
+                    .and_then(|p| p.findex.as_fn(code))
+                    .map(|fun| fun.ty(code).ret);
+                (call, ret)
+            }
+            Callee::This(field) => {
+                let method = self.f.regs[0].method(field.0, code);
+                let name = method
+                    .map(|m| m.name(code))
+                    .unwrap_or_else(|| Str::from(format!("<method {}>", field.0)));
+                let call = call(
+                    Expr::Field(Box::new(cst_this()), name),
+                    self.args_expr(args),
+                );
+                let ret = method
+                    .and_then(|m| m.findex.as_fn(code))
+                    .map(|fun| fun.ty(code).ret);
+                (call, ret)
+            }
+            Callee::Closure(fun) => {
+                let call = call(self.expr(fun), self.args_expr(args));
+                (call, self.f.regtype(fun).as_fun(code).map(|ty| ty.ret))
+            }
+        };
+        let is_void = |ty: RefType| matches!(code.try_get(ty), Ok(Type::Void));
+        if ret.is_some_and(is_void) || is_void(self.f.regtype(dst)) {
+            self.push_stmt(stmt(call));
+        } else {
+            self.push_effect(i, dst, call);
+        }
+    }
+
+    /// Array literals are compiled to an allocation filled element by element and then wrapped in an array object :
+    /// `alloc_array` and `SetArray`s (`alloc_bytes` and `SetMem`s for numbers) followed by `ArrayObj.alloc`. Returns
+    /// true if the call starts or ends such a sequence.
+    fn array_literal(&mut self, i: usize, dst: Reg, fun: RefFun, args: &[Reg]) -> bool {
+        let code = self.code;
+        let filling = matches!(self.expr_ctx.last(), Some(ExprCtx::Array { reg, .. }) if args.first() == Some(reg));
+        if filling && is_array_wrap(code, fun) {
+            if let Some(ExprCtx::Array { values, .. }) = self.expr_ctx.pop() {
+                self.push_expr(i, dst, Expr::ArrayLiteral(values));
+            }
+            true
+        } else if is_array_alloc(code, fun)
+            && self.f.var_name(code, i).is_none()
+            && args
+                .last()
+                .and_then(|&len| self.int_constant(self.pos, len))
+                .is_some_and(|len| self.is_array_filled(dst, len))
+        {
+            self.expr_ctx.push(ExprCtx::Array {
+                reg: dst,
+                values: Vec::new(),
+            });
+            true
         } else {
-            self.push_stmt(comment(fun.display::<EnhancedFmt>(self.code).to_string()));
-            let call = if let Some((func, true)) =
-                fun.as_fn(self.code).map(|func| (func, func.is_method()))
+            false
+        }
+    }
+
+    /// Whether the buffer of `len` elements (or bytes) allocated in `arr` by the current opcode is only filled and then
+    /// wrapped in an array object, in the same basic block. Each element must be written exactly once, in order.
+    fn is_array_filled(&self, arr: Reg, len: i32) -> bool {
+        let block = self.cfg().block_of(self.pos);
+        // Index of the next element to write, in bytes for a buffer of bytes
+        let mut next = 0;
+        for (pos, op) in self.f.ops.iter().enumerate().skip(self.pos + 1) {
+            if self.cfg().block_of(pos) != block {
+                return false;
+            }
+            match *op {
+                Opcode::SetArray { array, index, src }
+                | Opcode::SetMem {
+                    bytes: array,
+                    index,
+                    src,
+                } if array == arr => {
+                    let size = match op {
+                        Opcode::SetArray { .. } => Some(1),
+                        _ => mem_size(&self.code[self.f[src]]),
+                    };
+                    match size {
+                        Some(size)
+                            if index != arr
+                                && src != arr
+                                && self.int_constant(pos, index) == Some(next) =>
+                        {
+                            next += size;
+                        }
+                        _ => return false,
+                    }
+                }
+                Opcode::Call1 { fun, arg0, .. } | Opcode::Call2 { fun, arg0, .. }
+                    if arg0 == arr =>
+                {
+                    return next == len && is_array_wrap(self.code, fun);
+                }
+                // Would be taken for an element of the array
+                Opcode::SetField { obj, .. }
+                    if !matches!(self.code[self.f[obj]], Type::Virtual { .. }) =>
+                {
+                    return false;
+                }
+                _ if op.read_regs().contains(&arr) || op.written_reg() == Some(arr) => {
+                    return false
+                }
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// The integer held by `reg` just before the opcode at `pos`, if it has been loaded from a constant
+    fn int_constant(&self, pos: usize, reg: Reg) -> Option<i32> {
+        let ssa = self.ssa();
+        let value = ssa.value_at(pos, reg)?;
+        match ssa.values[value.0].def {
+            Def::Op(def) => match self.f.ops[def] {
+                Opcode::Int { ptr, .. } => self.code.ints.get(ptr.0).copied(),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Add an element to the array literal being filled in `arr`, if there is one
+    fn push_array_value(&mut self, arr: Reg, src: Reg) -> bool {
+        let value = self.expr(src);
+        match self.expr_ctx.last_mut() {
+            Some(ExprCtx::Array { reg, values }) if *reg == arr => {
+                values.push(value);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Lower a call to a trivial getter or setter to a field access, if enabled. Returns `None` if the call must be
+    /// kept, like a setter whose return value is used.
+    fn property_access(&mut self, i: usize, dst: Reg, callee: Callee, args: &[Reg]) -> Option<()> {
+        if !self.opts.property_access {
+            return None;
+        }
+        let code = self.code;
+        let (fun, obj, args) = match callee {
+            Callee::Fun(fun) => (fun, self.expr(*args.first()?), &args[1..]),
+            Callee::Method(field) => (
+                self.f.regtype(*args.first()?).method(field.0, code)?.findex,
+                self.expr(args[0]),
+                &args[1..],
+            ),
+            Callee::This(field) => (
+                self.f.regs[0].method(field.0, code)?.findex,
+                cst_this(),
+                args,
+            ),
+            Callee::Closure(_) => return None,
+        };
+        match (accessor(code, fun.as_fn(code)?)?, args) {
+            (Accessor::Get(prop), []) => {
+                self.push_expr(i, dst, Expr::Field(Box::new(obj), prop));
+            }
+            (Accessor::Set(prop), &[value])
+                if self.f.var_name(code, i).is_none() && !self.is_read_after(dst) =>
             {
-                call(
-                    Expr::Field(Box::new(self.expr(args[0])), func.name(self.code)),
-                    self.args_expr(&args[1..]),
-                )
-            } else {
-                call_fun(fun, self.args_expr(args))
-            };
-            if fun.ty(self.code).ret.is_void() {
-                self.push_stmt(stmt(call));
-            } else {
-                self.push_expr(i, dst, call);
+                self.push_stmt(Statement::Assign {
+                    declaration: false,
+                    variable: Expr::Field(Box::new(obj), prop),
+                    assign: self.expr(value),
+                });
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// Lower an increment or a decrement of `dst`. When the old value is used, like in `a[i++]`, the compiler copies
+    /// the variable to a temporary just before : the temporary becomes the `i++` expression. Only integers are
+    /// collapsed, the copy of a Float or a Dynamic is stored in its temporary before the increment.
+    fn push_incr(&mut self, i: usize, dst: Reg, op: fn(Expr) -> Expr) {
+        let prev = i.checked_sub(1);
+        if let Some((p, &Opcode::Mov { dst: tmp, src })) = prev.map(|p| (p, &self.f.ops[p])) {
+            let old = self.expr(tmp);
+            if src == dst
+                && self.cfg().block_of(p) == self.cfg().block_of(i)
+                && self.f.var_name(self.code, p).is_none()
+                && matches!(old, Expr::Variable(_, Some(_)))
+                && self.is_read_after(tmp)
+            {
+                if matches!(
+                    self.code.get(self.f.regtype(dst)),
+                    Type::UI8 | Type::UI16 | Type::I32 | Type::I64
+                ) {
+                    self.sources.insert(tmp, self.op_sources());
+                    self.reg_state.insert(dst, old.clone());
+                    self.reg_state.insert(tmp, op(old));
+                    return;
+                }
+                self.push_temporary(tmp, old);
+            }
+        }
+        self.push_stmt(stmt(op(self.expr(dst))));
+    }
+
+    /// The string decoded from a call to a decoding function, if all the arguments are constants
+    fn decoded_string(&self, fun: RefFun, args: &[Reg]) -> Option<String> {
+        let decoders = &self.opts.string_decoders;
+        if decoders.is_empty() {
+            return None;
+        }
+        let code = self.code;
+        let args = args
+            .iter()
+            .map(|&r| match self.expr(r) {
+                Expr::Constant(c) => Some(match c {
+                    Constant::InlineInt(c) => DecodeArg::Int(c as i32),
+                    Constant::Int(c) => DecodeArg::Int(code[c]),
+                    Constant::Float(c) => DecodeArg::Float(code[c]),
+                    Constant::Bool(c) => DecodeArg::Bool(c),
+                    Constant::String(c) => DecodeArg::String(code[c].clone()),
+                    Constant::Str(c) => DecodeArg::String(c),
+                    Constant::Null => DecodeArg::Null,
+                    Constant::This => return None,
+                }),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        decoders.decode(fun, &args)
+    }
+
+    /// The value returned by a call to a function always returning the same constant, if enabled and if the arguments
+    /// can be dropped without losing side effects
+    fn constant_return(&self, dst: Reg, fun: RefFun, args: &[Reg]) -> Option<Expr> {
+        if !self.opts.constant_returns
+            || !args
+                .iter()
+                .all(|&r| matches!(self.expr(r), Expr::Variable(..) | Expr::Constant(_)))
+        {
+            return None;
+        }
+        let code = self.code;
+        Some(match code.constant_return(fun)? {
+            ConstantReturn::Int(c) => cst_int(c),
+            ConstantReturn::Float(c) => cst_float(c),
+            ConstantReturn::Bool(c) => cst_bool(c),
+            ConstantReturn::String(c) => cst_string(c),
+            ConstantReturn::Null => cst_null(),
+            ConstantReturn::Global(global) => global_expr(code, dst, code[global], global)?,
+        })
+    }
+
+    /// Like [push_expr](Self::push_expr) for an expression with side effects : if the value is never read, it is kept
+    /// as an expression statement instead of disappearing with its register. If it is read more than once, it is
+    /// stored in a temporary variable named after the register, inlining it would repeat the side effects.
+    fn push_effect(&mut self, i: usize, dst: Reg, expr: Expr) {
+        if self.f.var_name(self.code, i).is_some() {
+            self.push_expr(i, dst, expr);
+            return;
+        }
+        match reads_of(self.f, self.cfg(), self.pos, dst).len() {
+            0 => self.push_stmt(stmt(expr)),
+            1 => self.push_expr(i, dst, expr),
+            _ => self.push_temporary(dst, expr),
+        }
+    }
+
+    /// Store a value in a temporary variable named after its register, for registers without a debug name
+    fn push_temporary(&mut self, dst: Reg, expr: Expr) {
+        let variable = Expr::Variable(dst, None);
+        self.sources.remove(&dst);
+        self.reg_state.insert(dst, variable.clone());
+        let declaration = self.seen.insert(Str::from(dst.to_string()));
+        self.push_stmt(Statement::Assign {
+            declaration,
+            variable,
+            assign: expr,
+        });
+    }
+
+    /// Whether `dst` still holds the value copied from `src` by the current opcode everywhere this value of `src` is
+    /// read afterward
+    fn is_copy_kept(&self, src: Reg, dst: Reg) -> bool {
+        let pos = self.pos;
+        let ssa = self.ssa();
+        let (Some(copy), Some(&value)) = (ssa.writes[pos], ssa.reads[pos].first()) else {
+            return false;
+        };
+        debug_assert_eq!(ssa.values[value.0].reg, src);
+        ssa.uses(value)
+            .filter(|&p| p > pos)
+            .all(|p| ssa.value_at(p, dst) == Some(copy))
+    }
+
+    /// Whether the value written to `reg` by the current opcode might be read later
+    fn is_read_after(&self, reg: Reg) -> bool {
+        is_read_after(self.f, self.cfg(), self.pos, reg)
+    }
+
+    /// Whether the value with side effects written to `dst` by the current opcode can be inlined where it is read : it
+    /// is read once in the same block, and the opcodes with side effects in between are evaluated after it in the
+    /// expression reading it.
+    fn is_effect_inlinable(&self, dst: Reg) -> bool {
+        let pos = self.pos;
+        let read = match reads_of(self.f, self.cfg(), pos, dst)[..] {
+            // Nothing to reorder
+            [] => return true,
+            [read] => read,
+            _ => return false,
+        };
+        let args = self.f.ops[read].read_regs();
+        if read <= pos
+            || self.cfg().block_of(read) != self.cfg().block_of(pos)
+            || args.iter().filter(|&&r| r == dst).count() > 1
+        {
+            return false;
+        }
+        let arg = |reg| args.iter().position(|&r| r == reg);
+        (pos + 1..read)
+            .filter(|&i| has_side_effects(&self.f.ops[i]))
+            .all(|i| matches!(self.flows_into(i, read), Some(reg) if arg(reg) > arg(dst)))
+    }
+
+    /// Register through which the opcode at `to` reads the value written at `from`, if every value in between is read
+    /// exactly once
+    fn flows_into(&self, mut from: usize, to: usize) -> Option<Reg> {
+        loop {
+            let reg = self.f.ops[from].written_reg()?;
+            match reads_of(self.f, self.cfg(), from, reg)[..] {
+                [read] if read == to => return Some(reg),
+                [read] if read > from && read < to => from = read,
+                _ => return None,
             }
         }
     }
 
+    /// Condition for a conditional jump not to be taken, which is the condition to run the code following it
+    fn branch_cond(&self, o: &Opcode) -> Option<Expr> {
+        Some(match *o {
+            Opcode::JTrue { cond, .. } => not(self.expr(cond)),
+            Opcode::JFalse { cond, .. } => self.expr(cond),
+            Opcode::JNull { reg, .. } => noteq(self.expr(reg), cst_null()),
+            Opcode::JNotNull { reg, .. } => eq(self.expr(reg), cst_null()),
+            Opcode::JSGte { a, b, .. } | Opcode::JUGte { a, b, .. } => {
+                gt(self.expr(b), self.expr(a))
+            }
+            Opcode::JSGt { a, b, .. } => gte(self.expr(b), self.expr(a)),
+            Opcode::JSLte { a, b, .. } => lt(self.expr(b), self.expr(a)),
+            Opcode::JSLt { a, b, .. } | Opcode::JULt { a, b, .. } => {
+                lte(self.expr(b), self.expr(a))
+            }
+            Opcode::JNotLt { a, b, .. } => lt(self.expr(a), self.expr(b)),
+            Opcode::JNotGte { a, b, .. } => gte(self.expr(a), self.expr(b)),
+            Opcode::JEq { a, b, .. } => noteq(self.expr(a), self.expr(b)),
+            Opcode::JNotEq { a, b, .. } => eq(self.expr(a), self.expr(b)),
+            _ => return None,
+        })
+    }
+
+    /// Open an if scope, the statement is added to the current scope when it ends
+    fn push_if(&mut self, offset: i32, cond: Expr) {
+        if let Some(source) = self.source() {
+            self.scopes.push_stmt(source);
+        }
+        self.scopes.push_if(offset + 1, cond);
+    }
+
     /// Process a jmp instruction, might be the exit condition of a loop or an if
     fn push_jmp(&mut self, i: usize, offset: i32, cond: Expr) {
         if offset > 0 {
             // It's a loop
-            if matches!(self.f.ops[i + offset as usize], Opcode::JAlways { offset } if offset < 0) {
+            if matches!(self.f.ops.get(i + offset as usize), Some(&Opcode::JAlways { offset }) if offset < 0)
+            {
                 if let Some(loop_cond) = self.scopes.last_loop_cond_mut() {
                     if matches!(loop_cond, Expr::Unknown(_)) {
-                        //println!("old loop cond : {:?}", loop_cond);
                         *loop_cond = cond;
                     } else {
-                        self.scopes.push_if(offset + 1, cond);
+                        self.push_if(offset, cond);
                     }
                 } else {
-                    self.scopes.push_if(offset + 1, cond);
+                    self.push_if(offset, cond);
                 }
             } else {
                 // It's an if
-                self.scopes.push_if(offset + 1, cond);
+                self.push_if(offset, cond);
+            }
+        }
+    }
+}
+
+/// Name of an anonymous function inferred from the use of the closure created at `pos`
+fn closure_name(code: &Bytecode, f: &Function, pos: usize, fun: RefFun) -> Option<Str> {
+    is_anonymous(code, fun)
+        .then(|| context_name(code, f, pos))
+        .flatten()
+}
+
+fn closure_comment(code: &Bytecode, name: Option<&Str>, fun: RefFun) -> Statement {
+    let fun = fun.display::<EnhancedFmt>(code);
+    match name {
+        Some(name) => comment(format!("closure {name} : {fun}")),
+        None => comment(format!("closure : {fun}")),
+    }
+}
+
+/// A closure with its decompiled body, or an unknown expression if the function can't be found.
+///
+/// `env` is the environment of the closure, its fields are the captured variables.
+fn closure(state: &mut DecompilerState, fun: RefFun, name: Option<Str>, env: Option<Expr>) -> Expr {
+    match fun.try_as_fn(state.code) {
+        Ok(f) => {
+            let (mut stmts, diagnostics) = decompile(state.code, f, state.opts);
+            state.diagnostics.extend(diagnostics);
+            let captures = match &env {
+                Some(Expr::EnumConstr(_, _, args)) => args.clone(),
+                _ => Vec::new(),
+            };
+            if !captures.is_empty() {
+                post::visit(
+                    state.code,
+                    &mut stmts,
+                    &mut [&mut post::Captures(&captures)],
+                );
             }
+            Expr::Closure(Closure {
+                fun,
+                name,
+                has_env: env.is_some(),
+                captures,
+                stmts,
+            })
+        }
+        Err(e) => {
+            state.fail(e.to_string());
+            Expr::Unknown(e.to_string())
+        }
+    }
+}
+
+/// Recognize a method named like a property accessor whose body only reads or writes a field of `this`
+fn accessor(code: &Bytecode, f: &Function) -> Option<Accessor> {
+    let trivial = f.ops.len() <= MAX_ACCESSOR_LEN
+        && f.ops.iter().all(|op| {
+            matches!(
+                op,
+                Opcode::GetThis { .. }
+                    | Opcode::Field { .. }
+                    | Opcode::SetThis { .. }
+                    | Opcode::SetField { .. }
+                    | Opcode::Mov { .. }
+                    | Opcode::Ret { .. }
+            )
+        });
+    if !f.is_method() || !trivial {
+        return None;
+    }
+    let name = f.name(code);
+    // The arguments include this
+    let args = f.ty(code).args.len();
+    match (name.strip_prefix("get_"), name.strip_prefix("set_")) {
+        (Some(prop), _) if !prop.is_empty() && args == 1 => Some(Accessor::Get(Str::from(prop))),
+        (_, Some(prop)) if !prop.is_empty() && args == 2 => Some(Accessor::Set(Str::from(prop))),
+        _ => None,
+    }
+}
+
+/// `std/alloc_array` or `std/alloc_bytes`, allocating the storage of an array
+fn is_array_alloc(code: &Bytecode, fun: RefFun) -> bool {
+    match code.get(fun) {
+        FunPtr::Native(n) => {
+            n.lib(code) == "std" && matches!(&*n.name(code), "alloc_array" | "alloc_bytes")
+        }
+        FunPtr::Fun(_) => false,
+    }
+}
+
+/// Size in bytes of a value of this type in a buffer of bytes
+fn mem_size(ty: &Type) -> Option<i32> {
+    match ty {
+        Type::UI8 => Some(1),
+        Type::UI16 => Some(2),
+        Type::I32 | Type::F32 => Some(4),
+        Type::I64 | Type::F64 => Some(8),
+        _ => None,
+    }
+}
+
+/// `hl.types.ArrayObj.alloc` or `hl.types.ArrayBase.allocI32` and co, wrapping a storage in an array object
+fn is_array_wrap(code: &Bytecode, fun: RefFun) -> bool {
+    fun.name(code).starts_with("alloc")
+        && fun
+            .as_fn(code)
+            .and_then(|f| f.parent)
+            .and_then(|p| p.as_obj(code))
+            .is_some_and(|obj| {
+                let name = obj.name(code);
+                name.ends_with("ArrayObj") || name.ends_with("ArrayBase")
+            })
+}
+
+/// Opcodes that can be observed by other code : calls, writes outside of the registers and exceptions
+fn has_side_effects(op: &Opcode) -> bool {
+    matches!(
+        op,
+        Opcode::Call0 { .. }
+            | Opcode::Call1 { .. }
+            | Opcode::Call2 { .. }
+            | Opcode::Call3 { .. }
+            | Opcode::Call4 { .. }
+            | Opcode::CallN { .. }
+            | Opcode::CallMethod { .. }
+            | Opcode::CallThis { .. }
+            | Opcode::CallClosure { .. }
+            | Opcode::SetGlobal { .. }
+            | Opcode::SetField { .. }
+            | Opcode::SetThis { .. }
+            | Opcode::DynSet { .. }
+            | Opcode::SetI8 { .. }
+            | Opcode::SetI16 { .. }
+            | Opcode::SetMem { .. }
+            | Opcode::SetArray { .. }
+            | Opcode::Setref { .. }
+            | Opcode::SetEnumField { .. }
+            | Opcode::Throw { .. }
+            | Opcode::Rethrow { .. }
+            | Opcode::Assert
+            | Opcode::Asm { .. }
+    )
+}
+
+/// Expression for the value of a global of type `ty` loaded in `dst`
+fn global_expr(code: &Bytecode, dst: Reg, ty: RefType, global: RefGlobal) -> Option<Expr> {
+    if let Some(s) = code.global_string(global) {
+        return Some(cst_string(s));
+    }
+    // Other constants are objects with their fields set from the constant pools
+    if let Some(values) = code.global_constant_values(global) {
+        let args = values
+            .into_iter()
+            .map(|v| match v {
+                ConstantValue::Int(c) => cst_int(c),
+                ConstantValue::Float(c) => cst_float(c),
+                ConstantValue::Bool(c) => cst_bool(c),
+                ConstantValue::String(c) => cst_string(c),
+                ConstantValue::Type(t) => Expr::Variable(
+                    dst,
+                    Some(Str::from(t.display::<EnhancedFmt>(code).to_string())),
+                ),
+                ConstantValue::Global(g) => code
+                    .global_string(g)
+                    .map(cst_string)
+                    .unwrap_or_else(|| Expr::Unknown(format!("global@{}", g.0))),
+            })
+            .collect();
+        return Some(Expr::Constructor(ConstructorCall { ty, args }));
+    }
+    match &code[ty] {
+        Type::Obj(obj) | Type::Struct(obj) => {
+            Some(Expr::Variable(dst, Some(code[obj.name].to_owned())))
         }
+        Type::Enum { .. } => Some(Expr::Unknown("unknown enum variant".to_owned())),
+        _ => None,
     }
 }
 
 /// Decompile a function code to a list of [Statement]s.
 /// This works by analyzing each opcodes in order while trying to reconstruct scopes, contexts and intents.
+/// The raw AST built this way then goes through the post-processing [Pass]es.
 pub fn decompile_code(code: &Bytecode, f: &Function) -> Vec<Statement> {
-    let mut state = DecompilerState::new(code, f);
-
-    let iter = f.ops.iter().enumerate();
-    for (i, o) in iter {
-        // Opcodes are grouped by semantic
-        // Control flow first because they are the most important
-        match o {
-            //region CONTROL FLOW
-            &Opcode::JTrue { cond, offset } => state.push_jmp(i, offset, not(state.expr(cond))),
-            &Opcode::JFalse { cond, offset } => state.push_jmp(i, offset, state.expr(cond)),
-            &Opcode::JNull { reg, offset } => {
-                state.push_jmp(i, offset, noteq(state.expr(reg), cst_null()))
-            }
-            &Opcode::JNotNull { reg, offset } => {
-                state.push_jmp(i, offset, eq(state.expr(reg), cst_null()))
-            }
-            &Opcode::JSGte { a, b, offset } | &Opcode::JUGte { a, b, offset } => {
-                state.push_jmp(i, offset, gt(state.expr(b), state.expr(a)))
-            }
-            &Opcode::JSGt { a, b, offset } => {
-                state.push_jmp(i, offset, gte(state.expr(b), state.expr(a)))
-            }
-            &Opcode::JSLte { a, b, offset } => {
-                state.push_jmp(i, offset, lt(state.expr(b), state.expr(a)))
-            }
-            &Opcode::JSLt { a, b, offset } | &Opcode::JULt { a, b, offset } => {
-                state.push_jmp(i, offset, lte(state.expr(b), state.expr(a)))
-            }
-            &Opcode::JEq { a, b, offset } => {
-                state.push_jmp(i, offset, noteq(state.expr(a), state.expr(b)))
-            }
-            &Opcode::JNotEq { a, b, offset } => {
-                state.push_jmp(i, offset, eq(state.expr(a), state.expr(b)))
-            }
-            // Unconditional jumps can actually mean a lot of things
-            &Opcode::JAlways { offset } => {
-                if offset < 0 {
-                    // It's either the jump backward of a loop or a continue statement
-                    let loop_start = state
-                        .scopes
-                        .last_loop_start()
-                        .expect("Backward jump but we aren't in a loop ?");
-
-                    // Scan the next instructions in order to find another jump to the same place
-                    if f.ops.iter().enumerate().skip(i + 1).find_map(|(j, o)| {
-                        // We found another jump to the same place !
-                        if matches!(o, Opcode::JAlways {offset} if (j as i32 + offset + 1) as usize == loop_start) {
-                            Some(true)
-                        } else {
-                            None
-                        }
-                    }).unwrap_or(false) {
-                        // If this jump is not the last jump backward for the current loop, so it's definitely a continue; statement
-                        state.push_stmt(Statement::Continue);
-                    } else {
-                        // It's the last jump backward of the loop, which means the end of the loop
-                        // we generate the loop statement
-                        if let Some(stmt) = state.scopes.end_last_loop() {
-                            state.push_stmt(stmt);
-                        } else {
-                            panic!("Last scope is not a loop !");
-                        }
-                    }
+    decompile_code_with(code, f, &DecompilerOptions::default())
+}
+
+/// Same as [decompile_code] but with custom options.
+pub fn decompile_code_with(
+    code: &Bytecode,
+    f: &Function,
+    opts: &DecompilerOptions,
+) -> Vec<Statement> {
+    decompile(code, f, opts).0
+}
+
+/// Same as [decompile_code_with] but also returns the problems found, instead of only leaving them as comments in the
+/// statements. Meant for tools reporting them on their own.
+pub fn decompile_code_with_diagnostics(
+    code: &Bytecode,
+    f: &Function,
+    opts: &DecompilerOptions,
+) -> (Vec<Statement>, Vec<Diagnostic>) {
+    decompile(code, f, opts)
+}
+
+/// Same as [decompile_code_with] but fails if some opcodes couldn't be decompiled, instead of leaving them as
+/// comments. [DecompilerOptions::raw_fallback] makes it accept the comments.
+pub fn try_decompile_function(
+    code: &Bytecode,
+    f: &Function,
+    opts: &DecompilerOptions,
+) -> Result<Vec<Statement>, DecompileError> {
+    let (statements, diagnostics) = decompile(code, f, opts);
+    if diagnostics.is_empty() || opts.raw_fallback {
+        Ok(statements)
+    } else {
+        Err(DecompileError { diagnostics })
+    }
+}
+
+/// Decompile a function with the chosen backend, also returns the problems found
+fn decompile(
+    code: &Bytecode,
+    f: &Function,
+    opts: &DecompilerOptions,
+) -> (Vec<Statement>, Vec<Diagnostic>) {
+    let invalid = diagnostic::validate(code, f);
+    if !invalid.is_empty() {
+        // Even displaying the opcodes could follow the invalid indices
+        let statements = invalid.iter().map(|d| comment(d.to_string())).collect();
+        return (statements, invalid);
+    }
+    match opts.backend {
+        Backend::Scopes => {
+            let (statements, diagnostics, unstructured) =
+                decompile_range_inner(code, f, 0..f.ops.len(), opts);
+            if unstructured && opts.goto_fallback {
+                structured::decompile(code, f, opts)
+            } else {
+                (statements, diagnostics)
+            }
+        }
+        Backend::Structured => structured::decompile(code, f, opts),
+    }
+}
+
+/// Decompile only a range of opcodes of a function, like a basic block or the body of a loop.
+///
+/// Registers assigned before the range are displayed as variables. Scopes still open at the end of the range are
+/// closed and jumps back to before the range are left as comments.
+pub fn decompile_range(code: &Bytecode, f: &Function, ops: Range<usize>) -> Vec<Statement> {
+    decompile_range_with(code, f, ops, &DecompilerOptions::default())
+}
+
+/// Same as [decompile_range] but with custom options. Always uses the [Backend::Scopes] backend.
+pub fn decompile_range_with(
+    code: &Bytecode,
+    f: &Function,
+    ops: Range<usize>,
+    opts: &DecompilerOptions,
+) -> Vec<Statement> {
+    decompile_range_inner(code, f, ops, opts).0
+}
+
+/// Also returns whether the control flow couldn't be followed
+fn decompile_range_inner(
+    code: &Bytecode,
+    f: &Function,
+    ops: Range<usize>,
+    opts: &DecompilerOptions,
+) -> (Vec<Statement>, Vec<Diagnostic>, bool) {
+    let end = ops.end.min(f.ops.len());
+    let start = ops.start.min(end);
+    let mut state = DecompilerState::new(code, f, opts);
+    if start > 0 {
+        for i in 0..f.regs.len() {
+            state
+                .reg_state
+                .entry(Reg(i as u32))
+                .or_insert(Expr::Variable(Reg(i as u32), None));
+        }
+        state.pending_ops = start;
+    }
+
+    for (i, o) in f.ops.iter().enumerate().take(end).skip(start) {
+        process_op(&mut state, i, o, start);
+        state.scopes.advance();
+    }
+    if opts.interleave_opcodes {
+        state.flush_ops(end);
+    }
+    if state.scopes.has_scopes() {
+        if start == 0 && end == f.ops.len() {
+            state.pos = end - 1;
+            state.fail_control_flow("scopes still open at the end of the function");
+        }
+        state.scopes.close_all();
+    }
+    let mut statements = state.scopes.statements();
+
+    // AST post processing step !
+    post::run_passes(code, f, &mut statements, &opts.passes);
+
+    (statements, state.diagnostics, state.unstructured)
+}
+
+/// Translate an opcode, updating the register state and the open scopes.
+///
+/// `start` is the first opcode being decompiled, jumps back before it are ignored.
+fn process_op(state: &mut DecompilerState, i: usize, o: &Opcode, start: usize) {
+    let f = state.f;
+    let code = state.code;
+    state.pos = i;
+    // Opcodes are grouped by semantic
+    // Control flow first because they are the most important
+    match o {
+        //region CONTROL FLOW
+        &Opcode::JTrue { offset, .. }
+        | &Opcode::JFalse { offset, .. }
+        | &Opcode::JNull { offset, .. }
+        | &Opcode::JNotNull { offset, .. }
+        | &Opcode::JSGte { offset, .. }
+        | &Opcode::JUGte { offset, .. }
+        | &Opcode::JSGt { offset, .. }
+        | &Opcode::JSLte { offset, .. }
+        | &Opcode::JSLt { offset, .. }
+        | &Opcode::JULt { offset, .. }
+        | &Opcode::JNotLt { offset, .. }
+        | &Opcode::JNotGte { offset, .. }
+        | &Opcode::JEq { offset, .. }
+        | &Opcode::JNotEq { offset, .. } => {
+            if let Some(cond) = state.branch_cond(o) {
+                state.push_jmp(i, offset, cond);
+            }
+        }
+        // Unconditional jumps can actually mean a lot of things
+        &Opcode::JAlways { offset } => {
+            let target = o.jump_targets(i)[0];
+            if offset < 0 && target < start {
+                // The loop started before the range
+                state.push_stmt(comment(format!("jump to {target}")));
+            } else if offset < 0 {
+                // It's either the jump backward of a loop or a continue statement
+                let Some(loop_start) = state.scopes.last_loop_start() else {
+                    state.fail_control_flow("backward jump outside of a loop");
+                    return;
+                };
+
+                // Scan the next instructions in order to find another jump to the same place
+                if f.ops_with_targets().skip(i + 1).any(|(_, o, targets)| {
+                    // We found another jump to the same place !
+                    matches!(o, Opcode::JAlways { .. }) && targets.as_slice() == [loop_start]
+                }) {
+                    // If this jump is not the last jump backward for the current loop, so it's definitely a continue; statement
+                    state.push_stmt(Statement::Continue);
                 } else {
-                    if let Some(offsets) = state.scopes.last_is_switch_ctx() {
-                        if let Some(pos) = offsets.iter().position(|o| *o == i) {
-                            state.scopes.push_switch_case(pos);
-                        } else {
-                            panic!("no matching offset for switch case ({i})");
-                        }
-                    } else if state.scopes.last_loop_start().is_some() {
-                        // Check the instruction just before the jump target
-                        // If it's a jump backward of a loop
-                        if matches!(f.ops[(i as i32 + offset) as usize], Opcode::JAlways {offset} if offset < 0)
-                        {
-                            // It's a break condition
-                            state.push_stmt(Statement::Break);
-                        }
-                        // TODO else
-                    } else if state.scopes.last_is_if() {
-                        // It's the jump over of an else clause
-                        state.scopes.push_else(offset + 1);
+                    // It's the last jump backward of the loop, which means the end of the loop
+                    // we generate the loop statement
+                    if let Some(stmt) = state.scopes.end_last_loop() {
+                        state.push_stmt(stmt);
                     } else {
-                        eprintln!(
-                            "{i}: JAlways has no matching scope (last: {:?})",
-                            state.scopes.scopes.last()
-                        );
+                        state.fail_control_flow("the last scope isn't a loop");
                     }
                 }
-            }
-            Opcode::Switch { reg, offsets, end } => {
-                // Convert to absolute positions
-                state.scopes.push_switch(
-                    *end + 1,
-                    state.expr(*reg),
-                    offsets.iter().map(|o| i + *o as usize).collect(),
-                );
-                // The default switch case is implicit
-            }
-            &Opcode::Label => state.scopes.push_loop(i),
-            &Opcode::Ret { ret } => {
-                // Do not display return void; only in case of an early return
-                if state.scopes.has_scopes() {
-                    state.push_stmt(Statement::Return(if f.regtype(ret).is_void() {
-                        None
+            } else {
+                if let Some(offsets) = state.scopes.last_is_switch_ctx() {
+                    if let Some(pos) = offsets.iter().position(|o| *o == i) {
+                        state.scopes.push_switch_case(pos);
                     } else {
-                        Some(state.expr(ret))
-                    }));
-                } else if !f.regtype(ret).is_void() {
-                    state.push_stmt(Statement::Return(Some(state.expr(ret))));
+                        state.fail_control_flow(format!("no switch case starts at {i}"));
+                    }
+                } else if state.scopes.last_loop_start().is_some() {
+                    // Check the instruction just before the jump target
+                    // If it's a jump backward of a loop
+                    if matches!(f.ops.get((i as i32 + offset) as usize), Some(&Opcode::JAlways {offset}) if offset < 0)
+                    {
+                        // It's a break condition
+                        state.push_stmt(Statement::Break);
+                    }
+                    // TODO else
+                } else if state.scopes.last_is_if() {
+                    // It's the jump over of an else clause
+                    state.scopes.push_else(offset + 1);
+                } else {
+                    state.fail_control_flow("jump with no matching scope");
                 }
             }
-            //endregion
-
-            //region EXCEPTIONS
-            &Opcode::Throw { exc } | &Opcode::Rethrow { exc } => {
-                state.push_stmt(Statement::Throw(state.expr(exc)));
+        }
+        Opcode::Switch { reg, offsets, end } => {
+            if let Some(source) = state.source() {
+                state.scopes.push_stmt(source);
+            }
+            // Convert to absolute positions
+            state.scopes.push_switch(
+                *end + 1,
+                state.expr(*reg),
+                offsets.iter().map(|o| i + *o as usize).collect(),
+            );
+            // The default switch case is implicit
+        }
+        &Opcode::Label => state.scopes.push_loop(i),
+        &Opcode::Ret { ret } => {
+            // Do not display return void; only in case of an early return
+            if state.scopes.has_scopes() {
+                state.push_stmt(Statement::Return(if f.regtype(ret).is_void() {
+                    None
+                } else {
+                    Some(state.expr(ret))
+                }));
+            } else if !f.regtype(ret).is_void() {
+                state.push_stmt(Statement::Return(Some(state.expr(ret))));
             }
-            &Opcode::Trap { exc, offset } => {
-                state.scopes.push_try(offset + 1);
-            }
-            &Opcode::EndTrap { exc } => {
-                // TODO try catch
-            }
-            //endregion
+        }
+        //endregion
 
-            //region CONSTANTS
-            &Opcode::Int { dst, ptr } => {
-                state.push_expr(i, dst, cst_int(ptr));
-            }
-            &Opcode::Float { dst, ptr } => {
-                state.push_expr(i, dst, cst_float(ptr));
-            }
-            &Opcode::Bool { dst, value } => {
-                state.push_expr(i, dst, cst_bool(value));
-            }
-            &Opcode::String { dst, ptr } => {
-                state.push_expr(i, dst, cst_string(ptr));
-            }
-            &Opcode::Null { dst } => {
-                state.push_expr(i, dst, cst_null());
-            }
-            //endregion
+        //region EXCEPTIONS
+        &Opcode::Throw { exc } | &Opcode::Rethrow { exc } => {
+            state.push_stmt(Statement::Throw(state.expr(exc)));
+        }
+        &Opcode::Trap { exc, offset } => {
+            state.scopes.push_try(offset + 1);
+        }
+        &Opcode::EndTrap { exc } => {
+            // TODO try catch
+        }
+        //endregion
 
-            //region OPERATORS
-            &Opcode::Mov { dst, src } => {
-                state.push_expr(i, dst, state.expr(src));
-                // Workaround for when the instructions after this one use dst and src interchangeably.
-                state
-                    .reg_state
-                    .insert(src, Expr::Variable(dst, f.var_name(code, i)));
-            }
-            &Opcode::Add { dst, a, b } => {
-                state.push_expr(i, dst, add(state.expr(a), state.expr(b)));
-            }
-            &Opcode::Sub { dst, a, b } => {
-                state.push_expr(i, dst, sub(state.expr(a), state.expr(b)));
-            }
-            &Opcode::Mul { dst, a, b } => {
-                state.push_expr(i, dst, mul(state.expr(a), state.expr(b)));
-            }
-            &Opcode::SDiv { dst, a, b } | &Opcode::UDiv { dst, a, b } => {
-                state.push_expr(i, dst, div(state.expr(a), state.expr(b)));
-            }
-            &Opcode::SMod { dst, a, b } | &Opcode::UMod { dst, a, b } => {
-                state.push_expr(i, dst, modulo(state.expr(a), state.expr(b)));
-            }
-            &Opcode::Shl { dst, a, b } => {
-                state.push_expr(i, dst, shl(state.expr(a), state.expr(b)));
-            }
-            &Opcode::SShr { dst, a, b } | &Opcode::UShr { dst, a, b } => {
-                state.push_expr(i, dst, shr(state.expr(a), state.expr(b)));
-            }
-            &Opcode::And { dst, a, b } => {
-                state.push_expr(i, dst, and(state.expr(a), state.expr(b)));
-            }
-            &Opcode::Or { dst, a, b } => {
-                state.push_expr(i, dst, or(state.expr(a), state.expr(b)));
-            }
-            &Opcode::Xor { dst, a, b } => {
-                state.push_expr(i, dst, xor(state.expr(a), state.expr(b)));
-            }
-            &Opcode::Neg { dst, src } => {
-                state.push_expr(i, dst, neg(state.expr(src)));
-            }
-            &Opcode::Not { dst, src } => {
-                state.push_expr(i, dst, not(state.expr(src)));
-            }
-            &Opcode::Incr { dst } => {
-                // FIXME sometimes it should be an expression
-                state.push_stmt(stmt(incr(state.expr(dst))));
-            }
-            &Opcode::Decr { dst } => {
-                state.push_stmt(stmt(decr(state.expr(dst))));
-            }
-            //endregion
+        //region CONSTANTS
+        &Opcode::Int { dst, ptr } => {
+            state.push_expr(i, dst, cst_int(ptr));
+        }
+        &Opcode::Float { dst, ptr } => {
+            state.push_expr(i, dst, cst_float(ptr));
+        }
+        &Opcode::Bool { dst, value } => {
+            state.push_expr(i, dst, cst_bool(value));
+        }
+        &Opcode::String { dst, ptr } => {
+            state.push_expr(i, dst, cst_string(ptr));
+        }
+        &Opcode::Null { dst } => {
+            state.push_expr(i, dst, cst_null());
+        }
+        //endregion
 
-            //region CALLS
-            &Opcode::Call0 { dst, fun } => {
-                if fun.ty(code).ret.is_void() {
-                    state.push_stmt(stmt(call_fun(fun, Vec::new())));
-                } else {
-                    state.push_expr(i, dst, call_fun(fun, Vec::new()));
-                }
-            }
-            &Opcode::Call1 { dst, fun, arg0 } => {
-                state.push_call(i, dst, fun, &[arg0]);
-            }
-            &Opcode::Call2 {
-                dst,
-                fun,
-                arg0,
-                arg1,
-            } => {
-                state.push_call(i, dst, fun, &[arg0, arg1]);
-            }
-            &Opcode::Call3 {
-                dst,
-                fun,
-                arg0,
-                arg1,
-                arg2,
-            } => {
-                state.push_call(i, dst, fun, &[arg0, arg1, arg2]);
-            }
-            &Opcode::Call4 {
-                dst,
-                fun,
-                arg0,
-                arg1,
-                arg2,
-                arg3,
-            } => {
-                state.push_call(i, dst, fun, &[arg0, arg1, arg2, arg3]);
-            }
-            Opcode::CallN { dst, fun, args } => {
-                if let Some(&ExprCtx::Constructor { reg, pos }) = state.expr_ctx.last() {
-                    if reg == args[0] {
-                        state.push_expr(
-                            pos,
-                            reg,
-                            Expr::Constructor(ConstructorCall::new(
-                                f.regtype(reg),
-                                state.args_expr(&args[1..]),
-                            )),
-                        );
-                    }
-                } else {
-                    state.push_stmt(comment(fun.display::<EnhancedFmt>(code).to_string()));
-                    let call = call_fun(*fun, state.args_expr(args));
-                    if fun.ty(code).ret.is_void() {
-                        state.push_stmt(stmt(call));
-                    } else {
-                        state.push_expr(i, *dst, call);
-                    }
-                }
+        //region OPERATORS
+        &Opcode::Mov { dst, src } => {
+            state.push_expr(i, dst, state.expr(src));
+            // The instructions after this one might use dst and src interchangeably for the new variable
+            let name = f.var_name(code, i);
+            if name.is_some() && state.is_copy_kept(src, dst) {
+                state.sources.remove(&src);
+                state.reg_state.insert(src, Expr::Variable(dst, name));
             }
-            Opcode::CallMethod { dst, field, args } => {
-                let call = call(
-                    ast::field(state.expr(args[0]), f.regtype(args[0]), *field, code),
-                    state.args_expr(&args[1..]),
-                );
-                if f.regtype(args[0])
-                    .method(field.0, code)
-                    .and_then(|p| p.findex.as_fn(code))
-                    .map(|fun| fun.ty(code).ret.is_void())
-                    .unwrap_or(false)
-                {
-                    state.push_stmt(stmt(call));
-                } else {
-                    state.push_expr(i, *dst, call);
-                }
-            }
-            Opcode::CallThis { dst, field, args } => {
-                let method = f.regs[0].method(field.0, code).unwrap();
-                let call = call(
-                    Expr::Field(Box::new(cst_this()), method.name(code)),
-                    state.args_expr(args),
-                );
-                if method
-                    .findex
-                    .as_fn(code)
-                    .map(|fun| fun.ty(code).ret.is_void())
-                    .unwrap_or(false)
-                {
-                    state.push_stmt(stmt(call));
-                } else {
-                    state.push_expr(i, *dst, call);
-                }
-            }
-            Opcode::CallClosure { dst, fun, args } => {
-                let call = call(state.expr(*fun), state.args_expr(args));
-                if f.regtype(*fun)
-                    .as_fun(code)
-                    .map(|ty| ty.ret.is_void())
-                    .unwrap_or(false)
-                {
-                    state.push_stmt(stmt(call));
-                } else {
-                    state.push_expr(i, *dst, call);
-                }
-            }
-            //endregion
+        }
+        &Opcode::Add { dst, a, b } => {
+            state.push_expr(i, dst, add(state.expr(a), state.expr(b)));
+        }
+        &Opcode::Sub { dst, a, b } => {
+            state.push_expr(i, dst, sub(state.expr(a), state.expr(b)));
+        }
+        &Opcode::Mul { dst, a, b } => {
+            state.push_expr(i, dst, mul(state.expr(a), state.expr(b)));
+        }
+        &Opcode::SDiv { dst, a, b } | &Opcode::UDiv { dst, a, b } => {
+            state.push_expr(i, dst, div(state.expr(a), state.expr(b)));
+        }
+        &Opcode::SMod { dst, a, b } | &Opcode::UMod { dst, a, b } => {
+            state.push_expr(i, dst, modulo(state.expr(a), state.expr(b)));
+        }
+        &Opcode::Shl { dst, a, b } => {
+            state.push_expr(i, dst, shl(state.expr(a), state.expr(b)));
+        }
+        &Opcode::SShr { dst, a, b } | &Opcode::UShr { dst, a, b } => {
+            state.push_expr(i, dst, shr(state.expr(a), state.expr(b)));
+        }
+        &Opcode::And { dst, a, b } => {
+            state.push_expr(i, dst, and(state.expr(a), state.expr(b)));
+        }
+        &Opcode::Or { dst, a, b } => {
+            state.push_expr(i, dst, or(state.expr(a), state.expr(b)));
+        }
+        &Opcode::Xor { dst, a, b } => {
+            state.push_expr(i, dst, xor(state.expr(a), state.expr(b)));
+        }
+        &Opcode::Neg { dst, src } => {
+            state.push_expr(i, dst, neg(state.expr(src)));
+        }
+        &Opcode::Not { dst, src } => {
+            state.push_expr(i, dst, not(state.expr(src)));
+        }
+        &Opcode::Incr { dst } => {
+            state.push_incr(i, dst, incr);
+        }
+        &Opcode::Decr { dst } => {
+            state.push_incr(i, dst, decr);
+        }
+        //endregion
 
-            //region CLOSURES
-            &Opcode::StaticClosure { dst, fun } => {
-                state.push_stmt(comment(format!(
-                    "closure : {}",
-                    fun.display::<EnhancedFmt>(code)
-                )));
-                state.push_expr(
-                    i,
-                    dst,
-                    Expr::Closure(fun, decompile_code(code, fun.as_fn(code).unwrap())),
-                );
-            }
-            &Opcode::InstanceClosure { dst, obj, fun } => {
-                state.push_stmt(comment(format!(
-                    "closure : {}",
-                    fun.display::<EnhancedFmt>(code)
-                )));
-                match &code[f[obj]] {
-                    // This is an anonymous enum holding the capture for the closure
-                    Type::Enum { .. } => {
-                        state.push_expr(
-                            i,
-                            dst,
-                            Expr::Closure(fun, decompile_code(code, fun.as_fn(code).unwrap())),
-                        );
-                    }
-                    _ => {
-                        state.push_expr(
-                            i,
-                            dst,
-                            Expr::Field(Box::new(state.expr(obj)), fun.name(code)),
-                        );
-                    }
-                }
-            }
-            //endregion
+        //region CALLS
+        &Opcode::Call0 { dst, fun } => {
+            state.push_call(i, dst, Callee::Fun(fun), &[]);
+        }
+        &Opcode::Call1 { dst, fun, arg0 } => {
+            state.push_call(i, dst, Callee::Fun(fun), &[arg0]);
+        }
+        &Opcode::Call2 {
+            dst,
+            fun,
+            arg0,
+            arg1,
+        } => {
+            state.push_call(i, dst, Callee::Fun(fun), &[arg0, arg1]);
+        }
+        &Opcode::Call3 {
+            dst,
+            fun,
+            arg0,
+            arg1,
+            arg2,
+        } => {
+            state.push_call(i, dst, Callee::Fun(fun), &[arg0, arg1, arg2]);
+        }
+        &Opcode::Call4 {
+            dst,
+            fun,
+            arg0,
+            arg1,
+            arg2,
+            arg3,
+        } => {
+            state.push_call(i, dst, Callee::Fun(fun), &[arg0, arg1, arg2, arg3]);
+        }
+        Opcode::CallN { dst, fun, args } => {
+            state.push_call(i, *dst, Callee::Fun(*fun), args);
+        }
+        Opcode::CallMethod { dst, field, args } => {
+            state.push_call(i, *dst, Callee::Method(*field), args);
+        }
+        Opcode::CallThis { dst, field, args } => {
+            state.push_call(i, *dst, Callee::This(*field), args);
+        }
+        Opcode::CallClosure { dst, fun, args } => {
+            state.push_call(i, *dst, Callee::Closure(*fun), args);
+        }
+        //endregion
 
-            //region ACCESSES
-            &Opcode::GetGlobal { dst, global } => {
-                // Is a string
-                if f[dst].0 == 13 {
+        //region CLOSURES
+        &Opcode::StaticClosure { dst, fun } => {
+            let name = closure_name(code, f, i, fun);
+            state.push_stmt(closure_comment(code, name.as_ref(), fun));
+            let expr = closure(state, fun, name, None);
+            state.push_expr(i, dst, expr);
+        }
+        &Opcode::InstanceClosure { dst, obj, fun } => {
+            let name = closure_name(code, f, i, fun);
+            state.push_stmt(closure_comment(code, name.as_ref(), fun));
+            match code.try_get(f[obj]) {
+                // This is an anonymous enum holding the capture for the closure
+                Ok(Type::Enum { .. }) => {
+                    let expr = closure(state, fun, name, Some(state.expr(obj)));
+                    state.push_expr(i, dst, expr);
+                }
+                _ => {
                     state.push_expr(
                         i,
                         dst,
-                        cst_string(RefString(
-                            code.globals_initializers
-                                .get(&global)
-                                .and_then(|&x| {
-                                    code.constants
-                                        .as_ref()
-                                        .map(|constants| constants[x].fields[0])
-                                })
-                                .unwrap_or(0),
-                        )),
+                        Expr::Field(Box::new(state.expr(obj)), fun.name(code)),
                     );
-                } else {
-                    match &code[f[dst]] {
-                        Type::Obj(obj) | Type::Struct(obj) => {
-                            state.push_expr(
-                                i,
-                                dst,
-                                Expr::Variable(dst, Some(code[obj.name].to_owned())),
-                            );
-                        }
-                        Type::Enum { .. } => {
-                            state.push_expr(
-                                i,
-                                dst,
-                                Expr::Unknown("unknown enum variant".to_owned()),
-                            );
-                        }
-                        _ => {}
-                    }
                 }
             }
-            &Opcode::Field { dst, obj, field } => {
-                state.push_expr(
-                    i,
-                    dst,
-                    ast::field(state.expr(obj), f.regtype(obj), field, code),
-                );
+        }
+        //endregion
+
+        //region ACCESSES
+        &Opcode::GetGlobal { dst, global } => {
+            if let Some(expr) = global_expr(code, dst, f[dst], global) {
+                state.push_expr(i, dst, expr);
             }
-            &Opcode::SetField { obj, field, src } => {
-                let ctx = state.expr_ctx.pop();
-                // Might be a SetField for an anonymous structure
-                if let Some(ExprCtx::Anonymous {
-                    pos,
-                    mut fields,
-                    mut remaining,
-                }) = ctx
-                {
-                    fields.insert(field, state.expr(src));
-                    remaining -= 1;
-                    // If we filled all the structure fields, we emit an expr
-                    if remaining == 0 {
-                        state.push_expr(pos, obj, Expr::Anonymous(f.regtype(obj), fields));
-                    } else {
-                        state.expr_ctx.push(ExprCtx::Anonymous {
-                            pos,
-                            fields,
-                            remaining,
-                        });
-                    }
-                } else if let Some(ctx) = ctx {
-                    state.expr_ctx.push(ctx);
+        }
+        &Opcode::Field { dst, obj, field } => {
+            state.push_expr(
+                i,
+                dst,
+                ast::field(state.expr(obj), f.regtype(obj), field, code),
+            );
+        }
+        &Opcode::SetField { obj, field, src } => {
+            let ctx = state.expr_ctx.pop();
+            // Might be a SetField for an anonymous structure
+            if let Some(ExprCtx::Anonymous {
+                pos,
+                mut fields,
+                mut remaining,
+            }) = ctx
+            {
+                fields.insert(field, state.expr(src));
+                remaining -= 1;
+                // If we filled all the structure fields, we emit an expr
+                if remaining == 0 {
+                    state.push_expr(pos, obj, Expr::Anonymous(f.regtype(obj), fields));
                 } else {
-                    // Otherwise this is just a normal field set
-                    state.push_stmt(Statement::Assign {
-                        declaration: false,
-                        variable: ast::field(state.expr(obj), f.regtype(obj), field, code),
-                        assign: state.expr(src),
+                    state.expr_ctx.push(ExprCtx::Anonymous {
+                        pos,
+                        fields,
+                        remaining,
                     });
                 }
-            }
-            &Opcode::GetThis { dst, field } => {
-                state.push_expr(i, dst, ast::field(cst_this(), f.regs[0], field, code));
-            }
-            &Opcode::SetThis { field, src } => {
+            } else if let Some(ctx) = ctx {
+                state.expr_ctx.push(ctx);
+            } else {
+                // Otherwise this is just a normal field set
                 state.push_stmt(Statement::Assign {
                     declaration: false,
-                    variable: ast::field(cst_this(), f.regs[0], field, code),
+                    variable: ast::field(state.expr(obj), f.regtype(obj), field, code),
                     assign: state.expr(src),
                 });
             }
-            &Opcode::DynGet { dst, obj, field } => {
-                state.push_expr(i, dst, array(state.expr(obj), cst_string(field)));
+        }
+        &Opcode::GetThis { dst, field } => {
+            state.push_expr(i, dst, ast::field(cst_this(), f.regs[0], field, code));
+        }
+        &Opcode::SetThis { field, src } => {
+            state.push_stmt(Statement::Assign {
+                declaration: false,
+                variable: ast::field(cst_this(), f.regs[0], field, code),
+                assign: state.expr(src),
+            });
+        }
+        &Opcode::DynGet { dst, obj, field } => {
+            state.push_expr(i, dst, array(state.expr(obj), cst_string(field)));
+        }
+        &Opcode::DynSet { obj, field, src } => {
+            state.push_stmt(Statement::Assign {
+                declaration: false,
+                variable: array(state.expr(obj), cst_string(field)),
+                assign: state.expr(src),
+            });
+        }
+        //endregion
+
+        //region VALUES
+        &Opcode::ToDyn { dst, src }
+        | &Opcode::ToSFloat { dst, src }
+        | &Opcode::ToUFloat { dst, src }
+        | &Opcode::ToVirtual { dst, src } => {
+            let expr = state.cast(src, dst, CastKind::Implicit);
+            state.push_expr(i, dst, expr);
+        }
+        &Opcode::UnsafeCast { dst, src } => {
+            let expr = state.cast(src, dst, CastKind::Unchecked);
+            state.push_expr(i, dst, expr);
+        }
+        &Opcode::ToInt { dst, src } => {
+            let expr = state.cast(src, dst, CastKind::ToInt);
+            state.push_expr(i, dst, expr);
+        }
+        &Opcode::SafeCast { dst, src } => {
+            let expr = state.cast(src, dst, CastKind::Checked);
+            state.push_expr(i, dst, expr);
+        }
+        &Opcode::Ref { dst, src } => {
+            state.push_expr(i, dst, state.expr(src));
+        }
+        &Opcode::Unref { dst, src } => {
+            state.push_expr(i, dst, state.expr(src));
+        }
+        &Opcode::Setref { dst, value } => {
+            state.push_stmt(Statement::Assign {
+                declaration: false,
+                variable: state.expr(dst),
+                assign: state.expr(value),
+            });
+        }
+        &Opcode::RefData { dst, src } => {
+            state.push_expr(i, dst, state.expr(src));
+        }
+        &Opcode::New { dst } => {
+            // Constructor analysis
+            match &code[f[dst]] {
+                Type::Obj(_) | Type::Struct(_) => {
+                    state
+                        .expr_ctx
+                        .push(ExprCtx::Constructor { reg: dst, pos: i });
+                }
+                Type::Virtual { fields } => {
+                    state.expr_ctx.push(ExprCtx::Anonymous {
+                        pos: i,
+                        fields: BTreeMap::new(),
+                        remaining: fields.len(),
+                    });
+                }
+                _ => {
+                    state.push_expr(
+                        i,
+                        dst,
+                        Expr::Constructor(ConstructorCall::new(f.regtype(dst), Vec::new())),
+                    );
+                }
             }
-            &Opcode::DynSet { obj, field, src } => {
+        }
+        //endregion
+
+        //region ENUMS
+        &Opcode::EnumAlloc { dst, construct } => {
+            state.push_expr(
+                i,
+                dst,
+                Expr::EnumConstr(f.regtype(dst), construct, Vec::new()),
+            );
+        }
+        Opcode::MakeEnum {
+            dst,
+            construct,
+            args,
+        } => {
+            state.push_expr(
+                i,
+                *dst,
+                Expr::EnumConstr(f.regtype(*dst), *construct, state.args_expr(args)),
+            );
+        }
+        &Opcode::EnumIndex { dst, value } => {
+            state.push_expr(
+                i,
+                dst,
+                Expr::Field(Box::new(state.expr(value)), Str::from("constructorIndex")),
+            );
+            //state.push_expr(i, dst, state.expr(value));
+        }
+        &Opcode::EnumField {
+            dst,
+            value,
+            construct,
+            field,
+        } => {
+            state.push_expr(
+                i,
+                dst,
+                Expr::Field(Box::new(state.expr(value)), enum_param_name(field.0)),
+            );
+        }
+        &Opcode::SetEnumField { value, field, src } => match state.expr(value) {
+            Expr::Variable(r, name) => {
                 state.push_stmt(Statement::Assign {
                     declaration: false,
-                    variable: array(state.expr(obj), cst_string(field)),
+                    variable: Expr::Field(Box::new(state.expr(value)), enum_param_name(field.0)),
                     assign: state.expr(src),
                 });
             }
-            //endregion
-
-            //region VALUES
-            &Opcode::ToDyn { dst, src }
-            | &Opcode::ToSFloat { dst, src }
-            | &Opcode::ToUFloat { dst, src }
-            | &Opcode::ToInt { dst, src }
-            | &Opcode::SafeCast { dst, src }
-            | &Opcode::UnsafeCast { dst, src }
-            | &Opcode::ToVirtual { dst, src } => {
-                state.push_expr(i, dst, state.expr(src));
-            }
-            &Opcode::Ref { dst, src } => {
-                state.push_expr(i, dst, state.expr(src));
-            }
-            &Opcode::Unref { dst, src } => {
-                state.push_expr(i, dst, state.expr(src));
+            // Filling the environment of a closure
+            _ if matches!(state.reg_state.get(&value), Some(Expr::EnumConstr(..))) => {
+                // Evaluated once when creating the closure, not each time the capture is read
+                if !matches!(state.expr(src), Expr::Variable(..) | Expr::Constant(_)) {
+                    state.push_temporary(src, state.expr(src));
+                }
+                let src = state.expr(src);
+                state.sources.insert(value, state.op_sources());
+                if let Some(Expr::EnumConstr(_, _, args)) = state.reg_state.get_mut(&value) {
+                    if args.len() <= field.0 {
+                        args.resize(field.0 + 1, Expr::Unknown("uninitialized".to_owned()));
+                    }
+                    args[field.0] = src;
+                }
             }
-            &Opcode::Setref { dst, value } => {
+            _ => {
+                state.push_stmt(comment("closure capture"));
                 state.push_stmt(Statement::Assign {
                     declaration: false,
-                    variable: state.expr(dst),
-                    assign: state.expr(value),
+                    variable: Expr::Field(
+                        Box::new(state.expr(value)),
+                        Str::from(field.0.to_string()),
+                    ),
+                    assign: state.expr(src),
                 });
             }
-            &Opcode::RefData { dst, src } => {
-                state.push_expr(i, dst, state.expr(src));
-            }
-            &Opcode::New { dst } => {
-                // Constructor analysis
-                match &code[f[dst]] {
-                    Type::Obj(_) | Type::Struct(_) => {
-                        state
-                            .expr_ctx
-                            .push(ExprCtx::Constructor { reg: dst, pos: i });
-                    }
-                    Type::Virtual { fields } => {
-                        state.expr_ctx.push(ExprCtx::Anonymous {
-                            pos: i,
-                            fields: HashMap::with_capacity(fields.len()),
-                            remaining: fields.len(),
-                        });
-                    }
-                    _ => {
-                        state.push_expr(
-                            i,
-                            dst,
-                            Expr::Constructor(ConstructorCall::new(f.regtype(dst), Vec::new())),
-                        );
-                    }
-                }
-            }
-            //endregion
+        },
+        //endregion
 
-            //region ENUMS
-            &Opcode::EnumAlloc { dst, construct } => {
-                state.push_expr(
-                    i,
-                    dst,
-                    Expr::EnumConstr(f.regtype(dst), construct, Vec::new()),
-                );
-            }
-            Opcode::MakeEnum {
+        //region ARRAYS
+        &Opcode::ArraySize { dst, array } => {
+            state.push_expr(
+                i,
                 dst,
-                construct,
-                args,
-            } => {
-                state.push_expr(
-                    i,
-                    *dst,
-                    Expr::EnumConstr(f.regtype(*dst), *construct, state.args_expr(args)),
-                );
-            }
-            &Opcode::EnumIndex { dst, value } => {
-                state.push_expr(
-                    i,
-                    dst,
-                    Expr::Field(Box::new(state.expr(value)), Str::from("constructorIndex")),
-                );
-                //state.push_expr(i, dst, state.expr(value));
-            }
-            &Opcode::EnumField {
-                dst,
-                value,
-                construct,
-                field,
-            } => {
-                state.push_expr(
-                    i,
-                    dst,
-                    Expr::Field(Box::new(state.expr(value)), Str::from(field.0.to_string())),
-                );
-            }
-            &Opcode::SetEnumField { value, field, src } => match state.expr(value) {
-                Expr::Variable(r, name) => {
-                    state.push_stmt(Statement::Assign {
-                        declaration: false,
-                        variable: Expr::Field(
-                            Box::new(state.expr(value)),
-                            Str::from(field.0.to_string()),
-                        ),
-                        assign: state.expr(src),
-                    });
-                }
-                _ => {
-                    state.push_stmt(comment("closure capture"));
-                    state.push_stmt(Statement::Assign {
-                        declaration: false,
-                        variable: Expr::Field(
-                            Box::new(state.expr(value)),
-                            Str::from(field.0.to_string()),
-                        ),
-                        assign: state.expr(src),
-                    });
-                }
-            },
-            //endregion
-
-            //region ARRAYS
-            &Opcode::ArraySize { dst, array } => {
-                state.push_expr(
-                    i,
-                    dst,
-                    Expr::Field(Box::new(state.expr(array)), Str::from("length")),
-                );
-            }
-            &Opcode::GetArray { dst, array, index } => {
-                state.push_expr(i, dst, ast::array(state.expr(array), state.expr(index)));
-            }
-            &Opcode::SetArray { array, index, src } => {
+                Expr::Field(Box::new(state.expr(array)), Str::from("length")),
+            );
+        }
+        &Opcode::GetArray { dst, array, index } => {
+            state.push_expr(i, dst, ast::array(state.expr(array), state.expr(index)));
+        }
+        &Opcode::SetArray { array, index, src } => {
+            if !state.push_array_value(array, src) {
                 state.push_stmt(Statement::Assign {
                     declaration: false,
                     variable: ast::array(state.expr(array), state.expr(index)),
                     assign: state.expr(src),
                 });
             }
-            //endregion
+        }
+        //endregion
 
-            //region MEM
-            &Opcode::GetMem { dst, bytes, index } => {
-                state.push_expr(i, dst, array(state.expr(bytes), state.expr(index)));
-            }
-            &Opcode::SetMem { bytes, index, src } => {
+        //region MEM
+        &Opcode::GetMem { dst, bytes, index } => {
+            state.push_expr(i, dst, array(state.expr(bytes), state.expr(index)));
+        }
+        &Opcode::SetMem { bytes, index, src } => {
+            if !state.push_array_value(bytes, src) {
                 state.push_stmt(Statement::Assign {
                     declaration: false,
                     variable: array(state.expr(bytes), state.expr(index)),
                     assign: state.expr(src),
                 });
             }
-            //endregion
-            _ => {}
         }
-        state.scopes.advance();
+        //endregion
+        _ => {}
     }
-    let mut statements = state.scopes.statements();
-
-    // AST post processing step !
-    // It makes a single pass for all visitors
-    post::visit(
-        code,
-        &mut statements,
-        &mut [
-            Box::new(post::IfExpressions),
-            Box::new(post::StringConcat),
-            Box::new(post::Itos),
-            Box::new(post::Trace),
-        ],
-    );
-
-    statements
 }
 
 /// Decompile a function out of context
 pub fn decompile_function(code: &Bytecode, f: &Function) -> Method {
+    decompile_function_with(code, f, &DecompilerOptions::default())
+}
+
+/// Same as [decompile_function] but with custom options.
+pub fn decompile_function_with(code: &Bytecode, f: &Function, opts: &DecompilerOptions) -> Method {
     Method {
         fun: f.findex,
         static_: true,
         dynamic: false,
-        statements: decompile_code(code, f),
+        override_: false,
+        statements: decompile_code_with(code, f, opts),
     }
 }
 
+/// Name of the argument in register `i`, from the debug info or else after its position : `arg0`, `arg1`...
+/// `this` isn't counted.
+pub(crate) fn arg_name(code: &Bytecode, f: &Function, i: usize) -> Str {
+    let pos = i.saturating_sub(usize::from(f.has_this(code)));
+    f.arg_name(code, pos)
+        .unwrap_or_else(|| Str::from(format!("arg{pos}")))
+}
+
+/// The parameters of a function with their names and types, without the first `skip` arguments which aren't
+/// parameters in the source (`this`, the environment of a closure).
+pub fn params(code: &Bytecode, f: &Function, skip: usize) -> Vec<Param> {
+    f.ty(code)
+        .args
+        .iter()
+        .enumerate()
+        .skip(skip)
+        .map(|(i, &ty)| Param {
+            name: arg_name(code, f, i),
+            ty,
+        })
+        .collect()
+}
+
 /// Decompile a class with its static and instance fields and methods.
 pub fn decompile_class(code: &Bytecode, obj: &TypeObj) -> Class {
+    decompile_class_with(code, obj, &DecompilerOptions::default())
+}
+
+/// Same as [decompile_class] but with custom options.
+pub fn decompile_class_with(code: &Bytecode, obj: &TypeObj, opts: &DecompilerOptions) -> Class {
+    let mut class = decompile_class_outline(code, obj);
+    if let Some(ty) = obj.get_static_type(code) {
+        if class.fields.iter().any(|f| f.static_) {
+            let global = RefGlobal(obj.global.0 - 1);
+            match &opts.static_values {
+                Some(all) => {
+                    if let Some(values) = all.get(&global) {
+                        static_initial_values(code, values, ty, &mut class.fields);
+                    }
+                }
+                None => {
+                    let values = code.static_initial_values(global);
+                    static_initial_values(code, &values, ty, &mut class.fields);
+                }
+            }
+        }
+    }
+    for m in &mut class.methods {
+        if let Ok(fun) = m.fun.try_as_fn(code) {
+            m.statements = decompile_code_with(code, fun, opts);
+        }
+    }
+    class
+}
+
+/// Set the initial values of the static variables, found in the code run on startup
+fn static_initial_values(
+    code: &Bytecode,
+    values: &HashMap<RefField, InitValue>,
+    static_type: &TypeObj,
+    fields: &mut [ClassField],
+) {
+    for (&field, &value) in values {
+        let Some(name) = static_type.fields.get(field.0).map(|f| f.name(code)) else {
+            continue;
+        };
+        if let Some(f) = fields.iter_mut().find(|f| f.static_ && f.name == name) {
+            f.value = Some(match value {
+                InitValue::Null => Constant::Null,
+                InitValue::Bool(b) => Constant::Bool(b),
+                InitValue::Int(i) => Constant::Int(i),
+                InitValue::Float(x) => Constant::Float(x),
+                InitValue::String(s) => Constant::String(s),
+            });
+        }
+    }
+}
+
+/// A superclass of `obj` declares a method named `name`
+fn overrides(code: &Bytecode, obj: &TypeObj, name: &str) -> bool {
+    std::iter::successors(obj.super_.and_then(|ty| ty.as_obj(code)), |parent| {
+        parent.super_.and_then(|ty| ty.as_obj(code))
+    })
+    // Malformed files might have cycles
+    .take(code.types.len())
+    .any(|parent| parent.protos.iter().any(|p| p.name(code) == name))
+}
+
+/// The class declaration with its fields and method signatures, but without decompiling any method body.
+///
+/// Bodies can then be decompiled on demand with [decompile_code_with], which is much cheaper on big classes
+/// when only a few methods are of interest.
+pub fn decompile_class_outline(code: &Bytecode, obj: &TypeObj) -> Class {
     let static_type = obj.get_static_type(code);
 
     let mut fields = Vec::new();
@@ -813,6 +1902,7 @@ pub fn decompile_class(code: &Bytecode, obj: &TypeObj) -> Class {
             name: f.name(code).to_owned(),
             static_: false,
             ty: f.t,
+            value: None,
         });
     }
     if let Some(ty) = static_type {
@@ -827,6 +1917,7 @@ pub fn decompile_class(code: &Bytecode, obj: &TypeObj) -> Class {
                 name: f.name(code).to_owned(),
                 static_: true,
                 ty: f.t,
+                value: None,
             });
         }
     }
@@ -837,7 +1928,8 @@ pub fn decompile_class(code: &Bytecode, obj: &TypeObj) -> Class {
             fun: *fun,
             static_: false,
             dynamic: true,
-            statements: decompile_code(code, fun.as_fn(code).unwrap()),
+            override_: false,
+            statements: Vec::new(),
         })
     }
     if let Some(ty) = static_type {
@@ -846,7 +1938,8 @@ pub fn decompile_class(code: &Bytecode, obj: &TypeObj) -> Class {
                 fun: *fun,
                 static_: true,
                 dynamic: false,
-                statements: decompile_code(code, fun.as_fn(code).unwrap()),
+                override_: false,
+                statements: Vec::new(),
             })
         }
     }
@@ -855,10 +1948,14 @@ pub fn decompile_class(code: &Bytecode, obj: &TypeObj) -> Class {
             fun: f.findex,
             static_: false,
             dynamic: false,
-            statements: decompile_code(code, f.findex.as_fn(code).unwrap()),
+            override_: overrides(code, obj, &f.name(code)),
+            statements: Vec::new(),
         })
     }
 
+    // Bindings of malformed files might point to natives or nowhere
+    methods.retain(|m| m.fun.try_as_fn(code).is_ok());
+
     Class {
         name: obj.name(code).to_owned(),
         parent: obj
@@ -870,15 +1967,49 @@ pub fn decompile_class(code: &Bytecode, obj: &TypeObj) -> Class {
     }
 }
 
+/// The enum declaration with its constructors and their parameter types, `None` if the type isn't an enum.
+pub fn decompile_enum(code: &Bytecode, ty: &Type) -> Option<Enum> {
+    let Type::Enum {
+        name, constructs, ..
+    } = ty
+    else {
+        return None;
+    };
+    Some(Enum {
+        name: code.get(*name),
+        variants: constructs
+            .iter()
+            .map(|c| EnumVariant {
+                name: c.name(code),
+                params: c.params.clone(),
+            })
+            .collect(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
     use std::hint::black_box;
     use std::io::BufReader;
 
-    use hlbc::Bytecode;
+    use hlbc::analysis::cfg::cfg;
+    use hlbc::builder::Builder;
+    use hlbc::cancel::CancellationToken;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{
+        ConstantDef, EnumConstruct, ObjField, RefEnumConstruct, RefField, RefFun, RefGlobal,
+        RefInt, RefString, RefType, Reg, Type,
+    };
+    use hlbc::{Bytecode, Str};
 
-    use crate::{decompile_class, decompile_code, decompile_function};
+    use crate::ast::{call, cst_int, Expr, Operation, Statement};
+    use crate::fmt::FormatOptions;
+    use crate::{
+        decompile_class, decompile_class_with, decompile_code, decompile_code_with,
+        decompile_code_with_diagnostics, decompile_function, decompile_range,
+        try_decompile_function, Backend, Batch, DecompilerOptions, Pass,
+    };
 
     #[test]
     fn decomp_code_all() {
@@ -895,6 +2026,1169 @@ mod tests {
         }
     }
 
+    #[test]
+    fn decomp_batch() {
+        for entry in fs::read_dir("../../data").unwrap() {
+            let path = entry.unwrap().path();
+            if let Some(ext) = path.extension() {
+                if ext == "hl" {
+                    let code = Bytecode::from_file(&path).unwrap();
+                    let mut batch = Batch::all(&code, DecompilerOptions::default());
+                    let mut count = 0;
+                    while !batch.is_done() {
+                        count += batch.step(7).len();
+                        assert_eq!(batch.done(), count);
+                    }
+                    assert_eq!(count, code.functions.len());
+                    assert!(batch.step(7).is_empty());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cancel_batch() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        b.function("", &[], void, &[void], vec![Opcode::Ret { ret: Reg(0) }]);
+        let code = b.build().unwrap();
+        let f = &code.functions[0];
+        let cancel = CancellationToken::new();
+        let mut batch = Batch::new(&code, [f, f, f], DecompilerOptions::default())
+            .with_cancellation(cancel.clone());
+        assert_eq!(batch.step(1).len(), 1);
+        cancel.cancel();
+        assert!(batch.is_done());
+        assert!(batch.step(2).is_empty());
+        assert_eq!(batch.done(), 1);
+    }
+
+    #[test]
+    fn decomp_range_all() {
+        for entry in fs::read_dir("../../data").unwrap() {
+            let path = entry.unwrap().path();
+            if let Some(ext) = path.extension() {
+                if ext == "hl" {
+                    let code = Bytecode::from_file(&path).unwrap();
+                    for f in &code.functions {
+                        for block in cfg(f).blocks {
+                            black_box(decompile_range(&code, f, block.ops));
+                        }
+                        black_box(decompile_range(&code, f, f.ops.len() / 2..f.ops.len()));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decomp_code_all_structured() {
+        let opts = DecompilerOptions {
+            backend: Backend::Structured,
+            ..DecompilerOptions::default()
+        };
+        for entry in fs::read_dir("../../data").unwrap() {
+            let path = entry.unwrap().path();
+            if let Some(ext) = path.extension() {
+                if ext == "hl" {
+                    let code = Bytecode::from_file(&path).unwrap();
+                    for f in &code.functions {
+                        black_box(decompile_code_with(&code, f, &opts));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn try_decompile() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        // A backward jump with no loop
+        b.function("", &[], void, &[], vec![Opcode::JAlways { offset: -1 }]);
+        let code = b.build().unwrap();
+        let f = &code.functions[0];
+        let mut opts = DecompilerOptions::default();
+        let err = try_decompile_function(&code, f, &opts).unwrap_err();
+        assert_eq!(err.diagnostics.len(), 1);
+        assert_eq!(err.diagnostics[0].pos, 0);
+
+        opts.raw_fallback = true;
+        let stmts = try_decompile_function(&code, f, &opts).unwrap();
+        assert!(matches!(stmts.as_slice(), [Statement::Comment(_)]));
+        assert_eq!(decompile_code(&code, f).len(), 1);
+
+        let (stmts, diagnostics) = decompile_code_with_diagnostics(&code, f, &opts);
+        assert_eq!(stmts.len(), 1);
+        assert_eq!(diagnostics, err.diagnostics);
+    }
+
+    #[test]
+    fn invalid_indices() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let fun = b.function(
+            "",
+            &[int],
+            int,
+            &[],
+            vec![
+                Opcode::Mov {
+                    dst: Reg(2),
+                    src: Reg(0),
+                },
+                Opcode::Ret { ret: Reg(2) },
+            ],
+        );
+        b.function_mut(fun).regs.push(RefType(99));
+        let code = b.build().unwrap();
+        let f = fun.as_fn(&code).unwrap();
+        let err = try_decompile_function(&code, f, &DecompilerOptions::default()).unwrap_err();
+        let diagnostics: Vec<_> = err.diagnostics.iter().map(|d| (d.pos, &*d.reason)).collect();
+        assert_eq!(
+            diagnostics,
+            [
+                (0, "type of reg1: Invalid type index 99 (there are 3)"),
+                (0, "dst is reg2 but there are 2 registers"),
+                (1, "ret is reg2 but there are 2 registers")
+            ]
+        );
+        // Every backend stops before following them
+        for backend in [Backend::Scopes, Backend::Structured] {
+            let opts = DecompilerOptions {
+                backend,
+                ..DecompilerOptions::default()
+            };
+            let (stmts, diagnostics) = decompile_code_with_diagnostics(&code, f, &opts);
+            assert_eq!(diagnostics, err.diagnostics);
+            assert!(stmts.iter().all(|s| matches!(s, Statement::Comment(_))));
+        }
+    }
+
+    #[test]
+    fn unreachable() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        b.function(
+            "",
+            &[int],
+            int,
+            &[int],
+            vec![
+                Opcode::Ret { ret: Reg(0) },
+                Opcode::Mov {
+                    dst: Reg(1),
+                    src: Reg(0),
+                },
+                Opcode::Ret { ret: Reg(1) },
+            ],
+        );
+        // The compiler leaves a return after a throw
+        b.function(
+            "",
+            &[int],
+            int,
+            &[],
+            vec![Opcode::Throw { exc: Reg(0) }, Opcode::Ret { ret: Reg(0) }],
+        );
+        let code = b.build().unwrap();
+        let f = &code.functions[0];
+        let stmts = decompile_code(&code, f);
+        assert!(matches!(
+            stmts.as_slice(),
+            [Statement::Return(_), Statement::Comment(c), Statement::Return(_)] if c == "unreachable"
+        ));
+        assert!(!decompile_code(&code, &code.functions[1])
+            .iter()
+            .any(|s| matches!(s, Statement::Comment(_))));
+
+        // Never reached from the entry block, so never emitted
+        let opts = DecompilerOptions {
+            backend: Backend::Structured,
+            ..DecompilerOptions::default()
+        };
+        assert!(matches!(
+            decompile_code_with(&code, f, &opts).as_slice(),
+            [Statement::Return(_)]
+        ));
+    }
+
+    #[test]
+    fn incr_expression() {
+        let decompile = |ty| {
+            let mut b = Builder::new();
+            let ty = b.ty(ty);
+            let i = b.string("i");
+            // return i++;
+            let fun = b.function(
+                "",
+                &[ty],
+                ty,
+                &[ty],
+                vec![
+                    Opcode::Mov {
+                        dst: Reg(1),
+                        src: Reg(0),
+                    },
+                    Opcode::Incr { dst: Reg(0) },
+                    Opcode::Ret { ret: Reg(1) },
+                ],
+            );
+            b.function_mut(fun).assigns = Some(vec![(i, 0)]);
+            let code = b.build().unwrap();
+            let f = &code.functions[0];
+            decompile_code(&code, f)
+                .iter()
+                .map(|s| s.display(&FormatOptions::new(2), &code, f).to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(decompile(Type::I32), ["return i++;"]);
+        assert_eq!(decompile(Type::I64), ["return i++;"]);
+        // Not collapsed, but the old value is still returned
+        assert_eq!(
+            decompile(Type::F64),
+            ["var reg1: Float = i;", "i++;", "return reg1;"]
+        );
+        assert_eq!(
+            decompile(Type::Dyn),
+            ["var reg1: Dynamic = i;", "i++;", "return reg1;"]
+        );
+    }
+
+    #[test]
+    fn goto_fallback() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        let bool = b.ty(Type::Bool);
+        // A loop with 2 entries, the jump back doesn't match any scope
+        b.function(
+            "",
+            &[bool],
+            void,
+            &[],
+            vec![
+                Opcode::JFalse {
+                    cond: Reg(0),
+                    offset: 2,
+                },
+                Opcode::Nop,
+                Opcode::JAlways { offset: 0 },
+                Opcode::Nop,
+                Opcode::JAlways { offset: -4 },
+            ],
+        );
+        let code = b.build().unwrap();
+        let f = &code.functions[0];
+        let mut opts = DecompilerOptions::default();
+        assert!(try_decompile_function(&code, f, &opts).is_err());
+
+        opts.goto_fallback = true;
+        let stmts = try_decompile_function(&code, f, &opts).unwrap();
+        assert!(matches!(stmts.first(), Some(Statement::Comment(_))));
+        assert!(format!("{stmts:?}").contains("Goto"));
+    }
+
+    #[test]
+    fn line_comments() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let one = b.int(1);
+        let x = b.string("x");
+        let fun = b.function(
+            "",
+            &[],
+            int,
+            &[int],
+            vec![
+                Opcode::Int {
+                    dst: Reg(0),
+                    ptr: one,
+                },
+                Opcode::Ret { ret: Reg(0) },
+            ],
+        );
+        let f = b.function_mut(fun);
+        f.debug_info = Some(vec![(0, 3), (0, 4)]);
+        f.assigns = Some(vec![(x, 1)]);
+        b.code_mut().debug_files = Some(vec![Str::from("Main.hx")]);
+        let code = b.build().unwrap();
+        let f = &code.functions[0];
+        assert_eq!(f.line(&code, 1), Some((Str::from("Main.hx"), 4)));
+        assert_eq!(f.line_map(&code).len(), 2);
+        let opts = DecompilerOptions {
+            line_comments: true,
+            passes: Vec::new(),
+            ..DecompilerOptions::default()
+        };
+        let fopts = FormatOptions::new(2).with_var_types(false);
+        let stmts: Vec<String> = decompile_code_with(&code, f, &opts)
+            .iter()
+            .map(|s| s.display(&fopts, &code, f).to_string())
+            .collect();
+        assert_eq!(
+            stmts,
+            ["// Main.hx:3", "var x = 1;", "// Main.hx:4", "return x;"]
+        );
+    }
+
+    #[test]
+    fn void_calls() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        let int = b.ty(Type::I32);
+        let dyn_ = b.ty(Type::Dyn);
+        let one = b.int(1);
+        let log = b.native("std", "log", &[int], void);
+        b.function(
+            "",
+            &[dyn_],
+            int,
+            &[int, int, void],
+            vec![
+                Opcode::Int {
+                    dst: Reg(1),
+                    ptr: one,
+                },
+                // Native returning nothing
+                Opcode::Call1 {
+                    dst: Reg(3),
+                    fun: log,
+                    arg0: Reg(1),
+                },
+                // Dynamic closure, only the destination register says it returns nothing
+                Opcode::CallClosure {
+                    dst: Reg(3),
+                    fun: Reg(0),
+                    args: vec![Reg(1)],
+                },
+                // Result never read
+                Opcode::CallClosure {
+                    dst: Reg(2),
+                    fun: Reg(0),
+                    args: vec![Reg(1)],
+                },
+                Opcode::CallClosure {
+                    dst: Reg(2),
+                    fun: Reg(0),
+                    args: vec![],
+                },
+                Opcode::Ret { ret: Reg(2) },
+            ],
+        );
+        let code = b.build().unwrap();
+        let f = &code.functions[0];
+        let opts = FormatOptions::new(2);
+        let stmts: Vec<String> = decompile_code(&code, f)
+            .iter()
+            .filter(|s| s.is_code())
+            .map(|s| s.display(&opts, &code, f).to_string())
+            .collect();
+        assert_eq!(stmts, ["log(1);", "arg0(1);", "arg0(1);", "return arg0();"]);
+    }
+
+    #[test]
+    fn decoded_strings() {
+        let mut b = Builder::new();
+        let dyn_ = b.ty(Type::Dyn);
+        let decode = b.native("std", "decode", &[dyn_], dyn_);
+        let olleh = b.string("olleh");
+        let fun = b.function(
+            "",
+            &[dyn_],
+            dyn_,
+            &[dyn_, dyn_],
+            vec![
+                Opcode::String {
+                    dst: Reg(1),
+                    ptr: olleh,
+                },
+                Opcode::Call1 {
+                    dst: Reg(2),
+                    fun: decode,
+                    arg0: Reg(1),
+                },
+                Opcode::Ret { ret: Reg(2) },
+            ],
+        );
+        let code = b.build().unwrap();
+        let f = fun.as_fn(&code).unwrap();
+        let mut opts = DecompilerOptions::default();
+        opts.string_decoders.register(decode, |args| {
+            Some(args.first()?.as_str()?.chars().rev().collect())
+        });
+        let fopts = FormatOptions::new(2);
+        let stmts: Vec<String> = decompile_code_with(&code, f, &opts)
+            .iter()
+            .filter(|s| s.is_code())
+            .map(|s| s.display(&fopts, &code, f).to_string())
+            .collect();
+        assert_eq!(stmts, ["return \"hello\";"]);
+    }
+
+    #[test]
+    fn repeated_reads() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let dyn_ = b.ty(Type::Dyn);
+        b.function(
+            "",
+            &[dyn_],
+            int,
+            &[int, int],
+            vec![
+                // The call result is read twice
+                Opcode::CallClosure {
+                    dst: Reg(1),
+                    fun: Reg(0),
+                    args: vec![],
+                },
+                Opcode::Add {
+                    dst: Reg(2),
+                    a: Reg(1),
+                    b: Reg(1),
+                },
+                Opcode::Ret { ret: Reg(2) },
+            ],
+        );
+        let code = b.build().unwrap();
+        let f = &code.functions[0];
+        let opts = FormatOptions::new(2);
+        let stmts: Vec<String> = decompile_code(&code, f)
+            .iter()
+            .filter(|s| s.is_code())
+            .map(|s| s.display(&opts, &code, f).to_string())
+            .collect();
+        assert_eq!(stmts, ["var reg1: Int = arg0();", "return reg1 + reg1;"]);
+    }
+
+    #[test]
+    fn intervening_effects() {
+        let decompile = |ops| {
+            let mut b = Builder::new();
+            let int = b.ty(Type::I32);
+            let dyn_ = b.ty(Type::Dyn);
+            b.function("", &[dyn_], int, &[int, int, int], ops);
+            let code = b.build().unwrap();
+            let f = &code.functions[0];
+            let opts = FormatOptions::new(2).with_var_types(false);
+            decompile_code(&code, f)
+                .iter()
+                .filter(|s| s.is_code())
+                .map(|s| s.display(&opts, &code, f).to_string())
+                .collect::<Vec<_>>()
+        };
+        let call = |dst| Opcode::CallClosure {
+            dst: Reg(dst),
+            fun: Reg(0),
+            args: vec![],
+        };
+        let add = Opcode::Add {
+            dst: Reg(3),
+            a: Reg(1),
+            b: Reg(2),
+        };
+        let ret = |ret| Opcode::Ret { ret: Reg(ret) };
+        // The first call happens before the second one
+        assert_eq!(
+            decompile(vec![call(1), call(2), add.clone(), ret(3)]),
+            ["return arg0() + arg0();"]
+        );
+        // The operands are read in the other order
+        assert_eq!(
+            decompile(vec![call(2), call(1), add, ret(3)]),
+            ["var reg2 = arg0();", "return arg0() + reg2;"]
+        );
+        // Another call is a statement in between
+        assert_eq!(
+            decompile(vec![call(1), call(3), ret(1)]),
+            ["var reg1 = arg0();", "arg0();", "return reg1;"]
+        );
+    }
+
+    #[test]
+    fn closure_environment() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let env = b.ty(Type::Enum {
+            name: RefString(0),
+            global: RefGlobal(0),
+            constructs: vec![EnumConstruct {
+                name: RefString(0),
+                params: vec![int],
+            }],
+        });
+        let now = b.native("std", "now", &[], int);
+        let inner = b.function(
+            "",
+            &[env, int],
+            int,
+            &[int, int],
+            vec![
+                Opcode::EnumField {
+                    dst: Reg(2),
+                    value: Reg(0),
+                    construct: RefEnumConstruct(0),
+                    field: RefField(0),
+                },
+                Opcode::Add {
+                    dst: Reg(3),
+                    a: Reg(2),
+                    b: Reg(1),
+                },
+                Opcode::Ret { ret: Reg(3) },
+            ],
+        );
+        let closure_t = b.fun_type(&[int], int);
+        let fun = b.function(
+            "",
+            &[],
+            closure_t,
+            &[int, env, closure_t],
+            vec![
+                Opcode::Call0 {
+                    dst: Reg(0),
+                    fun: now,
+                },
+                Opcode::EnumAlloc {
+                    dst: Reg(1),
+                    construct: RefEnumConstruct(0),
+                },
+                Opcode::SetEnumField {
+                    value: Reg(1),
+                    field: RefField(0),
+                    src: Reg(0),
+                },
+                Opcode::InstanceClosure {
+                    dst: Reg(2),
+                    fun: inner,
+                    obj: Reg(1),
+                },
+                Opcode::Ret { ret: Reg(2) },
+            ],
+        );
+        let code = b.build().unwrap();
+        let f = fun.as_fn(&code).unwrap();
+        let opts = FormatOptions::new(2).with_var_types(false);
+        let stmts: Vec<_> = decompile_code(&code, f)
+            .iter()
+            .filter(|s| s.is_code())
+            .map(|s| s.display(&opts, &code, f).to_string())
+            .collect();
+        // The call is made once, when creating the closure
+        assert_eq!(
+            stmts,
+            ["var reg0 = now();", "return (arg1: Int) -> reg0 + arg1;"]
+        );
+    }
+
+    #[test]
+    fn side_effects() {
+        let x = || Expr::Variable(Reg(0), None);
+        let pure = Expr::Op(Operation::Add(
+            Box::new(Expr::Field(Box::new(x()), Str::from("a"))),
+            Box::new(cst_int(RefInt(0))),
+        ));
+        assert!(!pure.has_side_effects());
+        assert!(call(x(), vec![]).has_side_effects());
+        assert!(Expr::Op(Operation::Not(Box::new(call(x(), vec![])))).has_side_effects());
+        assert!(Expr::Op(Operation::Incr(Box::new(x()))).has_side_effects());
+        assert!(Expr::ArrayLiteral(vec![]).has_side_effects());
+        assert!(!Expr::EnumConstr(RefType(0), RefEnumConstruct(0), vec![]).has_side_effects());
+    }
+
+    #[test]
+    fn constant_returns() {
+        let call = |fun| Opcode::Call0 { dst: Reg(0), fun };
+        let ret = Opcode::Ret { ret: Reg(0) };
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let five = b.int(5);
+        let value = b.function(
+            "getValue",
+            &[],
+            int,
+            &[int],
+            vec![
+                Opcode::Int {
+                    dst: Reg(0),
+                    ptr: five,
+                },
+                ret.clone(),
+            ],
+        );
+        // Getter calling a getter
+        let getter = b.function("getValue", &[], int, &[int], vec![call(value), ret.clone()]);
+        let fun = b.function("getValue", &[], int, &[int], vec![call(getter), ret]);
+        let code = b.build().unwrap();
+        let f = fun.as_fn(&code).unwrap();
+        let fopts = FormatOptions::new(2);
+        let decompile = |opts| -> Vec<String> {
+            decompile_code_with(&code, f, &opts)
+                .iter()
+                .filter(|s| s.is_code())
+                .map(|s| s.display(&fopts, &code, f).to_string())
+                .collect()
+        };
+        assert_ne!(decompile(DecompilerOptions::default()), ["return 5;"]);
+        let stmts = decompile(DecompilerOptions {
+            constant_returns: true,
+            ..DecompilerOptions::default()
+        });
+        assert_eq!(stmts, ["return 5;"]);
+    }
+
+    #[test]
+    fn global_constants() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let float = b.ty(Type::F64);
+        let bytes = b.ty(Type::Bytes);
+        let string = b.class("String", None, &[("bytes", bytes), ("length", int)]);
+        let point = b.class("Point", None, &[("x", int), ("y", float)]);
+        b.int(5);
+        b.float(1.5);
+        let hello = b.string("hello");
+        let funs = [string, point].map(|t| {
+            let global = b.global(t);
+            let fun = b.function(
+                "",
+                &[],
+                t,
+                &[t],
+                vec![
+                    Opcode::GetGlobal {
+                        dst: Reg(0),
+                        global,
+                    },
+                    Opcode::Ret { ret: Reg(0) },
+                ],
+            );
+            (global, fun)
+        });
+        // Indexes in the constant pools
+        b.code_mut().constants = Some(vec![
+            ConstantDef {
+                global: funs[0].0,
+                fields: vec![hello.0, 0],
+            },
+            ConstantDef {
+                global: funs[1].0,
+                fields: vec![0, 0],
+            },
+        ]);
+        let code = b.build().unwrap();
+        let fopts = FormatOptions::new(2);
+        let decompile = |f: RefFun| -> Vec<String> {
+            let f = f.as_fn(&code).unwrap();
+            decompile_code(&code, f)
+                .iter()
+                .filter(|s| s.is_code())
+                .map(|s| s.display(&fopts, &code, f).to_string())
+                .collect()
+        };
+        assert_eq!(decompile(funs[0].1), ["return \"hello\";"]);
+        assert_eq!(decompile(funs[1].1), ["return new Point(5, 1.5);"]);
+    }
+
+    #[test]
+    fn property_access() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        let int = b.ty(Type::I32);
+        let player = b.class("Player", None, &[("hp_", int)]);
+        let get = b.function(
+            "",
+            &[player],
+            int,
+            &[int, int, void],
+            vec![
+                Opcode::GetThis {
+                    dst: Reg(1),
+                    field: RefField(0),
+                },
+                Opcode::Ret { ret: Reg(1) },
+            ],
+        );
+        let set = b.function(
+            "",
+            &[player, int],
+            int,
+            &[int, void],
+            vec![
+                Opcode::SetThis {
+                    field: RefField(0),
+                    src: Reg(1),
+                },
+                Opcode::Ret { ret: Reg(1) },
+            ],
+        );
+        // this.set_hp(this.get_hp())
+        let update = b.function(
+            "",
+            &[player],
+            void,
+            &[int, int, void],
+            vec![
+                Opcode::CallThis {
+                    dst: Reg(1),
+                    field: RefField(0),
+                    args: vec![],
+                },
+                Opcode::CallThis {
+                    dst: Reg(2),
+                    field: RefField(1),
+                    args: vec![Reg(1)],
+                },
+                Opcode::Ret { ret: Reg(3) },
+            ],
+        );
+        b.method(player, "get_hp", get);
+        b.method(player, "set_hp", set);
+        b.method(player, "update", update);
+        let code = b.build().unwrap();
+        let f = update.as_fn(&code).unwrap();
+        let fopts = FormatOptions::new(2);
+        let decompile = |opts| -> Vec<String> {
+            decompile_code_with(&code, f, &opts)
+                .iter()
+                .filter(|s| s.is_code())
+                .map(|s| s.display(&fopts, &code, f).to_string())
+                .collect()
+        };
+        assert_eq!(
+            decompile(DecompilerOptions::default()),
+            ["this.set_hp(this.get_hp());"]
+        );
+        let stmts = decompile(DecompilerOptions {
+            property_access: true,
+            ..DecompilerOptions::default()
+        });
+        assert_eq!(stmts, ["this.hp = this.hp;"]);
+    }
+
+    #[test]
+    fn static_initial_values() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        let int = b.ty(Type::I32);
+        let main = b.class("Main", None, &[]);
+        let statics = b.class("$Main", None, &[("count", int)]);
+        let global = b.global(statics);
+        // 0 is no global
+        b.code_mut().types[main.0]
+            .get_type_obj_mut()
+            .unwrap()
+            .global = RefGlobal(global.0 + 1);
+        let five = b.int(5);
+        // Entrypoint : $Main.count = 5
+        b.function(
+            "",
+            &[],
+            void,
+            &[statics, int, void],
+            vec![
+                Opcode::GetGlobal {
+                    dst: Reg(0),
+                    global,
+                },
+                Opcode::Int {
+                    dst: Reg(1),
+                    ptr: five,
+                },
+                Opcode::SetField {
+                    obj: Reg(0),
+                    field: RefField(0),
+                    src: Reg(1),
+                },
+                Opcode::Ret { ret: Reg(2) },
+            ],
+        );
+        let code = b.build().unwrap();
+        let main = main.as_obj(&code).unwrap();
+        let class = decompile_class(&code, main);
+        let opts = FormatOptions::new(2);
+        assert_eq!(
+            class.display(&code, &opts).to_string(),
+            "class Main {\n  static var count: Int = 5;\n}"
+        );
+        // Same values when they are found once for every class
+        let with_values = DecompilerOptions::default().with_static_values(&code);
+        assert_eq!(
+            decompile_class_with(&code, main, &with_values)
+                .display(&code, &opts)
+                .to_string(),
+            class.display(&code, &opts).to_string()
+        );
+        assert_eq!(
+            class.display_extern(&code, &opts).to_string(),
+            "extern class Main {\n  static var count: Int;\n}"
+        );
+    }
+
+    #[test]
+    fn override_methods() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        // class Child extends Base, both have an update method
+        let base = b.class("Base", None, &[]);
+        let child = b.class("Child", Some(base), &[]);
+        for (class, name) in [(base, "update"), (child, "update"), (child, "draw")] {
+            let method = b.function("", &[class], void, &[], vec![Opcode::Ret { ret: Reg(0) }]);
+            b.method(class, name, method);
+        }
+        let code = b.build().unwrap();
+        let class = decompile_class(&code, child.as_obj(&code).unwrap());
+        let opts = FormatOptions::new(2);
+        let signatures: Vec<String> = class
+            .methods
+            .iter()
+            .map(|m| m.display_signature(&code, &opts).to_string())
+            .collect();
+        assert_eq!(
+            signatures,
+            ["override function update()", "function draw()"]
+        );
+        assert!(!decompile_class(&code, base.as_obj(&code).unwrap()).methods[0].override_);
+    }
+
+    #[test]
+    fn array_literal() {
+        let int = |dst, ptr| Opcode::Int { dst: Reg(dst), ptr };
+        let mut b = Builder::new();
+        let i32 = b.ty(Type::I32);
+        let array = b.ty(Type::Array);
+        let ty = b.ty(Type::Type);
+        let alloc_array = b.native("std", "alloc_array", &[ty, i32], array);
+        let array_obj = b.class("hl.types.ArrayObj", None, &[]);
+        let alloc = b.function(
+            "",
+            &[array],
+            array_obj,
+            &[],
+            vec![Opcode::Ret { ret: Reg(0) }],
+        );
+        b.method(array_obj, "alloc", alloc);
+        let [two, zero, seven, one] = [2, 0, 7, 1].map(|i| b.int(i));
+        // Fill an array of 2 elements with 7 at `first` and 2 at `second`
+        let mut fill = |first, second| {
+            b.function(
+                "",
+                &[],
+                array_obj,
+                &[ty, i32, array, i32, i32, array_obj],
+                vec![
+                    Opcode::Type {
+                        dst: Reg(0),
+                        ty: i32,
+                    },
+                    int(1, two),
+                    Opcode::Call2 {
+                        dst: Reg(2),
+                        fun: alloc_array,
+                        arg0: Reg(0),
+                        arg1: Reg(1),
+                    },
+                    int(3, first),
+                    int(4, seven),
+                    Opcode::SetArray {
+                        array: Reg(2),
+                        index: Reg(3),
+                        src: Reg(4),
+                    },
+                    int(3, second),
+                    int(4, two),
+                    Opcode::SetArray {
+                        array: Reg(2),
+                        index: Reg(3),
+                        src: Reg(4),
+                    },
+                    Opcode::Call1 {
+                        dst: Reg(5),
+                        fun: alloc,
+                        arg0: Reg(2),
+                    },
+                    Opcode::Ret { ret: Reg(5) },
+                ],
+            )
+        };
+        let in_order = fill(zero, one);
+        let reversed = fill(one, zero);
+        let twice = fill(zero, zero);
+        let code = b.build().unwrap();
+        let decompile = |fun: RefFun| {
+            let f = fun.as_fn(&code).unwrap();
+            decompile_code(&code, f)
+                .iter()
+                .map(|s| s.display(&FormatOptions::new(2), &code, f).to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(decompile(in_order), ["return [7, 2];"]);
+        // The order of the elements isn't the order of the writes
+        let stmts = decompile(reversed);
+        assert_eq!(stmts[2..4], ["reg2[1] = 7;", "reg2[0] = 2;"]);
+        // The second element is never written
+        let stmts = decompile(twice);
+        assert_eq!(stmts[2..4], ["reg2[0] = 7;", "reg2[0] = 2;"]);
+    }
+
+    #[test]
+    fn trace() {
+        let string = |dst, ptr| Opcode::String { dst: Reg(dst), ptr };
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        let dyn_ = b.ty(Type::Dyn);
+        // Position infos
+        let file_name = b.string("fileName");
+        let pos = b.ty(Type::Virtual {
+            fields: vec![ObjField {
+                name: file_name,
+                t: dyn_,
+            }],
+        });
+        let trace_t = b.fun_type(&[dyn_, pos], void);
+        let log = b.class("$haxe.Log", None, &[("trace", trace_t)]);
+        let global = b.global(log);
+        let trace = b.function(
+            "trace",
+            &[dyn_, pos],
+            void,
+            &[void],
+            vec![Opcode::Ret { ret: Reg(2) }],
+        );
+        // Static dynamic function, initialized with the method
+        b.bind(log, RefField(0), trace);
+        let hello = b.string("Hello");
+        let main_hx = b.string("Main.hx");
+        // haxe.Log.trace("Hello", {fileName: "Main.hx"})
+        let fun = b.function(
+            "",
+            &[],
+            void,
+            &[log, trace_t, dyn_, pos, dyn_, void],
+            vec![
+                Opcode::GetGlobal {
+                    dst: Reg(0),
+                    global,
+                },
+                Opcode::Field {
+                    dst: Reg(1),
+                    obj: Reg(0),
+                    field: RefField(0),
+                },
+                string(2, hello),
+                Opcode::New { dst: Reg(3) },
+                string(4, main_hx),
+                Opcode::SetField {
+                    obj: Reg(3),
+                    field: RefField(0),
+                    src: Reg(4),
+                },
+                Opcode::CallClosure {
+                    dst: Reg(5),
+                    fun: Reg(1),
+                    args: vec![Reg(2), Reg(3)],
+                },
+                Opcode::Ret { ret: Reg(5) },
+            ],
+        );
+        let code = b.build().unwrap();
+        let f = fun.as_fn(&code).unwrap();
+        let stmts: Vec<String> = decompile_code(&code, f)
+            .iter()
+            .filter(|s| s.is_code())
+            .map(|s| s.display(&FormatOptions::new(2), &code, f).to_string())
+            .collect();
+        assert_eq!(stmts[0], "trace(\"Hello\");");
+    }
+
+    #[test]
+    fn std_calls() {
+        let mut b = Builder::new();
+        let dyn_ = b.ty(Type::Dyn);
+        let string_t = b.fun_type(&[dyn_], dyn_);
+        let std = b.class("$Std", None, &[("string", string_t)]);
+        b.global(std);
+        let string = b.function(
+            "string",
+            &[dyn_],
+            dyn_,
+            &[],
+            vec![Opcode::Ret { ret: Reg(0) }],
+        );
+        // Static function
+        b.bind(std, RefField(0), string);
+        // return Std.string(arg0)
+        let fun = b.function(
+            "",
+            &[dyn_],
+            dyn_,
+            &[dyn_],
+            vec![
+                Opcode::Call1 {
+                    dst: Reg(1),
+                    fun: string,
+                    arg0: Reg(0),
+                },
+                Opcode::Ret { ret: Reg(1) },
+            ],
+        );
+        let code = b.build().unwrap();
+        let f = fun.as_fn(&code).unwrap();
+        let fopts = FormatOptions::new(2);
+        let decompile = |opts| -> Vec<String> {
+            decompile_code_with(&code, f, &opts)
+                .iter()
+                .filter(|s| s.is_code())
+                .map(|s| s.display(&fopts, &code, f).to_string())
+                .collect()
+        };
+        assert_eq!(
+            decompile(DecompilerOptions::default()),
+            ["return Std.string(arg0);"]
+        );
+        assert_eq!(
+            decompile(DecompilerOptions::default().with_pass(Pass::StdCalls, false)),
+            ["return string(arg0);"]
+        );
+    }
+
+    #[test]
+    fn typed_declarations() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let dyn_ = b.ty(Type::Dyn);
+        let assigns = vec![(b.string("d"), 0), (b.string("x"), 1)];
+        let fun = b.function(
+            "",
+            &[dyn_],
+            int,
+            &[int],
+            vec![
+                Opcode::SafeCast {
+                    dst: Reg(1),
+                    src: Reg(0),
+                },
+                Opcode::Ret { ret: Reg(1) },
+            ],
+        );
+        b.function_mut(fun).assigns = Some(assigns);
+        let code = b.build().unwrap();
+        let f = &code.functions[0];
+        let stmts = decompile_code(&code, f);
+        let opts = FormatOptions::new(2);
+        assert_eq!(
+            stmts[0].display(&opts, &code, f).to_string(),
+            "var x: Int = cast(d, Int);"
+        );
+        let opts = opts.with_var_types(false);
+        assert_eq!(
+            stmts[0].display(&opts, &code, f).to_string(),
+            "var x = cast(d, Int);"
+        );
+    }
+
+    #[test]
+    fn copies() {
+        let decompile = |ops| {
+            let mut b = Builder::new();
+            let int = b.ty(Type::I32);
+            let assigns = vec![(b.string("x"), 0), (b.string("y"), 1)];
+            let fun = b.function("", &[int], int, &[int], ops);
+            b.function_mut(fun).assigns = Some(assigns);
+            let code = b.build().unwrap();
+            let f = &code.functions[0];
+            let opts = FormatOptions::new(2).with_var_types(false);
+            decompile_code(&code, f)
+                .iter()
+                .map(|s| s.display(&opts, &code, f).to_string())
+                .collect::<Vec<_>>()
+        };
+        let copy = Opcode::Mov {
+            dst: Reg(1),
+            src: Reg(0),
+        };
+        let ret = Opcode::Ret { ret: Reg(0) };
+        // var y = x; return x; is compiled reading the copy
+        assert_eq!(
+            decompile(vec![copy.clone(), ret.clone()]),
+            ["var y = x;", "return y;"]
+        );
+        // The copy is modified, x is still x
+        assert_eq!(
+            decompile(vec![copy, Opcode::Incr { dst: Reg(1) }, ret]),
+            ["var y = x;", "y++;", "return x;"]
+        );
+    }
+
+    #[test]
+    fn casts() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let float = b.ty(Type::F64);
+        let dyn_ = b.ty(Type::Dyn);
+        let assigns = vec![(b.string("d"), 0), (b.string("x"), 1), (b.string("y"), 2)];
+        let fun = b.function(
+            "",
+            &[float],
+            dyn_,
+            &[int, dyn_],
+            vec![
+                Opcode::ToInt {
+                    dst: Reg(1),
+                    src: Reg(0),
+                },
+                Opcode::ToDyn {
+                    dst: Reg(2),
+                    src: Reg(1),
+                },
+                Opcode::Ret { ret: Reg(2) },
+            ],
+        );
+        b.function_mut(fun).assigns = Some(assigns);
+        let code = b.build().unwrap();
+        let f = &code.functions[0];
+        let fmt = FormatOptions::new(2);
+        let stmts = decompile_code(&code, f);
+        assert_eq!(
+            stmts[0].display(&fmt, &code, f).to_string(),
+            "var x: Int = Std.int(d);"
+        );
+        assert_eq!(
+            stmts[1].display(&fmt, &code, f).to_string(),
+            "var y: Dynamic = x;"
+        );
+
+        let opts = DecompilerOptions {
+            implicit_casts: true,
+            ..DecompilerOptions::default()
+        };
+        let stmts = decompile_code_with(&code, f, &opts);
+        assert_eq!(
+            stmts[1].display(&fmt, &code, f).to_string(),
+            "var y: Dynamic = (x : Dynamic);"
+        );
+    }
+
+    #[test]
+    fn decomp_code_all_no_passes() {
+        let mut opts = DecompilerOptions::default();
+        for pass in Pass::ALL {
+            opts = opts.with_pass(pass, false);
+        }
+        assert!(opts.passes.is_empty());
+        for entry in fs::read_dir("../../data").unwrap() {
+            let path = entry.unwrap().path();
+            if let Some(ext) = path.extension() {
+                if ext == "hl" {
+                    let code = Bytecode::from_file(&path).unwrap();
+                    for f in &code.functions {
+                        black_box(decompile_code_with(&code, f, &opts));
+                    }
+                }
+            }
+        }
+    }
+
     #[test]
     fn decomp_fn_all() {
         for entry in fs::read_dir("../../data").unwrap() {
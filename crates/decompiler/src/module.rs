@@ -0,0 +1,457 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{fs, io};
+
+use hlbc::types::{RefType, Type, TypeObj};
+use hlbc::{Bytecode, Resolve, Str};
+
+use crate::ast::{Class, Expr, Module, Typedef};
+use crate::fmt::FormatOptions;
+use crate::post::{visit, AstVisitor};
+use crate::{
+    decompile_class_outline, decompile_class_with, decompile_enum, DecompilerOptions, TypedefNames,
+};
+
+/// Split a fully qualified type name in its package and its short name
+pub(crate) fn split_path(name: &str) -> (&str, &str) {
+    name.rsplit_once('.').unwrap_or(("", name))
+}
+
+/// Fully qualified name of a class or enum, static companion types resolve to their class
+fn type_path(code: &Bytecode, ty: RefType) -> Option<Str> {
+    let name = match code.try_get(ty).ok()? {
+        Type::Obj(obj) => obj.name(code),
+        Type::Enum { name, .. } if !name.is_null() => code.get(*name),
+        _ => return None,
+    };
+    Some(match name.strip_prefix('$') {
+        Some(stripped) => Str::from(stripped),
+        None => name,
+    })
+}
+
+/// The module of a package, created empty on first use
+fn module<'m>(modules: &'m mut BTreeMap<Str, Module>, package: &str) -> &'m mut Module {
+    modules.entry(Str::from(package)).or_insert_with(|| Module {
+        package: Str::from(package),
+        imports: Vec::new(),
+        classes: Vec::new(),
+        enums: Vec::new(),
+        typedefs: Vec::new(),
+    })
+}
+
+/// Collect the types instantiated in method bodies
+struct TypeRefs<'a>(&'a mut HashSet<RefType>);
+
+impl AstVisitor for TypeRefs<'_> {
+    fn visit_expr(&mut self, _code: &Bytecode, expr: &mut Expr) {
+        match expr {
+            Expr::Constructor(call) => {
+                self.0.insert(call.ty);
+            }
+            Expr::EnumConstr(ty, _, _) => {
+                self.0.insert(*ty);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Types a class depends on : its parent, its fields, its method signatures and what its methods instantiate
+fn class_deps(code: &Bytecode, parent: Option<RefType>, class: &mut Class) -> HashSet<RefType> {
+    let mut deps = HashSet::new();
+    deps.extend(parent);
+    deps.extend(class.fields.iter().map(|f| f.ty));
+    for m in &mut class.methods {
+        if let Ok(fun) = m.fun.try_as_fn(code) {
+            deps.extend(fun.args(code));
+            deps.insert(fun.ty(code).ret);
+        }
+        visit(code, &mut m.statements, &mut [&mut TypeRefs(&mut deps)]);
+    }
+    deps
+}
+
+/// Decompile every class, enum and anonymous structure of the bytecode, grouped by package.
+///
+/// Static members (`$Class` types) are merged in their class. Anonymous structures have no name in the bytecode,
+/// identical ones are declared once as a typedef in the top level package, named by [TypedefNames::infer].
+pub fn decompile_modules(code: &Bytecode) -> Vec<Module> {
+    let typedefs = TypedefNames::infer(code, TypedefNames::DEFAULT_BUDGET);
+    decompile_modules_with(code, &DecompilerOptions::default(), &typedefs)
+}
+
+/// Same as [decompile_modules] but with custom options and typedef names.
+/// Display the modules with the same names (see [FormatOptions::with_typedefs]) so the structure types refer to them.
+pub fn decompile_modules_with(
+    code: &Bytecode,
+    opts: &DecompilerOptions,
+    typedefs: &TypedefNames,
+) -> Vec<Module> {
+    let opts = opts.clone().with_static_values(code);
+    group_modules(
+        code,
+        typedefs,
+        |_| true,
+        |obj| decompile_class_with(code, obj, &opts),
+    )
+}
+
+/// The declarations of the selected packages without any method body, see [write_extern_stubs].
+///
+/// A package is selected if it is in `packages` or is a subpackage of one of them, all packages are selected if
+/// `packages` is empty.
+pub fn extern_modules(code: &Bytecode, packages: &[&str]) -> Vec<Module> {
+    let typedefs = TypedefNames::infer(code, TypedefNames::DEFAULT_BUDGET);
+    extern_modules_with(code, packages, &typedefs)
+}
+
+fn extern_modules_with(code: &Bytecode, packages: &[&str], typedefs: &TypedefNames) -> Vec<Module> {
+    let selected = |package: &str| {
+        packages.is_empty()
+            || packages.iter().any(|p| {
+                package == *p || (package.starts_with(p) && package[p.len()..].starts_with('.'))
+            })
+    };
+    let mut modules = group_modules(
+        code,
+        typedefs,
+        |_| true,
+        |obj| decompile_class_outline(code, obj),
+    );
+    modules.retain(|m| selected(&m.package));
+    modules
+}
+
+/// Group the declarations by package, classes are built with `decompile`.
+/// Only the classes and enums whose fully qualified name is selected by `filter` are declared.
+fn group_modules(
+    code: &Bytecode,
+    typedefs: &TypedefNames,
+    filter: impl Fn(&str) -> bool,
+    mut decompile: impl FnMut(&TypeObj) -> Class,
+) -> Vec<Module> {
+    let mut modules: BTreeMap<Str, Module> = BTreeMap::new();
+    let mut deps: BTreeMap<Str, HashSet<RefType>> = BTreeMap::new();
+
+    for ty in &code.types {
+        match ty {
+            Type::Obj(obj) => {
+                let name = obj.name(code);
+                // Static members are declared with their class
+                if name.starts_with('$') || !filter(&name) {
+                    continue;
+                }
+                let (package, short) = split_path(&name);
+                let mut class = decompile(obj);
+                class.name = Str::from(short);
+                deps.entry(Str::from(package))
+                    .or_default()
+                    .extend(class_deps(code, obj.super_, &mut class));
+                module(&mut modules, package).classes.push(class);
+            }
+            // Closure environments are unnamed enums
+            Type::Enum { name, .. } if !name.is_null() && filter(&code.get(*name)) => {
+                let Some(mut decl) = decompile_enum(code, ty) else {
+                    continue;
+                };
+                let name = code.get(*name);
+                let (package, short) = split_path(&name);
+                decl.name = Str::from(short);
+                deps.entry(Str::from(package))
+                    .or_default()
+                    .extend(decl.variants.iter().flat_map(|v| v.params.iter().copied()));
+                module(&mut modules, package).enums.push(decl);
+            }
+            _ => {}
+        }
+    }
+
+    for (name, ty) in typedefs.typedefs(code) {
+        let Type::Virtual { fields } = &code[ty] else {
+            continue;
+        };
+        let fields = fields
+            .iter()
+            .map(|f| (f.name(code), f.t))
+            .collect::<Vec<_>>();
+        deps.entry(Str::from(""))
+            .or_default()
+            .extend(fields.iter().map(|&(_, t)| t));
+        module(&mut modules, "")
+            .typedefs
+            .push(Typedef { name, fields });
+    }
+
+    for (package, deps) in deps {
+        let imports: BTreeSet<Str> = deps
+            .into_iter()
+            .filter_map(|ty| type_path(code, ty))
+            // Types of the top level package and of the same package are always visible
+            .filter(|path| {
+                let (pkg, _) = split_path(path);
+                !pkg.is_empty() && pkg != &*package
+            })
+            .collect();
+        if let Some(module) = modules.get_mut(&package) {
+            module.imports = imports.into_iter().collect();
+        }
+    }
+    modules.into_values().collect()
+}
+
+/// Path of the file of a declaration in `dir` like `pack/age/Name.ext`.
+///
+/// Names come from the bytecode and can't be trusted : path separators and drive prefixes are replaced, so a name like
+/// `../../etc` or `/etc` still ends up inside `dir`.
+pub fn source_file_path(dir: &Path, package: &str, name: &str, ext: &str) -> PathBuf {
+    let part = |p: &str| p.replace(['/', '\\', ':', '\0'], "_");
+    let mut path = package
+        .split('.')
+        .map(part)
+        .filter(|p| !p.is_empty())
+        .fold(dir.to_path_buf(), |dir, p| dir.join(p));
+    path.push(format!("{}.{ext}", part(name)));
+    path
+}
+
+/// Write one file per declaration in `dir` like `pack/age/Name.hx`, starting with the package declaration and the
+/// imports of its package. Returns the number of files written.
+fn write_modules(
+    code: &Bytecode,
+    dir: &Path,
+    modules: &[Module],
+    fmt: &FormatOptions,
+    extern_: bool,
+) -> io::Result<usize> {
+    let prefix = if extern_ { "extern " } else { "" };
+    let mut count = 0;
+    for module in modules {
+        let mut write = |name: &str, decl: &dyn Display| {
+            count += 1;
+            let path = source_file_path(dir, &module.package, name, "hx");
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, format!("{}{decl}\n", module.display_header()))
+        };
+        for t in &module.typedefs {
+            write(&t.name, &t.display(code, fmt))?;
+        }
+        for e in &module.enums {
+            write(&e.name, &fmtools::fmt!({prefix}{e.display(code, fmt)}))?;
+        }
+        for c in &module.classes {
+            if extern_ {
+                write(&c.name, &c.display_extern(code, fmt))?;
+            } else {
+                write(&c.name, &c.display(code, fmt))?;
+            }
+        }
+    }
+    Ok(count)
+}
+
+/// Decompile the whole bytecode to a tree of Haxe source files in `dir`, one file per type like `pack/age/Name.hx`.
+///
+/// Each file starts with the package declaration and the imports of its package.
+/// Returns the number of files written.
+pub fn write_source_tree(
+    code: &Bytecode,
+    dir: &Path,
+    opts: &DecompilerOptions,
+    fmt: &FormatOptions,
+) -> io::Result<usize> {
+    write_source_tree_matching(code, dir, opts, fmt, |_| true)
+}
+
+/// Same as [write_source_tree], but only for the classes and enums whose fully qualified name is selected by `filter`.
+/// Typedefs of anonymous structures are always written.
+pub fn write_source_tree_matching(
+    code: &Bytecode,
+    dir: &Path,
+    opts: &DecompilerOptions,
+    fmt: &FormatOptions,
+    filter: impl Fn(&str) -> bool,
+) -> io::Result<usize> {
+    let fmt = with_typedefs(code, fmt);
+    let typedefs = fmt.typedefs().unwrap();
+    let opts = opts.clone().with_static_values(code);
+    let modules = group_modules(code, typedefs, filter, |obj| {
+        decompile_class_with(code, obj, &opts)
+    });
+    write_modules(code, dir, &modules, &fmt, false)
+}
+
+/// Write `extern` declarations of the classes and enums of the selected packages (see [extern_modules]) in `dir`,
+/// with the same layout as [write_source_tree].
+///
+/// New Haxe code compiled against those stubs is type checked against the API of the original program, without
+/// including its implementation. Types used in signatures must be in the selected packages to be found.
+pub fn write_extern_stubs(
+    code: &Bytecode,
+    dir: &Path,
+    packages: &[&str],
+    fmt: &FormatOptions,
+) -> io::Result<usize> {
+    let fmt = with_typedefs(code, fmt);
+    let modules = extern_modules_with(code, packages, fmt.typedefs().unwrap());
+    write_modules(code, dir, &modules, &fmt, true)
+}
+
+/// The format options with the typedef names used in every file, inferred if they aren't set
+pub(crate) fn with_typedefs(code: &Bytecode, fmt: &FormatOptions) -> FormatOptions {
+    match fmt.typedefs() {
+        Some(_) => fmt.clone(),
+        None => fmt.clone().with_typedefs(Arc::new(TypedefNames::infer(
+            code,
+            TypedefNames::DEFAULT_BUDGET,
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use hlbc::builder::Builder;
+    use hlbc::types::{EnumConstruct, ObjField, RefGlobal, Type};
+    use hlbc::Bytecode;
+    use temp_dir::TempDir;
+
+    use crate::fmt::FormatOptions;
+    use crate::{
+        decompile_enum, decompile_modules, extern_modules, source_file_path, write_source_tree,
+        write_source_tree_matching, DecompilerOptions,
+    };
+
+    fn code() -> Bytecode {
+        let mut b = Builder::new();
+        b.ty(Type::Void);
+        let int = b.ty(Type::I32);
+        let name = b.string("pkg.Color");
+        let constructs = vec![
+            EnumConstruct {
+                name: b.string("Rgb"),
+                params: vec![int; 3],
+            },
+            EnumConstruct {
+                name: b.string("Black"),
+                params: vec![],
+            },
+        ];
+        let color = b.ty(Type::Enum {
+            name,
+            global: RefGlobal(0),
+            constructs,
+        });
+        let field = b.string("color");
+        b.ty(Type::Virtual {
+            fields: vec![ObjField {
+                name: field,
+                t: color,
+            }],
+        });
+        b.class("pkg.Point", None, &[("x", int)]);
+        b.build().unwrap()
+    }
+
+    #[test]
+    fn enum_declaration() {
+        let code = code();
+        assert!(decompile_enum(&code, &code.types[1]).is_none());
+        let decl = decompile_enum(&code, &code.types[2]).unwrap();
+        assert_eq!(decl.name, "pkg.Color");
+        assert_eq!(
+            decl.display(&code, &FormatOptions::new(2)).to_string(),
+            "enum pkg.Color {\n  Rgb(_0: Int, _1: Int, _2: Int);\n  Black;\n}"
+        );
+    }
+
+    #[test]
+    fn modules() {
+        let code = code();
+        let modules = decompile_modules(&code);
+        let opts = FormatOptions::new(2);
+        assert_eq!(modules.len(), 2);
+        assert_eq!(
+            modules[0].display(&code, &opts).to_string(),
+            "package;\n\nimport pkg.Color;\n\ntypedef Anon3 = { color: pkg.Color };\n\n"
+        );
+        assert_eq!(
+            modules[1].display(&code, &opts).to_string(),
+            "package pkg;\n\nenum Color {\n  Rgb(_0: Int, _1: Int, _2: Int);\n  Black;\n}\n\nclass Point {\n  var x: Int;\n}\n\n"
+        );
+    }
+
+    #[test]
+    fn extern_stubs() {
+        let code = code();
+        let modules = extern_modules(&code, &["pkg"]);
+        assert_eq!(modules.len(), 1);
+        assert_eq!(
+            modules[0].classes[0]
+                .display_extern(&code, &FormatOptions::new(2))
+                .to_string(),
+            "extern class Point {\n  var x: Int;\n}"
+        );
+        assert!(extern_modules(&code, &["pk"]).is_empty());
+        assert_eq!(extern_modules(&code, &[]).len(), 2);
+    }
+
+    #[test]
+    fn source_tree() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path();
+        let count = write_source_tree(
+            &code(),
+            dir,
+            &DecompilerOptions::default(),
+            &FormatOptions::new(2),
+        )
+        .unwrap();
+        assert_eq!(count, 3);
+        assert!(dir.join("Anon3.hx").exists());
+        assert_eq!(
+            fs::read_to_string(dir.join("pkg").join("Color.hx")).unwrap(),
+            "package pkg;\n\nenum Color {\n  Rgb(_0: Int, _1: Int, _2: Int);\n  Black;\n}\n"
+        );
+    }
+
+    #[test]
+    fn source_tree_matching() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path();
+        let count = write_source_tree_matching(
+            &code(),
+            dir,
+            &DecompilerOptions::default(),
+            &FormatOptions::new(2),
+            |name| name == "pkg.Point",
+        )
+        .unwrap();
+        assert_eq!(count, 2);
+        assert!(dir.join("pkg").join("Point.hx").exists());
+        assert!(!dir.join("pkg").join("Color.hx").exists());
+    }
+
+    #[test]
+    fn file_paths() {
+        let dir = Path::new("out");
+        assert_eq!(
+            source_file_path(dir, "pack.age", "Name", "hx"),
+            dir.join("pack").join("age").join("Name.hx")
+        );
+        assert_eq!(source_file_path(dir, "", "Name", "hx"), dir.join("Name.hx"));
+        assert_eq!(
+            source_file_path(dir, "../../etc", "passwd", "hx"),
+            dir.join("_").join("_etc").join("passwd.hx")
+        );
+        assert_eq!(
+            source_file_path(dir, "/etc.C:", "..\\x", "hx"),
+            dir.join("_etc").join("C_").join(".._x.hx")
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use rayon::prelude::*;
+
+use hlbc::types::Type;
+use hlbc::Bytecode;
+
+use crate::ast::{Class, Method};
+use crate::{decompile_class_with, decompile_function_with, DecompilerOptions};
+
+/// Decompile every function of the bytecode on all cores, in bytecode order.
+///
+/// Functions are independent from each other, this scales with the number of cores on big games. See [Batch](crate::Batch)
+/// to decompile a few functions at a time on a single thread instead.
+pub fn decompile_all(code: &Bytecode, opts: &DecompilerOptions) -> Vec<Method> {
+    code.functions
+        .par_iter()
+        .map(|f| decompile_function_with(code, f, opts))
+        .collect()
+}
+
+/// Decompile every class of the bytecode on all cores, in bytecode order
+pub fn decompile_all_classes(code: &Bytecode, opts: &DecompilerOptions) -> Vec<Class> {
+    let opts = &opts.clone().with_static_values(code);
+    code.types
+        .par_iter()
+        .filter_map(|t| match t {
+            Type::Obj(obj) | Type::Struct(obj) => Some(decompile_class_with(code, obj, opts)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::Builder;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefFun, Reg, Type};
+
+    use crate::ast::Method;
+    use crate::fmt::FormatOptions;
+    use crate::{decompile_all, decompile_all_classes, Batch, DecompilerOptions};
+
+    #[test]
+    fn same_as_sequential() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let ints = [b.int(0), b.int(1)];
+        for i in 0..64 {
+            b.function(
+                "get",
+                &[],
+                int,
+                &[int],
+                vec![
+                    Opcode::Int {
+                        dst: Reg(0),
+                        ptr: ints[i % 2],
+                    },
+                    Opcode::Ret { ret: Reg(0) },
+                ],
+            );
+        }
+        let main = b.class("Main", None, &[]);
+        b.method(main, "get", RefFun(3));
+        let code = b.build().unwrap();
+
+        let opts = DecompilerOptions::default();
+        let fmt = FormatOptions::new(2);
+        let display = |methods: Vec<Method>| -> Vec<String> {
+            methods
+                .iter()
+                .map(|m| m.display(&code, &fmt).to_string())
+                .collect()
+        };
+        let parallel = decompile_all(&code, &opts);
+        assert_eq!(parallel.len(), 64);
+        assert!(parallel.iter().zip(0..).all(|(m, i)| m.fun == RefFun(i)));
+        assert_eq!(
+            display(parallel),
+            display(Batch::all(&code, opts.clone()).collect())
+        );
+
+        let classes = decompile_all_classes(&code, &opts);
+        assert_eq!(classes.len(), 1);
+        assert_eq!(classes[0].methods[0].fun, RefFun(3));
+    }
+}
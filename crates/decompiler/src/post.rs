@@ -1,18 +1,65 @@
-use hlbc::Bytecode;
+use std::collections::HashMap;
 
-use crate::ast::{add, ConstructorCall, Expr, Operation, Statement};
-use crate::call_fun;
+use hlbc::types::{FunPtr, Function, RefField, RefFun, Reg, Type};
+use hlbc::{Bytecode, Resolve, Str};
+
+use crate::ast::{
+    add, and, call, cst_bool, interval, not, null_coalesce, or, static_call, Call, CastKind,
+    Constant, ConstructorCall, Expr, Operation, Statement,
+};
+use crate::{call_fun, Pass};
 
 pub(crate) trait AstVisitor {
     fn visit_stmt(&mut self, code: &Bytecode, stmt: &mut Statement) {}
     fn visit_expr(&mut self, code: &Bytecode, expr: &mut Expr) {}
+    /// Called on a list of statements after all of its statements have been visited
+    fn visit_block(&mut self, code: &Bytecode, block: &mut Vec<Statement>) {}
+}
+
+/// Run the passes in order on the statements of `f`. Each pass is a full traversal of the AST.
+pub(crate) fn run_passes(
+    code: &Bytecode,
+    f: &Function,
+    stmts: &mut Vec<Statement>,
+    passes: &[Pass],
+) {
+    for pass in passes {
+        match pass {
+            Pass::StdIdioms => visit(code, stmts, &mut [&mut Itos, &mut Trace]),
+            Pass::StringConcat => {
+                visit(code, stmts, &mut [&mut StringConcat]);
+                let usage = VarUsage::collect(code, stmts);
+                visit(code, stmts, &mut [&mut StringBufConcat(usage)]);
+            }
+            Pass::DeadStores => {
+                let usage = VarUsage::collect(code, stmts);
+                if !usage.has_closure {
+                    visit(code, stmts, &mut [&mut DeadStores(usage)]);
+                }
+            }
+            Pass::InlineExpressions => {
+                let usage = VarUsage::collect(code, stmts);
+                if !usage.has_closure {
+                    visit(code, stmts, &mut [&mut InlineTemporaries(usage)]);
+                }
+            }
+            Pass::IfExpressions => visit(code, stmts, &mut [&mut IfExpressions]),
+            Pass::NullSafety => visit(code, stmts, &mut [&mut NullSafety]),
+            Pass::BooleanOperators => visit(code, stmts, &mut [&mut BooleanOperators(f)]),
+            Pass::LoopIdioms => visit(code, stmts, &mut [&mut LoopIdioms]),
+            Pass::Switches => visit(code, stmts, &mut [&mut Switches]),
+            Pass::CompoundAssignments => visit(code, stmts, &mut [&mut CompoundAssignments]),
+            Pass::MapLiterals => visit(code, stmts, &mut [&mut MapLiterals]),
+            Pass::StdCalls => visit(code, stmts, &mut [&mut StdCalls]),
+        }
+    }
 }
 
 /// Visit everything depth-first
 pub(crate) fn visit(
     code: &Bytecode,
-    stmts: &mut [Statement],
-    visitors: &mut [Box<dyn AstVisitor>],
+    stmts: &mut Vec<Statement>,
+    visitors: &mut [&mut dyn AstVisitor],
 ) {
     // Recurse
     macro_rules! rec {
@@ -26,7 +73,7 @@ pub(crate) fn visit(
             visit_expr(code, $e, visitors)
         };
     }
-    for stmt in stmts {
+    for stmt in stmts.iter_mut() {
         // No _ pattern, wouldn't want this match to de-sync when adding new items
         match stmt {
             Statement::Assign {
@@ -35,6 +82,12 @@ pub(crate) fn visit(
                 v!(assign);
                 v!(variable);
             }
+            Statement::CompoundAssign {
+                variable, value, ..
+            } => {
+                v!(value);
+                v!(variable);
+            }
             Statement::ExprStatement(e) => {
                 v!(e);
             }
@@ -61,6 +114,15 @@ pub(crate) fn visit(
                 v!(cond);
                 rec!(stmts);
             }
+            Statement::For {
+                variable,
+                iterable,
+                stmts,
+            } => {
+                v!(iterable);
+                v!(variable);
+                rec!(stmts);
+            }
             Statement::Break => {}
             Statement::Continue => {}
             Statement::Throw(e) => {
@@ -72,16 +134,23 @@ pub(crate) fn visit(
             Statement::Catch { stmts } => {
                 rec!(stmts);
             }
+            Statement::Label(_) => {}
+            Statement::Goto(_) => {}
+            Statement::Opcode(_) => {}
+            Statement::Source(_) => {}
             Statement::Comment(_) => {}
         }
         for visitor in visitors.iter_mut() {
             visitor.visit_stmt(code, stmt);
         }
     }
+    for visitor in visitors.iter_mut() {
+        visitor.visit_block(code, stmts);
+    }
 }
 
 /// Visit expressions by depth-first recursion into [Expr].
-pub(crate) fn visit_expr(code: &Bytecode, expr: &mut Expr, visitors: &mut [Box<dyn AstVisitor>]) {
+pub(crate) fn visit_expr(code: &Bytecode, expr: &mut Expr, visitors: &mut [&mut dyn AstVisitor]) {
     // Recurse
     macro_rules! rec {
         ($e:expr) => {
@@ -105,12 +174,20 @@ pub(crate) fn visit_expr(code: &Bytecode, expr: &mut Expr, visitors: &mut [Box<d
             rec!(arr);
             rec!(index);
         }
+        Expr::ArrayLiteral(values) => {
+            for e in values {
+                rec!(e);
+            }
+        }
         Expr::Call(call) => {
             rec!(&mut call.fun);
             for arg in call.args.iter_mut() {
                 rec!(arg);
             }
         }
+        Expr::Cast(e, _, _) => {
+            rec!(e);
+        }
         Expr::Constant(_) => {}
         Expr::Constructor(ConstructorCall { args, .. }) => {
             for arg in args {
@@ -118,7 +195,7 @@ pub(crate) fn visit_expr(code: &Bytecode, expr: &mut Expr, visitors: &mut [Box<d
             }
         }
         // /!\ No recurse in closure, as closure decompilation is already recursive.
-        Expr::Closure(_, _) => {}
+        Expr::Closure(_) => {}
         Expr::EnumConstr(_, _, args) => {
             for arg in args {
                 rec!(arg);
@@ -133,6 +210,12 @@ pub(crate) fn visit_expr(code: &Bytecode, expr: &mut Expr, visitors: &mut [Box<d
             v!(if_);
             v!(else_);
         }
+        Expr::MapLiteral(entries) => {
+            for (k, v) in entries {
+                rec!(k);
+                rec!(v);
+            }
+        }
         Expr::Op(op) => match op {
             Operation::Add(e1, e2) => {
                 rec!(e1);
@@ -210,7 +293,19 @@ pub(crate) fn visit_expr(code: &Bytecode, expr: &mut Expr, visitors: &mut [Box<d
                 rec!(e1);
                 rec!(e2);
             }
+            Operation::Interval(e1, e2) => {
+                rec!(e1);
+                rec!(e2);
+            }
+            Operation::NullCoalesce(e1, e2) => {
+                rec!(e1);
+                rec!(e2);
+            }
         },
+        Expr::SafeField(obj, _) => {
+            rec!(obj);
+        }
+        Expr::TypePath(_) => {}
         Expr::Unknown(_) => {}
         Expr::Variable(_, _) => {}
     }
@@ -292,15 +387,384 @@ impl AstVisitor for IfExpressions {
     }
 }
 
+/// Restore the boolean operators from the if expressions they are compiled to, as they short-circuit.
+/// ```haxe
+/// var a = if (x) {
+///     y
+/// } else {
+///     false
+/// };
+/// var b = if (x) {
+///     true
+/// } else {
+///     y
+/// };
+/// ```
+/// becomes :
+/// ```haxe
+/// var a = x && y;
+/// var b = x || y;
+/// ```
+///
+/// The other branch has to be a boolean too, `if (x) y else false` is kept for any other type of `y`.
+pub(crate) struct BooleanOperators<'f>(pub(crate) &'f Function);
+
+impl AstVisitor for BooleanOperators<'_> {
+    fn visit_expr(&mut self, code: &Bytecode, expr: &mut Expr) {
+        let Expr::IfElse { cond, if_, else_ } = expr else {
+            return;
+        };
+        let (Some(Statement::ExprStatement(a)), Some(Statement::ExprStatement(b))) =
+            (single_stmt(if_), single_stmt(else_))
+        else {
+            return;
+        };
+        let as_bool = |e: &Expr| match e {
+            Expr::Constant(Constant::Bool(value)) => Some(*value),
+            _ => None,
+        };
+        let cond = cond.as_ref().clone();
+        // One of the branches is a constant, the other has to be a boolean too
+        let replacement = match (as_bool(a), as_bool(b)) {
+            (Some(true), Some(false)) => cond,
+            (Some(false), Some(true)) => not(cond),
+            (Some(_), None) if !is_bool(code, self.0, b) => return,
+            (None, Some(_)) if !is_bool(code, self.0, a) => return,
+            (Some(true), _) => or(cond, b.clone()),
+            (Some(false), _) => and(not(cond), b.clone()),
+            (_, Some(false)) => and(cond, a.clone()),
+            (_, Some(true)) => or(not(cond), a.clone()),
+            (None, None) => return,
+        };
+        *expr = replacement;
+    }
+}
+
+/// We know for sure this expression is a boolean
+fn is_bool(code: &Bytecode, f: &Function, expr: &Expr) -> bool {
+    use Operation::*;
+    let ty = match expr {
+        Expr::Constant(Constant::Bool(_)) => return true,
+        Expr::Op(
+            Not(_) | And(..) | Or(..) | Eq(..) | NotEq(..) | Gt(..) | Gte(..) | Lt(..) | Lte(..),
+        ) => return true,
+        Expr::Variable(reg, _) => match f.regs.get(reg.0 as usize) {
+            Some(&ty) => ty,
+            None => return false,
+        },
+        Expr::Cast(_, ty, _) => *ty,
+        Expr::Call(call) => match call.fun {
+            Expr::FunRef(fun) if fun.0 < code.findex_max() => fun.ty(code).ret,
+            _ => return false,
+        },
+        _ => return false,
+    };
+    matches!(code.types.get(ty.0), Some(Type::Bool))
+}
+
+/// Restore the null coalescing and safe navigation operators from the if expressions they are compiled to.
+/// ```haxe
+/// var a = if (x != null) {
+///     x
+/// } else {
+///     y
+/// };
+/// var b = if (x == null) {
+///     null
+/// } else {
+///     x.name
+/// };
+/// ```
+/// becomes :
+/// ```haxe
+/// var a = x ?? y;
+/// var b = x?.name;
+/// ```
+pub(crate) struct NullSafety;
+
+impl AstVisitor for NullSafety {
+    fn visit_expr(&mut self, _code: &Bytecode, expr: &mut Expr) {
+        let Expr::IfElse { cond, if_, else_ } = expr else {
+            return;
+        };
+        let Some((value, not_null)) = null_check(cond) else {
+            return;
+        };
+        let (present, absent) = if not_null { (if_, else_) } else { (else_, if_) };
+        let (Some(Statement::ExprStatement(present)), Some(Statement::ExprStatement(absent))) =
+            (single_stmt(present), single_stmt(absent))
+        else {
+            return;
+        };
+        let is_null = |e: &Expr| matches!(e, Expr::Constant(Constant::Null));
+        let replacement = if same_place(present, value) && !is_null(absent) {
+            Some(null_coalesce(value.clone(), absent.clone()))
+        } else if is_null(absent) {
+            safe_access(present, value)
+        } else {
+            None
+        };
+        if let Some(replacement) = replacement {
+            *expr = replacement;
+        }
+    }
+}
+
+/// Match `value == null` or `value != null` for a value that can be evaluated twice.
+/// Returns the value and true if the condition checks it isn't null.
+fn null_check(cond: &Expr) -> Option<(&Expr, bool)> {
+    let (a, b, not_null) = match cond {
+        Expr::Op(Operation::Eq(a, b)) => (a, b, false),
+        Expr::Op(Operation::NotEq(a, b)) => (a, b, true),
+        _ => return None,
+    };
+    let value = match (&**a, &**b) {
+        (value, Expr::Constant(Constant::Null)) | (Expr::Constant(Constant::Null), value) => value,
+        _ => return None,
+    };
+    is_place(value).then_some((value, not_null))
+}
+
+/// Rewrite an access to a field of `obj`, or a method call on it, as a safe navigation
+fn safe_access(expr: &Expr, obj: &Expr) -> Option<Expr> {
+    match expr {
+        Expr::Field(receiver, name) if same_place(receiver, obj) => {
+            Some(Expr::SafeField(receiver.clone(), name.clone()))
+        }
+        Expr::Field(receiver, name) => Some(Expr::Field(
+            Box::new(safe_access(receiver, obj)?),
+            name.clone(),
+        )),
+        Expr::Call(c) => Some(call(safe_access(&c.fun, obj)?, c.args.clone())),
+        _ => None,
+    }
+}
+
 // TODO AST-PP switch expressions
 
+/// Turn chains of comparisons of the same value against constants into a switch.
+/// The compiler emits those for switches it can't turn into a jump table, like switches over strings.
+/// ```haxe
+/// if (s == "a") {
+///     f();
+/// } else if (s == "b") {
+///     g();
+/// } else if (s == "c") {
+///     h();
+/// } else {
+///     other();
+/// }
+/// ```
+/// becomes :
+/// ```haxe
+/// switch (s) {
+///     default:
+///         other();
+///     case "a":
+///         f();
+///     case "b":
+///         g();
+///     case "c":
+///         h();
+/// }
+/// ```
+pub(crate) struct Switches;
+
+impl Switches {
+    /// Shorter chains read just as well as if/else
+    const MIN_CASES: usize = 3;
+
+    /// The statement continues a chain of comparisons of `subject`
+    fn continues(stmt: &Statement, subject: &Expr) -> bool {
+        match stmt {
+            Statement::IfElse { cond, .. } => {
+                case_of(cond).is_some_and(|(s, _)| same_place(s, subject))
+            }
+            // An inner chain already turned into a switch
+            Statement::Switch { arg, .. } => same_place(arg, subject),
+            _ => false,
+        }
+    }
+
+    /// Number of cases of the chain starting at this if
+    fn chain_len(subject: &Expr, mut else_: &[Statement]) -> usize {
+        let mut len = 1;
+        while let Some(next) = single_stmt(else_).filter(|s| Self::continues(s, subject)) {
+            match next {
+                Statement::IfElse { else_: e, .. } => {
+                    len += 1;
+                    else_ = e;
+                }
+                Statement::Switch { cases, .. } => return len + cases.len(),
+                _ => unreachable!(),
+            }
+        }
+        len
+    }
+}
+
+impl AstVisitor for Switches {
+    fn visit_stmt(&mut self, _code: &Bytecode, stmt: &mut Statement) {
+        let Statement::IfElse { cond, else_, .. } = stmt else {
+            return;
+        };
+        let Some((subject, _)) = case_of(cond) else {
+            return;
+        };
+        if Self::chain_len(subject, else_) < Self::MIN_CASES {
+            return;
+        }
+        let subject = subject.clone();
+
+        let mut cases = Vec::new();
+        let mut next = std::mem::replace(stmt, Statement::Break);
+        let default = loop {
+            match next {
+                Statement::IfElse { cond, if_, else_ } => {
+                    cases.push((case_of(&cond).unwrap().1.clone(), if_));
+                    if !single_stmt(&else_).is_some_and(|s| Self::continues(s, &subject)) {
+                        break else_;
+                    }
+                    next = else_.into_iter().find(|s| s.is_code()).unwrap();
+                }
+                Statement::Switch {
+                    cases: rest,
+                    default,
+                    ..
+                } => {
+                    cases.extend(rest);
+                    break default;
+                }
+                _ => unreachable!(),
+            }
+        };
+        *stmt = Statement::Switch {
+            arg: subject,
+            default,
+            cases,
+        };
+    }
+}
+
+/// The only statement of a block, ignoring comments
+fn single_stmt(stmts: &[Statement]) -> Option<&Statement> {
+    let mut code = stmts.iter().filter(|s| s.is_code());
+    match (code.next(), code.next()) {
+        (Some(s), None) => Some(s),
+        _ => None,
+    }
+}
+
+/// Match `subject == constant`, returns the subject and the constant
+fn case_of(cond: &Expr) -> Option<(&Expr, &Expr)> {
+    let Expr::Op(Operation::Eq(a, b)) = cond else {
+        return None;
+    };
+    // Strings are compared with `a.__compare(b) == 0`
+    if let (Expr::Call(call), Expr::Constant(Constant::InlineInt(0))) = (a.as_ref(), b.as_ref()) {
+        if let (Expr::Field(subject, method), [pattern]) = (&call.fun, call.args.as_slice()) {
+            if &**method == "__compare" && is_case_constant(pattern) && is_place(subject) {
+                return Some((&**subject, pattern));
+            }
+        }
+    }
+    if is_case_constant(b) && is_place(a) {
+        Some((&**a, &**b))
+    } else if is_case_constant(a) && is_place(b) {
+        Some((&**b, &**a))
+    } else {
+        None
+    }
+}
+
+fn is_case_constant(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::Constant(
+            Constant::String(_)
+                | Constant::Str(_)
+                | Constant::Int(_)
+                | Constant::InlineInt(_)
+                | Constant::Float(_)
+        )
+    )
+}
+
+/// A variable or a field of a variable, which can be evaluated again with the same result
+fn is_place(expr: &Expr) -> bool {
+    match expr {
+        Expr::Variable(_, _) | Expr::Constant(Constant::This) => true,
+        Expr::Field(obj, _) => is_place(obj),
+        _ => false,
+    }
+}
+
+fn same_place(a: &Expr, b: &Expr) -> bool {
+    match (a, b) {
+        (Expr::Variable(r1, n1), Expr::Variable(r2, n2)) => r1 == r2 && n1 == n2,
+        (Expr::Constant(Constant::This), Expr::Constant(Constant::This)) => true,
+        (Expr::Field(o1, f1), Expr::Field(o2, f2)) => f1 == f2 && same_place(o1, o2),
+        _ => false,
+    }
+}
+
+/// Turn assignments of an operation on the assigned variable into compound assignments.
+/// ```haxe
+/// score = score + bonus;
+/// ```
+/// becomes :
+/// ```haxe
+/// score += bonus;
+/// ```
+/// Only the left operand is considered, `s = prefix + s` isn't the same as `s += prefix` for strings.
+pub(crate) struct CompoundAssignments;
+
+impl AstVisitor for CompoundAssignments {
+    fn visit_stmt(&mut self, _code: &Bytecode, stmt: &mut Statement) {
+        let Statement::Assign {
+            declaration: false,
+            variable,
+            assign: Expr::Op(op),
+        } = stmt
+        else {
+            return;
+        };
+        let Some((left, op, value)) = compound(op) else {
+            return;
+        };
+        if is_place(variable) && same_place(variable, left) {
+            *stmt = Statement::CompoundAssign {
+                variable: variable.clone(),
+                op,
+                value: value.clone(),
+            };
+        }
+    }
+}
+
+/// The operands and the compound assignment operator of a binary operation, if there is one
+fn compound(op: &Operation) -> Option<(&Expr, &'static str, &Expr)> {
+    let (a, op, b) = match op {
+        Operation::Add(a, b) => (a, "+=", b),
+        Operation::Sub(a, b) => (a, "-=", b),
+        Operation::Mul(a, b) => (a, "*=", b),
+        Operation::Div(a, b) => (a, "/=", b),
+        Operation::Mod(a, b) => (a, "%=", b),
+        Operation::Shl(a, b) => (a, "<<=", b),
+        Operation::Shr(a, b) => (a, ">>=", b),
+        Operation::Xor(a, b) => (a, "^=", b),
+        _ => return None,
+    };
+    Some((&**a, op, &**b))
+}
+
 /// Restore string concatenation. They are translated to calls to \_\_add__ at compilation.
 /// ```haxe
-/// __add__("hello ", "world")
+/// __add__("hello ", Std.string(value))
 /// ```
 /// becomes :
 /// ```haxe
-/// "hello " + "world"
+/// "hello " + value
 /// ```
 pub(crate) struct StringConcat;
 
@@ -321,60 +785,1635 @@ impl AstVisitor for StringConcat {
         };
 
         if let Some((arg0, arg1)) = args {
-            *expr = add(arg0, arg1);
+            *expr = concat(code, arg0, arg1);
         }
     }
 }
 
-/// Remove calls to `std/itos` and `std/alloc` when converting an integer to a string.
-pub(crate) struct Itos;
+/// Restore string concatenation done through a `StringBuf` used only to build a single string.
+/// ```haxe
+/// var buf = new StringBuf();
+/// buf.add("count : ");
+/// buf.add(count);
+/// trace(buf.toString());
+/// ```
+/// becomes :
+/// ```haxe
+/// trace("count : " + count);
+/// ```
+pub(crate) struct StringBufConcat(pub(crate) VarUsage);
 
-impl AstVisitor for Itos {
-    fn visit_expr(&mut self, code: &Bytecode, expr: &mut Expr) {
-        let var = match expr {
-            Expr::Call(call) => match call.fun {
-                Expr::FunRef(fun) if fun.name(code) == "__alloc__" => match &call.args[0] {
-                    Expr::Call(call) => match call.fun {
-                        Expr::FunRef(fun) if fun.name(code) == "itos" => Some(call.args[0].clone()),
-                        _ => None,
-                    },
-                    _ => None,
-                },
-                _ => None,
-            },
-            _ => None,
+impl StringBufConcat {
+    /// Try to rewrite the string buffer declared at `decl`, returns false if nothing changed.
+    fn rewrite(&self, code: &Bytecode, block: &mut Vec<Statement>, decl: usize) -> bool {
+        let name = match &block[decl] {
+            Statement::Assign {
+                declaration: true,
+                variable: Expr::Variable(_, Some(name)),
+                assign: Expr::Constructor(ConstructorCall { ty, args }),
+            } if args.is_empty()
+                && ty
+                    .as_obj(code)
+                    .is_some_and(|obj| obj.name(code) == "StringBuf") =>
+            {
+                name.clone()
+            }
+            _ => return false,
+        };
+
+        // Collect the buf.add(x) following the declaration
+        let mut adds = Vec::new();
+        let mut parts = Vec::new();
+        let mut end = None;
+        for (j, stmt) in block.iter().enumerate().skip(decl + 1) {
+            match stmt {
+                Statement::Comment(_) | Statement::Opcode(_) | Statement::Source(_) => {}
+                Statement::ExprStatement(Expr::Call(call))
+                    if is_method_call(&call.fun, &name, "add") && call.args.len() == 1 =>
+                {
+                    adds.push(j);
+                    parts.push(call.args[0].clone());
+                }
+                _ => {
+                    end = Some(j);
+                    break;
+                }
+            }
+        }
+        let Some(end) = end else {
+            return false;
+        };
+        // The buffer must not be used anywhere else than in the adds and the final toString()
+        if self.0.writes(&name) != 1 || self.0.reads(&name) != adds.len() + 1 {
+            return false;
+        }
+
+        // The first operation must be a string concatenation, not a numeric addition
+        let mut parts = parts.into_iter();
+        let value = match (parts.next(), parts.next()) {
+            (Some(a), None) if is_string(code, &a) => a,
+            (Some(a), Some(b)) if is_string(code, &a) || is_string(code, &b) => {
+                parts.fold(concat(code, a, b), |acc, p| concat(code, acc, p))
+            }
+            _ => return false,
+        };
+
+        let mut to_string = ReplaceToString {
+            name,
+            value: Some(value),
         };
+        let mut target = vec![block.remove(end)];
+        visit(code, &mut target, &mut [&mut to_string]);
+        block.insert(end, target.pop().unwrap());
+        if to_string.value.is_some() {
+            // The buffer was read but not with toString()
+            return false;
+        }
 
-        if let Some(int) = var {
-            *expr = int;
+        for &j in adds.iter().rev() {
+            block.remove(j);
         }
+        block.remove(decl);
+        true
     }
 }
 
-/// Restore inlined `trace` calls.
-pub(crate) struct Trace;
+impl AstVisitor for StringBufConcat {
+    fn visit_block(&mut self, code: &Bytecode, block: &mut Vec<Statement>) {
+        let mut i = 0;
+        while i < block.len() {
+            if !self.rewrite(code, block, i) {
+                i += 1;
+            }
+        }
+    }
+}
 
-impl AstVisitor for Trace {
-    fn visit_expr(&mut self, code: &Bytecode, expr: &mut Expr) {
-        let call = match expr {
-            Expr::Call(call) => match &call.fun {
-                Expr::Field(obj, field) => match obj.as_ref() {
-                    Expr::Variable(_, _) => {
-                        if field == "trace" {
-                            let trace = code.function_by_name(field).unwrap();
-                            Some(call_fun(trace.findex, vec![call.args[0].clone()]))
-                        } else {
-                            None
-                        }
-                    }
-                    _ => None,
-                },
-                _ => None,
-            },
-            _ => None,
+/// Replace `name.toString()` with a value
+struct ReplaceToString {
+    name: Str,
+    value: Option<Expr>,
+}
+
+impl AstVisitor for ReplaceToString {
+    fn visit_expr(&mut self, _code: &Bytecode, expr: &mut Expr) {
+        let matched = matches!(expr, Expr::Call(call) if call.args.is_empty() && is_method_call(&call.fun, &self.name, "toString"));
+        if matched {
+            if let Some(value) = self.value.take() {
+                *expr = value;
+            }
+        }
+    }
+}
+
+/// `fun` is `name.method`
+fn is_method_call(fun: &Expr, name: &Str, method: &str) -> bool {
+    match fun {
+        Expr::Field(obj, field) => {
+            &**field == method && matches!(obj.as_ref(), Expr::Variable(_, Some(n)) if n == name)
+        }
+        _ => false,
+    }
+}
+
+/// Concatenate two strings, removing the explicit conversions to string when the other operand
+/// already makes it a string concatenation.
+fn concat(code: &Bytecode, a: Expr, b: Expr) -> Expr {
+    let a = if is_string(code, &b) {
+        strip_std_string(code, a)
+    } else {
+        a
+    };
+    let b = if is_string(code, &a) {
+        strip_std_string(code, b)
+    } else {
+        b
+    };
+    add(a, b)
+}
+
+/// We know for sure this expression is a string
+fn is_string(code: &Bytecode, expr: &Expr) -> bool {
+    match expr {
+        Expr::Constant(Constant::String(_) | Constant::Str(_)) => true,
+        Expr::Op(Operation::Add(a, b)) => is_string(code, a) || is_string(code, b),
+        Expr::Call(call) => match call.fun {
+            Expr::FunRef(fun) => is_std_string(code, fun) || fun.name(code) == "__add__",
+            ref fun => is_std_string_path(fun),
+        },
+        _ => false,
+    }
+}
+
+/// `Std.string(x)` -> `x`
+fn strip_std_string(code: &Bytecode, expr: Expr) -> Expr {
+    match expr {
+        Expr::Call(call)
+            if matches!(call.fun, Expr::FunRef(fun) if is_std_string(code, fun))
+                || is_std_string_path(&call.fun) =>
+        {
+            let mut args = call.args;
+            if args.len() == 1 {
+                args.pop().unwrap()
+            } else {
+                Expr::Call(Box::new(Call::new(call.fun, args)))
+            }
+        }
+        _ => expr,
+    }
+}
+
+fn is_std_string(code: &Bytecode, fun: RefFun) -> bool {
+    fun.name(code) == "string"
+        && fun
+            .as_fn(code)
+            .and_then(|f| f.parent)
+            .and_then(|p| p.as_obj(code))
+            .is_some_and(|obj| obj.name(code).ends_with("Std"))
+}
+
+/// `Std.string` already qualified by [Itos] or [StdCalls]
+fn is_std_string_path(fun: &Expr) -> bool {
+    matches!(fun, Expr::Field(class, name) if &**name == "string" && matches!(&**class, Expr::TypePath(c) if &**c == "Std"))
+}
+
+/// Map types built by the compiler for map literals
+const MAP_TYPES: [&str; 4] = [
+    "haxe.ds.StringMap",
+    "haxe.ds.IntMap",
+    "haxe.ds.ObjectMap",
+    "haxe.ds.EnumValueMap",
+];
+
+/// Restore map literals, compiled to the construction of a map followed by calls to `set`.
+/// ```haxe
+/// var scores = new haxe.ds.StringMap();
+/// scores.set("alice", 3);
+/// scores.set("bob", 5);
+/// ```
+/// becomes :
+/// ```haxe
+/// var scores = ["alice" => 3, "bob" => 5];
+/// ```
+pub(crate) struct MapLiterals;
+
+impl MapLiterals {
+    /// Fold the calls to `set` immediately following the construction of a map at `i`
+    fn rewrite(code: &Bytecode, block: &mut Vec<Statement>, i: usize) {
+        let map = match &block[i] {
+            Statement::Assign {
+                variable,
+                assign: Expr::Constructor(ConstructorCall { ty, args }),
+                ..
+            } if args.is_empty()
+                && is_place(variable)
+                && ty
+                    .as_obj(code)
+                    .is_some_and(|obj| MAP_TYPES.iter().any(|&t| obj.name(code) == t)) =>
+            {
+                variable.clone()
+            }
+            _ => return,
         };
-        if let Some(call) = call {
-            *expr = call;
+
+        let mut entries = Vec::new();
+        for stmt in &block[i + 1..] {
+            let Statement::ExprStatement(Expr::Call(call)) = stmt else {
+                break;
+            };
+            let is_set = matches!(&call.fun, Expr::Field(obj, name) if &**name == "set" && same_place(obj, &map));
+            if !is_set || call.args.len() != 2 {
+                break;
+            }
+            // The map doesn't exist yet when the literal is evaluated
+            let mut entry = call.args.clone();
+            let mut mentions = Mentions {
+                place: &map,
+                found: false,
+            };
+            visit_expr(code, &mut entry[0], &mut [&mut mentions]);
+            visit_expr(code, &mut entry[1], &mut [&mut mentions]);
+            if mentions.found {
+                break;
+            }
+            let value = entry.pop().unwrap();
+            entries.push((entry.pop().unwrap(), value));
+        }
+        if entries.is_empty() {
+            return;
+        }
+
+        block.drain(i + 1..i + 1 + entries.len());
+        if let Statement::Assign { assign, .. } = &mut block[i] {
+            *assign = Expr::MapLiteral(entries);
+        }
+    }
+}
+
+impl AstVisitor for MapLiterals {
+    fn visit_block(&mut self, code: &Bytecode, block: &mut Vec<Statement>) {
+        let mut i = 0;
+        while i < block.len() {
+            Self::rewrite(code, block, i);
+            i += 1;
         }
     }
 }
+
+/// Find a use of a place in an expression
+struct Mentions<'a> {
+    place: &'a Expr,
+    found: bool,
+}
+
+impl AstVisitor for Mentions<'_> {
+    fn visit_expr(&mut self, _code: &Bytecode, expr: &mut Expr) {
+        self.found |= same_place(expr, self.place);
+    }
+}
+
+/// Restore the conversion of an integer to a string, compiled to calls to `std/itos` and `String.__alloc__`.
+/// ```haxe
+/// __alloc__(itos(x, len), len)
+/// ```
+/// becomes :
+/// ```haxe
+/// Std.string(x)
+/// ```
+/// The call is removed again by [Pass::StringConcat] in a concatenation.
+pub(crate) struct Itos;
+
+impl AstVisitor for Itos {
+    fn visit_expr(&mut self, code: &Bytecode, expr: &mut Expr) {
+        let Expr::Call(call) = expr else {
+            return;
+        };
+        let int = match (&call.fun, call.args.as_slice()) {
+            (Expr::FunRef(alloc), [Expr::Call(itos), ..]) if alloc.name(code) == "__alloc__" => {
+                match (&itos.fun, itos.args.as_slice()) {
+                    (Expr::FunRef(fun), [int, ..]) if fun.name(code) == "itos" => int.clone(),
+                    _ => return,
+                }
+            }
+            _ => return,
+        };
+        *expr = static_call(
+            Str::from_static("Std"),
+            Str::from_static("string"),
+            vec![int],
+        );
+    }
+}
+
+/// Classes of the standard library, static functions are compiled to direct calls losing their class
+const STD_CLASSES: [&str; 17] = [
+    "Std",
+    "Type",
+    "Reflect",
+    "Math",
+    "String",
+    "StringTools",
+    "Lambda",
+    "Sys",
+    "Date",
+    "haxe.EnumTools",
+    "haxe.EnumValueTools",
+    "haxe.Json",
+    "haxe.Timer",
+    "haxe.Utf8",
+    "haxe.io.Bytes",
+    "sys.FileSystem",
+    "sys.io.File",
+];
+
+/// Call the static functions of the standard library with their class, and restore the functions of `Type` the
+/// compiler replaced by a native.
+/// ```haxe
+/// string(x);
+/// createInstance($Foo, []);
+/// alloc_obj($Foo.__type__);
+/// ```
+/// becomes :
+/// ```haxe
+/// Std.string(x);
+/// Type.createInstance(Foo, []);
+/// Type.createEmptyInstance(Foo);
+/// ```
+pub(crate) struct StdCalls;
+
+impl AstVisitor for StdCalls {
+    fn visit_expr(&mut self, code: &Bytecode, expr: &mut Expr) {
+        let Expr::Call(call) = expr else {
+            return;
+        };
+        let Expr::FunRef(fun) = call.fun else {
+            return;
+        };
+        if let Some(class) = std_class(code, fun) {
+            if &*class == "Type" {
+                // Classes and enums are passed as values
+                call.args.iter_mut().for_each(class_path);
+            }
+            call.fun = Expr::Field(Box::new(Expr::TypePath(class)), fun.name(code));
+        } else if matches!(code.get(fun), FunPtr::Native(n) if n.name(code) == "alloc_obj") {
+            // hl.Type.allocObject, called by Type.createEmptyInstance with the type of the class
+            if let [Expr::Field(class, field)] = call.args.as_mut_slice() {
+                if &**field == "__type__" {
+                    let mut class = std::mem::replace(&mut **class, Expr::Unknown(String::new()));
+                    class_path(&mut class);
+                    *expr = static_call(
+                        Str::from_static("Type"),
+                        Str::from_static("createEmptyInstance"),
+                        vec![class],
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// The class of a static function of the standard library
+fn std_class(code: &Bytecode, fun: RefFun) -> Option<Str> {
+    let obj = fun.as_fn(code)?.parent?.as_obj(code)?;
+    let name = obj.name(code);
+    // Static functions are bound to the companion type of the class
+    let class = name.strip_prefix('$')?;
+    STD_CLASSES.contains(&class).then(|| Str::from(class))
+}
+
+/// A class is referenced with the global holding its companion object `$Foo`, which is written `Foo` in Haxe
+fn class_path(expr: &mut Expr) {
+    let inner = match &*expr {
+        Expr::Cast(e, _, CastKind::Implicit) => &**e,
+        e => e,
+    };
+    if let Expr::Variable(_, Some(name)) = inner {
+        if let Some(class) = name.strip_prefix('$') {
+            *expr = Expr::TypePath(Str::from(class));
+        }
+    }
+}
+
+/// Restore `trace` calls. They are compiled to a call to `haxe.Log.trace` with the position of the call as the last
+/// argument.
+/// ```haxe
+/// haxe.Log.trace(value, {fileName: "Main.hx", lineNumber: 12, className: "Main", methodName: "main"})
+/// ```
+/// becomes :
+/// ```haxe
+/// trace(value)
+/// ```
+/// The additional arguments of a trace (`trace(a, b)`) are stored in the position as `customParams`, they are put
+/// back as arguments.
+pub(crate) struct Trace;
+
+impl AstVisitor for Trace {
+    fn visit_expr(&mut self, code: &Bytecode, expr: &mut Expr) {
+        let Expr::Call(call) = expr else {
+            return;
+        };
+        let is_log_trace = matches!(&call.fun, Expr::Field(obj, field) if &**field == "trace"
+            && matches!(obj.as_ref(), Expr::Variable(_, Some(name)) if name.ends_with("haxe.Log")));
+        let (true, Some(trace), [value, pos]) = (
+            is_log_trace,
+            code.function_by_name("trace"),
+            call.args.as_slice(),
+        ) else {
+            return;
+        };
+        let Some(params) = custom_params(code, pos) else {
+            return;
+        };
+        // The value is passed as Dynamic
+        let value = match value {
+            Expr::Cast(e, _, CastKind::Implicit) => e.as_ref().clone(),
+            _ => value.clone(),
+        };
+        *expr = call_fun(trace.findex, std::iter::once(value).chain(params).collect());
+    }
+}
+
+/// The additional arguments of a trace stored in its position infos, `None` if we can't find them
+fn custom_params(code: &Bytecode, pos: &Expr) -> Option<Vec<Expr>> {
+    let Expr::Anonymous(ty, values) = pos else {
+        // Not a structure we rebuilt, there might be custom params in there
+        return matches!(pos, Expr::Constant(Constant::Null)).then(Vec::new);
+    };
+    let Type::Virtual { fields } = &code[*ty] else {
+        return None;
+    };
+    let params = fields
+        .iter()
+        .position(|f| f.name(code) == "customParams")
+        .and_then(|i| values.get(&RefField(i)));
+    match params {
+        None | Some(Expr::Constant(Constant::Null)) => Some(Vec::new()),
+        Some(Expr::ArrayLiteral(params)) => Some(params.clone()),
+        Some(_) => None,
+    }
+}
+
+/// Recover the condition of loops the decompiler couldn't find a condition for.
+/// ```haxe
+/// while (?) {
+///     if (i >= 10) {
+///         break;
+///     }
+///     i++;
+/// }
+/// ```
+/// becomes :
+/// ```haxe
+/// while (i < 10) {
+///     i++;
+/// }
+/// ```
+/// Loops without a leading exit condition become `while (true)`.
+///
+/// The loops generated by the Haxe compiler for `for` loops are then turned back into for loops :
+/// ```haxe
+/// var _g = 0;
+/// var _g1 = 10;
+/// while (_g < _g1) {
+///     var i = _g;
+///     _g++;
+/// }
+/// ```
+/// becomes :
+/// ```haxe
+/// for (i in 0...10) {
+/// }
+/// ```
+/// Loops over an array (`_g1[_g]` with `_g < _g1.length`) and over an iterator
+/// (`while (_g.hasNext()) { var x = _g.next(); }`) are recognized too.
+pub(crate) struct LoopIdioms;
+
+/// What a while loop condition tells about a possible for loop
+enum ForShape {
+    /// `counter < end`
+    Counted { counter: Str, end: Expr },
+    /// `counter < array.length`
+    Array {
+        counter: Str,
+        array: Str,
+        array_var: Expr,
+    },
+    /// `it.hasNext()`
+    Iterator { it: Str, it_var: Expr },
+}
+
+impl ForShape {
+    fn from_cond(cond: &Expr) -> Option<Self> {
+        let (counter, end) = match cond {
+            Expr::Op(Operation::Lt(a, b)) | Expr::Op(Operation::Gt(b, a)) => (a, b),
+            Expr::Call(call) if call.args.is_empty() => {
+                return match &call.fun {
+                    Expr::Field(obj, field) if &**field == "hasNext" => match obj.as_ref() {
+                        Expr::Variable(_, Some(it)) => Some(ForShape::Iterator {
+                            it: it.clone(),
+                            it_var: *obj.clone(),
+                        }),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+            }
+            _ => return None,
+        };
+        let Expr::Variable(_, Some(counter)) = counter.as_ref() else {
+            return None;
+        };
+        let counter = counter.clone();
+        Some(match end.as_ref() {
+            Expr::Field(array_var, field) if &**field == "length" => match array_var.as_ref() {
+                Expr::Variable(_, Some(array)) => ForShape::Array {
+                    counter,
+                    array: array.clone(),
+                    array_var: *array_var.clone(),
+                },
+                _ => return None,
+            },
+            end => ForShape::Counted {
+                counter,
+                end: end.clone(),
+            },
+        })
+    }
+
+    /// The variable only used to drive the loop
+    fn driver(&self) -> &Str {
+        match self {
+            ForShape::Counted { counter, .. } | ForShape::Array { counter, .. } => counter,
+            ForShape::Iterator { it, .. } => it,
+        }
+    }
+
+    /// The temporary variable holding the bound or the array, if any
+    fn source(&self) -> Option<&Str> {
+        let source = match self {
+            ForShape::Counted {
+                end: Expr::Variable(_, Some(end)),
+                ..
+            } => Some(end),
+            ForShape::Array { array, .. } => Some(array),
+            _ => None,
+        };
+        source.filter(|name| InlineTemporaries::is_temporary(name))
+    }
+}
+
+impl LoopIdioms {
+    /// Try to turn the while loop at `i` into a for loop.
+    /// Returns the new position of the loop if it has been rewritten.
+    fn rewrite_for(code: &Bytecode, block: &mut Vec<Statement>, i: usize) -> Option<usize> {
+        let Statement::While { cond, stmts } = &block[i] else {
+            return None;
+        };
+        let shape = ForShape::from_cond(cond)?;
+        let driver = shape.driver();
+
+        // The loop variable is assigned from the driver at the start of the body
+        let first = next_stmt(stmts, 0)?;
+        let Statement::Assign {
+            variable: variable @ Expr::Variable(_, Some(name)),
+            assign,
+            ..
+        } = &stmts[first]
+        else {
+            return None;
+        };
+        let assigned_ok = match (&shape, assign) {
+            (ForShape::Counted { .. }, value) => is_var(value, driver),
+            (ForShape::Array { array, .. }, Expr::Array(arr, index)) => {
+                is_var(arr, array) && is_var(index, driver)
+            }
+            (ForShape::Iterator { it, .. }, Expr::Call(call)) => {
+                call.args.is_empty() && is_method_call(&call.fun, it, "next")
+            }
+            _ => false,
+        };
+        if !assigned_ok {
+            return None;
+        }
+        // Followed by the counter increment
+        let incr = match shape {
+            ForShape::Iterator { .. } => None,
+            _ => {
+                let j = next_stmt(stmts, first + 1)?;
+                match &stmts[j] {
+                    // The decompiler sometimes names the counter after the variable it has just been copied to
+                    Statement::ExprStatement(Expr::Op(Operation::Incr(e)))
+                        if is_var(e, driver)
+                            || (matches!(shape, ForShape::Counted { .. }) && is_var(e, name)) =>
+                    {
+                        Some(j)
+                    }
+                    _ => return None,
+                }
+            }
+        };
+
+        // The driver must not be used by anything else, in the loop or after it
+        let mut body: Vec<Statement> = stmts
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| j != first && Some(j) != incr)
+            .map(|(_, s)| s.clone())
+            .collect();
+        if reads(code, &mut body, driver) > 0 || read_after(code, block, i, driver) {
+            return None;
+        }
+        // The bound of an interval is evaluated once, the condition of the while loop at each iteration
+        if let ForShape::Counted { end, .. } = &shape {
+            let fixed = match end {
+                Expr::Constant(_) => true,
+                Expr::Variable(_, Some(end)) => {
+                    InlineTemporaries::is_temporary(end)
+                        && VarUsage::collect(code, &mut body).writes(end) == 0
+                }
+                _ => false,
+            };
+            if !fixed {
+                return None;
+            }
+        }
+        let variable = variable.clone();
+
+        // Look for the initialization of the driver and of the loop source right before the loop
+        let source = shape.source().filter(|source| {
+            reads(code, &mut body, source) == 0 && !read_after(code, block, i, source)
+        });
+        let mut start = None;
+        let mut source_value = None;
+        let mut decls = Vec::new();
+        let mut j = i;
+        while let Some(prev) = (0..j).rev().find(|&k| block[k].is_code()) {
+            let Statement::Assign {
+                variable: Expr::Variable(_, Some(n)),
+                assign,
+                ..
+            } = &block[prev]
+            else {
+                break;
+            };
+            if n == driver && start.is_none() {
+                start = Some(assign.clone());
+            } else if source == Some(n) && source_value.is_none() {
+                source_value = Some(assign.clone());
+            } else {
+                break;
+            }
+            decls.push(prev);
+            j = prev;
+        }
+
+        let iterable = match shape {
+            ForShape::Counted { end, .. } => interval(start?, source_value.unwrap_or(end)),
+            ForShape::Array { array_var, .. } => {
+                if !matches!(start?, Expr::Constant(Constant::InlineInt(0))) {
+                    return None;
+                }
+                source_value.unwrap_or(array_var)
+            }
+            ForShape::Iterator { it, it_var } => match start {
+                // The iterator() call is implicit in a for loop
+                Some(Expr::Call(call))
+                    if call.args.is_empty()
+                        && matches!(&call.fun, Expr::Field(_, m) if &**m == "iterator") =>
+                {
+                    let Expr::Field(obj, _) = call.fun else {
+                        unreachable!()
+                    };
+                    *obj
+                }
+                Some(value) if InlineTemporaries::is_temporary(&it) => value,
+                // Keep the initialization, the iterator is a variable of its own
+                _ => {
+                    decls.clear();
+                    it_var
+                }
+            },
+        };
+
+        block[i] = Statement::For {
+            variable,
+            iterable,
+            stmts: body,
+        };
+        // Declarations have been found in decreasing order
+        for &d in &decls {
+            block.remove(d);
+        }
+        Some(i - decls.len())
+    }
+}
+
+impl AstVisitor for LoopIdioms {
+    fn visit_stmt(&mut self, _code: &Bytecode, stmt: &mut Statement) {
+        let Statement::While { cond, stmts } = stmt else {
+            return;
+        };
+        if !matches!(cond, Expr::Unknown(_)) {
+            return;
+        }
+        let first = stmts.iter().position(|s| s.is_code());
+        if let Some(i) = first {
+            if let Statement::IfElse {
+                cond: exit,
+                if_,
+                else_,
+            } = &stmts[i]
+            {
+                if else_.is_empty() && matches!(if_.as_slice(), [Statement::Break]) {
+                    *cond = not(exit.clone());
+                    stmts.remove(i);
+                    return;
+                }
+            }
+        }
+        *cond = cst_bool(true);
+    }
+
+    fn visit_block(&mut self, code: &Bytecode, block: &mut Vec<Statement>) {
+        let mut i = 0;
+        while i < block.len() {
+            i = Self::rewrite_for(code, block, i).unwrap_or(i) + 1;
+        }
+    }
+}
+
+/// Index of the first statement at or after `start` that isn't a comment
+fn next_stmt(stmts: &[Statement], start: usize) -> Option<usize> {
+    (start..stmts.len()).find(|&i| stmts[i].is_code())
+}
+
+fn is_var(expr: &Expr, name: &Str) -> bool {
+    matches!(expr, Expr::Variable(_, Some(n)) if n == name)
+}
+
+fn is_assign_to(stmt: &Statement, name: &Str) -> bool {
+    matches!(stmt, Statement::Assign { variable, .. } if is_var(variable, name))
+}
+
+/// Number of reads of a variable
+fn reads(code: &Bytecode, stmts: &mut Vec<Statement>, name: &Str) -> usize {
+    VarUsage::collect(code, stmts).reads(name)
+}
+
+/// The current value of a variable is read by the statements after `i`
+fn read_after(code: &Bytecode, block: &[Statement], i: usize, name: &Str) -> bool {
+    // Up to and including the next assignment, which may read the previous value
+    let end = block[i + 1..]
+        .iter()
+        .position(|s| is_assign_to(s, name))
+        .map_or(block.len(), |p| i + 2 + p);
+    reads(code, &mut block[i + 1..end].to_vec(), name) > 0
+}
+
+/// Replace the accesses to the environment of a closure (its first register) with the captured values.
+/// Only variables and constants are substituted, other values would be evaluated each time they are read.
+pub(crate) struct Captures<'a>(pub(crate) &'a [Expr]);
+
+impl AstVisitor for Captures<'_> {
+    fn visit_expr(&mut self, _code: &Bytecode, expr: &mut Expr) {
+        let captured = match expr {
+            // Named like the parameters of an enum constructor : _0, _1
+            Expr::Field(env, field) if matches!(**env, Expr::Variable(Reg(0), _)) => field
+                .strip_prefix('_')
+                .and_then(|i| i.parse::<usize>().ok())
+                .and_then(|i| self.0.get(i))
+                .filter(|v| matches!(v, Expr::Variable(..) | Expr::Constant(_)))
+                .cloned(),
+            _ => None,
+        };
+        if let Some(value) = captured {
+            *expr = value;
+        }
+    }
+}
+
+/// Reads and writes of each named variable in a function
+#[derive(Default)]
+pub(crate) struct VarUsage {
+    reads: HashMap<Str, usize>,
+    writes: HashMap<Str, usize>,
+    /// Closures can capture variables, which we don't track
+    has_closure: bool,
+}
+
+impl VarUsage {
+    fn collect(code: &Bytecode, stmts: &mut Vec<Statement>) -> Self {
+        let mut usage = Self::default();
+        visit(code, stmts, &mut [&mut usage]);
+        usage
+    }
+
+    fn reads(&self, name: &Str) -> usize {
+        self.reads.get(name).copied().unwrap_or(0)
+    }
+
+    fn writes(&self, name: &Str) -> usize {
+        self.writes.get(name).copied().unwrap_or(0)
+    }
+}
+
+impl AstVisitor for VarUsage {
+    fn visit_stmt(&mut self, _code: &Bytecode, stmt: &mut Statement) {
+        if let Statement::Assign {
+            variable: Expr::Variable(_, Some(name)),
+            ..
+        }
+        | Statement::For {
+            variable: Expr::Variable(_, Some(name)),
+            ..
+        } = stmt
+        {
+            // The assignment target has been counted as a read by visit_expr
+            if let Some(reads) = self.reads.get_mut(name) {
+                *reads = reads.saturating_sub(1);
+            }
+            *self.writes.entry(name.clone()).or_default() += 1;
+        }
+        // Also a read, already counted
+        if let Statement::CompoundAssign {
+            variable: Expr::Variable(_, Some(name)),
+            ..
+        } = stmt
+        {
+            *self.writes.entry(name.clone()).or_default() += 1;
+        }
+    }
+
+    fn visit_expr(&mut self, _code: &Bytecode, expr: &mut Expr) {
+        match expr {
+            Expr::Variable(_, Some(name)) => {
+                *self.reads.entry(name.clone()).or_default() += 1;
+            }
+            Expr::Op(Operation::Incr(e) | Operation::Decr(e)) => {
+                if let Expr::Variable(_, Some(name)) = &**e {
+                    *self.writes.entry(name.clone()).or_default() += 1;
+                }
+            }
+            Expr::Closure(_) => {
+                self.has_closure = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Remove assignments to variables that are never read.
+/// The assigned value is kept as an expression statement if evaluating it has side effects.
+pub(crate) struct DeadStores(pub(crate) VarUsage);
+
+impl AstVisitor for DeadStores {
+    fn visit_block(&mut self, _code: &Bytecode, block: &mut Vec<Statement>) {
+        block.retain_mut(|stmt| match stmt {
+            Statement::Assign {
+                variable: Expr::Variable(_, Some(name)),
+                assign,
+                ..
+            } if self.0.reads(name) == 0 => {
+                if has_side_effects(assign) {
+                    *stmt = Statement::ExprStatement(assign.clone());
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => true,
+        });
+    }
+}
+
+/// Inline compiler generated temporary variables used only once, in the statement following their declaration.
+/// ```haxe
+/// var _g = getList();
+/// _g.push(1);
+/// ```
+/// becomes :
+/// ```haxe
+/// getList().push(1);
+/// ```
+/// The value is only inlined if no side effect can happen between its declaration and its use.
+pub(crate) struct InlineTemporaries(pub(crate) VarUsage);
+
+impl InlineTemporaries {
+    /// Names of the temporaries generated by the Haxe compiler : `tmp`, `_g`, `_g1`, ...
+    fn is_temporary(name: &str) -> bool {
+        name == "tmp"
+            || name
+                .strip_prefix("_g")
+                .is_some_and(|n| n.chars().all(|c| c.is_ascii_digit()))
+    }
+}
+
+impl AstVisitor for InlineTemporaries {
+    fn visit_block(&mut self, code: &Bytecode, block: &mut Vec<Statement>) {
+        let mut i = 0;
+        while i < block.len() {
+            // Comments aren't code, we can look past them
+            let next = (i + 1..block.len()).find(|&j| block[j].is_code());
+            let inline = match (&block[i], next) {
+                (
+                    Statement::Assign {
+                        declaration: true,
+                        variable: Expr::Variable(_, Some(name)),
+                        ..
+                    },
+                    Some(next),
+                ) if Self::is_temporary(name)
+                    && self.0.reads(name) == 1
+                    && self.0.writes(name) == 1
+                    && stmt_read_before_effects(&block[next], name) == Some(true) =>
+                {
+                    Some((name.clone(), next))
+                }
+                _ => None,
+            };
+            if let Some((name, next)) = inline {
+                let Statement::Assign { assign, .. } = block.remove(i) else {
+                    unreachable!()
+                };
+                let mut substitute = Substitute {
+                    name,
+                    value: Some(assign),
+                };
+                // The next statement has moved back by one
+                let mut target = vec![block.remove(next - 1)];
+                visit(code, &mut target, &mut [&mut substitute]);
+                block.insert(next - 1, target.pop().unwrap());
+                // Look at the same position again, the statement we inlined into may be a temporary too
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Replace the first read of a variable with a value
+struct Substitute {
+    name: Str,
+    value: Option<Expr>,
+}
+
+impl AstVisitor for Substitute {
+    fn visit_expr(&mut self, _code: &Bytecode, expr: &mut Expr) {
+        if matches!(expr, Expr::Variable(_, Some(name)) if *name == self.name) {
+            if let Some(value) = self.value.take() {
+                *expr = value;
+            }
+        }
+    }
+}
+
+/// Operands of an operation
+fn operands(op: &Operation) -> (&Expr, Option<&Expr>) {
+    use Operation::*;
+    match op {
+        Add(a, b)
+        | Sub(a, b)
+        | Mul(a, b)
+        | Div(a, b)
+        | Mod(a, b)
+        | Shl(a, b)
+        | Shr(a, b)
+        | And(a, b)
+        | Or(a, b)
+        | Xor(a, b)
+        | Eq(a, b)
+        | NotEq(a, b)
+        | Gt(a, b)
+        | Gte(a, b)
+        | Lt(a, b)
+        | Lte(a, b)
+        | Interval(a, b)
+        | NullCoalesce(a, b) => (&**a, Some(&**b)),
+        Neg(a) | Not(a) | Incr(a) | Decr(a) => (&**a, None),
+    }
+}
+
+/// Conservative check : false if we are sure evaluating this expression can't have side effects
+pub(crate) fn has_side_effects(expr: &Expr) -> bool {
+    match expr {
+        Expr::Call(_) | Expr::Constructor(_) | Expr::IfElse { .. } | Expr::Unknown(_) => true,
+        // Throws if the value has another type
+        Expr::Cast(_, _, CastKind::Checked) => true,
+        Expr::Cast(e, _, _) => has_side_effects(e),
+        Expr::Op(Operation::Incr(_) | Operation::Decr(_)) => true,
+        Expr::Constant(_)
+        | Expr::FunRef(_)
+        | Expr::TypePath(_)
+        | Expr::Variable(_, _)
+        | Expr::Closure(_) => false,
+        Expr::Anonymous(_, fields) => fields.values().any(has_side_effects),
+        Expr::Array(arr, index) => has_side_effects(arr) || has_side_effects(index),
+        Expr::ArrayLiteral(values) => values.iter().any(has_side_effects),
+        Expr::MapLiteral(entries) => entries
+            .iter()
+            .any(|(k, v)| has_side_effects(k) || has_side_effects(v)),
+        Expr::EnumConstr(_, _, args) => args.iter().any(has_side_effects),
+        Expr::Field(obj, _) | Expr::SafeField(obj, _) => has_side_effects(obj),
+        Expr::Op(op) => {
+            let (a, b) = operands(op);
+            has_side_effects(a) || b.is_some_and(has_side_effects)
+        }
+    }
+}
+
+/// Look for the first read of a variable in evaluation order.
+/// Returns `Some(true)` if it's read before any side effect, `Some(false)` if a side effect (or a conditional evaluation)
+/// comes first, `None` if the expression has neither.
+fn read_before_effects(expr: &Expr, name: &Str) -> Option<bool> {
+    fn seq<'a>(exprs: impl IntoIterator<Item = &'a Expr>, name: &Str) -> Option<bool> {
+        exprs.into_iter().find_map(|e| read_before_effects(e, name))
+    }
+    match expr {
+        Expr::Variable(_, Some(n)) if n == name => Some(true),
+        Expr::Variable(_, _)
+        | Expr::Constant(_)
+        | Expr::FunRef(_)
+        | Expr::TypePath(_)
+        | Expr::Closure(_) => None,
+        // Unspecified order
+        Expr::Anonymous(_, fields) => seq(fields.values(), name).map(|_| false),
+        Expr::Array(arr, index) => seq([arr.as_ref(), index.as_ref()], name),
+        Expr::ArrayLiteral(values) => seq(values, name),
+        Expr::MapLiteral(entries) => seq(entries.iter().flat_map(|(k, v)| [k, v]), name),
+        Expr::Call(call) => seq(std::iter::once(&call.fun).chain(&call.args), name).or(Some(false)),
+        Expr::Constructor(ConstructorCall { args, .. }) => seq(args, name).or(Some(false)),
+        Expr::Cast(e, _, CastKind::Checked) => read_before_effects(e, name).or(Some(false)),
+        Expr::Cast(e, _, _) => read_before_effects(e, name),
+        Expr::EnumConstr(_, _, args) => seq(args, name),
+        Expr::Field(obj, _) | Expr::SafeField(obj, _) => read_before_effects(obj, name),
+        Expr::IfElse { cond, .. } => read_before_effects(cond, name).or(Some(false)),
+        Expr::Op(op @ (Operation::Incr(_) | Operation::Decr(_))) => {
+            read_before_effects(operands(op).0, name).or(Some(false))
+        }
+        // The right side of a boolean operator may not be evaluated
+        Expr::Op(Operation::And(a, b) | Operation::Or(a, b) | Operation::NullCoalesce(a, b)) => {
+            read_before_effects(a, name).or_else(|| read_before_effects(b, name).map(|_| false))
+        }
+        Expr::Op(op) => {
+            let (a, b) = operands(op);
+            seq(std::iter::once(a).chain(b), name)
+        }
+        Expr::Unknown(_) => Some(false),
+    }
+}
+
+/// [read_before_effects] for the parts of a statement evaluated first
+fn stmt_read_before_effects(stmt: &Statement, name: &Str) -> Option<bool> {
+    match stmt {
+        Statement::Assign {
+            variable, assign, ..
+        } => {
+            let target = match variable {
+                Expr::Variable(_, _) => None,
+                other => read_before_effects(other, name),
+            };
+            target.or_else(|| read_before_effects(assign, name))
+        }
+        Statement::CompoundAssign {
+            variable, value, ..
+        } => read_before_effects(variable, name).or_else(|| read_before_effects(value, name)),
+        Statement::ExprStatement(e) | Statement::Throw(e) | Statement::Return(Some(e)) => {
+            read_before_effects(e, name)
+        }
+        Statement::IfElse { cond, .. } => read_before_effects(cond, name),
+        Statement::Switch { arg, .. } => read_before_effects(arg, name),
+        Statement::For { iterable, .. } => read_before_effects(iterable, name),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::Builder;
+    use hlbc::types::{Function, RefField, RefFun, RefString, RefType, Reg, Type};
+    use hlbc::{Bytecode, Str};
+
+    use crate::ast::{
+        add, call, call_fun, comment, cst_bool, cst_null, cst_string, eq, incr, lt, noteq, stmt,
+        Closure, Constant, ConstructorCall, Expr, Operation, Statement,
+    };
+    use crate::fmt::FormatOptions;
+    use crate::post::{concat, is_string, run_passes, visit, Captures};
+    use crate::Pass;
+
+    fn var(reg: u32, name: &str) -> Expr {
+        Expr::Variable(Reg(reg), Some(Str::from(name)))
+    }
+
+    /// A function without registers, for the passes that don't look at their types
+    fn empty_fun() -> Function {
+        Function {
+            t: RefType(0),
+            findex: RefFun(0),
+            regs: Vec::new(),
+            ops: Vec::new(),
+            debug_info: None,
+            assigns: None,
+            name: RefString(0),
+            parent: None,
+        }
+    }
+
+    fn decl(variable: Expr, assign: Expr) -> Statement {
+        Statement::Assign {
+            declaration: true,
+            variable,
+            assign,
+        }
+    }
+
+    #[test]
+    fn closure_captures() {
+        let code = Bytecode::default();
+        let captures = [var(3, "x"), call_fun(RefFun(0), vec![])];
+        let mut closure = Closure {
+            fun: RefFun(1),
+            name: None,
+            has_env: true,
+            captures: captures.to_vec(),
+            stmts: vec![
+                comment("some call"),
+                Statement::Return(Some(add(
+                    Expr::Field(Box::new(Expr::Variable(Reg(0), None)), Str::from("_0")),
+                    Expr::Field(Box::new(Expr::Variable(Reg(0), None)), Str::from("_1")),
+                ))),
+            ],
+        };
+        visit(&code, &mut closure.stmts, &mut [&mut Captures(&captures)]);
+        let Some(Expr::Op(Operation::Add(x, y))) = closure.body_expr() else {
+            panic!("expected an addition, got {:?}", closure.stmts);
+        };
+        assert!(matches!(&**x, Expr::Variable(Reg(3), _)));
+        // The call isn't moved into the closure
+        assert!(matches!(&**y, Expr::Field(..)));
+    }
+
+    #[test]
+    fn dead_stores() {
+        let code = Bytecode::default();
+        let mut stmts = vec![
+            decl(var(0, "a"), cst_bool(true)),
+            decl(var(1, "b"), call_fun(RefFun(0), vec![])),
+            decl(var(2, "c"), cst_null()),
+            Statement::Return(Some(var(2, "c"))),
+        ];
+        run_passes(&code, &empty_fun(), &mut stmts, &[Pass::DeadStores]);
+        assert_eq!(stmts.len(), 3);
+        assert!(matches!(&stmts[0], Statement::ExprStatement(Expr::Call(_))));
+    }
+
+    #[test]
+    fn inline_temporaries() {
+        let code = Bytecode::default();
+        let mut stmts = vec![
+            decl(var(0, "_g"), call_fun(RefFun(0), vec![])),
+            Statement::Comment("comment".to_owned()),
+            stmt(call_fun(RefFun(1), vec![var(0, "_g")])),
+            // Not a temporary
+            decl(var(1, "a"), call_fun(RefFun(0), vec![])),
+            stmt(call_fun(RefFun(1), vec![var(1, "a")])),
+            // A side effect happens before the use
+            decl(var(2, "tmp"), call_fun(RefFun(0), vec![])),
+            stmt(call_fun(
+                RefFun(1),
+                vec![call_fun(RefFun(2), vec![]), var(2, "tmp")],
+            )),
+        ];
+        run_passes(&code, &empty_fun(), &mut stmts, &[Pass::InlineExpressions]);
+        assert_eq!(stmts.len(), 6);
+        let Statement::ExprStatement(Expr::Call(call)) = &stmts[1] else {
+            panic!("expected a call, got {:?}", stmts[1]);
+        };
+        assert!(matches!(&call.args[..], [Expr::Call(_)]));
+    }
+
+    #[test]
+    fn string_operands() {
+        let code = Bytecode::default();
+        let s = concat(&code, var(0, "a"), cst_string(RefString(0)));
+        assert!(is_string(&code, &s));
+        assert!(is_string(&code, &add(s, var(1, "b"))));
+        assert!(!is_string(&code, &add(var(0, "a"), var(1, "b"))));
+    }
+
+    #[test]
+    fn loop_condition() {
+        let code = Bytecode::default();
+        let mut stmts = vec![Statement::While {
+            cond: Expr::Unknown("no condition".to_owned()),
+            stmts: vec![
+                Statement::IfElse {
+                    cond: lt(var(0, "i"), Expr::Constant(Constant::InlineInt(10))),
+                    if_: vec![Statement::Break],
+                    else_: Vec::new(),
+                },
+                stmt(call_fun(RefFun(0), vec![])),
+            ],
+        }];
+        run_passes(&code, &empty_fun(), &mut stmts, &[Pass::LoopIdioms]);
+        let Statement::While { cond, stmts } = &stmts[0] else {
+            unreachable!()
+        };
+        assert!(matches!(cond, Expr::Op(_)));
+        assert_eq!(stmts.len(), 1);
+    }
+
+    fn int(i: i32) -> Expr {
+        Expr::Constant(Constant::InlineInt(i as usize))
+    }
+
+    #[test]
+    fn counted_for() {
+        let code = Bytecode::default();
+        let mut stmts = vec![
+            decl(var(0, "_g"), int(0)),
+            decl(var(1, "_g1"), var(5, "n")),
+            Statement::While {
+                cond: lt(var(0, "_g"), var(1, "_g1")),
+                stmts: vec![
+                    decl(var(2, "i"), var(0, "_g")),
+                    stmt(incr(var(0, "_g"))),
+                    stmt(call_fun(RefFun(0), vec![var(2, "i")])),
+                ],
+            },
+        ];
+        run_passes(&code, &empty_fun(), &mut stmts, &[Pass::LoopIdioms]);
+        assert_eq!(stmts.len(), 1);
+        let Statement::For {
+            variable: Expr::Variable(_, Some(name)),
+            iterable: Expr::Op(Operation::Interval(start, end)),
+            stmts,
+        } = &stmts[0]
+        else {
+            panic!("expected a for loop, got {:?}", stmts[0]);
+        };
+        assert_eq!(name, "i");
+        assert!(matches!(**start, Expr::Constant(Constant::InlineInt(0))));
+        assert!(matches!(&**end, Expr::Variable(_, Some(n)) if n == "n"));
+        assert_eq!(stmts.len(), 1);
+
+        // The bound may change while looping
+        let bounded_by = |end: Expr, body: Vec<Statement>| {
+            let mut stmts = vec![
+                decl(var(0, "_g"), int(0)),
+                Statement::While {
+                    cond: lt(var(0, "_g"), end),
+                    stmts: [
+                        vec![decl(var(2, "i"), var(0, "_g")), stmt(incr(var(0, "_g")))],
+                        body,
+                    ]
+                    .concat(),
+                },
+            ];
+            run_passes(&code, &empty_fun(), &mut stmts, &[Pass::LoopIdioms]);
+            matches!(stmts.last(), Some(Statement::For { .. }))
+        };
+        assert!(bounded_by(int(10), Vec::new()));
+        assert!(bounded_by(var(1, "_g1"), Vec::new()));
+        assert!(!bounded_by(
+            var(1, "_g1"),
+            vec![Statement::Assign {
+                declaration: false,
+                variable: var(1, "_g1"),
+                assign: int(0),
+            }]
+        ));
+        assert!(!bounded_by(var(5, "n"), Vec::new()));
+        assert!(!bounded_by(
+            Expr::Field(Box::new(var(5, "a")), Str::from("count")),
+            Vec::new()
+        ));
+    }
+
+    #[test]
+    fn iterator_for() {
+        let code = Bytecode::default();
+        let mut stmts = vec![
+            decl(
+                var(0, "_g"),
+                call(
+                    Expr::Field(Box::new(var(1, "list")), Str::from("iterator")),
+                    vec![],
+                ),
+            ),
+            Statement::While {
+                cond: call(
+                    Expr::Field(Box::new(var(0, "_g")), Str::from("hasNext")),
+                    vec![],
+                ),
+                stmts: vec![
+                    decl(
+                        var(2, "x"),
+                        call(
+                            Expr::Field(Box::new(var(0, "_g")), Str::from("next")),
+                            vec![],
+                        ),
+                    ),
+                    stmt(call_fun(RefFun(0), vec![var(2, "x")])),
+                ],
+            },
+            // The counter is still used, this isn't a for loop
+            Statement::While {
+                cond: lt(var(3, "j"), int(10)),
+                stmts: vec![decl(var(4, "k"), var(3, "j")), stmt(incr(var(3, "j")))],
+            },
+            Statement::Return(Some(var(3, "j"))),
+        ];
+        run_passes(&code, &empty_fun(), &mut stmts, &[Pass::LoopIdioms]);
+        assert_eq!(stmts.len(), 3);
+        assert!(matches!(
+            &stmts[0],
+            Statement::For { iterable: Expr::Variable(_, Some(n)), .. } if n == "list"
+        ));
+        assert!(matches!(&stmts[1], Statement::While { .. }));
+    }
+
+    #[test]
+    fn compound_assignments() {
+        let code = Bytecode::default();
+        let assign = |variable, assign| Statement::Assign {
+            declaration: false,
+            variable,
+            assign,
+        };
+        let mut stmts = vec![
+            assign(var(0, "score"), add(var(0, "score"), var(1, "bonus"))),
+            // Not commutative for strings
+            assign(var(0, "score"), add(var(1, "bonus"), var(0, "score"))),
+            decl(var(2, "x"), add(var(2, "x"), var(1, "bonus"))),
+        ];
+        run_passes(
+            &code,
+            &empty_fun(),
+            &mut stmts,
+            &[Pass::CompoundAssignments],
+        );
+        assert!(matches!(
+            &stmts[0],
+            Statement::CompoundAssign { op: "+=", value: Expr::Variable(_, Some(n)), .. } if n == "bonus"
+        ));
+        assert!(matches!(&stmts[1], Statement::Assign { .. }));
+        assert!(matches!(&stmts[2], Statement::Assign { .. }));
+    }
+
+    #[test]
+    fn boolean_operators() {
+        let mut b = Builder::new();
+        let bool = b.ty(Type::Bool);
+        let int = b.ty(Type::I32);
+        let fun = b.function("", &[], bool, &[bool, bool, bool, bool, int], Vec::new());
+        let code = b.build().unwrap();
+        let f = fun.as_fn(&code).unwrap();
+        let if_expr = |cond, if_, else_| Expr::IfElse {
+            cond: Box::new(cond),
+            if_: vec![stmt(if_)],
+            else_: vec![stmt(else_)],
+        };
+        let mut stmts = vec![
+            decl(
+                var(0, "a"),
+                if_expr(var(1, "x"), var(2, "y"), cst_bool(false)),
+            ),
+            decl(
+                var(0, "a"),
+                if_expr(var(1, "x"), cst_bool(true), var(2, "y")),
+            ),
+            // x && (y || z)
+            decl(
+                var(0, "a"),
+                if_expr(
+                    var(1, "x"),
+                    if_expr(var(2, "y"), cst_bool(true), var(3, "z")),
+                    cst_bool(false),
+                ),
+            ),
+            // Not a boolean
+            decl(
+                var(4, "n"),
+                if_expr(var(1, "x"), var(4, "n"), cst_bool(false)),
+            ),
+            decl(
+                var(0, "a"),
+                if_expr(var(1, "x"), cst_bool(true), var(9, "unknown")),
+            ),
+        ];
+        run_passes(&code, f, &mut stmts, &[Pass::BooleanOperators]);
+        let assigned: Vec<_> = stmts
+            .iter()
+            .map(|s| match s {
+                Statement::Assign { assign, .. } => assign,
+                _ => panic!("expected an assignment, got {s:?}"),
+            })
+            .collect();
+        assert!(matches!(assigned[0], Expr::Op(Operation::And(..))));
+        assert!(matches!(assigned[1], Expr::Op(Operation::Or(..))));
+        assert!(matches!(assigned[3], Expr::IfElse { .. }));
+        assert!(matches!(assigned[4], Expr::IfElse { .. }));
+        assert_eq!(
+            assigned[2]
+                .display(&FormatOptions::new(2), &code, f)
+                .to_string(),
+            "x && (y || z)"
+        );
+    }
+
+    #[test]
+    fn null_safety() {
+        let code = Bytecode::default();
+        let if_expr = |cond, if_, else_| Expr::IfElse {
+            cond: Box::new(cond),
+            if_: vec![stmt(if_)],
+            else_: vec![stmt(else_)],
+        };
+        let name = || Expr::Field(Box::new(var(1, "x")), Str::from("name"));
+        let mut stmts = vec![
+            decl(
+                var(0, "a"),
+                if_expr(noteq(var(1, "x"), cst_null()), var(1, "x"), var(2, "y")),
+            ),
+            decl(
+                var(0, "a"),
+                if_expr(
+                    eq(var(1, "x"), cst_null()),
+                    cst_null(),
+                    call(name(), vec![]),
+                ),
+            ),
+            // Not the checked value
+            decl(
+                var(0, "a"),
+                if_expr(noteq(var(1, "x"), cst_null()), var(2, "y"), var(1, "x")),
+            ),
+        ];
+        run_passes(&code, &empty_fun(), &mut stmts, &[Pass::NullSafety]);
+        assert!(matches!(
+            &stmts[0],
+            Statement::Assign {
+                assign: Expr::Op(Operation::NullCoalesce(..)),
+                ..
+            }
+        ));
+        let Statement::Assign {
+            assign: Expr::Call(call),
+            ..
+        } = &stmts[1]
+        else {
+            panic!("expected a call, got {:?}", stmts[1]);
+        };
+        assert!(matches!(&call.fun, Expr::SafeField(_, n) if n == "name"));
+        assert!(matches!(
+            &stmts[2],
+            Statement::Assign {
+                assign: Expr::IfElse { .. },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn map_literal() {
+        let mut b = Builder::new();
+        let map = b.class("haxe.ds.StringMap", None, &[]);
+        let [a, b_, c] = ["a", "b", "c"].map(|key| b.string(key));
+        let code = b.build().unwrap();
+        let set = |key, value| {
+            stmt(call(
+                Expr::Field(Box::new(var(0, "m")), Str::from("set")),
+                vec![cst_string(key), value],
+            ))
+        };
+        let mut stmts = vec![
+            decl(
+                var(0, "m"),
+                Expr::Constructor(ConstructorCall::new(map, Vec::new())),
+            ),
+            set(a, int(1)),
+            set(b_, int(2)),
+            // Reads the map
+            set(c, var(0, "m")),
+        ];
+        run_passes(&code, &empty_fun(), &mut stmts, &[Pass::MapLiterals]);
+        assert_eq!(stmts.len(), 2);
+        assert!(matches!(
+            &stmts[0],
+            Statement::Assign { assign: Expr::MapLiteral(entries), .. } if entries.len() == 2
+        ));
+    }
+
+    #[test]
+    fn std_calls() {
+        let mut b = Builder::new();
+        let dyn_ = b.ty(Type::Dyn);
+        let fun_t = b.fun_type(&[dyn_, dyn_], dyn_);
+        let type_ = b.class("$Type", None, &[("createInstance", fun_t)]);
+        let create_instance = b.function("createInstance", &[dyn_, dyn_], dyn_, &[], Vec::new());
+        b.bind(type_, RefField(0), create_instance);
+        let itos = b.native("std", "itos", &[dyn_, dyn_], dyn_);
+        let alloc = b.function("__alloc__", &[dyn_, dyn_], dyn_, &[], Vec::new());
+        let alloc_obj = b.native("std", "alloc_obj", &[dyn_], dyn_);
+        let concat = b.function("__add__", &[dyn_, dyn_], dyn_, &[], Vec::new());
+        let prefix = b.string("n = ");
+        let code = b.build().unwrap();
+
+        let to_string = || {
+            call_fun(
+                alloc,
+                vec![
+                    call_fun(itos, vec![var(0, "x"), var(1, "len")]),
+                    var(1, "len"),
+                ],
+            )
+        };
+        let mut stmts = vec![
+            decl(var(2, "s"), to_string()),
+            decl(
+                var(3, "t"),
+                call_fun(concat, vec![cst_string(prefix), to_string()]),
+            ),
+            decl(
+                var(4, "o"),
+                call_fun(create_instance, vec![var(5, "$Foo"), var(6, "args")]),
+            ),
+            decl(
+                var(4, "o"),
+                call_fun(
+                    alloc_obj,
+                    vec![Expr::Field(Box::new(var(5, "$Foo")), Str::from("__type__"))],
+                ),
+            ),
+            // Not the type of a class
+            decl(var(4, "o"), call_fun(alloc_obj, vec![var(7, "t")])),
+        ];
+        run_passes(
+            &code,
+            &empty_fun(),
+            &mut stmts,
+            &[Pass::StdIdioms, Pass::StringConcat, Pass::StdCalls],
+        );
+        let fopts = FormatOptions::new(2);
+        let f = empty_fun();
+        let lines: Vec<_> = stmts
+            .iter()
+            .map(|s| s.display(&fopts, &code, &f).to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "var s = Std.string(x);",
+                "var t = \"n = \" + x;",
+                "var o = Type.createInstance(Foo, args);",
+                "var o = Type.createEmptyInstance(Foo);",
+                "var o = alloc_obj(t);",
+            ]
+        );
+    }
+
+    #[test]
+    fn string_switch() {
+        let code = Bytecode::default();
+        let case = |s: usize, else_: Vec<Statement>| Statement::IfElse {
+            cond: eq(
+                call(
+                    Expr::Field(Box::new(var(0, "s")), Str::from("__compare")),
+                    vec![cst_string(RefString(s))],
+                ),
+                int(0),
+            ),
+            if_: vec![stmt(call_fun(RefFun(s), vec![]))],
+            else_,
+        };
+        let mut stmts = vec![case(
+            1,
+            vec![
+                Statement::Comment("comment".to_owned()),
+                case(2, vec![case(3, vec![stmt(call_fun(RefFun(0), vec![]))])]),
+            ],
+        )];
+        run_passes(&code, &empty_fun(), &mut stmts, &[Pass::Switches]);
+        let Statement::Switch {
+            arg: Expr::Variable(_, Some(arg)),
+            default,
+            cases,
+        } = &stmts[0]
+        else {
+            panic!("expected a switch, got {:?}", stmts[0]);
+        };
+        assert_eq!(arg, "s");
+        assert_eq!(default.len(), 1);
+        assert_eq!(cases.len(), 3);
+        assert!(matches!(
+            cases[2].0,
+            Expr::Constant(Constant::String(RefString(3)))
+        ));
+
+        // Too short to be worth a switch
+        let mut stmts = vec![case(1, vec![case(2, Vec::new())])];
+        run_passes(&code, &empty_fun(), &mut stmts, &[Pass::Switches]);
+        assert!(matches!(stmts[0], Statement::IfElse { .. }));
+    }
+}
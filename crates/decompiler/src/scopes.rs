@@ -127,6 +127,23 @@ impl Scopes {
         }
     }
 
+    /// Close every scope still open, when decompilation stops before the end of the function
+    pub(crate) fn close_all(&mut self) {
+        while self.scopes.len() > 1 {
+            let scope = self.scopes.pop().unwrap();
+            if let ScopeData::SwitchCase { pattern } = scope.data {
+                if let Some(ScopeData::Switch { cases, .. }) =
+                    self.scopes.last_mut().map(|s| &mut s.data)
+                {
+                    cases.push((pattern, scope.stmts));
+                }
+            } else {
+                let stmt = scope.make_stmt();
+                self.push_stmt(stmt);
+            }
+        }
+    }
+
     pub(crate) fn statements(mut self) -> Vec<Statement> {
         if let Some(Scope { stmts, data, .. }) = self.scopes.pop() {
             if matches!(data, ScopeData::Root) {
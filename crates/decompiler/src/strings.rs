@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use hlbc::types::RefFun;
+use hlbc::{Bytecode, Str};
+
+/// Constant argument of a call to a decoding function
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeArg {
+    Int(i32),
+    Float(f64),
+    Bool(bool),
+    String(Str),
+    Null,
+}
+
+impl DecodeArg {
+    pub fn as_int(&self) -> Option<i32> {
+        match *self {
+            DecodeArg::Int(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            DecodeArg::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// Turns the arguments of a call to a decoding function into the decoded string, `None` keeps the call
+pub type Decoder = Arc<dyn Fn(&[DecodeArg]) -> Option<String> + Send + Sync>;
+
+/// Decoders for the functions an obfuscated program calls to decode its string constants at runtime.
+///
+/// A call to a function with a decoder is replaced with the decoded string when all its arguments are constants.
+///
+/// ```
+/// # use hlbc::types::RefFun;
+/// use hlbc_decompiler::{DecodeArg, DecompilerOptions, StringDecoders};
+///
+/// // Obf.d("encoded", key) xors every char with the key
+/// let mut decoders = StringDecoders::default();
+/// decoders.register(RefFun(42), |args| {
+///     let [DecodeArg::String(s), DecodeArg::Int(key)] = args else {
+///         return None;
+///     };
+///     s.chars().map(|c| char::from_u32(c as u32 ^ *key as u32)).collect()
+/// });
+/// let opts = DecompilerOptions {
+///     string_decoders: decoders,
+///     ..DecompilerOptions::default()
+/// };
+/// ```
+#[derive(Clone, Default)]
+pub struct StringDecoders(HashMap<RefFun, Decoder>);
+
+impl StringDecoders {
+    /// Replace the calls to `fun` with the string returned by `decoder`
+    pub fn register(
+        &mut self,
+        fun: RefFun,
+        decoder: impl Fn(&[DecodeArg]) -> Option<String> + Send + Sync + 'static,
+    ) {
+        self.0.insert(fun, Arc::new(decoder));
+    }
+
+    /// Like [register](Self::register) with the name of the function, returns false if there is no such function
+    pub fn register_by_name(
+        &mut self,
+        code: &Bytecode,
+        name: &str,
+        decoder: impl Fn(&[DecodeArg]) -> Option<String> + Send + Sync + 'static,
+    ) -> bool {
+        match code.function_by_name(name) {
+            Some(f) => {
+                self.register(f.findex, decoder);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The decoded string of a call to `fun`, if it has a decoder and the decoder accepts the arguments
+    pub fn decode(&self, fun: RefFun, args: &[DecodeArg]) -> Option<String> {
+        self.0.get(&fun)?(args)
+    }
+}
+
+impl Debug for StringDecoders {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::types::RefFun;
+
+    use crate::{DecodeArg, StringDecoders};
+
+    #[test]
+    fn decode() {
+        let mut decoders = StringDecoders::default();
+        decoders.register(RefFun(1), |args| {
+            Some(args.first()?.as_str()?.chars().rev().collect())
+        });
+        let args = [DecodeArg::String("olleh".into())];
+        assert_eq!(decoders.decode(RefFun(1), &args).as_deref(), Some("hello"));
+        assert_eq!(decoders.decode(RefFun(2), &args), None);
+        assert_eq!(decoders.decode(RefFun(1), &[DecodeArg::Int(1)]), None);
+    }
+}
@@ -0,0 +1,470 @@
+//! Decompilation backend recovering the control flow from the control flow graph of a function.
+//!
+//! Conditions are delimited with post dominators : both branches of an `if` end where they join again.
+//! Loops are natural loops, found from the edges going back to a block dominating their source.
+//! When the graph can't be expressed with structured statements (e.g. a jump inside a loop from outside of it),
+//! blocks that were already emitted are reached with a [Statement::Goto].
+
+use std::collections::HashSet;
+
+use hlbc::analysis::cfg::{cfg, dominates, dominators, Cfg, EdgeKind};
+use hlbc::opcodes::Opcode;
+use hlbc::types::{Function, Reg};
+use hlbc::Bytecode;
+
+use crate::ast::{comment, not, Constant, Expr, Statement};
+use crate::post::{run_passes, visit, AstVisitor};
+use crate::{process_op, DecompilerOptions, DecompilerState, Diagnostic};
+
+/// A natural loop
+#[derive(Debug, Copy, Clone)]
+struct Loop {
+    header: usize,
+    /// First block executed after the loop
+    follow: Option<usize>,
+}
+
+struct Structurer<'c> {
+    state: DecompilerState<'c>,
+    cfg: Cfg,
+    /// Immediate post dominator of each block, None if it's the exit of the function
+    ipdom: Vec<Option<usize>>,
+    /// Loop starting at each block
+    loops: Vec<Option<Loop>>,
+    emitted: Vec<bool>,
+    /// Loops we are in, innermost last
+    loop_stack: Vec<Loop>,
+}
+
+impl Structurer<'_> {
+    /// Position of the first opcode of a block, used to name its label
+    fn start(&self, b: usize) -> usize {
+        self.cfg.blocks[b].ops.start
+    }
+
+    fn target(&self, b: usize, kind: EdgeKind) -> Option<usize> {
+        self.cfg
+            .successors(b)
+            .find(|e| e.kind == kind)
+            .map(|e| e.to)
+    }
+
+    /// Statement leaving the current loops to get to a block
+    fn exit(&self, b: usize) -> Option<Statement> {
+        let (innermost, outer) = self.loop_stack.split_last()?;
+        if b == innermost.header {
+            Some(Statement::Continue)
+        } else if innermost.follow == Some(b) {
+            Some(Statement::Break)
+        } else if outer.iter().any(|l| l.follow == Some(b)) {
+            Some(Statement::Goto(self.start(b)))
+        } else {
+            None
+        }
+    }
+
+    /// Emit the blocks from `b` following the control flow until `stop` is reached
+    fn region(&mut self, mut b: usize, stop: Option<usize>, out: &mut Vec<Statement>) {
+        loop {
+            if Some(b) == stop {
+                break;
+            }
+            if let Some(exit) = self.exit(b) {
+                out.push(exit);
+                break;
+            }
+            if self.emitted[b] {
+                out.push(Statement::Goto(self.start(b)));
+                break;
+            }
+            let next = if let Some(l) = self.loops[b] {
+                self.emit_loop(l, out);
+                l.follow
+            } else {
+                self.block(b, out)
+            };
+            match next {
+                Some(next) => b = next,
+                None => break,
+            }
+        }
+    }
+
+    fn emit_loop(&mut self, l: Loop, out: &mut Vec<Statement>) {
+        self.loop_stack.push(l);
+        let mut stmts = Vec::new();
+        if let Some(next) = self.block(l.header, &mut stmts) {
+            self.region(next, None, &mut stmts);
+        }
+        self.loop_stack.pop();
+        strip_continue(&mut stmts);
+        // Jumping to the header from outside means starting the loop again
+        out.push(stmts.remove(0));
+        // The exit condition is recovered by the loop idioms pass
+        out.push(Statement::While {
+            cond: Expr::Unknown("no condition".to_owned()),
+            stmts,
+        });
+    }
+
+    /// Emit the statements of a block and its terminator, returns the block to continue with
+    fn block(&mut self, b: usize, out: &mut Vec<Statement>) -> Option<usize> {
+        self.emitted[b] = true;
+        let ops = self.cfg.blocks[b].ops.clone();
+        out.push(Statement::Label(ops.start));
+
+        let f = self.state.f;
+        self.state.pending_ops = ops.start;
+        for i in ops.clone() {
+            if !is_control(&f.ops[i]) {
+                process_op(&mut self.state, i, &f.ops[i], 0);
+            }
+        }
+        self.state.pos = ops.end - 1;
+        if self.state.opts.interleave_opcodes {
+            self.state.flush_ops(ops.end);
+        }
+        out.append(&mut self.state.scopes.scopes[0].stmts);
+
+        match f.ops[ops.end - 1] {
+            Opcode::Ret { ret } => {
+                out.extend(self.state.source());
+                out.push(Statement::Return(if f.regtype(ret).is_void() {
+                    None
+                } else {
+                    Some(self.state.expr(ret))
+                }));
+                None
+            }
+            Opcode::Switch { reg, .. } => {
+                let arg = self.state.expr(reg);
+                // Only the switch statement is added to `out`
+                out.extend(self.state.source());
+                self.switch(b, arg, out)
+            }
+            Opcode::Trap { exc, .. } => self.trap(b, exc, out),
+            ref op => match self.state.branch_cond(op) {
+                Some(cond) => self.branch(b, cond, out),
+                None => self
+                    .target(b, EdgeKind::Jump)
+                    .or_else(|| self.target(b, EdgeKind::Next)),
+            },
+        }
+    }
+
+    /// Conditional jump, `cond` is the condition for the jump not to be taken
+    fn branch(&mut self, b: usize, cond: Expr, out: &mut Vec<Statement>) -> Option<usize> {
+        let (Some(t), Some(e)) = (
+            self.target(b, EdgeKind::True),
+            self.target(b, EdgeKind::False),
+        ) else {
+            return self
+                .target(b, EdgeKind::True)
+                .or_else(|| self.target(b, EdgeKind::False));
+        };
+        if t == e {
+            return Some(t);
+        }
+        let source = self.state.source();
+        if let Some(exit) = self.exit(t) {
+            out.extend(source);
+            out.push(Statement::IfElse {
+                cond: not(cond),
+                if_: vec![exit],
+                else_: Vec::new(),
+            });
+            return Some(e);
+        }
+        if let Some(exit) = self.exit(e) {
+            out.extend(source);
+            out.push(Statement::IfElse {
+                cond,
+                if_: vec![exit],
+                else_: Vec::new(),
+            });
+            return Some(t);
+        }
+
+        let merge = self.ipdom[b];
+        let mut if_ = Vec::new();
+        self.region(e, merge, &mut if_);
+        let mut else_ = Vec::new();
+        self.region(t, merge, &mut else_);
+        out.extend(source);
+        out.push(if if_.is_empty() {
+            Statement::IfElse {
+                cond: not(cond),
+                if_: else_,
+                else_: if_,
+            }
+        } else {
+            Statement::IfElse { cond, if_, else_ }
+        });
+        merge
+    }
+
+    fn switch(&mut self, b: usize, arg: Expr, out: &mut Vec<Statement>) -> Option<usize> {
+        let merge = self.ipdom[b];
+        let targets: Vec<_> = self
+            .cfg
+            .successors(b)
+            .filter_map(|e| match e.kind {
+                EdgeKind::Case(value) => Some((value, e.to)),
+                _ => None,
+            })
+            .collect();
+        // Cases sharing their target with a previous case end up as a goto
+        let mut cases = Vec::with_capacity(targets.len());
+        for (value, to) in targets {
+            let mut stmts = Vec::new();
+            self.region(to, merge, &mut stmts);
+            cases.push((Expr::Constant(Constant::InlineInt(value)), stmts));
+        }
+        let mut default = Vec::new();
+        if let Some(to) = self.target(b, EdgeKind::Default) {
+            self.region(to, merge, &mut default);
+        }
+        out.push(Statement::Switch {
+            arg,
+            default,
+            cases,
+        });
+        merge
+    }
+
+    fn trap(&mut self, b: usize, exc: Reg, out: &mut Vec<Statement>) -> Option<usize> {
+        let merge = self.ipdom[b];
+        let mut stmts = Vec::new();
+        if let Some(next) = self.target(b, EdgeKind::Next) {
+            self.region(next, merge, &mut stmts);
+        }
+        out.push(Statement::Try { stmts });
+        self.state.sources.remove(&exc);
+        self.state.reg_state.insert(exc, Expr::Variable(exc, None));
+        let mut stmts = Vec::new();
+        if let Some(handler) = self.target(b, EdgeKind::Catch) {
+            self.region(handler, merge, &mut stmts);
+        }
+        out.push(Statement::Catch { stmts });
+        merge
+    }
+}
+
+/// Opcodes only transferring control, they are translated from the shape of the graph
+fn is_control(op: &Opcode) -> bool {
+    matches!(
+        op,
+        Opcode::JTrue { .. }
+            | Opcode::JFalse { .. }
+            | Opcode::JNull { .. }
+            | Opcode::JNotNull { .. }
+            | Opcode::JSLt { .. }
+            | Opcode::JSGte { .. }
+            | Opcode::JSGt { .. }
+            | Opcode::JSLte { .. }
+            | Opcode::JULt { .. }
+            | Opcode::JUGte { .. }
+            | Opcode::JNotLt { .. }
+            | Opcode::JNotGte { .. }
+            | Opcode::JEq { .. }
+            | Opcode::JNotEq { .. }
+            | Opcode::JAlways { .. }
+            | Opcode::Switch { .. }
+            | Opcode::Label
+            | Opcode::Ret { .. }
+            | Opcode::Trap { .. }
+            | Opcode::EndTrap { .. }
+    )
+}
+
+/// Remove the `continue` ending the body of a loop, the loop does it anyway
+fn strip_continue(stmts: &mut Vec<Statement>) {
+    match stmts.last_mut() {
+        Some(Statement::Continue) => {
+            stmts.pop();
+        }
+        Some(Statement::IfElse { if_, else_, .. }) => {
+            strip_continue(if_);
+            strip_continue(else_);
+        }
+        _ => {}
+    }
+}
+
+/// Find the natural loops of a graph, indexed by their header
+fn natural_loops(
+    succs: &[Vec<usize>],
+    preds: &[Vec<usize>],
+    idom: &[Option<usize>],
+) -> Vec<Option<Loop>> {
+    let n = succs.len();
+    let mut loops = vec![None; n];
+    for (header, header_preds) in preds.iter().enumerate() {
+        let mut stack: Vec<_> = header_preds
+            .iter()
+            .copied()
+            .filter(|&u| idom[u].is_some() && dominates(idom, header, u))
+            .collect();
+        if stack.is_empty() {
+            continue;
+        }
+        let mut body = vec![false; n];
+        body[header] = true;
+        while let Some(u) = stack.pop() {
+            if !body[u] {
+                body[u] = true;
+                stack.extend(preds[u].iter().filter(|&&p| idom[p].is_some()));
+            }
+        }
+        let follow = (0..n)
+            .filter(|&b| body[b])
+            .flat_map(|b| succs[b].iter().copied())
+            .filter(|&s| !body[s])
+            .min();
+        loops[header] = Some(Loop { header, follow });
+    }
+    loops
+}
+
+/// Collect the targets of gotos
+#[derive(Default)]
+struct UsedLabels(HashSet<usize>);
+
+impl AstVisitor for UsedLabels {
+    fn visit_stmt(&mut self, _code: &Bytecode, stmt: &mut Statement) {
+        if let Statement::Goto(target) = stmt {
+            self.0.insert(*target);
+        }
+    }
+}
+
+/// Remove labels no goto jumps to
+struct PruneLabels(HashSet<usize>);
+
+impl AstVisitor for PruneLabels {
+    fn visit_block(&mut self, _code: &Bytecode, block: &mut Vec<Statement>) {
+        block.retain(|s| !matches!(s, Statement::Label(pos) if !self.0.contains(pos)));
+    }
+}
+
+/// Decompile a function by structuring its control flow graph
+pub(crate) fn decompile(
+    code: &Bytecode,
+    f: &Function,
+    opts: &DecompilerOptions,
+) -> (Vec<Statement>, Vec<Diagnostic>) {
+    let cfg = cfg(f);
+    let n = cfg.blocks.len();
+    if n == 0 {
+        return (Vec::new(), Vec::new());
+    }
+    let (succs, preds) = cfg.adjacency();
+    let idom = dominators(&succs, &preds, 0);
+
+    // Post dominators are the dominators of the reversed graph, starting from a virtual exit node
+    let exit = n;
+    let mut rsuccs = preds.clone();
+    rsuccs.push((0..n).filter(|&b| succs[b].is_empty()).collect());
+    let mut rpreds: Vec<_> = succs
+        .iter()
+        .map(|s| if s.is_empty() { vec![exit] } else { s.clone() })
+        .collect();
+    rpreds.push(Vec::new());
+    let ipdom = dominators(&rsuccs, &rpreds, exit)
+        .into_iter()
+        .take(n)
+        .map(|d| d.filter(|&d| d != exit))
+        .collect();
+
+    let mut structurer = Structurer {
+        state: DecompilerState::new(code, f, opts),
+        loops: natural_loops(&succs, &preds, &idom),
+        cfg,
+        ipdom,
+        emitted: vec![false; n],
+        loop_stack: Vec::new(),
+    };
+    let mut stmts = Vec::new();
+    structurer.region(0, None, &mut stmts);
+    // Do not display return void at the end
+    if matches!(stmts.last(), Some(Statement::Return(None))) {
+        stmts.pop();
+    }
+
+    let mut labels = UsedLabels::default();
+    visit(code, &mut stmts, &mut [&mut labels]);
+    if !labels.0.is_empty() {
+        stmts.insert(
+            0,
+            comment("Control flow that couldn't be structured is rendered with labels and gotos, this isn't valid Haxe"),
+        );
+    }
+    visit(code, &mut stmts, &mut [&mut PruneLabels(labels.0)]);
+
+    run_passes(code, f, &mut stmts, &opts.passes);
+    (stmts, structurer.state.diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::Builder;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{Reg, Type};
+
+    use crate::ast::Statement;
+    use crate::structured::decompile;
+    use crate::{Backend, DecompilerOptions};
+
+    fn decompile_ops(ops: Vec<Opcode>) -> Vec<Statement> {
+        let mut b = Builder::new();
+        let bool = b.ty(Type::Bool);
+        let int = b.ty(Type::I32);
+        b.function("", &[bool, int, int], int, &[], ops);
+        let code = b.build().unwrap();
+        let opts = DecompilerOptions {
+            passes: Vec::new(),
+            backend: Backend::Structured,
+            ..DecompilerOptions::default()
+        };
+        decompile(&code, &code.functions[0], &opts).0
+    }
+
+    #[test]
+    fn if_else() {
+        // if r0 { return r1 } else { return r2 }
+        let stmts = decompile_ops(vec![
+            Opcode::JFalse {
+                cond: Reg(0),
+                offset: 1,
+            },
+            Opcode::Ret { ret: Reg(1) },
+            Opcode::Ret { ret: Reg(2) },
+        ]);
+        assert!(matches!(
+            stmts.as_slice(),
+            [Statement::IfElse { if_, else_, .. }]
+                if matches!(if_.as_slice(), [Statement::Return(Some(_))])
+                    && matches!(else_.as_slice(), [Statement::Return(Some(_))])
+        ));
+    }
+
+    #[test]
+    fn irreducible() {
+        // A loop with 2 entries
+        let stmts = decompile_ops(vec![
+            Opcode::JFalse {
+                cond: Reg(0),
+                offset: 2,
+            },
+            Opcode::Nop,
+            Opcode::JAlways { offset: 0 },
+            Opcode::Nop,
+            Opcode::JAlways { offset: -4 },
+        ]);
+        assert!(matches!(
+            stmts.as_slice(),
+            [Statement::Comment(_), Statement::IfElse { else_, .. }]
+                if matches!(else_.as_slice(), [Statement::Goto(3)])
+        ));
+    }
+}
@@ -0,0 +1,1003 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use hlbc::fmt::EnhancedFmt;
+use hlbc::types::{Function, RefField, RefFun, RefType, Type};
+use hlbc::{Bytecode, Str};
+
+use crate::ast::{
+    CastKind, Class, Constant, ConstructorCall, Expr, Method, Operation, Param, Statement,
+};
+use crate::fmt::{
+    declared_type, enum_construct_name, enum_param_name, fun_name, to_haxe_type, FormatOptions,
+};
+use crate::params;
+
+/// What a token is, for syntax highlighting
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TokenKind {
+    Keyword,
+    /// Variable, function and field names
+    Ident,
+    /// Type names
+    Type,
+    /// Numbers, strings and booleans
+    Literal,
+    Operator,
+    /// Parentheses, braces, commas ...
+    Punctuation,
+    Comment,
+    /// Spaces, indentation and newlines
+    Whitespace,
+}
+
+/// Bytecode element a token refers to, where a click on it should navigate to
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum Origin {
+    #[default]
+    None,
+    Fun(RefFun),
+    Type(RefType),
+    /// A field of an object or of an anonymous structure
+    Field(RefType, RefField),
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub text: Str,
+    pub kind: TokenKind,
+    pub origin: Origin,
+    /// Function and opcodes the statement containing this token has been decompiled from, the opcodes producing the
+    /// values inlined in the statement included.
+    /// Only known when decompiled with [interleave_opcodes](crate::DecompilerOptions::interleave_opcodes).
+    pub ops: Option<(RefFun, Vec<usize>)>,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// A line of the output and the opcodes it has been decompiled from
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LineMapping {
+    /// Line number, starting at 0
+    pub line: usize,
+    pub fun: RefFun,
+    /// Positions of the opcodes, sorted
+    pub ops: Vec<usize>,
+}
+
+/// The text of the tokens, the same as the [Display] output of what they were rendered from
+pub fn text(tokens: &[Token]) -> String {
+    tokens.iter().map(|t| &*t.text).collect()
+}
+
+/// Map each line of the output to the opcodes it has been decompiled from, in order.
+pub fn source_map(tokens: &[Token]) -> Vec<LineMapping> {
+    let mut map: Vec<LineMapping> = Vec::new();
+    let mut line = 0;
+    for t in tokens {
+        if let Some((fun, ops)) = t.ops.clone().filter(|_| t.kind != TokenKind::Whitespace) {
+            let mapping = LineMapping { line, fun, ops };
+            if map.last() != Some(&mapping) {
+                map.push(mapping);
+            }
+        }
+        line += t.text.matches('\n').count();
+    }
+    map
+}
+
+/// Render the [ast](crate::ast) as a stream of [Token] instead of a string.
+///
+/// Tokens keep track of the bytecode elements they come from, so a viewer can highlight them and navigate to their
+/// definition. The text is the same as with the [Display] implementations of the [fmt](crate::fmt) module.
+///
+/// ```
+/// # use hlbc::Bytecode;
+/// # use hlbc_decompiler::fmt::FormatOptions;
+/// # use hlbc_decompiler::tokens::{source_map, text, TokenWriter};
+/// # let code = Bytecode::default();
+/// # let methods: Vec<hlbc_decompiler::ast::Method> = Vec::new();
+/// let mut writer = TokenWriter::new(&code);
+/// for m in &methods {
+///     writer.method(m, &FormatOptions::new(2));
+/// }
+/// let tokens = writer.finish();
+/// let source = text(&tokens);
+/// let map = source_map(&tokens);
+/// ```
+pub struct TokenWriter<'c> {
+    code: &'c Bytecode,
+    tokens: Vec<Token>,
+    /// Opcodes of the statement being written
+    ops: Option<(RefFun, Vec<usize>)>,
+    show_opcodes: bool,
+}
+
+impl<'c> TokenWriter<'c> {
+    pub fn new(code: &'c Bytecode) -> Self {
+        Self {
+            code,
+            tokens: Vec::new(),
+            ops: None,
+            show_opcodes: true,
+        }
+    }
+
+    /// Write the interleaved opcodes as comments, like [Display] does. Enabled by default.
+    /// When disabled, they are only used to find the origin of the statements.
+    pub fn with_opcodes(self, show_opcodes: bool) -> Self {
+        Self {
+            show_opcodes,
+            ..self
+        }
+    }
+
+    pub fn finish(self) -> Vec<Token> {
+        self.tokens
+    }
+
+    fn push(&mut self, kind: TokenKind, text: impl Into<Str>, origin: Origin) {
+        let text = text.into();
+        if !text.is_empty() {
+            self.tokens.push(Token {
+                text,
+                kind,
+                origin,
+                ops: self.ops.clone(),
+            });
+        }
+    }
+
+    fn ws(&mut self, text: impl Display) {
+        self.push(TokenKind::Whitespace, text.to_string(), Origin::None);
+    }
+
+    fn kw(&mut self, kw: &'static str) {
+        self.push(TokenKind::Keyword, Str::from_static(kw), Origin::None);
+    }
+
+    fn punct(&mut self, p: &'static str) {
+        self.push(TokenKind::Punctuation, Str::from_static(p), Origin::None);
+    }
+
+    /// Binary operator surrounded with spaces
+    fn op(&mut self, op: &'static str) {
+        self.ws(" ");
+        self.push(TokenKind::Operator, Str::from_static(op), Origin::None);
+        self.ws(" ");
+    }
+
+    fn ident(&mut self, name: impl Into<Str>, origin: Origin) {
+        self.push(TokenKind::Ident, name, origin);
+    }
+
+    fn ty(&mut self, ty: RefType, opts: &FormatOptions) {
+        let name = to_haxe_type(&self.code[ty], self.code, opts).to_string();
+        self.push(TokenKind::Type, name, Origin::Type(ty));
+    }
+
+    fn constant(&mut self, c: &Constant) {
+        let kind = match c {
+            Constant::Null | Constant::This => TokenKind::Keyword,
+            _ => TokenKind::Literal,
+        };
+        self.push(kind, c.display(self.code).to_string(), Origin::None);
+    }
+
+    fn join<T>(&mut self, items: impl IntoIterator<Item = T>, mut each: impl FnMut(&mut Self, T)) {
+        for (i, item) in items.into_iter().enumerate() {
+            if i > 0 {
+                self.punct(",");
+                self.ws(" ");
+            }
+            each(self, item);
+        }
+    }
+
+    fn params(&mut self, params: Vec<Param>, opts: &FormatOptions) {
+        self.join(params, |w, p| {
+            w.ident(p.name, Origin::None);
+            w.punct(":");
+            w.ws(" ");
+            w.ty(p.ty, opts);
+        });
+    }
+
+    /// Same as [Class::display]
+    pub fn class(&mut self, class: &Class, opts: &FormatOptions) {
+        let new_opts = opts.inc_nesting();
+        self.ws(opts);
+        self.kw("class");
+        self.ws(" ");
+        self.push(TokenKind::Type, class.name.clone(), Origin::None);
+        if let Some(parent) = &class.parent {
+            self.ws(" ");
+            self.kw("extends");
+            self.ws(" ");
+            self.push(TokenKind::Type, parent.clone(), Origin::None);
+        }
+        self.ws(" ");
+        self.punct("{");
+        self.ws("\n");
+        for f in &class.fields {
+            self.ws(&new_opts);
+            if f.static_ {
+                self.kw("static");
+                self.ws(" ");
+            }
+            self.kw("var");
+            self.ws(" ");
+            self.ident(f.name.clone(), Origin::None);
+            self.punct(":");
+            self.ws(" ");
+            self.ty(f.ty, opts);
+            if let Some(value) = &f.value {
+                self.op("=");
+                self.constant(value);
+            }
+            self.punct(";");
+            self.ws("\n");
+        }
+        for m in &class.methods {
+            self.ws("\n");
+            self.method(m, &new_opts);
+        }
+        self.ws(opts);
+        self.punct("}");
+    }
+
+    /// Same as [Method::display]
+    pub fn method(&mut self, method: &Method, opts: &FormatOptions) {
+        let code = self.code;
+        let fun = method.fun.as_fn(code).unwrap();
+        self.ops = None;
+        self.ws(opts);
+        if method.override_ {
+            self.kw("override");
+            self.ws(" ");
+        }
+        if method.static_ {
+            self.kw("static");
+            self.ws(" ");
+        }
+        if method.dynamic {
+            self.kw("dynamic");
+            self.ws(" ");
+        }
+        self.kw("function");
+        self.ws(" ");
+        self.ident(fun.name(code), Origin::Fun(fun.findex));
+        self.punct("(");
+        self.params(method.params(code), opts);
+        self.punct(")");
+        if !fun.ty(code).ret.is_void() {
+            self.punct(":");
+            self.ws(" ");
+            self.ty(fun.ty(code).ret, opts);
+        }
+        self.ws(" ");
+        self.punct("{");
+        if method.statements.is_empty() {
+            self.punct("}");
+        } else {
+            self.ws("\n");
+            self.block(&method.statements, &opts.inc_nesting(), fun);
+            self.ops = None;
+            self.ws(opts);
+            self.punct("}");
+        }
+        self.ws("\n");
+    }
+
+    /// Statements on their own line, indented with `opts`
+    fn block(&mut self, stmts: &[Statement], opts: &FormatOptions, f: &Function) {
+        // The end of the enclosing statement, like `} else {`, belongs to its own opcodes
+        let outer = self.ops.clone();
+        for stmt in stmts {
+            match stmt {
+                Statement::Source(ops) => {
+                    self.ops = Some((f.findex, ops.clone()));
+                    continue;
+                }
+                Statement::Opcode(pos) => {
+                    if self.show_opcodes {
+                        let ops = self.ops.replace((f.findex, vec![*pos]));
+                        self.ws(opts);
+                        self.stmt(stmt, opts, f);
+                        self.ws("\n");
+                        self.ops = ops;
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            self.ws(opts);
+            self.stmt(stmt, opts, f);
+            self.ws("\n");
+        }
+        self.ops = outer;
+    }
+
+    /// `{`, the statements then `}` on its own line
+    fn braces(&mut self, stmts: &[Statement], opts: &FormatOptions, f: &Function) {
+        self.punct("{");
+        self.ws("\n");
+        self.block(stmts, &opts.inc_nesting(), f);
+        self.ws(opts);
+        self.punct("}");
+    }
+
+    /// `kw (expr) `
+    fn header(&mut self, kw: &'static str, expr: &Expr, opts: &FormatOptions, f: &Function) {
+        self.kw(kw);
+        self.ws(" ");
+        self.punct("(");
+        self.expr(expr, opts, f);
+        self.punct(")");
+        self.ws(" ");
+    }
+
+    /// Same as [Statement::display]
+    fn stmt(&mut self, stmt: &Statement, opts: &FormatOptions, f: &Function) {
+        let code = self.code;
+        match stmt {
+            Statement::Assign {
+                declaration,
+                variable,
+                assign,
+            } => {
+                if *declaration {
+                    self.kw("var");
+                    self.ws(" ");
+                }
+                self.expr(variable, opts, f);
+                if let Some(ty) = declared_type(opts, code, f, *declaration, variable) {
+                    let origin = match variable {
+                        Expr::Variable(reg, _) => Origin::Type(f[*reg]),
+                        _ => Origin::None,
+                    };
+                    self.punct(":");
+                    self.ws(" ");
+                    self.push(TokenKind::Type, ty.to_string(), origin);
+                }
+                self.op("=");
+                self.expr(assign, opts, f);
+                self.punct(";");
+            }
+            Statement::CompoundAssign {
+                variable,
+                op,
+                value,
+            } => {
+                self.expr(variable, opts, f);
+                self.op(op);
+                self.expr(value, opts, f);
+                self.punct(";");
+            }
+            Statement::ExprStatement(expr) => {
+                self.expr(expr, opts, f);
+                self.punct(";");
+            }
+            Statement::Return(expr) => {
+                self.kw("return");
+                if let Some(e) = expr {
+                    self.ws(" ");
+                    self.expr(e, opts, f);
+                }
+                self.punct(";");
+            }
+            Statement::IfElse { cond, if_, else_ } => {
+                self.header("if", cond, opts, f);
+                self.braces(if_, opts, f);
+                if !else_.is_empty() {
+                    self.ws(" ");
+                    self.kw("else");
+                    self.ws(" ");
+                    self.braces(else_, opts, f);
+                }
+            }
+            Statement::Switch {
+                arg,
+                default,
+                cases,
+            } => {
+                let opts2 = opts.inc_nesting();
+                let opts3 = opts2.inc_nesting();
+                self.header("switch", arg, opts, f);
+                self.punct("{");
+                self.ws("\n");
+                if !default.is_empty() {
+                    self.ws(&opts2);
+                    self.kw("default");
+                    self.punct(":");
+                    self.ws("\n");
+                    self.block(default, &opts3, f);
+                }
+                for (pattern, stmts) in cases {
+                    self.ws(&opts2);
+                    self.kw("case");
+                    self.ws(" ");
+                    self.expr(pattern, opts, f);
+                    self.punct(":");
+                    self.ws("\n");
+                    self.block(stmts, &opts3, f);
+                }
+                self.ws(opts);
+                self.punct("}");
+            }
+            Statement::While { cond, stmts } => {
+                self.header("while", cond, opts, f);
+                self.braces(stmts, opts, f);
+            }
+            Statement::For {
+                variable,
+                iterable,
+                stmts,
+            } => {
+                self.kw("for");
+                self.ws(" ");
+                self.punct("(");
+                self.expr(variable, opts, f);
+                self.ws(" ");
+                self.kw("in");
+                self.ws(" ");
+                self.expr(iterable, opts, f);
+                self.punct(")");
+                self.ws(" ");
+                self.braces(stmts, opts, f);
+            }
+            Statement::Break => {
+                self.kw("break");
+                self.punct(";");
+            }
+            Statement::Continue => {
+                self.kw("continue");
+                self.punct(";");
+            }
+            Statement::Throw(exc) => {
+                self.kw("throw");
+                self.ws(" ");
+                self.expr(exc, opts, f);
+            }
+            Statement::Try { stmts } => {
+                self.kw("try");
+                self.ws(" ");
+                self.braces(stmts, opts, f);
+            }
+            Statement::Catch { stmts } => {
+                self.kw("catch");
+                self.ws(" ");
+                self.punct("()");
+                self.ws(" ");
+                self.braces(stmts, opts, f);
+            }
+            Statement::Label(pos) => {
+                self.ident(format!("L{pos}"), Origin::None);
+                self.punct(":");
+            }
+            Statement::Goto(pos) => {
+                self.kw("goto");
+                self.ws(" ");
+                self.ident(format!("L{pos}"), Origin::None);
+                self.punct(";");
+            }
+            Statement::Source(_) => {}
+            Statement::Opcode(_) | Statement::Comment(_) => {
+                self.push(
+                    TokenKind::Comment,
+                    stmt.display(opts, code, f).to_string(),
+                    Origin::None,
+                );
+            }
+        }
+    }
+
+    /// Same as [Expr::display]
+    fn expr(&mut self, expr: &Expr, opts: &FormatOptions, f: &Function) {
+        let code = self.code;
+        match expr {
+            Expr::Anonymous(ty, values) => match &code[*ty] {
+                Type::Virtual { fields } => {
+                    self.punct("{");
+                    self.join(fields.iter().enumerate(), |w, (i, field)| {
+                        w.ident(field.name(code), Origin::Field(*ty, RefField(i)));
+                        w.punct(":");
+                        w.ws(" ");
+                        w.expr(values.get(&RefField(i)).unwrap(), opts, f);
+                    });
+                    self.punct("}");
+                }
+                _ => self.push(
+                    TokenKind::Comment,
+                    Str::from_static("[invalid anonymous type]"),
+                    Origin::None,
+                ),
+            },
+            Expr::Array(array, index) => {
+                self.expr(array, opts, f);
+                self.punct("[");
+                self.expr(index, opts, f);
+                self.punct("]");
+            }
+            Expr::ArrayLiteral(values) => {
+                self.punct("[");
+                self.join(values, |w, e| w.expr(e, opts, f));
+                self.punct("]");
+            }
+            Expr::Call(call) => {
+                self.expr(&call.fun, opts, f);
+                self.args(&call.args, opts, f);
+            }
+            Expr::Cast(e, ty, kind) => match kind {
+                CastKind::Checked => {
+                    self.kw("cast");
+                    self.punct("(");
+                    self.expr(e, opts, f);
+                    self.punct(",");
+                    self.ws(" ");
+                    self.ty(*ty, opts);
+                    self.punct(")");
+                }
+                CastKind::Unchecked => {
+                    self.kw("cast");
+                    self.punct("(");
+                    self.expr(e, opts, f);
+                    self.punct(")");
+                }
+                CastKind::ToInt => {
+                    self.push(TokenKind::Type, Str::from_static("Std"), Origin::None);
+                    self.punct(".");
+                    self.ident(Str::from_static("int"), Origin::None);
+                    self.punct("(");
+                    self.expr(e, opts, f);
+                    self.punct(")");
+                }
+                CastKind::Implicit => {
+                    self.punct("(");
+                    self.expr(e, opts, f);
+                    self.op(":");
+                    self.ty(*ty, opts);
+                    self.punct(")");
+                }
+            },
+            Expr::Constant(c) => self.constant(c),
+            Expr::Constructor(ConstructorCall { ty, args }) => {
+                self.kw("new");
+                self.ws(" ");
+                self.push(
+                    TokenKind::Type,
+                    ty.display::<EnhancedFmt>(code).to_string(),
+                    Origin::Type(*ty),
+                );
+                self.args(args, opts, f);
+            }
+            Expr::Closure(closure) => {
+                let fun = closure.fun.as_fn(code).unwrap();
+                self.punct("(");
+                // The environment isn't an argument in the source
+                self.params(params(code, fun, usize::from(closure.has_env)), opts);
+                self.punct(")");
+                self.ws(" ");
+                self.push(
+                    TokenKind::Operator,
+                    Str::from_static("->"),
+                    Origin::Fun(closure.fun),
+                );
+                self.ws(" ");
+                if let Some(body) = closure.body_expr() {
+                    self.expr(body, opts, fun);
+                } else {
+                    self.braces(&closure.stmts, opts, fun);
+                }
+            }
+            Expr::EnumConstr(ty, constr, args) => {
+                self.ident(enum_construct_name(code, *ty, *constr), Origin::Type(*ty));
+                if !args.is_empty() {
+                    self.punct("(");
+                    self.join(args.iter().enumerate(), |w, (i, e)| {
+                        if opts.enum_param_names {
+                            w.ident(enum_param_name(i), Origin::None);
+                            w.punct(":");
+                            w.ws(" ");
+                        }
+                        w.expr(e, opts, f);
+                    });
+                    self.punct(")");
+                }
+            }
+            Expr::Field(receiver, name) => {
+                self.expr(receiver, opts, f);
+                self.punct(".");
+                self.ident(name.clone(), field_origin(code, f, receiver, name));
+            }
+            Expr::FunRef(fun) => {
+                self.ident(fun_name(code, *fun), Origin::Fun(*fun));
+            }
+            Expr::IfElse { cond, if_, else_ } => {
+                self.header("if", cond, opts, f);
+                self.braces(if_, opts, f);
+                self.ws(" ");
+                self.kw("else");
+                self.ws(" ");
+                self.braces(else_, opts, f);
+            }
+            Expr::MapLiteral(entries) => {
+                self.punct("[");
+                self.join(entries, |w, (k, v)| {
+                    w.expr(k, opts, f);
+                    w.op("=>");
+                    w.expr(v, opts, f);
+                });
+                self.punct("]");
+            }
+            Expr::Op(op) => self.operation(op, opts, f),
+            Expr::SafeField(receiver, name) => {
+                self.expr(receiver, opts, f);
+                self.punct("?.");
+                self.ident(name.clone(), field_origin(code, f, receiver, name));
+            }
+            Expr::TypePath(path) => {
+                self.push(TokenKind::Type, path.clone(), Origin::None);
+            }
+            Expr::Unknown(msg) => {
+                self.push(TokenKind::Comment, format!("[{msg}]"), Origin::None);
+            }
+            Expr::Variable(x, name) => {
+                let name = name.clone().unwrap_or_else(|| Str::from(x.to_string()));
+                self.ident(name, Origin::None);
+            }
+        }
+    }
+
+    fn args(&mut self, args: &[Expr], opts: &FormatOptions, f: &Function) {
+        self.punct("(");
+        self.join(args, |w, e| w.expr(e, opts, f));
+        self.punct(")");
+    }
+
+    /// Same as [Operation::display]
+    fn operation(&mut self, op: &Operation, opts: &FormatOptions, f: &Function) {
+        use Operation::*;
+        let (a, sym, b) = match op {
+            Add(a, b) => (a, "+", b),
+            Sub(a, b) => (a, "-", b),
+            Mul(a, b) => (a, "*", b),
+            Div(a, b) => (a, "/", b),
+            Mod(a, b) => (a, "%", b),
+            Shl(a, b) => (a, "<<", b),
+            Shr(a, b) => (a, ">>", b),
+            And(a, b) => (a, "&&", b),
+            Or(a, b) => (a, "||", b),
+            Xor(a, b) => (a, "^", b),
+            Eq(a, b) => (a, "==", b),
+            NotEq(a, b) => (a, "!=", b),
+            Gt(a, b) => (a, ">", b),
+            Gte(a, b) => (a, ">=", b),
+            Lt(a, b) => (a, "<", b),
+            Lte(a, b) => (a, "<=", b),
+            NullCoalesce(a, b) => (a, "??", b),
+            Interval(a, b) => {
+                self.operand(op, a, false, opts, f);
+                self.push(TokenKind::Operator, Str::from_static("..."), Origin::None);
+                self.operand(op, b, true, opts, f);
+                return;
+            }
+            Neg(e) | Not(e) => {
+                let sym = if matches!(op, Neg(_)) { "-" } else { "!" };
+                self.push(TokenKind::Operator, Str::from_static(sym), Origin::None);
+                self.operand(op, e, true, opts, f);
+                return;
+            }
+            Incr(e) | Decr(e) => {
+                let sym = if matches!(op, Incr(_)) { "++" } else { "--" };
+                self.operand(op, e, true, opts, f);
+                self.push(TokenKind::Operator, Str::from_static(sym), Origin::None);
+                return;
+            }
+        };
+        self.operand(op, a, false, opts, f);
+        self.op(sym);
+        self.operand(op, b, true, opts, f);
+    }
+
+    /// An operand of `op`, in parentheses if it binds less tightly
+    fn operand(
+        &mut self,
+        op: &Operation,
+        e: &Expr,
+        right: bool,
+        opts: &FormatOptions,
+        f: &Function,
+    ) {
+        let parens = op.needs_parens(e, right);
+        if parens {
+            self.punct("(");
+        }
+        self.expr(e, opts, f);
+        if parens {
+            self.punct(")");
+        }
+    }
+}
+
+/// The field accessed, when the type of the receiver is known
+fn field_origin(code: &Bytecode, f: &Function, receiver: &Expr, name: &Str) -> Origin {
+    let ty = match receiver {
+        Expr::Variable(reg, _) => f.regs.get(reg.0 as usize).copied(),
+        Expr::Constant(Constant::This) => f.regs.first().copied(),
+        _ => None,
+    };
+    let fields = match ty.map(|ty| (ty, &code[ty])) {
+        Some((ty, Type::Obj(obj) | Type::Struct(obj))) => Some((ty, &obj.fields)),
+        Some((ty, Type::Virtual { fields })) => Some((ty, fields)),
+        _ => None,
+    };
+    fields
+        .and_then(|(ty, fields)| {
+            let i = fields.iter().position(|field| field.name(code) == *name)?;
+            Some(Origin::Field(ty, RefField(i)))
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use hlbc::builder::Builder;
+    use hlbc::fmt::EnhancedFmt;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{
+        EnumConstruct, RefEnumConstruct, RefField, RefFun, RefGlobal, RefType, Reg, Type,
+    };
+    use hlbc::Bytecode;
+
+    use crate::ast::Statement;
+    use crate::fmt::FormatOptions;
+    use crate::tokens::{source_map, text, LineMapping, Origin, TokenKind, TokenWriter};
+    use crate::{decompile_function_with, facade, DecompilerOptions};
+
+    /// The tokens are rendered separately from the [Display] implementations, they must not drift apart
+    #[test]
+    fn same_text_as_display_all() {
+        let opts = DecompilerOptions {
+            interleave_opcodes: true,
+            ..DecompilerOptions::default()
+        };
+        for entry in fs::read_dir("../../data").unwrap() {
+            let path = entry.unwrap().path();
+            if let Some(ext) = path.extension() {
+                if ext == "hl" {
+                    let code = Bytecode::from_file(&path).unwrap();
+                    for f in &code.functions {
+                        assert_eq!(
+                            text(
+                                &facade::decompile_function_tokens(&code, f.findex, &opts).unwrap()
+                            ),
+                            facade::decompile_function(&code, f.findex, &opts).unwrap(),
+                            "{} in {}",
+                            f.name(&code),
+                            path.display()
+                        );
+                    }
+                    for (i, t) in code.types.iter().enumerate() {
+                        if matches!(t, Type::Obj(_)) {
+                            let ty = RefType(i);
+                            assert_eq!(
+                                text(&facade::decompile_class_tokens(&code, ty, &opts).unwrap()),
+                                facade::decompile_class(&code, ty, &opts).unwrap(),
+                                "{} in {}",
+                                t.display::<EnhancedFmt>(&code),
+                                path.display()
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn same_text_and_source_map() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let float = b.ty(Type::F64);
+        let fun = b.function(
+            "f",
+            &[float],
+            int,
+            &[int],
+            vec![
+                Opcode::ToInt {
+                    dst: Reg(1),
+                    src: Reg(0),
+                },
+                Opcode::Ret { ret: Reg(1) },
+            ],
+        );
+        let assigns = vec![(b.string("d"), 0), (b.string("x"), 1)];
+        b.function_mut(fun).assigns = Some(assigns);
+        let code = b.build().unwrap();
+        let f = &code.functions[0];
+        let opts = DecompilerOptions {
+            interleave_opcodes: true,
+            ..DecompilerOptions::default()
+        };
+        let method = decompile_function_with(&code, f, &opts);
+        let fmt = FormatOptions::new(2);
+
+        let mut writer = TokenWriter::new(&code);
+        writer.method(&method, &fmt);
+        let tokens = writer.finish();
+        assert_eq!(text(&tokens), method.display(&code, &fmt).to_string());
+        assert!(tokens
+            .iter()
+            .any(|t| t.kind == TokenKind::Type && t.origin == Origin::Type(int)));
+
+        let mut writer = TokenWriter::new(&code).with_opcodes(false);
+        writer.method(&method, &fmt);
+        let tokens = writer.finish();
+        assert_eq!(
+            text(&tokens),
+            "static function f(d: Float): Int {\n  var x: Int = Std.int(d);\n  return x;\n}\n"
+        );
+        assert_eq!(
+            source_map(&tokens),
+            [
+                LineMapping {
+                    line: 1,
+                    fun: RefFun(0),
+                    ops: vec![0]
+                },
+                LineMapping {
+                    line: 2,
+                    fun: RefFun(0),
+                    ops: vec![1]
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn enum_param_names() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let name = b.string("Color");
+        let rgb = b.string("Rgb");
+        let color = b.ty(Type::Enum {
+            name,
+            global: RefGlobal(0),
+            constructs: vec![EnumConstruct {
+                name: rgb,
+                params: vec![int; 3],
+            }],
+        });
+        let fun = b.function(
+            "f",
+            &[int, int, int],
+            color,
+            &[color],
+            vec![
+                Opcode::MakeEnum {
+                    dst: Reg(3),
+                    construct: RefEnumConstruct(0),
+                    args: vec![Reg(0), Reg(1), Reg(2)],
+                },
+                Opcode::Ret { ret: Reg(3) },
+            ],
+        );
+        let green = b.function(
+            "g",
+            &[color],
+            int,
+            &[int],
+            vec![
+                Opcode::EnumField {
+                    dst: Reg(1),
+                    value: Reg(0),
+                    construct: RefEnumConstruct(0),
+                    field: RefField(1),
+                },
+                Opcode::Ret { ret: Reg(1) },
+            ],
+        );
+        let code = b.build().unwrap();
+        let f = fun.as_fn(&code).unwrap();
+        let method = decompile_function_with(&code, f, &DecompilerOptions::default());
+        let fmt = FormatOptions::new(2).with_enum_param_names(true);
+
+        let mut writer = TokenWriter::new(&code);
+        writer.method(&method, &fmt);
+        let tokens = writer.finish();
+        assert_eq!(text(&tokens), method.display(&code, &fmt).to_string());
+        assert_eq!(
+            text(&tokens),
+            "static function f(arg0: Int, arg1: Int, arg2: Int): Color {\n  return Rgb(_0: arg0, _1: arg1, _2: arg2);\n}\n"
+        );
+
+        // Reading a parameter uses the same name
+        let g = green.as_fn(&code).unwrap();
+        let method = decompile_function_with(&code, g, &DecompilerOptions::default());
+        assert_eq!(
+            method.display(&code, &fmt).to_string(),
+            "static function g(arg0: Color): Int {\n  return arg0._1;\n}\n"
+        );
+    }
+
+    #[test]
+    fn statement_opcodes() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        let int = b.ty(Type::I32);
+        let one = b.int(1);
+        let log = b.native("std", "log", &[], void);
+        let fun = b.function(
+            "f",
+            &[int],
+            int,
+            &[int, int, void],
+            vec![
+                Opcode::Int {
+                    dst: Reg(1),
+                    ptr: one,
+                },
+                Opcode::Call0 {
+                    dst: Reg(3),
+                    fun: log,
+                },
+                Opcode::Add {
+                    dst: Reg(2),
+                    a: Reg(0),
+                    b: Reg(1),
+                },
+                Opcode::Ret { ret: Reg(2) },
+            ],
+        );
+        let a = b.string("a");
+        b.function_mut(fun).assigns = Some(vec![(a, 0)]);
+        let code = b.build().unwrap();
+        let f = fun.as_fn(&code).unwrap();
+        let opts = DecompilerOptions {
+            interleave_opcodes: true,
+            ..DecompilerOptions::default()
+        };
+        let method = decompile_function_with(&code, f, &opts);
+        // The constant is inlined in the return, past the call
+        let sources: Vec<_> = method
+            .statements
+            .iter()
+            .filter_map(|s| match s {
+                Statement::Source(ops) => Some(ops.as_slice()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sources, [&[1][..], &[0, 2, 3]]);
+
+        let mut writer = TokenWriter::new(&code).with_opcodes(false);
+        writer.method(&method, &FormatOptions::new(2));
+        let tokens = writer.finish();
+        assert_eq!(
+            text(&tokens),
+            "static function f(a: Int): Int {\n  // log@0\n  log();\n  return a + 1;\n}\n"
+        );
+        assert_eq!(
+            source_map(&tokens),
+            [
+                LineMapping {
+                    line: 2,
+                    fun,
+                    ops: vec![1]
+                },
+                LineMapping {
+                    line: 3,
+                    fun,
+                    ops: vec![0, 2, 3]
+                }
+            ]
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::{HashMap, HashSet};
+
+use hlbc::types::{RefType, Type};
+use hlbc::{Bytecode, Resolve, Str};
+
+use crate::module::split_path;
+
+/// Names of common structures, matched on their sorted field names
+const KNOWN_SHAPES: &[(&[&str], &str)] = &[
+    (&["x", "y"], "Point"),
+    (&["x", "y", "z"], "Point3"),
+    (&["height", "width"], "Size"),
+    (&["h", "w"], "Size"),
+    (&["height", "width", "x", "y"], "Rect"),
+    (&["h", "w", "x", "y"], "Rect"),
+    (&["b", "g", "r"], "Rgb"),
+    (&["a", "b", "g", "r"], "Rgba"),
+    (&["max", "min"], "Range"),
+    (&["key", "value"], "KeyValue"),
+];
+
+/// Fields of an anonymous structure, virtual types with the same fields are the same structure
+type Shape = Vec<(Str, RefType)>;
+
+fn shape(code: &Bytecode, ty: &Type) -> Option<Shape> {
+    match ty {
+        Type::Virtual { fields } => Some(fields.iter().map(|f| (f.name(code), f.t)).collect()),
+        _ => None,
+    }
+}
+
+/// Name of a common structure with the same field names
+fn known_shape(shape: &Shape) -> Option<&'static str> {
+    let mut names: Vec<&str> = shape.iter().map(|(name, _)| &**name).collect();
+    names.sort_unstable();
+    KNOWN_SHAPES
+        .iter()
+        .find(|(fields, _)| *fields == names.as_slice())
+        .map(|&(_, name)| name)
+}
+
+/// Typedef names of the anonymous structures (virtual types), which have no name in the bytecode.
+///
+/// Identical structures are declared as many times as they are used, they are clustered here so every decompiled file
+/// refers to them with the same name. Give it to [FormatOptions](crate::fmt::FormatOptions::with_typedefs) to display
+/// structure types with their name instead of `Dynamic`.
+#[derive(Debug, Clone, Default)]
+pub struct TypedefNames {
+    names: HashMap<Shape, Str>,
+    /// First virtual type of each structure, in bytecode order
+    types: Vec<RefType>,
+}
+
+impl TypedefNames {
+    /// Number of virtual types considered by [Self::infer] when there is no reason to choose
+    pub const DEFAULT_BUDGET: usize = 10_000;
+
+    /// Cluster the virtual types of the whole program. A structure looking like a common one (`{ x, y }` is a `Point`)
+    /// is named after it, the others are named `Anon<type index>`. Names already used by a class or an enum get a
+    /// number appended.
+    ///
+    /// Only the first `budget` virtual types are considered to keep huge programs fast, the others stay `Dynamic`.
+    pub fn infer(code: &Bytecode, budget: usize) -> Self {
+        let mut taken: HashSet<Str> = code
+            .types
+            .iter()
+            .filter_map(|t| match t {
+                Type::Obj(obj) => Some(obj.name(code)),
+                Type::Enum { name, .. } if !name.is_null() => Some(code.get(*name)),
+                _ => None,
+            })
+            .map(|name| Str::from(split_path(name.trim_start_matches('$')).1))
+            .collect();
+
+        let mut typedefs = Self::default();
+        let virtuals = code
+            .types
+            .iter()
+            .enumerate()
+            .filter_map(|(i, t)| Some((RefType(i), shape(code, t)?)))
+            .take(budget);
+        for (ty, shape) in virtuals {
+            if typedefs.names.contains_key(&shape) {
+                continue;
+            }
+            let base = known_shape(&shape)
+                .map(Str::from_static)
+                .unwrap_or_else(|| Str::from(format!("Anon{}", ty.0)));
+            let mut name = base.clone();
+            let mut n = 2;
+            while taken.contains(&name) {
+                name = Str::from(format!("{base}{n}"));
+                n += 1;
+            }
+            taken.insert(name.clone());
+            typedefs.names.insert(shape, name);
+            typedefs.types.push(ty);
+        }
+        typedefs
+    }
+
+    /// Typedef name of a structure type, `None` if it is not a named virtual type
+    pub fn name(&self, code: &Bytecode, ty: &Type) -> Option<Str> {
+        self.names.get(&shape(code, ty)?).cloned()
+    }
+
+    /// Name the structure of `ty` and every structure identical to it. Returns `false` if it is not a named virtual
+    /// type. The name should not be already used by another type.
+    pub fn rename(&mut self, code: &Bytecode, ty: RefType, name: &str) -> bool {
+        let Some(shape) = code.try_get(ty).ok().and_then(|t| shape(code, t)) else {
+            return false;
+        };
+        match self.names.get_mut(&shape) {
+            Some(old) => {
+                *old = Str::from(name);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Each structure once with its name and its first virtual type, in bytecode order
+    pub fn typedefs<'a>(&'a self, code: &'a Bytecode) -> impl Iterator<Item = (Str, RefType)> + 'a {
+        self.types
+            .iter()
+            .filter_map(move |&ty| Some((self.name(code, &code[ty])?, ty)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use hlbc::builder::Builder;
+    use hlbc::types::{ObjField, RefType, Type};
+    use hlbc::Str;
+
+    use crate::fmt::{to_haxe_type, FormatOptions};
+    use crate::TypedefNames;
+
+    #[test]
+    fn cluster_structures() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let [x, y, w] = ["x", "y", "w"].map(|name| ObjField {
+            name: b.string(name),
+            t: int,
+        });
+        let point = Type::Virtual { fields: vec![x, y] };
+        let first = b.ty(point.clone());
+        let anon = b.ty(Type::Virtual { fields: vec![w] });
+        // The same structure again, the builder would pool it
+        let types = &mut b.code_mut().types;
+        types.push(point);
+        let second = RefType(types.len() - 1);
+        let class = b.class("Point", None, &[]);
+        let code = b.build().unwrap();
+        let mut names = TypedefNames::infer(&code, TypedefNames::DEFAULT_BUDGET);
+        let name = |names: &TypedefNames, t: RefType| names.name(&code, &code.types[t.0]);
+        // The class already has the name
+        assert_eq!(name(&names, first), Some(Str::from("Point2")));
+        assert_eq!(name(&names, second), Some(Str::from("Point2")));
+        assert_eq!(name(&names, anon), Some(Str::from("Anon3")));
+        assert_eq!(name(&names, int), None);
+
+        assert!(names.rename(&code, second, "Vec2"));
+        assert!(!names.rename(&code, class, "Vec2"));
+        let typedefs: Vec<_> = names.typedefs(&code).collect();
+        assert_eq!(
+            typedefs,
+            [(Str::from("Vec2"), first), (Str::from("Anon3"), anon)]
+        );
+        let opts = FormatOptions::new(2).with_typedefs(Arc::new(names));
+        assert_eq!(
+            to_haxe_type(&code.types[second.0], &code, &opts).to_string(),
+            "Vec2"
+        );
+
+        let names = TypedefNames::infer(&code, 1);
+        assert_eq!(name(&names, anon), None);
+    }
+}
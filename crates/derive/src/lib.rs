@@ -64,6 +64,52 @@ pub fn derive_opcode_helper(input: proc_macro::TokenStream) -> proc_macro::Token
         }
     });
 
+    let asm_write = variants.iter().map(|v| {
+        let vname = &v.ident;
+        let fname: Vec<_> = v.fields.iter().map(|f| &f.ident).collect();
+        quote! {
+            #name::#vname { #( #fname, )* } => {
+                #(
+                    w__.write_char(' ')?;
+                    crate::asm::AsmOperand::write_asm(#fname, w__)?;
+                )*
+            }
+        }
+    });
+    let asm_parse = variants.iter().map(|v| {
+        let vname = &v.ident;
+        let vname_str = LitStr::new(&v.ident.to_string(), v.ident.span());
+        let fname = v.fields.iter().map(|f| &f.ident);
+        quote! {
+            #vname_str => Ok(#name::#vname {
+                #( #fname: crate::asm::AsmOperand::parse_asm(lex__)?, )*
+            }),
+        }
+    });
+
+    let registers = variants.iter().map(|v| {
+        let vname = &v.ident;
+        let (fname, push): (Vec<_>, Vec<_>) = v
+            .fields
+            .iter()
+            .filter_map(|f| {
+                let fname = f.ident.as_ref().unwrap();
+                let fname_str = LitStr::new(&fname.to_string(), fname.span());
+                let push = match ident(&f.ty).as_str() {
+                    "Reg" => quote! { regs__.push((#fname_str, *#fname)); },
+                    "Vec<Reg>" => {
+                        quote! { regs__.extend(#fname.iter().map(|r| (#fname_str, *r))); }
+                    }
+                    _ => return None,
+                };
+                Some((fname, push))
+            })
+            .unzip();
+        quote! {
+            #name::#vname { #( #fname, )* .. } => { #( #push )* }
+        }
+    });
+
     proc_macro::TokenStream::from(quote! {
         impl #name {
             /// Decode an instruction
@@ -108,6 +154,35 @@ pub fn derive_opcode_helper(input: proc_macro::TokenStream) -> proc_macro::Token
                 }
             }
 
+            #[cfg(feature = "disasm")]
+            /// Write the operands in the assembly syntax, each one preceded by a space
+            pub(crate) fn write_asm_operands(&self, w__: &mut std::fmt::Formatter) -> std::fmt::Result {
+                use std::fmt::Write;
+
+                match self {
+                    #( #asm_write )*
+                }
+                Ok(())
+            }
+
+            #[cfg(feature = "disasm")]
+            /// Parse the operands of the opcode named `name` in the assembly syntax
+            pub(crate) fn parse_asm_operands(name: &str, lex__: &mut crate::asm::Lexer) -> crate::Result<#name> {
+                match name {
+                    #( #asm_parse )*
+                    _ => Err(lex__.error(format!("Unknown opcode '{}'", name))),
+                }
+            }
+
+            /// Every register operand with the name of its field, in declaration order
+            pub fn registers(&self) -> Vec<(&'static str, crate::types::Reg)> {
+                let mut regs__ = Vec::new();
+                match self {
+                    #( #registers )*
+                }
+                regs__
+            }
+
             /// Get an opcode from its name. Returns a default value for the variant.
             pub fn from_name(name: &str) -> Option<Self> {
                 match name {
@@ -1,5 +1,30 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+#[cfg(not(target_arch = "wasm32"))]
+use std::{env, fs};
+
+#[cfg(not(target_arch = "wasm32"))]
+use eframe::egui::FontData;
 use eframe::egui::{FontDefinitions, FontFamily};
 
+/// Environment variable listing additional font files, separated like `PATH`
+#[cfg(not(target_arch = "wasm32"))]
+const FONTS_ENV: &str = "HLBC_FONTS";
+
+/// System fonts covering CJK characters, the first one found is used.
+/// Emojis are already covered by the default egui fonts.
+#[cfg(not(target_arch = "wasm32"))]
+const SYSTEM_CJK_FONTS: &[&str] = &[
+    "C:\\Windows\\Fonts\\msyh.ttc",
+    "C:\\Windows\\Fonts\\YuGothR.ttc",
+    "/System/Library/Fonts/PingFang.ttc",
+    "/System/Library/Fonts/Hiragino Sans GB.ttc",
+    "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/google-noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/truetype/wqy/wqy-microhei.ttc",
+];
+
 pub(crate) fn fonts() -> FontDefinitions {
     let mut fonts = FontDefinitions::default();
     let custom = egui_ui_refresh::fonts::fonts();
@@ -32,5 +57,46 @@ pub(crate) fn fonts() -> FontDefinitions {
         );
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    add_fallbacks(&mut fonts, &fallback_fonts());
+
     fonts
 }
+
+/// Font files to use for the characters missing from the UI fonts (game strings are often in CJK) :
+/// the files listed in `HLBC_FONTS` then a CJK font installed with the system.
+#[cfg(not(target_arch = "wasm32"))]
+fn fallback_fonts() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = env::var_os(FONTS_ENV)
+        .map(|list| env::split_paths(&list).collect())
+        .unwrap_or_default();
+    paths.extend(
+        SYSTEM_CJK_FONTS
+            .iter()
+            .map(PathBuf::from)
+            .find(|path| path.is_file()),
+    );
+    paths
+}
+
+/// Add fonts at the end of the proportional and monospace families,
+/// so they are only used for the characters the other fonts don't have.
+#[cfg(not(target_arch = "wasm32"))]
+fn add_fallbacks(fonts: &mut FontDefinitions, paths: &[PathBuf]) {
+    for path in paths {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("Can't load font {}: {e}", path.display());
+                continue;
+            }
+        };
+        let name = path.display().to_string();
+        fonts
+            .font_data
+            .insert(name.clone(), FontData::from_owned(data));
+        for family in [FontFamily::Proportional, FontFamily::Monospace] {
+            fonts.families.entry(family).or_default().push(name.clone());
+        }
+    }
+}
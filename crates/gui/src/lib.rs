@@ -9,8 +9,9 @@ use hlbc::Bytecode;
 
 use crate::model::{AppCtx, AppCtxHandle};
 use crate::views::{
-    AppView, ClassesView, DefaultAppView, DynamicTabViewer, FilesView, FunctionsView, GlobalsView,
-    InfoView, StringsView, SyncInspectorView, ViewWithId,
+    AppView, ClassesView, DefaultAppView, DynamicTabViewer, FileLayoutView, FilesView,
+    FunctionsView, GlobalsView, InfoView, PaneContent, PaneView, SourceMapView, StringsView,
+    SyncInspectorView, ViewWithId,
 };
 
 mod about;
@@ -90,6 +91,9 @@ impl eframe::App for App {
             if let Some(tab) = self.ctx.as_ref().and_then(|app| app.take_tab_to_open()) {
                 self.dock_state.main_surface_mut().push_to_focused_leaf(tab);
             }
+            if let Some(tab) = self.ctx.as_ref().and_then(|app| app.take_split_to_open()) {
+                Self::split_focused(&mut self.dock_state, tab, Split::Right);
+            }
         }
 
         // UI
@@ -205,6 +209,22 @@ impl App {
         }
     }
 
+    /// Split the focused leaf to show `tab` next to it
+    fn split_focused(dock: &mut DockState<Box<dyn AppView>>, tab: Box<dyn AppView>, split: Split) {
+        let tree = dock.main_surface_mut();
+        match tree.focused_leaf() {
+            Some(leaf) => {
+                match split {
+                    Split::Left => tree.split_left(leaf, 0.5, vec![tab]),
+                    Split::Right => tree.split_right(leaf, 0.5, vec![tab]),
+                    Split::Above => tree.split_above(leaf, 0.5, vec![tab]),
+                    Split::Below => tree.split_below(leaf, 0.5, vec![tab]),
+                };
+            }
+            None => tree.push_to_focused_leaf(tab),
+        }
+    }
+
     #[cfg(feature = "examples")]
     fn load_examples_button(&mut self, ui: &mut Ui) {
         ui.menu_button("Load example", |ui| {
@@ -251,7 +271,7 @@ impl App {
                 if let Some(file) = rfd::FileDialog::new().pick_file() {
                     Ok(Some((
                         file.display().to_string(),
-                        Bytecode::from_file(file)?,
+                        hlbc_decompiler::facade::open(file)?,
                     )))
                 } else {
                     Ok(None)
@@ -313,6 +333,12 @@ impl App {
                                 "Files",
                                 || Box::new(FilesView::new(ctx.code())),
                             );
+                            Self::view_button::<FileLayoutView>(
+                                &mut self.dock_state,
+                                ui,
+                                "File layout",
+                                || Box::new(FileLayoutView::new(ctx)),
+                            );
                             Self::view_button_default::<GlobalsView>(
                                 &mut self.dock_state,
                                 ui,
@@ -323,6 +349,11 @@ impl App {
                                 ui,
                                 "Strings",
                             );
+                            Self::view_button_default::<SourceMapView>(
+                                &mut self.dock_state,
+                                ui,
+                                "Source map",
+                            );
                             #[cfg(feature = "search")]
                             if ui.button("Search").clicked() {
                                 self.dock_state
@@ -331,6 +362,36 @@ impl App {
                                         ctx.code(),
                                     )));
                             }
+                            ui.separator();
+                            let selected = ctx.selected();
+                            if ui
+                                .button("Split right")
+                                .on_hover_text(
+                                    "Open the selection in a pane with its own navigation",
+                                )
+                                .clicked()
+                            {
+                                Self::split_focused(
+                                    &mut self.dock_state,
+                                    Box::new(PaneView::new(selected, PaneContent::Inspector)),
+                                    Split::Right,
+                                );
+                                ui.close_menu();
+                            }
+                            if ui
+                                .button("Split below")
+                                .on_hover_text(
+                                    "Open the selection in a pane with its own navigation",
+                                )
+                                .clicked()
+                            {
+                                Self::split_focused(
+                                    &mut self.dock_state,
+                                    Box::new(PaneView::new(selected, PaneContent::Inspector)),
+                                    Split::Below,
+                                );
+                                ui.close_menu();
+                            }
                         });
 
                         ui.menu_button("Navigate", |ui| {
@@ -9,7 +9,6 @@ use egui_ui_refresh::RefreshedTheme;
 use image::ImageFormat;
 use poll_promise::Promise;
 
-use hlbc::Bytecode;
 use hlbc_gui::{App, HLBC_ICON};
 
 mod fonts;
@@ -46,10 +45,7 @@ fn main() -> eframe::Result<()> {
             } else {
                 let path = PathBuf::from(args.clone());
                 Some(Promise::spawn_thread("bg_loader", move || {
-                    Ok(Some((
-                        args,
-                        Bytecode::from_file(path)?,
-                    )))
+                    Ok(Some((args, hlbc_decompiler::facade::open(path)?)))
                 }))
             };
             cc.egui_ctx
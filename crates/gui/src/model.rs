@@ -11,50 +11,79 @@ use crate::views::AppView;
 
 /// Cheaply cloneable, for single threaded usage.
 #[derive(Clone)]
-pub(crate) struct AppCtxHandle(Rc<AppCtx>);
+pub(crate) struct AppCtxHandle {
+    ctx: Rc<AppCtx>,
+    /// Navigation of the pane this handle was created for, the global navigation otherwise.
+    nav: Option<Rc<Navigation>>,
+}
 
 macro_rules! delegate {
     ($name:ident) => {
         pub(crate) fn $name(&self) {
-            self.0.$name();
+            self.navigation().$name();
         }
     };
     ($name:ident; $t:ty) => {
         pub(crate) fn $name(&self) -> $t {
-            self.0.$name()
+            self.navigation().$name()
         }
     };
 }
 
 impl AppCtxHandle {
     pub(crate) fn new(appctx: AppCtx) -> Self {
-        Self(Rc::new(appctx))
+        Self {
+            ctx: Rc::new(appctx),
+            nav: None,
+        }
+    }
+
+    /// Create a handle where selection and navigation are scoped to `nav` instead of the global one.
+    /// Views rendered with this handle navigate independently from the rest of the app.
+    pub(crate) fn with_navigation(&self, nav: Rc<Navigation>) -> Self {
+        Self {
+            ctx: self.ctx.clone(),
+            nav: Some(nav),
+        }
+    }
+
+    fn navigation(&self) -> &Navigation {
+        self.nav.as_deref().unwrap_or(&self.ctx.navigation)
     }
 
     pub(crate) fn file(&self) -> String {
-        self.0.file.clone()
+        self.ctx.file.clone()
     }
 
     pub(crate) fn code(&self) -> &Bytecode {
-        &self.0.code
+        &self.ctx.code
     }
 
     pub(crate) fn usage(&self) -> &FullUsageReport {
-        &self.0.usage
+        &self.ctx.usage
     }
 
     pub(crate) fn open_tab(&self, tab: impl AppView + 'static) {
-        self.0.new_tab.set(Some(Box::new(tab)));
+        self.ctx.new_tab.set(Some(Box::new(tab)));
     }
 
     pub(crate) fn take_tab_to_open(&self) -> Option<Box<dyn AppView>> {
-        self.0.new_tab.take()
+        self.ctx.new_tab.take()
+    }
+
+    /// Open a tab in a new pane next to the focused one
+    pub(crate) fn open_split(&self, tab: impl AppView + 'static) {
+        self.ctx.new_split.set(Some(Box::new(tab)));
+    }
+
+    pub(crate) fn take_split_to_open(&self) -> Option<Box<dyn AppView>> {
+        self.ctx.new_split.take()
     }
 
     delegate!(selected; Item);
 
     pub(crate) fn set_selected(&self, s: Item) {
-        self.0.navigate_to(s)
+        self.navigation().navigate_to(s)
     }
 
     delegate!(can_navigate_forward; bool);
@@ -63,20 +92,17 @@ impl AppCtxHandle {
     delegate!(navigate_back);
 }
 
-/// Arbitrary value, should we let it grow indefinitely instead ?
-const NAVIGATION_HISTORY_MAX: usize = 64;
-
 pub(crate) struct AppCtx {
     file: String,
     code: Bytecode,
     usage: FullUsageReport,
-    /// Selection index in the navigation history buffer
-    selection: Cell<usize>,
-    /// Ring buffer of navigation history
-    navigation_history: RefCell<VecDeque<Item>>,
+    /// Global navigation, shared by all views not in a pane
+    navigation: Navigation,
     /// To open a tab from another tab.
     /// This can't be done directly because this would need a mutable reference to a tree and the tree owns the tab.
     new_tab: Cell<Option<Box<dyn AppView>>>,
+    /// Same as `new_tab`, but splitting the focused leaf
+    new_split: Cell<Option<Box<dyn AppView>>>,
 }
 
 impl AppCtx {
@@ -86,11 +112,42 @@ impl AppCtx {
             file,
             code,
             usage,
-            selection: Cell::new(0),
+            navigation: Navigation::default(),
             new_tab: Cell::new(None),
+            new_split: Cell::new(None),
+        }
+    }
+}
+
+/// Arbitrary value, should we let it grow indefinitely instead ?
+const NAVIGATION_HISTORY_MAX: usize = 64;
+
+/// A selection with its navigation history
+pub(crate) struct Navigation {
+    /// Selection index in the navigation history buffer
+    selection: Cell<usize>,
+    /// Ring buffer of navigation history
+    navigation_history: RefCell<VecDeque<Item>>,
+}
+
+impl Default for Navigation {
+    fn default() -> Self {
+        Self {
+            selection: Cell::new(0),
             navigation_history: RefCell::new(VecDeque::with_capacity(NAVIGATION_HISTORY_MAX)),
         }
     }
+}
+
+impl Navigation {
+    /// Start with `item` selected
+    pub(crate) fn starting_at(item: Item) -> Self {
+        let nav = Self::default();
+        if !matches!(item, Item::None) {
+            nav.navigate_to(item);
+        }
+        nav
+    }
 
     /// Navigate to a new selection
     fn navigate_to(&self, item: Item) {
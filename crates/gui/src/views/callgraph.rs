@@ -8,8 +8,8 @@ use eframe::epaint::CubicBezierShape;
 use hlbc::analysis::graph::petgraph::visit::EdgeRef;
 use hlbc::analysis::graph::petgraph::visit::IntoEdgeReferences;
 use hlbc::analysis::graph::Callgraph;
-use hlbc::fmt::EnhancedFmt;
 use hlbc::types::RefFun;
+use hlbc_decompiler::facade;
 
 use crate::views::{impl_id, impl_view_id};
 use crate::AppCtxHandle;
@@ -78,8 +78,8 @@ impl CallgraphView {
                                     .show(ui.ctx(), |ui| {
                                         Frame::window(ui.style().as_ref()).show(ui, |ui| {
                                             ui.code(
-                                                n.display_header::<EnhancedFmt>(ctx.code().deref())
-                                                    .to_string(),
+                                                facade::function_header(ctx.code().deref(), n)
+                                                    .unwrap_or_else(|e| e.to_string()),
                                             )
                                         })
                                     })
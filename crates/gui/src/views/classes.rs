@@ -1,18 +1,22 @@
-use eframe::egui::{Color32, RichText, Ui, WidgetText};
+use std::collections::BTreeMap;
+use std::mem;
+
+use eframe::egui::{CollapsingHeader, Color32, RichText, ScrollArea, TextStyle, Ui, WidgetText};
 
-use hlbc::fmt::EnhancedFmt;
 use hlbc::types::{RefType, Type};
+use hlbc::Str;
 
 use crate::model::{AppCtxHandle, Item};
-use crate::style::list_view;
+use crate::style::singleline;
 use crate::views::{impl_id, impl_view_id, DecompilerView, InspectorView};
 use crate::AppView;
 
+/// Classes grouped in a tree of packages
 #[derive(Default)]
 pub(crate) struct ClassesView {
     show_std: bool,
-    cache: Vec<RefType>,
-    cache_valid: bool,
+    /// Root package, built again when the filter changes
+    tree: Option<Package>,
 }
 
 impl_view_id!(ClassesView: unique);
@@ -25,46 +29,149 @@ impl AppView for ClassesView {
     }
 
     fn ui(&mut self, ui: &mut Ui, ctx: AppCtxHandle) {
-        if !self.cache_valid {
-            let code = ctx.code();
+        if ui.checkbox(&mut self.show_std, "Show stdlib").changed() {
+            self.tree = None;
+        }
 
-            self.cache = Vec::new();
-            for (i, t) in code.types.iter().enumerate() {
-                match t {
-                    Type::Obj(obj) => {
-                        if self.show_std || !obj.is_from_std(code) {
-                            self.cache.push(RefType(i));
-                        }
+        ui.add_space(6.0);
+
+        let show_std = self.show_std;
+        let tree = self.tree.get_or_insert_with(|| {
+            let code = ctx.code();
+            let types = code
+                .types
+                .iter()
+                .enumerate()
+                .filter_map(|(i, t)| match t {
+                    Type::Obj(obj) if show_std || !obj.is_from_std(code) => {
+                        let name = obj.name(code);
+                        // Static members are in the same package as their class
+                        let path = name.strip_prefix('$').map(Str::from).unwrap_or(name);
+                        Some((path, RefType(i)))
                     }
-                    _ => {}
+                    _ => None,
+                })
+                .collect();
+            Package::new(Str::from(""), types)
+        });
+
+        ScrollArea::both()
+            .auto_shrink([false, false])
+            .show(ui, |ui| package_ui(ui, &ctx, tree, ""));
+    }
+}
+
+/// A package of the tree. What it contains is only sorted out when it is expanded, so a bytecode with a lot of types
+/// can be displayed quickly.
+struct Package {
+    name: Str,
+    /// Number of types in this package and its subpackages
+    count: usize,
+    /// Types of this package and of its subpackages with their path relative to this package, until it is expanded
+    types: Vec<(Str, RefType)>,
+    /// Subpackages and types directly in this package, sorted by name
+    content: Option<(Vec<Package>, Vec<(Str, RefType)>)>,
+}
+
+impl Package {
+    fn new(name: Str, types: Vec<(Str, RefType)>) -> Self {
+        Self {
+            name,
+            count: types.len(),
+            types,
+            content: None,
+        }
+    }
+
+    /// Subpackages and types directly in this package, grouped on first call
+    fn content(&mut self) -> &mut (Vec<Package>, Vec<(Str, RefType)>) {
+        let types = &mut self.types;
+        self.content.get_or_insert_with(|| {
+            let mut packages: BTreeMap<Str, Vec<(Str, RefType)>> = BTreeMap::new();
+            let mut classes = Vec::new();
+            for (path, t) in mem::take(types) {
+                match path.split_once('.') {
+                    Some((package, rest)) => packages
+                        .entry(Str::from(package))
+                        .or_default()
+                        .push((Str::from(rest), t)),
+                    None => classes.push((path, t)),
                 }
             }
+            classes.sort_by(|(a, _), (b, _)| a.cmp(b));
+            let packages = packages
+                .into_iter()
+                .map(|(name, types)| Package::new(name, types))
+                .collect();
+            (packages, classes)
+        })
+    }
+}
 
-            self.cache_valid = true;
+/// Display the content of a package, subpackages are only loaded when expanded
+fn package_ui(ui: &mut Ui, ctx: &AppCtxHandle, package: &mut Package, path: &str) {
+    let (packages, classes) = package.content();
+    for p in packages {
+        let path = if path.is_empty() {
+            p.name.to_string()
+        } else {
+            format!("{path}.{}", p.name)
+        };
+        CollapsingHeader::new(format!("{} ({})", p.name, p.count))
+            .id_source(&path)
+            .show(ui, |ui| package_ui(ui, ctx, p, &path));
+    }
+    for (name, t) in classes.iter() {
+        let item = Item::Type(*t);
+        let label = ui.selectable_label(
+            ctx.selected() == item,
+            singleline(
+                name.to_string(),
+                TextStyle::Button.resolve(ui.style().as_ref()),
+                Color32::WHITE,
+            ),
+        );
+        label.context_menu(|ui| {
+            if ui.small_button("Open in inspector").clicked() {
+                let tab = InspectorView::new(item, ctx.code());
+                ctx.open_tab(tab);
+            }
+            if ui.small_button("Decompile").clicked() {
+                ctx.open_tab(DecompilerView::default());
+            }
+        });
+        if label.clicked() {
+            ctx.set_selected(item);
         }
+    }
+}
 
-        if ui.checkbox(&mut self.show_std, "Show stdlib").changed() {
-            self.cache_valid = false;
-        }
+#[cfg(test)]
+mod tests {
+    use hlbc::types::RefType;
+    use hlbc::Str;
 
-        ui.add_space(6.0);
+    use crate::views::classes::Package;
 
-        list_view(
-            ui,
-            ctx,
-            self.cache.len(),
-            |i| self.cache[i],
-            Item::Type,
-            |ctx, t| t.display::<EnhancedFmt>(ctx.code()).to_string(),
-            Some(|ui: &mut Ui, ctx: &AppCtxHandle, t| {
-                if ui.small_button("Open in inspector").clicked() {
-                    let tab = InspectorView::new(Item::Type(t), ctx.code());
-                    ctx.open_tab(tab);
-                }
-                if ui.small_button("Decompile").clicked() {
-                    ctx.open_tab(DecompilerView::default());
-                }
-            }),
-        );
+    #[test]
+    fn package_tree() {
+        let types = ["Main", "pkg.sub.B", "pkg.A", "pkg.sub.C", "other.D"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| (Str::from(name), RefType(i)))
+            .collect();
+        let mut root = Package::new(Str::from(""), types);
+        assert_eq!(root.count, 5);
+        let (packages, classes) = root.content();
+        assert_eq!(*classes, [(Str::from("Main"), RefType(0))]);
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].name.as_str(), "other");
+        let pkg = &mut packages[1];
+        assert_eq!(pkg.count, 3);
+        // Subpackages aren't sorted out until they are expanded
+        let (sub, classes) = pkg.content();
+        assert_eq!(*classes, [(Str::from("A"), RefType(2))]);
+        assert!(sub[0].content.is_none());
+        assert_eq!(sub[0].types.len(), 2);
     }
 }
@@ -1,10 +1,13 @@
 use eframe::egui::{Color32, RichText, ScrollArea, Ui, WidgetText};
 
 use hlbc::fmt::EnhancedFmt;
-use hlbc::types::FunPtr;
-use hlbc::Resolve;
+use hlbc::types::{FunPtr, RefFun};
+use hlbc::{Bytecode, Resolve};
+use hlbc_decompiler::ast::Method;
 use hlbc_decompiler::fmt::FormatOptions;
-use hlbc_decompiler::{decompile_class, decompile_function};
+use hlbc_decompiler::{
+    decompile_class_outline, decompile_code_with, facade, Backend, DecompilerOptions, Pass,
+};
 
 use crate::model::{AppCtxHandle, Item};
 use crate::views::{haxe_source_view, impl_id, impl_view_id};
@@ -12,13 +15,118 @@ use crate::AppView;
 
 #[derive(Default)]
 pub(crate) struct DecompilerView {
-    output: String,
+    opts: DecompilerOptions,
+    /// Incremented when the options change, outputs from an older generation are stale
+    generation: u64,
+    output: Output,
     // Cache key for decompilation
     cache_selected: Item,
 }
 
+#[derive(Default)]
+enum Output {
+    #[default]
+    Empty,
+    Text(String),
+    /// Methods are only decompiled when they come into view,
+    /// so changing the options doesn't mean decompiling the whole class again.
+    Class {
+        header: String,
+        methods: Vec<LazyMethod>,
+    },
+}
+
+struct LazyMethod {
+    method: Method,
+    text: String,
+    /// Options generation `text` has been decompiled with, None if it's only the signature
+    generation: Option<u64>,
+}
+
+impl LazyMethod {
+    fn update(&mut self, code: &Bytecode, opts: &DecompilerOptions, generation: u64) {
+        self.method.statements =
+            decompile_code_with(code, self.method.fun.as_fn(code).unwrap(), opts);
+        self.text = self
+            .method
+            .display(code, &FormatOptions::new(facade::INDENT).inc_nesting())
+            .to_string();
+        self.generation = Some(generation);
+    }
+}
+
 impl_view_id!(DecompilerView: unique);
 
+/// The decompiled source of a function, or the declaration of a native
+fn function_output(code: &Bytecode, fun: RefFun, opts: &DecompilerOptions) -> String {
+    match code.get(fun) {
+        FunPtr::Fun(_) => {
+            facade::decompile_function(code, fun, opts).unwrap_or_else(|e| e.to_string())
+        }
+        FunPtr::Native(n) => n.display::<EnhancedFmt>(code).to_string(),
+    }
+}
+
+impl DecompilerView {
+    fn options_ui(&mut self, ui: &mut Ui) {
+        ui.menu_button("Options", |ui| {
+            let mut changed = ui
+                .checkbox(&mut self.opts.interleave_opcodes, "Interleave opcodes")
+                .changed();
+            changed |= ui
+                .checkbox(&mut self.opts.implicit_casts, "Show implicit casts")
+                .changed();
+            changed |= ui
+                .checkbox(&mut self.opts.constant_returns, "Inline constant getters")
+                .on_hover_text(
+                    "Replace calls to functions always returning the same value with that value",
+                )
+                .changed();
+            changed |= ui
+                .checkbox(&mut self.opts.property_access, "Property access")
+                .on_hover_text("Replace calls to trivial getters and setters with the property")
+                .changed();
+            changed |= ui
+                .checkbox(&mut self.opts.goto_fallback, "Goto fallback")
+                .on_hover_text(
+                    "Render the control flow the decompiler can't follow with labels and gotos",
+                )
+                .changed();
+            changed |= ui
+                .checkbox(&mut self.opts.line_comments, "Source lines")
+                .on_hover_text("Annotate the statements with their source file and line")
+                .changed();
+            let mut structured = self.opts.backend == Backend::Structured;
+            if ui
+                .checkbox(&mut structured, "Structured control flow")
+                .changed()
+            {
+                self.opts.backend = if structured {
+                    Backend::Structured
+                } else {
+                    Backend::Scopes
+                };
+                changed = true;
+            }
+            ui.separator();
+            for pass in Pass::ALL {
+                let mut enabled = self.opts.is_enabled(pass);
+                if ui.checkbox(&mut enabled, pass.name()).changed() {
+                    self.opts = self.opts.clone().with_pass(pass, enabled);
+                    changed = true;
+                }
+            }
+            if changed {
+                self.generation += 1;
+                // A single function is cheap enough to decompile again right away
+                if matches!(self.output, Output::Text(_)) {
+                    self.cache_selected = Item::None;
+                }
+            }
+        });
+    }
+}
+
 impl AppView for DecompilerView {
     impl_id!(unique);
 
@@ -29,20 +137,32 @@ impl AppView for DecompilerView {
     }
 
     fn ui(&mut self, ui: &mut Ui, ctx: AppCtxHandle) {
-        if ctx.selected() != self.cache_selected {
-            let code = ctx.code();
+        self.options_ui(ui);
 
+        let code = ctx.code();
+        if ctx.selected() != self.cache_selected {
             self.output = match ctx.selected() {
-                Item::Fun(fun) => match code.get(fun) {
-                    FunPtr::Fun(func) => decompile_function(code, func)
-                        .display(code, &FormatOptions::new(2))
-                        .to_string(),
-                    FunPtr::Native(n) => n.display::<EnhancedFmt>(code).to_string(),
+                Item::Fun(fun) => Output::Text(function_output(code, fun, &self.opts)),
+                Item::Type(t) => match t.as_obj(code) {
+                    Some(obj) => {
+                        let class = decompile_class_outline(code, obj);
+                        let opts = FormatOptions::new(facade::INDENT);
+                        Output::Class {
+                            header: class.display_header(code, &opts).to_string(),
+                            methods: class
+                                .methods
+                                .into_iter()
+                                .map(|method| LazyMethod {
+                                    text: method.display(code, &opts.inc_nesting()).to_string(),
+                                    method,
+                                    generation: None,
+                                })
+                                .collect(),
+                        }
+                    }
+                    None => Output::Empty,
                 },
-                Item::Type(t) => decompile_class(code, t.as_obj(code).unwrap())
-                    .display(code, &FormatOptions::new(2))
-                    .to_string(),
-                _ => String::new(),
+                _ => Output::Empty,
             };
             self.cache_selected = ctx.selected();
         }
@@ -52,7 +172,55 @@ impl AppView for DecompilerView {
             .show(ui, |ui| {
                 // TextEdit will show us text we can edit (we don't want that)
                 // We need to pass a mut reference to an immutable str
-                haxe_source_view(ui, &self.output);
+                match &mut self.output {
+                    Output::Empty => {}
+                    Output::Text(text) => {
+                        haxe_source_view(ui, text);
+                    }
+                    Output::Class { header, methods } => {
+                        haxe_source_view(ui, header);
+                        for m in methods {
+                            let res = haxe_source_view(ui, &m.text);
+                            // Stale methods out of view are left alone until they are scrolled to
+                            if m.generation != Some(self.generation) && ui.is_rect_visible(res.rect)
+                            {
+                                m.update(code, &self.opts, self.generation);
+                                ui.ctx().request_repaint();
+                            }
+                        }
+                        haxe_source_view(ui, "}");
+                    }
+                }
             });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hlbc::builder::Builder;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{RefFun, Reg, Type};
+    use hlbc_decompiler::{facade, DecompilerOptions};
+
+    use crate::views::decompiler::function_output;
+
+    #[test]
+    fn same_output_as_facade() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        b.function(
+            "main",
+            &[],
+            void,
+            &[void],
+            vec![Opcode::Ret { ret: Reg(0) }],
+        );
+        let code = b.build().unwrap();
+
+        let opts = DecompilerOptions::default();
+        assert_eq!(
+            function_output(&code, RefFun(0), &opts),
+            facade::decompile_function(&code, RefFun(0), &opts).unwrap()
+        );
+    }
+}
@@ -1,9 +1,9 @@
 use eframe::egui::{Color32, RichText, ScrollArea, TextStyle, Ui, WidgetText};
 
 use hlbc::analysis::files::functions_in_files;
-use hlbc::fmt::EnhancedFmt;
 use hlbc::types::RefFun;
 use hlbc::{Bytecode, Str};
+use hlbc_decompiler::facade;
 
 use crate::model::{AppCtxHandle, Item};
 use crate::style::singleline;
@@ -31,20 +31,25 @@ impl AppView for FilesView {
     }
 
     fn ui(&mut self, ui: &mut Ui, ctx: AppCtxHandle) {
+        if self.files.is_empty() {
+            ui.label("No debug info in this bytecode");
+            return;
+        }
         ScrollArea::both().auto_shrink([false, false]).show_rows(
             ui,
             ui.text_style_height(&TextStyle::Button),
             self.files.len(),
             |ui, range| {
                 for (file, funs) in &self.files[range] {
-                    ui.collapsing(file.as_str(), |ui| {
+                    ui.collapsing(format!("{file} ({})", funs.len()), |ui| {
                         for &f in funs {
                             let item = Item::Fun(f);
                             let checked = ctx.selected() == item;
                             let label = ui.selectable_label(
                                 checked,
                                 singleline(
-                                    f.display_header::<EnhancedFmt>(ctx.code()).to_string(),
+                                    facade::function_header(ctx.code(), f)
+                                        .unwrap_or_else(|e| e.to_string()),
                                     TextStyle::Button.resolve(ui.style().as_ref()),
                                     Color32::WHITE,
                                 ),
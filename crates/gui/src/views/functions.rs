@@ -1,7 +1,7 @@
 use eframe::egui::{Ui, WidgetText};
 
-use hlbc::fmt::EnhancedFmt;
 use hlbc::types::RefFun;
+use hlbc_decompiler::facade;
 
 use crate::model::{AppCtxHandle, Item};
 use crate::style::{functions_icon_label, list_view};
@@ -12,6 +12,7 @@ use crate::AppView;
 pub(crate) struct FunctionsView {
     show_natives: bool,
     show_std: bool,
+    hide_generated: bool,
     cache: Vec<RefFun>,
     cache_valid: bool,
 }
@@ -31,7 +32,9 @@ impl AppView for FunctionsView {
             self.cache = Vec::new();
             let code = ctx.code();
             for f in &code.functions {
-                if self.show_std || !f.is_from_std(code) {
+                if (self.show_std || !f.is_from_std(code))
+                    && !(self.hide_generated && f.generated_hint(code).is_some())
+                {
                     self.cache.push(f.findex);
                 }
             }
@@ -55,6 +58,13 @@ impl AppView for FunctionsView {
             if ui.checkbox(&mut self.show_std, "Show stdlib").changed() {
                 self.cache_valid = false;
             }
+            if ui
+                .checkbox(&mut self.hide_generated, "Hide generated")
+                .on_hover_text("Hide functions likely generated by macros or build tools")
+                .changed()
+            {
+                self.cache_valid = false;
+            }
         });
 
         ui.add_space(6.0);
@@ -65,7 +75,7 @@ impl AppView for FunctionsView {
             self.cache.len(),
             |i| self.cache[i],
             Item::Fun,
-            |ctx, f| f.display_header::<EnhancedFmt>(ctx.code()).to_string(),
+            |ctx, f| facade::function_header(ctx.code(), f).unwrap_or_else(|e| e.to_string()),
             Some(|ui: &mut Ui, ctx: &AppCtxHandle, f| {
                 if ui.small_button("Open in inspector").clicked() {
                     let tab = InspectorView::new(Item::Fun(f), ctx.code());
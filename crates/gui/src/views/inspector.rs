@@ -9,10 +9,11 @@ use hlbc::types::{
     EnumConstruct, FunPtr, ObjField, RefField, RefFun, RefGlobal, RefString, RefType, Type, TypeObj,
 };
 use hlbc::{Bytecode, Resolve};
+use hlbc_decompiler::facade;
 
 use crate::model::{AppCtxHandle, Item};
 use crate::style::text_stitch;
-use crate::views::{impl_id, impl_view_id, ViewId};
+use crate::views::{impl_id, impl_view_id, PaneContent, PaneView, ViewId};
 use crate::{shortcuts, AppView};
 
 /// View detailed information about a bytecode element.
@@ -108,6 +109,10 @@ pub(crate) fn inspector_link(ui: &mut Ui, ctx: AppCtxHandle, item: Item) {
             ctx.open_tab(InspectorView::new(item, ctx.code()));
             ui.close_menu();
         }
+        if ui.button("Open in split pane").clicked() {
+            ctx.open_split(PaneView::new(item, PaneContent::Inspector));
+            ui.close_menu();
+        }
     });
     if res.clicked() {
         ctx.set_selected(item);
@@ -168,8 +173,10 @@ fn function_inspector(ui: &mut Ui, ctx: AppCtxHandle, fun: RefFun) {
                                         .monospace(),
                                 );
                                 ui.add_space(10.0);
-                                ui.monospace(o.display(code, f, i as i32, 11).to_string())
-                                    .on_hover_text(o.description());
+                                ui.monospace(
+                                    facade::opcode(code, f, i).unwrap_or_else(|e| e.to_string()),
+                                )
+                                .on_hover_text(o.description());
                             });
                             // TODO syntax highlighting
                             // TODO linking (requires bytecode visitor)
@@ -0,0 +1,224 @@
+use std::ops::Range;
+
+use eframe::egui::{Color32, Grid, RichText, ScrollArea, TextEdit, TextStyle, Ui, WidgetText};
+
+use hlbc::layout::{FileLayout, Table};
+use hlbc::types::{RefGlobal, RefString, RefType};
+use hlbc::Bytecode;
+
+use crate::model::{AppCtxHandle, Item};
+use crate::views::{impl_id, impl_view_id, AppView};
+
+/// Bytes per line of the hex dump
+const ROW: usize = 16;
+
+/// Raw layout of the bytecode file : where each table is and the bytes of the selected element.
+pub(crate) struct FileLayoutView {
+    /// The file as it was when the view was opened, with its layout
+    loaded: Result<(Vec<u8>, FileLayout), String>,
+    /// Last selection seen, the hex dump follows the selection when it changes
+    last: Item,
+    /// Description and range of the bytes in the hex dump
+    shown: Option<(String, Range<usize>)>,
+    /// Offset to look up
+    offset: String,
+}
+
+impl_view_id!(FileLayoutView: unique);
+
+impl FileLayoutView {
+    pub(crate) fn new(ctx: &AppCtxHandle) -> Self {
+        let loaded = raw_data(&ctx.file()).and_then(|data| {
+            FileLayout::parse(&data)
+                .map(|layout| (data, layout))
+                .map_err(|e| e.to_string())
+        });
+        Self {
+            loaded,
+            last: Item::None,
+            shown: None,
+            offset: String::new(),
+        }
+    }
+}
+
+impl AppView for FileLayoutView {
+    impl_id!(unique);
+
+    fn title(&self, _ctx: AppCtxHandle) -> WidgetText {
+        RichText::new("File layout").color(Color32::WHITE).into()
+    }
+
+    fn ui(&mut self, ui: &mut Ui, ctx: AppCtxHandle) {
+        let (data, layout) = match &self.loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                ui.label(format!("Can't read the file layout : {e}"));
+                return;
+            }
+        };
+        let code = ctx.code();
+
+        let selected = ctx.selected();
+        if selected != self.last {
+            self.last = selected;
+            if let Some(range) = item_range(layout, code, selected) {
+                self.shown = Some((selected.name(code), range));
+            }
+        }
+
+        ui.label(format!(
+            "Bytecode at offset {:#x}, {} bytes in a file of {} bytes",
+            layout.offset,
+            layout.end - layout.offset,
+            data.len()
+        ));
+        Grid::new("layout::tables")
+            .striped(true)
+            .num_columns(4)
+            .show(ui, |ui| {
+                for table in &layout.tables {
+                    if ui.link(table.table.to_string()).clicked() {
+                        self.shown = Some((table.table.to_string(), table.range.clone()));
+                    }
+                    ui.monospace(format!(
+                        "{:#010x}..{:#010x}",
+                        table.range.start, table.range.end
+                    ));
+                    ui.label(format!("{} bytes", table.range.len()));
+                    if table.table != Table::Header {
+                        ui.label(format!("{} elements", table.elements.len()));
+                    }
+                    ui.end_row();
+                }
+            });
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("Offset");
+            ui.add(TextEdit::singleline(&mut self.offset).desired_width(120.0))
+                .on_hover_text("Decimal or hexadecimal with 0x");
+            if let Some(offset) = parse_offset(&self.offset) {
+                match layout.at(offset) {
+                    Some((table, index)) => {
+                        let item = index.and_then(|i| element_item(code, table, i));
+                        match (item, index) {
+                            (Some(item), _) => {
+                                if ui.link(item.name(code)).clicked() {
+                                    ctx.set_selected(item);
+                                }
+                            }
+                            (None, Some(i)) => {
+                                ui.label(format!("{table} #{i}"));
+                            }
+                            (None, None) => {
+                                ui.label(table.to_string());
+                            }
+                        }
+                    }
+                    None => {
+                        ui.label("outside of the bytecode");
+                    }
+                }
+            }
+        });
+
+        ui.separator();
+        let Some((name, range)) = &self.shown else {
+            ui.label("Select an element or a table to see its bytes");
+            return;
+        };
+        ui.label(format!(
+            "{name} : {:#x}..{:#x} ({} bytes)",
+            range.start,
+            range.end,
+            range.len()
+        ));
+        hex_dump(ui, data, range.clone());
+    }
+}
+
+/// Rows of 16 bytes, the bytes out of `range` are blank
+fn hex_dump(ui: &mut Ui, data: &[u8], range: Range<usize>) {
+    let first = range.start / ROW * ROW;
+    let rows = (range.end - first).div_ceil(ROW);
+    ScrollArea::both()
+        .id_source("layout::hex")
+        .auto_shrink([false, false])
+        .show_rows(
+            ui,
+            ui.text_style_height(&TextStyle::Monospace),
+            rows,
+            |ui, rows| {
+                for row in rows {
+                    let start = first + row * ROW;
+                    let mut hex = String::with_capacity(ROW * 3);
+                    let mut ascii = String::with_capacity(ROW);
+                    for offset in start..start + ROW {
+                        match data.get(offset).filter(|_| range.contains(&offset)) {
+                            Some(&b) => {
+                                hex.push_str(&format!("{b:02x} "));
+                                ascii.push(if b.is_ascii_graphic() || b == b' ' {
+                                    b as char
+                                } else {
+                                    '.'
+                                });
+                            }
+                            None => {
+                                hex.push_str("   ");
+                                ascii.push(' ');
+                            }
+                        }
+                    }
+                    ui.monospace(format!("{start:08x}  {hex} {ascii}"));
+                }
+            },
+        );
+}
+
+/// Where the selected element is in the file
+fn item_range(layout: &FileLayout, code: &Bytecode, item: Item) -> Option<Range<usize>> {
+    match item {
+        Item::Fun(f) => layout.function(code, f),
+        Item::Type(RefType(i)) => layout.element(Table::Types, i),
+        Item::Global(RefGlobal(i)) => layout.element(Table::Globals, i),
+        Item::String(RefString(i)) => layout.element(Table::Strings, i),
+        Item::None => None,
+    }
+}
+
+/// The item for an element of a table, if it can be selected
+fn element_item(code: &Bytecode, table: Table, index: usize) -> Option<Item> {
+    Some(match table {
+        Table::Strings => Item::String(RefString(index)),
+        Table::Types => Item::Type(RefType(index)),
+        Table::Globals => Item::Global(RefGlobal(index)),
+        Table::Functions => Item::Fun(code.functions.get(index)?.findex),
+        Table::Natives => Item::Fun(code.natives.get(index)?.findex),
+        _ => return None,
+    })
+}
+
+fn parse_offset(s: &str) -> Option<usize> {
+    let s = s.trim();
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// The bytes of the loaded file, the bytecode isn't kept as is after loading
+fn raw_data(file: &str) -> Result<Vec<u8>, String> {
+    #[cfg(feature = "examples")]
+    if let Some(example) = crate::examples::EXAMPLES.iter().find(|e| e.name == file) {
+        return Ok(example.data.to_vec());
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::fs::read(file).map_err(|e| format!("{file} : {e}"))
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        Err("the file isn't kept in memory in the web app".to_owned())
+    }
+}
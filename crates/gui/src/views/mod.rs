@@ -12,10 +12,13 @@ pub(crate) use globals::*;
 pub(crate) use haxe_source_view::*;
 pub(crate) use info::*;
 pub(crate) use inspector::*;
+pub(crate) use layout::*;
+pub(crate) use pane::*;
 #[cfg(feature = "search")]
 pub(crate) use search::*;
 #[cfg(feature = "examples")]
 pub(crate) use source::*;
+pub(crate) use source_map::*;
 pub(crate) use strings::*;
 
 use crate::model::AppCtxHandle;
@@ -30,10 +33,13 @@ mod globals;
 mod haxe_source_view;
 mod info;
 mod inspector;
+mod layout;
+mod pane;
 #[cfg(feature = "search")]
 mod search;
 #[cfg(feature = "examples")]
 mod source;
+mod source_map;
 mod strings;
 
 /// Tab viewer with dynamic dispatch because I don't care
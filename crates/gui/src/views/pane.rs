@@ -0,0 +1,92 @@
+use std::rc::Rc;
+
+use eframe::egui::{Button, Color32, RichText, Ui, WidgetText};
+
+use crate::model::{AppCtxHandle, Item, Navigation};
+use crate::views::{impl_id, impl_view_id, DecompilerView, SyncInspectorView, ViewId};
+use crate::{shortcuts, AppView};
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub(crate) enum PaneContent {
+    Inspector,
+    Decompiler,
+}
+
+/// A view with its own selection and navigation history, independent of the rest of the app.
+/// Used to look at two elements side by side.
+pub(crate) struct PaneView {
+    id: ViewId,
+    nav: Rc<Navigation>,
+    content: PaneContent,
+    inspector: SyncInspectorView,
+    decompiler: DecompilerView,
+}
+
+impl_view_id!(PaneView);
+
+impl PaneView {
+    pub(crate) fn new(item: Item, content: PaneContent) -> Self {
+        Self {
+            id: ViewId::new_instance::<Self>(),
+            nav: Rc::new(Navigation::starting_at(item)),
+            content,
+            inspector: SyncInspectorView,
+            decompiler: DecompilerView::default(),
+        }
+    }
+}
+
+impl AppView for PaneView {
+    impl_id!();
+
+    fn title(&self, ctx: AppCtxHandle) -> WidgetText {
+        let ctx = ctx.with_navigation(self.nav.clone());
+        let kind = match self.content {
+            PaneContent::Inspector => "Inspector",
+            PaneContent::Decompiler => "Decompiler",
+        };
+        RichText::new(format!(
+            "{kind} (pane) : {}",
+            ctx.selected().name(ctx.code())
+        ))
+        .color(Color32::WHITE)
+        .into()
+    }
+
+    fn ui(&mut self, ui: &mut Ui, ctx: AppCtxHandle) {
+        let ctx = ctx.with_navigation(self.nav.clone());
+
+        // Only triggers when in view
+        if ui.input_mut(|i| i.consume_shortcut(&shortcuts::NAV_BACK)) {
+            ctx.navigate_back();
+        } else if ui.input_mut(|i| i.consume_shortcut(&shortcuts::NAV_FORWARD)) {
+            ctx.navigate_forward();
+        }
+
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(ctx.can_navigate_back(), Button::new("⏴"))
+                .on_hover_text("Back")
+                .clicked()
+            {
+                ctx.navigate_back();
+            }
+            if ui
+                .add_enabled(ctx.can_navigate_forward(), Button::new("⏵"))
+                .on_hover_text("Forward")
+                .clicked()
+            {
+                ctx.navigate_forward();
+            }
+            ui.separator();
+            ui.selectable_value(&mut self.content, PaneContent::Inspector, "Inspector");
+            ui.selectable_value(&mut self.content, PaneContent::Decompiler, "Decompiler");
+        });
+        ui.separator();
+
+        match self.content {
+            PaneContent::Inspector => self.inspector.ui(ui, ctx),
+            PaneContent::Decompiler => self.decompiler.ui(ui, ctx),
+        }
+    }
+}
@@ -1,8 +1,9 @@
-use eframe::egui::{Color32, ComboBox, RichText, Ui, WidgetText};
+use eframe::egui::{Button, Color32, ComboBox, RichText, Ui, WidgetText};
 
-use hlbc::fmt::EnhancedFmt;
+use hlbc::page::Page;
 use hlbc::types::RefFun;
 use hlbc::Bytecode;
+use hlbc_decompiler::facade;
 use hlbc_indexing::{ClangdSearcher, Contains, Searcher, SkimSearcher};
 
 use crate::style::singleline_simple;
@@ -13,11 +14,14 @@ pub(crate) struct SearchView {
     id: ViewId,
     searcher: (SearchMethod, Box<dyn Searcher>),
     query_text: String,
+    page: Page,
     matches: Vec<RefFun>,
 }
 
 impl_view_id!(SearchView);
 
+const RESULTS_PER_PAGE: usize = 30;
+
 #[derive(PartialEq, Copy, Clone)]
 enum SearchMethod {
     Contains,
@@ -49,6 +53,7 @@ impl SearchView {
             id: ViewId::new_instance::<Self>(),
             searcher: (SearchMethod::Contains, SearchMethod::Contains.searcher()),
             query_text: String::new(),
+            page: Page::first(RESULTS_PER_PAGE),
             matches: Vec::new(),
         }
     }
@@ -87,9 +92,33 @@ impl AppView for SearchView {
             if old != self.searcher.0 {
                 self.searcher.1 = self.searcher.0.searcher();
             }
+            let mut changed = false;
             if old != self.searcher.0 || ui.text_edit_singleline(&mut self.query_text).changed() {
+                self.page = Page::first(RESULTS_PER_PAGE);
+                changed = true;
+            }
+            if ui
+                .add_enabled(self.page.offset > 0, Button::new("⏴"))
+                .on_hover_text("Previous results")
+                .clicked()
+            {
+                self.page = self.page.prev();
+                changed = true;
+            }
+            if ui
+                .add_enabled(self.matches.len() == self.page.limit, Button::new("⏵"))
+                .on_hover_text("Next results")
+                .clicked()
+            {
+                self.page = self.page.next();
+                changed = true;
+            }
+            if changed {
                 // let start = Instant::now();
-                self.matches = self.searcher.1.search(ctx.code(), &self.query_text, 30);
+                self.matches = self
+                    .searcher
+                    .1
+                    .search(ctx.code(), &self.query_text, self.page);
                 // println!("{} ms", start.elapsed().as_millis());
             }
         });
@@ -98,7 +127,7 @@ impl AppView for SearchView {
             //dbg!(ctx.code().resolve(*f));
             ui.label(singleline_simple(
                 ui,
-                f.display_header::<EnhancedFmt>(ctx.code()).to_string(),
+                facade::function_header(ctx.code(), *f).unwrap_or_else(|e| e.to_string()),
             ));
         }
     }
@@ -0,0 +1,153 @@
+use eframe::egui::{Color32, RichText, ScrollArea, TextStyle, Ui, WidgetText};
+
+use hlbc::types::{FunPtr, RefFun};
+use hlbc::{Bytecode, Resolve};
+use hlbc_decompiler::tokens::LineMapping;
+use hlbc_decompiler::{facade, DecompilerOptions};
+
+use crate::model::{AppCtxHandle, Item};
+use crate::views::{impl_id, impl_view_id, AppView};
+
+/// Disassembly and decompiled code of the selected function next to each other. Hovering a line of code highlights the
+/// opcodes it has been decompiled from, hovering an opcode highlights the lines it ended up in.
+#[derive(Default)]
+pub(crate) struct SourceMapView {
+    output: Option<Mapped>,
+    /// Cache key for decompilation
+    cache_selected: Item,
+    /// Under the pointer in the last frame
+    hovered: Hovered,
+}
+
+struct Mapped {
+    fun: RefFun,
+    ops: Vec<String>,
+    lines: Vec<String>,
+    map: Vec<LineMapping>,
+}
+
+#[derive(Default, Copy, Clone, Eq, PartialEq)]
+enum Hovered {
+    #[default]
+    None,
+    Op(usize),
+    Line(usize),
+}
+
+impl_view_id!(SourceMapView: unique);
+
+impl Mapped {
+    fn new(code: &Bytecode, fun: RefFun) -> Result<Self, String> {
+        let (source, map) =
+            facade::decompile_function_mapped(code, fun, &DecompilerOptions::default())
+                .map_err(|e| e.to_string())?;
+        Ok(Self {
+            fun,
+            ops: facade::disassemble(code, fun).map_err(|e| e.to_string())?,
+            lines: source.lines().map(str::to_owned).collect(),
+            map,
+        })
+    }
+
+    /// The line has been decompiled from the opcode
+    fn maps(&self, line: usize, op: usize) -> bool {
+        self.map
+            .iter()
+            .any(|m| m.line == line && m.fun == self.fun && m.ops.contains(&op))
+    }
+
+    fn is_highlighted_op(&self, hovered: Hovered, op: usize) -> bool {
+        match hovered {
+            Hovered::Op(hovered) => hovered == op,
+            Hovered::Line(line) => self.maps(line, op),
+            Hovered::None => false,
+        }
+    }
+
+    fn is_highlighted_line(&self, hovered: Hovered, line: usize) -> bool {
+        match hovered {
+            Hovered::Line(hovered) => hovered == line,
+            Hovered::Op(op) => self.maps(line, op),
+            Hovered::None => false,
+        }
+    }
+}
+
+impl AppView for SourceMapView {
+    impl_id!(unique);
+
+    fn title(&self, _ctx: AppCtxHandle) -> WidgetText {
+        RichText::new("Source map").color(Color32::WHITE).into()
+    }
+
+    fn ui(&mut self, ui: &mut Ui, ctx: AppCtxHandle) {
+        if ctx.selected() != self.cache_selected {
+            self.cache_selected = ctx.selected();
+            self.hovered = Hovered::None;
+            self.output = match ctx.selected() {
+                Item::Fun(fun) => match ctx.code().get(fun) {
+                    FunPtr::Fun(_) => {
+                        Some(Mapped::new(ctx.code(), fun).unwrap_or_else(|e| Mapped {
+                            fun,
+                            ops: Vec::new(),
+                            lines: vec![e],
+                            map: Vec::new(),
+                        }))
+                    }
+                    FunPtr::Native(_) => None,
+                },
+                _ => None,
+            };
+        }
+        let Some(mapped) = &self.output else {
+            ui.label("Select a function to see its opcodes next to its decompiled code");
+            return;
+        };
+
+        let hovered = self.hovered;
+        let mut now_hovered = Hovered::None;
+        let row_height = ui.text_style_height(&TextStyle::Monospace);
+        ui.columns(2, |columns| {
+            ScrollArea::both()
+                .id_source("source_map::ops")
+                .auto_shrink([false, false])
+                .show_rows(
+                    &mut columns[0],
+                    row_height,
+                    mapped.ops.len(),
+                    |ui, range| {
+                        for i in range {
+                            let text =
+                                RichText::new(format!("{i:>3}: {}", mapped.ops[i])).monospace();
+                            if ui
+                                .selectable_label(mapped.is_highlighted_op(hovered, i), text)
+                                .hovered()
+                            {
+                                now_hovered = Hovered::Op(i);
+                            }
+                        }
+                    },
+                );
+            ScrollArea::both()
+                .id_source("source_map::source")
+                .auto_shrink([false, false])
+                .show_rows(
+                    &mut columns[1],
+                    row_height,
+                    mapped.lines.len(),
+                    |ui, range| {
+                        for i in range {
+                            let text = RichText::new(&mapped.lines[i]).monospace();
+                            if ui
+                                .selectable_label(mapped.is_highlighted_line(hovered, i), text)
+                                .hovered()
+                            {
+                                now_hovered = Hovered::Line(i);
+                            }
+                        }
+                    },
+                );
+        });
+        self.hovered = now_hovered;
+    }
+}
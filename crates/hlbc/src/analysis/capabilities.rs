@@ -0,0 +1,201 @@
+//! Find which HashLink runtime can run a bytecode file.
+//!
+//! The bytecode format version only tells part of the story, newer VMs also added opcodes and types without
+//! changing it. Versions listed here are the first HashLink release supporting a feature.
+
+use std::cmp::Reverse;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::opcodes::Opcode;
+use crate::types::Type;
+use crate::Bytecode;
+
+/// A HashLink release
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct HlVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl HlVersion {
+    pub const fn new(major: u8, minor: u8) -> Self {
+        Self { major, minor }
+    }
+}
+
+impl Display for HlVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Something used by the bytecode that isn't supported by every runtime
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Feature {
+    /// Bytecode format version
+    Format(u8),
+    /// An opcode, by name
+    Opcode(&'static str),
+    /// A kind of type, by name
+    Type(&'static str),
+}
+
+impl Feature {
+    /// First HashLink version supporting this feature
+    pub fn since(&self) -> HlVersion {
+        match *self {
+            Feature::Format(5) => HlVersion::new(1, 12),
+            Feature::Format(_) => HlVersion::new(1, 6),
+            Feature::Opcode("Prefetch" | "Asm") => HlVersion::new(1, 12),
+            Feature::Opcode(_) => HlVersion::new(1, 11),
+            Feature::Type("packed") => HlVersion::new(1, 13),
+            Feature::Type(_) => HlVersion::new(1, 12),
+        }
+    }
+}
+
+impl Display for Feature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Feature::Format(v) => write!(f, "bytecode v{v}"),
+            Feature::Opcode(name) => write!(f, "opcode {name}"),
+            Feature::Type(name) => write!(f, "{name} type"),
+        }
+    }
+}
+
+/// A feature used by the bytecode
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Requirement {
+    pub feature: Feature,
+    /// Number of times the feature is used
+    pub count: usize,
+}
+
+/// What a runtime needs to support to run the bytecode
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    /// Bytecode format version
+    pub version: u8,
+    /// Debug information (file names, lines and variable names) is present
+    pub debug: bool,
+    /// The constants section is present
+    pub constants: bool,
+    /// The bytes pool is present
+    pub bytes: bool,
+    /// Features not supported by every runtime, sorted from the most recent
+    pub requirements: Vec<Requirement>,
+}
+
+impl Capabilities {
+    /// The oldest HashLink version able to run the bytecode
+    pub fn min_hashlink(&self) -> HlVersion {
+        self.requirements
+            .iter()
+            .map(|r| r.feature.since())
+            .max()
+            .unwrap_or_else(|| Feature::Format(self.version).since())
+    }
+}
+
+impl Display for Capabilities {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        writeln!(f, "bytecode version: {}", self.version)?;
+        writeln!(f, "debug info: {}", yes_no(self.debug))?;
+        writeln!(f, "constants: {}", yes_no(self.constants))?;
+        writeln!(f, "bytes pool: {}", yes_no(self.bytes))?;
+        write!(f, "min HashLink version: {}", self.min_hashlink())?;
+        for r in &self.requirements {
+            write!(f, "\n  {} : {}", r.feature.since(), r.feature)?;
+            if r.count > 1 {
+                write!(f, " ({} uses)", r.count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Opcodes added after the first releases of the VM
+fn opcode_feature(op: &Opcode) -> Option<Feature> {
+    match op {
+        Opcode::Assert
+        | Opcode::RefData { .. }
+        | Opcode::RefOffset { .. }
+        | Opcode::Nop
+        | Opcode::Prefetch { .. }
+        | Opcode::Asm { .. } => Some(Feature::Opcode(op.name())),
+        _ => None,
+    }
+}
+
+/// Types added after the first releases of the VM
+fn type_feature(ty: &Type) -> Option<Feature> {
+    match ty {
+        Type::Struct(_) => Some(Feature::Type("struct")),
+        Type::Packed(_) => Some(Feature::Type("packed")),
+        _ => None,
+    }
+}
+
+impl Bytecode {
+    /// Report the sections and the features used by this bytecode, see [Capabilities::min_hashlink].
+    pub fn capabilities(&self) -> Capabilities {
+        let mut requirements: Vec<Requirement> = Vec::new();
+        let mut require =
+            |feature: Feature| match requirements.iter_mut().find(|r| r.feature == feature) {
+                Some(r) => r.count += 1,
+                None => requirements.push(Requirement { feature, count: 1 }),
+            };
+        require(Feature::Format(self.version));
+        self.types
+            .iter()
+            .filter_map(type_feature)
+            .for_each(&mut require);
+        self.ops()
+            .filter_map(|(_, (_, op))| opcode_feature(op))
+            .for_each(&mut require);
+        // Stable sort, features with the same version stay in order of discovery
+        requirements.sort_by_key(|r| Reverse(r.feature.since()));
+
+        Capabilities {
+            version: self.version,
+            debug: self.debug_files.is_some(),
+            constants: self.constants.is_some(),
+            bytes: self.bytes.is_some(),
+            requirements,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::capabilities::{Feature, HlVersion};
+    use crate::builder::Builder;
+    use crate::opcodes::Opcode;
+    use crate::types::{Reg, Type};
+
+    #[test]
+    fn capabilities() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        b.function(
+            "",
+            &[],
+            void,
+            &[],
+            vec![Opcode::Nop, Opcode::Nop, Opcode::Ret { ret: Reg(0) }],
+        );
+        let code = b.code_mut();
+        code.version = 4;
+        code.bytes = None;
+        let code = b.build().unwrap();
+        let caps = code.capabilities();
+        assert!(!caps.debug);
+        assert_eq!(caps.min_hashlink(), HlVersion::new(1, 11));
+        assert_eq!(caps.requirements[0].feature, Feature::Opcode("Nop"));
+        assert_eq!(caps.requirements[0].count, 2);
+        assert_eq!(caps.requirements[1].feature, Feature::Format(4));
+    }
+}
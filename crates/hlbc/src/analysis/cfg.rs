@@ -0,0 +1,362 @@
+//! Control flow graph of a function.
+//!
+//! Instructions are split in basic blocks : sequences of instructions only entered by the first one and only left
+//! by the last one. Blocks are linked together by the jumps between them.
+//!
+//! ```
+//! # use hlbc::Bytecode;
+//! use hlbc::analysis::cfg::cfg;
+//! # let code = Bytecode::default();
+//!
+//! for f in &code.functions {
+//!     let cfg = cfg(f);
+//!     println!("{}", cfg.display(&code, f));
+//! }
+//! ```
+
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::ops::Range;
+
+use crate::analysis::escape;
+use crate::fmt::fmt;
+use crate::opcodes::Opcode;
+use crate::types::Function;
+use crate::Bytecode;
+
+/// A sequence of instructions always executed from start to end
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BasicBlock {
+    /// Index of the instructions of this block in the function
+    pub ops: Range<usize>,
+}
+
+impl BasicBlock {
+    /// Index of the last instruction of the block, the one deciding where to go next
+    pub fn last(&self) -> usize {
+        self.ops.end - 1
+    }
+}
+
+/// How control is transferred from a block to another
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum EdgeKind {
+    /// Execution continues with the next instruction
+    Next,
+    /// Unconditional jump
+    Jump,
+    /// Conditional jump taken
+    True,
+    /// Conditional jump not taken
+    False,
+    /// Switch case with this value
+    Case(usize),
+    /// Switch on a value with no case
+    Default,
+    /// Exception handler of a trap
+    Catch,
+}
+
+impl EdgeKind {
+    pub fn name(&self) -> String {
+        match self {
+            EdgeKind::Next => String::new(),
+            EdgeKind::Jump => "jump".to_owned(),
+            EdgeKind::True => "true".to_owned(),
+            EdgeKind::False => "false".to_owned(),
+            EdgeKind::Case(value) => format!("case {value}"),
+            EdgeKind::Default => "default".to_owned(),
+            EdgeKind::Catch => "catch".to_owned(),
+        }
+    }
+}
+
+/// Transfer of control between 2 blocks
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Edge {
+    /// Index of the source block
+    pub from: usize,
+    /// Index of the target block
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+/// Control flow graph of a function
+#[derive(Debug, Clone, Default)]
+pub struct Cfg {
+    /// Blocks sorted by position in the function, the first block is the entry point
+    pub blocks: Vec<BasicBlock>,
+    /// Edges sorted by source block
+    pub edges: Vec<Edge>,
+}
+
+impl Cfg {
+    /// Index of the block containing an instruction
+    pub fn block_of(&self, op: usize) -> Option<usize> {
+        let i = self.blocks.partition_point(|b| b.ops.end <= op);
+        self.blocks
+            .get(i)
+            .filter(|b| b.ops.contains(&op))
+            .map(|_| i)
+    }
+
+    /// Edges leaving a block
+    pub fn successors(&self, block: usize) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(move |e| e.from == block)
+    }
+
+    /// Edges entering a block
+    pub fn predecessors(&self, block: usize) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(move |e| e.to == block)
+    }
+
+    /// Successors and predecessors of each block, without duplicates
+    pub fn adjacency(&self) -> (Vec<Vec<usize>>, Vec<Vec<usize>>) {
+        let n = self.blocks.len();
+        let mut succs = vec![Vec::new(); n];
+        let mut preds = vec![Vec::new(); n];
+        for e in &self.edges {
+            if !succs[e.from].contains(&e.to) {
+                succs[e.from].push(e.to);
+                preds[e.to].push(e.from);
+            }
+        }
+        (succs, preds)
+    }
+
+    /// Generate dot language, each node lists the instructions of a block
+    pub fn display<'a>(&'a self, code: &'a Bytecode, f: &'a Function) -> impl Display + 'a {
+        const INDENT: &str = "    ";
+
+        fmt(move |w| {
+            writeln!(w, "digraph {{")?;
+            writeln!(w, "{INDENT}fontname=\"Jetbrains Mono,Fira Code,monospace\"")?;
+            writeln!(w, "{INDENT}node [fontname=\"Jetbrains Mono,Fira Code,monospace\" style=filled fillcolor=\"#f8f8f8\"]")?;
+            writeln!(
+                w,
+                "{INDENT}edge [fontname=\"Jetbrains Mono,Fira Code,monospace\"]"
+            )?;
+            for (i, b) in self.blocks.iter().enumerate() {
+                write!(w, "{INDENT}{i} [ label = \"")?;
+                for op in b.ops.clone() {
+                    let line = f.ops[op].display(code, f, op as i32, 11).to_string();
+                    write!(w, "{op:>3}: {}\\l", escape(&line))?;
+                }
+                writeln!(w, "\" fontsize=13 shape=box ]")?;
+            }
+            for e in &self.edges {
+                writeln!(
+                    w,
+                    "{INDENT}{} -> {} [ label = \"{}\" ]",
+                    e.from,
+                    e.to,
+                    e.kind.name()
+                )?;
+            }
+            writeln!(w, "}}")
+        })
+    }
+}
+
+/// Successors of an instruction ending a block, positions are absolute
+struct Exits {
+    targets: Vec<(usize, EdgeKind)>,
+    /// Kind of the edge to the next instruction if execution can continue there
+    next: Option<EdgeKind>,
+}
+
+/// Find where an instruction can transfer control, returns `None` if it doesn't end a block
+fn exits(pos: usize, op: &Opcode) -> Option<Exits> {
+    let targets = op.jump_targets(pos);
+    let (targets, next) = match op {
+        Opcode::JAlways { .. } => (vec![(targets[0], EdgeKind::Jump)], None),
+        Opcode::Switch { .. } => (
+            targets
+                .into_iter()
+                .enumerate()
+                .map(|(value, target)| (target, EdgeKind::Case(value)))
+                .collect(),
+            Some(EdgeKind::Default),
+        ),
+        Opcode::Trap { .. } => (vec![(targets[0], EdgeKind::Catch)], Some(EdgeKind::Next)),
+        Opcode::Ret { .. } | Opcode::Throw { .. } | Opcode::Rethrow { .. } => (Vec::new(), None),
+        // Conditional jumps
+        _ if !targets.is_empty() => (vec![(targets[0], EdgeKind::True)], Some(EdgeKind::False)),
+        _ => return None,
+    };
+    Some(Exits { targets, next })
+}
+
+/// Build the control flow graph of a function from its jumps.
+///
+/// Jumps outside of the function are ignored.
+pub fn cfg(f: &Function) -> Cfg {
+    let len = f.ops.len();
+    let mut leaders = BTreeSet::new();
+    if len > 0 {
+        leaders.insert(0);
+    }
+    for (i, op) in f.ops() {
+        if let Some(exits) = exits(i, op) {
+            leaders.insert(i + 1);
+            leaders.extend(exits.targets.iter().map(|&(t, _)| t));
+        }
+    }
+    let leaders: Vec<_> = leaders.into_iter().filter(|&l| l < len).collect();
+
+    let blocks: Vec<_> = leaders
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| BasicBlock {
+            ops: start..leaders.get(i + 1).copied().unwrap_or(len),
+        })
+        .collect();
+
+    let mut cfg = Cfg {
+        blocks,
+        edges: Vec::new(),
+    };
+    for (i, b) in cfg.blocks.iter().enumerate() {
+        let last = b.last();
+        let exits = exits(last, &f.ops[last]).unwrap_or(Exits {
+            targets: Vec::new(),
+            next: Some(EdgeKind::Next),
+        });
+        let next = exits.next.map(|kind| (last + 1, kind));
+        for (target, kind) in exits.targets.into_iter().chain(next) {
+            if let Some(to) = cfg.block_of(target) {
+                cfg.edges.push(Edge { from: i, to, kind });
+            }
+        }
+    }
+    cfg
+}
+
+/// Immediate dominator of each node reachable from `entry`, the entry is its own dominator.
+///
+/// Implementation of *A Simple, Fast Dominance Algorithm* (Cooper, Harvey & Kennedy).
+pub fn dominators(succs: &[Vec<usize>], preds: &[Vec<usize>], entry: usize) -> Vec<Option<usize>> {
+    let n = succs.len();
+    // Post order
+    let mut order = Vec::with_capacity(n);
+    let mut visited = vec![false; n];
+    visited[entry] = true;
+    let mut stack = vec![(entry, 0)];
+    while let Some(top) = stack.last_mut() {
+        let (node, i) = *top;
+        if let Some(&next) = succs[node].get(i) {
+            top.1 += 1;
+            if !visited[next] {
+                visited[next] = true;
+                stack.push((next, 0));
+            }
+        } else {
+            order.push(node);
+            stack.pop();
+        }
+    }
+    let mut po = vec![0; n];
+    for (i, &node) in order.iter().enumerate() {
+        po[node] = i;
+    }
+
+    let mut idom = vec![None; n];
+    idom[entry] = Some(entry);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &node in order.iter().rev().filter(|&&node| node != entry) {
+            let mut new: Option<usize> = None;
+            for &p in preds[node].iter().filter(|&&p| idom[p].is_some()) {
+                new = Some(match new {
+                    None => p,
+                    Some(other) => {
+                        let (mut a, mut b) = (p, other);
+                        while a != b {
+                            while po[a] < po[b] {
+                                a = idom[a].unwrap();
+                            }
+                            while po[b] < po[a] {
+                                b = idom[b].unwrap();
+                            }
+                        }
+                        a
+                    }
+                });
+            }
+            if new.is_some() && idom[node] != new {
+                idom[node] = new;
+                changed = true;
+            }
+        }
+    }
+    idom
+}
+
+/// Check if `a` dominates `b`
+pub fn dominates(idom: &[Option<usize>], a: usize, mut b: usize) -> bool {
+    loop {
+        if a == b {
+            return true;
+        }
+        match idom[b] {
+            Some(d) if d != b => b = d,
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::cfg::{cfg, BasicBlock, Edge, EdgeKind};
+    use crate::opcodes::Opcode;
+    use crate::types::{Function, RefFun, RefString, RefType, Reg};
+
+    #[test]
+    fn if_else() {
+        // if r0 { r1 = r0 } else { r1 = r2 }; return r1
+        let f = Function {
+            t: RefType(0),
+            findex: RefFun(0),
+            regs: vec![],
+            ops: vec![
+                Opcode::JFalse {
+                    cond: Reg(0),
+                    offset: 2,
+                },
+                Opcode::Mov {
+                    dst: Reg(1),
+                    src: Reg(0),
+                },
+                Opcode::JAlways { offset: 1 },
+                Opcode::Mov {
+                    dst: Reg(1),
+                    src: Reg(2),
+                },
+                Opcode::Ret { ret: Reg(1) },
+            ],
+            debug_info: None,
+            assigns: None,
+            name: RefString(0),
+            parent: None,
+        };
+        let cfg = cfg(&f);
+        assert_eq!(
+            cfg.blocks,
+            [0..1, 1..3, 3..4, 4..5].map(|ops| BasicBlock { ops })
+        );
+        let edge = |from, to, kind| Edge { from, to, kind };
+        assert_eq!(
+            cfg.edges,
+            [
+                edge(0, 2, EdgeKind::True),
+                edge(0, 1, EdgeKind::False),
+                edge(1, 3, EdgeKind::Jump),
+                edge(2, 3, EdgeKind::Next),
+            ]
+        );
+        assert_eq!(cfg.block_of(2), Some(1));
+        assert_eq!(cfg.predecessors(3).count(), 2);
+        assert!(cfg.successors(3).next().is_none());
+    }
+}
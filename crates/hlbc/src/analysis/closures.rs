@@ -0,0 +1,235 @@
+//! Names for the anonymous functions, inferred from where they are created.
+//!
+//! Local functions and lambdas have no name in the bytecode. A closure stored in a field or a variable is named after
+//! it, a closure passed to a function is named after the parameter receiving it (e.g. `onClick`). The other closures
+//! are named after the function creating them, like `update$lambda1`.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::analysis::cfg::cfg;
+use crate::analysis::liveness::reads_of;
+use crate::opcodes::Opcode;
+use crate::types::{Function, RefFun, Reg};
+use crate::{Bytecode, RefFunKnown, Str};
+
+/// The function has no name of its own, it isn't a method or bound to a field
+pub fn is_anonymous(code: &Bytecode, fun: RefFun) -> bool {
+    fun.as_fn(code).is_some_and(|f| f.name.0 == 0)
+}
+
+/// Name given by its use to the closure created by the instruction at `pos` in `f` : the variable or the field it is
+/// stored in, or the parameter it is passed to.
+///
+/// Variable and parameter names come from the debug information.
+pub fn context_name(code: &Bytecode, f: &Function, pos: usize) -> Option<Str> {
+    let closure = match f.ops.get(pos)? {
+        Opcode::StaticClosure { dst, .. } | Opcode::InstanceClosure { dst, .. } => *dst,
+        _ => return None,
+    };
+    if let Some(name) = f.var_name(code, pos) {
+        return Some(name);
+    }
+    reads_of(f, &cfg(f), pos, closure)
+        .into_iter()
+        .find_map(|i| use_name(code, f, i, closure))
+}
+
+/// Name given to the closure in `closure` by the instruction at `pos` reading it
+fn use_name(code: &Bytecode, f: &Function, pos: usize, closure: Reg) -> Option<Str> {
+    match f.ops[pos] {
+        Opcode::Mov { src, .. } if src == closure => f.var_name(code, pos),
+        Opcode::SetField { obj, field, src } if src == closure => {
+            Some(f[obj].field(field, code)?.name(code))
+        }
+        Opcode::SetThis { field, src } if src == closure => {
+            Some(f.regs.first()?.field(field, code)?.name(code))
+        }
+        Opcode::DynSet { field, src, .. } if src == closure => Some(code[field].clone()),
+        ref op => {
+            let (callee, args) = f.callee(code, op)?;
+            let callee = callee.as_fn(code)?;
+            // Argument names don't include `this`, which isn't in the arguments of `CallThis`
+            let this = callee.has_this(code) && !matches!(op, Opcode::CallThis { .. });
+            let this = usize::from(this);
+            let arg = args.iter().position(|&a| a == closure)?;
+            callee.arg_name(code, arg.checked_sub(this)?)
+        }
+    }
+}
+
+/// The anonymous function an instruction creates a closure of
+fn created_closure(code: &Bytecode, op: &Opcode) -> Option<RefFun> {
+    match *op {
+        Opcode::StaticClosure { fun, .. } | Opcode::InstanceClosure { fun, .. } => {
+            Some(fun).filter(|&fun| is_anonymous(code, fun))
+        }
+        _ => None,
+    }
+}
+
+/// Inferred names of all the anonymous functions of the bytecode created by another function
+pub fn closure_names(code: &Bytecode) -> HashMap<RefFun, Str> {
+    // The first place each anonymous function is created, a local function can create itself to recurse
+    let mut created = HashMap::new();
+    for f in &code.functions {
+        for (pos, op) in f.ops.iter().enumerate() {
+            if let Some(fun) = created_closure(code, op).filter(|&fun| fun != f.findex) {
+                created.entry(fun).or_insert((f.findex, pos));
+            }
+        }
+    }
+    let mut funs: Vec<_> = created.keys().copied().collect();
+    funs.sort_unstable();
+    let mut names = HashMap::new();
+    for fun in funs {
+        name_closure(code, &created, &mut names, &mut HashSet::new(), fun);
+    }
+    names
+}
+
+/// Name a closure and the anonymous functions creating it
+fn name_closure(
+    code: &Bytecode,
+    created: &HashMap<RefFun, (RefFun, usize)>,
+    names: &mut HashMap<RefFun, Str>,
+    visiting: &mut HashSet<RefFun>,
+    fun: RefFun,
+) -> Option<Str> {
+    if let Some(name) = names.get(&fun) {
+        return Some(name.clone());
+    }
+    let &(parent, pos) = created.get(&fun)?;
+    if !visiting.insert(fun) {
+        // Closures creating each other
+        return None;
+    }
+    let f = parent.as_fn(code)?;
+    let name = context_name(code, f, pos).or_else(|| {
+        let enclosing = if is_anonymous(code, parent) {
+            name_closure(code, created, names, visiting, parent)
+                .unwrap_or_else(|| Str::from_static("anonymous"))
+        } else {
+            f.name(code)
+        };
+        // Number the closures of the function without a better name
+        let n = f.ops[..pos]
+            .iter()
+            .filter_map(|op| created_closure(code, op))
+            .filter(|&other| other != fun)
+            .count();
+        Some(Str::from(format!("{enclosing}$lambda{n}")))
+    })?;
+    names.insert(fun, name.clone());
+    Some(name)
+}
+
+impl Bytecode {
+    /// Give their [inferred names](closure_names) to the anonymous functions, the disassembler and the decompiler then
+    /// show them like the other function names, and [Bytecode::function_by_name] finds them. Returns the number of
+    /// functions named.
+    ///
+    /// The names are only used for display, the string pool and the functions are left untouched : saving the
+    /// bytecode writes it as it was loaded.
+    pub fn name_closures(&mut self) -> usize {
+        let names = closure_names(self);
+        let mut funs: Vec<_> = names.keys().copied().collect();
+        funs.sort_unstable();
+        for fun in funs {
+            if let Some(&RefFunKnown::Fun(i)) = self.findexes.get(fun.0) {
+                // Named functions keep their name when a closure is named the same
+                self.fnames.entry(names[&fun].clone()).or_insert(i);
+            }
+        }
+        let count = names.len();
+        self.closure_names = names;
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::closures::{closure_names, context_name};
+    use crate::builder::Builder;
+    use crate::opcodes::Opcode;
+    use crate::types::{Function, RefFun, RefString, Reg, Type};
+    use crate::{Bytecode, Resolve};
+
+    #[test]
+    fn names() {
+        let mut b = Builder::new();
+        let dyn_ = b.ty(Type::Dyn);
+        let ret = || vec![Opcode::Ret { ret: Reg(0) }];
+        let mut closure = |ops| b.function("", &[dyn_], dyn_, &[dyn_], ops);
+        let on_click = closure(ret());
+        let inner = closure(ret());
+        // Creates inner
+        let outer = closure(vec![
+            Opcode::StaticClosure {
+                dst: Reg(0),
+                fun: inner,
+            },
+            Opcode::Ret { ret: Reg(0) },
+        ]);
+        let tmp = closure(ret());
+        let on = b.function("on", &[dyn_], dyn_, &[dyn_], ret());
+        let name = b.string("onClick");
+        b.function_mut(on).assigns = Some(vec![(name, 0)]);
+        // main passes on_click to on(onClick), creates outer and keeps tmp in a variable
+        let main = b.function(
+            "main",
+            &[dyn_],
+            dyn_,
+            &[dyn_],
+            vec![
+                Opcode::StaticClosure {
+                    dst: Reg(1),
+                    fun: on_click,
+                },
+                Opcode::Call1 {
+                    dst: Reg(0),
+                    fun: on,
+                    arg0: Reg(1),
+                },
+                Opcode::StaticClosure {
+                    dst: Reg(1),
+                    fun: outer,
+                },
+                Opcode::StaticClosure {
+                    dst: Reg(0),
+                    fun: tmp,
+                },
+                Opcode::Ret { ret: Reg(1) },
+            ],
+        );
+        let name = b.string("tmp");
+        b.function_mut(main).assigns = Some(vec![(name, 4)]);
+        b.code_mut().entrypoint = main;
+        let mut code = b.build().unwrap();
+        let fun =
+            |code: &Bytecode, fun: RefFun| -> Function { code.get(fun).as_fn().unwrap().clone() };
+
+        let main_fn = fun(&code, main);
+        assert_eq!(context_name(&code, &main_fn, 0).as_deref(), Some("onClick"));
+        assert_eq!(context_name(&code, &main_fn, 2), None);
+        assert_eq!(context_name(&code, &main_fn, 3).as_deref(), Some("tmp"));
+        assert_eq!(context_name(&code, &main_fn, 1), None);
+
+        let names = closure_names(&code);
+        assert_eq!(names.len(), 4);
+        assert_eq!(&*names[&on_click], "onClick");
+        assert_eq!(&*names[&outer], "main$lambda1");
+        assert_eq!(&*names[&inner], "main$lambda1$lambda0");
+
+        let strings = code.strings.len();
+        assert_eq!(code.name_closures(), 4);
+        assert_eq!(fun(&code, tmp).name(&code), "tmp");
+        assert_eq!(fun(&code, on).name(&code), "on");
+        assert_eq!(
+            code.function_by_name("onClick").map(|f| f.findex),
+            Some(on_click)
+        );
+        // Nothing to write back
+        assert_eq!(code.strings.len(), strings);
+        assert_eq!(fun(&code, tmp).name, RefString(0));
+    }
+}
@@ -0,0 +1,107 @@
+//! Instructions that can never be executed : no path from the start of the function leads to them.
+//!
+//! The compiler leaves a few behind, like a jump after a return. Obfuscators insert a lot more to mislead readers.
+
+use std::ops::Range;
+
+use crate::analysis::cfg::{cfg, Cfg};
+use crate::opcodes::Opcode;
+use crate::types::RefFun;
+use crate::Bytecode;
+
+/// Whether each block can be reached from the entry block. Exception handlers are reached through their trap.
+pub fn reachable_blocks(cfg: &Cfg) -> Vec<bool> {
+    let mut succs = vec![Vec::new(); cfg.blocks.len()];
+    for e in &cfg.edges {
+        succs[e.from].push(e.to);
+    }
+    let mut reached = vec![false; cfg.blocks.len()];
+    let mut todo = Vec::new();
+    if !reached.is_empty() {
+        reached[0] = true;
+        todo.push(0);
+    }
+    while let Some(b) = todo.pop() {
+        for &to in &succs[b] {
+            if !reached[to] {
+                reached[to] = true;
+                todo.push(to);
+            }
+        }
+    }
+    reached
+}
+
+/// Ranges of instructions that can't be reached, consecutive unreachable blocks are merged
+pub fn unreachable_ops(cfg: &Cfg) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for (b, reached) in cfg.blocks.iter().zip(reachable_blocks(cfg)) {
+        if reached {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(r) if r.end == b.ops.start => r.end = b.ops.end,
+            _ => ranges.push(b.ops.clone()),
+        }
+    }
+    ranges
+}
+
+/// Unreachable code left by the compiler, only jumps and returns
+pub fn is_leftover(ops: &[Opcode]) -> bool {
+    ops.iter()
+        .all(|op| matches!(op, Opcode::JAlways { .. } | Opcode::Ret { .. }))
+}
+
+impl Bytecode {
+    /// Every function with unreachable instructions, with the ranges of those instructions.
+    ///
+    /// Ranges made only of jumps and returns are ignored, the compiler emits them after a return or a throw.
+    pub fn dead_code(&self) -> Vec<(RefFun, Vec<Range<usize>>)> {
+        self.functions
+            .iter()
+            .filter_map(|f| {
+                let ranges: Vec<_> = unreachable_ops(&cfg(f))
+                    .into_iter()
+                    .filter(|r| !is_leftover(&f.ops[r.clone()]))
+                    .collect();
+                (!ranges.is_empty()).then_some((f.findex, ranges))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::cfg::cfg;
+    use crate::analysis::deadcode::unreachable_ops;
+    use crate::builder::Builder;
+    use crate::opcodes::Opcode;
+    use crate::types::{Reg, Type};
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn dead_code() {
+        // if r0 { return r0 } else { return r1 }; r1 = r0; return r1
+        let ops = vec![
+            Opcode::JFalse {
+                cond: Reg(0),
+                offset: 2,
+            },
+            Opcode::Ret { ret: Reg(0) },
+            Opcode::JAlways { offset: 1 },
+            Opcode::Ret { ret: Reg(1) },
+            Opcode::Mov {
+                dst: Reg(1),
+                src: Reg(0),
+            },
+            Opcode::Ret { ret: Reg(1) },
+        ];
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let fun = b.function("", &[int, int], int, &[], ops);
+        let code = b.build().unwrap();
+        assert_eq!(unreachable_ops(&cfg(&code.functions[0])), [2..3, 4..6]);
+        assert_eq!(code.dead_code(), [(fun, vec![4..6])]);
+    }
+}
@@ -0,0 +1,420 @@
+//! Compare two versions of a program, like a game before and after an update.
+//!
+//! Indices change between two compilations, so elements are matched by name instead : functions by their qualified
+//! name and signature, types by their name, strings by their content and globals by their type.
+//! Elements with the same name are matched in order of appearance.
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+use crate::fmt::EnhancedFmt;
+use crate::opcodes::Opcode;
+use crate::types::{FunPtr, Function, RefFun, RefGlobal, RefString, RefType, Type};
+use crate::{Bytecode, Str};
+
+/// Functions bigger than this (old opcodes * new opcodes) are diffed as entirely replaced
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// A line of the diff of two functions. Opcodes are displayed as in the assembly view, without the indices.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum OpChange {
+    Kept(String),
+    Removed(String),
+    Added(String),
+}
+
+/// A function present in both versions with different opcodes
+#[derive(Debug, Clone)]
+pub struct FunctionDiff {
+    /// The function in the old bytecode
+    pub old: RefFun,
+    /// The function in the new bytecode
+    pub new: RefFun,
+    pub ops: Vec<OpChange>,
+}
+
+impl FunctionDiff {
+    /// Number of added and removed opcodes
+    pub fn changes(&self) -> usize {
+        self.ops
+            .iter()
+            .filter(|c| !matches!(c, OpChange::Kept(_)))
+            .count()
+    }
+}
+
+/// Differences between two bytecodes, see [Bytecode::diff].
+/// Removed elements are indices in the old bytecode, added elements are indices in the new one.
+#[derive(Debug, Clone, Default)]
+pub struct BytecodeDiff {
+    pub added_functions: Vec<RefFun>,
+    pub removed_functions: Vec<RefFun>,
+    pub changed_functions: Vec<FunctionDiff>,
+    /// Only named types are compared : classes, enums and abstracts
+    pub added_types: Vec<RefType>,
+    pub removed_types: Vec<RefType>,
+    /// Types with different fields, methods or constructors, as `(old, new)`
+    pub changed_types: Vec<(RefType, RefType)>,
+    pub added_strings: Vec<Str>,
+    pub removed_strings: Vec<Str>,
+    pub added_globals: Vec<RefGlobal>,
+    pub removed_globals: Vec<RefGlobal>,
+}
+
+impl BytecodeDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_functions.is_empty()
+            && self.removed_functions.is_empty()
+            && self.changed_functions.is_empty()
+            && self.added_types.is_empty()
+            && self.removed_types.is_empty()
+            && self.changed_types.is_empty()
+            && self.added_strings.is_empty()
+            && self.removed_strings.is_empty()
+            && self.added_globals.is_empty()
+            && self.removed_globals.is_empty()
+    }
+}
+
+/// Elements matched by key, as `(matched, removed, added)`. Repeated keys are matched in order.
+#[allow(clippy::type_complexity)]
+fn pair<K: Eq + Hash, T>(
+    old: impl IntoIterator<Item = (K, T)>,
+    new: impl IntoIterator<Item = (K, T)>,
+) -> (Vec<(T, T)>, Vec<T>, Vec<T>) {
+    let mut old_items = Vec::new();
+    let mut unmatched: HashMap<K, VecDeque<usize>> = HashMap::new();
+    for (key, t) in old {
+        unmatched.entry(key).or_default().push_back(old_items.len());
+        old_items.push(Some(t));
+    }
+    let mut pairs = Vec::new();
+    let mut added = Vec::new();
+    for (key, t) in new {
+        match unmatched.get_mut(&key).and_then(VecDeque::pop_front) {
+            Some(i) => pairs.push((old_items[i].take().unwrap(), t)),
+            None => added.push(t),
+        }
+    }
+    let removed = old_items.into_iter().flatten().collect();
+    (pairs, removed, added)
+}
+
+/// Qualified name and signature of a function, e.g. `Game.update (Game, f64) -> void`
+fn function_key(code: &Bytecode, fun: FunPtr) -> String {
+    match fun {
+        FunPtr::Fun(f) => {
+            let parent = f
+                .parent
+                .map(|p| code[p].display::<EnhancedFmt>(code).to_string())
+                .unwrap_or_default();
+            format!(
+                "{parent}.{} {}",
+                f.name(code),
+                code[f.t].display::<EnhancedFmt>(code)
+            )
+        }
+        FunPtr::Native(n) => format!(
+            "{}/{} {}",
+            n.lib(code),
+            n.name(code),
+            code[n.t].display::<EnhancedFmt>(code)
+        ),
+    }
+}
+
+/// Remove the `@index` suffixes of the displayed elements
+fn strip_indices(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '@' && chars.peek().is_some_and(char::is_ascii_digit) {
+            while chars.next_if(char::is_ascii_digit).is_some() {}
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// An opcode as in the assembly view, without anything changing between compilations : indices and absolute jump
+/// targets.
+fn normalized_op(code: &Bytecode, f: &Function, op: &Opcode) -> String {
+    let global = |g: RefGlobal| code[code[g]].display::<EnhancedFmt>(code).to_string();
+    let text = match op {
+        Opcode::StaticClosure { dst, fun } => format!("{} {dst} = {}", op.name(), fun.name(code)),
+        Opcode::InstanceClosure { dst, fun, obj } => {
+            format!("{} {dst} = {obj}.{}", op.name(), fun.name(code))
+        }
+        Opcode::GetGlobal { dst, global: g } => {
+            format!("{} {dst} = global<{}>", op.name(), global(*g))
+        }
+        Opcode::SetGlobal { global: g, src } => {
+            format!("{} global<{}> = {src}", op.name(), global(*g))
+        }
+        // Jump targets become relative to the opcode
+        _ => op.display(code, f, 0, 0).to_string(),
+    };
+    strip_indices(&text)
+}
+
+/// Diff of two lists of opcodes, from their longest common subsequence after the common prefix and suffix
+fn diff_ops(old: &[String], new: &[String]) -> Vec<OpChange> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    let mut changes: Vec<_> = old[..prefix].iter().cloned().map(OpChange::Kept).collect();
+    let (mut i, mut j) = (0, 0);
+    if a.len() * b.len() <= MAX_DIFF_CELLS {
+        // lcs[i * w + j] is the length of the longest common subsequence of a[i..] and b[j..]
+        let w = b.len() + 1;
+        let mut lcs = vec![0u32; (a.len() + 1) * w];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i * w + j] = if a[i] == b[j] {
+                    lcs[(i + 1) * w + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * w + j].max(lcs[i * w + j + 1])
+                };
+            }
+        }
+        while i < a.len() && j < b.len() {
+            if a[i] == b[j] {
+                changes.push(OpChange::Kept(a[i].clone()));
+                i += 1;
+                j += 1;
+            } else if lcs[(i + 1) * w + j] >= lcs[i * w + j + 1] {
+                changes.push(OpChange::Removed(a[i].clone()));
+                i += 1;
+            } else {
+                changes.push(OpChange::Added(b[j].clone()));
+                j += 1;
+            }
+        }
+    }
+    changes.extend(a[i..].iter().cloned().map(OpChange::Removed));
+    changes.extend(b[j..].iter().cloned().map(OpChange::Added));
+    changes.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .cloned()
+            .map(OpChange::Kept),
+    );
+    changes
+}
+
+/// Name of the types compared between versions
+fn type_key(code: &Bytecode, ty: &Type) -> Option<String> {
+    match ty {
+        Type::Obj(_) | Type::Struct(_) | Type::Enum { .. } | Type::Abstract { .. } => {
+            Some(ty.display::<EnhancedFmt>(code).to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Everything that makes a type declaration, a type changed if this changed
+fn type_content(code: &Bytecode, ty: &Type) -> Vec<String> {
+    let ty_name = |t: RefType| code[t].display::<EnhancedFmt>(code).to_string();
+    match ty {
+        Type::Obj(obj) | Type::Struct(obj) => obj
+            .super_
+            .map(|s| format!("extends {}", ty_name(s)))
+            .into_iter()
+            .chain(
+                obj.own_fields
+                    .iter()
+                    .map(|f| format!("var {}: {}", f.name(code), ty_name(f.t))),
+            )
+            .chain(obj.protos.iter().map(|p| format!("fn {}", p.name(code))))
+            .collect(),
+        Type::Enum { constructs, .. } => constructs
+            .iter()
+            .map(|c| {
+                let params: Vec<_> = c.params.iter().map(|&p| ty_name(p)).collect();
+                format!("{}({})", code[c.name], params.join(", "))
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+impl Bytecode {
+    /// Compare this bytecode with a newer version of the program, see the [module](self) documentation.
+    /// Changed functions come with the diff of their opcodes.
+    pub fn diff(&self, new: &Bytecode) -> BytecodeDiff {
+        let mut diff = BytecodeDiff::default();
+
+        let functions = |code: &Bytecode| -> Vec<_> {
+            code.functions
+                .iter()
+                .map(FunPtr::Fun)
+                .chain(code.natives.iter().map(FunPtr::Native))
+                .map(|f| (function_key(code, f), f.findex()))
+                .collect()
+        };
+        let (pairs, removed, added) = pair(functions(self), functions(new));
+        diff.removed_functions = removed;
+        diff.added_functions = added;
+        let ops = |code: &Bytecode, f: &Function| -> Vec<_> {
+            f.ops.iter().map(|op| normalized_op(code, f, op)).collect()
+        };
+        for (old_fun, new_fun) in pairs {
+            let (Some(old_f), Some(new_f)) = (old_fun.as_fn(self), new_fun.as_fn(new)) else {
+                continue;
+            };
+            let (old_ops, new_ops) = (ops(self, old_f), ops(new, new_f));
+            if old_ops != new_ops {
+                diff.changed_functions.push(FunctionDiff {
+                    old: old_fun,
+                    new: new_fun,
+                    ops: diff_ops(&old_ops, &new_ops),
+                });
+            }
+        }
+
+        let types = |code: &Bytecode| -> Vec<_> {
+            code.types
+                .iter()
+                .enumerate()
+                .filter_map(|(i, t)| Some((type_key(code, t)?, RefType(i))))
+                .collect()
+        };
+        let (pairs, removed, added) = pair(types(self), types(new));
+        diff.removed_types = removed;
+        diff.added_types = added;
+        diff.changed_types = pairs
+            .into_iter()
+            .filter(|&(o, n)| type_content(self, &self[o]) != type_content(new, &new[n]))
+            .collect();
+
+        let strings = |code: &Bytecode| -> Vec<_> {
+            (0..code.strings.len())
+                .map(|i| (code[RefString(i)].clone(), RefString(i)))
+                .collect()
+        };
+        let (_, removed, added) = pair(strings(self), strings(new));
+        diff.removed_strings = removed.into_iter().map(|s| self[s].clone()).collect();
+        diff.added_strings = added.into_iter().map(|s| new[s].clone()).collect();
+
+        let globals = |code: &Bytecode| -> Vec<_> {
+            (0..code.globals.len())
+                .map(|i| {
+                    let ty = code[code[RefGlobal(i)]].display::<EnhancedFmt>(code);
+                    (ty.to_string(), RefGlobal(i))
+                })
+                .collect()
+        };
+        let (_, removed, added) = pair(globals(self), globals(new));
+        diff.removed_globals = removed;
+        diff.added_globals = added;
+
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::diff::OpChange;
+    use crate::builder::Builder;
+    use crate::opcodes::Opcode;
+    use crate::types::{RefFun, RefGlobal, RefInt, Reg, Type};
+    use crate::{Bytecode, Str};
+
+    /// A `Game` class with int fields, its global, and functions returning an int
+    fn code(
+        fields: &[&str],
+        functions: Vec<(&str, Vec<Opcode>)>,
+        build: impl FnOnce(&mut Builder),
+    ) -> Bytecode {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        b.int(1);
+        let fields: Vec<_> = fields.iter().map(|&name| (name, int)).collect();
+        let game = b.class("Game", None, &fields);
+        b.global(game);
+        for (name, ops) in functions {
+            b.function(name, &[], int, &[int], ops);
+        }
+        build(&mut b);
+        b.build().unwrap()
+    }
+
+    #[test]
+    fn diff() {
+        let int = Opcode::Int {
+            dst: Reg(0),
+            ptr: RefInt(0),
+        };
+        let ret = Opcode::Ret { ret: Reg(0) };
+        let mut abstract_ = None;
+        let old = code(
+            &["x"],
+            vec![
+                ("a", vec![int.clone(), ret.clone()]),
+                ("b", vec![ret.clone()]),
+            ],
+            |b| {
+                let name = b.string("b");
+                abstract_ = Some(b.ty(Type::Abstract { name }));
+                b.string("hello");
+            },
+        );
+        let new = code(
+            &["x", "y"],
+            vec![
+                ("c", vec![ret.clone()]),
+                (
+                    "a",
+                    vec![
+                        int,
+                        Opcode::Mov {
+                            dst: Reg(0),
+                            src: Reg(0),
+                        },
+                        ret,
+                    ],
+                ),
+            ],
+            |b| {
+                let int = b.ty(Type::I32);
+                b.global(int);
+                b.string("world");
+            },
+        );
+
+        let diff = old.diff(&new);
+        assert!(!diff.is_empty());
+        assert_eq!(diff.removed_functions, [RefFun(1)]);
+        assert_eq!(diff.added_functions, [RefFun(0)]);
+        assert_eq!(diff.changed_functions.len(), 1);
+        let changed = &diff.changed_functions[0];
+        assert_eq!((changed.old, changed.new), (RefFun(0), RefFun(1)));
+        assert_eq!(changed.changes(), 1);
+        assert!(matches!(
+            &changed.ops[..],
+            [OpChange::Kept(_), OpChange::Added(_), OpChange::Kept(_)]
+        ));
+
+        let game = old.globals[0];
+        assert_eq!(diff.removed_types, [abstract_.unwrap()]);
+        assert!(diff.added_types.is_empty());
+        assert_eq!(diff.changed_types, [(game, game)]);
+        assert_eq!(diff.removed_strings, [Str::from("b"), Str::from("hello")]);
+        assert_eq!(
+            diff.added_strings,
+            [Str::from("y"), Str::from("c"), Str::from("world")]
+        );
+        assert!(diff.removed_globals.is_empty());
+        assert_eq!(diff.added_globals, [RefGlobal(1)]);
+
+        assert!(old.diff(&old).is_empty());
+    }
+}
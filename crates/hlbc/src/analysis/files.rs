@@ -1,21 +1,36 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::ops::Range;
 
 use crate::types::{Function, RefFun};
 use crate::{Bytecode, Str};
 
-/// Finds the function which are present in a given file.
-/// Only looks at the first instruction to determine the file.
+/// Index in the debug files of the source file a function is defined in.
 ///
-/// Panics if no debug info is present.
-pub fn functions_in_files(code: &Bytecode) -> HashMap<Str, Vec<RefFun>> {
-    let df = code.debug_files.as_ref().unwrap();
+/// Looks at the last instruction, usually a `Ret` : the first instructions can be inlined code from another file.
+/// Returns `None` without debug info.
+pub fn source_file(f: &Function) -> Option<usize> {
+    f.debug_info.as_ref()?.last().map(|&(file, _)| file)
+}
+
+/// Groups the functions by the source file they are defined in (see [source_file]), in bytecode order.
+/// Files without any function are omitted.
+///
+/// Returns an empty map if no debug info is present.
+pub fn functions_in_files(code: &Bytecode) -> BTreeMap<Str, Vec<RefFun>> {
+    let Some(df) = code.debug_files.as_ref() else {
+        return BTreeMap::new();
+    };
     let mut funs = vec![Vec::new(); df.len()];
     for f in &code.functions {
-        let file = f.debug_info.as_ref().unwrap()[0].0;
-        funs[file].push(f.findex);
+        if let Some(file) = source_file(f).filter(|&file| file < df.len()) {
+            funs[file].push(f.findex);
+        }
     }
-    df.iter().cloned().zip(funs).collect()
+    df.iter()
+        .cloned()
+        .zip(funs)
+        .filter(|(_, funs)| !funs.is_empty())
+        .collect()
 }
 
 /// Finds the files a function is present in. Usually there is only one except
@@ -23,15 +38,15 @@ pub fn functions_in_files(code: &Bytecode) -> HashMap<Str, Vec<RefFun>> {
 /// the original function file.
 ///
 /// Panics if no debug info.
-pub fn files_in_function(code: &Bytecode, f: &Function) -> HashMap<Str, Vec<Range<usize>>> {
+pub fn files_in_function(code: &Bytecode, f: &Function) -> BTreeMap<Str, Vec<Range<usize>>> {
     let dbg = f.debug_info.as_ref().unwrap();
     let mut start = 0;
     let mut curr_file = dbg[0].0;
-    let mut ranges = HashMap::<_, Vec<Range<usize>>>::new();
+    let mut ranges = BTreeMap::<_, Vec<Range<usize>>>::new();
     for (i, &(file, _)) in dbg.iter().enumerate() {
         if file != curr_file {
             ranges
-                .entry(code.debug_file(file).unwrap())
+                .entry(code.debug_file(curr_file).unwrap())
                 .or_default()
                 .push(start..i);
             start = i;
@@ -39,12 +54,19 @@ pub fn files_in_function(code: &Bytecode, f: &Function) -> HashMap<Str, Vec<Rang
         }
     }
     ranges
+        .entry(code.debug_file(curr_file).unwrap())
+        .or_default()
+        .push(start..dbg.len());
+    ranges
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::analysis::files::{files_in_function, functions_in_files};
-    use crate::Bytecode;
+    use crate::analysis::files::{files_in_function, functions_in_files, source_file};
+    use crate::builder::Builder;
+    use crate::opcodes::Opcode;
+    use crate::types::{Reg, Type};
+    use crate::{Bytecode, Str};
 
     #[test]
     fn test_files() {
@@ -57,6 +79,38 @@ mod tests {
     fn test_lines() {
         let code = Bytecode::from_file("../../data/Empty.hl").unwrap();
         let files = files_in_function(&code, code.entrypoint());
+        let covered: usize = files.values().flatten().map(|r| r.len()).sum();
+        assert_eq!(covered, code.entrypoint().ops.len());
         dbg!(files);
     }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn inlined_ranges() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        let fun = b.function("main", &[], void, &[void], vec![Opcode::Nop; 3]);
+        b.function_mut(fun).ops.push(Opcode::Ret { ret: Reg(0) });
+        // Code inlined from B.hx in the middle of the function
+        b.function_mut(fun).debug_info = Some(vec![(0, 1), (1, 5), (1, 6), (0, 2)]);
+        b.code_mut().debug_files = Some(vec![Str::from("A.hx"), Str::from("B.hx")]);
+        let code = b.build().unwrap();
+        let files = files_in_function(&code, &code.functions[0]);
+        assert_eq!(files["A.hx"], [0..1, 3..4]);
+        assert_eq!(files["B.hx"], [1..3]);
+        assert_eq!(source_file(&code.functions[0]), Some(0));
+    }
+
+    #[test]
+    fn test_source_file() {
+        let code = Bytecode::from_file("../../data/Empty.hl").unwrap();
+        let files = functions_in_files(&code);
+        assert_eq!(
+            files.values().map(Vec::len).sum::<usize>(),
+            code.functions.len()
+        );
+        let main = code.main();
+        let file = code.debug_file(source_file(main).unwrap()).unwrap();
+        assert!(files[&file].contains(&main.findex));
+    }
 }
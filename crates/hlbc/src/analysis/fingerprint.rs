@@ -0,0 +1,262 @@
+//! Match the functions of two versions of a program when names and indices changed, like a game update
+//! recompiled with a different obfuscation.
+//!
+//! Each function gets a structural fingerprint : how many of each opcode it has, the constants it uses and the
+//! functions it calls. Two functions are similar when their fingerprints are.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::opcodes::Opcode;
+use crate::types::{FunPtr, Function, RefFun};
+use crate::{Bytecode, Resolve, Str};
+
+/// Pairs less similar than this are not reported by [match_functions]
+pub const MIN_SIMILARITY: f32 = 0.7;
+
+/// Functions with less opcodes than this proportion of the other aren't compared
+const MIN_LEN_RATIO: f32 = 0.5;
+
+/// A constant used by a function
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+enum Constant {
+    Int(i32),
+    /// Bits of the float
+    Float(u64),
+    String(Str),
+    Bytes(Vec<u8>),
+}
+
+/// Structural summary of a function, independent of its name and of the indices of the bytecode elements
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Fingerprint {
+    /// Number of each kind of opcode
+    ops: BTreeMap<&'static str, u32>,
+    constants: HashSet<Constant>,
+    /// Names of the called functions, natives and standard library functions are usually not renamed
+    calls: HashSet<Str>,
+    args: usize,
+    len: usize,
+}
+
+/// |a & b| / |a | b|, 1 when both are empty
+fn jaccard<T: Eq + Hash>(a: &HashSet<T>, b: &HashSet<T>) -> f32 {
+    let union = a.union(b).count();
+    if union == 0 {
+        1.0
+    } else {
+        a.intersection(b).count() as f32 / union as f32
+    }
+}
+
+impl Fingerprint {
+    pub fn new(code: &Bytecode, f: &Function) -> Self {
+        let mut ops = BTreeMap::new();
+        let mut constants = HashSet::new();
+        for op in &f.ops {
+            *ops.entry(op.name()).or_insert(0) += 1;
+            // Constants out of the pools of a malformed file are left out of the fingerprint
+            let constant = match *op {
+                Opcode::Int { ptr, .. } => code.try_get(ptr).ok().map(Constant::Int),
+                Opcode::Float { ptr, .. } => {
+                    code.try_get(ptr).ok().map(|f| Constant::Float(f.to_bits()))
+                }
+                Opcode::String { ptr, .. } => code.try_get(ptr).ok().map(Constant::String),
+                Opcode::Bytes { ptr, .. } => {
+                    code.try_get(ptr).ok().map(|b| Constant::Bytes(b.to_vec()))
+                }
+                _ => None,
+            };
+            constants.extend(constant);
+        }
+        let calls = f
+            .find_fun_refs()
+            .map(|(_, _, fun)| match code.get(fun) {
+                FunPtr::Fun(callee) => callee.name(code),
+                FunPtr::Native(n) => Str::from(format!("{}/{}", n.lib(code), n.name(code))),
+            })
+            .collect();
+        Self {
+            ops,
+            constants,
+            calls,
+            args: f.args(code).len(),
+            len: f.ops.len(),
+        }
+    }
+
+    /// How alike two functions are, between 0 (nothing in common) and 1 (identical fingerprints)
+    pub fn similarity(&self, other: &Self) -> f32 {
+        let (mut common, mut total) = (0, 0);
+        for (name, &n) in &self.ops {
+            let m = other.ops.get(name).copied().unwrap_or(0);
+            common += n.min(m);
+            total += n.max(m);
+        }
+        total += other
+            .ops
+            .iter()
+            .filter(|(name, _)| !self.ops.contains_key(*name))
+            .map(|(_, &m)| m)
+            .sum::<u32>();
+        let ops = if total == 0 {
+            1.0
+        } else {
+            common as f32 / total as f32
+        };
+        let args = if self.args == other.args { 1.0 } else { 0.0 };
+        0.5 * ops
+            + 0.25 * jaccard(&self.constants, &other.constants)
+            + 0.15 * jaccard(&self.calls, &other.calls)
+            + 0.1 * args
+    }
+}
+
+/// Functions of `new` matching functions of `old`, as `(old, new, similarity)` sorted by old findex.
+///
+/// Each function is matched at most once, to its most similar counterpart with a similarity of at least
+/// [MIN_SIMILARITY]. Functions without code are ignored. Only functions of comparable sizes are compared, but this
+/// is still quadratic on big programs.
+pub fn match_functions(old: &Bytecode, new: &Bytecode) -> Vec<(RefFun, RefFun, f32)> {
+    let fingerprints = |code: &Bytecode| -> Vec<_> {
+        code.functions
+            .iter()
+            .filter(|f| !f.ops.is_empty())
+            .map(|f| (f.findex, Fingerprint::new(code, f)))
+            .collect()
+    };
+    let old_prints = fingerprints(old);
+    let mut new_prints = fingerprints(new);
+    new_prints.sort_by_key(|(_, p)| p.len);
+
+    let mut candidates = Vec::new();
+    for (old_fun, print) in &old_prints {
+        let min = (print.len as f32 * MIN_LEN_RATIO).ceil() as usize;
+        let max = (print.len as f32 / MIN_LEN_RATIO) as usize;
+        let start = new_prints.partition_point(|(_, p)| p.len < min);
+        for (new_fun, other) in new_prints[start..].iter().take_while(|(_, p)| p.len <= max) {
+            let similarity = print.similarity(other);
+            if similarity >= MIN_SIMILARITY {
+                candidates.push((*old_fun, *new_fun, similarity));
+            }
+        }
+    }
+
+    // Greedily keep the best pairs, ties are broken in favor of the closest indices
+    candidates.sort_by(|a, b| {
+        b.2.total_cmp(&a.2)
+            .then_with(|| a.0 .0.abs_diff(a.1 .0).cmp(&b.0 .0.abs_diff(b.1 .0)))
+    });
+    let mut old_matched = HashMap::new();
+    let mut new_matched = HashSet::new();
+    for (old_fun, new_fun, similarity) in candidates {
+        if !old_matched.contains_key(&old_fun) && new_matched.insert(new_fun) {
+            old_matched.insert(old_fun, (new_fun, similarity));
+        }
+    }
+    let mut matches: Vec<_> = old_matched
+        .into_iter()
+        .map(|(old_fun, (new_fun, similarity))| (old_fun, new_fun, similarity))
+        .collect();
+    matches.sort_by_key(|m| m.0);
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::fingerprint::{match_functions, Fingerprint};
+    use crate::builder::Builder;
+    use crate::opcodes::Opcode;
+    use crate::types::{RefBytes, RefFun, RefInt, Reg, Type};
+    use crate::Bytecode;
+
+    fn code(names: [&str; 2], bodies: [Vec<Opcode>; 2]) -> Bytecode {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        b.int(42);
+        b.int(7);
+        for (name, ops) in names.into_iter().zip(bodies) {
+            b.function(name, &[], int, &[int], ops);
+        }
+        b.build().unwrap()
+    }
+
+    #[test]
+    fn match_renamed() {
+        let int = |ptr| Opcode::Int {
+            dst: Reg(0),
+            ptr: RefInt(ptr),
+        };
+        let ret = Opcode::Ret { ret: Reg(0) };
+        let add = Opcode::Add {
+            dst: Reg(0),
+            a: Reg(0),
+            b: Reg(0),
+        };
+        let first = vec![int(0), add.clone(), add.clone(), ret.clone()];
+        let second = vec![int(1), ret.clone()];
+        let old = code(["a", "b"], [first, second.clone()]);
+        // Renamed and swapped, with a small change in the first function
+        let new = code(
+            ["c", "d"],
+            [second, vec![int(0), add.clone(), add.clone(), add, ret]],
+        );
+        let matches = match_functions(&old, &new);
+        assert_eq!(matches.len(), 2);
+        assert_eq!((matches[0].0, matches[0].1), (RefFun(0), RefFun(1)));
+        assert!(matches[0].2 < 1.0);
+        assert_eq!(matches[1], (RefFun(1), RefFun(0), 1.0));
+
+        let f = &old.functions[0];
+        let print = Fingerprint::new(&old, f);
+        assert_eq!(print.similarity(&print), 1.0);
+    }
+
+    #[test]
+    fn constants() {
+        let bytes = |ptr| Opcode::Bytes {
+            dst: Reg(0),
+            ptr: RefBytes(ptr),
+        };
+        let ret = Opcode::Ret { ret: Reg(0) };
+        let mut b = Builder::new();
+        let ty = b.ty(Type::Bytes);
+        b.bytes(b"old");
+        b.bytes(b"same");
+        b.function("a", &[], ty, &[ty], vec![bytes(1), ret.clone()]);
+        b.function("b", &[], ty, &[ty], vec![bytes(0), ret.clone()]);
+        // Out of the pools, like in a malformed file
+        b.function(
+            "c",
+            &[],
+            ty,
+            &[ty],
+            vec![
+                bytes(5),
+                Opcode::Int {
+                    dst: Reg(0),
+                    ptr: RefInt(9),
+                },
+                ret,
+            ],
+        );
+        let code = b.build().unwrap();
+        let [a, b, c] = [0, 1, 2].map(|i| Fingerprint::new(&code, &code.functions[i]));
+        // Same content at another index
+        let mut other = Builder::new();
+        let ty = other.ty(Type::Bytes);
+        other.bytes(b"same");
+        other.function(
+            "d",
+            &[],
+            ty,
+            &[ty],
+            vec![bytes(0), Opcode::Ret { ret: Reg(0) }],
+        );
+        let other = other.build().unwrap();
+        let d = Fingerprint::new(&other, &other.functions[0]);
+        assert_eq!(a.similarity(&d), 1.0);
+        assert!(b.similarity(&d) < 1.0);
+        assert!(c.constants.is_empty());
+    }
+}
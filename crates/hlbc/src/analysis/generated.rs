@@ -0,0 +1,149 @@
+//! Heuristics to find functions generated by macros or build tools.
+//!
+//! Serializers, network synchronization and resource accessors make up a large part of some games while being
+//! of little interest when looking for the game logic.
+
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::mem::discriminant;
+
+use crate::opcodes::Opcode;
+use crate::types::Function;
+use crate::Bytecode;
+
+/// A switch with more cases than this is considered to be a generated dispatch table
+const HUGE_SWITCH_CASES: usize = 64;
+/// Minimum number of instructions for a function to be considered repetitive
+const REGULAR_MIN_OPS: usize = 200;
+/// Maximum number of distinct instructions for a long function to be considered repetitive
+const REGULAR_MAX_DISTINCT: usize = 5;
+
+/// Methods generated by hxbit for serializable and networked classes
+const GENERATED_METHODS: &[&str] = &[
+    "hxSerialize",
+    "hxUnserialize",
+    "unserializeInit",
+    "getCLID",
+    "getSerializeSchema",
+    "networkFlush",
+    "networkSetBit",
+    "networkAllow",
+    "networkGetName",
+    "networkRPC",
+    "networkSync",
+];
+
+/// Packages only containing generated classes
+const GENERATED_PACKAGES: &[&str] = &["hxbit.", "hxd._res."];
+
+/// Why a function is thought to be generated
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GeneratedHint {
+    /// The function or its class has a name known to be used by a code generator
+    Naming,
+    /// The function contains a switch with this many cases
+    HugeSwitch(usize),
+    /// The function is long but made of very few distinct instructions
+    Regularity,
+}
+
+impl Display for GeneratedHint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GeneratedHint::Naming => f.write_str("generated name"),
+            GeneratedHint::HugeSwitch(cases) => write!(f, "switch with {cases} cases"),
+            GeneratedHint::Regularity => f.write_str("repetitive code"),
+        }
+    }
+}
+
+/// Check a function name and the name of its class against known generators
+pub fn is_generated_name(class: Option<&str>, name: &str) -> bool {
+    GENERATED_METHODS.contains(&name)
+        || class.is_some_and(|class| GENERATED_PACKAGES.iter().any(|p| class.starts_with(p)))
+}
+
+/// Look for generated code patterns in a list of instructions
+pub fn ops_hint(ops: &[Opcode]) -> Option<GeneratedHint> {
+    let max_cases = ops
+        .iter()
+        .filter_map(|o| match o {
+            Opcode::Switch { offsets, .. } => Some(offsets.len()),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+    if max_cases > HUGE_SWITCH_CASES {
+        return Some(GeneratedHint::HugeSwitch(max_cases));
+    }
+
+    if ops.len() >= REGULAR_MIN_OPS {
+        let distinct: HashSet<_> = ops.iter().map(discriminant).collect();
+        if distinct.len() <= REGULAR_MAX_DISTINCT {
+            return Some(GeneratedHint::Regularity);
+        }
+    }
+    None
+}
+
+impl Function {
+    /// Heuristically detect if this function has been generated by a macro or a build tool
+    /// instead of being written by hand.
+    pub fn generated_hint(&self, code: &Bytecode) -> Option<GeneratedHint> {
+        let class = self
+            .parent
+            .and_then(|p| p.as_obj(code))
+            .map(|obj| obj.name(code));
+        if is_generated_name(class.as_deref(), &self.name(code)) {
+            Some(GeneratedHint::Naming)
+        } else {
+            ops_hint(&self.ops)
+        }
+    }
+}
+
+impl Bytecode {
+    /// All the functions that are likely to be generated, see [Function::generated_hint].
+    /// Functions are listed in the order of the bytecode.
+    pub fn generated_functions(&self) -> impl Iterator<Item = (&Function, GeneratedHint)> {
+        self.functions
+            .iter()
+            .filter_map(|f| f.generated_hint(self).map(|hint| (f, hint)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::generated::{is_generated_name, ops_hint, GeneratedHint};
+    use crate::opcodes::Opcode;
+    use crate::types::Reg;
+
+    #[test]
+    fn naming() {
+        assert!(is_generated_name(Some("game.Player"), "hxSerialize"));
+        assert!(is_generated_name(Some("hxd._res.R_atlas"), "get"));
+        assert!(!is_generated_name(Some("game.Player"), "update"));
+        assert!(!is_generated_name(None, "main"));
+    }
+
+    #[test]
+    fn ops() {
+        let switch = vec![Opcode::Switch {
+            reg: Reg(0),
+            offsets: vec![0; 100],
+            end: 0,
+        }];
+        assert_eq!(ops_hint(&switch), Some(GeneratedHint::HugeSwitch(100)));
+
+        let repetitive = vec![
+            Opcode::Mov {
+                dst: Reg(0),
+                src: Reg(1),
+            };
+            300
+        ];
+        assert_eq!(ops_hint(&repetitive), Some(GeneratedHint::Regularity));
+
+        assert_eq!(ops_hint(&[Opcode::Ret { ret: Reg(0) }]), None);
+    }
+}
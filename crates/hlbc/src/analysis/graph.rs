@@ -1,15 +1,17 @@
 //! Utilities to generate a callgraph and generate dot graphs
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::fmt::{Display, Formatter};
 
 pub use petgraph;
 use petgraph::graphmap::DiGraphMap;
-use petgraph::visit::{EdgeRef, IntoEdgeReferences, IntoNodeReferences, NodeIndexable, NodeRef};
+use petgraph::visit::{EdgeRef, IntoEdgeReferences, IntoNodeReferences};
 
+use crate::analysis::escape;
+use crate::fmt::EnhancedFmt;
 use crate::types::{FunPtr, Function, RefFun};
-use crate::{Bytecode, Opcode, Resolve, Type};
+use crate::{Bytecode, Opcode, Resolve, Str, Type};
 
 pub enum Call {
     // Called with Call0, Call1, ...
@@ -128,11 +130,48 @@ static TYPE: [&str; 2] = ["graph", "digraph"];
 static EDGE: [&str; 2] = ["--", "->"];
 static INDENT: &str = "    ";
 
+impl Call {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Call::Direct => "direct",
+            Call::Closure => "closure",
+        }
+    }
+}
+
+/// Where a function is declared, used to group the nodes of a graph
+fn owner(code: &Bytecode, f: RefFun) -> Option<Str> {
+    match code.get(f) {
+        FunPtr::Fun(fun) => fun.parent.map(|p| match p.as_obj(code) {
+            Some(obj) => obj.name(code),
+            None => Str::from(p.display::<EnhancedFmt>(code).to_string()),
+        }),
+        FunPtr::Native(n) => Some(n.lib(code)),
+    }
+}
+
 pub struct GraphDisplay<'a> {
     g: &'a Callgraph,
     code: &'a Bytecode,
 }
 
+impl GraphDisplay<'_> {
+    fn node(&self, f: &mut Formatter<'_>, indent: &str, node: RefFun) -> fmt::Result {
+        let (color, fill) = if self.code.get(node).is_fun() {
+            ("#b20400", "#edd6d5")
+        } else {
+            ("#1c2b7f", "#d7dbef")
+        };
+        writeln!(
+            f,
+            "{indent}{} [ label = \"{}@{}\" fontsize=18 shape=box color=\"{color}\" fillcolor=\"{fill}\" ]",
+            node.0,
+            escape(&node.name(self.code)),
+            node.0,
+        )
+    }
+}
+
 impl Display for GraphDisplay<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         writeln!(f, "{} {{", TYPE[self.g.is_directed() as usize])?;
@@ -149,25 +188,32 @@ impl Display for GraphDisplay<'_> {
             INDENT
         )?;
 
-        // output all labels
-        for node in self.g.node_references() {
-            writeln!(
-                f,
-                "{}{} [ label = \"{:?}\" fontsize=18 shape=box color=\"#b20400\" fillcolor=\"#edd6d5\" ]",
-                INDENT,
-                self.g.to_index(node.id()),
-                self.code.get(*node.weight())
-            )?;
+        // Group functions by class (or by lib for natives), sorted by name for a stable output
+        let mut clusters: BTreeMap<Str, Vec<RefFun>> = BTreeMap::new();
+        for (node, _) in self.g.node_references() {
+            match owner(self.code, node) {
+                Some(owner) => clusters.entry(owner).or_default().push(node),
+                None => self.node(f, INDENT, node)?,
+            }
         }
+        for (i, (owner, nodes)) in clusters.iter().enumerate() {
+            writeln!(f, "{INDENT}subgraph cluster_{i} {{")?;
+            writeln!(f, "{INDENT}{INDENT}label = \"{}\"", escape(owner))?;
+            for &node in nodes {
+                self.node(f, &INDENT.repeat(2), node)?;
+            }
+            writeln!(f, "{INDENT}}}")?;
+        }
+
         // output all edges
         for edge in self.g.edge_references() {
             writeln!(
                 f,
                 "{}{} {} {} [ label = \"{}\" ]",
                 INDENT,
-                self.g.to_index(edge.source()),
+                edge.source().0,
                 EDGE[self.g.is_directed() as usize],
-                self.g.to_index(edge.target()),
+                edge.target().0,
                 match edge.weight() {
                     Call::Direct => "",
                     Call::Closure => "closure",
@@ -180,7 +226,97 @@ impl Display for GraphDisplay<'_> {
     }
 }
 
-/// Generate dot language
+/// Generate dot language, functions are grouped in a cluster per class
 pub fn display_graph<'a>(g: &'a Callgraph, code: &'a Bytecode) -> GraphDisplay<'a> {
     GraphDisplay { g, code }
 }
+
+pub struct GraphJson<'a> {
+    g: &'a Callgraph,
+    code: &'a Bytecode,
+}
+
+impl Display for GraphJson<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{{\n{INDENT}\"nodes\": [")?;
+        for (i, (node, _)) in self.g.node_references().enumerate() {
+            if i > 0 {
+                writeln!(f, ",")?;
+            }
+            write!(
+                f,
+                "{INDENT}{INDENT}{{ \"findex\": {}, \"name\": \"{}\", \"native\": {}, \"owner\": ",
+                node.0,
+                escape(&node.name(self.code)),
+                !self.code.get(node).is_fun()
+            )?;
+            match owner(self.code, node) {
+                Some(owner) => write!(f, "\"{}\" }}", escape(&owner))?,
+                None => write!(f, "null }}")?,
+            }
+        }
+        writeln!(f, "\n{INDENT}],\n{INDENT}\"edges\": [")?;
+        for (i, edge) in self.g.edge_references().enumerate() {
+            if i > 0 {
+                writeln!(f, ",")?;
+            }
+            write!(
+                f,
+                "{INDENT}{INDENT}{{ \"from\": {}, \"to\": {}, \"call\": \"{}\" }}",
+                edge.source().0,
+                edge.target().0,
+                edge.weight().name()
+            )?;
+        }
+        writeln!(f, "\n{INDENT}]\n}}")
+    }
+}
+
+/// Generate a json document with the list of nodes and the list of edges.
+///
+/// ```json
+/// {
+///     "nodes": [
+///         { "findex": 1, "name": "main", "native": false, "owner": "Main" }
+///     ],
+///     "edges": [
+///         { "from": 1, "to": 2, "call": "direct" }
+///     ]
+/// }
+/// ```
+pub fn display_graph_json<'a>(g: &'a Callgraph, code: &'a Bytecode) -> GraphJson<'a> {
+    GraphJson { g, code }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::graph::{display_graph, display_graph_json, Call, Callgraph};
+    use crate::builder::Builder;
+    use crate::types::{RefFun, Type};
+
+    #[test]
+    fn export() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        b.function("main", &[], void, &[], vec![]);
+        b.native("std", "log\"s", &[], void);
+        let code = b.build().unwrap();
+        let mut g = Callgraph::new();
+        g.add_node(RefFun(0));
+        g.add_node(RefFun(1));
+        g.add_edge(RefFun(0), RefFun(1), Call::Direct);
+
+        let dot = display_graph(&g, &code).to_string();
+        assert!(dot.contains("subgraph cluster_0 {"));
+        assert!(dot.contains("label = \"std\""));
+        assert!(dot.contains("label = \"log\\\"s@1\""));
+        assert!(dot.contains("0 -> 1"));
+
+        let json = display_graph_json(&g, &code).to_string();
+        assert!(
+            json.contains(r#"{ "findex": 1, "name": "log\"s", "native": true, "owner": "std" }"#)
+        );
+        assert!(json.contains(r#"{ "findex": 0, "name": "main", "native": false, "owner": null }"#));
+        assert!(json.contains(r#"{ "from": 0, "to": 1, "call": "direct" }"#));
+    }
+}
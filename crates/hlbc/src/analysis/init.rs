@@ -0,0 +1,248 @@
+//! Code run on startup before `main` : the entrypoint and the static initializers of the classes.
+//!
+//! The entrypoint registers the types and runs the static initialization of every class, the initial values of
+//! their static variables and the body of their `__init__`. Static variables are fields of the class object held by a
+//! global, e.g. `static var count = 5` is a field of the global holding `$Counter`.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::analysis::cfg::cfg;
+use crate::opcodes::Opcode;
+use crate::types::{Function, RefField, RefFloat, RefFun, RefGlobal, RefInt, RefString};
+use crate::Bytecode;
+
+/// A constant value loaded by an opcode
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum InitValue {
+    Null,
+    Bool(bool),
+    Int(RefInt),
+    Float(RefFloat),
+    String(RefString),
+}
+
+/// Assignment of a static variable in an initializer
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct StaticInit {
+    /// Global holding the class object
+    pub global: RefGlobal,
+    pub field: RefField,
+    /// The value assigned, if it is a constant
+    pub value: Option<InitValue>,
+}
+
+/// What a function run on startup initializes
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Initializer {
+    pub fun: RefFun,
+    /// Globals assigned, in order
+    pub globals: Vec<RefGlobal>,
+    /// Static variables assigned, in order
+    pub static_fields: Vec<StaticInit>,
+}
+
+impl Initializer {
+    /// Find the assignments of globals and static variables of a function.
+    ///
+    /// Values are only followed inside a basic block : a constant loaded before a branch isn't reported.
+    pub fn new(f: &Function) -> Self {
+        let starts: HashSet<usize> = cfg(f).blocks.iter().map(|b| b.ops.start).collect();
+        let mut globals = Vec::new();
+        let mut static_fields = Vec::new();
+        // Registers holding a global and registers holding a constant
+        let mut loaded = HashMap::new();
+        let mut constants = HashMap::new();
+        for (i, op) in f.ops.iter().enumerate() {
+            if starts.contains(&i) {
+                loaded.clear();
+                constants.clear();
+            }
+            match *op {
+                Opcode::SetGlobal { global, .. } => globals.push(global),
+                Opcode::SetField { obj, field, src } => {
+                    if let Some(&global) = loaded.get(&obj) {
+                        static_fields.push(StaticInit {
+                            global,
+                            field,
+                            value: constants.get(&src).copied(),
+                        });
+                    }
+                }
+                _ => {}
+            }
+            let Some(dst) = op.written_reg() else {
+                continue;
+            };
+            let global = match *op {
+                Opcode::GetGlobal { global, .. } => Some(global),
+                Opcode::Mov { src, .. } => loaded.get(&src).copied(),
+                _ => None,
+            };
+            let constant = match *op {
+                Opcode::Null { .. } => Some(InitValue::Null),
+                Opcode::Bool { value, .. } => Some(InitValue::Bool(value)),
+                Opcode::Int { ptr, .. } => Some(InitValue::Int(ptr)),
+                Opcode::Float { ptr, .. } => Some(InitValue::Float(ptr)),
+                Opcode::String { ptr, .. } => Some(InitValue::String(ptr)),
+                Opcode::Mov { src, .. } => constants.get(&src).copied(),
+                _ => None,
+            };
+            match global {
+                Some(global) => loaded.insert(dst, global),
+                None => loaded.remove(&dst),
+            };
+            match constant {
+                Some(constant) => constants.insert(dst, constant),
+                None => constants.remove(&dst),
+            };
+        }
+        Self {
+            fun: f.findex,
+            globals,
+            static_fields,
+        }
+    }
+}
+
+impl Bytecode {
+    /// The functions run on startup to initialize the program : the entrypoint, then the `__init__` functions of
+    /// the classes.
+    pub fn initializer_functions(&self) -> Vec<RefFun> {
+        let mut funs = vec![self.entrypoint];
+        funs.extend(
+            self.functions
+                .iter()
+                .filter(|f| f.parent.is_some() && f.findex != self.entrypoint)
+                .filter(|f| f.name(self) == "__init__")
+                .map(|f| f.findex),
+        );
+        funs
+    }
+
+    /// What each of the [initializer functions](Bytecode::initializer_functions) initializes
+    pub fn initializers(&self) -> Vec<Initializer> {
+        self.initializer_functions()
+            .into_iter()
+            .filter_map(|fun| fun.try_as_fn(self).ok())
+            .map(Initializer::new)
+            .collect()
+    }
+
+    /// The constant initial values of the static variables of the class held by `global`.
+    ///
+    /// Only the first assignment of a variable counts, a variable first assigned something else than a constant
+    /// has no initial value.
+    pub fn static_initial_values(&self, global: RefGlobal) -> HashMap<RefField, InitValue> {
+        self.all_static_initial_values()
+            .remove(&global)
+            .unwrap_or_default()
+    }
+
+    /// The constant initial values of the static variables of every class, by the global holding the class.
+    /// Same as [Bytecode::static_initial_values] but the initializers are analyzed only once for all the classes.
+    pub fn all_static_initial_values(&self) -> HashMap<RefGlobal, HashMap<RefField, InitValue>> {
+        let mut values = HashMap::<RefGlobal, HashMap<_, _>>::new();
+        for init in self.initializers() {
+            for s in init.static_fields {
+                values
+                    .entry(s.global)
+                    .or_default()
+                    .entry(s.field)
+                    .or_insert(s.value);
+            }
+        }
+        values
+            .into_iter()
+            .map(|(global, fields)| {
+                let fields = fields
+                    .into_iter()
+                    .filter_map(|(field, value)| Some((field, value?)))
+                    .collect();
+                (global, fields)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::init::{InitValue, Initializer, StaticInit};
+    use crate::builder::Builder;
+    use crate::opcodes::Opcode;
+    use crate::types::{RefField, RefFun, RefGlobal, Reg, Type};
+
+    #[test]
+    fn static_values() {
+        let mut b = Builder::new();
+        let dyn_ = b.ty(Type::Dyn);
+        let (five, six) = (b.int(5), b.int(6));
+        let hello = b.string("hello");
+        // $Main.x = 5; $Main.y = new Main(); $Main.x = 6; global 0 = "hello"
+        let ops = vec![
+            Opcode::GetGlobal {
+                dst: Reg(0),
+                global: RefGlobal(1),
+            },
+            Opcode::Int {
+                dst: Reg(1),
+                ptr: five,
+            },
+            Opcode::SetField {
+                obj: Reg(0),
+                field: RefField(0),
+                src: Reg(1),
+            },
+            Opcode::New { dst: Reg(1) },
+            Opcode::SetField {
+                obj: Reg(0),
+                field: RefField(1),
+                src: Reg(1),
+            },
+            Opcode::Int {
+                dst: Reg(1),
+                ptr: six,
+            },
+            Opcode::SetField {
+                obj: Reg(0),
+                field: RefField(0),
+                src: Reg(1),
+            },
+            Opcode::String {
+                dst: Reg(1),
+                ptr: hello,
+            },
+            Opcode::SetGlobal {
+                global: RefGlobal(0),
+                src: Reg(1),
+            },
+            Opcode::Ret { ret: Reg(1) },
+        ];
+        b.function("", &[], dyn_, &[dyn_; 2], ops);
+        let code = b.build().unwrap();
+        assert_eq!(code.initializer_functions(), [RefFun(0)]);
+        let init = Initializer::new(&code.functions[0]);
+        assert_eq!(init.globals, [RefGlobal(0)]);
+        assert_eq!(
+            init.static_fields[..2],
+            [
+                StaticInit {
+                    global: RefGlobal(1),
+                    field: RefField(0),
+                    value: Some(InitValue::Int(five)),
+                },
+                StaticInit {
+                    global: RefGlobal(1),
+                    field: RefField(1),
+                    value: None,
+                }
+            ]
+        );
+        let values = code.static_initial_values(RefGlobal(1));
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[&RefField(0)], InitValue::Int(five));
+        assert!(code.static_initial_values(RefGlobal(0)).is_empty());
+        let all = code.all_static_initial_values();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[&RefGlobal(1)], values);
+    }
+}
@@ -0,0 +1,235 @@
+//! Register liveness, to know if the value stored in a register is used.
+//!
+//! Every path of the control flow graph is considered, even the ones that can't happen at runtime.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::analysis::cfg::{cfg, Cfg};
+use crate::types::{Function, Reg};
+
+/// Registers read and written by the instructions of a function, and the registers live around each instruction.
+/// A register is live when its value might be read before being overwritten.
+#[derive(Debug, Clone, Default)]
+pub struct Liveness {
+    /// Registers live before each instruction
+    pub live_in: Vec<HashSet<Reg>>,
+    /// Registers live after each instruction
+    pub live_out: Vec<HashSet<Reg>>,
+    /// Instructions writing each register, in order
+    pub defs: HashMap<Reg, Vec<usize>>,
+    /// Instructions reading each register, in order
+    pub uses: HashMap<Reg, Vec<usize>>,
+}
+
+impl Liveness {
+    /// Whether the value of `reg` might be read after the instruction at `pos`
+    pub fn is_live_after(&self, pos: usize, reg: Reg) -> bool {
+        self.live_out
+            .get(pos)
+            .is_some_and(|live| live.contains(&reg))
+    }
+}
+
+/// Compute the live registers of every instruction of a function
+pub fn liveness(f: &Function) -> Liveness {
+    let cfg = cfg(f);
+    let mut defs: HashMap<Reg, Vec<usize>> = HashMap::new();
+    let mut uses: HashMap<Reg, Vec<usize>> = HashMap::new();
+    for (i, op) in f.ops.iter().enumerate() {
+        for r in op.read_regs() {
+            uses.entry(r).or_default().push(i);
+        }
+        if let Some(r) = op.written_reg() {
+            defs.entry(r).or_default().push(i);
+        }
+    }
+
+    // Registers read before being written in each block, and registers written in each block
+    let (gen, kill): (Vec<_>, Vec<_>) = cfg
+        .blocks
+        .iter()
+        .map(|b| {
+            let mut gen = HashSet::new();
+            let mut kill = HashSet::new();
+            for op in f.ops[b.ops.clone()].iter().rev() {
+                if let Some(r) = op.written_reg() {
+                    gen.remove(&r);
+                    kill.insert(r);
+                }
+                gen.extend(op.read_regs());
+            }
+            (gen, kill)
+        })
+        .unzip();
+
+    // Backward dataflow on the blocks until nothing changes
+    let mut block_in: Vec<HashSet<Reg>> = gen.clone();
+    let mut block_out = vec![HashSet::new(); cfg.blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in (0..cfg.blocks.len()).rev() {
+            let out: HashSet<Reg> = cfg
+                .successors(b)
+                .flat_map(|e| block_in[e.to].iter().copied())
+                .collect();
+            let live: HashSet<Reg> = out.difference(&kill[b]).chain(&gen[b]).copied().collect();
+            if live != block_in[b] {
+                block_in[b] = live;
+                changed = true;
+            }
+            block_out[b] = out;
+        }
+    }
+
+    let mut live_in = vec![HashSet::new(); f.ops.len()];
+    let mut live_out = vec![HashSet::new(); f.ops.len()];
+    for (b, out) in cfg.blocks.iter().zip(block_out) {
+        let mut live = out;
+        for i in b.ops.clone().rev() {
+            live_out[i] = live.clone();
+            let op = &f.ops[i];
+            if let Some(r) = op.written_reg() {
+                live.remove(&r);
+            }
+            live.extend(op.read_regs());
+            live_in[i] = live.clone();
+        }
+    }
+
+    Liveness {
+        live_in,
+        live_out,
+        defs,
+        uses,
+    }
+}
+
+/// Whether the value written to `reg` by the instruction at `pos` might be read later, before being overwritten.
+pub fn is_read_after(f: &Function, cfg: &Cfg, pos: usize, reg: Reg) -> bool {
+    find_reads(f, cfg, pos, reg, |_| true)
+}
+
+/// Instructions that might read the value written to `reg` by the instruction at `pos`, sorted by position.
+pub fn reads_of(f: &Function, cfg: &Cfg, pos: usize, reg: Reg) -> Vec<usize> {
+    let mut reads = Vec::new();
+    find_reads(f, cfg, pos, reg, |i| {
+        reads.push(i);
+        false
+    });
+    reads.sort_unstable();
+    reads.dedup();
+    reads
+}
+
+/// Call `found` with each instruction reading the value written to `reg` at `pos`, stops as soon as it returns true.
+fn find_reads(
+    f: &Function,
+    cfg: &Cfg,
+    pos: usize,
+    reg: Reg,
+    mut found: impl FnMut(usize) -> bool,
+) -> bool {
+    let Some(first) = cfg.block_of(pos) else {
+        return false;
+    };
+    // Blocks to scan from the given instruction
+    let mut todo = vec![(first, pos + 1)];
+    let mut visited = HashSet::new();
+    while let Some((block, start)) = todo.pop() {
+        let mut overwritten = false;
+        for (i, op) in f.ops[start..cfg.blocks[block].ops.end]
+            .iter()
+            .enumerate()
+            .map(|(i, op)| (start + i, op))
+        {
+            if op.read_regs().contains(&reg) && found(i) {
+                return true;
+            }
+            if op.written_reg() == Some(reg) {
+                overwritten = true;
+                break;
+            }
+        }
+        if !overwritten {
+            for e in cfg.successors(block) {
+                if visited.insert(e.to) {
+                    todo.push((e.to, cfg.blocks[e.to].ops.start));
+                }
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::cfg::cfg;
+    use crate::analysis::liveness::{is_read_after, liveness, reads_of};
+    use crate::opcodes::Opcode;
+    use crate::types::{Function, RefFun, RefString, RefType, Reg};
+
+    /// r1 = r0; if r0 { r1 = r2 } else { r2 = r1 }; return r2
+    fn branches() -> Function {
+        Function {
+            t: RefType(0),
+            findex: RefFun(0),
+            regs: vec![],
+            ops: vec![
+                Opcode::Mov {
+                    dst: Reg(1),
+                    src: Reg(0),
+                },
+                Opcode::JFalse {
+                    cond: Reg(0),
+                    offset: 2,
+                },
+                Opcode::Mov {
+                    dst: Reg(1),
+                    src: Reg(2),
+                },
+                Opcode::JAlways { offset: 1 },
+                Opcode::Mov {
+                    dst: Reg(2),
+                    src: Reg(1),
+                },
+                Opcode::Ret { ret: Reg(2) },
+            ],
+            debug_info: None,
+            assigns: None,
+            name: RefString(0),
+            parent: None,
+        }
+    }
+
+    #[test]
+    fn reads() {
+        let f = branches();
+        let cfg = cfg(&f);
+        // Read in the else branch only
+        assert!(is_read_after(&f, &cfg, 0, Reg(1)));
+        assert_eq!(reads_of(&f, &cfg, 0, Reg(1)), [4]);
+        // Not read after the then branch
+        assert!(!is_read_after(&f, &cfg, 2, Reg(1)));
+        assert!(reads_of(&f, &cfg, 2, Reg(1)).is_empty());
+        assert!(is_read_after(&f, &cfg, 4, Reg(2)));
+        assert!(!is_read_after(&f, &cfg, 5, Reg(2)));
+    }
+
+    #[test]
+    fn live_registers() {
+        let f = branches();
+        let live = liveness(&f);
+        let set = |regs: &[u32]| regs.iter().copied().map(Reg).collect();
+        assert_eq!(live.live_in[0], set(&[0, 2]));
+        assert_eq!(live.live_out[0], set(&[0, 1, 2]));
+        assert_eq!(live.live_out[2], set(&[2]));
+        assert_eq!(live.live_in[4], set(&[1]));
+        assert!(live.live_out[5].is_empty());
+        assert!(live.is_live_after(0, Reg(1)));
+        assert!(!live.is_live_after(2, Reg(1)));
+        assert_eq!(live.defs[&Reg(1)], [0, 2]);
+        assert_eq!(live.uses[&Reg(1)], [4]);
+        assert_eq!(live.uses[&Reg(2)], [2, 5]);
+    }
+}
@@ -0,0 +1,275 @@
+//! Heuristics to find where to start reading an unknown program : its main update loop and its big state machines.
+//!
+//! Games usually hand an update function to the engine, which calls it every frame. Most of the game logic is
+//! reachable from there, often through switches on the state of an object.
+
+use std::cmp::Reverse;
+use std::collections::{HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+
+use crate::analysis::cfg::{cfg, Cfg, EdgeKind};
+use crate::opcodes::Opcode;
+use crate::types::{Function, RefFun, Reg};
+use crate::Bytecode;
+
+/// Minimum number of cases for a switch to be considered a state machine
+const STATE_MACHINE_CASES: usize = 4;
+
+/// Method names commonly given to the function called every frame
+const UPDATE_NAMES: &[&str] = &[
+    "update",
+    "fixedupdate",
+    "mainloop",
+    "loop",
+    "tick",
+    "step",
+    "frame",
+    "onframe",
+    "onenterframe",
+];
+
+/// Why a function is thought to be an update loop
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LoopHint {
+    /// The function has a name commonly used for update functions
+    Naming,
+    /// The function contains a loop without any exit
+    EndlessLoop,
+}
+
+impl Display for LoopHint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoopHint::Naming => f.write_str("update name"),
+            LoopHint::EndlessLoop => f.write_str("endless loop"),
+        }
+    }
+}
+
+/// A switch on a field, executed repeatedly
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StateMachine {
+    pub fun: RefFun,
+    /// Index of the switch instruction
+    pub pos: usize,
+    pub cases: usize,
+}
+
+/// Starting points to explore a program, see [Bytecode::main_loops]
+#[derive(Debug, Clone, Default)]
+pub struct MainLoopReport {
+    /// Number of functions reachable from the entrypoint
+    pub reachable: usize,
+    /// Functions looking like an update loop, the closest to the entrypoint first
+    pub update_loops: Vec<(RefFun, LoopHint)>,
+    /// State machines run by a loop, the biggest first
+    pub state_machines: Vec<StateMachine>,
+}
+
+/// Functions reachable from `roots` through calls and closures, the closest first.
+/// Methods of a class become reachable when the class is instantiated.
+fn reachable(code: &Bytecode, roots: impl IntoIterator<Item = RefFun>) -> Vec<RefFun> {
+    let mut seen = HashSet::new();
+    let mut instantiated = HashSet::new();
+    let mut queue: VecDeque<_> = roots.into_iter().collect();
+    let mut order = Vec::new();
+    while let Some(fun) = queue.pop_front() {
+        if !seen.insert(fun) {
+            continue;
+        }
+        order.push(fun);
+        let Some(f) = fun.as_fn(code) else {
+            continue;
+        };
+        queue.extend(f.find_fun_refs().map(|(_, _, r)| r));
+        for op in &f.ops {
+            let Opcode::New { dst } = *op else {
+                continue;
+            };
+            let mut ty = Some(f[dst]);
+            while let Some(obj) = ty
+                .filter(|&t| instantiated.insert(t))
+                .and_then(|t| t.as_obj(code))
+            {
+                queue.extend(obj.protos.iter().map(|p| p.findex));
+                queue.extend(obj.bindings.values().copied());
+                ty = obj.super_;
+            }
+        }
+    }
+    order
+}
+
+/// Blocks of each loop of a function, as a range of block indices from the target of a jump back to its source
+fn loops(cfg: &Cfg) -> impl Iterator<Item = (usize, usize)> + '_ {
+    cfg.edges
+        .iter()
+        .filter(|e| e.to <= e.from)
+        .map(|e| (e.to, e.from))
+}
+
+/// A loop that can't be left, except by an exception
+fn has_endless_loop(f: &Function, cfg: &Cfg) -> bool {
+    loops(cfg).any(|(start, end)| {
+        let inside = start..=end;
+        inside.clone().all(|b| {
+            let mut successors = cfg.successors(b).peekable();
+            let returns = successors.peek().is_none()
+                && matches!(
+                    f.ops[cfg.blocks[b].last()],
+                    Opcode::Ret { .. } | Opcode::Throw { .. } | Opcode::Rethrow { .. }
+                );
+            !returns && successors.all(|e| e.kind == EdgeKind::Catch || inside.contains(&e.to))
+        })
+    })
+}
+
+/// The instruction last writing a register before `pos`, in the same block
+fn definition<'a>(f: &'a Function, cfg: &Cfg, pos: usize, reg: Reg) -> Option<&'a Opcode> {
+    let block = &cfg.blocks[cfg.block_of(pos)?];
+    f.ops[block.ops.start..pos]
+        .iter()
+        .rev()
+        .find(|op| op.written_reg() == Some(reg))
+}
+
+/// Big switches on a field or on the index of an enum
+fn state_switches<'a>(f: &'a Function, cfg: &'a Cfg) -> impl Iterator<Item = (usize, usize)> + 'a {
+    f.ops
+        .iter()
+        .enumerate()
+        .filter_map(move |(pos, op)| match op {
+            Opcode::Switch { reg, offsets, .. } if offsets.len() >= STATE_MACHINE_CASES => {
+                matches!(
+                    definition(f, cfg, pos, *reg),
+                    Some(Opcode::Field { .. } | Opcode::GetThis { .. } | Opcode::EnumIndex { .. })
+                )
+                .then_some((pos, offsets.len()))
+            }
+            _ => None,
+        })
+}
+
+impl Bytecode {
+    /// Find the update loops and the state machines of the program, starting points to explore it.
+    ///
+    /// Update loops are functions reachable from the entrypoint with a name like `update` or with a loop that never
+    /// exits. State machines are switches with a lot of cases on a field, inside a loop or in a function called by an
+    /// update loop. Functions of the standard library are ignored.
+    pub fn main_loops(&self) -> MainLoopReport {
+        let reached = reachable(self, [self.entrypoint]);
+        let mut update_loops = Vec::new();
+        let mut cfgs = Vec::new();
+        for &fun in &reached {
+            let Some(f) = fun
+                .as_fn(self)
+                .filter(|f| !f.ops.is_empty() && !f.is_from_std(self))
+            else {
+                continue;
+            };
+            let cfg = cfg(f);
+            let name = f.name(self).to_lowercase();
+            if has_endless_loop(f, &cfg) {
+                update_loops.push((fun, LoopHint::EndlessLoop));
+            } else if UPDATE_NAMES.contains(&name.as_str()) {
+                update_loops.push((fun, LoopHint::Naming));
+            }
+            cfgs.push((f, cfg));
+        }
+
+        let updated: HashSet<_> = reachable(self, update_loops.iter().map(|&(f, _)| f))
+            .into_iter()
+            .collect();
+        let mut state_machines: Vec<_> = cfgs
+            .iter()
+            .flat_map(|&(f, ref cfg)| {
+                let updated = updated.contains(&f.findex);
+                state_switches(f, cfg)
+                    .filter(move |&(pos, _)| {
+                        let block = cfg.block_of(pos);
+                        updated
+                            || loops(cfg).any(|(start, end)| {
+                                block.is_some_and(|b| (start..=end).contains(&b))
+                            })
+                    })
+                    .map(move |(pos, cases)| StateMachine {
+                        fun: f.findex,
+                        pos,
+                        cases,
+                    })
+            })
+            .collect();
+        state_machines.sort_by_key(|s| Reverse(s.cases));
+
+        MainLoopReport {
+            reachable: reached.len(),
+            update_loops,
+            state_machines,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::mainloop::{LoopHint, StateMachine};
+    use crate::builder::Builder;
+    use crate::opcodes::Opcode;
+    use crate::types::{RefField, Reg, Type};
+
+    #[test]
+    fn main_loops() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        let int = b.ty(Type::I32);
+        let game = b.class("Game", None, &[("state", int)]);
+        // Instantiates the game, the engine calls its update method
+        b.function(
+            "main",
+            &[],
+            void,
+            &[game, int],
+            vec![Opcode::New { dst: Reg(0) }, Opcode::Ret { ret: Reg(0) }],
+        );
+        let update = b.function(
+            "",
+            &[game],
+            void,
+            &[int],
+            vec![
+                Opcode::Field {
+                    dst: Reg(1),
+                    obj: Reg(0),
+                    field: RefField(0),
+                },
+                Opcode::Switch {
+                    reg: Reg(1),
+                    offsets: vec![0; 5],
+                    end: 0,
+                },
+                Opcode::Ret { ret: Reg(0) },
+            ],
+        );
+        b.method(game, "update", update);
+        // Never reached
+        b.function(
+            "other",
+            &[],
+            void,
+            &[game, int],
+            vec![Opcode::JAlways { offset: -1 }],
+        );
+        let code = b.build().unwrap();
+
+        let report = code.main_loops();
+        assert_eq!(report.reachable, 2);
+        assert_eq!(report.update_loops, [(update, LoopHint::Naming)]);
+        assert_eq!(
+            report.state_machines,
+            [StateMachine {
+                fun: update,
+                pos: 1,
+                cases: 5
+            }]
+        );
+    }
+}
@@ -8,11 +8,44 @@ use crate::{Bytecode, Function, Native, Opcode, RefFun, RefType, Resolve, Type,
 #[cfg(feature = "graph")]
 pub mod graph;
 
+pub mod capabilities;
+pub mod cfg;
+pub mod closures;
+pub mod deadcode;
+pub mod diff;
+pub mod fingerprint;
 pub mod files;
+pub mod generated;
+pub mod init;
+pub mod liveness;
+pub mod mainloop;
+pub mod natives;
+pub mod profile;
+pub mod returns;
+pub mod search;
+pub mod signature;
+pub mod ssa;
+pub mod stats;
+pub mod taint;
 pub mod usage;
 
+/// Escape a string to be used in a double quoted string in dot or json
+pub(crate) fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
 impl Bytecode {
-    /// Iterate on every instruction of every function
+    /// Iterate on every instruction of every function, in bytecode order
     pub fn ops(&self) -> impl Iterator<Item = (&Function, (usize, &Opcode))> {
         self.functions
             .iter()
@@ -61,6 +94,43 @@ impl Function {
         })
     }
 
+    /// The function called by a call instruction of this function with its arguments. `this` is the first argument of
+    /// `CallMethod` but isn't in the arguments of `CallThis`.
+    pub(crate) fn callee(&self, code: &Bytecode, op: &Opcode) -> Option<(RefFun, Vec<Reg>)> {
+        Some(match op {
+            &Opcode::Call0 { fun, .. } => (fun, Vec::new()),
+            &Opcode::Call1 { fun, arg0, .. } => (fun, vec![arg0]),
+            &Opcode::Call2 {
+                fun, arg0, arg1, ..
+            } => (fun, vec![arg0, arg1]),
+            &Opcode::Call3 {
+                fun,
+                arg0,
+                arg1,
+                arg2,
+                ..
+            } => (fun, vec![arg0, arg1, arg2]),
+            &Opcode::Call4 {
+                fun,
+                arg0,
+                arg1,
+                arg2,
+                arg3,
+                ..
+            } => (fun, vec![arg0, arg1, arg2, arg3]),
+            Opcode::CallN { fun, args, .. } => (*fun, args.clone()),
+            Opcode::CallMethod { field, args, .. } => {
+                let obj = self[*args.first()?];
+                (obj.method(field.0, code)?.findex, args.clone())
+            }
+            Opcode::CallThis { field, args, .. } => {
+                let this = *self.regs.first()?;
+                (this.method(field.0, code)?.findex, args.clone())
+            }
+            _ => return None,
+        })
+    }
+
     /// Starting from a position in a function, finds the last time a register has been assigned a closure
     pub fn find_last_closure_assign(
         &self,
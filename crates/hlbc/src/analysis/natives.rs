@@ -0,0 +1,135 @@
+//! Catalog of the native functions imported by a program.
+//!
+//! Natives are only known by their library and their name, like `std/alloc_bytes`. The natives of the HashLink
+//! standard library are bound to well known Haxe functions, this module maps them back to get readable names (e.g.
+//! `Bytes.alloc`). Natives of other libraries (`sdl`, `directx`, a game's own library) are listed as is.
+
+use crate::types::{RefFun, RefType};
+use crate::{Bytecode, Native, Str};
+
+/// A native of the standard library and the Haxe function it implements
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct KnownNative {
+    pub lib: &'static str,
+    pub name: &'static str,
+    /// Name of the Haxe function, without the package
+    pub haxe: &'static str,
+}
+
+const fn known(lib: &'static str, name: &'static str, haxe: &'static str) -> KnownNative {
+    KnownNative { lib, name, haxe }
+}
+
+/// Natives of libhl bound directly to a static Haxe function, with the same arguments.
+///
+/// Natives wrapped by the Haxe function (e.g. `std/value_to_string` behind `Std.string`) or bound to instance methods
+/// (e.g. `std/bytes_blit` for `hl.Bytes.blit`) aren't listed, a call to them wouldn't read the same.
+pub const KNOWN_NATIVES: &[KnownNative] = &[
+    // hl.Bytes
+    known("std", "alloc_bytes", "Bytes.alloc"),
+    // Std
+    known("std", "random", "Std.random"),
+    // Math
+    known("std", "math_abs", "Math.abs"),
+    known("std", "math_sqrt", "Math.sqrt"),
+    known("std", "math_floor", "Math.floor"),
+    known("std", "math_ceil", "Math.ceil"),
+    known("std", "math_round", "Math.round"),
+    known("std", "math_pow", "Math.pow"),
+    known("std", "math_exp", "Math.exp"),
+    known("std", "math_log", "Math.log"),
+    known("std", "math_sin", "Math.sin"),
+    known("std", "math_cos", "Math.cos"),
+    known("std", "math_tan", "Math.tan"),
+    known("std", "math_atan2", "Math.atan2"),
+    // Sys
+    known("std", "sys_time", "Sys.time"),
+    known("std", "sys_cpu_time", "Sys.cpuTime"),
+];
+
+/// The Haxe function implemented by a native of the standard library
+pub fn known_native(lib: &str, name: &str) -> Option<&'static KnownNative> {
+    // Lazily loaded libs are prefixed with '?'
+    let lib = lib.strip_prefix('?').unwrap_or(lib);
+    KNOWN_NATIVES
+        .iter()
+        .find(|k| k.lib == lib && k.name == name)
+}
+
+/// A native function imported by the program
+#[derive(Debug, Clone)]
+pub struct NativeInfo {
+    pub findex: RefFun,
+    pub lib: Str,
+    pub name: Str,
+    /// The lib is loaded on the first call
+    pub lazy: bool,
+    /// Signature, a [TypeFun](crate::types::TypeFun)
+    pub ty: RefType,
+    pub known: Option<&'static KnownNative>,
+}
+
+impl Native {
+    /// The Haxe function this native implements, if it is from the standard library
+    pub fn known(&self, code: &Bytecode) -> Option<&'static KnownNative> {
+        known_native(&self.lib(code), &self.name(code))
+    }
+
+    /// Readable name of the native, the Haxe function for the natives of the standard library or `lib/name`
+    pub fn friendly_name(&self, code: &Bytecode) -> Str {
+        match self.known(code) {
+            Some(k) => Str::from_static(k.haxe),
+            None => Str::from(format!("{}/{}", self.lib(code), self.name(code))),
+        }
+    }
+}
+
+impl Bytecode {
+    /// All the natives imported by the program, ordered by findex
+    pub fn native_catalog(&self) -> Vec<NativeInfo> {
+        let mut natives: Vec<NativeInfo> = self
+            .natives
+            .iter()
+            .map(|n| NativeInfo {
+                findex: n.findex,
+                lib: n.lib(self),
+                name: n.name(self),
+                lazy: n.lib_is_lazy(self),
+                ty: n.t,
+                known: n.known(self),
+            })
+            .collect();
+        natives.sort_unstable_by_key(|n| n.findex);
+        natives
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::natives::known_native;
+    use crate::builder::Builder;
+    use crate::types::Type;
+
+    #[test]
+    fn catalog() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let alloc = b.native("?std", "alloc_bytes", &[int], int);
+        b.native("sdl", "win_create", &[int], int);
+        // The pool isn't in findex order
+        b.code_mut().natives.reverse();
+        let code = b.build().unwrap();
+
+        let natives = code.native_catalog();
+        assert_eq!(natives.len(), 2);
+        assert_eq!(natives[0].findex, alloc);
+        assert!(natives[0].lazy);
+        assert_eq!(natives[0].known.map(|k| k.haxe), Some("Bytes.alloc"));
+        assert_eq!(natives[1].name, "win_create");
+        assert_eq!(natives[1].known, None);
+
+        assert_eq!(code.natives[1].friendly_name(&code), "Bytes.alloc");
+        assert_eq!(code.natives[0].friendly_name(&code), "sdl/win_create");
+        assert!(known_native("sdl", "alloc_bytes").is_none());
+    }
+}
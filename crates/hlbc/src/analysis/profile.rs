@@ -0,0 +1,223 @@
+//! Size and complexity of each function, to find the interesting code of a large program : the game logic is in the
+//! biggest and most branching functions, far from the thousands of tiny accessors and wrappers.
+
+use std::collections::BTreeMap;
+
+use crate::opcodes::Opcode;
+use crate::types::{Function, RefFun};
+use crate::{Bytecode, Str};
+
+/// Size and complexity of a function
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FunctionProfile {
+    pub findex: RefFun,
+    /// Name with its class, like `Player.update`
+    pub name: Str,
+    /// Number of opcodes
+    pub ops: usize,
+    /// Number of registers, arguments included
+    pub regs: usize,
+    /// Number of conditional branches : one per conditional jump and per case of a switch
+    pub branches: usize,
+}
+
+impl FunctionProfile {
+    /// Cyclomatic complexity, the number of independent paths through the function
+    pub fn complexity(&self) -> usize {
+        self.branches + 1
+    }
+}
+
+/// Totals for the functions of a class or a package
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ProfileTotals {
+    pub functions: usize,
+    pub ops: usize,
+    pub regs: usize,
+    pub branches: usize,
+}
+
+impl ProfileTotals {
+    fn add(&mut self, f: &FunctionProfile) {
+        self.functions += 1;
+        self.ops += f.ops;
+        self.regs += f.regs;
+        self.branches += f.branches;
+    }
+}
+
+/// How to rank the functions
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum ProfileKey {
+    #[default]
+    Ops,
+    Regs,
+    Complexity,
+}
+
+impl ProfileKey {
+    fn of(&self, f: &FunctionProfile) -> usize {
+        match self {
+            ProfileKey::Ops => f.ops,
+            ProfileKey::Regs => f.regs,
+            ProfileKey::Complexity => f.complexity(),
+        }
+    }
+}
+
+/// Profile of every function, see [Bytecode::profile]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ProfileReport {
+    /// Functions with code, in bytecode order unless sorted with [ProfileReport::sort]
+    pub functions: Vec<FunctionProfile>,
+    /// Totals by class, static functions count for their class
+    pub classes: BTreeMap<Str, ProfileTotals>,
+    /// Totals by dotted package path, the top level package is empty. Functions outside a class count for the top
+    /// level package.
+    pub packages: BTreeMap<Str, ProfileTotals>,
+}
+
+impl ProfileReport {
+    /// The functions from the biggest to the smallest, functions of the same size are in bytecode order
+    pub fn ranked(&self, key: ProfileKey) -> Vec<&FunctionProfile> {
+        let mut ranked: Vec<_> = self.functions.iter().collect();
+        ranked.sort_by_key(|f| std::cmp::Reverse(key.of(f)));
+        ranked
+    }
+
+    /// Sort the functions like [ProfileReport::ranked], so the json output follows the same order
+    pub fn sort(&mut self, key: ProfileKey) {
+        self.functions.sort_by_key(|f| std::cmp::Reverse(key.of(f)));
+    }
+
+    /// The whole report as json
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> crate::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+impl Bytecode {
+    /// Measure the size and the complexity of every function, with totals by class and package
+    pub fn profile(&self) -> ProfileReport {
+        let mut report = ProfileReport::default();
+        for f in &self.functions {
+            let class = f
+                .parent
+                .and_then(|p| p.as_obj(self))
+                .map(|obj| obj.name(self));
+            // Static functions are bound to the companion type of their class
+            let class = class.map(|name| match name.strip_prefix('$') {
+                Some(name) => Str::from(name),
+                None => name,
+            });
+            let profile = FunctionProfile {
+                findex: f.findex,
+                name: match &class {
+                    Some(class) => Str::from(format!("{class}.{}", f.name(self))),
+                    None => f.name(self),
+                },
+                ops: f.ops.len(),
+                regs: f.regs.len(),
+                branches: branches(f),
+            };
+
+            let package = class
+                .as_deref()
+                .and_then(|c| c.rsplit_once('.'))
+                .map_or("", |(package, _)| package);
+            report
+                .packages
+                .entry(Str::from(package))
+                .or_default()
+                .add(&profile);
+            if let Some(class) = class {
+                report.classes.entry(class).or_default().add(&profile);
+            }
+            report.functions.push(profile);
+        }
+        report
+    }
+}
+
+/// Number of conditional branches of a function
+fn branches(f: &Function) -> usize {
+    f.ops()
+        .map(|(i, op)| match op {
+            Opcode::JAlways { .. } => 0,
+            _ => op.jump_targets(i).len(),
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::profile::ProfileKey;
+    use crate::builder::Builder;
+    use crate::opcodes::Opcode;
+    use crate::types::{RefFun, Reg, Type};
+    use crate::Str;
+
+    #[test]
+    fn profile() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let ret = || vec![Opcode::Ret { ret: Reg(0) }];
+        b.function("main", &[], int, &[int], ret());
+        // if, switch with 2 cases and a loop
+        let update = b.function(
+            "",
+            &[],
+            int,
+            &[int; 2],
+            vec![
+                Opcode::JFalse {
+                    cond: Reg(0),
+                    offset: 1,
+                },
+                Opcode::Switch {
+                    reg: Reg(0),
+                    offsets: vec![0, 1],
+                    end: 1,
+                },
+                Opcode::JAlways { offset: -3 },
+                Opcode::Ret { ret: Reg(0) },
+            ],
+        );
+        let create = b.function("", &[], int, &[int; 3], ret());
+        let player = b.class("game.Player", None, &[]);
+        b.method(player, "update", update);
+        let statics = b.class("$game.Player", None, &[]);
+        b.method(statics, "create", create);
+        let code = b.build().unwrap();
+
+        let report = code.profile();
+        let update = &report.functions[1];
+        assert_eq!(&*update.name, "game.Player.update");
+        assert_eq!(update.ops, 4);
+        assert_eq!(update.branches, 3);
+        assert_eq!(update.complexity(), 4);
+        assert_eq!(&*report.functions[2].name, "game.Player.create");
+        assert_eq!(&*report.functions[0].name, "main");
+
+        let ranked: Vec<_> = report
+            .ranked(ProfileKey::Regs)
+            .iter()
+            .map(|f| f.findex)
+            .collect();
+        assert_eq!(ranked, [RefFun(2), RefFun(1), RefFun(0)]);
+        let mut sorted = report.clone();
+        sorted.sort(ProfileKey::Complexity);
+        let sorted: Vec<_> = sorted.functions.iter().map(|f| f.findex).collect();
+        assert_eq!(sorted, [RefFun(1), RefFun(0), RefFun(2)]);
+
+        let player = &report.classes[&Str::from("game.Player")];
+        assert_eq!(player.functions, 2);
+        assert_eq!(player.ops, 5);
+        assert_eq!(report.packages[&Str::from("game")].branches, 3);
+        assert_eq!(report.packages[&Str::from("")].functions, 1);
+    }
+}
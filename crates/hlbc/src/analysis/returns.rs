@@ -0,0 +1,116 @@
+//! Functions always returning the same value, like the getters of configuration values.
+
+use crate::opcodes::Opcode;
+use crate::types::{RefFloat, RefFun, RefGlobal, RefInt, RefString};
+use crate::Bytecode;
+
+/// How many getters calling other getters are followed to find the constant
+const MAX_DEPTH: usize = 8;
+
+/// The value a function always returns
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ConstantReturn {
+    Int(RefInt),
+    Float(RefFloat),
+    Bool(bool),
+    String(RefString),
+    Null,
+    /// The value of a global, like a singleton or the static object of a class
+    Global(RefGlobal),
+}
+
+impl Bytecode {
+    /// The value always returned by a function made of loading a constant or a global and returning it.
+    /// A function returning the result of a call without arguments to such a function is followed.
+    /// Natives never return a constant.
+    pub fn constant_return(&self, fun: RefFun) -> Option<ConstantReturn> {
+        constant_return(self, fun, MAX_DEPTH)
+    }
+}
+
+fn constant_return(code: &Bytecode, fun: RefFun, depth: usize) -> Option<ConstantReturn> {
+    let f = fun.try_as_fn(code).ok()?;
+    let mut ops = f
+        .ops
+        .iter()
+        .filter(|o| !matches!(o, Opcode::Nop | Opcode::Label));
+    let (value, reg) = match *ops.next()? {
+        Opcode::Int { dst, ptr } => (ConstantReturn::Int(ptr), dst),
+        Opcode::Float { dst, ptr } => (ConstantReturn::Float(ptr), dst),
+        Opcode::Bool { dst, value } => (ConstantReturn::Bool(value), dst),
+        Opcode::String { dst, ptr } => (ConstantReturn::String(ptr), dst),
+        Opcode::Null { dst } => (ConstantReturn::Null, dst),
+        Opcode::GetGlobal { dst, global } => (ConstantReturn::Global(global), dst),
+        Opcode::Call0 { dst, fun } if depth > 0 => (constant_return(code, fun, depth - 1)?, dst),
+        _ => return None,
+    };
+    match (ops.next()?, ops.next()) {
+        (&Opcode::Ret { ret }, None) if ret == reg => Some(value),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::returns::ConstantReturn;
+    use crate::builder::Builder;
+    use crate::opcodes::Opcode;
+    use crate::types::{RefFun, RefGlobal, Reg, Type};
+
+    #[test]
+    fn constant_returns() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let zero = b.int(0);
+        let call = |fun| Opcode::Call0 {
+            dst: Reg(1),
+            fun: RefFun(fun),
+        };
+        let ret = Opcode::Ret { ret: Reg(1) };
+        let ops = [
+            vec![
+                Opcode::Int {
+                    dst: Reg(1),
+                    ptr: zero,
+                },
+                Opcode::Nop,
+                ret.clone(),
+            ],
+            vec![call(0), ret.clone()],
+            vec![
+                Opcode::GetGlobal {
+                    dst: Reg(1),
+                    global: RefGlobal(2),
+                },
+                ret.clone(),
+            ],
+            // Returns another register
+            vec![
+                Opcode::Int {
+                    dst: Reg(1),
+                    ptr: zero,
+                },
+                Opcode::Ret { ret: Reg(0) },
+            ],
+            // Calls itself
+            vec![call(4), ret],
+        ];
+        // Functions are numbered in order
+        for ops in ops {
+            b.function("", &[int], int, &[int], ops);
+        }
+        let code = b.build().unwrap();
+        let returns: Vec<_> = (0..6).map(|i| code.constant_return(RefFun(i))).collect();
+        assert_eq!(
+            returns,
+            [
+                Some(ConstantReturn::Int(zero)),
+                Some(ConstantReturn::Int(zero)),
+                Some(ConstantReturn::Global(RefGlobal(2))),
+                None,
+                None,
+                None
+            ]
+        );
+    }
+}
@@ -0,0 +1,215 @@
+//! Find calls, constants and opcode sequences in the whole program, with the location of each hit.
+//!
+//! Opcode sequence pattern syntax : opcode names separated by spaces, like `GetThis Field _ * Ret`
+//! - an opcode name (as in `explain`) matches this opcode.
+//! - `_` matches any single opcode.
+//! - `*` matches any number of opcodes, as few as possible.
+
+use std::str::FromStr;
+
+use crate::opcodes::Opcode;
+use crate::types::{Function, RefFun, RefString};
+use crate::Bytecode;
+
+/// A location in the code : `len` opcodes starting at `pos` in a function
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Hit {
+    pub fun: RefFun,
+    pub pos: usize,
+    pub len: usize,
+}
+
+impl Hit {
+    fn single(f: &Function, pos: usize) -> Self {
+        Self {
+            fun: f.findex,
+            pos,
+            len: 1,
+        }
+    }
+}
+
+/// A pattern matching opcodes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpPattern {
+    /// `_`
+    Any,
+    /// `*`
+    Many,
+    /// Opcode name
+    Opcode(&'static str),
+}
+
+/// A pattern matching a sequence of opcodes. See the [module](self) documentation for the syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequencePattern(pub Vec<OpPattern>);
+
+impl FromStr for SequencePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let patterns = s
+            .split_whitespace()
+            .map(|p| match p {
+                "_" => Ok(OpPattern::Any),
+                "*" => Ok(OpPattern::Many),
+                _ => Opcode::from_name(p)
+                    .map(|o| OpPattern::Opcode(o.name()))
+                    .ok_or_else(|| format!("Unknown opcode '{p}'")),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if patterns.iter().all(|p| *p == OpPattern::Many) {
+            return Err("The pattern must match at least one opcode".to_owned());
+        }
+        Ok(Self(patterns))
+    }
+}
+
+impl SequencePattern {
+    /// Number of opcodes matched at the start of `ops`
+    pub fn match_at(&self, ops: &[Opcode]) -> Option<usize> {
+        match_at(&self.0, ops, 0)
+    }
+}
+
+fn match_at(patterns: &[OpPattern], ops: &[Opcode], len: usize) -> Option<usize> {
+    let Some((first, rest)) = patterns.split_first() else {
+        return Some(len);
+    };
+    match first {
+        OpPattern::Many => {
+            (0..=ops.len()).find_map(|skip| match_at(rest, &ops[skip..], len + skip))
+        }
+        OpPattern::Any => match_at(rest, ops.get(1..)?, len + 1),
+        &OpPattern::Opcode(name) if ops.first()?.name() == name => {
+            match_at(rest, &ops[1..], len + 1)
+        }
+        OpPattern::Opcode(_) => None,
+    }
+}
+
+impl Bytecode {
+    /// Every call to a function, and every closure created from it
+    pub fn calls_to(&self, fun: RefFun) -> Vec<Hit> {
+        self.functions
+            .iter()
+            .flat_map(|f| {
+                f.find_fun_refs()
+                    .filter(move |&(_, _, target)| target == fun)
+                    .map(move |(pos, _, _)| Hit::single(f, pos))
+            })
+            .collect()
+    }
+
+    /// Every use of a string constant in the code, including dynamic field accesses by name.
+    /// Give it a regex to search with regular expressions.
+    pub fn strings_matching(&self, mut predicate: impl FnMut(&str) -> bool) -> Vec<Hit> {
+        let mut matches = vec![None; self.strings.len()];
+        let mut is_match =
+            |s: RefString| *matches[s.0].get_or_insert_with(|| predicate(&self.strings[s.0]));
+        self.ops()
+            .filter(|&(_, (_, op))| match *op {
+                Opcode::String { ptr, .. } => is_match(ptr),
+                Opcode::DynGet { field, .. } | Opcode::DynSet { field, .. } => is_match(field),
+                _ => false,
+            })
+            .map(|(f, (pos, _))| Hit::single(f, pos))
+            .collect()
+    }
+
+    /// Every use of an int constant
+    pub fn int_uses(&self, value: i32) -> Vec<Hit> {
+        self.ops()
+            .filter(|&(_, (_, op))| matches!(*op, Opcode::Int { ptr, .. } if self[ptr] == value))
+            .map(|(f, (pos, _))| Hit::single(f, pos))
+            .collect()
+    }
+
+    /// Every sequence of opcodes matching the pattern. Matches in a function don't overlap.
+    pub fn find_sequence(&self, pattern: &SequencePattern) -> Vec<Hit> {
+        let mut hits = Vec::new();
+        for f in &self.functions {
+            let mut pos = 0;
+            while pos < f.ops.len() {
+                match pattern.match_at(&f.ops[pos..]) {
+                    Some(len) if len > 0 => {
+                        hits.push(Hit {
+                            fun: f.findex,
+                            pos,
+                            len,
+                        });
+                        pos += len;
+                    }
+                    _ => pos += 1,
+                }
+            }
+        }
+        hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::search::{Hit, OpPattern, SequencePattern};
+    use crate::builder::Builder;
+    use crate::opcodes::Opcode;
+    use crate::types::{RefField, RefFun, Reg, Type};
+
+    #[test]
+    fn parse_pattern() {
+        let p: SequencePattern = "GetThis _ * Ret".parse().unwrap();
+        assert_eq!(
+            p.0,
+            [
+                OpPattern::Opcode("GetThis"),
+                OpPattern::Any,
+                OpPattern::Many,
+                OpPattern::Opcode("Ret")
+            ]
+        );
+        assert!("GetThis Nope".parse::<SequencePattern>().is_err());
+        assert!("*".parse::<SequencePattern>().is_err());
+    }
+
+    #[test]
+    fn search() {
+        let mut b = Builder::new();
+        let dyn_ = b.ty(Type::Dyn);
+        let ops = vec![
+            Opcode::GetThis {
+                dst: Reg(0),
+                field: RefField(0),
+            },
+            Opcode::Int {
+                dst: Reg(0),
+                ptr: b.int(42),
+            },
+            Opcode::String {
+                dst: Reg(0),
+                ptr: b.string("player_hp"),
+            },
+            Opcode::Call0 {
+                dst: Reg(0),
+                fun: RefFun(0),
+            },
+            Opcode::Ret { ret: Reg(0) },
+        ];
+        b.function("", &[], dyn_, &[dyn_], ops);
+        let code = b.build().unwrap();
+        let hit = |pos, len| Hit {
+            fun: RefFun(0),
+            pos,
+            len,
+        };
+        assert_eq!(code.calls_to(RefFun(0)), [hit(3, 1)]);
+        assert!(code.calls_to(RefFun(1)).is_empty());
+        assert_eq!(code.strings_matching(|s| s.ends_with("_hp")), [hit(2, 1)]);
+        assert_eq!(code.int_uses(42), [hit(1, 1)]);
+        assert!(code.int_uses(7).is_empty());
+
+        let find = |p: &str| code.find_sequence(&p.parse().unwrap());
+        assert_eq!(find("GetThis * Call0"), [hit(0, 4)]);
+        assert_eq!(find("_ _"), [hit(0, 2), hit(2, 2)]);
+        assert!(find("String Ret").is_empty());
+    }
+}
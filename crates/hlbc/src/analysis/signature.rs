@@ -0,0 +1,268 @@
+//! Find functions by their signature.
+//!
+//! Names are often meaningless in obfuscated or stripped binaries, but types are always there.
+//!
+//! Pattern syntax : `(arg, arg, ...) -> ret`
+//! - a type name : `String`, `i32`, `Int`, `null<i32>`, `Player`. Names are matched case-insensitively
+//!   and a name without a package matches any package (`Player` matches `game.Player`).
+//! - `_` matches any single type.
+//! - a function type, with the same syntax : `(Int) -> Void`.
+//! - `..` as the last argument matches any number of remaining arguments.
+//! - the return type can be omitted : `(String, ..)`.
+//!
+//! Methods take their instance as the first argument.
+
+use std::str::FromStr;
+
+use crate::fmt::EnhancedFmt;
+use crate::types::{RefFun, RefType, Type, TypeFun};
+use crate::Bytecode;
+
+/// A pattern matching a single type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypePattern {
+    /// `_`
+    Any,
+    /// Lowercase type name
+    Name(String),
+    /// `(arg, ...) -> ret`
+    Fun(Box<SignaturePattern>),
+}
+
+impl TypePattern {
+    pub fn matches(&self, code: &Bytecode, ty: RefType) -> bool {
+        match self {
+            TypePattern::Any => true,
+            TypePattern::Name(name) => {
+                let display = ty.display::<EnhancedFmt>(code).to_string().to_lowercase();
+                display == *name
+                    || display
+                        .rsplit_once('.')
+                        .map(|(_, short)| short == name)
+                        .unwrap_or(false)
+            }
+            TypePattern::Fun(pattern) => match &code[ty] {
+                Type::Fun(fun) | Type::Method(fun) => pattern.matches(code, fun),
+                _ => false,
+            },
+        }
+    }
+}
+
+impl FromStr for TypePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("Empty type pattern".to_owned());
+        }
+        Ok(match s {
+            "_" => TypePattern::Any,
+            _ if s.starts_with('(') => TypePattern::Fun(Box::new(s.parse()?)),
+            // Haxe names for the base types
+            "Int" => TypePattern::Name("i32".to_owned()),
+            "Float" => TypePattern::Name("f64".to_owned()),
+            "Single" => TypePattern::Name("f32".to_owned()),
+            _ => TypePattern::Name(s.to_lowercase()),
+        })
+    }
+}
+
+/// A pattern matching a function signature. See the [module](self) documentation for the syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignaturePattern {
+    pub args: Vec<TypePattern>,
+    /// Allow more arguments than specified in `args`
+    pub varargs: bool,
+    pub ret: TypePattern,
+}
+
+impl SignaturePattern {
+    pub fn matches(&self, code: &Bytecode, ty: &TypeFun) -> bool {
+        let arity_ok = if self.varargs {
+            ty.args.len() >= self.args.len()
+        } else {
+            ty.args.len() == self.args.len()
+        };
+        arity_ok
+            && self
+                .args
+                .iter()
+                .zip(&ty.args)
+                .all(|(p, &arg)| p.matches(code, arg))
+            && self.ret.matches(code, ty.ret)
+    }
+}
+
+impl FromStr for SignaturePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        // The parenthesis closing the arguments, function types can be nested in them
+        let close = s
+            .starts_with('(')
+            .then(|| with_depth(s).find(|&(_, c, depth)| c == ')' && depth == 1))
+            .flatten()
+            .map(|(i, _, _)| i)
+            .ok_or_else(|| format!("Expected arguments between parentheses, got '{s}'"))?;
+        let args = &s[1..close];
+        let ret = match s[close + 1..].trim() {
+            "" => TypePattern::Any,
+            rest => rest
+                .strip_prefix("->")
+                .ok_or_else(|| format!("Expected '->' after the arguments, got '{rest}'"))?
+                .parse()?,
+        };
+
+        let mut patterns = Vec::new();
+        let mut varargs = false;
+        for arg in split_top_level(args) {
+            if varargs {
+                return Err("'..' must be the last argument".to_owned());
+            }
+            if arg.trim() == ".." {
+                varargs = true;
+            } else {
+                patterns.push(arg.parse()?);
+            }
+        }
+        Ok(Self {
+            args: patterns,
+            varargs,
+            ret,
+        })
+    }
+}
+
+/// The characters of `s` with the number of brackets (parentheses and angle brackets) open before them.
+/// The `>` of an arrow doesn't close anything.
+fn with_depth(s: &str) -> impl Iterator<Item = (usize, char, i32)> + '_ {
+    let mut depth = 0;
+    let mut prev = ' ';
+    s.char_indices().map(move |(i, c)| {
+        let before = depth;
+        match c {
+            '(' | '<' => depth += 1,
+            ')' => depth -= 1,
+            '>' if prev != '-' => depth -= 1,
+            _ => {}
+        }
+        prev = c;
+        (i, c, before)
+    })
+}
+
+/// Split on commas that aren't inside brackets
+fn split_top_level(s: &str) -> Vec<&str> {
+    if s.trim().is_empty() {
+        return Vec::new();
+    }
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for (i, _, _) in with_depth(s).filter(|&(_, c, depth)| c == ',' && depth == 0) {
+        parts.push(&s[start..i]);
+        start = i + 1;
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+impl Bytecode {
+    /// Find all functions and natives with a signature matching the pattern.
+    /// Functions come first, then natives, both in the order of the bytecode.
+    pub fn find_by_signature<'a>(
+        &'a self,
+        pattern: &'a SignaturePattern,
+    ) -> impl Iterator<Item = RefFun> + 'a {
+        self.functions
+            .iter()
+            .map(|f| (f.findex, f.t))
+            .chain(self.natives.iter().map(|n| (n.findex, n.t)))
+            .filter(move |&(_, t)| {
+                t.as_fun(self)
+                    .map(|ty| pattern.matches(self, ty))
+                    .unwrap_or(false)
+            })
+            .map(|(findex, _)| findex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::signature::{SignaturePattern, TypePattern};
+    use crate::builder::Builder;
+    use crate::types::{RefFun, Type};
+
+    #[test]
+    fn parse_pattern() {
+        let p: SignaturePattern = "(String, Int) -> Bool".parse().unwrap();
+        assert_eq!(
+            p.args,
+            vec![
+                TypePattern::Name("string".to_owned()),
+                TypePattern::Name("i32".to_owned())
+            ]
+        );
+        assert!(!p.varargs);
+        assert_eq!(p.ret, TypePattern::Name("bool".to_owned()));
+
+        let p: SignaturePattern = "(null<i32>, _, ..)".parse().unwrap();
+        assert_eq!(
+            p.args,
+            vec![TypePattern::Name("null<i32>".to_owned()), TypePattern::Any]
+        );
+        assert!(p.varargs);
+        assert_eq!(p.ret, TypePattern::Any);
+
+        let p: SignaturePattern = "() -> Void".parse().unwrap();
+        assert!(p.args.is_empty());
+
+        // Function types, as arguments or returned
+        let p: SignaturePattern = "((Int) -> Void, null<i32>) -> (String) -> Bool"
+            .parse()
+            .unwrap();
+        let callback = SignaturePattern {
+            args: vec![TypePattern::Name("i32".to_owned())],
+            varargs: false,
+            ret: TypePattern::Name("void".to_owned()),
+        };
+        assert_eq!(
+            p.args,
+            vec![
+                TypePattern::Fun(Box::new(callback)),
+                TypePattern::Name("null<i32>".to_owned())
+            ]
+        );
+        let TypePattern::Fun(ret) = &p.ret else {
+            panic!("expected a function type, got {:?}", p.ret);
+        };
+        assert_eq!(ret.args, vec![TypePattern::Name("string".to_owned())]);
+        assert_eq!(ret.ret, TypePattern::Name("bool".to_owned()));
+
+        assert!("(.., Int)".parse::<SignaturePattern>().is_err());
+        assert!("(Int) Int".parse::<SignaturePattern>().is_err());
+        assert!("((Int) -> Void".parse::<SignaturePattern>().is_err());
+        assert!("String -> Int".parse::<SignaturePattern>().is_err());
+        assert!("(Int, ) -> Int".parse::<SignaturePattern>().is_err());
+    }
+
+    #[test]
+    fn find_callbacks() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let void = b.ty(Type::Void);
+        let callback = b.fun_type(&[int], void);
+        b.function("on", &[callback], void, &[], Vec::new());
+        b.function("set", &[int], void, &[], Vec::new());
+        let code = b.build().unwrap();
+        let find = |pattern: &str| -> Vec<RefFun> {
+            let pattern = pattern.parse().unwrap();
+            code.find_by_signature(&pattern).collect()
+        };
+        assert_eq!(find("((Int) -> Void) -> Void"), [RefFun(0)]);
+        assert_eq!(find("((_) -> _)"), [RefFun(0)]);
+        assert!(find("((Float) -> Void)").is_empty());
+    }
+}
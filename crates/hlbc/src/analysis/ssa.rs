@@ -0,0 +1,363 @@
+//! Static single assignment form of a function.
+//!
+//! Registers are reused for unrelated values : each write to a register creates a new version of it, a [Value].
+//! Where the control flow joins, a [Phi] merges the versions coming from each predecessor into a new one. Reading
+//! a register at some point then always designates a single value, defined at a single place.
+//!
+//! The form is minimal : phi nodes are placed on the dominance frontiers of the definitions, even for registers that
+//! are never read afterward. Instructions that can't be reached from the start of the function read no value.
+
+use std::collections::HashSet;
+
+use crate::analysis::cfg::{cfg, dominators, Cfg};
+use crate::types::{Function, Reg};
+
+/// A version of a register, the value written by a single definition
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Value(pub usize);
+
+/// Where a value is defined
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Def {
+    /// Value of the register when entering the function, the arguments or uninitialized registers
+    Entry,
+    /// Written by the instruction at this position
+    Op(usize),
+    /// Phi node at the start of this block
+    Phi(usize),
+}
+
+/// A version of a register and where it comes from
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ValueInfo {
+    pub reg: Reg,
+    pub def: Def,
+}
+
+/// Merge of the values of a register at a join point
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Phi {
+    pub reg: Reg,
+    pub dst: Value,
+    /// Value coming from each predecessor block, as (block, value)
+    pub args: Vec<(usize, Value)>,
+}
+
+/// A function in static single assignment form, as computed by [ssa]
+#[derive(Debug, Clone, Default)]
+pub struct Ssa {
+    pub cfg: Cfg,
+    /// Immediate dominator of each block
+    pub idom: Vec<Option<usize>>,
+    /// Every value, the first ones are the [entry](Def::Entry) values of each register, indexed by register
+    pub values: Vec<ValueInfo>,
+    /// Phi nodes at the start of each block
+    pub phis: Vec<Vec<Phi>>,
+    /// Values read by each instruction, in the order of [Opcode::read_regs](crate::opcodes::Opcode::read_regs)
+    pub reads: Vec<Vec<Value>>,
+    /// Value written by each instruction
+    pub writes: Vec<Option<Value>>,
+}
+
+impl Ssa {
+    /// The value held by `reg` just before the instruction at `pos`, `None` if it can't be reached
+    pub fn value_at(&self, pos: usize, reg: Reg) -> Option<Value> {
+        let mut block = self.cfg.block_of(pos)?;
+        let mut end = pos;
+        loop {
+            let start = self.cfg.blocks[block].ops.start;
+            if let Some(v) = self.writes[start..end]
+                .iter()
+                .rev()
+                .flatten()
+                .find(|v| self.values[v.0].reg == reg)
+            {
+                return Some(*v);
+            }
+            if let Some(phi) = self.phis[block].iter().find(|p| p.reg == reg) {
+                return Some(phi.dst);
+            }
+            // Without a phi node, the value is the same on every path coming from the dominator
+            match self.idom[block]? {
+                d if d == block => {
+                    return self
+                        .values
+                        .get(reg.0 as usize)
+                        .filter(|v| v.def == Def::Entry)
+                        .map(|_| Value(reg.0 as usize));
+                }
+                d => {
+                    block = d;
+                    end = self.cfg.blocks[d].ops.end;
+                }
+            }
+        }
+    }
+
+    /// Positions of the instructions reading a value
+    pub fn uses(&self, value: Value) -> impl Iterator<Item = usize> + '_ {
+        self.reads
+            .iter()
+            .enumerate()
+            .filter(move |(_, reads)| reads.contains(&value))
+            .map(|(i, _)| i)
+    }
+
+    /// Conversion back to variables : the values merged by phi nodes must live in the same variable. Returns the
+    /// variable of each value, variables are numbered from 0.
+    ///
+    /// Values of a register that are never merged get their own variable, even if the bytecode stores them in the
+    /// same register.
+    pub fn variables(&self) -> Vec<usize> {
+        // Union find
+        let mut parent: Vec<usize> = (0..self.values.len()).collect();
+        for phi in self.phis.iter().flatten() {
+            for &(_, arg) in &phi.args {
+                let (a, b) = (find(&mut parent, phi.dst.0), find(&mut parent, arg.0));
+                parent[a] = b;
+            }
+        }
+        let mut numbers = vec![usize::MAX; self.values.len()];
+        let mut count = 0;
+        (0..self.values.len())
+            .map(|v| {
+                let root = find(&mut parent, v);
+                if numbers[root] == usize::MAX {
+                    numbers[root] = count;
+                    count += 1;
+                }
+                numbers[root]
+            })
+            .collect()
+    }
+}
+
+/// Representative of the set of `v` in a union find
+fn find(parent: &mut [usize], mut v: usize) -> usize {
+    while parent[v] != v {
+        parent[v] = parent[parent[v]];
+        v = parent[v];
+    }
+    v
+}
+
+/// Put a function in static single assignment form
+pub fn ssa(f: &Function) -> Ssa {
+    let cfg = cfg(f);
+    let n = cfg.blocks.len();
+    let nregs = f
+        .ops
+        .iter()
+        .flat_map(|op| op.read_regs().into_iter().chain(op.written_reg()))
+        .map(|r| r.0 as usize + 1)
+        .max()
+        .unwrap_or(0)
+        .max(f.regs.len());
+    let mut values: Vec<ValueInfo> = (0..nregs)
+        .map(|r| ValueInfo {
+            reg: Reg(r as u32),
+            def: Def::Entry,
+        })
+        .collect();
+    let mut phis = vec![Vec::new(); n];
+    let mut reads = vec![Vec::new(); f.ops.len()];
+    let mut writes = vec![None; f.ops.len()];
+    if n == 0 {
+        return Ssa {
+            cfg,
+            idom: Vec::new(),
+            values,
+            phis,
+            reads,
+            writes,
+        };
+    }
+
+    let (succs, preds) = cfg.adjacency();
+    let idom = dominators(&succs, &preds, 0);
+
+    // Dominance frontiers
+    let mut frontiers = vec![HashSet::new(); n];
+    for (b, block_preds) in preds.iter().enumerate() {
+        let Some(dom) = idom[b].filter(|_| block_preds.len() > 1) else {
+            continue;
+        };
+        for &p in block_preds {
+            let mut runner = p;
+            while runner != dom && idom[runner].is_some() {
+                frontiers[runner].insert(b);
+                let Some(next) = idom[runner].filter(|&d| d != runner) else {
+                    break;
+                };
+                runner = next;
+            }
+        }
+    }
+
+    // Place the phi nodes on the iterated dominance frontiers of the blocks writing each register.
+    // Every register is defined on entry.
+    let mut def_blocks = vec![vec![0]; nregs];
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        for op in &f.ops[block.ops.clone()] {
+            if let Some(r) = op.written_reg() {
+                def_blocks[r.0 as usize].push(b);
+            }
+        }
+    }
+    let mut has_phi = vec![HashSet::new(); n];
+    for (r, mut todo) in def_blocks.into_iter().enumerate() {
+        while let Some(b) = todo.pop() {
+            for &front in &frontiers[b] {
+                if has_phi[front].insert(r) {
+                    todo.push(front);
+                }
+            }
+        }
+    }
+    for (b, regs) in has_phi.into_iter().enumerate() {
+        let mut regs: Vec<_> = regs.into_iter().collect();
+        regs.sort_unstable();
+        phis[b] = regs
+            .into_iter()
+            .map(|r| {
+                values.push(ValueInfo {
+                    reg: Reg(r as u32),
+                    def: Def::Phi(b),
+                });
+                Phi {
+                    reg: Reg(r as u32),
+                    dst: Value(values.len() - 1),
+                    args: Vec::new(),
+                }
+            })
+            .collect();
+    }
+
+    // Rename with a walk of the dominator tree, keeping the current value of each register
+    let mut children = vec![Vec::new(); n];
+    for (b, d) in idom.iter().enumerate() {
+        if let Some(d) = d.filter(|&d| d != b) {
+            children[d].push(b);
+        }
+    }
+    let mut current: Vec<Value> = (0..nregs).map(Value).collect();
+    // Blocks to enter, or values to restore when leaving a block
+    enum Visit {
+        Enter(usize),
+        Restore(Vec<(Reg, Value)>),
+    }
+    let mut stack = vec![Visit::Enter(0)];
+    while let Some(visit) = stack.pop() {
+        let b = match visit {
+            Visit::Enter(b) => b,
+            Visit::Restore(saved) => {
+                for (r, v) in saved.into_iter().rev() {
+                    current[r.0 as usize] = v;
+                }
+                continue;
+            }
+        };
+        let mut saved = Vec::new();
+        for phi in &phis[b] {
+            saved.push((phi.reg, current[phi.reg.0 as usize]));
+            current[phi.reg.0 as usize] = phi.dst;
+        }
+        for i in cfg.blocks[b].ops.clone() {
+            let op = &f.ops[i];
+            reads[i] = op
+                .read_regs()
+                .into_iter()
+                .map(|r| current[r.0 as usize])
+                .collect();
+            if let Some(r) = op.written_reg() {
+                values.push(ValueInfo {
+                    reg: r,
+                    def: Def::Op(i),
+                });
+                let v = Value(values.len() - 1);
+                writes[i] = Some(v);
+                saved.push((r, current[r.0 as usize]));
+                current[r.0 as usize] = v;
+            }
+        }
+        for &s in &succs[b] {
+            for phi in &mut phis[s] {
+                phi.args.push((b, current[phi.reg.0 as usize]));
+            }
+        }
+        stack.push(Visit::Restore(saved));
+        stack.extend(children[b].iter().rev().map(|&c| Visit::Enter(c)));
+    }
+
+    Ssa {
+        cfg,
+        idom,
+        values,
+        phis,
+        reads,
+        writes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::ssa::{ssa, Def, Value};
+    use crate::opcodes::Opcode;
+    use crate::types::{Function, RefFun, RefString, RefType, Reg};
+
+    #[test]
+    fn phi_nodes() {
+        // if r0 { r1 = r0 } else { r1 = r2 }; return r1
+        let f = Function {
+            t: RefType(0),
+            findex: RefFun(0),
+            regs: vec![RefType(0); 3],
+            ops: vec![
+                Opcode::JFalse {
+                    cond: Reg(0),
+                    offset: 2,
+                },
+                Opcode::Mov {
+                    dst: Reg(1),
+                    src: Reg(0),
+                },
+                Opcode::JAlways { offset: 1 },
+                Opcode::Mov {
+                    dst: Reg(1),
+                    src: Reg(2),
+                },
+                Opcode::Ret { ret: Reg(1) },
+            ],
+            debug_info: None,
+            assigns: None,
+            name: RefString(0),
+            parent: None,
+        };
+        let ssa = ssa(&f);
+        // Entry values
+        assert_eq!(ssa.reads[0], [Value(0)]);
+        assert_eq!(ssa.reads[3], [Value(2)]);
+        let then = ssa.writes[1].unwrap();
+        let else_ = ssa.writes[3].unwrap();
+        assert_eq!(ssa.values[then.0].def, Def::Op(1));
+        // The join block merges both versions of r1
+        let join = ssa.cfg.block_of(4).unwrap();
+        assert_eq!(ssa.phis[join].len(), 1);
+        let phi = &ssa.phis[join][0];
+        assert_eq!(phi.reg, Reg(1));
+        let mut args: Vec<_> = phi.args.iter().map(|&(_, v)| v).collect();
+        args.sort();
+        assert_eq!(args, [then, else_]);
+        assert_eq!(ssa.reads[4], [phi.dst]);
+        assert_eq!(ssa.value_at(4, Reg(1)), Some(phi.dst));
+        assert_eq!(ssa.value_at(2, Reg(1)), Some(then));
+        assert_eq!(ssa.value_at(3, Reg(0)), Some(Value(0)));
+        assert_eq!(ssa.uses(phi.dst).collect::<Vec<_>>(), [4]);
+
+        // Both versions and the phi end up in a single variable, unlike the entry value of r1
+        let vars = ssa.variables();
+        assert_eq!(vars[then.0], vars[phi.dst.0]);
+        assert_eq!(vars[else_.0], vars[phi.dst.0]);
+        assert_ne!(vars[1], vars[phi.dst.0]);
+    }
+}
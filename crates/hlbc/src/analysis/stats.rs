@@ -0,0 +1,238 @@
+//! Statistics on the use of each class : how many times it is instantiated, how often its fields are accessed and
+//! which of its methods are never called. Gives a quick overview of the important classes of a large unknown program.
+
+use std::collections::{BTreeMap, HashSet};
+
+use crate::opcodes::Opcode;
+use crate::types::{RefField, RefFun, RefType, Type};
+use crate::{Bytecode, Str};
+
+/// Use of a class or a struct in the code
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TypeStats {
+    pub ty: RefType,
+    /// Number of `New` instructions creating an instance
+    pub instantiations: usize,
+    /// Number of reads and writes of each field declared by the type, indexed like its own fields. Accesses to
+    /// inherited fields count for the class declaring them.
+    pub field_accesses: Vec<usize>,
+    /// Methods never called nor referenced
+    pub unused_methods: Vec<RefFun>,
+}
+
+impl TypeStats {
+    /// Total number of field accesses
+    pub fn accesses(&self) -> usize {
+        self.field_accesses.iter().sum()
+    }
+}
+
+/// Totals for the classes of a package
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct PackageStats {
+    /// Number of classes and structs
+    pub types: usize,
+    pub instantiations: usize,
+    pub field_accesses: usize,
+    pub unused_methods: usize,
+}
+
+/// Statistics of every class, see [Bytecode::type_stats]
+#[derive(Debug, Clone, Default)]
+pub struct TypeStatsReport {
+    /// Classes and structs, in bytecode order
+    pub types: Vec<TypeStats>,
+    /// Totals by dotted package path, the top level package is empty
+    pub packages: BTreeMap<Str, PackageStats>,
+}
+
+impl Bytecode {
+    /// Count the instantiations and the field accesses of every class and struct, and find their unused methods.
+    ///
+    /// A method is unused when no function calls it or makes a closure of it. Calls through a parent class or by name
+    /// on a dynamic value count for every method of that name, and methods named like `__string` or `toString` are
+    /// called by the runtime : methods reported unused are really unreachable.
+    pub fn type_stats(&self) -> TypeStatsReport {
+        let mut stats: Vec<Option<TypeStats>> = self
+            .types
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                t.get_type_obj().map(|obj| TypeStats {
+                    ty: RefType(i),
+                    instantiations: 0,
+                    field_accesses: vec![0; obj.own_fields.len()],
+                    unused_methods: Vec::new(),
+                })
+            })
+            .collect();
+
+        // Functions referenced directly, and method names called by dynamic dispatch
+        let mut referenced: HashSet<RefFun> = self
+            .types
+            .iter()
+            .filter_map(Type::get_type_obj)
+            .flat_map(|obj| obj.bindings.values().copied())
+            .collect();
+        let mut dispatched = HashSet::new();
+        for f in &self.functions {
+            referenced.extend(f.find_fun_refs().map(|(_, _, fun)| fun));
+            let this = f.regs.first().copied();
+            for op in &f.ops {
+                match *op {
+                    Opcode::New { dst } => {
+                        if let Some(s) = stats.get_mut(f[dst].0).and_then(Option::as_mut) {
+                            s.instantiations += 1;
+                        }
+                    }
+                    Opcode::Field { obj, field, .. } | Opcode::SetField { obj, field, .. } => {
+                        self.count_access(&mut stats, f[obj], field);
+                    }
+                    Opcode::GetThis { field, .. } | Opcode::SetThis { field, .. } => {
+                        if let Some(this) = this {
+                            self.count_access(&mut stats, this, field);
+                        }
+                    }
+                    Opcode::CallMethod {
+                        field, ref args, ..
+                    } => {
+                        if let Some(p) = args.first().and_then(|&obj| f[obj].method(field.0, self))
+                        {
+                            dispatched.insert(p.name(self));
+                        }
+                    }
+                    Opcode::CallThis { field, .. } => {
+                        if let Some(p) = this.and_then(|this| this.method(field.0, self)) {
+                            dispatched.insert(p.name(self));
+                        }
+                    }
+                    Opcode::DynGet { field, .. } | Opcode::DynSet { field, .. } => {
+                        dispatched.insert(self[field].clone());
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut report = TypeStatsReport::default();
+        for mut s in stats.into_iter().flatten() {
+            let Some(obj) = self[s.ty].get_type_obj() else {
+                continue;
+            };
+            s.unused_methods = obj
+                .protos
+                .iter()
+                .filter(|p| !referenced.contains(&p.findex))
+                .filter(|p| {
+                    let name = p.name(self);
+                    !dispatched.contains(&name) && !name.starts_with("__") && name != "toString"
+                })
+                .map(|p| p.findex)
+                .collect();
+            let name = obj.name(self);
+            let package = name.rsplit_once('.').map_or("", |(package, _)| package);
+            let total = report.packages.entry(Str::from(package)).or_default();
+            total.types += 1;
+            total.instantiations += s.instantiations;
+            total.field_accesses += s.accesses();
+            total.unused_methods += s.unused_methods.len();
+            report.types.push(s);
+        }
+        report
+    }
+
+    /// Count an access to a field of `ty` for the class declaring it
+    fn count_access(&self, stats: &mut [Option<TypeStats>], ty: RefType, field: RefField) {
+        // Fields are laid out parents first, go up to the class declaring it
+        let mut owner = ty;
+        // Bounded in case of a cycle in malformed bytecode
+        for _ in 0..self.types.len() {
+            let Some(obj) = self.types.get(owner.0).and_then(Type::get_type_obj) else {
+                return;
+            };
+            let inherited = obj.fields.len() - obj.own_fields.len();
+            if field.0 >= inherited {
+                if let Some(count) = stats[owner.0]
+                    .as_mut()
+                    .and_then(|s| s.field_accesses.get_mut(field.0 - inherited))
+                {
+                    *count += 1;
+                }
+                return;
+            }
+            match obj.super_ {
+                Some(parent) => owner = parent,
+                None => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::Builder;
+    use crate::opcodes::Opcode;
+    use crate::types::{RefField, RefFun, Reg, Type};
+    use crate::Str;
+
+    #[test]
+    fn type_stats() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        // p = new Player(); p.x; p.y = p.x; p.move(); new Player()
+        let main = b.function(
+            "",
+            &[],
+            void,
+            &[],
+            vec![
+                Opcode::New { dst: Reg(0) },
+                Opcode::Field {
+                    dst: Reg(1),
+                    obj: Reg(0),
+                    field: RefField(0),
+                },
+                Opcode::SetField {
+                    obj: Reg(0),
+                    field: RefField(1),
+                    src: Reg(1),
+                },
+                Opcode::CallMethod {
+                    dst: Reg(1),
+                    field: RefField(0),
+                    args: vec![Reg(0)],
+                },
+                Opcode::New { dst: Reg(0) },
+                Opcode::Ret { ret: Reg(1) },
+            ],
+        );
+        // Entity { x; move(); jump(); toString() }
+        let entity = b.class("game.Entity", None, &[("x", void)]);
+        // Player extends Entity { y }
+        let player = b.class("game.Player", Some(entity), &[("y", void)]);
+        b.function_mut(main).regs = vec![player, void];
+        for name in ["move", "jump", "toString"] {
+            let method = b.function("", &[entity], void, &[], vec![Opcode::Ret { ret: Reg(0) }]);
+            b.method(entity, name, method);
+            b.method(player, name, method);
+        }
+        let code = b.build().unwrap();
+
+        let report = code.type_stats();
+        let [entity, player] = &report.types[..] else {
+            panic!("{:?}", report.types)
+        };
+        assert_eq!(entity.instantiations, 0);
+        assert_eq!(entity.field_accesses, [1]);
+        assert_eq!(entity.unused_methods, [RefFun(2)]);
+        assert_eq!(player.instantiations, 2);
+        assert_eq!(player.field_accesses, [1]);
+        assert_eq!(player.unused_methods, [RefFun(2)]);
+
+        let game = &report.packages[&Str::from("game")];
+        assert_eq!(game.types, 2);
+        assert_eq!(game.instantiations, 2);
+        assert_eq!(game.field_accesses, 2);
+        assert_eq!(game.unused_methods, 2);
+    }
+}
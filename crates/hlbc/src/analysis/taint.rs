@@ -0,0 +1,367 @@
+//! Tracking of a value across the program, to find where some data ends up : the save data read from a file, a
+//! network payload or the result of a license check.
+//!
+//! Inside a function, the value is followed through the registers with the [SSA form](crate::analysis::ssa) : every
+//! value computed from a tainted value is tainted. Passing a tainted value to a function taints its argument, returning
+//! one taints the result of every call to the function, and storing one in a field or a global taints every read of
+//! that field or global. Values stored in arrays, dynamic objects or captured by closures aren't followed.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::analysis::ssa::{ssa, Def, Ssa, Value};
+use crate::analysis::usage::{CodeLocation, FieldAccess, UsageIndex};
+use crate::opcodes::Opcode;
+use crate::types::{Function, RefField, RefFun, RefGlobal, RefString, RefType, Reg};
+use crate::Bytecode;
+
+/// Where the tracked value comes from
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TaintSource {
+    /// The value written to `reg` by the instruction at `pos`, or the value of the argument `reg` when `pos` is `None`
+    Register {
+        fun: RefFun,
+        pos: Option<usize>,
+        reg: Reg,
+    },
+    /// The value of a global, wherever it is read
+    Global(RefGlobal),
+    /// The result of every call to a function, like a native reading a file
+    CallResult(RefFun),
+}
+
+/// A place where the tracked value leaves a function
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TaintSink {
+    /// Passed to a function, `arg` is the register of the callee receiving it
+    Call {
+        loc: CodeLocation,
+        fun: RefFun,
+        arg: usize,
+    },
+    /// Stored in a field of a class, `field` counts the inherited fields
+    Field {
+        loc: CodeLocation,
+        ty: RefType,
+        field: RefField,
+    },
+    /// Stored in a global
+    Global {
+        loc: CodeLocation,
+        global: RefGlobal,
+    },
+    /// Stored in a field of a dynamic object
+    DynField { loc: CodeLocation, name: RefString },
+    /// Returned by the function
+    Return { loc: CodeLocation },
+}
+
+/// Where a value flows, see [Bytecode::taint]
+#[derive(Debug, Clone, Default)]
+pub struct TaintReport {
+    /// Functions handling the value, sorted
+    pub functions: Vec<RefFun>,
+    /// Places where the value leaves a function, in the order they were found
+    pub sinks: Vec<TaintSink>,
+}
+
+impl Bytecode {
+    /// Follow a value across calls, returns, field and global stores, see the [module docs](crate::analysis::taint)
+    pub fn taint(&self, source: TaintSource) -> TaintReport {
+        let mut tracker = Tracker {
+            code: self,
+            usage: UsageIndex::new(self),
+            ssas: HashMap::new(),
+            tainted: HashMap::new(),
+            todo: Vec::new(),
+            sinks: Vec::new(),
+        };
+        match source {
+            TaintSource::Register { fun, pos, reg } => tracker.todo.push((fun, pos, reg)),
+            TaintSource::Global(global) => tracker.seed_global_reads(global),
+            TaintSource::CallResult(fun) => tracker.seed_call_results(fun),
+        }
+        tracker.run();
+
+        let mut functions: Vec<RefFun> = tracker
+            .tainted
+            .into_iter()
+            .filter(|(_, values)| !values.is_empty())
+            .map(|(fun, _)| fun)
+            .collect();
+        functions.sort_unstable();
+        TaintReport {
+            functions,
+            sinks: tracker.sinks,
+        }
+    }
+}
+
+struct Tracker<'a> {
+    code: &'a Bytecode,
+    usage: UsageIndex,
+    ssas: HashMap<RefFun, Ssa>,
+    tainted: HashMap<RefFun, HashSet<Value>>,
+    /// Registers to taint, as in [TaintSource::Register]
+    todo: Vec<(RefFun, Option<usize>, Reg)>,
+    sinks: Vec<TaintSink>,
+}
+
+impl Tracker<'_> {
+    fn run(&mut self) {
+        while let Some((fun, pos, reg)) = self.todo.pop() {
+            let Ok(f) = fun.try_as_fn(self.code) else {
+                continue;
+            };
+            let ssa = self.ssas.entry(fun).or_insert_with(|| ssa(f));
+            let value = match pos {
+                Some(pos) => ssa.writes.get(pos).copied().flatten(),
+                None => Some(Value(reg.0 as usize))
+                    .filter(|v| ssa.values.get(v.0).is_some_and(|i| i.def == Def::Entry)),
+            };
+            let tainted = self.tainted.entry(fun).or_default();
+            if !value.is_some_and(|v| tainted.insert(v)) {
+                continue;
+            }
+            propagate(ssa, tainted);
+            for sink in sinks(self.code, f, ssa, tainted) {
+                if !self.sinks.contains(&sink) {
+                    self.sinks.push(sink);
+                    self.follow(sink);
+                }
+            }
+        }
+    }
+
+    /// Taint where the value goes after leaving a function
+    fn follow(&mut self, sink: TaintSink) {
+        match sink {
+            TaintSink::Call { fun, arg, .. } => self.todo.push((fun, None, Reg(arg as u32))),
+            TaintSink::Field { ty, field, .. } => {
+                for r in self.code.field_references(ty, field) {
+                    if r.access == FieldAccess::Read {
+                        self.seed_result(r.loc);
+                    }
+                }
+            }
+            TaintSink::Global { global, .. } => self.seed_global_reads(global),
+            TaintSink::Return { loc } => self.seed_call_results(loc.fun),
+            TaintSink::DynField { .. } => {}
+        }
+    }
+
+    /// Taint the register written by an instruction
+    fn seed_result(&mut self, loc: CodeLocation) {
+        if let Some(dst) = loc
+            .fun
+            .as_fn(self.code)
+            .and_then(|f| f.ops.get(loc.op))
+            .and_then(Opcode::written_reg)
+        {
+            self.todo.push((loc.fun, Some(loc.op), dst));
+        }
+    }
+
+    fn seed_global_reads(&mut self, global: RefGlobal) {
+        let code = self.code;
+        for &loc in &self.usage[global] {
+            if let Some(Opcode::GetGlobal { dst, .. }) = loc.fun.as_fn(code).map(|f| &f.ops[loc.op])
+            {
+                self.todo.push((loc.fun, Some(loc.op), *dst));
+            }
+        }
+    }
+
+    fn seed_call_results(&mut self, fun: RefFun) {
+        let code = self.code;
+        for &loc in &self.usage[fun] {
+            let Some(f) = loc.fun.as_fn(code) else {
+                continue;
+            };
+            let op = &f.ops[loc.op];
+            // Closures of the function are also referencing it
+            if f.callee(code, op).is_some_and(|(callee, _)| callee == fun) {
+                if let Some(dst) = op.written_reg() {
+                    self.todo.push((loc.fun, Some(loc.op), dst));
+                }
+            }
+        }
+    }
+}
+
+/// Taint every value computed from a tainted value
+fn propagate(ssa: &Ssa, tainted: &mut HashSet<Value>) {
+    loop {
+        let mut changed = false;
+        for (reads, write) in ssa.reads.iter().zip(&ssa.writes) {
+            if let Some(w) = *write {
+                if !tainted.contains(&w) && reads.iter().any(|v| tainted.contains(v)) {
+                    tainted.insert(w);
+                    changed = true;
+                }
+            }
+        }
+        for phi in ssa.phis.iter().flatten() {
+            if !tainted.contains(&phi.dst) && phi.args.iter().any(|(_, v)| tainted.contains(v)) {
+                tainted.insert(phi.dst);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// The instructions of a function making a tainted value leave it
+fn sinks(code: &Bytecode, f: &Function, ssa: &Ssa, tainted: &HashSet<Value>) -> Vec<TaintSink> {
+    let mut sinks = Vec::new();
+    for (i, op) in f.ops() {
+        // Values read are in the same order as the registers
+        let regs = op.read_regs();
+        let is_tainted = |reg: Reg| {
+            regs.iter()
+                .zip(&ssa.reads[i])
+                .any(|(&r, v)| r == reg && tainted.contains(v))
+        };
+        let loc = CodeLocation {
+            fun: f.findex,
+            op: i,
+        };
+        match *op {
+            Opcode::SetField { obj, field, src } if is_tainted(src) => {
+                sinks.push(TaintSink::Field {
+                    loc,
+                    ty: f[obj],
+                    field,
+                });
+            }
+            Opcode::SetThis { field, src } if is_tainted(src) => {
+                sinks.push(TaintSink::Field {
+                    loc,
+                    ty: f.regs[0],
+                    field,
+                });
+            }
+            Opcode::SetGlobal { global, src } if is_tainted(src) => {
+                sinks.push(TaintSink::Global { loc, global });
+            }
+            Opcode::DynSet { field, src, .. } if is_tainted(src) => {
+                sinks.push(TaintSink::DynField { loc, name: field });
+            }
+            Opcode::Ret { ret } if is_tainted(ret) => sinks.push(TaintSink::Return { loc }),
+            _ => {
+                let Some((fun, args)) = f.callee(code, op) else {
+                    continue;
+                };
+                // The callee receives `this` in its first register
+                let offset = usize::from(matches!(op, Opcode::CallThis { .. }));
+                for (arg, &reg) in args.iter().enumerate() {
+                    if is_tainted(reg) {
+                        sinks.push(TaintSink::Call {
+                            loc,
+                            fun,
+                            arg: arg + offset,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    sinks
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::taint::{TaintSink, TaintSource};
+    use crate::analysis::usage::CodeLocation;
+    use crate::builder::Builder;
+    use crate::opcodes::Opcode;
+    use crate::types::{RefFun, RefGlobal, Reg, Type};
+
+    #[test]
+    fn taint() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let global = b.global(int);
+        let main = b.function("", &[int], int, &[int; 2], vec![]);
+        // check(r0) : return r0
+        let check = b.function(
+            "",
+            &[int],
+            int,
+            &[int; 2],
+            vec![Opcode::Ret { ret: Reg(0) }],
+        );
+        // Reads the global and ignores it
+        b.function(
+            "",
+            &[int],
+            int,
+            &[int; 2],
+            vec![
+                Opcode::GetGlobal {
+                    dst: Reg(0),
+                    global,
+                },
+                Opcode::Ret { ret: Reg(1) },
+            ],
+        );
+        let read = b.native("std", "read", &[int], int);
+        // r0 = read(); r1 = r0 + r2; r2 = check(r1); $g = r2; return r2
+        b.function_mut(main).ops = vec![
+            Opcode::Call0 {
+                dst: Reg(0),
+                fun: read,
+            },
+            Opcode::Add {
+                dst: Reg(1),
+                a: Reg(0),
+                b: Reg(2),
+            },
+            Opcode::Call1 {
+                dst: Reg(2),
+                fun: check,
+                arg0: Reg(1),
+            },
+            Opcode::SetGlobal {
+                global,
+                src: Reg(2),
+            },
+            Opcode::Ret { ret: Reg(0) },
+        ];
+        let code = b.build().unwrap();
+        let loc = |fun, op| CodeLocation {
+            fun: RefFun(fun),
+            op,
+        };
+
+        let report = code.taint(TaintSource::CallResult(read));
+        assert_eq!(report.functions, [RefFun(0), RefFun(1), RefFun(2)]);
+        assert_eq!(
+            report.sinks,
+            [
+                TaintSink::Call {
+                    loc: loc(0, 2),
+                    fun: RefFun(1),
+                    arg: 0
+                },
+                TaintSink::Global {
+                    loc: loc(0, 3),
+                    global: RefGlobal(0)
+                },
+                TaintSink::Return { loc: loc(0, 4) },
+                TaintSink::Return { loc: loc(1, 0) },
+            ]
+        );
+
+        // The argument of check only flows to its return, and back in the caller
+        let report = code.taint(TaintSource::Register {
+            fun: RefFun(1),
+            pos: None,
+            reg: Reg(0),
+        });
+        assert_eq!(report.functions, [RefFun(0), RefFun(1), RefFun(2)]);
+        assert_eq!(report.sinks[0], TaintSink::Return { loc: loc(1, 0) });
+        assert!(!report.sinks.contains(&TaintSink::Return { loc: loc(0, 4) }));
+    }
+}
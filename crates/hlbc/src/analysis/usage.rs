@@ -5,12 +5,13 @@
 
 use std::ops::Index;
 
+use crate::cancel::CancellationToken;
 use crate::opcodes::Opcode;
 use crate::types::{
     EnumConstruct, FunPtr, Function, ObjField, ObjProto, RefEnumConstruct, RefField, RefFun,
-    RefString, RefType, Reg, Type, TypeFun, TypeObj,
+    RefGlobal, RefString, RefType, Reg, Type, TypeFun, TypeObj,
 };
-use crate::Bytecode;
+use crate::{Bytecode, Result};
 
 /// The different ways a function can be used
 #[derive(Debug, Clone)]
@@ -245,10 +246,212 @@ pub fn usage_report(code: &Bytecode) -> FullUsageReport {
     report
 }
 
+/// A place in the code referencing a bytecode element
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct CodeLocation {
+    pub fun: RefFun,
+    /// Index of the instruction in the function
+    pub op: usize,
+}
+
+/// Cross-references from the code : every instruction referencing a string, a global, a type or a function.
+///
+/// Unlike [FullUsageReport] which also looks at declarations, this only looks at instructions.
+/// The index is built once, queries are then a lookup with the [Index] trait.
+/// Locations are ordered as the functions and instructions in the bytecode.
+#[derive(Debug, Clone, Default)]
+pub struct UsageIndex {
+    strings: Vec<Vec<CodeLocation>>,
+    globals: Vec<Vec<CodeLocation>>,
+    types: Vec<Vec<CodeLocation>>,
+    functions: Vec<Vec<CodeLocation>>,
+}
+
+impl UsageIndex {
+    pub fn new(code: &Bytecode) -> Self {
+        // A token that is never cancelled
+        Self::new_cancellable(code, &CancellationToken::new()).unwrap()
+    }
+
+    /// Same as [UsageIndex::new] but stops with [Error::Cancelled](crate::Error::Cancelled) when `cancel` is
+    /// cancelled. The token is checked between functions.
+    pub fn new_cancellable(code: &Bytecode, cancel: &CancellationToken) -> Result<Self> {
+        let mut index = Self {
+            strings: vec![Vec::new(); code.strings.len()],
+            globals: vec![Vec::new(); code.globals.len()],
+            types: vec![Vec::new(); code.types.len()],
+            functions: vec![Vec::new(); code.findex_max().max(code.functions.len())],
+        };
+        for f in &code.functions {
+            cancel.check()?;
+            for (i, op) in f.ops() {
+                index.index_op(
+                    code,
+                    f,
+                    op,
+                    CodeLocation {
+                        fun: f.findex,
+                        op: i,
+                    },
+                );
+            }
+        }
+        Ok(index)
+    }
+
+    fn index_op(&mut self, code: &Bytecode, f: &Function, op: &Opcode, loc: CodeLocation) {
+        match op {
+            Opcode::Call0 { fun, .. }
+            | Opcode::Call1 { fun, .. }
+            | Opcode::Call2 { fun, .. }
+            | Opcode::Call3 { fun, .. }
+            | Opcode::Call4 { fun, .. }
+            | Opcode::CallN { fun, .. }
+            | Opcode::StaticClosure { fun, .. }
+            | Opcode::InstanceClosure { fun, .. } => self.functions[fun.0].push(loc),
+            Opcode::CallMethod { args, field, .. } => {
+                if let Some(proto) = f[args[0]].method(field.0, code) {
+                    self.functions[proto.findex.0].push(loc);
+                }
+            }
+            Opcode::CallThis { field, .. } => {
+                if let Some(proto) = f[Reg(0)].method(field.0, code) {
+                    self.functions[proto.findex.0].push(loc);
+                }
+            }
+
+            Opcode::String { ptr, .. } => self.strings[ptr.0].push(loc),
+            Opcode::DynGet { field, .. } | Opcode::DynSet { field, .. } => {
+                self.strings[field.0].push(loc)
+            }
+
+            Opcode::GetGlobal { global, .. } | Opcode::SetGlobal { global, .. } => {
+                self.globals[global.0].push(loc);
+                // Since bytecode v5, string literals are globals initialized with a constant
+                if let Some(ptr) = code.global_string(*global) {
+                    self.strings[ptr.0].push(loc);
+                }
+            }
+
+            Opcode::Type { ty, .. } => self.types[ty.0].push(loc),
+            Opcode::New { dst }
+            | Opcode::SafeCast { dst, .. }
+            | Opcode::UnsafeCast { dst, .. }
+            | Opcode::ToVirtual { dst, .. }
+            | Opcode::MakeEnum { dst, .. }
+            | Opcode::EnumAlloc { dst, .. } => self.types[f[*dst].0].push(loc),
+            _ => {}
+        }
+    }
+
+    /// The functions referencing an element, each listed once
+    pub fn functions_using<I>(&self, index: I) -> Vec<RefFun>
+    where
+        Self: Index<I, Output = [CodeLocation]>,
+    {
+        let mut funs: Vec<RefFun> = self[index].iter().map(|loc| loc.fun).collect();
+        funs.dedup();
+        funs
+    }
+}
+
+/// Whether an instruction reads or writes a field
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FieldAccess {
+    /// `Field` or `GetThis`
+    Read,
+    /// `SetField` or `SetThis`
+    Write,
+}
+
+/// An instruction accessing a field, see [Bytecode::field_references]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FieldReference {
+    pub loc: CodeLocation,
+    pub access: FieldAccess,
+}
+
+impl Bytecode {
+    /// Every instruction reading or writing a field of a class or a struct, ordered as the functions and instructions
+    /// in the bytecode. `field` counts the inherited fields, like in the opcodes.
+    ///
+    /// Accesses through a subclass are included, and so are accesses through a parent class declaring the field : the
+    /// object may be an instance of `ty`.
+    pub fn field_references(&self, ty: RefType, field: RefField) -> Vec<FieldReference> {
+        let has_field = |t: RefType| t.as_obj(self).is_some_and(|obj| field.0 < obj.fields.len());
+        if !has_field(ty) {
+            return Vec::new();
+        }
+        let related =
+            |t: RefType| self.is_subclass(t, ty) || (has_field(t) && self.is_subclass(ty, t));
+        let mut refs = Vec::new();
+        for f in &self.functions {
+            let this = f.regs.first().copied();
+            for (i, op) in f.ops() {
+                let (obj, accessed, access) = match *op {
+                    Opcode::Field { obj, field, .. } => (Some(f[obj]), field, FieldAccess::Read),
+                    Opcode::SetField { obj, field, .. } => {
+                        (Some(f[obj]), field, FieldAccess::Write)
+                    }
+                    Opcode::GetThis { field, .. } => (this, field, FieldAccess::Read),
+                    Opcode::SetThis { field, .. } => (this, field, FieldAccess::Write),
+                    _ => continue,
+                };
+                if accessed == field && obj.is_some_and(related) {
+                    refs.push(FieldReference {
+                        loc: CodeLocation {
+                            fun: f.findex,
+                            op: i,
+                        },
+                        access,
+                    });
+                }
+            }
+        }
+        refs
+    }
+}
+
+impl Index<RefString> for UsageIndex {
+    type Output = [CodeLocation];
+
+    fn index(&self, index: RefString) -> &Self::Output {
+        self.strings.index(index.0)
+    }
+}
+
+impl Index<RefGlobal> for UsageIndex {
+    type Output = [CodeLocation];
+
+    fn index(&self, index: RefGlobal) -> &Self::Output {
+        self.globals.index(index.0)
+    }
+}
+
+impl Index<RefType> for UsageIndex {
+    type Output = [CodeLocation];
+
+    fn index(&self, index: RefType) -> &Self::Output {
+        self.types.index(index.0)
+    }
+}
+
+impl Index<RefFun> for UsageIndex {
+    type Output = [CodeLocation];
+
+    fn index(&self, index: RefFun) -> &Self::Output {
+        self.functions.index(index.0)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::analysis::usage::FullUsageReport;
-    use crate::Bytecode;
+    use crate::analysis::usage::{CodeLocation, FieldAccess, FullUsageReport, UsageIndex};
+    use crate::builder::Builder;
+    use crate::cancel::CancellationToken;
+    use crate::opcodes::Opcode;
+    use crate::types::{RefField, Reg, Type};
+    use crate::{Bytecode, Error};
 
     #[test]
     fn list_fun() {
@@ -265,4 +468,117 @@ mod tests {
         usage.compute_usage_all(&code);
         dbg!(usage);
     }
+
+    #[test]
+    fn usage_index() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        let int = b.ty(Type::I32);
+        let global = b.global(int);
+        let hello = b.string("hello");
+        let callee = b.function(
+            "",
+            &[],
+            void,
+            &[int],
+            vec![Opcode::GetGlobal {
+                dst: Reg(0),
+                global,
+            }],
+        );
+        let caller = b.function(
+            "",
+            &[],
+            void,
+            &[int],
+            vec![
+                Opcode::String {
+                    dst: Reg(0),
+                    ptr: hello,
+                },
+                Opcode::Call0 {
+                    dst: Reg(0),
+                    fun: callee,
+                },
+                Opcode::Call0 {
+                    dst: Reg(0),
+                    fun: callee,
+                },
+            ],
+        );
+        let code = b.build().unwrap();
+
+        let index = UsageIndex::new(&code);
+        assert_eq!(index[hello], [CodeLocation { fun: caller, op: 0 }]);
+        assert_eq!(index[callee].len(), 2);
+        assert_eq!(index.functions_using(callee), vec![caller]);
+        assert_eq!(index.functions_using(global), vec![callee]);
+        assert!(index[caller].is_empty());
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert!(matches!(
+            UsageIndex::new_cancellable(&code, &cancel),
+            Err(Error::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn field_refs() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        // A { x }, B extends A { y }, C { z }
+        let a = b.class("A", None, &[("x", void)]);
+        let b_ = b.class("B", Some(a), &[("y", void)]);
+        let c = b.class("C", None, &[("z", void)]);
+        b.function(
+            "",
+            &[],
+            void,
+            &[b_, a, c, void],
+            vec![
+                Opcode::Field {
+                    dst: Reg(3),
+                    obj: Reg(1),
+                    field: RefField(0),
+                },
+                Opcode::SetField {
+                    obj: Reg(0),
+                    field: RefField(1),
+                    src: Reg(3),
+                },
+                Opcode::GetThis {
+                    dst: Reg(3),
+                    field: RefField(0),
+                },
+                Opcode::Field {
+                    dst: Reg(3),
+                    obj: Reg(2),
+                    field: RefField(0),
+                },
+                Opcode::SetThis {
+                    field: RefField(0),
+                    src: Reg(3),
+                },
+            ],
+        );
+        let code = b.build().unwrap();
+
+        let refs = |ty, field| {
+            code.field_references(ty, RefField(field))
+                .into_iter()
+                .map(|r| (r.loc.op, r.access))
+                .collect::<Vec<_>>()
+        };
+        let x = [
+            (0, FieldAccess::Read),
+            (2, FieldAccess::Read),
+            (4, FieldAccess::Write),
+        ];
+        assert_eq!(refs(b_, 0), x);
+        assert_eq!(refs(a, 0), x);
+        assert_eq!(refs(b_, 1), [(1, FieldAccess::Write)]);
+        assert!(refs(a, 1).is_empty());
+        assert_eq!(refs(c, 0), [(3, FieldAccess::Read)]);
+    }
 }
@@ -0,0 +1,1297 @@
+//! Parse the disassembly of functions back into [Opcode]s and [Function]s, to patch a function in text form and
+//! assemble it again.
+//!
+//! The syntax is the output of the disassembler, [Function::display] with [EnhancedFmt] :
+//!
+//! ```text
+//! fn main@12 (i32) -> void (3 regs, 4 ops)
+//!     reg0  i32
+//!     reg1  String
+//!     reg2  void
+//!   0: Int         reg0 = 5
+//!   1: String      reg1 = "hello"
+//!   2: JFalse      if reg0 == false jump to 4
+//!   3: Call1       reg2 = log@3(reg1)
+//!   4: Ret         reg2
+//! ```
+//!
+//! References are written the way the disassembler resolves them : constants by value, strings between quotes,
+//! functions as `name@findex`, fields and enum constructs by name and jumps by the position they land on. Lines
+//! may start with the source file and line of the opcode, and comments start with a semicolon.
+//!
+//! Opcodes without a readable form, like `Switch`, are displayed with their raw operands in declaration order. This
+//! raw form is accepted for every opcode, it is the one of [Function::display_annotated] :
+//! - registers : `reg0`
+//! - constant pool and function references : `@12`
+//! - fields and enum constructs : `<field3>`, `<construct3>`
+//! - inline values and jump offsets, relative to the next opcode : `true`, `-3`
+//! - lists : `[reg0 reg1]`
+//!
+//! Constants and types are found back by value, identical entries of a pool aren't told apart. Strings must already
+//! be in the pool, see [Bytecode::add_string].
+
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use crate::fmt::{fmt, EnhancedFmt};
+use crate::opcodes::Opcode;
+use crate::types::{
+    Function, JumpOffset, RefBytes, RefEnumConstruct, RefField, RefFloat, RefFun, RefGlobal,
+    RefInt, RefString, RefType, Reg, Type,
+};
+use crate::{Bytecode, Error, Result};
+
+impl Opcode {
+    /// Display this opcode with its raw operands. See [crate::asm].
+    pub fn display_asm(&self) -> impl Display + '_ {
+        fmt(move |f| {
+            f.write_str(self.name())?;
+            self.write_asm_operands(f)
+        })
+    }
+}
+
+/// Parse an opcode as displayed by the disassembler, like `Mov reg1 = reg0`, or with its raw operands.
+///
+/// `parent` is the function the opcode is in, for the types of the registers, and `pos` its position in the
+/// function, for the jumps.
+pub fn parse_opcode(code: &Bytecode, parent: &Function, pos: usize, text: &str) -> Result<Opcode> {
+    Resolver::new(code).opcode(parent, pos, 1, strip_comment(text).trim())
+}
+
+/// Parse a function as displayed by the disassembler or by [Function::display_annotated].
+///
+/// The name, parent and variable names are those of the function with the same findex in `code`. The register
+/// list can be left out to keep the registers of that function.
+pub fn parse_function(code: &Bytecode, src: &str) -> Result<Function> {
+    let mut functions = parse_functions(code, src)?;
+    match functions.len() {
+        1 => Ok(functions.remove(0)),
+        n => Err(Error::InvalidAsm {
+            line: 1,
+            msg: format!("Expected one function, found {n}"),
+        }),
+    }
+}
+
+/// Parse a listing of functions, see [parse_function]. Each function starts with its `fn` header.
+pub fn parse_functions(code: &Bytecode, src: &str) -> Result<Vec<Function>> {
+    let resolver = Resolver::new(code);
+    let mut functions = Vec::new();
+    let mut current: Option<Listing> = None;
+    for (i, text) in src.lines().enumerate() {
+        let line = i + 1;
+        let text = strip_comment(text).trim();
+        if text.is_empty() {
+            continue;
+        }
+        if let Some(header) = text.strip_prefix("fn ") {
+            if let Some(listing) = current.take() {
+                functions.push(listing.finish()?);
+            }
+            current = Some(resolver.header(line, header)?);
+            continue;
+        }
+        let Some(listing) = current.as_mut() else {
+            return Err(error(line, "Expected a 'fn' header".to_owned()));
+        };
+        if let Some(reg) = register_line(text) {
+            listing.register(&resolver, line, reg)?;
+        } else if let Some((debug, pos, op)) = opcode_line(text) {
+            listing.opcode(&resolver, line, debug, pos, op)?;
+        } else {
+            return Err(error(
+                line,
+                format!("Expected a register or an opcode, got '{text}'"),
+            ));
+        }
+    }
+    if let Some(listing) = current {
+        functions.push(listing.finish()?);
+    }
+    Ok(functions)
+}
+
+fn error(line: usize, msg: String) -> Error {
+    Error::InvalidAsm { line, msg }
+}
+
+/// Remove the comment at the end of a line, semicolons in strings don't start a comment
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// A line of the register list like `reg0  i32`, split in the register and its type
+fn register_line(text: &str) -> Option<(usize, &str)> {
+    let (reg, ty) = text.split_once(char::is_whitespace)?;
+    Some((reg.strip_prefix("reg")?.parse().ok()?, ty.trim()))
+}
+
+/// A line of the opcode list like `Main.hx:12    3: Ret reg0`, split in the debug position, the position of the
+/// opcode and the opcode
+fn opcode_line(text: &str) -> Option<(Option<&str>, usize, &str)> {
+    // The position is the first number followed by ': '
+    let mut start = 0;
+    while let Some(i) = text[start..].find(": ") {
+        let colon = start + i;
+        let num = text[..colon]
+            .rfind(char::is_whitespace)
+            .map_or(0, |i| i + 1);
+        if let Ok(pos) = text[num..colon].parse() {
+            let debug = text[..num].trim();
+            return Some((
+                (!debug.is_empty()).then_some(debug),
+                pos,
+                &text[colon + 2..],
+            ));
+        }
+        start = colon + 2;
+    }
+    None
+}
+
+/// A function being parsed
+struct Listing {
+    f: Function,
+    /// Line of the header
+    line: usize,
+    /// The registers are listed, instead of being those of the existing function
+    regs_listed: bool,
+    debug: Vec<(usize, usize)>,
+}
+
+impl Listing {
+    fn register(
+        &mut self,
+        resolver: &Resolver,
+        line: usize,
+        (reg, ty): (usize, &str),
+    ) -> Result<()> {
+        if !self.f.ops.is_empty() {
+            return Err(error(
+                line,
+                "Registers must be listed before the opcodes".to_owned(),
+            ));
+        }
+        if !self.regs_listed {
+            self.regs_listed = true;
+            self.f.regs.clear();
+        }
+        if reg != self.f.regs.len() {
+            return Err(error(line, format!("Expected reg{}", self.f.regs.len())));
+        }
+        self.f.regs.push(resolver.ty(line, ty)?);
+        Ok(())
+    }
+
+    fn opcode(
+        &mut self,
+        resolver: &Resolver,
+        line: usize,
+        debug: Option<&str>,
+        pos: usize,
+        op: &str,
+    ) -> Result<()> {
+        if pos != self.f.ops.len() {
+            return Err(error(line, format!("Expected opcode {}", self.f.ops.len())));
+        }
+        if let Some(debug) = debug {
+            self.debug.push(resolver.debug_position(line, debug)?);
+        }
+        let op = resolver.opcode(&self.f, pos, line, op.trim())?;
+        self.f.ops.push(op);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Function> {
+        if !self.debug.is_empty() {
+            if self.debug.len() != self.f.ops.len() {
+                return Err(error(
+                    self.line,
+                    "Some opcodes have a source position and others don't".to_owned(),
+                ));
+            }
+            self.f.debug_info = Some(self.debug);
+        }
+        Ok(self.f)
+    }
+}
+
+/// Finds the elements of the bytecode back from the way the disassembler displays them
+struct Resolver<'a> {
+    code: &'a Bytecode,
+    /// Types displayed by themselves, in the function header and the register list
+    types: OnceCell<HashMap<String, RefType>>,
+    /// Types displayed as a reference, by the `Type` opcode
+    type_refs: OnceCell<HashMap<String, RefType>>,
+    strings: OnceCell<HashMap<&'a str, RefString>>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(code: &'a Bytecode) -> Self {
+        Self {
+            code,
+            types: OnceCell::new(),
+            type_refs: OnceCell::new(),
+            strings: OnceCell::new(),
+        }
+    }
+
+    /// The header of a function like `name@12 (i32) -> void (3 regs, 4 ops)`
+    fn header(&self, line: usize, header: &str) -> Result<Listing> {
+        let (fun, ty) = header
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| error(line, "Expected 'fn name@findex type'".to_owned()))?;
+        let findex = self.fun(line, fun)?;
+        let ty = ty.trim();
+        // Only in the disassembly
+        let ty = match ty.rsplit_once(" (") {
+            Some((ty, counts)) if counts.ends_with(" ops)") => ty,
+            _ => ty,
+        };
+        let existing = self.code.functions.iter().find(|f| f.findex == findex);
+        Ok(Listing {
+            f: Function {
+                t: self.ty(line, ty)?,
+                findex,
+                regs: existing.map(|f| f.regs.clone()).unwrap_or_default(),
+                ops: Vec::new(),
+                debug_info: None,
+                assigns: existing.and_then(|f| f.assigns.clone()),
+                name: existing.map_or(RefString(0), |f| f.name),
+                parent: existing.and_then(|f| f.parent),
+            },
+            line,
+            regs_listed: false,
+            debug: Vec::new(),
+        })
+    }
+
+    /// A source position like `Main.hx:12`
+    fn debug_position(&self, line: usize, text: &str) -> Result<(usize, usize)> {
+        text.rsplit_once(':')
+            .and_then(|(file, l)| {
+                let file = self
+                    .code
+                    .debug_files
+                    .as_ref()?
+                    .iter()
+                    .position(|f| f == file)?;
+                Some((file, l.parse().ok()?))
+            })
+            .ok_or_else(|| error(line, format!("Unknown source position '{text}'")))
+    }
+
+    fn opcode(&self, parent: &Function, pos: usize, line: usize, text: &str) -> Result<Opcode> {
+        let (name, operands) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let operands = Operands {
+            resolver: self,
+            parent,
+            pos,
+            line,
+            text: operands.trim(),
+        };
+        let raw = || {
+            let mut lex = Lexer::new(line, operands.text);
+            let op = Opcode::parse_asm_operands(name, &mut lex)?;
+            lex.expect_end()?;
+            Ok(op)
+        };
+        match operands.readable(name) {
+            Ok(Some(op)) => Ok(op),
+            Ok(None) => raw(),
+            Err(e) => raw().map_err(|_| e),
+        }
+    }
+
+    fn ty(&self, line: usize, text: &str) -> Result<RefType> {
+        let types = self.types.get_or_init(|| {
+            let mut types = HashMap::new();
+            for (i, t) in self.code.types.iter().enumerate() {
+                let name = t.display::<EnhancedFmt>(self.code).to_string();
+                types.entry(name).or_insert(RefType(i));
+            }
+            types
+        });
+        types
+            .get(text)
+            .copied()
+            .ok_or_else(|| error(line, format!("Unknown type '{text}'")))
+    }
+
+    fn type_ref(&self, line: usize, text: &str) -> Result<RefType> {
+        let types = self.type_refs.get_or_init(|| {
+            let mut types = HashMap::new();
+            for i in 0..self.code.types.len() {
+                let name = RefType(i).display::<EnhancedFmt>(self.code).to_string();
+                types.entry(name).or_insert(RefType(i));
+            }
+            types
+        });
+        types
+            .get(text)
+            .copied()
+            .ok_or_else(|| error(line, format!("Unknown type '{text}'")))
+    }
+
+    fn string(&self, line: usize, text: &str) -> Result<RefString> {
+        let strings = self.strings.get_or_init(|| {
+            let mut strings = HashMap::new();
+            for (i, s) in self.code.strings.iter().enumerate() {
+                strings.entry(&**s).or_insert(RefString(i));
+            }
+            strings
+        });
+        strings
+            .get(text)
+            .copied()
+            .ok_or_else(|| error(line, format!("Unknown string \"{text}\"")))
+    }
+
+    /// A function like `name@12`
+    fn fun(&self, line: usize, text: &str) -> Result<RefFun> {
+        text.rsplit_once('@')
+            .and_then(|(_, findex)| findex.parse().ok())
+            .map(RefFun)
+            .ok_or_else(|| error(line, format!("Expected 'name@findex', got '{text}'")))
+    }
+}
+
+/// The operands of an opcode, after its name
+struct Operands<'r, 'c, 't> {
+    resolver: &'r Resolver<'c>,
+    parent: &'r Function,
+    pos: usize,
+    line: usize,
+    text: &'t str,
+}
+
+impl<'t> Operands<'_, '_, 't> {
+    /// The opcode displayed in its readable form, `None` if it has none
+    fn readable(&self, name: &str) -> Result<Option<Opcode>> {
+        let op = match name {
+            "Mov" => {
+                let [dst, src] = self.split("{} = {}")?;
+                Opcode::Mov {
+                    dst: self.reg(dst)?,
+                    src: self.reg(src)?,
+                }
+            }
+            "Int" => {
+                let [dst, value] = self.split("{} = {}")?;
+                Opcode::Int {
+                    dst: self.reg(dst)?,
+                    ptr: self.int(value)?,
+                }
+            }
+            "Float" => {
+                let [dst, value] = self.split("{} = {}")?;
+                Opcode::Float {
+                    dst: self.reg(dst)?,
+                    ptr: self.float(value)?,
+                }
+            }
+            "Bool" => {
+                let [dst, value] = self.split("{} = {}")?;
+                Opcode::Bool {
+                    dst: self.reg(dst)?,
+                    value: self.parse(value)?,
+                }
+            }
+            "String" => {
+                let [dst, value] = self.split("{} = \"{}\"")?;
+                Opcode::String {
+                    dst: self.reg(dst)?,
+                    ptr: self.resolver.string(self.line, value)?,
+                }
+            }
+            "Null" => {
+                let [dst] = self.split("{} = null")?;
+                Opcode::Null {
+                    dst: self.reg(dst)?,
+                }
+            }
+            "Add" | "Sub" | "Mul" | "SDiv" | "UDiv" | "SMod" | "UMod" | "Shl" | "SShr" | "UShr"
+            | "And" | "Or" | "Xor" => {
+                let symbol = match name {
+                    "Add" => "+",
+                    "Sub" => "-",
+                    "Mul" => "*",
+                    "SDiv" | "UDiv" => "/",
+                    "SMod" | "UMod" => "%",
+                    "Shl" => "<<",
+                    "SShr" | "UShr" => ">>",
+                    "And" => "&",
+                    "Or" => "|",
+                    _ => "^",
+                };
+                let [dst, a, b] = self.split(&format!("{{}} = {{}} {symbol} {{}}"))?;
+                let (dst, a, b) = (self.reg(dst)?, self.reg(a)?, self.reg(b)?);
+                match name {
+                    "Add" => Opcode::Add { dst, a, b },
+                    "Sub" => Opcode::Sub { dst, a, b },
+                    "Mul" => Opcode::Mul { dst, a, b },
+                    "SDiv" => Opcode::SDiv { dst, a, b },
+                    "UDiv" => Opcode::UDiv { dst, a, b },
+                    "SMod" => Opcode::SMod { dst, a, b },
+                    "UMod" => Opcode::UMod { dst, a, b },
+                    "Shl" => Opcode::Shl { dst, a, b },
+                    "SShr" => Opcode::SShr { dst, a, b },
+                    "UShr" => Opcode::UShr { dst, a, b },
+                    "And" => Opcode::And { dst, a, b },
+                    "Or" => Opcode::Or { dst, a, b },
+                    _ => Opcode::Xor { dst, a, b },
+                }
+            }
+            "Neg" => {
+                let [dst, src] = self.split("{} = -{}")?;
+                Opcode::Neg {
+                    dst: self.reg(dst)?,
+                    src: self.reg(src)?,
+                }
+            }
+            "Not" => {
+                let [dst, src] = self.split("{} = !{}")?;
+                Opcode::Not {
+                    dst: self.reg(dst)?,
+                    src: self.reg(src)?,
+                }
+            }
+            "Incr" => {
+                let [dst] = self.split("{}++")?;
+                Opcode::Incr {
+                    dst: self.reg(dst)?,
+                }
+            }
+            "Decr" => {
+                let [dst] = self.split("{}--")?;
+                Opcode::Decr {
+                    dst: self.reg(dst)?,
+                }
+            }
+            "Call0" | "Call1" | "Call2" | "Call3" | "Call4" | "CallN" => {
+                let [dst, call] = self.split("{} = {}")?;
+                // Function names may contain parentheses, the arguments start after the findex
+                let open = call
+                    .rfind('@')
+                    .and_then(|at| Some(at + call[at..].find('(')?))
+                    .ok_or_else(|| self.error("Expected 'name@findex(args)'".to_owned()))?;
+                let args = call[open + 1..]
+                    .strip_suffix(')')
+                    .ok_or_else(|| self.error("Expected ')'".to_owned()))?;
+                let (dst, fun, args) = (
+                    self.reg(dst)?,
+                    self.resolver.fun(self.line, &call[..open])?,
+                    self.regs(args)?,
+                );
+                match (name, args.as_slice()) {
+                    ("Call0", []) => Opcode::Call0 { dst, fun },
+                    ("Call1", &[arg0]) => Opcode::Call1 { dst, fun, arg0 },
+                    ("Call2", &[arg0, arg1]) => Opcode::Call2 {
+                        dst,
+                        fun,
+                        arg0,
+                        arg1,
+                    },
+                    ("Call3", &[arg0, arg1, arg2]) => Opcode::Call3 {
+                        dst,
+                        fun,
+                        arg0,
+                        arg1,
+                        arg2,
+                    },
+                    ("Call4", &[arg0, arg1, arg2, arg3]) => Opcode::Call4 {
+                        dst,
+                        fun,
+                        arg0,
+                        arg1,
+                        arg2,
+                        arg3,
+                    },
+                    ("CallN", _) => Opcode::CallN { dst, fun, args },
+                    _ => return Err(self.error(format!("Wrong number of arguments for {name}"))),
+                }
+            }
+            "CallMethod" => {
+                let [dst, obj, field, args] = self.split("{} = {}.{}({})")?;
+                let obj = self.reg(obj)?;
+                let mut all = vec![obj];
+                all.extend(self.regs(args)?);
+                Opcode::CallMethod {
+                    dst: self.reg(dst)?,
+                    field: self.field(obj, field)?,
+                    args: all,
+                }
+            }
+            "CallThis" => {
+                let [dst, field, args] = self.split("{} = reg0.{}({})")?;
+                Opcode::CallThis {
+                    dst: self.reg(dst)?,
+                    field: self.field(Reg(0), field)?,
+                    args: self.regs(args)?,
+                }
+            }
+            "CallClosure" => {
+                let [dst, fun, args] = self.split("{} = {}({})")?;
+                Opcode::CallClosure {
+                    dst: self.reg(dst)?,
+                    fun: self.reg(fun)?,
+                    args: self.regs(args)?,
+                }
+            }
+            "StaticClosure" => {
+                let [dst, fun] = self.split("{} = {}")?;
+                Opcode::StaticClosure {
+                    dst: self.reg(dst)?,
+                    fun: self.resolver.fun(self.line, fun)?,
+                }
+            }
+            "InstanceClosure" => {
+                let [dst, obj, fun] = self.split("{} = {}.{}")?;
+                Opcode::InstanceClosure {
+                    dst: self.reg(dst)?,
+                    fun: self.resolver.fun(self.line, fun)?,
+                    obj: self.reg(obj)?,
+                }
+            }
+            "GetGlobal" => {
+                let [dst, global] = self.split("{} = global@{}")?;
+                Opcode::GetGlobal {
+                    dst: self.reg(dst)?,
+                    global: RefGlobal(self.parse(global)?),
+                }
+            }
+            "SetGlobal" => {
+                let [global, src] = self.split("global@{} = {}")?;
+                Opcode::SetGlobal {
+                    global: RefGlobal(self.parse(global)?),
+                    src: self.reg(src)?,
+                }
+            }
+            "Field" => {
+                let [dst, obj, field] = self.split("{} = {}.{}")?;
+                let obj = self.reg(obj)?;
+                Opcode::Field {
+                    dst: self.reg(dst)?,
+                    obj,
+                    field: self.field(obj, field)?,
+                }
+            }
+            "SetField" => {
+                let [obj, field, src] = self.split("{}.{} = {}")?;
+                let obj = self.reg(obj)?;
+                Opcode::SetField {
+                    obj,
+                    field: self.field(obj, field)?,
+                    src: self.reg(src)?,
+                }
+            }
+            "GetThis" => {
+                let [dst, field] = self.split("{} = this.{}")?;
+                Opcode::GetThis {
+                    dst: self.reg(dst)?,
+                    field: self.field(Reg(0), field)?,
+                }
+            }
+            "SetThis" => {
+                let [field, src] = self.split("this.{} = {}")?;
+                Opcode::SetThis {
+                    field: self.field(Reg(0), field)?,
+                    src: self.reg(src)?,
+                }
+            }
+            "DynGet" => {
+                let [dst, obj, field] = self.split("{} = {}[\"{}\"]")?;
+                Opcode::DynGet {
+                    dst: self.reg(dst)?,
+                    obj: self.reg(obj)?,
+                    field: self.resolver.string(self.line, field)?,
+                }
+            }
+            "DynSet" => {
+                let [obj, field, src] = self.split("{}[\"{}\"] = {}")?;
+                Opcode::DynSet {
+                    obj: self.reg(obj)?,
+                    field: self.resolver.string(self.line, field)?,
+                    src: self.reg(src)?,
+                }
+            }
+            "JTrue" | "JFalse" | "JNull" | "JNotNull" => {
+                let value = match name {
+                    "JTrue" => "== true",
+                    "JFalse" => "== false",
+                    "JNull" => "== null",
+                    _ => "!= null",
+                };
+                let [reg, target] = self.split(&format!("if {{}} {value} jump to {{}}"))?;
+                let (reg, offset) = (self.reg(reg)?, self.target(target)?);
+                match name {
+                    "JTrue" => Opcode::JTrue { cond: reg, offset },
+                    "JFalse" => Opcode::JFalse { cond: reg, offset },
+                    "JNull" => Opcode::JNull { reg, offset },
+                    _ => Opcode::JNotNull { reg, offset },
+                }
+            }
+            "JSLt" | "JSGte" | "JSGt" | "JSLte" | "JULt" | "JUGte" | "JNotLt" | "JNotGte"
+            | "JEq" | "JNotEq" => {
+                let symbol = match name {
+                    "JSLt" | "JULt" => "<",
+                    "JSGte" | "JUGte" => ">=",
+                    "JSGt" => ">",
+                    "JSLte" => "<=",
+                    "JNotLt" => "!<",
+                    "JNotGte" => "!>=",
+                    "JEq" => "==",
+                    _ => "!=",
+                };
+                let [a, b, target] = self.split(&format!("if {{}} {symbol} {{}} jump to {{}}"))?;
+                let (a, b, offset) = (self.reg(a)?, self.reg(b)?, self.target(target)?);
+                match name {
+                    "JSLt" => Opcode::JSLt { a, b, offset },
+                    "JSGte" => Opcode::JSGte { a, b, offset },
+                    "JSGt" => Opcode::JSGt { a, b, offset },
+                    "JSLte" => Opcode::JSLte { a, b, offset },
+                    "JULt" => Opcode::JULt { a, b, offset },
+                    "JUGte" => Opcode::JUGte { a, b, offset },
+                    "JNotLt" => Opcode::JNotLt { a, b, offset },
+                    "JNotGte" => Opcode::JNotGte { a, b, offset },
+                    "JEq" => Opcode::JEq { a, b, offset },
+                    _ => Opcode::JNotEq { a, b, offset },
+                }
+            }
+            "JAlways" => {
+                let [target] = self.split("jump to {}")?;
+                Opcode::JAlways {
+                    offset: self.target(target)?,
+                }
+            }
+            "ToDyn" | "ToInt" | "SafeCast" | "UnsafeCast" | "ToVirtual" => {
+                let [dst, src] = self.split("{} = cast {}")?;
+                let (dst, src) = (self.reg(dst)?, self.reg(src)?);
+                match name {
+                    "ToDyn" => Opcode::ToDyn { dst, src },
+                    "ToInt" => Opcode::ToInt { dst, src },
+                    "SafeCast" => Opcode::SafeCast { dst, src },
+                    "UnsafeCast" => Opcode::UnsafeCast { dst, src },
+                    _ => Opcode::ToVirtual { dst, src },
+                }
+            }
+            "Ret" => {
+                let [ret] = self.split("{}")?;
+                Opcode::Ret {
+                    ret: self.reg(ret)?,
+                }
+            }
+            "Throw" => {
+                let [exc] = self.split("throw {}")?;
+                Opcode::Throw {
+                    exc: self.reg(exc)?,
+                }
+            }
+            "Rethrow" => {
+                let [exc] = self.split("rethrow {}")?;
+                Opcode::Rethrow {
+                    exc: self.reg(exc)?,
+                }
+            }
+            "NullCheck" => {
+                let [reg] = self.split("if {} == null throw exc")?;
+                Opcode::NullCheck {
+                    reg: self.reg(reg)?,
+                }
+            }
+            "Trap" => {
+                let [exc, target] = self.split("try {} jump to {}")?;
+                Opcode::Trap {
+                    exc: self.reg(exc)?,
+                    offset: self.target(target)?,
+                }
+            }
+            "EndTrap" => {
+                let [exc] = self.split("catch {}")?;
+                Opcode::EndTrap {
+                    exc: self.reg(exc)?,
+                }
+            }
+            "GetArray" => {
+                let [dst, array, index] = self.split("{} = {}[{}]")?;
+                Opcode::GetArray {
+                    dst: self.reg(dst)?,
+                    array: self.reg(array)?,
+                    index: self.reg(index)?,
+                }
+            }
+            "SetArray" => {
+                let [array, index, src] = self.split("{}[{}] = {}")?;
+                Opcode::SetArray {
+                    array: self.reg(array)?,
+                    index: self.reg(index)?,
+                    src: self.reg(src)?,
+                }
+            }
+            "New" => {
+                // The type is the one of the register
+                let [dst, _] = self.split("{} = new {}")?;
+                Opcode::New {
+                    dst: self.reg(dst)?,
+                }
+            }
+            "ArraySize" => {
+                let [dst, array] = self.split("{} = {}.length")?;
+                Opcode::ArraySize {
+                    dst: self.reg(dst)?,
+                    array: self.reg(array)?,
+                }
+            }
+            "Type" => {
+                let [dst, ty] = self.split("{} = {}")?;
+                Opcode::Type {
+                    dst: self.reg(dst)?,
+                    ty: self.resolver.type_ref(self.line, ty)?,
+                }
+            }
+            "Ref" => {
+                let [dst, src] = self.split("{} = &{}")?;
+                Opcode::Ref {
+                    dst: self.reg(dst)?,
+                    src: self.reg(src)?,
+                }
+            }
+            "Unref" => {
+                let [dst, src] = self.split("{} = *{}")?;
+                Opcode::Unref {
+                    dst: self.reg(dst)?,
+                    src: self.reg(src)?,
+                }
+            }
+            "MakeEnum" => {
+                let [dst, construct, args] = self.split("{} = variant {} ({})")?;
+                let dst = self.reg(dst)?;
+                Opcode::MakeEnum {
+                    dst,
+                    construct: self.construct(dst, construct)?,
+                    args: self.regs(args)?,
+                }
+            }
+            "EnumAlloc" => {
+                let [dst, construct] = self.split("{} = new {}")?;
+                let dst = self.reg(dst)?;
+                Opcode::EnumAlloc {
+                    dst,
+                    construct: self.construct(dst, construct)?,
+                }
+            }
+            "EnumIndex" => {
+                let [dst, value] = self.split("{} = variant of {}")?;
+                Opcode::EnumIndex {
+                    dst: self.reg(dst)?,
+                    value: self.reg(value)?,
+                }
+            }
+            "EnumField" => {
+                let [dst, value, construct, field] = self.split("{} = ({} as {}).{}")?;
+                let value = self.reg(value)?;
+                Opcode::EnumField {
+                    dst: self.reg(dst)?,
+                    value,
+                    construct: self.construct(value, construct)?,
+                    field: RefField(self.parse(field)?),
+                }
+            }
+            "SetEnumField" => {
+                let [value, field, src] = self.split("{}.{} = {}")?;
+                Opcode::SetEnumField {
+                    value: self.reg(value)?,
+                    field: RefField(self.parse(field)?),
+                    src: self.reg(src)?,
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(op))
+    }
+
+    fn error(&self, msg: String) -> Error {
+        error(self.line, msg)
+    }
+
+    /// Split the operands along the literal parts of `pattern`, each `{}` is an operand
+    fn split<const N: usize>(&self, pattern: &str) -> Result<[&'t str; N]> {
+        let err = || self.error(format!("Expected '{pattern}', got '{}'", self.text));
+        let mut literals = pattern.split("{}");
+        let mut rest = self
+            .text
+            .strip_prefix(literals.next().unwrap_or_default())
+            .ok_or_else(err)?;
+        let mut operands = [""; N];
+        for (i, operand) in operands.iter_mut().enumerate() {
+            let literal = literals.next().unwrap_or_default();
+            // The last literal ends the text, operands like strings may contain it
+            let end = if i == N - 1 {
+                rest.strip_suffix(literal).ok_or_else(err)?.len()
+            } else {
+                rest.find(literal).ok_or_else(err)?
+            };
+            *operand = rest[..end].trim();
+            rest = &rest[end + literal.len()..];
+        }
+        Ok(operands)
+    }
+
+    fn parse<T: std::str::FromStr>(&self, text: &str) -> Result<T> {
+        text.parse()
+            .map_err(|_| self.error(format!("Invalid value '{text}'")))
+    }
+
+    fn reg(&self, text: &str) -> Result<Reg> {
+        text.strip_prefix("reg")
+            .and_then(|r| r.parse().ok())
+            .map(Reg)
+            .ok_or_else(|| self.error(format!("Expected a register, got '{text}'")))
+    }
+
+    /// A list of registers like `reg0, reg1`
+    fn regs(&self, text: &str) -> Result<Vec<Reg>> {
+        if text.is_empty() {
+            return Ok(Vec::new());
+        }
+        text.split(',').map(|r| self.reg(r.trim())).collect()
+    }
+
+    /// A jump displayed as the position it lands on
+    fn target(&self, text: &str) -> Result<JumpOffset> {
+        Ok(self.parse::<JumpOffset>(text)? - self.pos as JumpOffset - 1)
+    }
+
+    fn int(&self, text: &str) -> Result<RefInt> {
+        let value: i32 = self.parse(text)?;
+        self.resolver
+            .code
+            .ints
+            .iter()
+            .position(|&i| i == value)
+            .map(RefInt)
+            .ok_or_else(|| self.error(format!("Unknown int constant {text}")))
+    }
+
+    fn float(&self, text: &str) -> Result<RefFloat> {
+        // Compared as displayed, to find NaN
+        self.resolver
+            .code
+            .floats
+            .iter()
+            .position(|f| f.to_string() == text)
+            .map(RefFloat)
+            .ok_or_else(|| self.error(format!("Unknown float constant {text}")))
+    }
+
+    fn reg_type(&self, reg: Reg) -> Result<&Type> {
+        let ty = self
+            .parent
+            .regs
+            .get(reg.0 as usize)
+            .ok_or_else(|| self.error(format!("Unknown register {reg}")))?;
+        Ok(&self.resolver.code[*ty])
+    }
+
+    /// A field of the object in `obj`, by name or like `<field3>`
+    fn field(&self, obj: Reg, text: &str) -> Result<RefField> {
+        if let Some(index) = text
+            .strip_prefix("<field")
+            .and_then(|t| t.strip_suffix('>'))
+        {
+            return self.parse(index).map(RefField);
+        }
+        let code = self.resolver.code;
+        let position = match self.reg_type(obj)? {
+            Type::Virtual { fields } => fields.iter().position(|f| code[f.name] == *text),
+            ty => ty
+                .get_type_obj()
+                .and_then(|obj| obj.fields.iter().position(|f| code[f.name] == *text)),
+        };
+        position
+            .map(RefField)
+            .ok_or_else(|| self.error(format!("Unknown field '{text}' of {obj}")))
+    }
+
+    /// A construct of the enum in `reg`, by name or like `<construct3>`
+    fn construct(&self, reg: Reg, text: &str) -> Result<RefEnumConstruct> {
+        if let Some(index) = text
+            .strip_prefix("<construct")
+            .and_then(|t| t.strip_suffix('>'))
+        {
+            return self.parse(index).map(RefEnumConstruct);
+        }
+        let code = self.resolver.code;
+        match self.reg_type(reg)? {
+            Type::Enum { constructs, .. } => constructs.iter().position(|c| code[c.name] == *text),
+            _ => None,
+        }
+        .map(RefEnumConstruct)
+        .ok_or_else(|| self.error(format!("Unknown enum construct '{text}' of {reg}")))
+    }
+}
+
+/// Splits the raw operands into tokens : words, `[` and `]`.
+pub(crate) struct Lexer<'a> {
+    line: usize,
+    rest: &'a str,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(line: usize, src: &'a str) -> Self {
+        Self { line, rest: src }
+    }
+
+    fn peek_token(&self) -> Option<&'a str> {
+        let rest = self.rest.trim_start();
+        match rest.chars().next()? {
+            '[' | ']' => Some(&rest[..1]),
+            _ => Some(
+                &rest[..rest
+                    .find(|c: char| c.is_whitespace() || c == '[' || c == ']')
+                    .unwrap_or(rest.len())],
+            ),
+        }
+    }
+
+    fn next_token(&mut self) -> Result<&'a str> {
+        let token = self
+            .peek_token()
+            .ok_or_else(|| self.error("Unexpected end of line".to_owned()))?;
+        let rest = self.rest.trim_start();
+        self.rest = &rest[token.len()..];
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<()> {
+        match self.next_token()? {
+            token if token == expected => Ok(()),
+            token => Err(self.error(format!("Expected '{expected}', got '{token}'"))),
+        }
+    }
+
+    fn expect_end(&self) -> Result<()> {
+        match self.peek_token() {
+            Some(token) => Err(self.error(format!("Unexpected token '{token}'"))),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn error(&self, msg: String) -> Error {
+        error(self.line, msg)
+    }
+
+    /// Parse a token of the form `<prefix><number><suffix>`
+    fn number<T: std::str::FromStr>(&mut self, prefix: &str, suffix: &str) -> Result<T> {
+        let token = self.next_token()?;
+        token
+            .strip_prefix(prefix)
+            .and_then(|t| t.strip_suffix(suffix))
+            .and_then(|t| t.parse().ok())
+            .ok_or_else(|| {
+                self.error(format!(
+                    "Expected '{prefix}<number>{suffix}', got '{token}'"
+                ))
+            })
+    }
+}
+
+/// An opcode operand that can be written to and parsed from the raw syntax.
+pub(crate) trait AsmOperand: Sized {
+    fn write_asm(&self, f: &mut Formatter) -> FmtResult;
+
+    fn parse_asm(lex: &mut Lexer) -> Result<Self>;
+}
+
+impl AsmOperand for Reg {
+    fn write_asm(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "reg{}", self.0)
+    }
+
+    fn parse_asm(lex: &mut Lexer) -> Result<Self> {
+        lex.number("reg", "").map(Reg)
+    }
+}
+
+macro_rules! asm_operand_pool_ref {
+    ($($ty:ident),*) => {
+        $(
+        impl AsmOperand for $ty {
+            fn write_asm(&self, f: &mut Formatter) -> FmtResult {
+                write!(f, "@{}", self.0)
+            }
+
+            fn parse_asm(lex: &mut Lexer) -> Result<Self> {
+                lex.number("@", "").map($ty)
+            }
+        }
+        )*
+    };
+}
+
+asm_operand_pool_ref!(RefInt, RefFloat, RefBytes, RefString, RefGlobal, RefType, RefFun);
+
+impl AsmOperand for RefField {
+    fn write_asm(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "<field{}>", self.0)
+    }
+
+    fn parse_asm(lex: &mut Lexer) -> Result<Self> {
+        lex.number("<field", ">").map(RefField)
+    }
+}
+
+impl AsmOperand for RefEnumConstruct {
+    fn write_asm(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "<construct{}>", self.0)
+    }
+
+    fn parse_asm(lex: &mut Lexer) -> Result<Self> {
+        lex.number("<construct", ">").map(RefEnumConstruct)
+    }
+}
+
+impl AsmOperand for bool {
+    fn write_asm(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{self}")
+    }
+
+    fn parse_asm(lex: &mut Lexer) -> Result<Self> {
+        match lex.next_token()? {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            token => Err(lex.error(format!("Expected 'true' or 'false', got '{token}'"))),
+        }
+    }
+}
+
+/// Inline integers and jump offsets
+impl AsmOperand for i32 {
+    fn write_asm(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{self}")
+    }
+
+    fn parse_asm(lex: &mut Lexer) -> Result<Self> {
+        lex.number("", "")
+    }
+}
+
+impl<T: AsmOperand> AsmOperand for Vec<T> {
+    fn write_asm(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str("[")?;
+        for (i, e) in self.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            e.write_asm(f)?;
+        }
+        f.write_str("]")
+    }
+
+    fn parse_asm(lex: &mut Lexer) -> Result<Self> {
+        lex.expect("[")?;
+        let mut list = Vec::new();
+        while lex.peek_token() != Some("]") {
+            list.push(T::parse_asm(lex)?);
+        }
+        lex.expect("]")?;
+        Ok(list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::{parse_function, parse_functions, parse_opcode};
+    use crate::builder::Builder;
+    use crate::fmt::EnhancedFmt;
+    use crate::opcodes::Opcode;
+    use crate::types::{EnumConstruct, Function, RefEnumConstruct, RefField, RefGlobal, Reg, Type};
+    use crate::Bytecode;
+
+    fn code() -> Bytecode {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        let int = b.ty(Type::I32);
+        let float = b.ty(Type::F64);
+        let dyn_ = b.ty(Type::Dyn);
+        let game = b.class("Game", None, &[("hp", int)]);
+        let name = b.string("Shape");
+        let circle = b.string("Circle");
+        let shape = b.ty(Type::Enum {
+            name,
+            global: RefGlobal(0),
+            constructs: vec![EnumConstruct {
+                name: circle,
+                params: vec![int],
+            }],
+        });
+        b.global(int);
+        let (five, half) = (b.int(5), b.float(0.5));
+        let hello = b.string("hello; \"world\"");
+        let field = b.string("field");
+        let log = b.native("std", "log", &[dyn_], void);
+        b.function(
+            "main",
+            &[game],
+            void,
+            &[int, int, float, dyn_, shape, void],
+            vec![
+                Opcode::Int {
+                    dst: Reg(1),
+                    ptr: five,
+                },
+                Opcode::Float {
+                    dst: Reg(3),
+                    ptr: half,
+                },
+                Opcode::String {
+                    dst: Reg(4),
+                    ptr: hello,
+                },
+                Opcode::Label,
+                Opcode::Add {
+                    dst: Reg(2),
+                    a: Reg(1),
+                    b: Reg(1),
+                },
+                Opcode::JSLt {
+                    a: Reg(2),
+                    b: Reg(1),
+                    offset: -3,
+                },
+                Opcode::Field {
+                    dst: Reg(1),
+                    obj: Reg(0),
+                    field: RefField(0),
+                },
+                Opcode::SetThis {
+                    field: RefField(0),
+                    src: Reg(2),
+                },
+                Opcode::DynSet {
+                    obj: Reg(4),
+                    field,
+                    src: Reg(1),
+                },
+                Opcode::Call1 {
+                    dst: Reg(6),
+                    fun: log,
+                    arg0: Reg(4),
+                },
+                Opcode::GetGlobal {
+                    dst: Reg(2),
+                    global: RefGlobal(0),
+                },
+                Opcode::MakeEnum {
+                    dst: Reg(5),
+                    construct: RefEnumConstruct(0),
+                    args: vec![Reg(2)],
+                },
+                Opcode::Switch {
+                    reg: Reg(2),
+                    offsets: vec![1, 2],
+                    end: 2,
+                },
+                Opcode::Type {
+                    dst: Reg(4),
+                    ty: game,
+                },
+                Opcode::JAlways { offset: 0 },
+                Opcode::Ret { ret: Reg(6) },
+            ],
+        );
+        b.build().unwrap()
+    }
+
+    fn assert_same(a: &Function, b: &Function) {
+        assert_eq!(a.findex, b.findex);
+        assert_eq!(a.t, b.t);
+        assert_eq!(a.regs, b.regs);
+        assert_eq!(format!("{:?}", a.ops), format!("{:?}", b.ops));
+        assert_eq!(a.debug_info, b.debug_info);
+    }
+
+    #[test]
+    fn disassembly_roundtrip() {
+        let code = code();
+        let f = &code.functions[0];
+        let listing = f.display::<EnhancedFmt>(&code).to_string();
+        assert!(listing.contains("reg4 = \"hello; \"world\"\""));
+        let parsed = parse_function(&code, &listing).unwrap();
+        assert_same(&parsed, f);
+        assert_eq!(parsed.name, f.name);
+        // Without the register list
+        let annotated = f.display_annotated(&code).to_string();
+        assert_same(&parse_function(&code, &annotated).unwrap(), f);
+    }
+
+    #[test]
+    fn debug_positions() {
+        let mut code = code();
+        code.debug_files = Some(vec!["Main.hx".into()]);
+        let f = &mut code.functions[0];
+        f.debug_info = Some((0..f.ops.len()).map(|i| (0, 10 + i)).collect());
+        let f = &code.functions[0];
+        let listing = f.display::<EnhancedFmt>(&code).to_string();
+        assert_same(&parse_functions(&code, &listing).unwrap()[0], f);
+    }
+
+    #[test]
+    fn opcodes() {
+        let code = code();
+        let f = &code.functions[0];
+        let parse = |text| parse_opcode(&code, f, 3, text).map(|op| format!("{op:?}"));
+        assert_eq!(
+            parse("Mov reg1 = reg0 ; copy").unwrap(),
+            format!(
+                "{:?}",
+                Opcode::Mov {
+                    dst: Reg(1),
+                    src: Reg(0)
+                }
+            )
+        );
+        // Raw operands
+        assert_eq!(
+            parse("Mov reg1 reg0").unwrap(),
+            parse("Mov reg1 = reg0").unwrap()
+        );
+        assert_eq!(
+            parse("JAlways jump to 1").unwrap(),
+            format!("{:?}", Opcode::JAlways { offset: -3 })
+        );
+        assert_eq!(
+            parse("GetThis reg1 = this.hp").unwrap(),
+            parse("GetThis reg1 <field0>").unwrap()
+        );
+        assert!(parse("Nope reg0").is_err());
+        assert!(parse("Mov reg0").is_err());
+        assert!(parse("Mov reg0 = reg1 = reg2").is_err());
+        assert!(parse("Int reg0 = 6").is_err());
+        assert!(parse("String reg0 = \"unknown\"").is_err());
+        assert!(parse("GetThis reg1 = this.mp").is_err());
+        assert!(parse("Call2 reg6 = log@0(reg4)").is_err());
+    }
+
+    #[test]
+    fn listing_errors() {
+        let code = code();
+        assert!(parse_functions(&code, "  0: Ret reg0").is_err());
+        // Opcodes out of order
+        assert!(parse_functions(&code, "fn main@1 (Game) -> void\n  1: Ret reg0").is_err());
+        assert!(parse_function(&code, "").is_err());
+        let f =
+            parse_function(&code, "fn main@1 (Game) -> void\n reg0 Game\n  0: Ret reg0").unwrap();
+        assert_eq!(f.regs, [code.functions[0].regs[0]]);
+    }
+}
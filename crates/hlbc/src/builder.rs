@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+
+use crate::opcodes::Opcode;
+use crate::types::{
+    Function, Native, ObjField, ObjProto, RefBytes, RefField, RefFloat, RefFun, RefGlobal, RefInt,
+    RefString, RefType, Type, TypeFun, TypeObj,
+};
+use crate::{Bytecode, Result, Str};
+
+/// Build a program in memory, to generate bytecode or to write tests without the Haxe compiler.
+///
+/// Constants, strings and types are deduplicated, and functions and natives get the next free findex. The lookup
+/// structures are built by [Builder::build] like when loading a file, so the result can be used right away.
+///
+/// ```
+/// # use hlbc::builder::Builder;
+/// # use hlbc::opcodes::Opcode;
+/// # use hlbc::types::{Reg, Type};
+/// # use hlbc::Resolve;
+/// let mut b = Builder::new();
+/// let int = b.ty(Type::I32);
+/// let one = b.int(1);
+/// let main = b.function(
+///     "main",
+///     &[],
+///     int,
+///     &[int],
+///     vec![Opcode::Int { dst: Reg(0), ptr: one }, Opcode::Ret { ret: Reg(0) }],
+/// );
+/// let code = b.build().unwrap();
+/// assert_eq!(code.get(main).as_fn().unwrap().name(&code), "main");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Builder {
+    code: Bytecode,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Builder {
+    /// An empty program of the latest version. The empty string is at index 0, as elements without a name point to
+    /// it, and Void is type 0 like in every compiled program.
+    pub fn new() -> Self {
+        Self {
+            code: Bytecode {
+                version: Bytecode::MAX_VERSION,
+                strings: vec![Str::default()],
+                types: vec![Type::Void],
+                bytes: Some((Vec::new(), Vec::new())),
+                constants: Some(Vec::new()),
+                ..Bytecode::default()
+            },
+        }
+    }
+
+    /// Pool a string
+    pub fn string(&mut self, s: &str) -> RefString {
+        RefString(intern(&mut self.code.strings, s, |a, b| a == b))
+    }
+
+    /// Pool an integer constant
+    pub fn int(&mut self, value: i32) -> RefInt {
+        RefInt(intern(&mut self.code.ints, value, |a, b| a == b))
+    }
+
+    /// Pool a float constant
+    pub fn float(&mut self, value: f64) -> RefFloat {
+        RefFloat(intern(&mut self.code.floats, value, |a, b| {
+            a.to_bits() == b.to_bits()
+        }))
+    }
+
+    /// Append to the bytes pool
+    pub fn bytes(&mut self, value: &[u8]) -> RefBytes {
+        let (bytes, pos) = self.code.bytes.get_or_insert_with(Default::default);
+        pos.push(bytes.len());
+        bytes.extend_from_slice(value);
+        RefBytes(pos.len() - 1)
+    }
+
+    /// Pool a type
+    pub fn ty(&mut self, t: Type) -> RefType {
+        RefType(intern(&mut self.code.types, t, |a, b| a == b))
+    }
+
+    /// Pool the type of a function
+    pub fn fun_type(&mut self, args: &[RefType], ret: RefType) -> RefType {
+        self.ty(Type::Fun(TypeFun {
+            args: args.to_vec(),
+            ret,
+        }))
+    }
+
+    /// A class with its own fields, without static part. Add its methods with [Builder::method].
+    pub fn class(
+        &mut self,
+        name: &str,
+        super_: Option<RefType>,
+        fields: &[(&str, RefType)],
+    ) -> RefType {
+        let name = self.string(name);
+        let own_fields = fields
+            .iter()
+            .map(|&(name, t)| ObjField {
+                name: self.string(name),
+                t,
+            })
+            .collect();
+        self.code.types.push(Type::Obj(TypeObj {
+            name,
+            super_,
+            global: RefGlobal(0),
+            own_fields,
+            protos: Vec::new(),
+            bindings: BTreeMap::new(),
+            fields: Vec::new(),
+        }));
+        RefType(self.code.types.len() - 1)
+    }
+
+    /// Bind a function as a method of a class, it takes its name and parent
+    ///
+    /// # Panics
+    ///
+    /// When `class` isn't a class
+    pub fn method(&mut self, class: RefType, name: &str, fun: RefFun) {
+        let name = self.string(name);
+        let obj = self.code.types[class.0]
+            .get_type_obj_mut()
+            .expect("not a class");
+        obj.protos.push(ObjProto {
+            name,
+            findex: fun,
+            pindex: obj.protos.len() as i32,
+        });
+    }
+
+    /// Bind a function to a field of a class, like static functions
+    ///
+    /// # Panics
+    ///
+    /// When `class` isn't a class
+    pub fn bind(&mut self, class: RefType, field: RefField, fun: RefFun) {
+        self.code.types[class.0]
+            .get_type_obj_mut()
+            .expect("not a class")
+            .bindings
+            .insert(field, fun);
+    }
+
+    /// Add a global variable
+    pub fn global(&mut self, t: RefType) -> RefGlobal {
+        self.code.globals.push(t);
+        RefGlobal(self.code.globals.len() - 1)
+    }
+
+    /// Add a native function
+    pub fn native(&mut self, lib: &str, name: &str, args: &[RefType], ret: RefType) -> RefFun {
+        let findex = self.next_findex();
+        let native = Native {
+            name: self.string(name),
+            lib: self.string(lib),
+            t: self.fun_type(args, ret),
+            findex,
+        };
+        self.code.natives.push(native);
+        findex
+    }
+
+    /// Add a function, its registers are its arguments followed by `locals`. Methods get their name from their
+    /// class instead, leave it empty for anonymous functions.
+    pub fn function(
+        &mut self,
+        name: &str,
+        args: &[RefType],
+        ret: RefType,
+        locals: &[RefType],
+        ops: Vec<Opcode>,
+    ) -> RefFun {
+        let findex = self.next_findex();
+        let f = Function {
+            t: self.fun_type(args, ret),
+            findex,
+            regs: args.iter().chain(locals).copied().collect(),
+            ops,
+            debug_info: None,
+            assigns: None,
+            name: self.string(name),
+            parent: None,
+        };
+        self.code.functions.push(f);
+        findex
+    }
+
+    /// A function added with [Builder::function], to set its debug information
+    ///
+    /// # Panics
+    ///
+    /// When `fun` is a native
+    pub fn function_mut(&mut self, fun: RefFun) -> &mut Function {
+        self.code
+            .functions
+            .iter_mut()
+            .find(|f| f.findex == fun)
+            .expect("not a function")
+    }
+
+    /// The program being built, for everything else
+    pub fn code_mut(&mut self) -> &mut Bytecode {
+        &mut self.code
+    }
+
+    /// Build the lookup structures
+    pub fn build(mut self) -> Result<Bytecode> {
+        self.code.link()?;
+        Ok(self.code)
+    }
+
+    fn next_findex(&self) -> RefFun {
+        RefFun(self.code.functions.len() + self.code.natives.len())
+    }
+}
+
+fn intern<T, U>(pool: &mut Vec<T>, value: U, eq: impl Fn(&T, &U) -> bool) -> usize
+where
+    T: From<U>,
+{
+    match pool.iter().position(|v| eq(v, &value)) {
+        Some(i) => i,
+        None => {
+            pool.push(T::from(value));
+            pool.len() - 1
+        }
+    }
+}
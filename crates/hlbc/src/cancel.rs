@@ -0,0 +1,51 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::{Error, Result};
+
+/// Shared flag asking long running operations to stop.
+///
+/// Clones share the same flag : keep one to [cancel](CancellationToken::cancel) the work from another thread
+/// (a cancel button, a timeout) and give the other to the operation. Operations check it periodically and stop
+/// early, so the thread running them can be reused instead of being abandoned.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask every operation using this token to stop. There is no way back.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// [Error::Cancelled] if cancellation has been requested, to bail out with `?`
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cancel::CancellationToken;
+
+    #[test]
+    fn shared_flag() {
+        let token = CancellationToken::new();
+        let other = token.clone();
+        assert!(other.check().is_ok());
+        token.cancel();
+        assert!(other.is_cancelled());
+        assert!(other.check().is_err());
+    }
+}
@@ -18,6 +18,9 @@ use crate::types::{
 use crate::Resolve;
 use crate::{Bytecode, RefFun};
 
+/// Maximum number of characters of a string shown in an annotation
+const STRING_PREVIEW: usize = 40;
+
 //region Display impls
 
 impl Display for Reg {
@@ -599,7 +602,8 @@ impl FunPtr<'_> {
 //endregion
 
 impl Opcode {
-    /// This display is an enhanced assembly view, with nice printing and added information from the context
+    /// This display is an enhanced assembly view, with nice printing and added information from the context.
+    /// It can be parsed back with [crate::asm::parse_opcode].
     pub fn display(
         &self,
         ctx: &Bytecode,
@@ -668,7 +672,7 @@ impl Opcode {
                 arg2,
                 arg3,
             } => op!(
-                "{dst} = {}({arg0}, {arg1},{arg2}, {arg3})",
+                "{dst} = {}({arg0}, {arg1}, {arg2}, {arg3})",
                 fun.display::<EnhancedFmt>(ctx)
             ),
             Opcode::CallN { dst, fun, args } => {
@@ -699,10 +703,10 @@ impl Opcode {
                 op!("{dst} = {fun}({})", fmtools::join(", ", args))
             }
             Opcode::StaticClosure { dst, fun } => {
-                op!("{dst} = {:?}", ctx.get(*fun))
+                op!("{dst} = {}", fun.display::<EnhancedFmt>(ctx))
             }
             Opcode::InstanceClosure { dst, fun, obj } => {
-                op!("{dst} = {obj}.{:?}", ctx.get(*fun))
+                op!("{dst} = {obj}.{}", fun.display::<EnhancedFmt>(ctx))
             }
             Opcode::GetGlobal { dst, global } => {
                 op!("{dst} = global@{}", global.0)
@@ -872,10 +876,110 @@ impl Opcode {
             Opcode::SetEnumField { value, field, src } => {
                 op!("{value}.{} = {src}", field.0)
             }
-            // Fallback to debug impl
-            _ => format!("{self:?}"),
+            // Raw operands, see crate::asm
+            _ => {
+                let operands = fmt(|f| self.write_asm_operands(f)).to_string();
+                if operands.is_empty() {
+                    self.name().to_owned()
+                } else {
+                    format!("{:<align$}{operands}", self.name())
+                }
+            }
         }
     }
+
+    /// The elements referenced by this opcode resolved to their names and values, like the relocations shown by
+    /// `objdump -dr` : function signatures, string previews, field, type and enum variant names.
+    pub fn annotation(&self, ctx: &Bytecode, parent: &Function) -> Option<String> {
+        let ty = |t: RefType| ctx[t].display::<EnhancedFmt>(ctx).to_string();
+        let fun = |f: &RefFun| f.display_header::<EnhancedFmt>(ctx).to_string();
+        let field = |obj: RefType, field: &RefField| match obj.field(*field, ctx) {
+            Some(fi) => format!("{}.{}: {}", ty(obj), fi.name(ctx), ty(fi.t)),
+            None => format!("{}.{field}", ty(obj)),
+        };
+        let method = |obj: RefType, field: &RefField| match obj.method(field.0, ctx) {
+            Some(p) => fun(&p.findex),
+            None => format!("{}.{field}", ty(obj)),
+        };
+        let construct = |t: RefType, c: &RefEnumConstruct| {
+            format!("{}.{}", ty(t), c.display::<EnhancedFmt>(ctx, &ctx[t]))
+        };
+        let annotation = match self {
+            Opcode::Int { ptr, .. } => ptr.display::<EnhancedFmt>(ctx).to_string(),
+            Opcode::Float { ptr, .. } => ptr.display::<EnhancedFmt>(ctx).to_string(),
+            Opcode::String { ptr, .. }
+            | Opcode::DynGet { field: ptr, .. }
+            | Opcode::DynSet { field: ptr, .. } => {
+                let s = &ctx[*ptr];
+                match s.char_indices().nth(STRING_PREVIEW) {
+                    Some((end, _)) => format!("{:?}...", &s[..end]),
+                    None => format!("{:?}", &**s),
+                }
+            }
+            Opcode::Call0 { fun: f, .. }
+            | Opcode::Call1 { fun: f, .. }
+            | Opcode::Call2 { fun: f, .. }
+            | Opcode::Call3 { fun: f, .. }
+            | Opcode::Call4 { fun: f, .. }
+            | Opcode::CallN { fun: f, .. }
+            | Opcode::StaticClosure { fun: f, .. }
+            | Opcode::InstanceClosure { fun: f, .. } => fun(f),
+            Opcode::CallMethod { field: f, args, .. } => match args.first() {
+                Some(obj) => method(parent[*obj], f),
+                None => return None,
+            },
+            Opcode::CallThis { field: f, .. } => method(parent.regs[0], f),
+            Opcode::GetGlobal { global, .. } | Opcode::SetGlobal { global, .. } => {
+                format!("global@{}: {}", global.0, ty(ctx[*global]))
+            }
+            Opcode::Field { obj, field: f, .. } | Opcode::SetField { obj, field: f, .. } => {
+                field(parent[*obj], f)
+            }
+            Opcode::GetThis { field: f, .. } | Opcode::SetThis { field: f, .. } => {
+                field(parent.regs[0], f)
+            }
+            Opcode::Type { ty: t, .. } => ty(*t),
+            Opcode::New { dst } => ty(parent[*dst]),
+            Opcode::MakeEnum {
+                dst, construct: c, ..
+            }
+            | Opcode::EnumAlloc { dst, construct: c } => construct(parent[*dst], c),
+            Opcode::EnumField {
+                value,
+                construct: c,
+                ..
+            } => construct(parent[*value], c),
+            _ => return None,
+        };
+        Some(annotation)
+    }
+
+    /// This opcode in the assembly syntax (see [crate::asm]), followed by a comment with its
+    /// [resolved references](Self::annotation)
+    pub fn display_annotated<'a>(
+        &'a self,
+        ctx: &'a Bytecode,
+        parent: &'a Function,
+    ) -> impl Display + 'a {
+        fmt(move |f| match self.annotation(ctx, parent) {
+            Some(annotation) => write!(f, "{:<32} ; {annotation}", self.display_asm().to_string()),
+            None => Display::fmt(&self.display_asm(), f),
+        })
+    }
+}
+
+impl Function {
+    /// Every opcode of the function in the assembly syntax with its references resolved in a comment, like
+    /// `objdump -dr`
+    pub fn display_annotated<'a>(&'a self, ctx: &'a Bytecode) -> impl Display + 'a {
+        fmt(move |f| {
+            writeln!(f, "{}", self.display_header::<EnhancedFmt>(ctx))?;
+            for (i, o) in self.ops.iter().enumerate() {
+                writeln!(f, "{i:>4}: {}", o.display_annotated(ctx, self))?;
+            }
+            Ok(())
+        })
+    }
 }
 
 #[cfg(test)]
@@ -884,9 +988,11 @@ mod test {
     use std::fs;
     use std::path::Path;
 
+    use crate::builder::Builder;
     use crate::fmt::{fmt, DisplayFmt, EnhancedFmt};
     use crate::fmt::{BytecodeFmt, DebugFmt};
-    use crate::types::{FunPtr, Reg};
+    use crate::opcodes::Opcode;
+    use crate::types::{FunPtr, RefField, Reg, Type};
     use crate::Bytecode;
 
     struct Null;
@@ -917,6 +1023,41 @@ mod test {
         )
     }
 
+    #[test]
+    fn annotated() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        let game = b.class("Game", None, &[("hp", int)]);
+        let long = b.string("a rather long string that goes on and on and on");
+        b.function("", &[], int, &[game, int], vec![]);
+        let code = b.build().unwrap();
+        let f = &code.functions[0];
+        let field = Opcode::Field {
+            dst: Reg(1),
+            obj: Reg(0),
+            field: RefField(0),
+        };
+        assert_eq!(field.annotation(&code, f).unwrap(), "Game.hp: i32");
+        assert!(field
+            .display_annotated(&code, f)
+            .to_string()
+            .ends_with(" ; Game.hp: i32"));
+        let string = Opcode::String {
+            dst: Reg(1),
+            ptr: long,
+        };
+        assert_eq!(
+            string.annotation(&code, f).unwrap(),
+            "\"a rather long string that goes on and on\"..."
+        );
+        let ret = Opcode::Ret { ret: Reg(1) };
+        assert!(ret.annotation(&code, f).is_none());
+        assert_eq!(
+            ret.display_annotated(&code, f).to_string(),
+            ret.display_asm().to_string()
+        );
+    }
+
     fn test_fmt(path: impl AsRef<Path>) {
         let code = Bytecode::from_file(path).unwrap();
         for f in code.functions() {
@@ -928,6 +1069,7 @@ mod test {
                     write!(Null, "{}", fun.display::<EnhancedFmt>(&code)).unwrap();
                     write!(Null, "{}", fun.display_header::<DisplayFmt>(&code)).unwrap();
                     write!(Null, "{}", fun.display::<DisplayFmt>(&code)).unwrap();
+                    write!(Null, "{}", fun.display_annotated(&code)).unwrap();
                 }
                 FunPtr::Native(n) => {
                     write!(Null, "{}", n.display::<EnhancedFmt>(&code)).unwrap();
@@ -0,0 +1,864 @@
+//! Compile simple Haxe-like statements into opcodes to inject them in an existing function.
+//!
+//! ```text
+//! // Comments run to the end of the line
+//! /* or to the end of the block */
+//! log("entering with", x);
+//! this.count = 0;
+//! this.reset(null);
+//! return 3;
+//! ```
+//!
+//! Only a restricted language is supported :
+//! - calls to functions and natives by name (`log(...)`) and to methods of a variable (`this.reset(...)`)
+//! - constants : integers, floats, strings, `true`, `false` and `null`
+//! - variables : `this`, the named arguments of the function and raw registers (`reg3`)
+//! - field reads (`x.count`) and writes (`this.count = ...`) on variables
+//! - `return` with or without a value
+//!
+//! Temporary values are stored in new registers of the function, reused between statements.
+//! Values are converted to `Dynamic` when a function or a field expects one, there is no other type checking.
+
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use crate::opcodes::Opcode;
+use crate::types::{Function, ObjProto, RefField, RefFun, RefType, Reg, Type};
+use crate::{Bytecode, Error, Resolve, Result};
+
+/// Compile `src` to opcodes for the function `findex`. Registers needed by the code are added to the function.
+///
+/// The code is compiled in a copy of the bytecode, `code` is left untouched if it fails.
+pub fn compile(code: &mut Bytecode, findex: RefFun, src: &str) -> Result<Vec<Opcode>> {
+    let fun = position(code, findex)?;
+    let stmts = Parser::new(src)?.parse()?;
+    let mut scratch = code.clone();
+    let ops = compile_in(&mut scratch, fun, &stmts)?;
+    *code = scratch;
+    Ok(ops)
+}
+
+/// Compile `src` for the function `findex` and insert it before the opcode at `index`.
+/// See [compile] and [Function::insert_ops].
+pub fn inject(code: &mut Bytecode, findex: RefFun, index: usize, src: &str) -> Result<()> {
+    let fun = position(code, findex)?;
+    let stmts = Parser::new(src)?.parse()?;
+    let mut scratch = code.clone();
+    let ops = compile_in(&mut scratch, fun, &stmts)?;
+    scratch.functions[fun].insert_ops(index, ops)?;
+    *code = scratch;
+    Ok(())
+}
+
+/// Index of the function in the function pool
+fn position(code: &Bytecode, findex: RefFun) -> Result<usize> {
+    code.functions
+        .iter()
+        .position(|f| f.findex == findex)
+        .ok_or_else(|| Error::InvalidIndex {
+            kind: "function",
+            index: findex.0,
+            len: code.findex_max(),
+        })
+}
+
+fn compile_in(code: &mut Bytecode, fun: usize, stmts: &[(usize, Stmt)]) -> Result<Vec<Opcode>> {
+    let mut compiler = Compiler {
+        code,
+        fun,
+        ops: Vec::new(),
+        free: Vec::new(),
+        used: Vec::new(),
+        line: 1,
+    };
+    stmts.iter().try_for_each(|s| compiler.stmt(s))?;
+    Ok(compiler.ops)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Int(i32),
+    Float(f64),
+    Str(String),
+    Punct(char),
+}
+
+#[derive(Debug)]
+enum Expr {
+    Int(i32),
+    Float(f64),
+    Bool(bool),
+    Null,
+    Str(String),
+    Var(String),
+    Field(String, String),
+    Call(Callee, Vec<Expr>),
+}
+
+#[derive(Debug)]
+enum Callee {
+    /// A function or a native by name
+    Fun(String),
+    /// A method of a variable
+    Method(String, String),
+}
+
+#[derive(Debug)]
+enum Stmt {
+    Call(Callee, Vec<Expr>),
+    SetField(String, String, Expr),
+    Return(Option<Expr>),
+}
+
+fn error(line: usize, msg: impl Into<String>) -> Error {
+    Error::InvalidInjection {
+        line,
+        msg: msg.into(),
+    }
+}
+
+/// Splits the source in tokens, skipping whitespace and comments
+struct Lexer<'a> {
+    src: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    line: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(src: &'a str) -> Self {
+        Self {
+            src,
+            chars: src.char_indices().peekable(),
+            line: 1,
+        }
+    }
+
+    fn error(&self, msg: impl Into<String>) -> Error {
+        error(self.line, msg)
+    }
+
+    fn bump(&mut self) -> Option<(usize, char)> {
+        let next = self.chars.next();
+        if let Some((_, '\n')) = next {
+            self.line += 1;
+        }
+        next
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    /// The char after the next one
+    fn peek2(&self) -> Option<char> {
+        self.chars.clone().nth(1).map(|(_, c)| c)
+    }
+
+    /// Offset of the next char
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.src.len(), |&(i, _)| i)
+    }
+
+    fn eat_while(&mut self, pred: impl Fn(char) -> bool) {
+        while self.peek().is_some_and(&pred) {
+            self.bump();
+        }
+    }
+
+    fn skip_trivia(&mut self) -> Result<()> {
+        loop {
+            match (self.peek(), self.peek2()) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                }
+                (Some('/'), Some('/')) => self.eat_while(|c| c != '\n'),
+                (Some('/'), Some('*')) => {
+                    let line = self.line;
+                    self.bump();
+                    self.bump();
+                    loop {
+                        match self.bump() {
+                            Some((_, '*')) if self.peek() == Some('/') => {
+                                self.bump();
+                                break;
+                            }
+                            Some(_) => {}
+                            None => return Err(error(line, "Unterminated comment")),
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// The next token and the line it is on
+    fn token(&mut self) -> Result<Option<(usize, Token)>> {
+        self.skip_trivia()?;
+        let line = self.line;
+        let Some(c) = self.peek() else {
+            return Ok(None);
+        };
+        let token = match c {
+            '(' | ')' | ',' | '.' | '=' | ';' => {
+                self.bump();
+                Token::Punct(c)
+            }
+            '"' => self.string()?,
+            '-' if self.peek2().is_some_and(|c| c.is_ascii_digit()) => self.number()?,
+            c if c.is_ascii_digit() => self.number()?,
+            c if c.is_alphabetic() || c == '_' => {
+                let start = self.offset();
+                self.eat_while(|c| c.is_alphanumeric() || c == '_');
+                Token::Ident(self.src[start..self.offset()].to_owned())
+            }
+            c => return Err(self.error(format!("Unexpected character '{c}'"))),
+        };
+        Ok(Some((line, token)))
+    }
+
+    fn string(&mut self) -> Result<Token> {
+        self.bump();
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some((_, '"')) => return Ok(Token::Str(s)),
+                Some((_, '\\')) => match self.bump() {
+                    Some((_, 'n')) => s.push('\n'),
+                    Some((_, 'r')) => s.push('\r'),
+                    Some((_, 't')) => s.push('\t'),
+                    Some((_, c @ ('"' | '\\'))) => s.push(c),
+                    _ => return Err(self.error("Invalid escape sequence")),
+                },
+                Some((_, '\n')) | None => return Err(self.error("Unterminated string")),
+                Some((_, c)) => s.push(c),
+            }
+        }
+    }
+
+    /// Decimal or hexadecimal integer, or decimal float with an optional exponent
+    fn number(&mut self) -> Result<Token> {
+        let start = self.offset();
+        let neg = self.peek() == Some('-');
+        if neg {
+            self.bump();
+        }
+        let token = if self.peek() == Some('0') && matches!(self.peek2(), Some('x' | 'X')) {
+            self.bump();
+            self.bump();
+            let digits = self.offset();
+            self.eat_while(|c| c.is_ascii_hexdigit());
+            // 0xFFFFFFFF is -1 like in Haxe
+            u32::from_str_radix(&self.src[digits..self.offset()], 16)
+                .ok()
+                .map(|i| {
+                    Token::Int(if neg {
+                        (i as i32).wrapping_neg()
+                    } else {
+                        i as i32
+                    })
+                })
+        } else {
+            self.eat_while(|c| c.is_ascii_digit());
+            let mut float = false;
+            if self.peek() == Some('.') && self.peek2().is_some_and(|c| c.is_ascii_digit()) {
+                float = true;
+                self.bump();
+                self.eat_while(|c| c.is_ascii_digit());
+            }
+            if matches!(self.peek(), Some('e' | 'E')) {
+                float = true;
+                self.bump();
+                if matches!(self.peek(), Some('+' | '-')) {
+                    self.bump();
+                }
+                self.eat_while(|c| c.is_ascii_digit());
+            }
+            let num = &self.src[start..self.offset()];
+            if float {
+                num.parse().ok().map(Token::Float)
+            } else {
+                num.parse().ok().map(Token::Int)
+            }
+        };
+        // A number can't be followed by a name : 12ab
+        let end = self.offset();
+        self.eat_while(|c| c.is_alphanumeric() || c == '_');
+        let num = &self.src[start..self.offset()];
+        match token {
+            Some(token) if num.len() == end - start => Ok(token),
+            _ => Err(self.error(format!("Invalid number '{num}'"))),
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(src: &str) -> Result<Self> {
+        let mut lexer = Lexer::new(src);
+        let mut tokens = Vec::new();
+        while let Some(token) = lexer.token()? {
+            tokens.push(token);
+        }
+        Ok(Self { tokens, pos: 0 })
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or_else(|| self.tokens.last())
+            .map(|(line, _)| *line)
+            .unwrap_or(1)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn next_token(&mut self) -> Result<Token> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .map(|(_, t)| t.clone())
+            .ok_or_else(|| error(self.line(), "Unexpected end of input"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, punct: char) -> bool {
+        if self.peek() == Some(&Token::Punct(punct)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: char) -> Result<()> {
+        match self.next_token()? {
+            Token::Punct(c) if c == punct => Ok(()),
+            t => Err(error(self.line(), format!("Expected '{punct}', got {t:?}"))),
+        }
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.next_token()? {
+            Token::Ident(name) => Ok(name),
+            t => Err(error(self.line(), format!("Expected a name, got {t:?}"))),
+        }
+    }
+
+    /// Every statement with the line it starts on
+    fn parse(mut self) -> Result<Vec<(usize, Stmt)>> {
+        let mut stmts = Vec::new();
+        while self.peek().is_some() {
+            let line = self.line();
+            stmts.push((line, self.stmt()?));
+        }
+        Ok(stmts)
+    }
+
+    fn stmt(&mut self) -> Result<Stmt> {
+        let stmt = if self.peek() == Some(&Token::Ident("return".to_owned())) {
+            self.pos += 1;
+            if self.peek() == Some(&Token::Punct(';')) {
+                Stmt::Return(None)
+            } else {
+                Stmt::Return(Some(self.expr()?))
+            }
+        } else {
+            match self.expr()? {
+                Expr::Field(var, field) if self.eat('=') => {
+                    Stmt::SetField(var, field, self.expr()?)
+                }
+                Expr::Call(callee, args) => Stmt::Call(callee, args),
+                _ => {
+                    return Err(error(
+                        self.line(),
+                        "Only calls, field assignments and returns are statements",
+                    ))
+                }
+            }
+        };
+        self.expect(';')?;
+        Ok(stmt)
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        Ok(match self.next_token()? {
+            Token::Int(i) => Expr::Int(i),
+            Token::Float(f) => Expr::Float(f),
+            Token::Str(s) => Expr::Str(s),
+            Token::Ident(name) => match name.as_str() {
+                "true" => Expr::Bool(true),
+                "false" => Expr::Bool(false),
+                "null" => Expr::Null,
+                _ if self.eat('.') => {
+                    let member = self.ident()?;
+                    if self.eat('(') {
+                        Expr::Call(Callee::Method(name, member), self.args()?)
+                    } else {
+                        Expr::Field(name, member)
+                    }
+                }
+                _ if self.eat('(') => Expr::Call(Callee::Fun(name), self.args()?),
+                _ => Expr::Var(name),
+            },
+            t => return Err(error(self.line(), format!("Unexpected {t:?}"))),
+        })
+    }
+
+    /// Arguments of a call, after the opening parenthesis
+    fn args(&mut self) -> Result<Vec<Expr>> {
+        let mut args = Vec::new();
+        if self.eat(')') {
+            return Ok(args);
+        }
+        loop {
+            args.push(self.expr()?);
+            if self.eat(')') {
+                return Ok(args);
+            }
+            self.expect(',')?;
+        }
+    }
+}
+
+struct Compiler<'a> {
+    code: &'a mut Bytecode,
+    /// Index of the function in the function pool
+    fun: usize,
+    ops: Vec<Opcode>,
+    /// Registers added by the compiler that are free to reuse
+    free: Vec<Reg>,
+    /// Registers added by the compiler used by the current statement
+    used: Vec<Reg>,
+    line: usize,
+}
+
+impl Compiler<'_> {
+    fn f(&self) -> &Function {
+        &self.code.functions[self.fun]
+    }
+
+    fn error(&self, msg: impl Into<String>) -> Error {
+        error(self.line, msg)
+    }
+
+    /// Find a type in the type pool, adding it if it doesn't exist
+    fn ty(&mut self, ty: Type) -> RefType {
+        RefType(
+            self.code
+                .types
+                .iter()
+                .position(|t| *t == ty)
+                .unwrap_or_else(|| {
+                    self.code.types.push(ty);
+                    self.code.types.len() - 1
+                }),
+        )
+    }
+
+    /// A register of type `ty` for a temporary value
+    fn temp(&mut self, ty: RefType) -> Reg {
+        let regs = &self.code.functions[self.fun].regs;
+        let reg = match self.free.iter().position(|r| regs[r.0 as usize] == ty) {
+            Some(i) => self.free.swap_remove(i),
+            None => {
+                let regs = &mut self.code.functions[self.fun].regs;
+                regs.push(ty);
+                Reg(regs.len() as u32 - 1)
+            }
+        };
+        self.used.push(reg);
+        reg
+    }
+
+    fn var(&self, name: &str) -> Result<Reg> {
+        let code = &*self.code;
+        let f = self.f();
+        let start = usize::from(f.is_method());
+        if name == "this" {
+            return if start == 1 {
+                Ok(Reg(0))
+            } else {
+                Err(self.error("'this' is only available in methods"))
+            };
+        }
+        if let Some(reg) = name
+            .strip_prefix("reg")
+            .and_then(|i| i.parse::<usize>().ok())
+        {
+            return if reg < f.regs.len() {
+                Ok(Reg(reg as u32))
+            } else {
+                Err(self.error(format!("There are only {} registers", f.regs.len())))
+            };
+        }
+        let nargs = f.ty(code).args.len();
+        (start..nargs)
+            .find(|&i| f.arg_name(code, i - start).as_deref() == Some(name))
+            .map(|i| Reg(i as u32))
+            .ok_or_else(|| self.error(format!("Unknown variable '{name}'")))
+    }
+
+    /// Field of the object in `reg` and its type
+    fn field(&self, reg: Reg, name: &str) -> Result<(RefField, RefType)> {
+        let code = &*self.code;
+        code[self.f()[reg]]
+            .get_type_obj()
+            .and_then(|obj| {
+                obj.fields
+                    .iter()
+                    .enumerate()
+                    .find(|(_, f)| f.name(code) == name)
+                    .map(|(i, f)| (RefField(i), f.t))
+            })
+            .ok_or_else(|| self.error(format!("No field '{name}' on reg{}", reg.0)))
+    }
+
+    /// Method of the object in `reg`, searched in its parents too
+    fn method(&self, reg: Reg, name: &str) -> Result<ObjProto> {
+        let code = &*self.code;
+        let mut ty = Some(self.f()[reg]);
+        while let Some(obj) = ty.and_then(|t| code[t].get_type_obj()) {
+            if let Some(p) = obj.protos.iter().find(|p| p.name(code) == name) {
+                return Ok(p.clone());
+            }
+            ty = obj.super_;
+        }
+        Err(self.error(format!("No method '{name}' on reg{}", reg.0)))
+    }
+
+    fn function(&self, name: &str) -> Result<RefFun> {
+        let code = &*self.code;
+        code.functions
+            .iter()
+            .find(|f| f.name(code) == name)
+            .map(|f| f.findex)
+            .or_else(|| {
+                code.natives
+                    .iter()
+                    .find(|n| n.name(code) == name)
+                    .map(|n| n.findex)
+            })
+            .ok_or_else(|| self.error(format!("Unknown function '{name}'")))
+    }
+
+    /// Convert the value in `reg` so it can be stored where `ty` is expected
+    fn coerce(&mut self, reg: Reg, ty: RefType) -> Reg {
+        let from = self.f()[reg];
+        let primitive = matches!(
+            self.code[from],
+            Type::UI8 | Type::UI16 | Type::I32 | Type::I64 | Type::F32 | Type::F64 | Type::Bool
+        );
+        if from != ty && primitive && matches!(self.code[ty], Type::Dyn | Type::Null(_)) {
+            let dst = self.temp(ty);
+            self.ops.push(Opcode::ToDyn { dst, src: reg });
+            dst
+        } else {
+            reg
+        }
+    }
+
+    fn stmt(&mut self, (line, stmt): &(usize, Stmt)) -> Result<()> {
+        self.line = *line;
+        match stmt {
+            Stmt::Call(callee, args) => {
+                self.call(callee, args)?;
+            }
+            Stmt::SetField(var, field, value) => {
+                let obj = self.var(var)?;
+                let (field, ty) = self.field(obj, field)?;
+                let src = self.expr(value, Some(ty))?;
+                let src = self.coerce(src, ty);
+                self.ops.push(Opcode::SetField { obj, field, src });
+            }
+            Stmt::Return(value) => {
+                let ty = self.f().ty(&*self.code).ret;
+                let ret = match value {
+                    Some(value) => {
+                        let ret = self.expr(value, Some(ty))?;
+                        self.coerce(ret, ty)
+                    }
+                    None => {
+                        let void = self.ty(Type::Void);
+                        self.temp(void)
+                    }
+                };
+                self.ops.push(Opcode::Ret { ret });
+            }
+        }
+        // Temporary values don't outlive their statement
+        self.free.append(&mut self.used);
+        Ok(())
+    }
+
+    /// Compute an expression in a register. `expected` is the type of `null`, it is `Dynamic` by default.
+    fn expr(&mut self, expr: &Expr, expected: Option<RefType>) -> Result<Reg> {
+        Ok(match expr {
+            &Expr::Int(value) => {
+                let ty = self.ty(Type::I32);
+                let dst = self.temp(ty);
+                let ptr = self.code.add_int(value);
+                self.ops.push(Opcode::Int { dst, ptr });
+                dst
+            }
+            &Expr::Float(value) => {
+                let ty = self.ty(Type::F64);
+                let dst = self.temp(ty);
+                let ptr = self.code.add_float(value);
+                self.ops.push(Opcode::Float { dst, ptr });
+                dst
+            }
+            &Expr::Bool(value) => {
+                let ty = self.ty(Type::Bool);
+                let dst = self.temp(ty);
+                self.ops.push(Opcode::Bool { dst, value });
+                dst
+            }
+            Expr::Null => {
+                let ty = match expected {
+                    Some(ty) => ty,
+                    None => self.ty(Type::Dyn),
+                };
+                let dst = self.temp(ty);
+                self.ops.push(Opcode::Null { dst });
+                dst
+            }
+            Expr::Str(value) => self.string_expr(value)?,
+            Expr::Var(name) => self.var(name)?,
+            Expr::Field(var, field) => {
+                let obj = self.var(var)?;
+                let (field, ty) = self.field(obj, field)?;
+                let dst = self.temp(ty);
+                self.ops.push(Opcode::Field { dst, obj, field });
+                dst
+            }
+            Expr::Call(callee, args) => self.call(callee, args)?,
+        })
+    }
+
+    /// A Haxe `String` object like the Haxe compiler makes them, raw bytes if there is no `String` class
+    fn string_expr(&mut self, value: &str) -> Result<Reg> {
+        let ptr = self.code.add_string(value);
+        let ty = self.ty(Type::Bytes);
+        let bytes = self.temp(ty);
+        self.ops.push(Opcode::String { dst: bytes, ptr });
+        let code = &*self.code;
+        let Some(string) = code
+            .types
+            .iter()
+            .position(|t| matches!(t, Type::Obj(obj) if obj.name(code) == "String"))
+        else {
+            return Ok(bytes);
+        };
+        let len = self.expr(&Expr::Int(value.encode_utf16().count() as i32), None)?;
+        let dst = self.temp(RefType(string));
+        self.ops.push(Opcode::New { dst });
+        for (name, src) in [("bytes", bytes), ("length", len)] {
+            let (field, _) = self.field(dst, name)?;
+            self.ops.push(Opcode::SetField {
+                obj: dst,
+                field,
+                src,
+            });
+        }
+        Ok(dst)
+    }
+
+    fn call(&mut self, callee: &Callee, args: &[Expr]) -> Result<Reg> {
+        let (fun, obj) = match callee {
+            Callee::Fun(name) => (self.function(name)?, None),
+            Callee::Method(var, name) => {
+                let obj = self.var(var)?;
+                let proto = self.method(obj, name)?;
+                (proto.findex, Some((obj, proto.pindex)))
+            }
+        };
+        self.code.try_get(fun)?;
+        let ty = fun.ty(&*self.code).clone();
+        let mut regs: Vec<Reg> = obj.iter().map(|&(obj, _)| obj).collect();
+        if ty.args.len() != regs.len() + args.len() {
+            return Err(self.error(format!(
+                "Expected {} arguments, got {}",
+                ty.args.len() - regs.len(),
+                args.len()
+            )));
+        }
+        for (arg, &ty) in args.iter().zip(&ty.args[regs.len()..]) {
+            let reg = self.expr(arg, Some(ty))?;
+            regs.push(self.coerce(reg, ty));
+        }
+        let dst = self.temp(ty.ret);
+        self.ops.push(match obj {
+            // Virtual call, overriden methods are called
+            Some((_, pindex)) if pindex >= 0 => Opcode::CallMethod {
+                dst,
+                field: RefField(pindex as usize),
+                args: regs,
+            },
+            _ => match regs.len() {
+                0 => Opcode::Call0 { dst, fun },
+                1 => Opcode::Call1 {
+                    dst,
+                    fun,
+                    arg0: regs[0],
+                },
+                2 => Opcode::Call2 {
+                    dst,
+                    fun,
+                    arg0: regs[0],
+                    arg1: regs[1],
+                },
+                3 => Opcode::Call3 {
+                    dst,
+                    fun,
+                    arg0: regs[0],
+                    arg1: regs[1],
+                    arg2: regs[2],
+                },
+                4 => Opcode::Call4 {
+                    dst,
+                    fun,
+                    arg0: regs[0],
+                    arg1: regs[1],
+                    arg2: regs[2],
+                    arg3: regs[3],
+                },
+                _ => Opcode::CallN {
+                    dst,
+                    fun,
+                    args: regs,
+                },
+            },
+        });
+        Ok(dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::Builder;
+    use crate::inject::{compile, inject};
+    use crate::opcodes::Opcode;
+    use crate::types::{RefField, RefFun, RefType, Reg, Type};
+    use crate::{Bytecode, Error};
+
+    fn code() -> Bytecode {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        let int = b.ty(Type::I32);
+        let bytes = b.ty(Type::Bytes);
+        let string = b.class("String", None, &[("bytes", bytes), ("length", int)]);
+        let foo = b.class("Foo", None, &[("count", int)]);
+        let main = b.function("", &[foo, int], int, &[], vec![Opcode::Ret { ret: Reg(1) }]);
+        b.method(foo, "main", main);
+        let x = b.string("x");
+        b.function_mut(main).assigns = Some(vec![(x, 0)]);
+        b.native("std", "log", &[string], void);
+        b.build().unwrap()
+    }
+
+    #[test]
+    fn statements() {
+        let mut code = code();
+        inject(
+            &mut code,
+            RefFun(0),
+            0,
+            "log(\"hi\"); // String object\nthis.count = x;\nreturn 3;",
+        )
+        .unwrap();
+        let f = &code.functions[0];
+        assert_eq!(f.ops.len(), 10);
+        assert!(
+            matches!(f.ops[0], Opcode::String { dst: Reg(2), ptr } if code.strings[ptr.0] == "hi")
+        );
+        assert!(matches!(f.ops[1], Opcode::Int { dst: Reg(3), ptr } if code.ints[ptr.0] == 2));
+        assert!(matches!(f.ops[2], Opcode::New { dst: Reg(4) }));
+        assert!(matches!(
+            f.ops[4],
+            Opcode::SetField {
+                obj: Reg(4),
+                field: RefField(1),
+                src: Reg(3),
+            }
+        ));
+        assert!(matches!(
+            f.ops[5],
+            Opcode::Call1 {
+                dst: Reg(5),
+                fun: RefFun(1),
+                arg0: Reg(4),
+            }
+        ));
+        assert!(matches!(
+            f.ops[6],
+            Opcode::SetField {
+                obj: Reg(0),
+                field: RefField(0),
+                src: Reg(1),
+            }
+        ));
+        // The temporary register for the length is reused
+        assert!(matches!(f.ops[7], Opcode::Int { dst: Reg(3), ptr } if code.ints[ptr.0] == 3));
+        assert!(matches!(f.ops[8], Opcode::Ret { ret: Reg(3) }));
+        assert_eq!(f.regs.len(), 6);
+        assert_eq!(f.regs[5], RefType(0));
+    }
+
+    #[test]
+    fn errors() {
+        let mut code = code();
+        let err = compile(&mut code, RefFun(0), "log(1, 2);").unwrap_err();
+        assert!(matches!(err, Error::InvalidInjection { line: 1, .. }));
+        let err = compile(&mut code, RefFun(0), "this.count = 1;\nunknown();").unwrap_err();
+        assert!(matches!(err, Error::InvalidInjection { line: 2, .. }));
+        assert!(compile(&mut code, RefFun(0), "x;").is_err());
+        assert!(compile(&mut code, RefFun(0), "log(\"hi);").is_err());
+        assert!(compile(&mut code, RefFun(1), "return;").is_err());
+        // Failed compilations don't change anything
+        assert_eq!(code.functions[0].regs.len(), 2);
+        let (ints, strings, types) = (code.ints.len(), code.strings.len(), code.types.len());
+        assert!(inject(&mut code, RefFun(0), 0, "log(\"unused\");\nunknown();").is_err());
+        assert_eq!(code.functions[0].ops.len(), 1);
+        assert_eq!(code.functions[0].regs.len(), 2);
+        assert_eq!(
+            (code.ints.len(), code.strings.len(), code.types.len()),
+            (ints, strings, types)
+        );
+    }
+
+    #[test]
+    fn lexer() {
+        let mut code = code();
+        let ops = compile(
+            &mut code,
+            RefFun(0),
+            "log(\"http://a; \\\"b\\\"\"); /* this.count = 1;\n*/ return -0x10;",
+        )
+        .unwrap();
+        assert!(
+            matches!(ops[0], Opcode::String { ptr, .. } if code.strings[ptr.0] == "http://a; \"b\"")
+        );
+        assert!(
+            matches!(ops.last(), Some(Opcode::Ret { .. })) && code.ints.contains(&-16),
+            "{ops:?}"
+        );
+        for src in ["return 12ab;", "return 0x;", "log(\"a\nb\");", "/* return;"] {
+            assert!(
+                matches!(
+                    compile(&mut code, RefFun(0), src),
+                    Err(Error::InvalidInjection { .. })
+                ),
+                "{src}"
+            );
+        }
+    }
+}
@@ -0,0 +1,332 @@
+//! Byte ranges of the structures of a bytecode file, to see where a function or a string lives in the file.
+//!
+//! The file is parsed again without building the [Bytecode], only the offsets of the tables and their elements are kept.
+
+use std::fmt;
+use std::io::Read;
+use std::ops::Range;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::read::read_varu;
+use crate::types::{ConstantDef, Function, Native, RefFun, RefType, Type};
+use crate::{Bytecode, Error, RefFunKnown, Result, Section};
+
+/// A table of the bytecode file, in file order
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Table {
+    /// Magic header, version, flags, size of the other tables and entrypoint
+    Header,
+    Ints,
+    Floats,
+    /// The strings blob and the length of each string
+    Strings,
+    /// The bytes blob and the position of each element, since bytecode v5
+    Bytes,
+    /// Source files of the debug info, stored like the strings
+    DebugFiles,
+    Types,
+    Globals,
+    Natives,
+    Functions,
+    /// Globals initializers, since bytecode v4
+    Constants,
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Table::Header => "header",
+            Table::Ints => "ints",
+            Table::Floats => "floats",
+            Table::Strings => "strings",
+            Table::Bytes => "bytes",
+            Table::DebugFiles => "debug files",
+            Table::Types => "types",
+            Table::Globals => "globals",
+            Table::Natives => "natives",
+            Table::Functions => "functions",
+            Table::Constants => "constants",
+        })
+    }
+}
+
+/// Where a table and its elements are in the file
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TableLayout {
+    pub table: Table,
+    /// Range of the whole table
+    pub range: Range<usize>,
+    /// Range of each element, indexed like the table in [Bytecode]. The elements of the blob tables (strings, bytes,
+    /// debug files) are the bytes of their content.
+    pub elements: Vec<Range<usize>>,
+}
+
+/// Byte ranges of the structures of a bytecode file, see [FileLayout::parse]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct FileLayout {
+    /// Offset of the magic header, not 0 for bytecode embedded in another file
+    pub offset: usize,
+    /// Offset after the last table
+    pub end: usize,
+    /// The tables present in the file, in file order
+    pub tables: Vec<TableLayout>,
+}
+
+impl FileLayout {
+    /// Map the structures of the bytecode in `data` to their byte ranges. Like with [Bytecode::from_embedded], the
+    /// bytecode can be embedded in a bigger file.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut error = Error::MalformedBytecode("Magic header not found".to_owned());
+        for offset in Bytecode::embedded_offsets(data) {
+            match Self::parse_at(data, offset) {
+                Ok(layout) => return Ok(layout),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    fn parse_at(data: &[u8], offset: usize) -> Result<Self> {
+        let c = &mut Cursor { data, pos: offset };
+        let mut header = [0u8; 4];
+        c.read_exact(&mut header)?;
+        let version = header[3];
+        let flags = read_varu(c)?;
+        let has_debug = flags & 1 == 1;
+        let nints = read_varu(c)? as usize;
+        let nfloats = read_varu(c)? as usize;
+        let nstrings = read_varu(c)? as usize;
+        let nbytes = if version >= Section::Bytes.since() {
+            Some(read_varu(c)? as usize)
+        } else {
+            None
+        };
+        let ntypes = read_varu(c)? as usize;
+        let nglobals = read_varu(c)? as usize;
+        let nnatives = read_varu(c)? as usize;
+        let nfunctions = read_varu(c)? as usize;
+        let nconstants = if version >= Section::Constants.since() {
+            Some(read_varu(c)? as usize)
+        } else {
+            None
+        };
+        RefFun::read(c)?;
+
+        let mut tables = vec![TableLayout {
+            table: Table::Header,
+            range: offset..c.pos,
+            elements: Vec::new(),
+        }];
+        tables.push(c.table(Table::Ints, |c| c.fixed(nints, 4))?);
+        tables.push(c.table(Table::Floats, |c| c.fixed(nfloats, 8))?);
+        tables.push(c.table(Table::Strings, |c| c.strings(nstrings))?);
+        if let Some(n) = nbytes {
+            tables.push(c.table(Table::Bytes, |c| c.bytes(n))?);
+        }
+        if has_debug {
+            tables.push(c.table(Table::DebugFiles, |c| {
+                let n = read_varu(c)? as usize;
+                c.strings(n)
+            })?);
+        }
+        tables.push(c.table(Table::Types, |c| c.each(ntypes, Type::read))?);
+        tables.push(c.table(Table::Globals, |c| c.each(nglobals, RefType::read))?);
+        tables.push(c.table(Table::Natives, |c| c.each(nnatives, Native::read))?);
+        tables.push(c.table(Table::Functions, |c| {
+            c.each(nfunctions, |c| Function::read(c, has_debug, version))
+        })?);
+        if let Some(n) = nconstants {
+            tables.push(c.table(Table::Constants, |c| c.each(n, ConstantDef::read))?);
+        }
+
+        Ok(FileLayout {
+            offset,
+            end: c.pos,
+            tables,
+        })
+    }
+
+    pub fn table(&self, table: Table) -> Option<&TableLayout> {
+        self.tables.iter().find(|t| t.table == table)
+    }
+
+    /// Range of an element of a table
+    pub fn element(&self, table: Table, index: usize) -> Option<Range<usize>> {
+        self.table(table)?.elements.get(index).cloned()
+    }
+
+    /// Range of a function or a native
+    pub fn function(&self, code: &Bytecode, findex: RefFun) -> Option<Range<usize>> {
+        match code.findexes.get(findex.0)? {
+            RefFunKnown::Fun(i) => self.element(Table::Functions, *i),
+            RefFunKnown::Native(i) => self.element(Table::Natives, *i),
+        }
+    }
+
+    /// The table and the element containing the byte at `offset`
+    pub fn at(&self, offset: usize) -> Option<(Table, Option<usize>)> {
+        let table = self.tables.iter().find(|t| t.range.contains(&offset))?;
+        let index = table.elements.iter().position(|e| e.contains(&offset));
+        Some((table.table, index))
+    }
+}
+
+/// Reader keeping track of its position in the file
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Read for Cursor<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.data.get(self.pos..).unwrap_or_default().read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Cursor<'_> {
+    fn skip(&mut self, n: usize) -> Result<()> {
+        if n > self.data.len() - self.pos {
+            return Err(Error::MalformedBytecode(format!(
+                "Table of {n} bytes at {} goes past the end of the file",
+                self.pos
+            )));
+        }
+        self.pos += n;
+        Ok(())
+    }
+
+    fn table(
+        &mut self,
+        table: Table,
+        elements: impl FnOnce(&mut Self) -> Result<Vec<Range<usize>>>,
+    ) -> Result<TableLayout> {
+        let start = self.pos;
+        let elements = elements(self)?;
+        Ok(TableLayout {
+            table,
+            range: start..self.pos,
+            elements,
+        })
+    }
+
+    /// `n` elements of `size` bytes
+    fn fixed(&mut self, n: usize, size: usize) -> Result<Vec<Range<usize>>> {
+        let start = self.pos;
+        self.skip(n.saturating_mul(size))?;
+        Ok((0..n)
+            .map(|i| start + i * size..start + (i + 1) * size)
+            .collect())
+    }
+
+    fn each<T>(
+        &mut self,
+        n: usize,
+        mut read: impl FnMut(&mut Self) -> Result<T>,
+    ) -> Result<Vec<Range<usize>>> {
+        let mut elements = Vec::with_capacity(n);
+        for _ in 0..n {
+            let start = self.pos;
+            read(self)?;
+            elements.push(start..self.pos);
+        }
+        Ok(elements)
+    }
+
+    /// Strings stored in one blob separated by a nul byte, followed by their lengths
+    fn strings(&mut self, n: usize) -> Result<Vec<Range<usize>>> {
+        let size = self.read_i32::<LittleEndian>()? as usize;
+        let blob = self.pos;
+        self.skip(size)?;
+        let mut elements = Vec::with_capacity(n);
+        let mut acc = 0;
+        for _ in 0..n {
+            let len = read_varu(self)? as usize;
+            if acc + len > size {
+                return Err(Error::MalformedBytecode(format!(
+                    "String of {len} bytes at {acc} out of the strings blob ({size} bytes)"
+                )));
+            }
+            elements.push(blob + acc..blob + acc + len);
+            acc += len + 1;
+        }
+        Ok(elements)
+    }
+
+    /// Bytes stored in one blob, followed by the position of each element. An element ends where the next one in the
+    /// blob starts.
+    fn bytes(&mut self, n: usize) -> Result<Vec<Range<usize>>> {
+        let size = self.read_i32::<LittleEndian>()? as usize;
+        let blob = self.pos;
+        self.skip(size)?;
+        let mut positions = Vec::with_capacity(n);
+        for _ in 0..n {
+            positions.push((read_varu(self)? as usize).min(size));
+        }
+        let mut sorted = positions.clone();
+        sorted.sort_unstable();
+        Ok(positions
+            .into_iter()
+            .map(|p| {
+                let end = sorted[sorted.partition_point(|&s| s <= p)..]
+                    .first()
+                    .copied()
+                    .unwrap_or(size);
+                blob + p..blob + end
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::Builder;
+    use crate::layout::{FileLayout, Table};
+    use crate::opcodes::Opcode;
+    use crate::types::{Reg, Type};
+
+    #[test]
+    fn layout() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        b.int(1);
+        b.int(2);
+        let main = b.function(
+            "main",
+            &[],
+            void,
+            &[void],
+            vec![Opcode::Ret { ret: Reg(0) }],
+        );
+        b.string("hello");
+        // Not in order
+        b.code_mut().bytes = Some((b"abcde".to_vec(), vec![3, 0]));
+        let code = b.build().unwrap();
+        let mut data = b"MZ..".to_vec();
+        code.serialize(&mut data).unwrap();
+
+        let layout = FileLayout::parse(&data).unwrap();
+        assert_eq!(layout.offset, 4);
+        assert_eq!(layout.end, data.len());
+        assert_eq!(layout.tables[0].range.start, 4);
+        // The tables follow each other
+        for pair in layout.tables.windows(2) {
+            assert_eq!(pair[0].range.end, pair[1].range.start);
+        }
+
+        let hello = layout.element(Table::Strings, 2).unwrap();
+        assert_eq!(&data[hello.clone()], b"hello");
+        assert_eq!(layout.at(hello.start + 1), Some((Table::Strings, Some(2))));
+        assert_eq!(layout.element(Table::Ints, 1).unwrap().len(), 4);
+        assert_eq!(&data[layout.element(Table::Bytes, 0).unwrap()], b"de");
+        assert_eq!(&data[layout.element(Table::Bytes, 1).unwrap()], b"abc");
+
+        let main = layout.function(&code, main).unwrap();
+        assert_eq!(Some(main.clone()), layout.element(Table::Functions, 0));
+        assert_eq!(main.end, layout.table(Table::Functions).unwrap().range.end);
+        assert_eq!(layout.at(1), None);
+        assert!(FileLayout::parse(&data[..data.len() - 1]).is_err());
+    }
+}
@@ -13,16 +13,35 @@ use std::ops::Index;
 
 use crate::opcodes::Opcode;
 use crate::types::{
-    ConstantDef, FunPtr, Function, Native, ObjField, RefFloat, RefFun, RefGlobal, RefInt,
-    RefString, RefType, Type, TypeObj,
+    ConstantDef, ConstantValue, FunPtr, Function, Native, ObjField, RefBytes, RefFloat, RefFun,
+    RefGlobal, RefInt, RefString, RefType, Type, TypeObj,
 };
 
+#[cfg(feature = "analysis")]
 pub mod analysis;
+#[cfg(feature = "disasm")]
+pub mod asm;
+/// Build programs in memory, for tests
+#[cfg(any(test, feature = "test-utils"))]
+pub mod builder;
+/// Stopping long running operations early
+pub mod cancel;
+#[cfg(feature = "disasm")]
 pub mod fmt;
+pub mod inject;
+pub mod layout;
 /// Opcodes definitions.
 pub mod opcodes;
+pub mod page;
+pub mod patch;
 /// All about reading bytecode
 mod read;
+/// Cheaply cloneable string type
+mod string;
+/// Custom names for the elements of a program
+pub mod symbols;
+#[cfg(feature = "analysis")]
+pub mod translation;
 /// Bytecode elements definitions.
 /// All the Ref* types in this modules are references to bytecode elements like constants or function.
 /// They are required since we cannot use rust references as that would make our structure self-referential.
@@ -31,11 +50,7 @@ pub mod types;
 /// All about writing bytecode
 mod write;
 
-/// Cheaply cloneable string with inline storage
-// pub type Str = smol_str::SmolStr;
-// pub type Str = kstring::KStringBase<kstring::backend::RcStr>;
-pub type Str = flexstr::SharedStr;
-// pub type Str = String;
+pub use string::Str;
 
 pub type Result<T> = core::result::Result<T, Error>;
 
@@ -45,19 +60,42 @@ pub enum Error {
     MalformedBytecode(String),
     #[error("Unsupported bytecode version {version} (expected {min} <= version <= {max})")]
     UnsupportedVersion { version: u8, min: u8, max: u8 },
+    #[error("Bytecode version {version} doesn't match its sections: {problems}")]
+    VersionMismatch { version: u8, problems: String },
     #[error("Value '{value}' is too big to be serialized (|expected| < {limit})")]
     ValueOutOfBounds { value: i32, limit: u32 },
+    #[error("Invalid assembly at line {line}: {msg}")]
+    InvalidAsm { line: usize, msg: String },
+    #[error("Invalid translation file at line {line}: {msg}")]
+    InvalidTranslation { line: usize, msg: String },
+    #[error("Invalid injected code at line {line}: {msg}")]
+    InvalidInjection { line: usize, msg: String },
+    #[error("Invalid {kind} index {index} (there are {len})")]
+    InvalidIndex {
+        kind: &'static str,
+        index: usize,
+        len: usize,
+    },
+    #[error("Operation cancelled")]
+    Cancelled,
     #[error(transparent)]
     IoError(#[from] std::io::Error),
     #[error(transparent)]
     Utf8Error(#[from] core::str::Utf8Error),
+    #[cfg(feature = "serde")]
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
 }
 
 /// Bytecode structure containing all the information.
 /// Every field is public for flexibility, but you aren't encouraged to modify them.
 ///
 /// This type is like an arena, you usually work with custom
-#[derive(Debug)]
+///
+/// There is no interior mutability, lookup structures are built once when loading.
+/// A loaded bytecode is `Send + Sync`, threads can share it by reference instead of cloning it.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Bytecode {
     /// Bytecode format version
     pub version: u8,
@@ -91,13 +129,101 @@ pub struct Bytecode {
     // Fields below are not part of the data.
     // Those are acceleration structures used to speed up lookup.
     /// Acceleration structure mapping function references (findex) to functions indexes in the native or function pool.
+    #[cfg_attr(feature = "serde", serde(skip))]
     findexes: Vec<RefFunKnown>,
     /// Acceleration structure mapping function names to function indexes in the function pool
+    #[cfg_attr(feature = "serde", serde(skip))]
     fnames: HashMap<Str, usize>,
+    /// Names inferred for the anonymous functions by [Bytecode::name_closures], only used for display
+    #[cfg_attr(feature = "serde", serde(skip))]
+    closure_names: HashMap<RefFun, Str>,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub globals_initializers: HashMap<RefGlobal, usize>,
 }
 
+// Shared between threads by the gui, the cli and batch jobs, this must keep compiling
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Bytecode>();
+};
+
+#[cfg(feature = "analysis")]
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<analysis::usage::FullUsageReport>();
+    assert_send_sync::<analysis::usage::UsageIndex>();
+    assert_send_sync::<analysis::cfg::Cfg>();
+    assert_send_sync::<analysis::capabilities::Capabilities>();
+};
+
+/// A section of the bytecode format which is only present since some version
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Section {
+    /// [Bytecode::constants]
+    Constants,
+    /// [Bytecode::bytes]
+    Bytes,
+}
+
+impl Section {
+    pub const ALL: [Section; 2] = [Section::Constants, Section::Bytes];
+
+    /// First format version with this section
+    pub const fn since(self) -> u8 {
+        match self {
+            Section::Constants => 4,
+            Section::Bytes => 5,
+        }
+    }
+}
+
+impl std::fmt::Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Section::Constants => "constants",
+            Section::Bytes => "bytes pool",
+        })
+    }
+}
+
 impl Bytecode {
+    /// Oldest bytecode format version supported
+    pub const MIN_VERSION: u8 = 4;
+    /// Latest bytecode format version supported
+    pub const MAX_VERSION: u8 = 5;
+
+    /// The section is present
+    pub fn has_section(&self, section: Section) -> bool {
+        match section {
+            Section::Constants => self.constants.is_some(),
+            Section::Bytes => self.bytes.is_some(),
+        }
+    }
+
+    /// Check the sections present are exactly the ones of the format version, or the bytecode would be written
+    /// incorrectly. The error lists every mismatch.
+    pub fn check_sections(&self) -> Result<()> {
+        let problems: Vec<String> = Section::ALL
+            .iter()
+            .filter_map(|&s| {
+                let since = s.since();
+                match (self.version >= since, self.has_section(s)) {
+                    (true, false) => Some(format!("missing {s} (required since v{since})")),
+                    (false, true) => Some(format!("{s} (unsupported before v{since})")),
+                    _ => None,
+                }
+            })
+            .collect();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::VersionMismatch {
+                version: self.version,
+                problems: problems.join(", "),
+            })
+        }
+    }
+
     /// Get the entrypoint function.
     pub fn entrypoint(&self) -> &Function {
         self.get(self.entrypoint).as_fn().unwrap()
@@ -118,6 +244,7 @@ impl Bytecode {
         self.findexes.len()
     }
 
+    /// Iterate on all functions and natives, ordered by findex.
     pub fn functions(&self) -> impl Iterator<Item = FunPtr<'_>> {
         (0..self.findex_max()).map(RefFun).map(|r| self.get(r))
     }
@@ -125,6 +252,59 @@ impl Bytecode {
     pub fn debug_file(&self, index: usize) -> Option<Str> {
         self.debug_files.as_ref().map(|files| files[index].clone())
     }
+
+    /// The constant initializing a global, if any.
+    ///
+    /// *Since bytecode v4*
+    pub fn global_constant(&self, global: RefGlobal) -> Option<&ConstantDef> {
+        let &i = self.globals_initializers.get(&global)?;
+        self.constants.as_ref()?.get(i)
+    }
+
+    /// The fields of the constant initializing a global, resolved in the constant pools according to the fields of
+    /// the global type like the runtime does.
+    pub fn global_constant_values(&self, global: RefGlobal) -> Option<Vec<ConstantValue>> {
+        let constant = self.global_constant(global)?;
+        let obj = self.globals.get(global.0)?.as_obj(self)?;
+        constant
+            .fields
+            .iter()
+            .zip(&obj.fields)
+            .map(|(&v, field)| {
+                Some(match self.types.get(field.t.0)? {
+                    Type::UI8 | Type::UI16 | Type::I32 | Type::I64 => ConstantValue::Int(RefInt(v)),
+                    Type::F32 | Type::F64 => ConstantValue::Float(RefFloat(v)),
+                    Type::Bool => ConstantValue::Bool(v != 0),
+                    Type::Bytes => ConstantValue::String(RefString(v)),
+                    Type::Type => ConstantValue::Type(RefType(v)),
+                    _ => ConstantValue::Global(RefGlobal(v)),
+                })
+            })
+            .collect()
+    }
+
+    /// The string a global is initialized with, if it holds a string constant
+    pub fn global_string(&self, global: RefGlobal) -> Option<RefString> {
+        let is_string = self
+            .globals
+            .get(global.0)?
+            .as_obj(self)
+            .is_some_and(|obj| obj.name(self) == "String");
+        if !is_string {
+            return None;
+        }
+        match self.global_constant_values(global)?.first()? {
+            &ConstantValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Serialize the whole bytecode structure to json. References to other elements are serialized as their index in
+    /// the corresponding pool.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
 }
 
 impl Default for Bytecode {
@@ -144,6 +324,7 @@ impl Default for Bytecode {
             constants: None,
             findexes: vec![],
             fnames: Default::default(),
+            closure_names: Default::default(),
             globals_initializers: Default::default(),
         }
     }
@@ -165,6 +346,19 @@ pub trait Resolve<I> {
         Self: 'a;
 
     fn get(&self, index: I) -> Self::Output<'_>;
+
+    /// Checked version of [Resolve::get], returns [Error::InvalidIndex] instead of panicking when the index is out of
+    /// range, like in a malformed file.
+    fn try_get(&self, index: I) -> Result<Self::Output<'_>>;
+}
+
+/// Element of a pool, or an error naming the kind of element
+fn checked<'a, T>(pool: &'a [T], kind: &'static str, index: usize) -> Result<&'a T> {
+    pool.get(index).ok_or(Error::InvalidIndex {
+        kind,
+        index,
+        len: pool.len(),
+    })
 }
 
 impl Resolve<RefInt> for Bytecode {
@@ -173,6 +367,10 @@ impl Resolve<RefInt> for Bytecode {
     fn get(&self, index: RefInt) -> Self::Output<'_> {
         self.ints[index.0]
     }
+
+    fn try_get(&self, index: RefInt) -> Result<Self::Output<'_>> {
+        checked(&self.ints, "int", index.0).copied()
+    }
 }
 
 impl Resolve<RefFloat> for Bytecode {
@@ -181,6 +379,10 @@ impl Resolve<RefFloat> for Bytecode {
     fn get(&self, index: RefFloat) -> Self::Output<'_> {
         self.floats[index.0]
     }
+
+    fn try_get(&self, index: RefFloat) -> Result<Self::Output<'_>> {
+        checked(&self.floats, "float", index.0).copied()
+    }
 }
 
 impl Resolve<RefString> for Bytecode {
@@ -193,6 +395,40 @@ impl Resolve<RefString> for Bytecode {
             Str::from_static("<none>")
         }
     }
+
+    fn try_get(&self, index: RefString) -> Result<Self::Output<'_>> {
+        if index.0 > 0 {
+            checked(&self.strings, "string", index.0).cloned()
+        } else {
+            Ok(Str::from_static("<none>"))
+        }
+    }
+}
+
+impl Resolve<RefBytes> for Bytecode {
+    type Output<'a> = &'a [u8];
+
+    fn get(&self, index: RefBytes) -> Self::Output<'_> {
+        let (bytes, pos) = self.bytes.as_ref().unwrap();
+        let end = pos.get(index.0 + 1).copied().unwrap_or(bytes.len());
+        &bytes[pos[index.0]..end]
+    }
+
+    fn try_get(&self, index: RefBytes) -> Result<Self::Output<'_>> {
+        let (bytes, pos) = match &self.bytes {
+            Some((bytes, pos)) => (bytes.as_slice(), pos.as_slice()),
+            None => (&[][..], &[][..]),
+        };
+        let start = *checked(pos, "bytes", index.0)?;
+        let end = pos.get(index.0 + 1).copied().unwrap_or(bytes.len());
+        bytes.get(start..end).ok_or_else(|| {
+            Error::MalformedBytecode(format!(
+                "bytes@{} spans {start}..{end} out of {} bytes",
+                index.0,
+                bytes.len()
+            ))
+        })
+    }
 }
 
 impl Resolve<RefType> for Bytecode {
@@ -201,6 +437,10 @@ impl Resolve<RefType> for Bytecode {
     fn get(&self, index: RefType) -> Self::Output<'_> {
         &self.types[index.0]
     }
+
+    fn try_get(&self, index: RefType) -> Result<Self::Output<'_>> {
+        checked(&self.types, "type", index.0)
+    }
 }
 
 impl Resolve<RefGlobal> for Bytecode {
@@ -209,6 +449,10 @@ impl Resolve<RefGlobal> for Bytecode {
     fn get(&self, index: RefGlobal) -> Self::Output<'_> {
         &self.globals[index.0]
     }
+
+    fn try_get(&self, index: RefGlobal) -> Result<Self::Output<'_>> {
+        checked(&self.globals, "global", index.0)
+    }
 }
 
 impl Resolve<RefFun> for Bytecode {
@@ -220,6 +464,13 @@ impl Resolve<RefFun> for Bytecode {
             RefFunKnown::Native(n) => FunPtr::Native(&self.natives[n]),
         }
     }
+
+    fn try_get(&self, index: RefFun) -> Result<Self::Output<'_>> {
+        Ok(match *checked(&self.findexes, "function", index.0)? {
+            RefFunKnown::Fun(fun) => FunPtr::Fun(checked(&self.functions, "function", fun)?),
+            RefFunKnown::Native(n) => FunPtr::Native(checked(&self.natives, "native", n)?),
+        })
+    }
 }
 
 //endregion
@@ -267,3 +518,134 @@ impl Index<RefGlobal> for Bytecode {
 }
 
 //endregion
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::Builder;
+    use crate::types::{RefBytes, RefFun, RefString, RefType, Type};
+    use crate::{Bytecode, Error, Resolve};
+
+    #[test]
+    fn try_get() {
+        let mut b = Builder::new();
+        b.ty(Type::Void);
+        let code = b.build().unwrap();
+        assert!(matches!(code.try_get(RefType(0)), Ok(Type::Void)));
+        assert!(matches!(
+            code.try_get(RefType(1)),
+            Err(Error::InvalidIndex {
+                kind: "type",
+                index: 1,
+                len: 1
+            })
+        ));
+        assert_eq!(&*code.try_get(RefString(0)).unwrap(), "<none>");
+        assert!(code.try_get(RefString(3)).is_err());
+        assert!(code.try_get(RefFun(0)).is_err());
+        assert!(RefFun(0).try_as_fn(&code).is_err());
+        assert!(RefType(5).as_obj(&code).is_none());
+    }
+
+    #[test]
+    fn try_get_bytes() {
+        let mut b = Builder::new();
+        b.ty(Type::Void);
+        assert!(matches!(
+            b.build().unwrap().try_get(RefBytes(0)),
+            Err(Error::InvalidIndex { kind: "bytes", .. })
+        ));
+
+        let mut b = Builder::new();
+        let hello = b.bytes(b"hello");
+        let world = b.bytes(b"world");
+        let mut code = b.build().unwrap();
+        assert_eq!(code.try_get(hello).unwrap(), b"hello");
+        assert_eq!(code.get(world), b"world");
+        assert!(matches!(
+            code.try_get(RefBytes(2)),
+            Err(Error::InvalidIndex {
+                kind: "bytes",
+                index: 2,
+                len: 2
+            })
+        ));
+        code.bytes.as_mut().unwrap().1[1] = 100;
+        assert!(matches!(
+            code.try_get(world),
+            Err(Error::MalformedBytecode(_))
+        ));
+    }
+
+    fn sample() -> Bytecode {
+        use crate::opcodes::Opcode;
+        use crate::types::Reg;
+
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        b.function("main", &[], void, &[void], vec![Opcode::Ret { ret: Reg(0) }]);
+        b.build().unwrap()
+    }
+
+    #[test]
+    fn sections() {
+        let mut code = sample();
+        assert!(code.check_sections().is_ok());
+        code.version = 4;
+        let mut buf = Vec::new();
+        assert!(matches!(
+            code.serialize(&mut buf),
+            Err(Error::VersionMismatch { version: 4, .. })
+        ));
+        assert!(buf.is_empty());
+        code.bytes = None;
+        code.serialize(&mut buf).unwrap();
+        assert_eq!(Bytecode::deserialize(&buf[..]).unwrap().version, 4);
+
+        code.constants = None;
+        code.version = 5;
+        let err = code.check_sections().unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Bytecode version 5 doesn't match its sections: missing constants (required since v4), \
+            missing bytes pool (required since v5)"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn to_json() {
+        let json = sample().to_json().unwrap();
+        assert!(json.contains(r#""strings":["","main"]"#));
+        assert!(json.contains(r#""types":["Void",{"Fun":{"args":[],"ret":0}}]"#));
+        assert!(json.contains(r#""ops":[{"Ret":{"ret":0}}]"#));
+        assert!(!json.contains("findexes"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn from_json() {
+        use crate::opcodes::Opcode;
+
+        let json = sample().to_json().unwrap();
+        let code = Bytecode::from_json(&json).unwrap();
+        assert!(RefFun(0).try_as_fn(&code).is_ok());
+        let main = code.function_by_name("main").unwrap();
+        assert!(matches!(main.ops[..], [Opcode::Ret { .. }]));
+
+        // Back to the binary format
+        let mut bin = Vec::new();
+        code.serialize(&mut bin).unwrap();
+        let code = Bytecode::deserialize(&bin[..]).unwrap();
+        assert_eq!(code.functions.len(), 1);
+
+        let json = json.replace(r#""findex":0"#, r#""findex":3"#);
+        assert!(matches!(
+            Bytecode::from_json(&json),
+            Err(Error::InvalidIndex { kind: "findex", .. })
+        ));
+        assert!(matches!(
+            Bytecode::from_json("{}"),
+            Err(Error::JsonError(_))
+        ));
+    }
+}
@@ -1,3 +1,5 @@
+use smallvec::{smallvec, SmallVec};
+
 use crate::types::{
     InlineBool, InlineInt, JumpOffset, RefBytes, RefEnumConstruct, RefField, RefFloat, RefFun,
     RefGlobal, RefInt, RefString, RefType, Reg,
@@ -11,6 +13,7 @@ use crate::types::{
 ///
 /// The order of opcodes here is important as it defines the number used for serialization.
 #[derive(Debug, Clone, hlbc_derive::OpcodeHelper)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Opcode {
     /// Copy value from *src* into *dst*
     ///
@@ -750,10 +753,89 @@ pub enum Opcode {
     },
 }
 
+impl Opcode {
+    /// Registers read by this opcode
+    pub fn read_regs(&self) -> Vec<Reg> {
+        match self {
+            // Read then written
+            Opcode::Incr { dst } | Opcode::Decr { dst } => vec![*dst],
+            // The register is a reference to the value being written
+            Opcode::Setref { dst, value } => vec![*dst, *value],
+            // The exception register is only written, by Trap when an exception is caught
+            Opcode::Trap { .. } | Opcode::EndTrap { .. } | Opcode::Asm { .. } => Vec::new(),
+            _ => self
+                .registers()
+                .into_iter()
+                .filter(|&(name, _)| name != "dst")
+                .map(|(_, reg)| reg)
+                .collect(),
+        }
+    }
+
+    /// Absolute positions this opcode can jump to when it is at `pos` : the target of a jump, the cases of a switch
+    /// or the exception handler of a trap. Falling through to `pos + 1` isn't included.
+    ///
+    /// There is one target per offset, in order, a switch has one target per case. Malformed jumps give positions
+    /// outside of the function.
+    pub fn jump_targets(&self, pos: usize) -> SmallVec<[usize; 2]> {
+        let target = |offset: JumpOffset| (pos as isize + offset as isize + 1) as usize;
+        match self {
+            Opcode::JTrue { offset, .. }
+            | Opcode::JFalse { offset, .. }
+            | Opcode::JNull { offset, .. }
+            | Opcode::JNotNull { offset, .. }
+            | Opcode::JSLt { offset, .. }
+            | Opcode::JSGte { offset, .. }
+            | Opcode::JSGt { offset, .. }
+            | Opcode::JSLte { offset, .. }
+            | Opcode::JULt { offset, .. }
+            | Opcode::JUGte { offset, .. }
+            | Opcode::JNotLt { offset, .. }
+            | Opcode::JNotGte { offset, .. }
+            | Opcode::JEq { offset, .. }
+            | Opcode::JNotEq { offset, .. }
+            | Opcode::JAlways { offset }
+            | Opcode::Trap { offset, .. } => smallvec![target(*offset)],
+            Opcode::Switch { offsets, .. } => offsets.iter().map(|&o| target(o)).collect(),
+            _ => SmallVec::new(),
+        }
+    }
+
+    /// Register written by this opcode. `Trap` writes the exception register when an exception is caught.
+    pub fn written_reg(&self) -> Option<Reg> {
+        match self {
+            Opcode::Setref { .. } => None,
+            Opcode::Trap { exc, .. } => Some(*exc),
+            _ => self
+                .registers()
+                .into_iter()
+                .find(|&(name, _)| name == "dst")
+                .map(|(_, reg)| reg),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::opcodes::Opcode;
-    use crate::types::Reg;
+    use crate::types::{RefFun, Reg};
+
+    #[test]
+    fn jump_targets() {
+        let jfalse = Opcode::JFalse {
+            cond: Reg(0),
+            offset: 2,
+        };
+        assert_eq!(jfalse.jump_targets(0).as_slice(), [3]);
+        assert!(!jfalse.jump_targets(0).spilled());
+        let switch = Opcode::Switch {
+            reg: Reg(0),
+            offsets: vec![1, -3],
+            end: 2,
+        };
+        assert_eq!(switch.jump_targets(5).as_slice(), [7, 3]);
+        assert!(Opcode::Ret { ret: Reg(0) }.jump_targets(0).is_empty());
+    }
 
     #[test]
     fn test_doc() {
@@ -770,4 +852,26 @@ mod test {
             Opcode::Null { dst: Reg(0) }.description()
         );
     }
+
+    #[test]
+    fn registers() {
+        let op = Opcode::CallN {
+            dst: Reg(0),
+            fun: RefFun(0),
+            args: vec![Reg(1), Reg(2)],
+        };
+        assert_eq!(
+            op.registers(),
+            [("dst", Reg(0)), ("args", Reg(1)), ("args", Reg(2))]
+        );
+        assert_eq!(op.read_regs(), [Reg(1), Reg(2)]);
+        assert_eq!(op.written_reg(), Some(Reg(0)));
+        let op = Opcode::Incr { dst: Reg(3) };
+        assert_eq!(op.read_regs(), [Reg(3)]);
+        assert_eq!(op.written_reg(), Some(Reg(3)));
+        let op = Opcode::EndTrap { exc: Reg(1) };
+        assert!(op.read_regs().is_empty());
+        assert_eq!(op.written_reg(), None);
+        assert!(Opcode::Label.registers().is_empty());
+    }
 }
@@ -0,0 +1,99 @@
+//! Paging through listings.
+//!
+//! Big games contain hundreds of thousands of functions and strings. Every listing in this crate iterates in a
+//! stable order (the order of the elements in the bytecode, by index), so a result set can be browsed one [Page]
+//! at a time without collecting it first.
+//!
+//! ```
+//! use hlbc::page::{Page, Paginate};
+//! # let code = hlbc::Bytecode::default();
+//!
+//! let page = Page::first(50);
+//! let first_functions: Vec<_> = code.functions().page(page).collect();
+//! let next_functions: Vec<_> = code.functions().page(page.next()).collect();
+//! let first_strings = page.slice(&code.strings);
+//! ```
+
+use std::iter::{Skip, Take};
+
+/// A window over a listing
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Page {
+    /// Number of elements to skip
+    pub offset: usize,
+    /// Maximum number of elements in the page
+    pub limit: usize,
+}
+
+impl Page {
+    /// The whole listing
+    pub const ALL: Page = Page {
+        offset: 0,
+        limit: usize::MAX,
+    };
+
+    pub fn new(offset: usize, limit: usize) -> Self {
+        Self { offset, limit }
+    }
+
+    /// The first `limit` elements
+    pub fn first(limit: usize) -> Self {
+        Self::new(0, limit)
+    }
+
+    /// The page following this one, with the same size
+    pub fn next(self) -> Self {
+        Self::new(self.offset.saturating_add(self.limit), self.limit)
+    }
+
+    /// The page preceding this one, with the same size
+    pub fn prev(self) -> Self {
+        Self::new(self.offset.saturating_sub(self.limit), self.limit)
+    }
+
+    /// Index after the last element of the page
+    pub fn end(&self) -> usize {
+        self.offset.saturating_add(self.limit)
+    }
+
+    /// Restrict a slice to this page
+    pub fn slice<'a, T>(&self, items: &'a [T]) -> &'a [T] {
+        let start = self.offset.min(items.len());
+        &items[start..self.end().min(items.len())]
+    }
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/// Restrict any iterator to a [Page].
+pub trait Paginate: Iterator + Sized {
+    fn page(self, page: Page) -> Take<Skip<Self>> {
+        self.skip(page.offset).take(page.limit)
+    }
+}
+
+impl<I: Iterator> Paginate for I {}
+
+#[cfg(test)]
+mod tests {
+    use crate::page::{Page, Paginate};
+
+    #[test]
+    fn pages() {
+        let items: Vec<usize> = (0..10).collect();
+        let page = Page::first(4);
+        assert_eq!(page.slice(&items), &[0, 1, 2, 3]);
+        assert_eq!(page.next().next().slice(&items), &[8, 9]);
+        assert!(Page::new(20, 4).slice(&items).is_empty());
+        assert_eq!(
+            items.iter().copied().page(page.next()).collect::<Vec<_>>(),
+            page.next().slice(&items)
+        );
+        assert_eq!(page.next().prev(), page);
+        assert_eq!(Page::ALL.next().slice(&items), &[] as &[usize]);
+    }
+}
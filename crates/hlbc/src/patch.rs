@@ -0,0 +1,550 @@
+//! Edit the opcodes of a function and the constant pools while keeping the bytecode valid.
+//!
+//! Jumps are relative to the opcode doing the jump, inserting opcodes would make the jumps over the insertion point
+//! land on the wrong opcode. Those operations fix up jump offsets, switch tables, trap handlers and the debug
+//! information of the function.
+//!
+//! Constant pools only grow and values are replaced in place, so existing references stay valid and see the new
+//! values. The lookup structures of [Bytecode] are updated along.
+
+use std::mem;
+use std::ops::Range;
+
+use crate::opcodes::Opcode;
+use crate::types::{
+    ConstantDef, Function, JumpOffset, RefField, RefFloat, RefFun, RefInt, RefString, RefType, Reg,
+    Type, TypeObj,
+};
+use crate::{Bytecode, Error, RefFunKnown, Result, Str};
+
+impl Opcode {
+    /// Every jump offset of this opcode, relative to the position after the opcode.
+    pub fn jump_offsets_mut(&mut self) -> Vec<&mut JumpOffset> {
+        match self {
+            Opcode::JTrue { offset, .. }
+            | Opcode::JFalse { offset, .. }
+            | Opcode::JNull { offset, .. }
+            | Opcode::JNotNull { offset, .. }
+            | Opcode::JSLt { offset, .. }
+            | Opcode::JSGte { offset, .. }
+            | Opcode::JSGt { offset, .. }
+            | Opcode::JSLte { offset, .. }
+            | Opcode::JULt { offset, .. }
+            | Opcode::JUGte { offset, .. }
+            | Opcode::JNotLt { offset, .. }
+            | Opcode::JNotGte { offset, .. }
+            | Opcode::JEq { offset, .. }
+            | Opcode::JNotEq { offset, .. }
+            | Opcode::JAlways { offset }
+            | Opcode::Trap { offset, .. } => vec![offset],
+            Opcode::Switch { offsets, end, .. } => offsets.iter_mut().chain([end]).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Function {
+    fn check_op(&self, index: usize, len: usize) -> Result<()> {
+        if index < len {
+            Ok(())
+        } else {
+            Err(Error::InvalidIndex {
+                kind: "opcode",
+                index,
+                len: self.ops.len(),
+            })
+        }
+    }
+
+    /// Replace the opcode at `index`, returning the previous one.
+    /// The jump offsets of `op` are relative to `index` like any other opcode.
+    pub fn replace_op(&mut self, index: usize, op: Opcode) -> Result<Opcode> {
+        self.check_op(index, self.ops.len())?;
+        Ok(mem::replace(&mut self.ops[index], op))
+    }
+
+    /// Insert opcodes before the opcode at `index`, or at the end if `index` is the number of opcodes.
+    ///
+    /// Jumps to `index` still go to the same opcode : the inserted opcodes only run when execution reaches them
+    /// from the opcode before. Jump offsets of the inserted opcodes are relative to their own position.
+    /// Inserted opcodes share the debug information of the opcode at `index`.
+    pub fn insert_ops(
+        &mut self,
+        index: usize,
+        ops: impl IntoIterator<Item = Opcode>,
+    ) -> Result<()> {
+        self.check_op(index, self.ops.len() + 1)?;
+        let ops: Vec<Opcode> = ops.into_iter().collect();
+        let n = ops.len();
+        if n == 0 {
+            return Ok(());
+        }
+        let moved = |pos: isize| {
+            if pos >= index as isize {
+                pos + n as isize
+            } else {
+                pos
+            }
+        };
+        for (pos, op) in self.ops.iter_mut().enumerate() {
+            let pos = pos as isize;
+            for offset in op.jump_offsets_mut() {
+                let target = pos + *offset as isize + 1;
+                *offset = (moved(target) - moved(pos) - 1) as JumpOffset;
+            }
+        }
+        self.ops.splice(index..index, ops);
+
+        if let Some(debug) = &mut self.debug_info {
+            if let Some(&info) = debug.get(index).or_else(|| debug.last()) {
+                debug.splice(index..index, vec![info; n]);
+            }
+        }
+        if let Some(assigns) = &mut self.assigns {
+            // Assignments are stored as the position after the opcode, 0 for arguments
+            for (_, pos) in assigns {
+                if *pos > index {
+                    *pos += n;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace a range of opcodes with [Opcode::Nop], the other opcodes don't move.
+    ///
+    /// `Nop` is only supported since HashLink 1.11, see [Bytecode::capabilities](crate::Bytecode::capabilities).
+    pub fn nop_range(&mut self, range: Range<usize>) -> Result<()> {
+        if range.start > range.end {
+            return Err(Error::InvalidIndex {
+                kind: "opcode",
+                index: range.start,
+                len: self.ops.len(),
+            });
+        }
+        self.check_op(range.end, self.ops.len() + 1)?;
+        self.ops[range].fill(Opcode::Nop);
+        Ok(())
+    }
+}
+
+/// Replace the element at `index` of a pool, returning the previous one
+fn replace_in<T>(pool: &mut [T], kind: &'static str, index: usize, value: T) -> Result<T> {
+    let len = pool.len();
+    pool.get_mut(index)
+        .map(|old| mem::replace(old, value))
+        .ok_or(Error::InvalidIndex { kind, index, len })
+}
+
+/// Index of `value` in a pool, added at the end if it isn't there yet
+fn intern<T: Into<U>, U>(pool: &mut Vec<U>, value: T, eq: impl Fn(&U) -> bool) -> usize {
+    pool.iter().position(eq).unwrap_or_else(|| {
+        pool.push(value.into());
+        pool.len() - 1
+    })
+}
+
+impl Bytecode {
+    /// Reference to a string, added to the string pool if it isn't there yet
+    pub fn add_string(&mut self, value: &str) -> RefString {
+        RefString(intern(&mut self.strings, value, |s| &**s == value))
+    }
+
+    /// Replace a string of the pool, returning the previous value.
+    /// Everything referencing it sees the new value, including the function names.
+    pub fn replace_string(&mut self, index: RefString, value: &str) -> Result<Str> {
+        let old = replace_in(&mut self.strings, "string", index.0, Str::from(value))?;
+        for (i, f) in self.functions.iter().enumerate() {
+            if f.name == index {
+                if self.fnames.get(&old) == Some(&i) {
+                    self.fnames.remove(&old);
+                }
+                self.fnames.insert(Str::from(value), i);
+            }
+        }
+        Ok(old)
+    }
+
+    /// Reference to an integer, added to the int pool if it isn't there yet
+    pub fn add_int(&mut self, value: i32) -> RefInt {
+        RefInt(intern(&mut self.ints, value, |&i| i == value))
+    }
+
+    /// Replace an integer of the pool, returning the previous value
+    pub fn replace_int(&mut self, index: RefInt, value: i32) -> Result<i32> {
+        replace_in(&mut self.ints, "int", index.0, value)
+    }
+
+    /// Reference to a float, added to the float pool if it isn't there yet. Floats are compared bit by bit.
+    pub fn add_float(&mut self, value: f64) -> RefFloat {
+        RefFloat(intern(&mut self.floats, value, |f| {
+            f.to_bits() == value.to_bits()
+        }))
+    }
+
+    /// Replace a float of the pool, returning the previous value
+    pub fn replace_float(&mut self, index: RefFloat, value: f64) -> Result<f64> {
+        replace_in(&mut self.floats, "float", index.0, value)
+    }
+
+    /// Set the initializer of a global, returning the one it replaces
+    pub fn set_constant(&mut self, constant: ConstantDef) -> Result<Option<ConstantDef>> {
+        if constant.global.0 >= self.globals.len() {
+            return Err(Error::InvalidIndex {
+                kind: "global",
+                index: constant.global.0,
+                len: self.globals.len(),
+            });
+        }
+        let constants = self.constants.get_or_insert_with(Vec::new);
+        Ok(match self.globals_initializers.get(&constant.global) {
+            Some(&i) => Some(mem::replace(&mut constants[i], constant)),
+            None => {
+                self.globals_initializers
+                    .insert(constant.global, constants.len());
+                constants.push(constant);
+                None
+            }
+        })
+    }
+
+    /// Rename a function without changing the other uses of its current name string.
+    /// The method or the field it is bound to is renamed too, so the name is kept when serializing.
+    pub fn rename_function(&mut self, findex: RefFun, name: &str) -> Result<()> {
+        let i = self.function_index(findex)?;
+        let new = self.add_string(name);
+        let f = &mut self.functions[i];
+        let old = mem::replace(&mut f.name, new);
+        let parent = f.parent;
+
+        if let Some(obj) = parent
+            .and_then(|p| self.types.get_mut(p.0))
+            .and_then(Type::get_type_obj_mut)
+        {
+            for p in &mut obj.protos {
+                if p.findex == findex {
+                    p.name = new;
+                }
+            }
+            // Bindings refer to the fields including the parents ones
+            let inherited = obj.fields.len() - obj.own_fields.len();
+            for (field, _) in obj.bindings.iter().filter(|&(_, &f)| f == findex) {
+                if let Some(f) = obj.fields.get_mut(field.0) {
+                    f.name = new;
+                }
+                if let Some(f) = field
+                    .0
+                    .checked_sub(inherited)
+                    .and_then(|i| obj.own_fields.get_mut(i))
+                {
+                    f.name = new;
+                }
+            }
+        }
+
+        if let Some(old) = self.strings.get(old.0) {
+            if self.fnames.get(old) == Some(&i) {
+                self.fnames.remove(old);
+            }
+        }
+        self.fnames.insert(Str::from(name), i);
+        Ok(())
+    }
+
+    /// Rename a class, a struct, an enum or an abstract type. The other types have no name.
+    pub fn rename_type(&mut self, ty: RefType, name: &str) -> Result<()> {
+        if !matches!(
+            self.types.get(ty.0),
+            Some(Type::Obj(_) | Type::Struct(_) | Type::Enum { .. } | Type::Abstract { .. })
+        ) {
+            return Err(Error::InvalidIndex {
+                kind: "named type",
+                index: ty.0,
+                len: self.types.len(),
+            });
+        }
+        let new = self.add_string(name);
+        match &mut self.types[ty.0] {
+            Type::Obj(obj) | Type::Struct(obj) => obj.name = new,
+            Type::Enum { name, .. } | Type::Abstract { name } => *name = new,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Rename a field of a class or a struct, `field` counts the inherited fields. The field is renamed in the class
+    /// declaring it and in every class inheriting it.
+    pub fn rename_field(&mut self, ty: RefType, field: RefField, name: &str) -> Result<()> {
+        let fields = |t: RefType| {
+            self.types
+                .get(t.0)
+                .and_then(Type::get_type_obj)
+                .map_or(0, |obj| obj.fields.len())
+        };
+        let len = fields(ty);
+        if field.0 >= len {
+            return Err(Error::InvalidIndex {
+                kind: "field",
+                index: field.0,
+                len,
+            });
+        }
+        // Fields are laid out parents first, go up to the class declaring it
+        let mut owner = ty;
+        while let Some(parent) = self.types[owner.0]
+            .get_type_obj()
+            .and_then(|obj| obj.super_)
+        {
+            if fields(parent) <= field.0 {
+                break;
+            }
+            owner = parent;
+        }
+        let heirs: Vec<usize> = (0..self.types.len())
+            .filter(|&t| self.is_subclass(RefType(t), owner))
+            .collect();
+
+        let new = self.add_string(name);
+        for t in heirs {
+            if let Some(obj) = self.types[t].get_type_obj_mut() {
+                obj.fields[field.0].name = new;
+                if t == owner.0 {
+                    let inherited = obj.fields.len() - obj.own_fields.len();
+                    obj.own_fields[field.0 - inherited].name = new;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Name a register of a function in its debug information, the decompiler uses it as the variable name.
+    /// An argument is renamed, any other register is named at every instruction writing it.
+    pub fn rename_variable(&mut self, findex: RefFun, reg: Reg, name: &str) -> Result<()> {
+        let i = self.function_index(findex)?;
+        let f = &self.functions[i];
+        let nargs = f.args(self).len();
+        let has_this = f.has_this(self);
+        if reg.0 as usize >= f.regs.len() || (has_this && reg.0 == 0) {
+            return Err(Error::InvalidIndex {
+                kind: "register",
+                index: reg.0 as usize,
+                len: f.regs.len(),
+            });
+        }
+        // Arguments are the assigns at position 0, in order. Every other assign is at the position following the
+        // instruction writing the variable.
+        let assigns = f.assigns.clone().unwrap_or_default();
+        let (mut args, mut vars): (Vec<_>, Vec<_>) =
+            assigns.into_iter().partition(|&(_, p)| p == 0);
+        let writes: Vec<usize> = f
+            .ops()
+            .filter(|(_, op)| op.written_reg() == Some(reg))
+            .map(|(p, _)| p + 1)
+            .collect();
+
+        let new = self.add_string(name);
+        if (reg.0 as usize) < nargs {
+            let pos = reg.0 as usize - usize::from(has_this);
+            while args.len() <= pos {
+                let placeholder = self.add_string(&format!("arg{}", args.len()));
+                args.push((placeholder, 0));
+            }
+            args[pos].0 = new;
+        }
+        vars.retain(|(_, p)| !writes.contains(p));
+        vars.extend(writes.into_iter().map(|p| (new, p)));
+        args.extend(vars);
+        self.functions[i].assigns = Some(args);
+        Ok(())
+    }
+
+    /// Index of a function in the function pool
+    fn function_index(&self, findex: RefFun) -> Result<usize> {
+        match self.findexes.get(findex.0) {
+            Some(&RefFunKnown::Fun(i)) => Ok(i),
+            _ => Err(Error::InvalidIndex {
+                kind: "function",
+                index: findex.0,
+                len: self.findexes.len(),
+            }),
+        }
+    }
+
+    /// `ty` is `parent` or inherits from it
+    pub(crate) fn is_subclass(&self, mut ty: RefType, parent: RefType) -> bool {
+        // Bounded in case of a cycle in malformed bytecode
+        for _ in 0..self.types.len() {
+            if ty == parent {
+                return true;
+            }
+            match self.types.get(ty.0).and_then(Type::get_type_obj) {
+                Some(TypeObj {
+                    super_: Some(s), ..
+                }) => ty = *s,
+                _ => return false,
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::Builder;
+    use crate::opcodes::Opcode;
+    use crate::types::{
+        ConstantDef, Function, RefField, RefFun, RefGlobal, RefInt, RefString, RefType, Reg, Type,
+    };
+
+    fn function(ops: Vec<Opcode>) -> Function {
+        Function {
+            t: RefType(0),
+            findex: RefFun(0),
+            regs: vec![RefType(0)],
+            debug_info: Some((0..ops.len()).map(|i| (0, i)).collect()),
+            ops,
+            assigns: Some(vec![(RefString(1), 0), (RefString(2), 2)]),
+            name: RefString(0),
+            parent: None,
+        }
+    }
+
+    #[test]
+    fn insert_fixups() {
+        let mut f = function(vec![
+            Opcode::Label,
+            Opcode::JTrue {
+                cond: Reg(0),
+                offset: 2,
+            },
+            Opcode::Incr { dst: Reg(0) },
+            Opcode::JAlways { offset: -4 },
+            Opcode::Ret { ret: Reg(0) },
+        ]);
+        f.insert_ops(2, [Opcode::Nop, Opcode::Nop]).unwrap();
+        assert_eq!(f.ops.len(), 7);
+        assert!(matches!(f.ops[1], Opcode::JTrue { offset: 4, .. }));
+        assert!(matches!(f.ops[5], Opcode::JAlways { offset: -6 }));
+        assert!(matches!(f.ops[4], Opcode::Incr { .. }));
+        assert_eq!(
+            f.debug_info.as_ref().unwrap()[..4],
+            [(0, 0), (0, 1), (0, 2), (0, 2)]
+        );
+        assert_eq!(f.assigns.as_ref().unwrap()[1].1, 2);
+
+        f.insert_ops(0, [Opcode::Nop]).unwrap();
+        assert_eq!(f.assigns.as_ref().unwrap()[1].1, 3);
+        assert!(f.insert_ops(9, [Opcode::Nop]).is_err());
+    }
+
+    #[test]
+    fn switch_and_trap() {
+        let mut f = function(vec![
+            Opcode::Trap {
+                exc: Reg(0),
+                offset: 2,
+            },
+            Opcode::Switch {
+                reg: Reg(0),
+                offsets: vec![0, 1],
+                end: 1,
+            },
+            Opcode::Nop,
+            Opcode::Ret { ret: Reg(0) },
+        ]);
+        f.insert_ops(3, [Opcode::Nop]).unwrap();
+        assert!(matches!(f.ops[0], Opcode::Trap { offset: 3, .. }));
+        match &f.ops[1] {
+            Opcode::Switch { offsets, end, .. } => {
+                assert_eq!(offsets, &[0, 2]);
+                assert_eq!(*end, 2);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn replace_and_nop() {
+        let mut f = function(vec![
+            Opcode::Incr { dst: Reg(0) },
+            Opcode::Ret { ret: Reg(0) },
+        ]);
+        let old = f.replace_op(0, Opcode::Decr { dst: Reg(0) }).unwrap();
+        assert!(matches!(old, Opcode::Incr { .. }));
+        f.nop_range(0..1).unwrap();
+        assert!(matches!(f.ops[0], Opcode::Nop));
+        assert!(f.nop_range(1..3).is_err());
+        assert!(f.replace_op(2, Opcode::Nop).is_err());
+    }
+
+    #[test]
+    fn pools() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        b.int(3);
+        b.global(int);
+        b.function("main", &[], int, &[], vec![]);
+        let mut code = b.build().unwrap();
+
+        assert_eq!(code.add_string("main"), RefString(1));
+        assert_eq!(code.add_string("hello"), RefString(2));
+        assert_eq!(code.replace_string(RefString(1), "start").unwrap(), "main");
+        assert!(code.function_by_name("main").is_none());
+        assert!(code.function_by_name("start").is_some());
+        assert!(code.replace_string(RefString(3), "").is_err());
+
+        assert_eq!(code.add_int(3), RefInt(0));
+        assert_eq!(code.add_int(4), RefInt(1));
+        assert_eq!(code.replace_int(RefInt(0), 5).unwrap(), 3);
+        assert_eq!(code[RefInt(0)], 5);
+        assert_eq!(code.add_float(-0.0).0, 0);
+        assert_eq!(code.add_float(0.0).0, 1);
+
+        let constant = |fields| ConstantDef {
+            global: RefGlobal(0),
+            fields,
+        };
+        assert!(code.set_constant(constant(vec![1])).unwrap().is_none());
+        let old = code.set_constant(constant(vec![2])).unwrap().unwrap();
+        assert_eq!(old.fields, [1]);
+        assert_eq!(code.constants.as_ref().unwrap().len(), 1);
+        assert!(code
+            .set_constant(ConstantDef {
+                global: RefGlobal(1),
+                fields: vec![],
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn rename() {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        let bound = b.function("", &[], void, &[], vec![]);
+        let method = b.function("", &[], void, &[], vec![]);
+        let main = b.class("Main", None, &[("run", void)]);
+        b.method(main, "run", method);
+        b.code_mut().types[main.0]
+            .get_type_obj_mut()
+            .unwrap()
+            .bindings
+            .insert(RefField(0), bound);
+        let run = b.string("run");
+        let mut code = b.build().unwrap();
+
+        code.rename_function(method, "update").unwrap();
+        let obj = code.types[main.0].get_type_obj().unwrap();
+        assert_eq!(code[obj.protos[0].name], "update");
+        assert_eq!(obj.own_fields[0].name, run);
+        assert_eq!(code[code.functions[1].name], "update");
+        assert_eq!(code.functions[0].name, run);
+        assert!(code.function_by_name("run").is_none());
+
+        code.rename_function(bound, "onUpdate").unwrap();
+        let obj = code.types[main.0].get_type_obj().unwrap();
+        assert_eq!(code[obj.own_fields[0].name], "onUpdate");
+        assert_eq!(code[obj.fields[0].name], "onUpdate");
+        assert_eq!(code[run], "run");
+        assert!(code.rename_function(RefFun(2), "").is_err());
+    }
+}
@@ -1,12 +1,12 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fs;
 use std::io::{BufRead, BufReader, Read};
 use std::path::Path;
-use std::str::from_utf8;
+use std::sync::Arc;
 
 use byteorder::{LittleEndian, ReadBytesExt};
 
-use crate::{Bytecode, ConstantDef, Opcode, RefFun, RefFunKnown, RefGlobal, Str};
+use crate::{Bytecode, ConstantDef, Opcode, RefFun, RefFunKnown, RefGlobal, Section, Str};
 use crate::{Error, Result};
 use crate::types::{
     EnumConstruct, Function, Native, ObjField, ObjProto, RefField, RefFloat, RefInt, RefString,
@@ -22,21 +22,72 @@ impl Bytecode {
     }
 
     /// Load the bytecode from any source. This method will skip bytes until the magic header is found.
-    /// This also means it will read bytes indefinitely if it can't find the magic header.
+    /// This also means it will read the whole source if it can't find the magic header.
     pub fn deserialize(mut r: impl BufRead) -> Result<Self> {
         // Search for the magic header
         let finder = memchr::memmem::Finder::new("HLB");
+        // Last bytes of the previous buffer, the magic header might be split between two buffers
+        let mut tail = Vec::new();
         loop {
             let buffer = r.fill_buf()?;
+            if buffer.is_empty() {
+                return Err(Error::MalformedBytecode(
+                    "Magic header not found".to_owned(),
+                ));
+            }
+            let mut joined = tail.clone();
+            joined.extend_from_slice(&buffer[..buffer.len().min(2)]);
+            if let Some(index) = finder.find(&joined).filter(|&i| i < tail.len()) {
+                let start = tail[index..].to_vec();
+                return Self::deserialize_exact(&mut start.as_slice().chain(r));
+            }
             if let Some(index) = finder.find(buffer) {
                 r.consume(index);
                 return Self::deserialize_exact(&mut r);
             }
+            tail = buffer[buffer.len().saturating_sub(2)..].to_vec();
             let len = buffer.len();
-            // Edge case is when this buffer ends with 'HL', we must not consume
-            // the last 2 bytes, so they can be used for the next search.
-            r.consume(len - 2);
+            r.consume(len);
+        }
+    }
+
+    /// Load the bytecode embedded in a bigger file, like an executable or a package with its `hlboot.dat` inside.
+    ///
+    /// Unlike [Bytecode::deserialize], every [candidate header](Bytecode::embedded_offsets) is tried until one of them
+    /// is the start of valid bytecode, as the executable code and data might contain the header bytes too. The whole
+    /// source is read in memory.
+    ///
+    /// HL/C builds are compiled to native code through C and don't carry any bytecode, nor any metadata describing
+    /// the types and functions of the program. Only the bytecode embedded next to the native code can be loaded.
+    pub fn from_embedded(mut r: impl Read) -> Result<Self> {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+        let mut error = None;
+        for offset in Self::embedded_offsets(&data) {
+            match Self::deserialize_exact(&mut &data[offset..]) {
+                Ok(code) => return Ok(code),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
         }
+        Err(error.unwrap_or_else(|| {
+            Error::MalformedBytecode(if is_executable(&data) {
+                "No bytecode found, this might be an HL/C build".to_owned()
+            } else {
+                "Magic header not found".to_owned()
+            })
+        }))
+    }
+
+    /// Offsets of the magic headers followed by a supported version in a binary blob, where bytecode might start
+    pub fn embedded_offsets(data: &[u8]) -> Vec<usize> {
+        memchr::memmem::find_iter(data, "HLB")
+            .filter(|&i| {
+                data.get(i + 3)
+                    .is_some_and(|v| (Self::MIN_VERSION..=Self::MAX_VERSION).contains(v))
+            })
+            .collect()
     }
 
     /// Load the bytecode from any source.
@@ -51,11 +102,11 @@ impl Bytecode {
             )));
         }
         let version = r.read_u8()?;
-        if version < 4 || version > 5 {
+        if !(Self::MIN_VERSION..=Self::MAX_VERSION).contains(&version) {
             return Err(Error::UnsupportedVersion {
                 version,
-                min: 4,
-                max: 5,
+                min: Self::MIN_VERSION,
+                max: Self::MAX_VERSION,
             });
         }
         let flags = read_varu(r)?;
@@ -63,7 +114,7 @@ impl Bytecode {
         let nints = read_varu(r)? as usize;
         let nfloats = read_varu(r)? as usize;
         let nstrings = read_varu(r)? as usize;
-        let nbytes = if version >= 5 {
+        let nbytes = if version >= Section::Bytes.since() {
             Some(read_varu(r)? as usize)
         } else {
             None
@@ -72,30 +123,28 @@ impl Bytecode {
         let nglobals = read_varu(r)? as usize;
         let nnatives = read_varu(r)? as usize;
         let nfunctions = read_varu(r)? as usize;
-        let nconstants = if version >= 4 {
+        let nconstants = if version >= Section::Constants.since() {
             Some(read_varu(r)? as usize)
         } else {
             None
         };
         let entrypoint = RefFun::read(r)?;
 
-        let mut ints = vec![0i32; nints];
-        for i in ints.iter_mut() {
-            *i = r.read_i32::<LittleEndian>()?;
+        let mut ints = Vec::with_capacity(capacity(nints));
+        for _ in 0..nints {
+            ints.push(r.read_i32::<LittleEndian>()?);
         }
 
-        let mut floats = vec![0f64; nfloats];
-        for i in floats.iter_mut() {
-            *i = r.read_f64::<LittleEndian>()?;
+        let mut floats = Vec::with_capacity(capacity(nfloats));
+        for _ in 0..nfloats {
+            floats.push(r.read_f64::<LittleEndian>()?);
         }
 
         let strings = read_strings(r, nstrings)?;
 
         let bytes = if let Some(nbytes) = nbytes {
-            let size = r.read_i32::<LittleEndian>()? as usize;
-            let mut bytes = vec![0; size];
-            r.read_exact(&mut bytes)?;
-            let mut pos = Vec::with_capacity(nbytes);
+            let bytes = read_blob(r)?;
+            let mut pos = Vec::with_capacity(capacity(nbytes));
             for _ in 0..nbytes {
                 pos.push(read_varu(r)? as usize);
             }
@@ -111,28 +160,28 @@ impl Bytecode {
             None
         };
 
-        let mut types = Vec::with_capacity(ntypes);
+        let mut types = Vec::with_capacity(capacity(ntypes));
         for _ in 0..ntypes {
             types.push(Type::read(r)?);
         }
 
-        let mut globals = Vec::with_capacity(nglobals);
+        let mut globals = Vec::with_capacity(capacity(nglobals));
         for _ in 0..nglobals {
             globals.push(RefType::read(r)?);
         }
 
-        let mut natives = Vec::with_capacity(nnatives);
+        let mut natives = Vec::with_capacity(capacity(nnatives));
         for _ in 0..nnatives {
             natives.push(Native::read(r)?);
         }
 
-        let mut functions = Vec::with_capacity(nfunctions);
+        let mut functions = Vec::with_capacity(capacity(nfunctions));
         for _ in 0..nfunctions {
             functions.push(Function::read(r, has_debug, version)?);
         }
 
         let constants = if let Some(n) = nconstants {
-            let mut constants = Vec::with_capacity(n);
+            let mut constants = Vec::with_capacity(capacity(n));
             for _ in 0..n {
                 constants.push(ConstantDef::read(r)?)
             }
@@ -141,22 +190,38 @@ impl Bytecode {
             None
         };
 
-        // Parsing is finished, we now build links between everything
+        let mut code = Bytecode {
+            version,
+            entrypoint,
+            ints,
+            floats,
+            strings,
+            bytes,
+            debug_files,
+            types,
+            globals,
+            natives,
+            functions,
+            constants,
+            ..Bytecode::default()
+        };
+        code.link()?;
+        Ok(code)
+    }
+
+    /// Parsing is finished, we now build links between everything
+    pub(crate) fn link(&mut self) -> Result<()> {
+        let types = &mut self.types;
+        let functions = &mut self.functions;
 
         // Global function indexes
-        let mut findexes = vec![RefFunKnown::Fun(0); nfunctions + nnatives];
-        for (i, f) in functions.iter().enumerate() {
-            findexes[f.findex.0] = RefFunKnown::Fun(i);
-        }
-        for (i, n) in natives.iter().enumerate() {
-            findexes[n.findex.0] = RefFunKnown::Native(i);
-        }
+        let findexes = index_functions(functions, &self.natives)?;
 
         // Flatten types fields
         // Start by collecting every field in the hierarchy
         // The order is important because we refer to fields by index
         let mut new_fields: Vec<Option<Vec<ObjField>>> = Vec::with_capacity(types.len());
-        for t in &types {
+        for t in types.iter() {
             if let Some(obj) = t.get_type_obj() {
                 let mut parent = obj.super_.as_ref().map(|s| &types[s.0]);
                 let mut acc = VecDeque::with_capacity(obj.own_fields.len());
@@ -202,48 +267,97 @@ impl Bytecode {
             }
         }
 
-        // Function names
-        let mut fnames = HashMap::with_capacity(functions.len());
-        for (i, f) in functions.iter().enumerate() {
-            // FIXME duplicates ?
-            fnames.insert(strings[f.name.0].clone(), i);
-        }
-        fnames.insert(
-            Str::from("init"),
-            match findexes[entrypoint.0] {
-                RefFunKnown::Fun(x) => x,
-                _ => 0,
-            },
+        self.fnames = function_names(&self.strings, functions, &findexes, self.entrypoint);
+        self.findexes = findexes;
+        self.globals_initializers = index_initializers(self.constants.as_deref());
+        Ok(())
+    }
+
+    /// Load the bytecode from its json representation, as produced by [Bytecode::to_json].
+    /// The lookup structures are built again, so the pools can be edited before loading them back.
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<Self> {
+        let mut code: Bytecode = serde_json::from_str(json)?;
+        code.findexes = index_functions(&code.functions, &code.natives)?;
+        code.fnames = function_names(
+            &code.strings,
+            &code.functions,
+            &code.findexes,
+            code.entrypoint,
         );
+        code.globals_initializers = index_initializers(code.constants.as_deref());
+        Ok(code)
+    }
+}
 
-        let globals_initializers = if let Some(constants) = &constants {
-            let mut tmp = HashMap::with_capacity(constants.len());
-            for (i, c) in constants.iter().enumerate() {
-                tmp.insert(c.global, i);
-            }
-            tmp
-        } else {
-            HashMap::new()
-        };
+/// Starts like a PE, ELF or Mach-O executable
+fn is_executable(data: &[u8]) -> bool {
+    [
+        &b"MZ"[..],
+        b"\x7fELF",
+        b"\xcf\xfa\xed\xfe",
+        b"\xce\xfa\xed\xfe",
+    ]
+    .iter()
+    .any(|magic| data.starts_with(magic))
+}
 
-        Ok(Bytecode {
-            version,
-            entrypoint,
-            ints,
-            floats,
-            strings,
-            bytes,
-            debug_files,
-            types,
-            globals,
-            natives,
-            functions,
-            constants,
-            findexes,
-            fnames,
-            globals_initializers,
-        })
+/// Map every findex to its position in the function or native pool
+fn index_functions(functions: &[Function], natives: &[Native]) -> Result<Vec<RefFunKnown>> {
+    let len = functions.len() + natives.len();
+    let mut findexes = vec![RefFunKnown::Fun(0); len];
+    let entries = functions
+        .iter()
+        .enumerate()
+        .map(|(i, f)| (f.findex, RefFunKnown::Fun(i)))
+        .chain(
+            natives
+                .iter()
+                .enumerate()
+                .map(|(i, n)| (n.findex, RefFunKnown::Native(i))),
+        );
+    for (findex, known) in entries {
+        *findexes.get_mut(findex.0).ok_or(Error::InvalidIndex {
+            kind: "findex",
+            index: findex.0,
+            len,
+        })? = known;
     }
+    Ok(findexes)
+}
+
+/// Map function names to their position in the function pool
+fn function_names(
+    strings: &[Str],
+    functions: &[Function],
+    findexes: &[RefFunKnown],
+    entrypoint: RefFun,
+) -> HashMap<Str, usize> {
+    let mut fnames = HashMap::with_capacity(functions.len());
+    for (i, f) in functions.iter().enumerate() {
+        // FIXME duplicates ?
+        if let Some(name) = strings.get(f.name.0) {
+            fnames.insert(name.clone(), i);
+        }
+    }
+    fnames.insert(
+        Str::from("init"),
+        match findexes.get(entrypoint.0) {
+            Some(&RefFunKnown::Fun(x)) => x,
+            _ => 0,
+        },
+    );
+    fnames
+}
+
+/// Map globals to the constant initializing them
+fn index_initializers(constants: Option<&[ConstantDef]>) -> HashMap<RefGlobal, usize> {
+    constants
+        .unwrap_or_default()
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (c.global, i))
+        .collect()
 }
 
 impl RefInt {
@@ -319,11 +433,11 @@ impl TypeObj {
         let nfields = read_varu(r)? as usize;
         let nprotos = read_varu(r)? as usize;
         let nbindings = read_varu(r)? as usize;
-        let mut own_fields = Vec::with_capacity(nfields);
+        let mut own_fields = Vec::with_capacity(capacity(nfields));
         for _ in 0..nfields {
             own_fields.push(ObjField::read(r)?);
         }
-        let mut protos = Vec::with_capacity(nprotos);
+        let mut protos = Vec::with_capacity(capacity(nprotos));
         for _ in 0..nprotos {
             protos.push(ObjProto {
                 name: RefString::read(r)?,
@@ -331,7 +445,7 @@ impl TypeObj {
                 pindex: read_vari(r)?,
             });
         }
-        let mut bindings = HashMap::with_capacity(nbindings);
+        let mut bindings = BTreeMap::new();
         for _ in 0..nbindings {
             bindings.insert(RefField::read(r)?, RefFun::read(r)?);
         }
@@ -372,7 +486,7 @@ impl Type {
             14 => Ok(Ref(RefType::read(r)?)),
             15 => {
                 let nfields = read_varu(r)? as usize;
-                let mut fields = Vec::with_capacity(nfields);
+                let mut fields = Vec::with_capacity(capacity(nfields));
                 for _ in 0..nfields {
                     fields.push(ObjField::read(r)?);
                 }
@@ -386,11 +500,11 @@ impl Type {
                 let name = RefString::read(r)?;
                 let global = RefGlobal::read(r)?;
                 let nconstructs = read_varu(r)? as usize;
-                let mut constructs = Vec::with_capacity(nconstructs);
+                let mut constructs = Vec::with_capacity(capacity(nconstructs));
                 for _ in 0..nconstructs {
                     let name = RefString::read(r)?;
                     let nparams = read_varu(r)? as usize;
-                    let mut params = Vec::with_capacity(nparams);
+                    let mut params = Vec::with_capacity(capacity(nparams));
                     for _ in 0..nparams {
                         params.push(RefType::read(r)?);
                     }
@@ -430,18 +544,18 @@ impl Function {
         let findex = RefFun::read(r)?;
         let nregs = read_varu(r)? as usize;
         let nops = read_varu(r)? as usize;
-        let mut regs = Vec::with_capacity(nregs);
+        let mut regs = Vec::with_capacity(capacity(nregs));
         for _ in 0..nregs {
             regs.push(RefType::read(r)?);
         }
-        let mut ops = Vec::with_capacity(nops);
+        let mut ops = Vec::with_capacity(capacity(nops));
         for _ in 0..nops {
             ops.push(Opcode::read(r)?);
         }
 
         // This is extracted from the hashlink source code, do not count on me to explain what it does
         let debug_info = if has_debug {
-            let mut tmp = Vec::with_capacity(nops);
+            let mut tmp = Vec::with_capacity(capacity(nops));
             let mut currfile: i32 = -1;
             let mut currline: i32 = 0;
             let mut i = 0;
@@ -478,7 +592,7 @@ impl Function {
 
         let assigns = if has_debug && version >= 3 {
             let len = read_varu(r)? as usize;
-            let mut assigns = Vec::with_capacity(len);
+            let mut assigns = Vec::with_capacity(capacity(len));
             for _ in 0..len {
                 assigns.push((RefString::read(r)?, read_vari(r)? as usize));
             }
@@ -503,7 +617,7 @@ impl ConstantDef {
     pub(crate) fn read(r: &mut impl Read) -> Result<Self> {
         let global = RefGlobal::read(r)?;
         let nfields = read_varu(r)? as usize;
-        let mut fields = Vec::with_capacity(nfields);
+        let mut fields = Vec::with_capacity(capacity(nfields));
         for _ in 0..nfields {
             fields.push(read_varu(r)? as usize);
         }
@@ -538,19 +652,49 @@ pub(crate) fn read_varu(r: &mut impl Read) -> Result<u32> {
     }
 }
 
+/// Counts read from the file are only trusted up to this many elements, past it the vectors grow as the elements are
+/// read. A garbage header fails at the end of the data instead of allocating gigabytes upfront.
+const MAX_PREALLOC: usize = 4096;
+
+fn capacity(n: usize) -> usize {
+    n.min(MAX_PREALLOC)
+}
+
+/// A blob of bytes prefixed with its size
+fn read_blob(r: &mut impl Read) -> Result<Vec<u8>> {
+    let size = r.read_i32::<LittleEndian>()?;
+    let size = usize::try_from(size)
+        .map_err(|_| Error::MalformedBytecode(format!("Negative blob size '{size}'")))?;
+    // Grows as the data is read, a wrong size fails before allocating it
+    let mut blob = Vec::new();
+    r.by_ref().take(size as u64).read_to_end(&mut blob)?;
+    if blob.len() < size {
+        return Err(Error::MalformedBytecode(format!(
+            "Blob of {size} bytes truncated to {} bytes",
+            blob.len()
+        )));
+    }
+    Ok(blob)
+}
+
+/// Strings are stored in one blob, separated by a nul byte. The strings are slices of the blob, kept in one allocation.
 fn read_strings(r: &mut impl Read, nstrings: usize) -> Result<Vec<Str>> {
-    let mut strings = Vec::with_capacity(nstrings);
-    let mut string_data = vec![0u8; r.read_i32::<LittleEndian>()? as usize];
-    r.read_exact(&mut string_data)?;
+    let mut strings = Vec::with_capacity(capacity(nstrings));
+    // Validated once, the nul separators are char boundaries
+    let blob = String::from_utf8(read_blob(r)?).map_err(|e| e.utf8_error())?;
+    let blob: Arc<str> = Arc::from(blob);
     let mut acc = 0;
     for _ in 0..nstrings {
-        let ssize = read_varu(r)? as usize + 1;
-        //println!("size: {ssize} {:?}", &string_data[acc..(acc + ssize)]);
-        //let cstr = unsafe { CStr::from_bytes_with_nul_unchecked(&string_data[acc..(acc + ssize)]) };
-        strings.push(Str::from_ref(from_utf8(
-            &string_data[acc..(acc + ssize - 1)],
-        )?));
-        acc += ssize;
+        let size = read_varu(r)? as usize;
+        // Short strings are stored inline, no allocation
+        let s = Str::from_shared(&blob, acc..acc + size).ok_or_else(|| {
+            Error::MalformedBytecode(format!(
+                "String of {size} bytes at {acc} out of the strings blob ({} bytes)",
+                blob.len()
+            ))
+        })?;
+        strings.push(s);
+        acc += size + 1;
     }
     Ok(strings)
 }
@@ -559,9 +703,12 @@ fn read_strings(r: &mut impl Read, nstrings: usize) -> Result<Vec<Str>> {
 mod tests {
     use std::collections::HashSet;
     use std::fs;
-    use std::io::{BufWriter, Write};
+    use std::io::{BufReader, BufWriter, Write};
 
-    use crate::Bytecode;
+    use crate::builder::Builder;
+    use crate::read::read_strings;
+    use crate::write::write_var;
+    use crate::{Bytecode, Error};
 
     #[test]
     fn test_deserialize_all() {
@@ -576,6 +723,66 @@ mod tests {
         }
     }
 
+    #[test]
+    fn embedded() {
+        let mut buf = Vec::new();
+        let code = Builder::new().build().unwrap();
+        code.serialize(&mut buf).unwrap();
+        // A fake header before the real one
+        let mut data = b"MZ..HLB\x07..".to_vec();
+        data.extend_from_slice(&buf);
+        data.extend_from_slice(b"trailing data");
+        assert_eq!(Bytecode::embedded_offsets(&data), [10]);
+        let code = Bytecode::from_embedded(&data[..]).unwrap();
+        assert_eq!(code.version, 5);
+        assert!(Bytecode::deserialize(&data[..]).is_err());
+        assert!(matches!(
+            Bytecode::from_embedded(&b"MZ...."[..]),
+            Err(Error::MalformedBytecode(msg)) if msg.contains("HL/C")
+        ));
+        assert!(Bytecode::from_embedded(&b"MZ....HLB"[..]).is_err());
+
+        // The header split between the two buffers of the reader
+        let data = [b"..HL".as_slice(), &buf[2..]].concat();
+        let r = BufReader::with_capacity(4, &data[..]);
+        assert!(Bytecode::deserialize(r).is_ok());
+        assert!(Bytecode::deserialize(&b"no header"[..]).is_err());
+    }
+
+    #[test]
+    fn garbage_counts() {
+        // Valid header claiming about 500 million elements of each kind, followed by nothing
+        let mut data = b"HLB\x05".to_vec();
+        write_var(&mut data, 0).unwrap();
+        for _ in 0..9 {
+            write_var(&mut data, 0x1FFF_FFFF).unwrap();
+        }
+        write_var(&mut data, 0).unwrap();
+        assert!(Bytecode::deserialize(&data[..]).is_err());
+
+        // Same with a huge blob size
+        let mut data = b"HLB\x05".to_vec();
+        for _ in 0..11 {
+            write_var(&mut data, 0).unwrap();
+        }
+        data.extend_from_slice(&i32::MAX.to_le_bytes());
+        assert!(Bytecode::deserialize(&data[..]).is_err());
+    }
+
+    #[test]
+    fn strings_blob() {
+        let blob = "ab\0été\0";
+        let mut data = (blob.len() as i32).to_le_bytes().to_vec();
+        data.extend_from_slice(blob.as_bytes());
+        write_var(&mut data, 2).unwrap();
+        write_var(&mut data, 5).unwrap();
+        let strings = read_strings(&mut data.as_slice(), 2).unwrap();
+        assert_eq!(strings, ["ab", "été"]);
+        // One more string than in the blob
+        write_var(&mut data, 1).unwrap();
+        assert!(read_strings(&mut data.as_slice(), 3).is_err());
+    }
+
     #[test]
     fn test_deserialize_wartales() {
         let path = "E:\\Games\\Wartales\\hlboot.dat";
@@ -0,0 +1,258 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+
+/// Strings up to this length are stored inline
+const INLINE_CAP: usize = 22;
+
+/// Cheaply cloneable immutable string.
+///
+/// Short strings are stored inline and static strings are borrowed, without any allocation. Longer strings are a
+/// slice of a shared buffer : the strings of a loaded bytecode all point into the strings blob of the file, kept in
+/// one allocation, instead of being allocated one by one.
+#[derive(Clone)]
+pub struct Str(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Static(&'static str),
+    Inline { len: u8, buf: [u8; INLINE_CAP] },
+    Shared { buf: Arc<str>, start: u32, end: u32 },
+    // A whole buffer, for strings built at runtime and the slices too far in their buffer for 32 bits offsets
+    Whole(Arc<str>),
+}
+
+impl Str {
+    pub const fn from_static(s: &'static str) -> Self {
+        Self(Repr::Static(s))
+    }
+
+    /// Copy a string, inline if it is short enough
+    pub fn from_ref(s: impl AsRef<str>) -> Self {
+        let s = s.as_ref();
+        Self::inline(s).unwrap_or_else(|| Self::shared(Arc::from(s)))
+    }
+
+    /// A slice of a shared buffer, `None` if the range is out of the buffer or not on char boundaries.
+    /// Short strings are copied inline instead of keeping the buffer alive.
+    pub fn from_shared(buf: &Arc<str>, range: Range<usize>) -> Option<Self> {
+        let s = buf.get(range.clone())?;
+        Some(match (Self::inline(s), u32::try_from(range.end)) {
+            (Some(inline), _) => inline,
+            (None, Ok(end)) => Self(Repr::Shared {
+                buf: buf.clone(),
+                start: range.start as u32,
+                end,
+            }),
+            // Offsets are stored on 32 bits
+            (None, Err(_)) => Self::shared(Arc::from(s)),
+        })
+    }
+
+    fn inline(s: &str) -> Option<Self> {
+        (s.len() <= INLINE_CAP).then(|| {
+            let mut buf = [0; INLINE_CAP];
+            buf[..s.len()].copy_from_slice(s.as_bytes());
+            Self(Repr::Inline {
+                len: s.len() as u8,
+                buf,
+            })
+        })
+    }
+
+    fn shared(buf: Arc<str>) -> Self {
+        Self(Repr::Whole(buf))
+    }
+
+    pub fn as_str(&self) -> &str {
+        match &self.0 {
+            Repr::Static(s) => s,
+            // Always built from a whole str
+            Repr::Inline { len, buf } => {
+                std::str::from_utf8(&buf[..*len as usize]).unwrap_or_default()
+            }
+            Repr::Shared { buf, start, end } => &buf[*start as usize..*end as usize],
+            Repr::Whole(buf) => buf,
+        }
+    }
+}
+
+impl Default for Str {
+    fn default() -> Self {
+        Self::from_static("")
+    }
+}
+
+impl Deref for Str {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for Str {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Borrow<str> for Str {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Display for Str {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(self.as_str(), f)
+    }
+}
+
+impl Debug for Str {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl PartialEq for Str {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Str {}
+
+impl PartialOrd for Str {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Str {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+/// Same as [str], to look up a [Borrow]ed key
+impl Hash for Str {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+macro_rules! impl_eq {
+    ($($other:ty),*) => {
+        $(
+            impl PartialEq<$other> for Str {
+                fn eq(&self, other: &$other) -> bool {
+                    self.as_str() == &other[..]
+                }
+            }
+
+            impl PartialEq<Str> for $other {
+                fn eq(&self, other: &Str) -> bool {
+                    &self[..] == other.as_str()
+                }
+            }
+        )*
+    };
+}
+
+impl_eq!(str, &str, String);
+
+impl From<&str> for Str {
+    fn from(s: &str) -> Self {
+        Self::from_ref(s)
+    }
+}
+
+impl From<&String> for Str {
+    fn from(s: &String) -> Self {
+        Self::from_ref(s)
+    }
+}
+
+impl From<String> for Str {
+    fn from(s: String) -> Self {
+        Self::inline(&s).unwrap_or_else(|| Self::shared(Arc::from(s)))
+    }
+}
+
+impl From<&Str> for Str {
+    fn from(s: &Str) -> Self {
+        s.clone()
+    }
+}
+
+impl From<Str> for String {
+    fn from(s: Str) -> Self {
+        s.as_str().to_owned()
+    }
+}
+
+impl FromIterator<char> for Str {
+    fn from_iter<T: IntoIterator<Item = char>>(iter: T) -> Self {
+        Self::from(String::from_iter(iter))
+    }
+}
+
+impl<'a> FromIterator<&'a str> for Str {
+    fn from_iter<T: IntoIterator<Item = &'a str>>(iter: T) -> Self {
+        Self::from(String::from_iter(iter))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Str {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Str {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Str::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::Str;
+
+    #[test]
+    fn representations() {
+        let blob: Arc<str> = Arc::from("short\0a string longer than the inline storage\0");
+        let short = Str::from_shared(&blob, 0..5).unwrap();
+        let long = Str::from_shared(&blob, 6..45).unwrap();
+        assert_eq!(short, "short");
+        assert_eq!(long, "a string longer than the inline storage");
+        // Only the long string keeps the blob alive
+        assert_eq!(Arc::strong_count(&blob), 2);
+        assert!(Str::from_shared(&blob, 40..60).is_none());
+        assert!(Str::from_shared(&Arc::from("é"), 0..1).is_none());
+
+        assert_eq!(Str::from_static("short"), short);
+        assert_eq!(Str::from(String::from("short")), short);
+        assert_eq!(Str::from_ref(&*long), long);
+        assert_eq!(Str::default(), "");
+        assert!(long < short);
+        assert_eq!(
+            format!("{short} {long:?}"),
+            "short \"a string longer than the inline storage\""
+        );
+
+        let mut map = HashMap::new();
+        map.insert(long.clone(), 1);
+        assert_eq!(map.get("a string longer than the inline storage"), Some(&1));
+    }
+}
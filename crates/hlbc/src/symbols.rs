@@ -0,0 +1,191 @@
+//! Names given to the elements of a stripped or obfuscated program while reverse engineering it.
+//!
+//! A symbol map records better names for functions, types, fields and variables, keyed by index. Once
+//! [applied](SymbolMap::apply) to the bytecode, the disassembler and the decompiler use them like the original names.
+//! Maps are shared as `.hlbcmap` JSON files (with the `serde` feature) :
+//!
+//! ```json
+//! {
+//!   "functions": { "12": "updatePlayer" },
+//!   "types": { "5": "Player" },
+//!   "fields": { "5": { "2": "health" } },
+//!   "variables": { "12": { "3": "speed" } }
+//! }
+//! ```
+//!
+//! Indices are only meaningful for the bytecode the map was made for, a map can't follow a new version of a program.
+
+use std::collections::BTreeMap;
+
+use crate::types::{RefField, RefFun, RefType, Reg};
+use crate::{Bytecode, Result};
+
+/// Custom names for the elements of a program
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SymbolMap {
+    /// Function names by findex
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "BTreeMap::is_empty")
+    )]
+    pub functions: BTreeMap<usize, String>,
+    /// Class, struct, enum and abstract names by type index
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "BTreeMap::is_empty")
+    )]
+    pub types: BTreeMap<usize, String>,
+    /// Field names by type index, then by field index including the inherited fields
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "BTreeMap::is_empty")
+    )]
+    pub fields: BTreeMap<usize, BTreeMap<usize, String>>,
+    /// Variable names by findex, then by register
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "BTreeMap::is_empty")
+    )]
+    pub variables: BTreeMap<usize, BTreeMap<u32, String>>,
+}
+
+impl SymbolMap {
+    /// Extension of the symbol map files
+    pub const EXTENSION: &'static str = "hlbcmap";
+
+    /// Number of names in the map
+    pub fn len(&self) -> usize {
+        self.functions.len()
+            + self.types.len()
+            + self.fields.values().map(BTreeMap::len).sum::<usize>()
+            + self.variables.values().map(BTreeMap::len).sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add the names of another map, replacing the names given to the same elements
+    pub fn merge(&mut self, other: SymbolMap) {
+        self.functions.extend(other.functions);
+        self.types.extend(other.types);
+        for (ty, fields) in other.fields {
+            self.fields.entry(ty).or_default().extend(fields);
+        }
+        for (fun, vars) in other.variables {
+            self.variables.entry(fun).or_default().extend(vars);
+        }
+    }
+
+    /// Rename the elements of the bytecode. Fails on the first index that doesn't exist in this bytecode, the names
+    /// applied before stay.
+    pub fn apply(&self, code: &mut Bytecode) -> Result<()> {
+        for (&ty, name) in &self.types {
+            code.rename_type(RefType(ty), name)?;
+        }
+        for (&ty, fields) in &self.fields {
+            for (&field, name) in fields {
+                code.rename_field(RefType(ty), RefField(field), name)?;
+            }
+        }
+        for (&fun, name) in &self.functions {
+            code.rename_function(RefFun(fun), name)?;
+        }
+        for (&fun, vars) in &self.variables {
+            for (&reg, name) in vars {
+                code.rename_variable(RefFun(fun), Reg(reg), name)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a map from the content of a `.hlbcmap` file
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Content of a `.hlbcmap` file, indented to keep the diffs readable when the map is versioned
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::builder::Builder;
+    use crate::opcodes::Opcode;
+    use crate::symbols::SymbolMap;
+    use crate::types::{RefString, Reg, Type};
+
+    #[test]
+    fn apply() {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        b.function(
+            "",
+            &[int],
+            int,
+            &[int],
+            vec![
+                Opcode::Mov {
+                    dst: Reg(1),
+                    src: Reg(0),
+                },
+                Opcode::Ret { ret: Reg(1) },
+            ],
+        );
+        // b extends a, their fields are c and then d
+        let a = b.class("a", None, &[("c", int)]);
+        let child = b.class("b", Some(a), &[("d", int)]);
+        let mut code = b.build().unwrap();
+        let mut map = SymbolMap::default();
+        map.functions.insert(0, "update".to_owned());
+        map.types.insert(child.0, "Player".to_owned());
+        map.fields
+            .insert(child.0, BTreeMap::from([(0, "health".to_owned())]));
+        map.variables.insert(
+            0,
+            BTreeMap::from([(0, "speed".to_owned()), (1, "copy".to_owned())]),
+        );
+        assert_eq!(map.len(), 5);
+        map.apply(&mut code).unwrap();
+
+        let name = |s: RefString| code.strings[s.0].to_string();
+        let f = &code.functions[0];
+        assert_eq!(name(f.name), "update");
+        assert_eq!(f.arg_name(&code, 0).as_deref(), Some("speed"));
+        assert_eq!(f.var_name(&code, 0).as_deref(), Some("copy"));
+        let [Type::Obj(parent), Type::Obj(child)] = &code.types[a.0..] else {
+            unreachable!()
+        };
+        assert_eq!(name(child.name), "Player");
+        assert_eq!(name(parent.name), "a");
+        // The inherited field is renamed in the parent declaring it
+        assert_eq!(name(parent.own_fields[0].name), "health");
+        assert_eq!(name(child.fields[0].name), "health");
+        assert_eq!(name(child.own_fields[0].name), "d");
+
+        let mut other = SymbolMap::default();
+        other.types.insert(9, "Nope".to_owned());
+        assert!(other.apply(&mut code).is_err());
+        map.merge(other);
+        assert_eq!(map.types.len(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn json() {
+        let json =
+            r#"{ "functions": { "12": "updatePlayer" }, "fields": { "5": { "2": "health" } } }"#;
+        let map = SymbolMap::from_json(json).unwrap();
+        assert_eq!(map.functions[&12], "updatePlayer");
+        assert_eq!(map.fields[&5][&2], "health");
+        assert!(map.types.is_empty());
+        assert_eq!(SymbolMap::from_json(&map.to_json().unwrap()).unwrap(), map);
+    }
+}
@@ -0,0 +1,430 @@
+//! Export the string literals of a program for translation, and patch the translations back in.
+//!
+//! Only the strings used by the code are exported : type, field and function names are also in the string pool but
+//! changing them would break reflection. Each string keeps its index in the pool, and lists the functions using it
+//! as context for translators.
+//!
+//! Two formats are supported :
+//! - CSV with the columns `index,context,source,translation`, for spreadsheets
+//! - gettext PO, with the index as `msgctxt` and the functions as reference comments (`#:`), for translation tools
+//!
+//! Importing only replaces strings with a non-empty translation, after checking the source text still matches the
+//! bytecode. A translation is added to the pool and only the string literals ([Opcode::String]) are pointed to it,
+//! the original string might also be the name of a field. The patched bytecode can then be written with
+//! [Bytecode::serialize].
+
+use std::io::Write;
+
+use crate::analysis::usage::UsageIndex;
+use crate::opcodes::Opcode;
+use crate::types::{RefFun, RefString};
+use crate::{Bytecode, Error, Resolve, Result, Str};
+
+/// A string literal to translate
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TranslationEntry {
+    pub index: RefString,
+    /// Functions using this string, as `Class.method@findex`
+    pub context: Vec<Str>,
+    pub source: Str,
+}
+
+/// Name of a function with its class, used as a reference for translators
+fn fun_context(code: &Bytecode, fun: RefFun) -> Str {
+    let Ok(f) = fun.try_as_fn(code) else {
+        return Str::from(format!("@{}", fun.0));
+    };
+    match f.parent.and_then(|p| p.as_obj(code)) {
+        Some(obj) => Str::from(format!("{}.{}@{}", obj.name(code), f.name(code), fun.0)),
+        None => Str::from(format!("{}@{}", f.name(code), fun.0)),
+    }
+}
+
+/// Every string used by the code, in pool order
+pub fn translatable_strings(code: &Bytecode) -> Vec<TranslationEntry> {
+    let usage = UsageIndex::new(code);
+    code.strings
+        .iter()
+        .enumerate()
+        .filter_map(|(i, s)| {
+            let funs = usage.functions_using(RefString(i));
+            if funs.is_empty() || s.is_empty() {
+                return None;
+            }
+            Some(TranslationEntry {
+                index: RefString(i),
+                context: funs.into_iter().map(|f| fun_context(code, f)).collect(),
+                source: s.clone(),
+            })
+        })
+        .collect()
+}
+
+fn csv_field(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+/// Write the strings used by the code as CSV, with an empty translation column
+pub fn export_csv(code: &Bytecode, w: &mut impl Write) -> Result<()> {
+    writeln!(w, "index,context,source,translation")?;
+    for e in translatable_strings(code) {
+        writeln!(
+            w,
+            "{},{},{},\"\"",
+            e.index.0,
+            csv_field(&e.context.join(" ")),
+            csv_field(&e.source)
+        )?;
+    }
+    Ok(())
+}
+
+fn po_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Write the strings used by the code as a gettext PO template
+pub fn export_po(code: &Bytecode, w: &mut impl Write) -> Result<()> {
+    writeln!(w, "msgid \"\"")?;
+    writeln!(w, "msgstr \"Content-Type: text/plain; charset=UTF-8\\n\"")?;
+    for e in translatable_strings(code) {
+        writeln!(w)?;
+        writeln!(w, "#: {}", e.context.join(" "))?;
+        writeln!(w, "msgctxt \"{}\"", e.index.0)?;
+        writeln!(w, "msgid {}", po_string(&e.source))?;
+        writeln!(w, "msgstr \"\"")?;
+    }
+    Ok(())
+}
+
+fn invalid(line: usize, msg: impl Into<String>) -> Error {
+    Error::InvalidTranslation {
+        line,
+        msg: msg.into(),
+    }
+}
+
+/// Replace a string literal everywhere it is used, `line` is used for error reporting
+fn patch(
+    code: &mut Bytecode,
+    line: usize,
+    index: &str,
+    source: &str,
+    translation: &str,
+) -> Result<bool> {
+    if translation.is_empty() {
+        return Ok(false);
+    }
+    let index = index
+        .trim()
+        .parse::<usize>()
+        .map_err(|_| invalid(line, format!("invalid string index '{index}'")))?;
+    let current = code.try_get(RefString(index))?;
+    if &*current != source {
+        return Err(invalid(
+            line,
+            format!("string@{index} is '{current}' in the bytecode, not '{source}'"),
+        ));
+    }
+    let translated = match code.strings.iter().position(|s| s == translation) {
+        Some(i) => RefString(i),
+        None => {
+            code.strings.push(Str::from(translation));
+            RefString(code.strings.len() - 1)
+        }
+    };
+    for op in code.functions.iter_mut().flat_map(|f| &mut f.ops) {
+        if let Opcode::String { ptr, .. } = op {
+            if ptr.0 == index {
+                *ptr = translated;
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Split CSV records in fields, quoted fields can contain commas, newlines and doubled quotes.
+/// Returns each record with the line it starts at.
+fn csv_records(input: &str) -> Result<Vec<(usize, Vec<String>)>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut start = 1;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push((start, std::mem::take(&mut record)));
+                line += 1;
+                start = line;
+            }
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    if quoted {
+        return Err(invalid(start, "unterminated quoted field"));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((start, record));
+    }
+    Ok(records)
+}
+
+/// Apply the translations of a CSV file made with [export_csv]. Returns the number of strings replaced.
+pub fn import_csv(code: &mut Bytecode, input: &str) -> Result<usize> {
+    let mut count = 0;
+    // Skip the header
+    for (line, record) in csv_records(input)?.into_iter().skip(1) {
+        match record.as_slice() {
+            [index, _, source, translation] => {
+                count += patch(code, line, index, source, translation)? as usize;
+            }
+            [empty] if empty.is_empty() => {}
+            _ => {
+                return Err(invalid(
+                    line,
+                    format!("expected 4 fields, got {}", record.len()),
+                ))
+            }
+        }
+    }
+    Ok(count)
+}
+
+fn po_unescape(line: usize, s: &str) -> Result<String> {
+    let s = s
+        .trim()
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(|| invalid(line, "expected a quoted string"))?;
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some(c @ ('"' | '\\')) => out.push(c),
+            _ => return Err(invalid(line, "invalid escape sequence")),
+        }
+    }
+    Ok(out)
+}
+
+/// A PO entry being parsed
+#[derive(Default)]
+struct PoEntry {
+    line: usize,
+    fuzzy: bool,
+    ctxt: Option<String>,
+    id: String,
+    str: String,
+}
+
+impl PoEntry {
+    /// Entries without a context (like the header) weren't made by [export_po] and are ignored,
+    /// like fuzzy entries which need to be reviewed.
+    fn apply(self, code: &mut Bytecode) -> Result<bool> {
+        match self.ctxt {
+            Some(ctxt) if !self.fuzzy => patch(code, self.line, &ctxt, &self.id, &self.str),
+            _ => Ok(false),
+        }
+    }
+}
+
+/// Apply the translations of a PO file made from [export_po]. Returns the number of strings replaced.
+pub fn import_po(code: &mut Bytecode, input: &str) -> Result<usize> {
+    #[derive(Copy, Clone)]
+    enum Keyword {
+        Ctxt,
+        Id,
+        Str,
+    }
+
+    let mut count = 0;
+    let mut entry = PoEntry::default();
+    let mut current = None;
+    for (i, l) in input.lines().enumerate() {
+        let line = i + 1;
+        let l = l.trim();
+        // Anything but a continuation after the translation starts a new entry
+        if matches!(current, Some(Keyword::Str)) && !l.starts_with('"') {
+            count += std::mem::take(&mut entry).apply(code)? as usize;
+            current = None;
+        }
+        if l.is_empty() {
+            continue;
+        }
+        if let Some(flags) = l.strip_prefix("#,") {
+            entry.fuzzy |= flags.split(',').any(|f| f.trim() == "fuzzy");
+            continue;
+        }
+        if l.starts_with('#') {
+            continue;
+        }
+        let (keyword, value) = if let Some(v) = l.strip_prefix("msgctxt ") {
+            (Keyword::Ctxt, v)
+        } else if let Some(v) = l.strip_prefix("msgid ") {
+            (Keyword::Id, v)
+        } else if let Some(v) = l.strip_prefix("msgstr ") {
+            (Keyword::Str, v)
+        } else if l.starts_with('"') {
+            // Continuation of the previous string
+            let Some(keyword) = current else {
+                return Err(invalid(line, "string outside of an entry"));
+            };
+            (keyword, l)
+        } else {
+            return Err(invalid(line, format!("unexpected '{l}'")));
+        };
+        let value = po_unescape(line, value)?;
+        if current.is_none() {
+            entry.line = line;
+        }
+        match keyword {
+            Keyword::Ctxt => entry.ctxt.get_or_insert_with(String::new).push_str(&value),
+            Keyword::Id => entry.id.push_str(&value),
+            Keyword::Str => entry.str.push_str(&value),
+        }
+        current = Some(keyword);
+    }
+    count += entry.apply(code)? as usize;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::Builder;
+    use crate::opcodes::Opcode;
+    use crate::translation::{export_csv, export_po, import_csv, import_po};
+    use crate::types::{Reg, Type};
+    use crate::Bytecode;
+
+    fn code() -> Bytecode {
+        let mut b = Builder::new();
+        let void = b.ty(Type::Void);
+        let bytes = b.ty(Type::Bytes);
+        let main = b.function("main", &[], void, &[bytes], Vec::new());
+        let hello = b.string("Hello, \"world\"");
+        let bye = b.string("Bye\nnow");
+        // Also the name of the function
+        let name = b.string("main");
+        b.function_mut(main).ops = vec![
+            Opcode::String {
+                dst: Reg(0),
+                ptr: hello,
+            },
+            Opcode::String {
+                dst: Reg(0),
+                ptr: bye,
+            },
+            Opcode::String {
+                dst: Reg(0),
+                ptr: name,
+            },
+        ];
+        b.build().unwrap()
+    }
+
+    /// The string loaded by the opcode at `pos` of the main function
+    fn literal(code: &Bytecode, pos: usize) -> &str {
+        match code.functions[0].ops[pos] {
+            Opcode::String { ptr, .. } => &code.strings[ptr.0],
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let mut code = code();
+        let mut out = Vec::new();
+        export_csv(&code, &mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        assert_eq!(
+            csv,
+            "index,context,source,translation\n\
+             1,\"main@0\",\"main\",\"\"\n\
+             2,\"main@0\",\"Hello, \"\"world\"\"\",\"\"\n\
+             3,\"main@0\",\"Bye\nnow\",\"\"\n"
+        );
+        let translated = csv
+            .replace("\"main\",\"\"", "\"main\",\"principal\"")
+            .replace(
+                "\"\"world\"\"\",\"\"",
+                "\"\"world\"\"\",\"Bonjour, \"\"monde\"\"\"",
+            );
+        assert_eq!(import_csv(&mut code, &translated).unwrap(), 2);
+        assert_eq!(literal(&code, 0), "Bonjour, \"monde\"");
+        assert_eq!(literal(&code, 1), "Bye\nnow");
+        assert_eq!(literal(&code, 2), "principal");
+        // Only the literal is translated
+        assert_eq!(code.functions[0].name(&code), "main");
+        // Importing again reuses the translations
+        let len = code.strings.len();
+        assert_eq!(import_csv(&mut code, &translated).unwrap(), 2);
+        assert_eq!(code.strings.len(), len);
+        // The source doesn't match the bytecode
+        assert!(import_csv(&mut code, &translated.replace("\"main\",", "\"Main\",")).is_err());
+    }
+
+    #[test]
+    fn po_round_trip() {
+        let mut code = code();
+        let mut out = Vec::new();
+        export_po(&code, &mut out).unwrap();
+        let po = String::from_utf8(out).unwrap();
+        assert!(po.contains("#: main@0\nmsgctxt \"3\"\nmsgid \"Bye\\nnow\"\nmsgstr \"\"\n"));
+        let translated = po
+            .replacen(
+                "msgid \"Bye\\nnow\"\nmsgstr \"\"",
+                "msgid \"Bye\\nnow\"\nmsgstr \"Au revoir\\n\"\n\"maintenant\"",
+                1,
+            )
+            .replacen("msgctxt \"2\"", "#, fuzzy\nmsgctxt \"2\"", 1)
+            .replacen(
+                "msgid \"Hello, \\\"world\\\"\"\nmsgstr \"\"",
+                "msgid \"Hello, \\\"world\\\"\"\nmsgstr \"Salut\"",
+                1,
+            );
+        assert_eq!(import_po(&mut code, &translated).unwrap(), 1);
+        assert_eq!(literal(&code, 0), "Hello, \"world\"");
+        assert_eq!(literal(&code, 1), "Au revoir\nmaintenant");
+    }
+}
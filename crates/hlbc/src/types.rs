@@ -1,7 +1,9 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::ops::Index;
 
-use crate::{Bytecode, Opcode, Resolve, Str};
+use smallvec::SmallVec;
+
+use crate::{Bytecode, Error, Opcode, Resolve, Result, Str};
 
 /// Offset for a jump instruction. Can be negative, indicating a backward jump.
 pub type JumpOffset = i32;
@@ -13,22 +15,47 @@ pub type InlineBool = bool;
 ///
 /// Registers are a function local variables.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct Reg(pub u32);
 
 /// A reference to the i32 constant pool
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct RefInt(pub usize);
 
 /// A reference to the f64 constant pool
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct RefFloat(pub usize);
 
 /// A reference to the bytes constant pool
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct RefBytes(pub usize);
 
 /// Reference to the string constant pool
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct RefString(pub usize);
 
 impl RefString {
@@ -40,10 +67,16 @@ impl RefString {
 
 /// A reference to a global
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct RefGlobal(pub usize);
 
 /// An object field definition
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ObjField {
     /// Field name
     pub name: RefString,
@@ -58,11 +91,17 @@ impl ObjField {
 }
 
 /// A reference to an object field
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct RefField(pub usize);
 
 /// An object method definition
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ObjProto {
     /// Method name
     pub name: RefString,
@@ -80,6 +119,7 @@ impl ObjProto {
 
 /// An enum variant definition
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EnumConstruct {
     /// Variant name
     pub name: RefString,
@@ -95,10 +135,16 @@ impl EnumConstruct {
 
 /// A reference to an enum variant
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct RefEnumConstruct(pub usize);
 
 /// Common type for [Type::Fun] and [Type::Method]
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TypeFun {
     pub args: Vec<RefType>,
     pub ret: RefType,
@@ -106,6 +152,7 @@ pub struct TypeFun {
 
 /// Common type for [Type::Obj] and [Type::Struct]
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TypeObj {
     pub name: RefString,
     pub super_: Option<RefType>,
@@ -115,7 +162,7 @@ pub struct TypeObj {
     /// Methods in this class
     pub protos: Vec<ObjProto>,
     /// Functions bounds to class fields
-    pub bindings: HashMap<RefField, RefFun>,
+    pub bindings: BTreeMap<RefField, RefFun>,
 
     // Data below is not stored in the bytecode
     /// Fields including parents in the hierarchy
@@ -130,7 +177,7 @@ impl TypeObj {
     /// Get the static part of this class
     pub fn get_static_type<'a>(&self, ctx: &'a Bytecode) -> Option<&'a TypeObj> {
         if self.global.0 > 0 {
-            ctx.globals[self.global.0 - 1].as_obj(ctx)
+            ctx.try_get(RefGlobal(self.global.0 - 1)).ok()?.as_obj(ctx)
         } else {
             None
         }
@@ -139,6 +186,7 @@ impl TypeObj {
 
 /// Type available in the hashlink type system. Every type is one of those.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
     Void,
     UI8,
@@ -224,7 +272,12 @@ impl Type {
 }
 
 /// Reference to a type in the constant pool
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct RefType(pub usize);
 
 impl RefType {
@@ -260,29 +313,44 @@ impl RefType {
         }
     }
 
+    /// Returns `None` if this isn't a function type or if the reference is invalid
     pub fn as_fun<'a>(&self, ctx: &'a Bytecode) -> Option<&'a TypeFun> {
-        match ctx.get(*self) {
+        match ctx.try_get(*self).ok()? {
             Type::Fun(fun) => Some(fun),
             Type::Method(fun) => Some(fun),
             _ => None,
         }
     }
 
+    /// Returns `None` if this isn't an object type or if the reference is invalid
     pub fn as_obj<'a>(&self, ctx: &'a Bytecode) -> Option<&'a TypeObj> {
-        ctx.get(*self).get_type_obj()
+        ctx.try_get(*self).ok()?.get_type_obj()
     }
 
     pub fn field<'a>(&self, field: RefField, ctx: &'a Bytecode) -> Option<&'a ObjField> {
-        self.as_obj(ctx).map(|obj| &obj.fields[field.0])
+        self.as_obj(ctx)?.fields.get(field.0)
     }
 
     pub fn method<'a>(&self, meth: usize, ctx: &'a Bytecode) -> Option<&'a ObjProto> {
-        self.as_obj(ctx).map(|obj| &obj.protos[meth])
+        self.as_obj(ctx)?.protos.get(meth)
+    }
+
+    /// Resolve an enum variant of this type. Returns `None` if this isn't an enum type.
+    pub fn enum_construct<'a>(
+        &self,
+        construct: RefEnumConstruct,
+        ctx: &'a Bytecode,
+    ) -> Option<&'a EnumConstruct> {
+        match ctx.try_get(*self).ok()? {
+            Type::Enum { constructs, .. } => constructs.get(construct.0),
+            _ => None,
+        }
     }
 }
 
 /// A native function reference. Contains no code but indicates the library from where to load it.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Native {
     /// Native function name
     pub name: RefString,
@@ -323,6 +391,7 @@ impl Native {
 
 /// A function definition with its code.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Function {
     /// Type of the function : args and return type. Guaranteed to be a [TypeFun].
     pub t: RefType,
@@ -350,9 +419,13 @@ impl Function {
         self[reg]
     }
 
-    /// Convenience method to resolve the function name
+    /// Convenience method to resolve the function name, or the name inferred for an anonymous function by
+    /// [Bytecode::name_closures]
     pub fn name(&self, code: &Bytecode) -> Str {
-        code.get(self.name)
+        match code.closure_names.get(&self.findex) {
+            Some(name) if self.name.0 == 0 => name.clone(),
+            _ => code.get(self.name),
+        }
     }
 
     /// Get the function signature type
@@ -371,6 +444,15 @@ impl Function {
         code.index(self.ty(code).ret)
     }
 
+    /// The first argument of the function is `this` : methods and constructors
+    pub fn has_this(&self, code: &Bytecode) -> bool {
+        self.is_method()
+            || code
+                .strings
+                .get(self.name.0)
+                .is_some_and(|name| name == "__constructor__")
+    }
+
     /// Uses the assigns to find the name of an argument
     pub fn arg_name(&self, code: &Bytecode, pos: usize) -> Option<Str> {
         self.assigns.as_ref().and_then(|a| {
@@ -400,6 +482,19 @@ impl Function {
         })
     }
 
+    /// *Debug* Source file and line the opcode at `pos` was compiled from
+    pub fn line(&self, code: &Bytecode, pos: usize) -> Option<(Str, usize)> {
+        let &(file, line) = self.debug_info.as_ref()?.get(pos)?;
+        Some((code.debug_files.as_ref()?.get(file)?.clone(), line))
+    }
+
+    /// *Debug* Source file and line of each opcode, indexed by opcode position. Empty without debug info.
+    pub fn line_map(&self, code: &Bytecode) -> Vec<(Str, usize)> {
+        (0..self.ops.len())
+            .map_while(|pos| self.line(code, pos))
+            .collect()
+    }
+
     /// A function is a method if the first argument has the same type as the parent type
     pub fn is_method(&self) -> bool {
         self.parent
@@ -411,6 +506,11 @@ impl Function {
     pub fn ops(&self) -> impl Iterator<Item = (usize, &Opcode)> {
         self.ops.iter().enumerate()
     }
+
+    /// Like [Function::ops], with the absolute positions each opcode can jump to, see [Opcode::jump_targets]
+    pub fn ops_with_targets(&self) -> impl Iterator<Item = (usize, &Opcode, SmallVec<[usize; 2]>)> {
+        self.ops().map(|(i, op)| (i, op, op.jump_targets(i)))
+    }
 }
 
 impl Index<Reg> for Function {
@@ -424,6 +524,11 @@ impl Index<Reg> for Function {
 
 /// Index reference to a function or a native in the pool (findex)
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct RefFun(pub usize);
 
 impl RefFun {
@@ -432,6 +537,13 @@ impl RefFun {
         code.get(*self).as_fn()
     }
 
+    /// Checked version of [RefFun::as_fn], fails if the reference is invalid or points to a native
+    pub fn try_as_fn<'a>(&self, code: &'a Bytecode) -> Result<&'a Function> {
+        code.try_get(*self)?.as_fn().ok_or_else(|| {
+            Error::MalformedBytecode(format!("findex {} is a native, not a function", self.0))
+        })
+    }
+
     pub fn name(&self, code: &Bytecode) -> Str {
         match code.get(*self) {
             FunPtr::Fun(fun) => fun.name(code),
@@ -495,7 +607,21 @@ impl<'a> FunPtr<'a> {
 
 /// A constant definition
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConstantDef {
     pub global: RefGlobal,
     pub fields: Vec<usize>,
 }
+
+/// A field of a [ConstantDef] resolved according to its type, see [Bytecode::global_constant_values]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ConstantValue {
+    Int(RefInt),
+    Float(RefFloat),
+    Bool(bool),
+    /// Bytes are taken from the string pool
+    String(RefString),
+    Type(RefType),
+    /// Fields of any other type are set to the value of a global
+    Global(RefGlobal),
+}
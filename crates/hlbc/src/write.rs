@@ -1,5 +1,7 @@
 use std::ffi::CString;
-use std::io::Write;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
 
 use byteorder::{LittleEndian, WriteBytesExt};
 
@@ -8,9 +10,22 @@ use crate::{Bytecode, ConstantDef, Function, Native, ObjField, Str, Type, TypeOb
 use crate::{Error, Result};
 
 impl Bytecode {
+    /// Write the bytecode to a file, creating or truncating it.
+    /// The output can be loaded by the Hashlink VM.
+    pub fn to_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut w = BufWriter::with_capacity(512 * 1024, fs::File::create(path)?);
+        self.serialize(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
     /// Serialize the bytecode to any sink.
     /// Bytecode is serialized to the same format.
+    ///
+    /// Serializing is deterministic : the same [Bytecode] always produces the same bytes.
+    /// Fails without writing anything if the sections don't match the version, see [Bytecode::check_sections].
     pub fn serialize(&self, w: &mut impl Write) -> Result<()> {
+        self.check_sections()?;
         w.write_all(&[b'H', b'L', b'B'])?;
         w.write_u8(self.version)?;
         write_var(w, if self.debug_files.is_some() { 1 } else { 0 })?;
@@ -142,6 +157,7 @@ impl TypeObj {
             p.findex.write(w)?;
             write_var(w, p.pindex)?;
         }
+        // Bindings are sorted by field
         for (fi, fun) in &self.bindings {
             fi.write(w)?;
             fun.write(w)?;
@@ -310,7 +326,7 @@ pub(crate) fn write_var(w: &mut impl Write, value: i32) -> Result<()> {
         if value < 0x2000 {
             w.write_u8(((value >> 8) | 0xA0) as u8)?;
             w.write_u8((value & 0xFF) as u8)?;
-        } else if value < 20000000 {
+        } else if value < 0x20000000 {
             w.write_u8(((value >> 24) | 0xE0) as u8)?;
             w.write_u8(((value >> 16) & 0xFF) as u8)?;
             w.write_u8(((value >> 8) & 0xFF) as u8)?;
@@ -318,7 +334,7 @@ pub(crate) fn write_var(w: &mut impl Write, value: i32) -> Result<()> {
         } else {
             return Err(Error::ValueOutOfBounds {
                 value,
-                limit: 20000000,
+                limit: 0x20000000,
             });
         }
     } else if value < 0x80 {
@@ -334,17 +350,24 @@ pub(crate) fn write_var(w: &mut impl Write, value: i32) -> Result<()> {
     } else {
         return Err(Error::ValueOutOfBounds {
             value,
-            limit: 20000000,
+            limit: 0x20000000,
         });
     }
     Ok(())
 }
 
 pub(crate) fn write_strings(w: &mut impl Write, strings: &[Str]) -> Result<()> {
-    let cstr: Vec<CString> = strings
+    let cstr = strings
         .iter()
-        .map(|s| CString::new(s.as_bytes()).unwrap())
-        .collect();
+        .map(|s| {
+            CString::new(s.as_bytes()).map_err(|e| {
+                Error::MalformedBytecode(format!(
+                    "String '{s}' contains a nul byte at position {}",
+                    e.nul_position()
+                ))
+            })
+        })
+        .collect::<Result<Vec<CString>>>()?;
     let size = cstr
         .iter()
         .map(|s| s.as_bytes_with_nul().len())
@@ -387,12 +410,56 @@ fn flush_repeat(
 mod tests {
     use std::fs;
 
+    use crate::read::read_vari;
+    use crate::write::write_var;
     use crate::Bytecode;
 
+    #[test]
+    fn var_roundtrip() {
+        for value in [
+            0,
+            1,
+            0x7F,
+            0x80,
+            0x1FFF,
+            0x2000,
+            0x1FFFFFFF,
+            -1,
+            -0x1FFF,
+            -0x2000,
+            -0x1FFFFFFF,
+        ] {
+            let mut buf = Vec::new();
+            write_var(&mut buf, value).unwrap();
+            assert_eq!(read_vari(&mut buf.as_slice()).unwrap(), value);
+        }
+        assert!(write_var(&mut Vec::new(), 0x20000000).is_err());
+        assert!(write_var(&mut Vec::new(), -0x20000000).is_err());
+    }
+
+    /// Serializing what we deserialized from a serialized bytecode must give the exact same bytes.
+    #[test]
+    fn ser_deser_stable_all() {
+        for entry in fs::read_dir("../../data").unwrap() {
+            let path = entry.unwrap().path();
+            if let Some(ext) = path.extension() {
+                if ext == "hl" {
+                    let code = Bytecode::from_file(&path).unwrap();
+                    let mut first = Vec::new();
+                    code.serialize(&mut first).unwrap();
+                    let code = Bytecode::deserialize(first.as_slice()).unwrap();
+                    let mut second = Vec::with_capacity(first.len());
+                    code.serialize(&mut second).unwrap();
+                    assert_eq!(first, second, "{}", path.display());
+                }
+            }
+        }
+    }
+
     //#[test]
     fn ser_eq_deser() {
         // FIXME this test fails because we are not generating the same bytecode after deserialization
-        // This has to do with non deterministic hashing because we store some things in HashMap (fields ?)
+        // The bytecode emitted by the Haxe compiler isn't stored in a canonical form (bindings order, var encoding)
         let data = fs::read("../../data/Anonymous.hl").unwrap();
         // Deserialize
         let code = Bytecode::deserialize(&mut data.as_slice()).unwrap();
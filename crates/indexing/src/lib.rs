@@ -1,43 +1,72 @@
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 
-use hlbc::types::RefFun;
+use hlbc::cancel::CancellationToken;
+use hlbc::page::Page;
+use hlbc::types::{FunPtr, RefFun};
 use hlbc::Bytecode;
 
 #[cfg(feature = "tantivy")]
 mod tantivy;
 
 pub trait Searcher {
-    fn search(&self, code: &Bytecode, needle: &str, limit: usize) -> Vec<RefFun>;
+    /// Search functions by name. Results are ordered by relevance, then by findex.
+    fn search(&self, code: &Bytecode, needle: &str, page: Page) -> Vec<RefFun> {
+        // A token that is never cancelled
+        self.search_cancellable(code, needle, page, &CancellationToken::new())
+            .unwrap_or_default()
+    }
+
+    /// Same as [Searcher::search], but gives up with [Error::Cancelled](hlbc::Error::Cancelled) when `cancel` is
+    /// cancelled.
+    fn search_cancellable(
+        &self,
+        code: &Bytecode,
+        needle: &str,
+        page: Page,
+        cancel: &CancellationToken,
+    ) -> hlbc::Result<Vec<RefFun>>;
+}
+
+/// The candidates of a search, stops early when `cancel` is cancelled
+fn candidates<'a>(
+    code: &'a Bytecode,
+    cancel: &'a CancellationToken,
+) -> impl Iterator<Item = FunPtr<'a>> + 'a {
+    code.functions().take_while(|_| !cancel.is_cancelled())
 }
 
-struct Comp<T>(T, f32);
+/// A candidate with its score and its position in the candidates, used to order equal scores
+struct Comp<T>(T, f32, usize);
 
 impl<T> Eq for Comp<T> {}
 
 impl<T> PartialEq<Self> for Comp<T> {
     fn eq(&self, other: &Comp<T>) -> bool {
-        self.1.eq(&other.1)
+        self.cmp(other) == Ordering::Equal
     }
 }
 
 impl<T> PartialOrd<Self> for Comp<T> {
     fn partial_cmp(&self, other: &Comp<T>) -> Option<Ordering> {
-        other.1.partial_cmp(&self.1)
+        Some(self.cmp(other))
     }
 }
 
 impl<T> Ord for Comp<T> {
     fn cmp(&self, other: &Comp<T>) -> Ordering {
-        other.1.total_cmp(&self.1)
+        other.1.total_cmp(&self.1).then(self.2.cmp(&other.2))
     }
 }
 
-pub fn top_candidates<T>(n: usize, results: impl Iterator<Item = (T, f32)>) -> Vec<(T, f32)> {
-    let mut top = BinaryHeap::with_capacity(n + 1);
-    for (c, score) in results {
+/// Select a page of the best candidates, ordered by decreasing score.
+/// Candidates with the same score keep their relative order so pages are stable.
+pub fn top_candidates<T>(page: Page, results: impl Iterator<Item = (T, f32)>) -> Vec<(T, f32)> {
+    let n = page.end();
+    let mut top = BinaryHeap::with_capacity(n.saturating_add(1).min(1024));
+    for (i, (c, score)) in results.enumerate() {
         if score > 0.0 {
-            top.push(Comp(c, score));
+            top.push(Comp(c, score, i));
             if top.len() > n {
                 top.pop();
             }
@@ -45,6 +74,7 @@ pub fn top_candidates<T>(n: usize, results: impl Iterator<Item = (T, f32)>) -> V
     }
     top.into_sorted_vec()
         .into_iter()
+        .skip(page.offset)
         .map(|c| (c.0, c.1))
         .collect()
 }
@@ -52,11 +82,17 @@ pub fn top_candidates<T>(n: usize, results: impl Iterator<Item = (T, f32)>) -> V
 pub struct Contains;
 
 impl Searcher for Contains {
-    fn search(&self, code: &Bytecode, needle: &str, limit: usize) -> Vec<RefFun> {
+    fn search_cancellable(
+        &self,
+        code: &Bytecode,
+        needle: &str,
+        page: Page,
+        cancel: &CancellationToken,
+    ) -> hlbc::Result<Vec<RefFun>> {
         let needle_len = needle.len() as f32;
-        top_candidates(
-            limit,
-            code.functions().map(|f| {
+        let results = top_candidates(
+            page,
+            candidates(code, cancel).map(|f| {
                 let name = f.name(code);
                 let len = name.len() as f32;
                 (
@@ -73,7 +109,9 @@ impl Searcher for Contains {
         )
         .into_iter()
         .map(|(c, s)| c)
-        .collect()
+        .collect();
+        cancel.check()?;
+        Ok(results)
     }
 }
 
@@ -108,10 +146,16 @@ impl ClangdSearcher {
 }
 
 impl Searcher for ClangdSearcher {
-    fn search(&self, code: &Bytecode, needle: &str, limit: usize) -> Vec<RefFun> {
-        top_candidates(
-            limit,
-            code.functions().map(|f| {
+    fn search_cancellable(
+        &self,
+        code: &Bytecode,
+        needle: &str,
+        page: Page,
+        cancel: &CancellationToken,
+    ) -> hlbc::Result<Vec<RefFun>> {
+        let results = top_candidates(
+            page,
+            candidates(code, cancel).map(|f| {
                 (
                     f.findex(),
                     fuzzy_matcher::FuzzyMatcher::fuzzy_match(&self.0, &f.name(code), needle)
@@ -122,7 +166,9 @@ impl Searcher for ClangdSearcher {
         )
         .into_iter()
         .map(|(c, s)| c)
-        .collect()
+        .collect();
+        cancel.check()?;
+        Ok(results)
     }
 }
 
@@ -135,10 +181,16 @@ impl SkimSearcher {
 }
 
 impl Searcher for SkimSearcher {
-    fn search(&self, code: &Bytecode, needle: &str, limit: usize) -> Vec<RefFun> {
-        top_candidates(
-            limit,
-            code.functions().map(|f| {
+    fn search_cancellable(
+        &self,
+        code: &Bytecode,
+        needle: &str,
+        page: Page,
+        cancel: &CancellationToken,
+    ) -> hlbc::Result<Vec<RefFun>> {
+        let results = top_candidates(
+            page,
+            candidates(code, cancel).map(|f| {
                 (
                     f.findex(),
                     fuzzy_matcher::FuzzyMatcher::fuzzy_match(&self.0, &f.name(code), needle)
@@ -149,6 +201,40 @@ impl Searcher for SkimSearcher {
         )
         .into_iter()
         .map(|(c, s)| c)
-        .collect()
+        .collect();
+        cancel.check()?;
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hlbc::cancel::CancellationToken;
+    use hlbc::page::Page;
+    use hlbc::Bytecode;
+
+    use crate::{top_candidates, Contains, Searcher};
+
+    #[test]
+    fn stable_pages() {
+        let results = || [(0, 1.0), (1, 2.0), (2, 1.0), (3, 0.0), (4, 1.0)].into_iter();
+        let ids = |page| {
+            top_candidates(page, results())
+                .into_iter()
+                .map(|(c, _)| c)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(Page::first(2)), vec![1, 0]);
+        assert_eq!(ids(Page::first(2).next()), vec![2, 4]);
+        assert_eq!(ids(Page::ALL), vec![1, 0, 2, 4]);
+    }
+
+    #[test]
+    fn cancelled_search() {
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert!(Contains
+            .search_cancellable(&Bytecode::default(), "main", Page::ALL, &cancel)
+            .is_err());
     }
 }
@@ -8,6 +8,7 @@ use tantivy::schema::{
 use tantivy::tokenizer::{LowerCaser, NgramTokenizer, TextAnalyzer};
 use tantivy::{doc, Index};
 
+use hlbc::page::Page;
 use hlbc::types::RefFun;
 use hlbc::Bytecode;
 
@@ -62,12 +63,21 @@ impl TantivySearcher {
 }
 
 impl TantivySearcher {
-    pub fn query(&self, query_text: &str) -> Vec<RefFun> {
+    pub fn query(&self, query_text: &str, page: Page) -> Vec<RefFun> {
         let reader = self.index.reader().unwrap();
         let searcher = reader.searcher();
         let parser = QueryParser::for_index(&self.index, vec![Field::from_field_id(1)]);
         let query = parser.parse_query(query_text).unwrap();
-        let top_docs = searcher.search(&query, &TopDocs::with_limit(10)).unwrap();
+        // The collector allocates room for the whole page, it can't be larger than the index
+        let limit = page
+            .limit
+            .min((searcher.num_docs() as usize).saturating_sub(page.offset));
+        if limit == 0 {
+            return Vec::new();
+        }
+        let top_docs = searcher
+            .search(&query, &TopDocs::with_limit(limit).and_offset(page.offset))
+            .unwrap();
         top_docs
             .into_iter()
             .map(|(_, d)| {
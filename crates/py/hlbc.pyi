@@ -0,0 +1,74 @@
+"""Hashlink bytecode disassembler, analyzer and decompiler"""
+
+from os import PathLike
+from typing import List, Optional, Tuple, Union
+
+__version__: str
+
+class Bytecode:
+    """A loaded bytecode file"""
+
+    @staticmethod
+    def load(path: Union[str, PathLike]) -> "Bytecode":
+        """Load a bytecode file, like `hlboot.dat` or a `.hl` file"""
+    @staticmethod
+    def from_bytes(data: bytes) -> "Bytecode":
+        """Load bytecode from the content of a file"""
+    @property
+    def version(self) -> int:
+        """Bytecode format version"""
+    @property
+    def entrypoint(self) -> "Function":
+        """The function executed on startup"""
+    @property
+    def strings(self) -> List[str]:
+        """The string constant pool"""
+    def functions(self) -> List["Function"]:
+        """Every function with code, natives excluded, in bytecode order"""
+    def natives(self) -> List["Function"]:
+        """Every native function, in bytecode order"""
+    def function(self, findex: int) -> "Function":
+        """A function or a native by findex"""
+    def function_by_name(self, name: str) -> Optional["Function"]:
+        """A function by name, `None` if there is none"""
+    def types(self) -> List["Type"]:
+        """Every type, in bytecode order"""
+    def decompile_function(self, findex: int) -> str:
+        """Decompile a function to Haxe source code"""
+    def decompile_class(self, index: int) -> str:
+        """Decompile a class to Haxe source code"""
+
+class Function:
+    """A function or a native"""
+
+    @property
+    def findex(self) -> int: ...
+    @property
+    def name(self) -> str: ...
+    @property
+    def is_native(self) -> bool: ...
+    @property
+    def header(self) -> str:
+        """The function signature"""
+    def disassemble(self) -> List[str]:
+        """The opcodes in the assembly view, one string per opcode. Empty for a native."""
+    def decompile(self) -> str:
+        """Decompile the function to Haxe source code, raises a `ValueError` for a native"""
+
+class Type:
+    """A type of the bytecode"""
+
+    @property
+    def index(self) -> int: ...
+    @property
+    def name(self) -> str:
+        """The type name as shown in the disassembly, e.g. `i32` or `game.Player`"""
+    @property
+    def kind(self) -> str:
+        """What kind of type it is, e.g. `obj`, `fun` or `enum`"""
+    def fields(self) -> List[Tuple[str, str]]:
+        """Fields declared by the class, as `(name, type)` tuples"""
+    def methods(self) -> List[Function]:
+        """Methods of the class and functions bound to its fields"""
+    def decompile(self) -> str:
+        """Decompile the class to Haxe source code"""
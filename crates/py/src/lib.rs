@@ -0,0 +1,383 @@
+//! Python bindings to load, inspect and decompile Hashlink bytecode. Built as the `hlbc` python module with
+//! [maturin](https://www.maturin.rs), see the README.
+
+// The methods generated by pyo3 convert the returned PyErr into itself
+#![allow(clippy::useless_conversion)]
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use pyo3::exceptions::{PyIndexError, PyValueError};
+use pyo3::prelude::*;
+
+use hlbc::types::{RefFun, RefType, Type, TypeObj};
+use hlbc::{Bytecode, Resolve};
+use hlbc_decompiler::{facade, DecompilerOptions};
+
+/// Indentation of the decompiled code
+const INDENT: usize = 4;
+
+/// Errors of hlbc as python exceptions
+fn py_err(e: hlbc::Error) -> PyErr {
+    match e {
+        hlbc::Error::IoError(e) => e.into(),
+        hlbc::Error::InvalidIndex { .. } => PyIndexError::new_err(e.to_string()),
+        e => PyValueError::new_err(e.to_string()),
+    }
+}
+
+/// A loaded bytecode file
+#[pyclass(name = "Bytecode", module = "hlbc", frozen)]
+struct PyBytecode {
+    code: Arc<Bytecode>,
+}
+
+#[pymethods]
+impl PyBytecode {
+    /// Load a bytecode file, like `hlboot.dat` or a `.hl` file
+    #[staticmethod]
+    fn load(path: PathBuf) -> PyResult<Self> {
+        Ok(Self {
+            code: Arc::new(facade::open(path).map_err(py_err)?),
+        })
+    }
+
+    /// Load bytecode from the content of a file
+    #[staticmethod]
+    fn from_bytes(data: &[u8]) -> PyResult<Self> {
+        Ok(Self {
+            code: Arc::new(Bytecode::deserialize(data).map_err(py_err)?),
+        })
+    }
+
+    /// Bytecode format version
+    #[getter]
+    fn version(&self) -> u8 {
+        self.code.version
+    }
+
+    /// The function executed on startup
+    #[getter]
+    fn entrypoint(&self) -> PyFunction {
+        self.function_unchecked(self.code.entrypoint)
+    }
+
+    /// The string constant pool
+    #[getter]
+    fn strings(&self) -> Vec<String> {
+        self.code.strings.iter().map(|s| s.to_string()).collect()
+    }
+
+    /// Every function with code, natives excluded, in bytecode order
+    fn functions(&self) -> Vec<PyFunction> {
+        self.code
+            .functions
+            .iter()
+            .map(|f| self.function_unchecked(f.findex))
+            .collect()
+    }
+
+    /// Every native function, in bytecode order
+    fn natives(&self) -> Vec<PyFunction> {
+        self.code
+            .natives
+            .iter()
+            .map(|n| self.function_unchecked(n.findex))
+            .collect()
+    }
+
+    /// A function or a native by findex
+    fn function(&self, findex: usize) -> PyResult<PyFunction> {
+        self.code.try_get(RefFun(findex)).map_err(py_err)?;
+        Ok(self.function_unchecked(RefFun(findex)))
+    }
+
+    /// A function by name, `None` if there is none
+    fn function_by_name(&self, name: &str) -> Option<PyFunction> {
+        self.code
+            .function_by_name(name)
+            .map(|f| self.function_unchecked(f.findex))
+    }
+
+    /// Every type, in bytecode order
+    fn types(&self) -> Vec<PyType> {
+        (0..self.code.types.len())
+            .map(|i| PyType {
+                code: self.code.clone(),
+                index: RefType(i),
+            })
+            .collect()
+    }
+
+    /// Decompile a function to Haxe source code
+    fn decompile_function(&self, findex: usize) -> PyResult<String> {
+        self.function(findex)?.decompile()
+    }
+
+    /// Decompile a class to Haxe source code
+    fn decompile_class(&self, index: usize) -> PyResult<String> {
+        facade::decompile_class_indented(
+            &self.code,
+            RefType(index),
+            &DecompilerOptions::default(),
+            INDENT,
+        )
+        .map_err(py_err)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "<Bytecode v{} with {} functions, {} natives and {} types>",
+            self.code.version,
+            self.code.functions.len(),
+            self.code.natives.len(),
+            self.code.types.len()
+        )
+    }
+}
+
+impl PyBytecode {
+    fn function_unchecked(&self, findex: RefFun) -> PyFunction {
+        PyFunction {
+            code: self.code.clone(),
+            findex,
+        }
+    }
+}
+
+/// A function or a native
+#[pyclass(name = "Function", module = "hlbc", frozen)]
+struct PyFunction {
+    code: Arc<Bytecode>,
+    findex: RefFun,
+}
+
+#[pymethods]
+impl PyFunction {
+    #[getter]
+    fn findex(&self) -> usize {
+        self.findex.0
+    }
+
+    #[getter]
+    fn name(&self) -> String {
+        self.findex.name(&self.code).to_string()
+    }
+
+    #[getter]
+    fn is_native(&self) -> bool {
+        self.code.get(self.findex).is_native()
+    }
+
+    /// The function signature, e.g. `fn update@12 (Game, f64) -> void`
+    #[getter]
+    fn header(&self) -> String {
+        facade::function_header(&self.code, self.findex).unwrap_or_default()
+    }
+
+    /// The opcodes in the assembly view, one string per opcode. Empty for a native.
+    fn disassemble(&self) -> PyResult<Vec<String>> {
+        facade::disassemble(&self.code, self.findex).map_err(py_err)
+    }
+
+    /// Decompile the function to Haxe source code, raises a `ValueError` for a native
+    fn decompile(&self) -> PyResult<String> {
+        facade::decompile_function_indented(
+            &self.code,
+            self.findex,
+            &DecompilerOptions::default(),
+            INDENT,
+        )
+        .map_err(py_err)
+    }
+
+    fn __repr__(&self) -> String {
+        self.header()
+    }
+}
+
+/// A type of the bytecode
+#[pyclass(name = "Type", module = "hlbc", frozen)]
+struct PyType {
+    code: Arc<Bytecode>,
+    index: RefType,
+}
+
+impl PyType {
+    fn obj(&self) -> PyResult<&TypeObj> {
+        match self.code.try_get(self.index).map_err(py_err)? {
+            Type::Obj(obj) | Type::Struct(obj) => Ok(obj),
+            _ => Err(PyValueError::new_err(format!(
+                "type@{} is not a class",
+                self.index.0
+            ))),
+        }
+    }
+}
+
+#[pymethods]
+impl PyType {
+    #[getter]
+    fn index(&self) -> usize {
+        self.index.0
+    }
+
+    /// The type name as shown in the disassembly, e.g. `i32` or `game.Player`
+    #[getter]
+    fn name(&self) -> String {
+        facade::type_name(&self.code, self.index).unwrap_or_default()
+    }
+
+    /// What kind of type it is, e.g. `obj`, `fun` or `enum`
+    #[getter]
+    fn kind(&self) -> &'static str {
+        match &self.code.types[self.index.0] {
+            Type::Void => "void",
+            Type::UI8 => "u8",
+            Type::UI16 => "u16",
+            Type::I32 => "i32",
+            Type::I64 => "i64",
+            Type::F32 => "f32",
+            Type::F64 => "f64",
+            Type::Bool => "bool",
+            Type::Bytes => "bytes",
+            Type::Dyn => "dynamic",
+            Type::Fun(_) => "fun",
+            Type::Obj(_) => "obj",
+            Type::Array => "array",
+            Type::Type => "type",
+            Type::Ref(_) => "ref",
+            Type::Virtual { .. } => "virtual",
+            Type::DynObj => "dynobj",
+            Type::Abstract { .. } => "abstract",
+            Type::Enum { .. } => "enum",
+            Type::Null(_) => "null",
+            Type::Method(_) => "method",
+            Type::Struct(_) => "struct",
+            Type::Packed(_) => "packed",
+        }
+    }
+
+    /// Fields declared by the class, as `(name, type)` tuples
+    fn fields(&self) -> PyResult<Vec<(String, String)>> {
+        Ok(self
+            .obj()?
+            .own_fields
+            .iter()
+            .map(|f| {
+                (
+                    f.name(&self.code).to_string(),
+                    facade::type_name(&self.code, f.t).unwrap_or_default(),
+                )
+            })
+            .collect())
+    }
+
+    /// Methods of the class and functions bound to its fields
+    fn methods(&self) -> PyResult<Vec<PyFunction>> {
+        let obj = self.obj()?;
+        Ok(obj
+            .protos
+            .iter()
+            .map(|p| p.findex)
+            .chain(obj.bindings.values().copied())
+            .map(|findex| PyFunction {
+                code: self.code.clone(),
+                findex,
+            })
+            .collect())
+    }
+
+    /// Decompile the class to Haxe source code
+    fn decompile(&self) -> PyResult<String> {
+        facade::decompile_class_indented(
+            &self.code,
+            self.index,
+            &DecompilerOptions::default(),
+            INDENT,
+        )
+        .map_err(py_err)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<Type@{} {}>", self.index.0, self.name())
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "hlbc")]
+fn hlbc_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add_class::<PyBytecode>()?;
+    m.add_class::<PyFunction>()?;
+    m.add_class::<PyType>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use pyo3::exceptions::{PyIndexError, PyValueError};
+    use pyo3::prelude::*;
+    use pyo3::PyTypeInfo;
+
+    use hlbc::builder::Builder;
+    use hlbc::opcodes::Opcode;
+    use hlbc::types::{Reg, Type};
+
+    use crate::PyBytecode;
+
+    fn bytecode() -> PyBytecode {
+        let mut b = Builder::new();
+        let int = b.ty(Type::I32);
+        b.class("Point", None, &[("x", int)]);
+        b.native("std", "random", &[int], int);
+        b.function("id", &[int], int, &[], vec![Opcode::Ret { ret: Reg(0) }]);
+        PyBytecode {
+            code: Arc::new(b.build().unwrap()),
+        }
+    }
+
+    /// Errors are raised as the python exception matching their kind
+    fn raises<E: PyTypeInfo, T>(result: PyResult<T>) -> bool {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| result.err().is_some_and(|e| e.is_instance_of::<E>(py)))
+    }
+
+    #[test]
+    fn functions() {
+        let code = bytecode();
+        assert_eq!(code.functions().len(), 1);
+        assert_eq!(code.natives().len(), 1);
+        assert!(raises::<PyIndexError, _>(code.function(2)));
+
+        let native = code.function(0).unwrap();
+        assert!(native.is_native());
+        assert_eq!(native.disassemble().unwrap(), Vec::<String>::new());
+        assert!(raises::<PyValueError, _>(native.decompile()));
+
+        let id = code.function_by_name("id").unwrap();
+        assert_eq!(id.disassemble().unwrap(), ["Ret         reg0"]);
+        assert_eq!(
+            id.decompile().unwrap(),
+            "static function id(arg0: Int): Int {\n    return arg0;\n}\n"
+        );
+        assert_eq!(code.decompile_function(1).unwrap(), id.decompile().unwrap());
+    }
+
+    #[test]
+    fn types() {
+        let code = bytecode();
+        let types = code.types();
+        let int = types.iter().find(|t| t.kind() == "i32").unwrap();
+        assert!(raises::<PyValueError, _>(int.fields()));
+        let point = types.iter().find(|t| t.name() == "Point").unwrap();
+        assert_eq!(point.kind(), "obj");
+        assert_eq!(
+            point.fields().unwrap(),
+            [("x".to_owned(), "i32".to_owned())]
+        );
+        assert!(raises::<PyIndexError, _>(code.decompile_class(100)));
+    }
+}